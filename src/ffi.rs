@@ -0,0 +1,645 @@
+//! A C interface for embedding terminus-store in other languages.
+//!
+//! Stores, graphs, layers and layer builders are passed to C as opaque
+//! handles, which are pointers to the types of `store::sync`. Handles
+//! are returned through out parameters, and have to be released with
+//! the matching `ts_*_free` function. Functions that can fail return
+//! a `TsStatus`, and on failure leave their out parameters untouched.
+//! A description of the last failure on the calling thread is
+//! available through `ts_last_error`.
+//!
+//! Strings are passed in as null-terminated UTF-8. Objects are either
+//! nodes or plain string values. When reading triples, the object of
+//! a typed value is given as its lexical form, so its datatype is
+//! lost.
+//!
+//! This module requires the `ffi` feature. To build a shared library,
+//! run `cargo rustc --release --features ffi --crate-type cdylib`.
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use crate::io::ntriples::literal_parts;
+use crate::layer::{ObjectType, StringTriple};
+use crate::store::sync::*;
+
+/// The result of a call through the C interface.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TsStatus {
+    /// The call succeeded.
+    Ok = 0,
+    /// A required pointer argument was null.
+    NullArgument = 1,
+    /// A string argument was not valid UTF-8.
+    InvalidUtf8 = 2,
+    /// The requested graph or layer does not exist.
+    NotFound = 3,
+    /// The graph head was not updated, because the layer is not a descendant of the current head.
+    Conflict = 4,
+    /// An i/o error occurred.
+    Io = 5,
+    /// The call panicked.
+    Panic = 6,
+}
+
+/// A triple read from a layer, with strings owned by the library.
+///
+/// Strings are released with `ts_triple_free_strings`.
+#[repr(C)]
+#[derive(Debug)]
+pub struct TsTriple {
+    pub subject: *mut c_char,
+    pub predicate: *mut c_char,
+    pub object: *mut c_char,
+    pub object_is_node: bool,
+}
+
+/// An iterator over triples, as returned by `ts_layer_query`.
+pub struct TsTriples(Box<dyn Iterator<Item = StringTriple> + Send>);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', "\\0")).unwrap();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+fn io_error(err: io::Error) -> TsStatus {
+    set_last_error(err.to_string());
+    TsStatus::Io
+}
+
+/// Run the body of an exported function, turning panics into a status.
+fn run(body: impl FnOnce() -> Result<(), TsStatus>) -> TsStatus {
+    match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(())) => TsStatus::Ok,
+        Ok(Err(status)) => status,
+        Err(_) => {
+            set_last_error("panic in terminus-store".to_string());
+            TsStatus::Panic
+        }
+    }
+}
+
+unsafe fn handle<'a, T>(handle: *const T) -> Result<&'a T, TsStatus> {
+    handle.as_ref().ok_or(TsStatus::NullArgument)
+}
+
+unsafe fn str_arg<'a>(s: *const c_char) -> Result<&'a str, TsStatus> {
+    if s.is_null() {
+        return Err(TsStatus::NullArgument);
+    }
+
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| TsStatus::InvalidUtf8)
+}
+
+unsafe fn optional_str_arg<'a>(s: *const c_char) -> Result<Option<&'a str>, TsStatus> {
+    if s.is_null() {
+        Ok(None)
+    } else {
+        str_arg(s).map(Some)
+    }
+}
+
+/// Check an out parameter before doing any work.
+fn out_arg<T>(out: *mut *mut T) -> Result<(), TsStatus> {
+    if out.is_null() {
+        Err(TsStatus::NullArgument)
+    } else {
+        Ok(())
+    }
+}
+
+unsafe fn write_handle<T>(out: *mut *mut T, value: T) {
+    *out = Box::into_raw(Box::new(value));
+}
+
+unsafe fn free_handle<T>(handle: *mut T) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+unsafe fn string_triple(
+    subject: *const c_char,
+    predicate: *const c_char,
+    object: *const c_char,
+    object_is_node: bool,
+) -> Result<StringTriple, TsStatus> {
+    let subject = str_arg(subject)?;
+    let predicate = str_arg(predicate)?;
+    let object = str_arg(object)?;
+
+    Ok(if object_is_node {
+        StringTriple::new_node(subject, predicate, object)
+    } else {
+        StringTriple::new_value(subject, predicate, object)
+    })
+}
+
+fn c_string(s: String) -> *mut c_char {
+    CString::new(s.replace('\0', "\\0")).unwrap().into_raw()
+}
+
+/// A description of the last failure on this thread, or null if there was none.
+///
+/// The string is owned by the library, and stays valid until the
+/// next failing call on this thread.
+#[no_mangle]
+pub extern "C" fn ts_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map(|message| message.as_ptr())
+            .unwrap_or(ptr::null())
+    })
+}
+
+/// Open a store that is kept in memory.
+///
+/// # Safety
+///
+/// `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ts_store_open_memory(out: *mut *mut SyncStore) -> TsStatus {
+    run(|| {
+        out_arg(out)?;
+        write_handle(out, open_sync_memory_store());
+        Ok(())
+    })
+}
+
+/// Open a store in a directory.
+///
+/// # Safety
+///
+/// `path` must be a null-terminated string, and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ts_store_open_directory(
+    path: *const c_char,
+    out: *mut *mut SyncStore,
+) -> TsStatus {
+    run(|| {
+        let path = str_arg(path)?;
+        out_arg(out)?;
+        write_handle(out, open_sync_directory_store(path));
+        Ok(())
+    })
+}
+
+/// Release a store. Graphs, layers and builders of the store stay valid.
+///
+/// # Safety
+///
+/// `store` must be null or a store handle that wasn't released yet.
+#[no_mangle]
+pub unsafe extern "C" fn ts_store_free(store: *mut SyncStore) {
+    free_handle(store)
+}
+
+/// Create a graph.
+///
+/// # Safety
+///
+/// `store` must be a store handle, `name` a null-terminated string,
+/// and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ts_store_create_graph(
+    store: *const SyncStore,
+    name: *const c_char,
+    out: *mut *mut SyncNamedGraph,
+) -> TsStatus {
+    run(|| {
+        let store = handle(store)?;
+        let name = str_arg(name)?;
+        out_arg(out)?;
+        let graph = store.create(name).map_err(io_error)?;
+        write_handle(out, graph);
+        Ok(())
+    })
+}
+
+/// Open an existing graph, returning `NotFound` if it doesn't exist.
+///
+/// # Safety
+///
+/// See `ts_store_create_graph`.
+#[no_mangle]
+pub unsafe extern "C" fn ts_store_open_graph(
+    store: *const SyncStore,
+    name: *const c_char,
+    out: *mut *mut SyncNamedGraph,
+) -> TsStatus {
+    run(|| {
+        let store = handle(store)?;
+        let name = str_arg(name)?;
+        out_arg(out)?;
+        let graph = store
+            .open(name)
+            .map_err(io_error)?
+            .ok_or(TsStatus::NotFound)?;
+        write_handle(out, graph);
+        Ok(())
+    })
+}
+
+/// Start building a new base layer.
+///
+/// # Safety
+///
+/// `store` must be a store handle, and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ts_store_create_base_layer(
+    store: *const SyncStore,
+    out: *mut *mut SyncStoreLayerBuilder,
+) -> TsStatus {
+    run(|| {
+        let store = handle(store)?;
+        out_arg(out)?;
+        let builder = store.create_base_layer().map_err(io_error)?;
+        write_handle(out, builder);
+        Ok(())
+    })
+}
+
+/// Release a graph.
+///
+/// # Safety
+///
+/// `graph` must be null or a graph handle that wasn't released yet.
+#[no_mangle]
+pub unsafe extern "C" fn ts_graph_free(graph: *mut SyncNamedGraph) {
+    free_handle(graph)
+}
+
+/// The layer a graph points at, returning `NotFound` if the graph is empty.
+///
+/// # Safety
+///
+/// `graph` must be a graph handle, and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ts_graph_head(
+    graph: *const SyncNamedGraph,
+    out: *mut *mut SyncStoreLayer,
+) -> TsStatus {
+    run(|| {
+        let graph = handle(graph)?;
+        out_arg(out)?;
+        let layer = graph.head().map_err(io_error)?.ok_or(TsStatus::NotFound)?;
+        write_handle(out, layer);
+        Ok(())
+    })
+}
+
+/// Point a graph at a layer, returning `Conflict` if the layer doesn't descend from the current head.
+///
+/// # Safety
+///
+/// `graph` must be a graph handle and `layer` a layer handle.
+#[no_mangle]
+pub unsafe extern "C" fn ts_graph_set_head(
+    graph: *const SyncNamedGraph,
+    layer: *const SyncStoreLayer,
+) -> TsStatus {
+    run(|| {
+        let graph = handle(graph)?;
+        let layer = handle(layer)?;
+        if graph.set_head(layer).map_err(io_error)? {
+            Ok(())
+        } else {
+            Err(TsStatus::Conflict)
+        }
+    })
+}
+
+/// Release a layer.
+///
+/// # Safety
+///
+/// `layer` must be null or a layer handle that wasn't released yet.
+#[no_mangle]
+pub unsafe extern "C" fn ts_layer_free(layer: *mut SyncStoreLayer) {
+    free_handle(layer)
+}
+
+/// Start building a child layer of a layer.
+///
+/// # Safety
+///
+/// `layer` must be a layer handle, and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ts_layer_open_write(
+    layer: *const SyncStoreLayer,
+    out: *mut *mut SyncStoreLayerBuilder,
+) -> TsStatus {
+    run(|| {
+        let layer = handle(layer)?;
+        out_arg(out)?;
+        let builder = layer.open_write().map_err(io_error)?;
+        write_handle(out, builder);
+        Ok(())
+    })
+}
+
+/// Query the triples of a layer matching a pattern.
+///
+/// A null subject, predicate or object matches anything. The object
+/// is a node if `object_is_node` is true, and a string value
+/// otherwise.
+///
+/// # Safety
+///
+/// `layer` must be a layer handle, the strings must be null or
+/// null-terminated, and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ts_layer_query(
+    layer: *const SyncStoreLayer,
+    subject: *const c_char,
+    predicate: *const c_char,
+    object: *const c_char,
+    object_is_node: bool,
+    out: *mut *mut TsTriples,
+) -> TsStatus {
+    run(|| {
+        let layer = handle(layer)?;
+        let subject = optional_str_arg(subject)?;
+        let predicate = optional_str_arg(predicate)?;
+        let object = optional_str_arg(object)?;
+        out_arg(out)?;
+        let triples = match object {
+            Some(object) if object_is_node => layer.query_node(subject, predicate, object),
+            object => layer.query(subject, predicate, object.map(|o| o.to_string().into())),
+        };
+        write_handle(out, TsTriples(triples));
+        Ok(())
+    })
+}
+
+/// Release a triple iterator.
+///
+/// # Safety
+///
+/// `triples` must be null or an iterator handle that wasn't released yet.
+#[no_mangle]
+pub unsafe extern "C" fn ts_triples_free(triples: *mut TsTriples) {
+    free_handle(triples)
+}
+
+/// Read the next triple from an iterator.
+///
+/// Returns false when there are no more triples, or when an argument
+/// is null.
+///
+/// # Safety
+///
+/// `triples` must be an iterator handle, and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ts_triples_next(triples: *mut TsTriples, out: *mut TsTriple) -> bool {
+    let mut found = false;
+    run(|| {
+        let triples = triples.as_mut().ok_or(TsStatus::NullArgument)?;
+        if out.is_null() {
+            return Err(TsStatus::NullArgument);
+        }
+        if let Some(triple) = triples.0.next() {
+            let (object, object_is_node) = match triple.object {
+                ObjectType::Node(node) => (node, true),
+                object => (literal_parts(object.value().unwrap()).0, false),
+            };
+            *out = TsTriple {
+                subject: c_string(triple.subject),
+                predicate: c_string(triple.predicate),
+                object: c_string(object),
+                object_is_node,
+            };
+            found = true;
+        }
+        Ok(())
+    });
+
+    found
+}
+
+/// Release the strings of a triple returned by `ts_triples_next`, setting them to null.
+///
+/// # Safety
+///
+/// `triple` must be null or point to a triple filled in by
+/// `ts_triples_next` whose strings weren't released yet.
+#[no_mangle]
+pub unsafe extern "C" fn ts_triple_free_strings(triple: *mut TsTriple) {
+    if let Some(triple) = triple.as_mut() {
+        for s in [
+            &mut triple.subject,
+            &mut triple.predicate,
+            &mut triple.object,
+        ] {
+            if !s.is_null() {
+                drop(CString::from_raw(*s));
+                *s = ptr::null_mut();
+            }
+        }
+    }
+}
+
+/// Release a layer builder without committing it.
+///
+/// # Safety
+///
+/// `builder` must be null or a builder handle that wasn't released yet.
+#[no_mangle]
+pub unsafe extern "C" fn ts_builder_free(builder: *mut SyncStoreLayerBuilder) {
+    free_handle(builder)
+}
+
+/// Add a triple to a layer builder.
+///
+/// The object is a node if `object_is_node` is true, and a string
+/// value otherwise.
+///
+/// # Safety
+///
+/// `builder` must be a builder handle, and the strings null-terminated.
+#[no_mangle]
+pub unsafe extern "C" fn ts_builder_add_triple(
+    builder: *const SyncStoreLayerBuilder,
+    subject: *const c_char,
+    predicate: *const c_char,
+    object: *const c_char,
+    object_is_node: bool,
+) -> TsStatus {
+    run(|| {
+        let builder = handle(builder)?;
+        let triple = string_triple(subject, predicate, object, object_is_node)?;
+        builder.add_string_triple(triple).map_err(io_error)?;
+        Ok(())
+    })
+}
+
+/// Remove a triple in a layer builder.
+///
+/// # Safety
+///
+/// See `ts_builder_add_triple`.
+#[no_mangle]
+pub unsafe extern "C" fn ts_builder_remove_triple(
+    builder: *const SyncStoreLayerBuilder,
+    subject: *const c_char,
+    predicate: *const c_char,
+    object: *const c_char,
+    object_is_node: bool,
+) -> TsStatus {
+    run(|| {
+        let builder = handle(builder)?;
+        let triple = string_triple(subject, predicate, object, object_is_node)?;
+        builder.remove_string_triple(triple).map_err(io_error)
+    })
+}
+
+/// Commit a layer builder, returning the new layer.
+///
+/// The builder handle still has to be released afterwards.
+///
+/// # Safety
+///
+/// `builder` must be a builder handle, and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ts_builder_commit(
+    builder: *const SyncStoreLayerBuilder,
+    out: *mut *mut SyncStoreLayer,
+) -> TsStatus {
+    run(|| {
+        let builder = handle(builder)?;
+        out_arg(out)?;
+        let layer = builder.commit().map_err(io_error)?;
+        write_handle(out, layer);
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn c(s: &str) -> CString {
+        CString::new(s).unwrap()
+    }
+
+    #[test]
+    fn embed_through_c_interface() {
+        unsafe {
+            let mut store = ptr::null_mut();
+            assert_eq!(TsStatus::Ok, ts_store_open_memory(&mut store));
+            let mut graph = ptr::null_mut();
+            assert_eq!(
+                TsStatus::NotFound,
+                ts_store_open_graph(store, c("farm").as_ptr(), &mut graph)
+            );
+            assert!(graph.is_null());
+            assert_eq!(
+                TsStatus::Ok,
+                ts_store_create_graph(store, c("farm").as_ptr(), &mut graph)
+            );
+
+            let mut builder = ptr::null_mut();
+            assert_eq!(
+                TsStatus::Ok,
+                ts_store_create_base_layer(store, &mut builder)
+            );
+            for (subject, predicate, object, is_node) in [
+                ("cow", "says", "moo", false),
+                ("cow", "likes", "duck", true),
+                ("duck", "says", "quack", false),
+            ] {
+                let status = ts_builder_add_triple(
+                    builder,
+                    c(subject).as_ptr(),
+                    c(predicate).as_ptr(),
+                    c(object).as_ptr(),
+                    is_node,
+                );
+                assert_eq!(TsStatus::Ok, status);
+            }
+            assert_eq!(
+                TsStatus::NullArgument,
+                ts_builder_add_triple(builder, ptr::null(), c("p").as_ptr(), c("o").as_ptr(), true)
+            );
+            assert_eq!(
+                TsStatus::InvalidUtf8,
+                ts_builder_add_triple(
+                    builder,
+                    b"\xff\0".as_ptr() as *const c_char,
+                    c("p").as_ptr(),
+                    c("o").as_ptr(),
+                    true
+                )
+            );
+            let mut layer = ptr::null_mut();
+            assert_eq!(TsStatus::Ok, ts_builder_commit(builder, &mut layer));
+            ts_builder_free(builder);
+            assert_eq!(TsStatus::Ok, ts_graph_set_head(graph, layer));
+            ts_layer_free(layer);
+
+            let mut head = ptr::null_mut();
+            assert_eq!(TsStatus::Ok, ts_graph_head(graph, &mut head));
+            let mut triples = ptr::null_mut();
+            let status = ts_layer_query(
+                head,
+                ptr::null(),
+                c("says").as_ptr(),
+                ptr::null(),
+                false,
+                &mut triples,
+            );
+            assert_eq!(TsStatus::Ok, status);
+            let mut found = Vec::new();
+            let mut triple = TsTriple {
+                subject: ptr::null_mut(),
+                predicate: ptr::null_mut(),
+                object: ptr::null_mut(),
+                object_is_node: true,
+            };
+            while ts_triples_next(triples, &mut triple) {
+                found.push((
+                    CStr::from_ptr(triple.subject).to_str().unwrap().to_string(),
+                    CStr::from_ptr(triple.object).to_str().unwrap().to_string(),
+                    triple.object_is_node,
+                ));
+                ts_triple_free_strings(&mut triple);
+                assert!(triple.subject.is_null());
+            }
+            ts_triples_free(triples);
+            assert_eq!(
+                vec![
+                    ("cow".to_string(), "moo".to_string(), false),
+                    ("duck".to_string(), "quack".to_string(), false)
+                ],
+                found
+            );
+
+            // a layer that doesn't descend from the head
+            let mut builder = ptr::null_mut();
+            assert_eq!(
+                TsStatus::Ok,
+                ts_store_create_base_layer(store, &mut builder)
+            );
+            let mut other = ptr::null_mut();
+            assert_eq!(TsStatus::Ok, ts_builder_commit(builder, &mut other));
+            assert_eq!(TsStatus::Conflict, ts_graph_set_head(graph, other));
+
+            ts_builder_free(builder);
+            ts_layer_free(other);
+            ts_layer_free(head);
+            ts_graph_free(graph);
+            ts_store_free(store);
+        }
+    }
+}
@@ -0,0 +1,303 @@
+//! Exporting triples as Apache Arrow record batches and Parquet files.
+//!
+//! Triples are exported in one of two shapes. The id columns contain
+//! the `subject`, `predicate` and `object` id of every triple, which
+//! is compact and enough for analyzing the graph structure. The
+//! resolved columns contain the strings instead: `subject`,
+//! `predicate` and `object`, where the object is the IRI of a node or
+//! the lexical form of a value, followed by the `datatype` IRI of a
+//! value, which is null for nodes, and the `lang` of a
+//! language-tagged string, which is null otherwise. Node strings are
+//! exported as is, so blank nodes appear as their skolem IRIs.
+//!
+//! Triples are exported in the order of `Layer::triples`, a batch at
+//! a time, so only a single batch of resolved strings is in memory at
+//! once.
+//!
+//! This module requires the `arrow` feature. Writing Parquet files
+//! also requires the `parquet` feature.
+use std::io;
+use std::sync::Arc;
+
+use arrow_array::builder::{StringBuilder, UInt64Builder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+
+use super::ntriples::{literal_parts, LiteralSuffix, XSD_PREFIX};
+use crate::layer::{IdTriple, Layer, ObjectType};
+
+const RDF_LANG_STRING: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#langString";
+
+fn arrow_error(err: ArrowError) -> io::Error {
+    io::Error::other(err)
+}
+
+/// The schema of batches of id columns.
+pub fn id_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("subject", DataType::UInt64, false),
+        Field::new("predicate", DataType::UInt64, false),
+        Field::new("object", DataType::UInt64, false),
+    ]))
+}
+
+/// The schema of batches of resolved columns.
+pub fn triple_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("subject", DataType::Utf8, false),
+        Field::new("predicate", DataType::Utf8, false),
+        Field::new("object", DataType::Utf8, false),
+        Field::new("datatype", DataType::Utf8, true),
+        Field::new("lang", DataType::Utf8, true),
+    ]))
+}
+
+/// Iterator over the triples of a layer as record batches, as returned by `id_batches` and `triple_batches`.
+pub struct TripleBatches<'a> {
+    layer: &'a dyn Layer,
+    triples: Box<dyn Iterator<Item = IdTriple> + 'a>,
+    batch_size: usize,
+    resolve: bool,
+}
+
+impl<'a> TripleBatches<'a> {
+    fn id_batch(&self, triples: &[IdTriple]) -> io::Result<RecordBatch> {
+        let mut columns = [
+            UInt64Builder::with_capacity(triples.len()),
+            UInt64Builder::with_capacity(triples.len()),
+            UInt64Builder::with_capacity(triples.len()),
+        ];
+        for triple in triples {
+            columns[0].append_value(triple.subject);
+            columns[1].append_value(triple.predicate);
+            columns[2].append_value(triple.object);
+        }
+        let columns = columns
+            .iter_mut()
+            .map(|column| Arc::new(column.finish()) as ArrayRef)
+            .collect();
+
+        RecordBatch::try_new(id_schema(), columns).map_err(arrow_error)
+    }
+
+    fn triple_batch(&self, triples: &[IdTriple]) -> io::Result<RecordBatch> {
+        let resolve_error = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "triple contains an id that is not known to the layer",
+            )
+        };
+        let mut columns: Vec<_> = (0..5).map(|_| StringBuilder::new()).collect();
+        for triple in triples {
+            let subject = self
+                .layer
+                .id_subject(triple.subject)
+                .ok_or_else(resolve_error)?;
+            let predicate = self
+                .layer
+                .id_predicate(triple.predicate)
+                .ok_or_else(resolve_error)?;
+            let object = self
+                .layer
+                .id_object(triple.object)
+                .ok_or_else(resolve_error)?;
+            columns[0].append_value(subject);
+            columns[1].append_value(predicate);
+            match object {
+                ObjectType::Node(node) => {
+                    columns[2].append_value(node);
+                    columns[3].append_null();
+                    columns[4].append_null();
+                }
+                object => {
+                    let (lexical, suffix) = literal_parts(object.value().unwrap());
+                    columns[2].append_value(lexical);
+                    match suffix {
+                        LiteralSuffix::None => {
+                            columns[3].append_value(format!("{}string", XSD_PREFIX));
+                            columns[4].append_null();
+                        }
+                        LiteralSuffix::Lang(lang) => {
+                            columns[3].append_value(RDF_LANG_STRING);
+                            columns[4].append_value(lang);
+                        }
+                        LiteralSuffix::Datatype(datatype) => {
+                            columns[3].append_value(format!("{}{}", XSD_PREFIX, datatype));
+                            columns[4].append_null();
+                        }
+                    }
+                }
+            }
+        }
+        let columns = columns
+            .iter_mut()
+            .map(|column| Arc::new(column.finish()) as ArrayRef)
+            .collect();
+
+        RecordBatch::try_new(triple_schema(), columns).map_err(arrow_error)
+    }
+}
+
+impl<'a> Iterator for TripleBatches<'a> {
+    type Item = io::Result<RecordBatch>;
+
+    fn next(&mut self) -> Option<io::Result<RecordBatch>> {
+        let triples: Vec<_> = self.triples.by_ref().take(self.batch_size).collect();
+        if triples.is_empty() {
+            return None;
+        }
+
+        Some(if self.resolve {
+            self.triple_batch(&triples)
+        } else {
+            self.id_batch(&triples)
+        })
+    }
+}
+
+/// The triples of a layer as batches of id columns, with at most `batch_size` rows each.
+///
+/// # Panics
+///
+/// Panics if `batch_size` is 0.
+pub fn id_batches(layer: &dyn Layer, batch_size: usize) -> TripleBatches<'_> {
+    assert!(batch_size > 0, "batch size should be at least 1");
+    TripleBatches {
+        layer,
+        triples: layer.triples(),
+        batch_size,
+        resolve: false,
+    }
+}
+
+/// The triples of a layer as batches of resolved columns, with at most `batch_size` rows each.
+///
+/// # Panics
+///
+/// Panics if `batch_size` is 0.
+pub fn triple_batches(layer: &dyn Layer, batch_size: usize) -> TripleBatches<'_> {
+    assert!(batch_size > 0, "batch size should be at least 1");
+    TripleBatches {
+        layer,
+        triples: layer.triples(),
+        batch_size,
+        resolve: true,
+    }
+}
+
+/// Write record batches as a Parquet file, returning the amount of rows written.
+///
+/// This requires the `parquet` feature.
+#[cfg(feature = "parquet")]
+pub fn write_parquet<W: io::Write + Send>(
+    batches: TripleBatches<'_>,
+    writer: W,
+) -> io::Result<usize> {
+    use parquet::arrow::ArrowWriter;
+
+    let schema = if batches.resolve {
+        triple_schema()
+    } else {
+        id_schema()
+    };
+    let parquet_error = io::Error::other;
+    let mut writer = ArrowWriter::try_new(writer, schema, None).map_err(parquet_error)?;
+    let mut count = 0;
+    for batch in batches {
+        let batch = batch?;
+        count += batch.num_rows();
+        writer.write(&batch).map_err(parquet_error)?;
+    }
+    writer.close().map_err(parquet_error)?;
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::{StringTriple, Value};
+    use crate::store::open_memory_store;
+    use arrow_array::{Array, StringArray, UInt64Array};
+
+    #[tokio::test]
+    async fn export_record_batches() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("cow", "likes", "duck"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_lang_string("cow", "says", "boe", "nl"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_literal("duck", "legs", Value::Integer(2)))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let layer = builder.commit().await.unwrap();
+
+        let batches: Vec<_> = id_batches(&layer, 3).map(|batch| batch.unwrap()).collect();
+        assert_eq!(
+            vec![3, 1],
+            batches.iter().map(|b| b.num_rows()).collect::<Vec<_>>()
+        );
+        let subjects = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap();
+        assert_eq!(layer.subject_id("cow"), Some(subjects.value(0)));
+
+        let batches: Vec<_> = triple_batches(&layer, 10)
+            .map(|batch| batch.unwrap())
+            .collect();
+        assert_eq!(1, batches.len());
+        let column = |i: usize| {
+            let column = batches[0]
+                .column(i)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .unwrap();
+            (0..column.len())
+                .map(|row| Some(column.value(row)).filter(|_| column.is_valid(row)))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            vec![Some("cow"), Some("cow"), Some("duck"), Some("duck")],
+            column(0)
+        );
+        assert_eq!(
+            vec![Some("duck"), Some("boe"), Some("2"), Some("quack")],
+            column(2)
+        );
+        assert_eq!(
+            vec![
+                None,
+                Some(RDF_LANG_STRING),
+                Some("http://www.w3.org/2001/XMLSchema#integer"),
+                Some("http://www.w3.org/2001/XMLSchema#string")
+            ],
+            column(3)
+        );
+        assert_eq!(vec![None, Some("nl"), None, None], column(4));
+
+        #[cfg(feature = "parquet")]
+        {
+            use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+            let mut file = Vec::new();
+            assert_eq!(
+                4,
+                write_parquet(triple_batches(&layer, 3), &mut file).unwrap()
+            );
+            let reader = ParquetRecordBatchReaderBuilder::try_new(bytes::Bytes::from(file))
+                .unwrap()
+                .build()
+                .unwrap();
+            let read: Vec<_> = reader.map(|batch| batch.unwrap()).collect();
+            assert_eq!(batches, read);
+        }
+    }
+}
@@ -0,0 +1,463 @@
+//! Importing tabular data from CSV and TSV files.
+//!
+//! Every row of the file describes a single subject, with one triple
+//! for every mapped column. A `CsvMapping` gives the IRI template for
+//! the subject, and for every mapped column the predicate and how its
+//! cells become objects. Columns are referred to by the name given in
+//! the first row of the file, and columns that aren't mapped are
+//! ignored.
+//!
+//! Templates are IRIs with column names in braces, like
+//! `http://example.com/person/{id}`, which are replaced by the cell in
+//! that column. Characters of the cell that aren't allowed in an IRI,
+//! or that have a special meaning in one, are percent-encoded. Empty
+//! cells don't result in a triple, and rows where a cell used by the
+//! subject template is empty are skipped.
+//!
+//! Fields can be quoted with `"`, in which case they can contain the
+//! delimiter, line breaks, and quotes written as `""`. Rows are read
+//! one at a time, so files of any size can be imported.
+use std::io;
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, Lines};
+
+use super::ntriples::{parse_xsd, ParseError};
+use crate::layer::{ObjectType, StringTriple, Value};
+use crate::store::{Store, StoreLayer, StoreLayerBuilder};
+
+/// How the cells of a column become objects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ColumnType {
+    /// A plain string.
+    String,
+    /// A string with the given language tag.
+    LangString(String),
+    /// An `xsd:boolean`, written as `true`, `false`, `1` or `0`.
+    Boolean,
+    /// An `xsd:integer`.
+    Integer,
+    /// An `xsd:decimal`.
+    Decimal,
+    /// An `xsd:double`.
+    Double,
+    /// An `xsd:dateTime`.
+    DateTime,
+    /// A node, whose IRI is the cell itself.
+    Iri,
+    /// A node, whose IRI is given by a template.
+    Node(String),
+}
+
+/// How the rows of a CSV file become triples.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvMapping {
+    subject: String,
+    columns: Vec<(String, String, ColumnType)>,
+    delimiter: char,
+}
+
+impl CsvMapping {
+    /// Construct a mapping for comma-separated files, with the given template for the subject of each row.
+    pub fn new(subject: &str) -> Self {
+        Self {
+            subject: subject.to_string(),
+            columns: Vec::new(),
+            delimiter: ',',
+        }
+    }
+
+    /// Use a different delimiter between fields, like `'\t'` for TSV files.
+    pub fn with_delimiter(mut self, delimiter: char) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Map the cells of a column to the objects of triples with the given predicate.
+    pub fn with_column(mut self, column: &str, predicate: &str, column_type: ColumnType) -> Self {
+        self.columns
+            .push((column.to_string(), predicate.to_string(), column_type));
+        self
+    }
+}
+
+fn unknown_column(column: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("mapping refers to unknown column {:?}", column),
+    )
+}
+
+enum Segment {
+    Text(String),
+    Column(usize),
+}
+
+/// A template with the positions of its columns looked up.
+struct Template(Vec<Segment>);
+
+impl Template {
+    fn compile(template: &str, header: &[String]) -> io::Result<Self> {
+        let mut segments = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..].find('}').ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unterminated column in template {:?}", template),
+                )
+            })? + start;
+            let column = &rest[start + 1..end];
+            let index = header
+                .iter()
+                .position(|name| name == column)
+                .ok_or_else(|| unknown_column(column))?;
+            segments.push(Segment::Text(rest[..start].to_string()));
+            segments.push(Segment::Column(index));
+            rest = &rest[end + 1..];
+        }
+        segments.push(Segment::Text(rest.to_string()));
+
+        Ok(Self(segments))
+    }
+
+    /// Fill in the template for a row, or None if one of its cells is empty.
+    fn fill(&self, row: &[String]) -> Option<String> {
+        let mut result = String::new();
+        for segment in self.0.iter() {
+            match segment {
+                Segment::Text(text) => result.push_str(text),
+                Segment::Column(index) if row[*index].is_empty() => return None,
+                Segment::Column(index) => {
+                    for c in row[*index].chars() {
+                        if c.is_ascii() && !(c.is_ascii_alphanumeric() || "-._~".contains(c)) {
+                            result.push_str(&format!("%{:02X}", c as u8));
+                        } else {
+                            result.push(c);
+                        }
+                    }
+                }
+            }
+        }
+
+        Some(result)
+    }
+}
+
+struct Column {
+    index: usize,
+    predicate: String,
+    column_type: ColumnType,
+    template: Option<Template>,
+}
+
+impl Column {
+    fn object(&self, row: &[String]) -> Result<Option<ObjectType>, String> {
+        let cell = &row[self.index];
+        if cell.is_empty() {
+            return Ok(None);
+        }
+        let datatype = match &self.column_type {
+            ColumnType::String => return Ok(Some(Value::String(cell.clone()).into())),
+            ColumnType::LangString(lang) => {
+                return Ok(Some(ObjectType::new_lang_string(cell, lang)));
+            }
+            ColumnType::Iri => return Ok(Some(ObjectType::Node(cell.clone()))),
+            ColumnType::Node(_) => {
+                return Ok(self
+                    .template
+                    .as_ref()
+                    .unwrap()
+                    .fill(row)
+                    .map(ObjectType::Node));
+            }
+            ColumnType::Boolean => "boolean",
+            ColumnType::Integer => "integer",
+            ColumnType::Decimal => "decimal",
+            ColumnType::Double => "double",
+            ColumnType::DateTime => "dateTime",
+        };
+
+        match parse_xsd(cell, datatype) {
+            Some(value) => Ok(Some(value.into())),
+            None => Err(format!("invalid {} {:?}", datatype, cell)),
+        }
+    }
+}
+
+/// Split a row into fields, or return None if it ends inside a quoted field.
+fn split_row(text: &str, delimiter: char) -> Result<Option<Vec<String>>, String> {
+    let mut fields = Vec::new();
+    let mut chars = text.chars().peekable();
+    loop {
+        let mut field = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next() {
+                    None => return Ok(None),
+                    Some('"') if chars.next_if_eq(&'"').is_some() => field.push('"'),
+                    Some('"') => break,
+                    Some(c) => field.push(c),
+                }
+            }
+            match chars.peek() {
+                None => {}
+                Some(&c) if c == delimiter => {}
+                Some(c) => return Err(format!("unexpected {:?} after quoted field", c)),
+            }
+        } else {
+            while let Some(c) = chars.next_if(|&c| c != delimiter) {
+                field.push(c);
+            }
+        }
+        fields.push(field);
+        if chars.next().is_none() {
+            return Ok(Some(fields));
+        }
+    }
+}
+
+/// Reads the rows of a CSV file.
+struct RowReader<R> {
+    lines: Lines<R>,
+    line: usize,
+    delimiter: char,
+}
+
+impl<R: AsyncBufRead + Unpin> RowReader<R> {
+    /// The next row, with the line it starts on, skipping empty lines.
+    async fn next_row(&mut self) -> io::Result<Option<(usize, Vec<String>)>> {
+        let mut text = String::new();
+        let mut start = None;
+        while let Some(line) = self.lines.next_line().await? {
+            self.line += 1;
+            if start.is_none() {
+                if line.is_empty() {
+                    continue;
+                }
+                start = Some(self.line);
+            } else {
+                text.push('\n');
+            }
+            text.push_str(&line);
+
+            let line = start.unwrap();
+            match split_row(&text, self.delimiter) {
+                Ok(Some(fields)) => return Ok(Some((line, fields))),
+                Ok(None) => {}
+                Err(message) => return Err(ParseError { line, message }.into()),
+            }
+        }
+
+        match start {
+            None => Ok(None),
+            Some(line) => Err(ParseError {
+                line,
+                message: "unterminated quoted field".to_string(),
+            }
+            .into()),
+        }
+    }
+}
+
+/// Add the triples for all rows of a CSV file to a builder.
+///
+/// Returns the amount of triples added. Invalid rows and cells that
+/// aren't valid for the type of their column result in an error of
+/// kind `InvalidData`, wrapping a `ParseError`. A mapping referring
+/// to columns that aren't in the file results in an error of kind
+/// `InvalidInput`. Nothing is added to the builder after the first
+/// error.
+pub async fn import<R: AsyncBufRead + Unpin>(
+    reader: R,
+    builder: &StoreLayerBuilder,
+    mapping: &CsvMapping,
+) -> io::Result<usize> {
+    let mut rows = RowReader {
+        lines: reader.lines(),
+        line: 0,
+        delimiter: mapping.delimiter,
+    };
+    let header = match rows.next_row().await? {
+        Some((_, header)) => header,
+        None => return Ok(0),
+    };
+    let subject = Template::compile(&mapping.subject, &header)?;
+    let columns = mapping
+        .columns
+        .iter()
+        .map(|(column, predicate, column_type)| {
+            let template = match column_type {
+                ColumnType::Node(template) => Some(Template::compile(template, &header)?),
+                _ => None,
+            };
+            Ok(Column {
+                index: header
+                    .iter()
+                    .position(|name| name == column)
+                    .ok_or_else(|| unknown_column(column))?,
+                predicate: predicate.clone(),
+                column_type: column_type.clone(),
+                template,
+            })
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let mut count = 0;
+    while let Some((line, row)) = rows.next_row().await? {
+        if row.len() != header.len() {
+            let message = format!("expected {} fields, found {}", header.len(), row.len());
+            return Err(ParseError { line, message }.into());
+        }
+        let subject = match subject.fill(&row) {
+            Some(subject) => subject,
+            None => continue,
+        };
+        for column in columns.iter() {
+            let object = column
+                .object(&row)
+                .map_err(|message| ParseError { line, message })?;
+            if let Some(object) = object {
+                builder.add_string_triple(StringTriple {
+                    subject: subject.clone(),
+                    predicate: column.predicate.clone(),
+                    object,
+                })?;
+                count += 1;
+            }
+        }
+    }
+
+    Ok(count)
+}
+
+impl Store {
+    /// Create a base layer with the triples for all rows of a CSV file.
+    ///
+    /// See the `io::csv` module for how rows become triples.
+    pub async fn import_csv<R: AsyncBufRead + Unpin>(
+        &self,
+        reader: R,
+        mapping: &CsvMapping,
+    ) -> io::Result<StoreLayer> {
+        let builder = self.create_base_layer().await?;
+        import(reader, &builder, mapping).await?;
+
+        builder.commit().await
+    }
+}
+
+impl StoreLayer {
+    /// Create a child layer adding the triples for all rows of a CSV file to this layer.
+    ///
+    /// See `Store::import_csv` for details.
+    pub async fn import_csv<R: AsyncBufRead + Unpin>(
+        &self,
+        reader: R,
+        mapping: &CsvMapping,
+    ) -> io::Result<StoreLayer> {
+        let builder = self.open_write().await?;
+        import(reader, &builder, mapping).await?;
+
+        builder.commit().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::{Decimal, Layer};
+    use crate::store::open_memory_store;
+    use std::str::FromStr;
+
+    #[tokio::test]
+    async fn import_mapped_rows() {
+        let document = "id\tname\tcity\tweight\tnote\thomepage\n\
+                        1\tDaisy\tNew York\t550.5\t\"moo\tand \"\"moo\"\"\nagain\"\thttp://example.com/daisy\n\
+                        \n\
+                        2\tBella\t\t\t\t\n\
+                        \tNobody\tParis\t1\t\t\n";
+        let mapping = CsvMapping::new("http://example.com/cow/{id}")
+            .with_delimiter('\t')
+            .with_column("name", "http://example.com/name", ColumnType::String)
+            .with_column(
+                "city",
+                "http://example.com/city",
+                ColumnType::Node("http://example.com/city/{city}".to_string()),
+            )
+            .with_column("weight", "http://example.com/weight", ColumnType::Decimal)
+            .with_column(
+                "note",
+                "http://example.com/note",
+                ColumnType::LangString("en".to_string()),
+            )
+            .with_column("homepage", "http://example.com/homepage", ColumnType::Iri);
+
+        let store = open_memory_store();
+        let layer = store
+            .import_csv(document.as_bytes(), &mapping)
+            .await
+            .unwrap();
+        let mut triples: Vec<_> = layer
+            .triples()
+            .map(|t| layer.id_triple_to_string(&t).unwrap())
+            .collect();
+        triples.sort();
+        let cow = |id: &str| format!("http://example.com/cow/{}", id);
+        let mut expected = vec![
+            StringTriple::new_value(&cow("1"), "http://example.com/name", "Daisy"),
+            StringTriple::new_node(
+                &cow("1"),
+                "http://example.com/city",
+                "http://example.com/city/New%20York",
+            ),
+            StringTriple::new_literal(
+                &cow("1"),
+                "http://example.com/weight",
+                Decimal::from_str("550.5").unwrap(),
+            ),
+            StringTriple::new_lang_string(
+                &cow("1"),
+                "http://example.com/note",
+                "moo\tand \"moo\"\nagain",
+                "en",
+            ),
+            StringTriple::new_node(
+                &cow("1"),
+                "http://example.com/homepage",
+                "http://example.com/daisy",
+            ),
+            StringTriple::new_value(&cow("2"), "http://example.com/name", "Bella"),
+        ];
+        expected.sort();
+        assert_eq!(expected, triples);
+
+        let invalid = "id,weight\n1,heavy\n";
+        let mapping = CsvMapping::new("http://example.com/cow/{id}").with_column(
+            "weight",
+            "http://example.com/weight",
+            ColumnType::Integer,
+        );
+        let err = store
+            .import_csv(invalid.as_bytes(), &mapping)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert_eq!(
+            "line 2: invalid integer \"heavy\"",
+            err.get_ref().unwrap().to_string()
+        );
+
+        let mapping = CsvMapping::new("http://example.com/cow/{cow}");
+        let err = store
+            .import_csv(invalid.as_bytes(), &mapping)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        assert!(store
+            .import_csv("id\n\"1\n".as_bytes(), &CsvMapping::new("{id}"))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,847 @@
+//! Reading and writing HDT files.
+//!
+//! HDT (Header, Dictionary, Triples) is a binary format for publishing
+//! and exchanging RDF datasets. After a global section, a file has a
+//! header with metadata as N-Triples, a dictionary giving every term
+//! an id, and the triples as ids. The dictionary has four sections,
+//! for terms used as both subject and object, subjects only,
+//! predicates and objects only, each a sorted list of strings in plain
+//! front coding. Triples are stored in SPO order as bitmap triples:
+//! subjects are implicit, an array holds the predicates of every
+//! subject and another the objects of every subject and predicate,
+//! with bitmaps marking where each list ends.
+//!
+//! Only this layout is supported, which is what HDT tools write by
+//! default. Other dictionary, triples or ordering formats result in
+//! an error of kind `Unsupported`. Invalid files and checksum
+//! mismatches result in an error of kind `InvalidData`. The header
+//! is skipped on import, and only states the dataset type and the
+//! amount of triples on export.
+//!
+//! In the dictionary, IRIs are stored without angle brackets, blank
+//! nodes with their `_:` prefix and literals with their quotes, but
+//! without escaping. They are converted to and from nodes and values
+//! the same way as for N-Triples, see the `ntriples` module. Quoted
+//! triples have no representation in HDT and are written as IRIs.
+//!
+//! Both dictionary and triples have to be sorted by the HDT ids, so
+//! files are read into memory entirely, and written after collecting
+//! all terms and triples in memory.
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::ntriples::{literal_parts, typed_literal};
+use crate::layer::{Layer, ObjectType, Skolemizer, StringTriple, Value};
+use crate::store::{Store, StoreLayer, StoreLayerBuilder};
+
+const COOKIE: &[u8] = b"$HDT";
+const GLOBAL_TYPE: u8 = 1;
+const HEADER_TYPE: u8 = 2;
+const DICTIONARY_TYPE: u8 = 3;
+const TRIPLES_TYPE: u8 = 4;
+
+const HDT_FORMAT: &str = "<http://purl.org/HDT/hdt#HDTv1>";
+const HEADER_FORMAT: &str = "ntriples";
+const DICTIONARY_FORMAT: &str = "<http://purl.org/HDT/hdt#dictionaryFour>";
+const TRIPLES_FORMAT: &str = "<http://purl.org/HDT/hdt#triplesBitmap>";
+
+const PFC_TYPE: u8 = 2;
+const LOG_ARRAY_TYPE: u8 = 1;
+const BITMAP_TYPE: u8 = 1;
+const SPO_ORDER: &str = "1";
+
+/// The amount of strings per block of a dictionary section written on export.
+const BLOCK_SIZE: usize = 16;
+
+/// CRC-8-CCITT, checksum of section headers.
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
+/// CRC-16-ANSI, checksum of control information.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xA001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    crc
+}
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82F6_3B78
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+}
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+/// CRC-32C, checksum of section data.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc = CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+
+    !crc
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn unsupported(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, message.into())
+}
+
+/// The amount of bits needed for a number.
+fn bits(n: u64) -> u8 {
+    (64 - n.leading_zeros()) as u8
+}
+
+/// Reads the parts of an HDT file from its bytes.
+struct Input<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Input<'a> {
+    fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|&end| end <= self.data.len())
+            .ok_or_else(|| invalid("unexpected end of file"))?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    /// A number in HDT's variable byte encoding, where the last byte has its high bit set.
+    fn vbyte(&mut self) -> io::Result<u64> {
+        let mut result = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            result |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 != 0 {
+                return Ok(result);
+            }
+        }
+
+        Err(invalid("variable byte number too large"))
+    }
+
+    fn vbyte_usize(&mut self) -> io::Result<usize> {
+        usize::try_from(self.vbyte()?).map_err(|_| invalid("number too large"))
+    }
+
+    /// A null-terminated string.
+    fn string(&mut self) -> io::Result<&'a str> {
+        let rest = &self.data[self.position..];
+        let len = rest
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| invalid("unterminated string"))?;
+        let string = std::str::from_utf8(&rest[..len]).map_err(|_| invalid("invalid UTF-8"))?;
+        self.position += len + 1;
+
+        Ok(string)
+    }
+
+    fn check_crc8(&mut self, start: usize) -> io::Result<()> {
+        let crc = crc8(&self.data[start..self.position]);
+        if self.byte()? != crc {
+            return Err(invalid("checksum mismatch"));
+        }
+
+        Ok(())
+    }
+
+    fn crc32_data(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let data = self.bytes(len)?;
+        let crc = u32::from_le_bytes(self.bytes(4)?.try_into().unwrap());
+        if crc != crc32(data) {
+            return Err(invalid("checksum mismatch"));
+        }
+
+        Ok(data)
+    }
+
+    /// Control information of the given type, returning its format and properties.
+    fn control_info(
+        &mut self,
+        expected_type: u8,
+    ) -> io::Result<(&'a str, HashMap<&'a str, &'a str>)> {
+        let start = self.position;
+        if self.bytes(COOKIE.len())? != COOKIE {
+            return Err(invalid("not an HDT file"));
+        }
+        if self.byte()? != expected_type {
+            return Err(invalid("unexpected section"));
+        }
+        let format = self.string()?;
+        let properties = self
+            .string()?
+            .split(';')
+            .filter(|property| !property.is_empty())
+            .map(|property| property.split_once('=').unwrap_or((property, "")))
+            .collect();
+        let crc = crc16(&self.data[start..self.position]);
+        if u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()) != crc {
+            return Err(invalid("checksum mismatch"));
+        }
+
+        Ok((format, properties))
+    }
+
+    fn log_array(&mut self) -> io::Result<Vec<u64>> {
+        let start = self.position;
+        if self.byte()? != LOG_ARRAY_TYPE {
+            return Err(unsupported("unsupported array type"));
+        }
+        let width = self.byte()? as usize;
+        if width > 64 {
+            return Err(invalid("invalid array width"));
+        }
+        let len = self.vbyte_usize()?;
+        self.check_crc8(start)?;
+        let size = len
+            .checked_mul(width)
+            .ok_or_else(|| invalid("array too large"))?
+            .div_ceil(8);
+        let data = self.crc32_data(size)?;
+
+        let mut values = Vec::with_capacity(len);
+        for i in 0..len {
+            let mut value = 0u64;
+            for bit in 0..width {
+                let position = i * width + bit;
+                if data[position / 8] & (1 << (position % 8)) != 0 {
+                    value |= 1 << bit;
+                }
+            }
+            values.push(value);
+        }
+
+        Ok(values)
+    }
+
+    fn bitmap(&mut self) -> io::Result<Vec<bool>> {
+        let start = self.position;
+        if self.byte()? != BITMAP_TYPE {
+            return Err(unsupported("unsupported bitmap type"));
+        }
+        let len = self.vbyte_usize()?;
+        self.check_crc8(start)?;
+        let data = self.crc32_data(len.div_ceil(8))?;
+
+        Ok((0..len)
+            .map(|i| data[i / 8] & (1 << (i % 8)) != 0)
+            .collect())
+    }
+
+    /// A dictionary section in plain front coding.
+    fn pfc(&mut self) -> io::Result<Vec<String>> {
+        let start = self.position;
+        if self.byte()? != PFC_TYPE {
+            return Err(unsupported("unsupported dictionary section type"));
+        }
+        let count = self.vbyte_usize()?;
+        let size = self.vbyte_usize()?;
+        let block_size = self.vbyte_usize()?;
+        self.check_crc8(start)?;
+        // the block offsets aren't needed when decoding all strings in order
+        self.log_array()?;
+        let mut text = Input {
+            data: self.crc32_data(size)?,
+            position: 0,
+        };
+        if block_size == 0 && count != 0 {
+            return Err(invalid("invalid block size"));
+        }
+
+        let mut strings: Vec<String> = Vec::with_capacity(count);
+        for i in 0..count {
+            let prefix = if i % block_size == 0 {
+                ""
+            } else {
+                let len = text.vbyte_usize()?;
+                let previous = &strings[i - 1];
+                previous
+                    .get(..len)
+                    .ok_or_else(|| invalid("invalid dictionary string"))?
+            };
+            let string = format!("{}{}", prefix, text.string()?);
+            strings.push(string);
+        }
+
+        Ok(strings)
+    }
+}
+
+fn node_from_term(term: &str, skolemizer: &Skolemizer) -> String {
+    match term.strip_prefix("_:") {
+        Some(label) => skolemizer.skolemize(label),
+        None => term.to_string(),
+    }
+}
+
+fn object_from_term(term: &str, skolemizer: &Skolemizer) -> io::Result<ObjectType> {
+    if !term.starts_with('"') {
+        return Ok(ObjectType::Node(node_from_term(term, skolemizer)));
+    }
+    let end = term.rfind('"').filter(|&end| end > 0);
+    let end = end.ok_or_else(|| invalid(format!("invalid literal {:?}", term)))?;
+    let lexical = term[1..end].to_string();
+    let suffix = &term[end + 1..];
+    if suffix.is_empty() {
+        Ok(Value::String(lexical).into())
+    } else if let Some(lang) = suffix.strip_prefix('@') {
+        Ok(ObjectType::new_lang_string(&lexical, lang))
+    } else if let Some(datatype) = suffix
+        .strip_prefix("^^<")
+        .and_then(|datatype| datatype.strip_suffix('>'))
+    {
+        Ok(typed_literal(lexical, datatype).into())
+    } else {
+        Err(invalid(format!("invalid literal {:?}", term)))
+    }
+}
+
+/// The term for a subject or object id, which is in the shared section or the section of its role.
+fn term<'a>(shared: &'a [String], own: &'a [String], id: u64) -> io::Result<&'a str> {
+    let index = (id as usize)
+        .checked_sub(1)
+        .ok_or_else(|| invalid("invalid id 0"))?;
+    shared
+        .get(index)
+        .or_else(|| own.get(index - shared.len()))
+        .map(|term| term.as_str())
+        .ok_or_else(|| invalid(format!("unknown id {}", id)))
+}
+
+/// Parse an HDT file, passing every triple to `emit`.
+///
+/// Returns the amount of triples. Errors returned by `emit` stop the
+/// parse, and are returned as is.
+pub fn parse(
+    data: &[u8],
+    skolemizer: &Skolemizer,
+    mut emit: impl FnMut(StringTriple) -> io::Result<()>,
+) -> io::Result<usize> {
+    let mut input = Input { data, position: 0 };
+    input.control_info(GLOBAL_TYPE)?;
+    let (_, properties) = input.control_info(HEADER_TYPE)?;
+    let header_len = properties
+        .get("length")
+        .and_then(|len| len.parse().ok())
+        .ok_or_else(|| invalid("header without length"))?;
+    input.bytes(header_len)?;
+
+    let (format, _) = input.control_info(DICTIONARY_TYPE)?;
+    if format != DICTIONARY_FORMAT {
+        return Err(unsupported(format!("unsupported dictionary {}", format)));
+    }
+    let shared = input.pfc()?;
+    let subjects = input.pfc()?;
+    let predicates = input.pfc()?;
+    let objects = input.pfc()?;
+
+    let (format, properties) = input.control_info(TRIPLES_TYPE)?;
+    if format != TRIPLES_FORMAT {
+        return Err(unsupported(format!("unsupported triples {}", format)));
+    }
+    if properties.get("order") != Some(&SPO_ORDER) {
+        return Err(unsupported("unsupported triple order"));
+    }
+    let bitmap_y = input.bitmap()?;
+    let bitmap_z = input.bitmap()?;
+    let array_y = input.log_array()?;
+    let array_z = input.log_array()?;
+    if bitmap_y.len() != array_y.len() || bitmap_z.len() != array_z.len() {
+        return Err(invalid("triple arrays and bitmaps differ in length"));
+    }
+
+    let mut subject_id = 0;
+    let mut subject = String::new();
+    let mut z = 0;
+    for (y, &predicate_id) in array_y.iter().enumerate() {
+        if y == 0 || bitmap_y[y - 1] {
+            subject_id += 1;
+            subject = node_from_term(term(&shared, &subjects, subject_id)?, skolemizer);
+        }
+        let predicate = (predicate_id as usize)
+            .checked_sub(1)
+            .and_then(|index| predicates.get(index))
+            .ok_or_else(|| invalid(format!("unknown predicate id {}", predicate_id)))?;
+        loop {
+            let object_id = *array_z
+                .get(z)
+                .ok_or_else(|| invalid("triple arrays differ in length"))?;
+            let object = object_from_term(term(&shared, &objects, object_id)?, skolemizer)?;
+            emit(StringTriple {
+                subject: subject.clone(),
+                predicate: predicate.clone(),
+                object,
+            })?;
+            z += 1;
+            if bitmap_z[z - 1] {
+                break;
+            }
+        }
+    }
+    if z != array_z.len() {
+        return Err(invalid("triple arrays differ in length"));
+    }
+
+    Ok(z)
+}
+
+/// Add all triples of an HDT file to a builder.
+///
+/// Returns the amount of triples read. Nothing is added to the
+/// builder when the dictionary is invalid, but an invalid triple
+/// section is only noticed after the triples before it were added.
+pub async fn import<R: AsyncRead + Unpin>(
+    mut reader: R,
+    builder: &StoreLayerBuilder,
+    skolemizer: Skolemizer,
+) -> io::Result<usize> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data).await?;
+
+    parse(&data, &skolemizer, |triple| {
+        builder.add_string_triple(triple)?;
+        Ok(())
+    })
+}
+
+fn push_vbyte(buf: &mut Vec<u8>, mut n: u64) {
+    while n > 0x7F {
+        buf.push((n & 0x7F) as u8);
+        n >>= 7;
+    }
+    buf.push(n as u8 | 0x80);
+}
+
+fn push_control_info(
+    buf: &mut Vec<u8>,
+    section_type: u8,
+    format: &str,
+    properties: &[(&str, String)],
+) {
+    let start = buf.len();
+    buf.extend_from_slice(COOKIE);
+    buf.push(section_type);
+    buf.extend_from_slice(format.as_bytes());
+    buf.push(0);
+    for (key, value) in properties {
+        buf.extend_from_slice(format!("{}={};", key, value).as_bytes());
+    }
+    buf.push(0);
+    let crc = crc16(&buf[start..]);
+    buf.extend_from_slice(&crc.to_le_bytes());
+}
+
+fn push_crc32_data(buf: &mut Vec<u8>, data: &[u8]) {
+    buf.extend_from_slice(data);
+    buf.extend_from_slice(&crc32(data).to_le_bytes());
+}
+
+fn push_log_array(buf: &mut Vec<u8>, values: &[u64]) {
+    let width = bits(values.iter().copied().max().unwrap_or(0)) as usize;
+    let start = buf.len();
+    buf.push(LOG_ARRAY_TYPE);
+    buf.push(width as u8);
+    push_vbyte(buf, values.len() as u64);
+    buf.push(crc8(&buf[start..]));
+
+    let mut data = vec![0u8; (values.len() * width).div_ceil(8)];
+    for (i, value) in values.iter().enumerate() {
+        for bit in 0..width {
+            if value & (1 << bit) != 0 {
+                let position = i * width + bit;
+                data[position / 8] |= 1 << (position % 8);
+            }
+        }
+    }
+    push_crc32_data(buf, &data);
+}
+
+fn push_bitmap(buf: &mut Vec<u8>, bits: &[bool]) {
+    let start = buf.len();
+    buf.push(BITMAP_TYPE);
+    push_vbyte(buf, bits.len() as u64);
+    buf.push(crc8(&buf[start..]));
+
+    let mut data = vec![0u8; bits.len().div_ceil(8)];
+    for (i, &bit) in bits.iter().enumerate() {
+        if bit {
+            data[i / 8] |= 1 << (i % 8);
+        }
+    }
+    push_crc32_data(buf, &data);
+}
+
+/// Push a sorted dictionary section in plain front coding.
+fn push_pfc(buf: &mut Vec<u8>, strings: &[String]) {
+    let mut text = Vec::new();
+    let mut blocks = Vec::new();
+    for (i, string) in strings.iter().enumerate() {
+        if i % BLOCK_SIZE == 0 {
+            blocks.push(text.len() as u64);
+            text.extend_from_slice(string.as_bytes());
+        } else {
+            let previous = strings[i - 1].as_bytes();
+            let prefix = previous
+                .iter()
+                .zip(string.as_bytes())
+                .take_while(|(a, b)| a == b)
+                .count();
+            push_vbyte(&mut text, prefix as u64);
+            text.extend_from_slice(&string.as_bytes()[prefix..]);
+        }
+        text.push(0);
+    }
+    blocks.push(text.len() as u64);
+
+    let start = buf.len();
+    buf.push(PFC_TYPE);
+    push_vbyte(buf, strings.len() as u64);
+    push_vbyte(buf, text.len() as u64);
+    push_vbyte(buf, BLOCK_SIZE as u64);
+    buf.push(crc8(&buf[start..]));
+    push_log_array(buf, &blocks);
+    push_crc32_data(buf, &text);
+}
+
+fn node_term(node: String, skolemizer: &Skolemizer) -> String {
+    skolemizer.deskolemize_node(&node).unwrap_or(node)
+}
+
+fn object_term(object: ObjectType, skolemizer: &Skolemizer) -> String {
+    match object {
+        ObjectType::Node(node) => node_term(node, skolemizer),
+        object => {
+            let (lexical, suffix) = literal_parts(object.value().unwrap());
+            format!("\"{}\"{}", lexical, suffix)
+        }
+    }
+}
+
+/// Sort the terms of a dictionary section, returning the strings and the HDT id of every layer id.
+///
+/// HDT ids of the section start after `offset`.
+fn section(mut terms: Vec<(String, u64)>, offset: usize) -> (Vec<String>, HashMap<u64, u64>) {
+    terms.sort_unstable();
+    let ids = terms
+        .iter()
+        .enumerate()
+        .map(|(i, (_, id))| (*id, (offset + i + 1) as u64))
+        .collect();
+
+    (terms.into_iter().map(|(term, _)| term).collect(), ids)
+}
+
+/// Write all triples of a layer as an HDT file.
+///
+/// Skolem IRIs of the given skolemizer are written as blank nodes.
+/// Returns the amount of triples written. The writer is flushed at
+/// the end.
+pub async fn write_hdt<W: AsyncWrite + Unpin>(
+    layer: &dyn Layer,
+    mut writer: W,
+    skolemizer: &Skolemizer,
+) -> io::Result<usize> {
+    let resolve_error = || invalid("triple contains an id that is not known to the layer");
+    let mut subject_ids: Vec<u64> = Vec::new();
+    let mut predicate_ids = Vec::new();
+    let mut object_ids = Vec::new();
+    for triple in layer.triples() {
+        if subject_ids.last() != Some(&triple.subject) {
+            subject_ids.push(triple.subject);
+        }
+        predicate_ids.push(triple.predicate);
+        object_ids.push(triple.object);
+    }
+    subject_ids.sort_unstable();
+    predicate_ids.sort_unstable();
+    predicate_ids.dedup();
+    object_ids.sort_unstable();
+    object_ids.dedup();
+
+    // subjects and object nodes share their ids in a layer
+    let (mut shared, mut subjects, mut objects) = (Vec::new(), Vec::new(), Vec::new());
+    let mut object_iter = object_ids.iter().peekable();
+    for &id in subject_ids.iter() {
+        while object_iter.next_if(|&&object| object < id).is_some() {}
+        let term = node_term(layer.id_subject(id).ok_or_else(resolve_error)?, skolemizer);
+        if object_iter.next_if_eq(&&id).is_some() {
+            shared.push((term, id));
+        } else {
+            subjects.push((term, id));
+        }
+    }
+    for &id in object_ids.iter() {
+        if subject_ids.binary_search(&id).is_err() {
+            let object = layer.id_object(id).ok_or_else(resolve_error)?;
+            objects.push((object_term(object, skolemizer), id));
+        }
+    }
+    let predicates = predicate_ids
+        .iter()
+        .map(|&id| Ok((layer.id_predicate(id).ok_or_else(resolve_error)?, id)))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let shared_len = shared.len();
+    let (shared, shared_ids) = section(shared, 0);
+    let (subjects, mut subject_map) = section(subjects, shared_len);
+    let (predicates, predicate_map) = section(predicates, 0);
+    let (objects, mut object_map) = section(objects, shared_len);
+    subject_map.extend(shared_ids.iter().map(|(&id, &hdt_id)| (id, hdt_id)));
+    object_map.extend(shared_ids);
+
+    let mut triples: Vec<_> = layer
+        .triples()
+        .map(|t| {
+            (
+                subject_map[&t.subject],
+                predicate_map[&t.predicate],
+                object_map[&t.object],
+            )
+        })
+        .collect();
+    triples.sort_unstable();
+    let (mut array_y, mut bitmap_y, mut array_z, mut bitmap_z) =
+        (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for (i, &(subject, predicate, object)) in triples.iter().enumerate() {
+        let next = triples.get(i + 1);
+        let last_object = next.is_none_or(|next| (next.0, next.1) != (subject, predicate));
+        array_z.push(object);
+        bitmap_z.push(last_object);
+        if last_object {
+            array_y.push(predicate);
+            bitmap_y.push(next.is_none_or(|next| next.0 != subject));
+        }
+    }
+
+    let header = format!(
+        "_:dataset <http://www.w3.org/1999/02/22-rdf-syntax-ns#type> <http://purl.org/HDT/hdt#Dataset> .\n\
+         _:dataset <http://rdfs.org/ns/void#triples> \"{}\" .\n",
+        triples.len()
+    );
+    let mut buf = Vec::new();
+    push_control_info(&mut buf, GLOBAL_TYPE, HDT_FORMAT, &[]);
+    push_control_info(
+        &mut buf,
+        HEADER_TYPE,
+        HEADER_FORMAT,
+        &[("length", header.len().to_string())],
+    );
+    buf.extend_from_slice(header.as_bytes());
+
+    let size: usize = [&shared, &subjects, &predicates, &objects]
+        .iter()
+        .flat_map(|section| section.iter())
+        .map(|term| term.len() + 1)
+        .sum();
+    push_control_info(
+        &mut buf,
+        DICTIONARY_TYPE,
+        DICTIONARY_FORMAT,
+        &[
+            ("mapping", "1".to_string()),
+            ("sizeStrings", size.to_string()),
+        ],
+    );
+    for section in [&shared, &subjects, &predicates, &objects] {
+        push_pfc(&mut buf, section);
+    }
+
+    push_control_info(
+        &mut buf,
+        TRIPLES_TYPE,
+        TRIPLES_FORMAT,
+        &[
+            ("order", SPO_ORDER.to_string()),
+            ("numTriples", triples.len().to_string()),
+        ],
+    );
+    push_bitmap(&mut buf, &bitmap_y);
+    push_bitmap(&mut buf, &bitmap_z);
+    push_log_array(&mut buf, &array_y);
+    push_log_array(&mut buf, &array_z);
+
+    writer.write_all(&buf).await?;
+    writer.flush().await?;
+
+    Ok(triples.len())
+}
+
+impl Store {
+    /// Create a base layer with all triples of an HDT file.
+    ///
+    /// Blank nodes are skolemized with the given scope. See the
+    /// `io::hdt` module for how the file is read.
+    pub async fn import_hdt<R: AsyncRead + Unpin>(
+        &self,
+        reader: R,
+        scope: &str,
+    ) -> io::Result<StoreLayer> {
+        let builder = self.create_base_layer().await?;
+        import(reader, &builder, Skolemizer::new(scope)).await?;
+
+        builder.commit().await
+    }
+}
+
+impl StoreLayer {
+    /// Create a child layer adding all triples of an HDT file to this layer.
+    ///
+    /// See `Store::import_hdt` for details.
+    pub async fn import_hdt<R: AsyncRead + Unpin>(
+        &self,
+        reader: R,
+        scope: &str,
+    ) -> io::Result<StoreLayer> {
+        let builder = self.open_write().await?;
+        import(reader, &builder, Skolemizer::new(scope)).await?;
+
+        builder.commit().await
+    }
+
+    /// Write all triples of this layer as an HDT file.
+    ///
+    /// Skolem IRIs of the given scope are written as blank nodes.
+    /// See the `io::hdt` module for how triples are written.
+    pub async fn export_hdt<W: AsyncWrite + Unpin>(
+        &self,
+        writer: W,
+        scope: &str,
+    ) -> io::Result<usize> {
+        write_hdt(self, writer, &Skolemizer::new(scope)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::open_memory_store;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(0xF4, crc8(b"123456789"));
+        assert_eq!(0xBB3D, crc16(b"123456789"));
+        assert_eq!(0xE306_9283, crc32(b"123456789"));
+    }
+
+    #[tokio::test]
+    async fn hdt_round_trip() {
+        let document = "<http://example.com/cow> <http://example.com/says> \"moo\\n\\\"moo\\\"\" .\n\
+                        <http://example.com/cow> <http://example.com/says> \"boe\"@nl .\n\
+                        <http://example.com/cow> <http://example.com/likes> _:grass .\n\
+                        <http://example.com/cow> <http://example.com/likes> <http://example.com/duck> .\n\
+                        <http://example.com/cow> <http://example.com/legs> \"4\"^^<http://www.w3.org/2001/XMLSchema#integer> .\n\
+                        <http://example.com/duck> <http://example.com/says> \"quack\" .\n\
+                        <http://example.com/duck> <http://example.com/likes> <http://example.com/cow> .\n\
+                        _:grass <http://example.com/colour> \"green\" .\n\
+                        <http://example.com/farmer> <http://example.com/owns> <http://example.com/cow> .\n";
+        let store = open_memory_store();
+        let layer = store
+            .import_ntriples(document.as_bytes(), "animals")
+            .await
+            .unwrap();
+        // enough strings for several dictionary blocks
+        let builder = layer.open_write().await.unwrap();
+        for i in 0..40 {
+            builder
+                .add_string_triple(StringTriple::new_value(
+                    "http://example.com/farmer",
+                    "http://example.com/counts",
+                    &format!("sheep {}", i),
+                ))
+                .unwrap();
+        }
+        let layer = builder.commit().await.unwrap();
+
+        let mut hdt = Vec::new();
+        assert_eq!(49, layer.export_hdt(&mut hdt, "animals").await.unwrap());
+        let imported = store.import_hdt(hdt.as_slice(), "animals").await.unwrap();
+        let triples = |layer: &StoreLayer| {
+            let mut triples: Vec<_> = layer
+                .triples()
+                .map(|t| layer.id_triple_to_string(&t).unwrap())
+                .collect();
+            triples.sort();
+            triples
+        };
+        assert_eq!(triples(&layer), triples(&imported));
+
+        // blank nodes are written with their label
+        let mut terms = Vec::new();
+        parse(&hdt, &Skolemizer::new("other"), |triple| {
+            terms.push(triple.subject);
+            Ok(())
+        })
+        .unwrap();
+        assert!(terms.contains(&Skolemizer::new("other").skolemize("grass")));
+
+        let mut corrupted = hdt.clone();
+        let last = corrupted.len() - 5;
+        corrupted[last] ^= 1;
+        let err = store
+            .import_hdt(corrupted.as_slice(), "animals")
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        let err = store
+            .import_hdt(&hdt[..hdt.len() / 2], "animals")
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+}
@@ -0,0 +1,16 @@
+//! Reading and writing RDF documents.
+//!
+//! Documents are read into layer builders, and written from layers.
+//! Blank nodes in documents are stored as skolem IRIs, using a
+//! `Skolemizer` with a scope chosen by the caller, as described in
+//! the `layer` module. Tabular data can be imported as well, by
+//! mapping its columns to predicates. Query bindings can be written as
+//! SPARQL query results.
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod csv;
+pub mod hdt;
+pub mod ntriples;
+pub mod sparql;
+#[cfg(feature = "turtle")]
+pub mod turtle;
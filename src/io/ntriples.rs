@@ -0,0 +1,1111 @@
+//! Reading and writing N-Triples and N-Quads documents.
+//!
+//! N-Triples is a line-based format with one triple per line. IRIs
+//! are stored as nodes and predicates without their angle brackets,
+//! and literals as values. Blank nodes are skolemized. Comments and
+//! empty lines are skipped. Quoted triples of N-Triples-star, written
+//! as `<< s p o >>`, are stored as nodes as described in the
+//! documentation of `StringTriple::quote`.
+//!
+//! Literals with a language tag become language-tagged strings.
+//! Literals of the XSD types `string`, `boolean`, `integer`,
+//! `decimal`, `double` and `dateTime` become the corresponding
+//! `Value`. Literals of other datatypes, or with a lexical form that
+//! isn't valid for their datatype, are stored as plain strings, so
+//! their datatype is lost.
+//!
+//! Layers are written the other way around, with typed values
+//! written as literals of their XSD type, and skolem IRIs of the
+//! given scope written as blank nodes. N-Quads adds the graph name to
+//! the triples of named graphs. Triples are written in the order they
+//! are given, resolving one id at a time through the dictionaries,
+//! so writing only keeps the current subject and predicate around.
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use thiserror::Error;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, Lines};
+
+use crate::layer::{
+    is_quoted_triple, DateTime, Decimal, IdTriple, Layer, LayerDiff, ObjectType, QuadStack,
+    Skolemizer, StringTriple, Value,
+};
+use crate::store::{Store, StoreLayer, StoreLayerBuilder};
+
+pub(super) const XSD_PREFIX: &str = "http://www.w3.org/2001/XMLSchema#";
+
+/// The error returned for a line that isn't valid N-Triples.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("line {line}: {message}")]
+pub struct ParseError {
+    /// The line number, counting from 1.
+    pub line: usize,
+    /// What is wrong with the line.
+    pub message: String,
+}
+
+impl From<ParseError> for io::Error {
+    fn from(err: ParseError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// Parse a single line of an N-Triples document.
+///
+/// Returns None if the line doesn't contain a triple, because it is
+/// empty or a comment. On failure, this returns a description of the
+/// problem.
+pub fn parse_line(line: &str, skolemizer: &Skolemizer) -> Result<Option<StringTriple>, String> {
+    let mut parser = LineParser {
+        rest: line,
+        skolemizer: Some(skolemizer),
+    };
+    parser.skip_whitespace();
+    if parser.at_end() {
+        return Ok(None);
+    }
+
+    let triple = parser.triple()?;
+    parser.skip_whitespace();
+    parser.expect('.')?;
+    parser.skip_whitespace();
+    if !parser.at_end() {
+        return Err(format!("unexpected {:?} after triple", parser.rest));
+    }
+
+    Ok(Some(triple))
+}
+
+/// The node string for a quoted triple, see `StringTriple::quote`.
+pub(crate) fn quote_triple(triple: &StringTriple) -> String {
+    let mut quoted = String::new();
+    push_quoted_triple(&mut quoted, triple, None);
+
+    quoted
+}
+
+/// The triple quoted by a node string, see `StringTriple::unquote`.
+pub(crate) fn unquote_triple(node: &str) -> Option<StringTriple> {
+    let mut parser = LineParser {
+        rest: node,
+        skolemizer: None,
+    };
+    let triple = parser.quoted_triple().ok()?;
+    if !parser.rest.is_empty() {
+        return None;
+    }
+
+    Some(triple)
+}
+
+struct LineParser<'a> {
+    rest: &'a str,
+    /// The skolemizer for blank nodes, which aren't allowed without one.
+    skolemizer: Option<&'a Skolemizer>,
+}
+
+impl<'a> LineParser<'a> {
+    fn skip_whitespace(&mut self) {
+        self.rest = self.rest.trim_start_matches([' ', '\t']);
+    }
+
+    /// Whether only a comment or line ending is left.
+    fn at_end(&self) -> bool {
+        self.rest.is_empty() || self.rest.starts_with(['#', '\r', '\n'])
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        match self.rest.strip_prefix(c) {
+            Some(rest) => {
+                self.rest = rest;
+                Ok(())
+            }
+            None => Err(format!("expected {:?}, found {:?}", c, self.rest)),
+        }
+    }
+
+    fn triple(&mut self) -> Result<StringTriple, String> {
+        let subject = self.node()?;
+        self.skip_whitespace();
+        let predicate = self.iri()?;
+        self.skip_whitespace();
+        let object = if self.rest.starts_with('"') {
+            self.literal()?
+        } else {
+            ObjectType::Node(self.node()?)
+        };
+
+        Ok(StringTriple {
+            subject,
+            predicate,
+            object,
+        })
+    }
+
+    fn quoted_triple(&mut self) -> Result<StringTriple, String> {
+        self.expect('<')?;
+        self.expect('<')?;
+        self.skip_whitespace();
+        let triple = self.triple()?;
+        self.skip_whitespace();
+        self.expect('>')?;
+        self.expect('>')?;
+
+        Ok(triple)
+    }
+
+    /// An IRI, blank node or quoted triple.
+    fn node(&mut self) -> Result<String, String> {
+        if self.rest.starts_with("<<") {
+            return Ok(quote_triple(&self.quoted_triple()?));
+        }
+        match self.rest.strip_prefix("_:") {
+            Some(rest) => {
+                let skolemizer = self.skolemizer.ok_or("unexpected blank node")?;
+                let end = rest
+                    .find(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"' | '#'))
+                    .unwrap_or(rest.len());
+                // a label can contain dots, but can't end with one
+                let label = rest[..end].trim_end_matches('.');
+                if label.is_empty() {
+                    return Err("empty blank node label".to_string());
+                }
+                self.rest = &rest[label.len()..];
+
+                Ok(skolemizer.skolemize(label))
+            }
+            None => self.iri(),
+        }
+    }
+
+    fn iri(&mut self) -> Result<String, String> {
+        self.expect('<')?;
+        let end = self.rest.find('>').ok_or("unterminated IRI")?;
+        let iri = &self.rest[..end];
+        if let Some(c) = iri
+            .chars()
+            .find(|c| matches!(c, ' ' | '<' | '"' | '{' | '}'))
+        {
+            return Err(format!("invalid character {:?} in IRI", c));
+        }
+        self.rest = &self.rest[end + 1..];
+
+        unescape_iri(iri)
+    }
+
+    fn literal(&mut self) -> Result<ObjectType, String> {
+        self.expect('"')?;
+        let mut end = None;
+        let mut escaped = false;
+        for (i, c) in self.rest.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => {
+                    end = Some(i);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let end = end.ok_or("unterminated literal")?;
+        let lexical = unescape_literal(&self.rest[..end])?;
+        self.rest = &self.rest[end + 1..];
+
+        if let Some(rest) = self.rest.strip_prefix('@') {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+                .unwrap_or(rest.len());
+            if end == 0 {
+                return Err("empty language tag".to_string());
+            }
+            self.rest = &rest[end..];
+
+            Ok(ObjectType::new_lang_string(&lexical, &rest[..end]))
+        } else if let Some(rest) = self.rest.strip_prefix("^^") {
+            self.rest = rest;
+            let datatype = self.iri()?;
+
+            Ok(typed_literal(lexical, &datatype).into())
+        } else {
+            Ok(Value::String(lexical).into())
+        }
+    }
+}
+
+/// The value for a literal with a datatype, as described in the module documentation.
+pub(super) fn typed_literal(lexical: String, datatype: &str) -> Value {
+    datatype
+        .strip_prefix(XSD_PREFIX)
+        .and_then(|datatype| parse_xsd(&lexical, datatype))
+        .unwrap_or(Value::String(lexical))
+}
+
+/// The value for the lexical form of an XSD datatype, given by its name without the XSD prefix.
+///
+/// Returns None for unsupported datatypes and invalid lexical forms.
+pub(super) fn parse_xsd(lexical: &str, datatype: &str) -> Option<Value> {
+    match datatype {
+        "boolean" => match lexical {
+            "true" | "1" => Some(Value::Boolean(true)),
+            "false" | "0" => Some(Value::Boolean(false)),
+            _ => None,
+        },
+        "integer" => lexical.parse().ok().map(Value::Integer),
+        "decimal" => Decimal::from_str(lexical).ok().map(Value::Decimal),
+        "dateTime" => parse_date_time(lexical).map(Value::DateTime),
+        "double" => match lexical {
+            "INF" | "+INF" => Some(Value::Double(f64::INFINITY)),
+            "-INF" => Some(Value::Double(f64::NEG_INFINITY)),
+            "NaN" => Some(Value::Double(f64::NAN)),
+            // rust also accepts spellings like "inf", which XSD doesn't
+            s if s.contains(char::is_alphabetic) && !s.contains(['e', 'E']) => None,
+            s => s.parse().ok().map(Value::Double),
+        },
+        _ => None,
+    }
+}
+
+/// Parse an XSD dateTime, which is taken to be in UTC if it has no timezone.
+fn parse_date_time(s: &str) -> Option<DateTime> {
+    let number = |s: &str, len: usize| -> Option<i64> {
+        if s.len() == len && s.bytes().all(|b| b.is_ascii_digit()) {
+            s.parse().ok()
+        } else {
+            None
+        }
+    };
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s),
+    };
+    let (date, time) = s.split_once('T')?;
+    let mut date = date.split('-');
+    let year = date.next().filter(|year| year.len() >= 4)?;
+    let year = number(year, year.len())?;
+    let year = if negative { -year } else { year };
+    let month = number(date.next()?, 2)?;
+    let day = number(date.next()?, 2)?;
+
+    let (time, offset) = if let Some(time) = time.strip_suffix('Z') {
+        (time, 0)
+    } else if time.len() > 6 && time.is_char_boundary(time.len() - 6) {
+        let (time, zone) = time.split_at(time.len() - 6);
+        let sign = match &zone[..1] {
+            "+" => 1,
+            "-" => -1,
+            _ => return None,
+        };
+        let (hours, minutes) = zone[1..].split_once(':')?;
+        (time, sign * (number(hours, 2)? * 60 + number(minutes, 2)?))
+    } else {
+        (time, 0)
+    };
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut time = time.split(':');
+    let hour = number(time.next()?, 2)?;
+    let minute = number(time.next()?, 2)?;
+    let second = number(time.next()?, 2)?;
+    let valid = date.next().is_none()
+        && time.next().is_none()
+        && fraction.bytes().all(|b| b.is_ascii_digit())
+        && (1..=12).contains(&month)
+        && (1..=31).contains(&day)
+        && minute < 60
+        && second < 60
+        && (hour < 24
+            || hour == 24 && minute == 0 && second == 0 && fraction.bytes().all(|b| b == b'0'));
+    if !valid {
+        return None;
+    }
+    let nanos = format!("{:0<9}", &fraction[..fraction.len().min(9)])
+        .parse()
+        .ok()?;
+    let seconds = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second
+        - offset * 60;
+
+    Some(DateTime::new(seconds, nanos))
+}
+
+/// Format a point in time as an XSD dateTime in UTC.
+fn format_date_time(date_time: DateTime) -> String {
+    let seconds = date_time.seconds();
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let time = seconds.rem_euclid(86400);
+    let mut result = format!(
+        "{}{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        if year < 0 { "-" } else { "" },
+        year.abs(),
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    );
+    if date_time.nanos() != 0 {
+        let nanos = format!(".{:09}", date_time.nanos());
+        result.push_str(nanos.trim_end_matches('0'));
+    }
+    result.push('Z');
+
+    result
+}
+
+/// The days since the unix epoch for a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+/// The date in the proleptic Gregorian calendar for the days since the unix epoch.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    (year, month, day)
+}
+
+/// Resolve the escape sequences of an IRI, which may only be unicode escapes.
+pub(super) fn unescape_iri(s: &str) -> Result<String, String> {
+    unescape(s, false)
+}
+
+/// Resolve the escape sequences of a literal.
+pub(super) fn unescape_literal(s: &str) -> Result<String, String> {
+    unescape(s, true)
+}
+
+fn unescape(s: &str, literal: bool) -> Result<String, String> {
+    if !s.contains('\\') {
+        return Ok(s.to_string());
+    }
+
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        let escaped = chars.next().ok_or("escape at end of string")?;
+        let c = match escaped {
+            'u' | 'U' => {
+                let len = if escaped == 'u' { 4 } else { 8 };
+                let hex = chars
+                    .as_str()
+                    .get(..len)
+                    .ok_or("truncated unicode escape")?;
+                // from_str_radix would also accept a sign
+                if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return Err(format!("invalid unicode escape {:?}", hex));
+                }
+                let code = u32::from_str_radix(hex, 16).unwrap();
+                chars = chars.as_str()[len..].chars();
+                char::from_u32(code).ok_or_else(|| format!("invalid code point {:X}", code))?
+            }
+            c if !literal => return Err(format!("invalid escape \\{} in IRI", c)),
+            't' => '\t',
+            'b' => '\u{8}',
+            'n' => '\n',
+            'r' => '\r',
+            'f' => '\u{c}',
+            '"' | '\'' | '\\' => escaped,
+            c => return Err(format!("invalid escape \\{}", c)),
+        };
+        result.push(c);
+    }
+
+    Ok(result)
+}
+
+/// Reads triples from an N-Triples document, one line at a time.
+pub struct NTriplesReader<R> {
+    lines: Lines<R>,
+    line: usize,
+    skolemizer: Skolemizer,
+}
+
+impl<R: AsyncBufRead + Unpin> NTriplesReader<R> {
+    /// Construct a reader for the given document, skolemizing blank nodes with the given skolemizer.
+    pub fn new(reader: R, skolemizer: Skolemizer) -> Self {
+        Self {
+            lines: reader.lines(),
+            line: 0,
+            skolemizer,
+        }
+    }
+
+    /// Read the next triple, or None at the end of the document.
+    ///
+    /// Invalid lines result in an error of kind `InvalidData`, wrapping a `ParseError`.
+    pub async fn next_triple(&mut self) -> io::Result<Option<StringTriple>> {
+        while let Some(line) = self.lines.next_line().await? {
+            self.line += 1;
+            let triple = parse_line(&line, &self.skolemizer).map_err(|message| ParseError {
+                line: self.line,
+                message,
+            })?;
+            if triple.is_some() {
+                return Ok(triple);
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Add all triples of an N-Triples document to a builder.
+///
+/// Returns the amount of triples read. Nothing is added to the
+/// builder after the first invalid line.
+pub async fn import<R: AsyncBufRead + Unpin>(
+    reader: R,
+    builder: &StoreLayerBuilder,
+    skolemizer: Skolemizer,
+) -> io::Result<usize> {
+    let mut reader = NTriplesReader::new(reader, skolemizer);
+    let mut count = 0;
+    while let Some(triple) = reader.next_triple().await? {
+        builder.add_string_triple(triple)?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// The amount of bytes collected before they are written out.
+const WRITE_BUFFER_SIZE: usize = 1 << 16;
+
+/// Whether a blank node label can be written as is.
+///
+/// Labels that can't, like those of anonymous blank nodes read from
+/// Turtle, are written as their skolem IRI instead.
+fn is_valid_label(label: &str) -> bool {
+    label.starts_with(|c: char| c.is_alphanumeric() || c == '_')
+        && !label.ends_with('.')
+        && label
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+fn push_iri(buf: &mut String, iri: &str) {
+    buf.push('<');
+    for c in iri.chars() {
+        match c {
+            '\0'..=' ' | '<' | '>' | '"' | '{' | '}' | '|' | '^' | '`' | '\\' => {
+                buf.push_str(&format!("\\u{:04X}", c as u32))
+            }
+            c => buf.push(c),
+        }
+    }
+    buf.push('>');
+}
+
+/// Push a node, writing skolem IRIs of the skolemizer's scope, if any, as blank nodes.
+fn push_node(buf: &mut String, node: &str, skolemizer: Option<&Skolemizer>) {
+    if is_quoted_triple(node) {
+        if let Some(triple) = unquote_triple(node) {
+            return push_quoted_triple(buf, &triple, skolemizer);
+        }
+    }
+    match skolemizer.and_then(|skolemizer| skolemizer.deskolemize_node(node)) {
+        Some(blank) if is_valid_label(&blank[2..]) => buf.push_str(&blank),
+        _ => push_iri(buf, node),
+    }
+}
+
+fn push_object(buf: &mut String, object: ObjectType, skolemizer: Option<&Skolemizer>) {
+    match object {
+        ObjectType::Node(node) => push_node(buf, &node, skolemizer),
+        object => push_literal(buf, object.value().unwrap()),
+    }
+}
+
+fn push_quoted_triple(buf: &mut String, triple: &StringTriple, skolemizer: Option<&Skolemizer>) {
+    buf.push_str("<< ");
+    push_node(buf, &triple.subject, skolemizer);
+    buf.push(' ');
+    push_iri(buf, &triple.predicate);
+    buf.push(' ');
+    push_object(buf, triple.object.clone(), skolemizer);
+    buf.push_str(" >>");
+}
+
+/// What follows the lexical form of a literal.
+pub(crate) enum LiteralSuffix {
+    /// Nothing, for plain strings.
+    None,
+    /// A language tag.
+    Lang(String),
+    /// An XSD datatype, given by its name without the XSD prefix.
+    Datatype(&'static str),
+}
+
+impl fmt::Display for LiteralSuffix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LiteralSuffix::None => Ok(()),
+            LiteralSuffix::Lang(lang) => write!(f, "@{}", lang),
+            LiteralSuffix::Datatype(datatype) => write!(f, "^^<{}{}>", XSD_PREFIX, datatype),
+        }
+    }
+}
+
+/// The lexical form of a value, and what follows it in a literal.
+pub(crate) fn literal_parts(value: Value) -> (String, LiteralSuffix) {
+    let (lexical, datatype) = match value {
+        Value::String(s) => return (s, LiteralSuffix::None),
+        Value::LangString { value, lang } => return (value, LiteralSuffix::Lang(lang)),
+        Value::Boolean(b) => (b.to_string(), "boolean"),
+        Value::Integer(i) => (i.to_string(), "integer"),
+        Value::Decimal(d) => (d.to_string(), "decimal"),
+        Value::Double(f) if f.is_nan() => ("NaN".to_string(), "double"),
+        Value::Double(f) if f.is_infinite() => {
+            let lexical = if f > 0.0 { "INF" } else { "-INF" };
+            (lexical.to_string(), "double")
+        }
+        Value::Double(f) => (format!("{:e}", f), "double"),
+        Value::DateTime(dt) => (format_date_time(dt), "dateTime"),
+    };
+
+    (lexical, LiteralSuffix::Datatype(datatype))
+}
+
+fn push_literal(buf: &mut String, value: Value) {
+    let (lexical, suffix) = literal_parts(value);
+    buf.push('"');
+    for c in lexical.chars() {
+        match c {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            c => buf.push(c),
+        }
+    }
+    buf.push('"');
+    buf.push_str(&suffix.to_string());
+}
+
+fn unresolved_id() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "triple contains an id that is not known to the layer",
+    )
+}
+
+/// Formats the triples of a layer as lines, remembering the last subject and predicate.
+struct LineWriter<'a> {
+    layer: &'a dyn Layer,
+    skolemizer: &'a Skolemizer,
+    subject: Option<(u64, String)>,
+    predicate: Option<(u64, String)>,
+    buf: String,
+}
+
+impl<'a> LineWriter<'a> {
+    fn new(layer: &'a dyn Layer, skolemizer: &'a Skolemizer) -> Self {
+        Self {
+            layer,
+            skolemizer,
+            subject: None,
+            predicate: None,
+            buf: String::with_capacity(WRITE_BUFFER_SIZE),
+        }
+    }
+
+    fn push_triple(&mut self, triple: IdTriple, graph: Option<&str>) -> io::Result<()> {
+        if self.subject.as_ref().map(|(id, _)| *id) != Some(triple.subject) {
+            let subject = self
+                .layer
+                .id_subject(triple.subject)
+                .ok_or_else(unresolved_id)?;
+            let mut text = String::new();
+            push_node(&mut text, &subject, Some(self.skolemizer));
+            self.subject = Some((triple.subject, text));
+        }
+        if self.predicate.as_ref().map(|(id, _)| *id) != Some(triple.predicate) {
+            let predicate = self
+                .layer
+                .id_predicate(triple.predicate)
+                .ok_or_else(unresolved_id)?;
+            let mut text = String::new();
+            push_iri(&mut text, &predicate);
+            self.predicate = Some((triple.predicate, text));
+        }
+        let object = self
+            .layer
+            .id_object(triple.object)
+            .ok_or_else(unresolved_id)?;
+
+        self.buf.push_str(&self.subject.as_ref().unwrap().1);
+        self.buf.push(' ');
+        self.buf.push_str(&self.predicate.as_ref().unwrap().1);
+        self.buf.push(' ');
+        push_object(&mut self.buf, object, Some(self.skolemizer));
+        if let Some(graph) = graph {
+            self.buf.push(' ');
+            push_node(&mut self.buf, graph, Some(self.skolemizer));
+        }
+        self.buf.push_str(" .\n");
+
+        Ok(())
+    }
+
+    /// Write the given triples, returning how many were written.
+    async fn write<W: AsyncWrite + Unpin>(
+        &mut self,
+        triples: impl Iterator<Item = IdTriple>,
+        graph: Option<&str>,
+        writer: &mut W,
+    ) -> io::Result<usize> {
+        let mut count = 0;
+        for triple in triples {
+            self.push_triple(triple, graph)?;
+            count += 1;
+            if self.buf.len() >= WRITE_BUFFER_SIZE {
+                writer.write_all(self.buf.as_bytes()).await?;
+                self.buf.clear();
+            }
+        }
+        writer.write_all(self.buf.as_bytes()).await?;
+        self.buf.clear();
+
+        Ok(count)
+    }
+}
+
+/// Write triples of a layer as an N-Triples document.
+///
+/// Skolem IRIs of the given skolemizer are written as blank nodes.
+/// Returns the amount of triples written. The writer is flushed at
+/// the end.
+pub async fn write_ntriples<W: AsyncWrite + Unpin>(
+    layer: &dyn Layer,
+    triples: impl Iterator<Item = IdTriple>,
+    mut writer: W,
+    skolemizer: &Skolemizer,
+) -> io::Result<usize> {
+    let count = LineWriter::new(layer, skolemizer)
+        .write(triples, None, &mut writer)
+        .await?;
+    writer.flush().await?;
+
+    Ok(count)
+}
+
+/// Write all triples of a layer and the quads of its named graphs as an N-Quads document.
+///
+/// The triples of the layer itself are in the default graph. See
+/// `write_ntriples` for details.
+pub async fn write_nquads<W: AsyncWrite + Unpin>(
+    layer: &dyn Layer,
+    quads: &QuadStack,
+    mut writer: W,
+    skolemizer: &Skolemizer,
+) -> io::Result<usize> {
+    let mut line_writer = LineWriter::new(layer, skolemizer);
+    let mut count = line_writer
+        .write(layer.triples(), None, &mut writer)
+        .await?;
+    for graph in quads.graphs() {
+        let triples = quads.graph_triples(&graph);
+        count += line_writer
+            .write(triples.into_iter(), Some(&graph), &mut writer)
+            .await?;
+    }
+    writer.flush().await?;
+
+    Ok(count)
+}
+
+/// Write the triples added and removed by a diff as two N-Triples documents.
+///
+/// Returns the amount of added and removed triples written. See
+/// `write_ntriples` for details.
+pub async fn write_diff<W1: AsyncWrite + Unpin, W2: AsyncWrite + Unpin>(
+    diff: &LayerDiff,
+    additions: W1,
+    removals: W2,
+    skolemizer: &Skolemizer,
+) -> io::Result<(usize, usize)> {
+    let added = write_ntriples(&**diff.to(), diff.additions(), additions, skolemizer).await?;
+    let removed = write_ntriples(&**diff.from(), diff.removals(), removals, skolemizer).await?;
+
+    Ok((added, removed))
+}
+
+impl Store {
+    /// Create a base layer with all triples of an N-Triples document.
+    ///
+    /// Blank nodes are skolemized with the given scope. See the
+    /// `io::ntriples` module for how the document is read.
+    pub async fn import_ntriples<R: AsyncBufRead + Unpin>(
+        &self,
+        reader: R,
+        scope: &str,
+    ) -> io::Result<StoreLayer> {
+        let builder = self.create_base_layer().await?;
+        import(reader, &builder, Skolemizer::new(scope)).await?;
+
+        builder.commit().await
+    }
+}
+
+impl StoreLayer {
+    /// Create a child layer adding all triples of an N-Triples document to this layer.
+    ///
+    /// See `Store::import_ntriples` for details.
+    pub async fn import_ntriples<R: AsyncBufRead + Unpin>(
+        &self,
+        reader: R,
+        scope: &str,
+    ) -> io::Result<StoreLayer> {
+        let builder = self.open_write().await?;
+        import(reader, &builder, Skolemizer::new(scope)).await?;
+
+        builder.commit().await
+    }
+
+    /// Write all triples of this layer as an N-Triples document.
+    ///
+    /// Skolem IRIs of the given scope are written as blank nodes.
+    /// See the `io::ntriples` module for how triples are written.
+    pub async fn export_ntriples<W: AsyncWrite + Unpin>(
+        &self,
+        writer: W,
+        scope: &str,
+    ) -> io::Result<usize> {
+        write_ntriples(self, self.triples(), writer, &Skolemizer::new(scope)).await
+    }
+
+    /// Write all triples and quads of this layer as an N-Quads document.
+    ///
+    /// See `StoreLayer::export_ntriples` for details.
+    pub async fn export_nquads<W: AsyncWrite + Unpin>(
+        &self,
+        writer: W,
+        scope: &str,
+    ) -> io::Result<usize> {
+        let quads = self.quads().await?;
+
+        write_nquads(self, &quads, writer, &Skolemizer::new(scope)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::Layer;
+    use crate::store::open_memory_store;
+
+    #[test]
+    fn parse_lines() {
+        let skolemizer = Skolemizer::new("test.nt");
+        let parse = |line: &str| parse_line(line, &skolemizer);
+
+        assert_eq!(Ok(None), parse(""));
+        assert_eq!(Ok(None), parse("  # a comment"));
+        assert_eq!(
+            Ok(Some(StringTriple::new_node(
+                "http://example.com/a",
+                "http://example.com/p",
+                &skolemizer.skolemize("b1.x")
+            ))),
+            parse("<http://example.com/a> <http://example.com/p> _:b1.x. # comment")
+        );
+        assert_eq!(
+            Ok(Some(StringTriple::new_value(
+                "a",
+                "p",
+                "tab\there \"quoted\" \u{e9}\u{1F600}"
+            ))),
+            parse(r#"<a>	<p> "tab\there \"quoted\" é\U0001F600" ."#)
+        );
+        assert_eq!(
+            Ok(Some(StringTriple::new_lang_string(
+                "a", "p", "hallo", "nl-BE"
+            ))),
+            parse(r#"<a> <p> "hallo"@nl-BE ."#)
+        );
+        let typed = |lexical: &str, datatype: &str| {
+            parse(&format!(
+                r#"<a> <p> "{}"^^<{}{}> ."#,
+                lexical, XSD_PREFIX, datatype
+            ))
+            .unwrap()
+            .unwrap()
+            .object
+        };
+        assert_eq!(
+            ObjectType::from(Value::Integer(-42)),
+            typed("-42", "integer")
+        );
+        assert_eq!(
+            ObjectType::from(Value::Boolean(true)),
+            typed("true", "boolean")
+        );
+        assert_eq!(
+            ObjectType::from(Value::Double(1.5e3)),
+            typed("1.5E3", "double")
+        );
+        assert_eq!(
+            ObjectType::from(Value::Decimal("3.14".parse().unwrap())),
+            typed("3.14", "decimal")
+        );
+        assert_eq!(ObjectType::Value("inf".to_string()), typed("inf", "double"));
+        assert_eq!(
+            ObjectType::Value("2021".to_string()),
+            typed("2021", "gYear")
+        );
+
+        assert!(parse("<a> <p> <b>").is_err());
+        assert!(parse("<a> <p> \"unterminated .").is_err());
+        assert!(parse("<a> _:p <b> .").is_err());
+        assert!(parse("<a> <p> <b> . <c>").is_err());
+        assert!(parse(r#"<a> <p> "\q" ."#).is_err());
+        assert!(parse("<a b> <p> <c> .").is_err());
+
+        assert_eq!(
+            Ok(Some(StringTriple::new_node("a\u{e9}", "p", "b"))),
+            parse(r"<a\u00E9> <p> <b> .")
+        );
+        assert!(parse(r"<a\nb> <p> <c> .").is_err());
+        assert!(parse(r"<a\\b> <p> <c> .").is_err());
+        assert!(parse(r#"<a> <p> "\u+041" ."#).is_err());
+        assert!(parse(r"<a\U+0000041> <p> <c> .").is_err());
+    }
+
+    #[tokio::test]
+    async fn import_ntriples_document() {
+        let document = "# animals\n\
+                        <http://example.com/cow> <http://example.com/says> \"moo\" .\n\
+                        \n\
+                        <http://example.com/cow> <http://example.com/likes> _:grass .\n\
+                        _:grass <http://example.com/colour> \"green\"@en .\n";
+        let store = open_memory_store();
+        let layer = store
+            .import_ntriples(document.as_bytes(), "animals.nt")
+            .await
+            .unwrap();
+        assert_eq!(3, layer.triple_count());
+        let grass = Skolemizer::new("animals.nt").skolemize("grass");
+        assert!(layer.string_triple_exists(&StringTriple::new_node(
+            "http://example.com/cow",
+            "http://example.com/likes",
+            &grass
+        )));
+        assert!(layer.string_triple_exists(&StringTriple::new_lang_string(
+            &grass,
+            "http://example.com/colour",
+            "green",
+            "en"
+        )));
+
+        let child = layer
+            .import_ntriples(
+                "<http://example.com/duck> <http://example.com/says> \"quack\" .".as_bytes(),
+                "ducks.nt",
+            )
+            .await
+            .unwrap();
+        assert_eq!(4, child.triple_count());
+
+        let err = store
+            .import_ntriples("<a> <p> <b> .\n<a> <p> .\n".as_bytes(), "broken.nt")
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert_eq!(
+            "line 2: expected '<', found \".\"",
+            err.get_ref().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn parse_and_write_quoted_triples() {
+        let skolemizer = Skolemizer::new("test.nt");
+        let line = r#"<< _:b0 <p> << <a> <q> "1"^^<http://www.w3.org/2001/XMLSchema#integer> >> >> <r> "x"@en ."#;
+        let triple = parse_line(line, &skolemizer).unwrap().unwrap();
+        let inner = StringTriple::new_literal("a", "q", Value::Integer(1));
+        let outer = StringTriple::new_node(&skolemizer.skolemize("b0"), "p", &inner.quote());
+        assert_eq!(
+            StringTriple {
+                subject: outer.quote(),
+                predicate: "r".to_string(),
+                object: ObjectType::new_lang_string("x", "en"),
+            },
+            triple
+        );
+        // whitespace doesn't matter
+        assert_eq!(
+            Ok(Some(triple.clone())),
+            parse_line(&line.replace("<< ", "<<").replace(" >>", ">>"), &skolemizer)
+        );
+        assert!(parse_line("<< <a> <p> <b> <r> <c> .", &skolemizer).is_err());
+        assert!(parse_line("<< <a> <p> <b> >> <r> .", &skolemizer).is_err());
+
+        let mut buf = String::new();
+        push_node(&mut buf, &triple.subject, Some(&skolemizer));
+        assert_eq!(line.strip_suffix(r#" <r> "x"@en ."#), Some(buf.as_str()));
+    }
+
+    #[test]
+    fn date_times_round_trip() {
+        for (lexical, seconds, nanos) in [
+            ("1970-01-01T00:00:00Z", 0, 0),
+            ("2021-03-04T05:06:07.25Z", 1614834367, 250_000_000),
+            ("1969-12-31T23:59:59Z", -1, 0),
+            ("2000-02-29T12:00:00Z", 951825600, 0),
+            ("-0044-03-15T12:00:00Z", -63549316800, 0),
+        ] {
+            let date_time = DateTime::new(seconds, nanos);
+            assert_eq!(Some(date_time), parse_date_time(lexical));
+            assert_eq!(lexical, format_date_time(date_time));
+        }
+        assert_eq!(
+            parse_date_time("2021-03-04T05:06:07Z"),
+            parse_date_time("2021-03-04T07:06:07+02:00")
+        );
+        assert_eq!(
+            parse_date_time("2021-03-05T00:00:00"),
+            parse_date_time("2021-03-04T24:00:00")
+        );
+        assert_eq!(None, parse_date_time("2021-13-04T05:06:07Z"));
+        assert_eq!(None, parse_date_time("2021-03-04T05:06Z"));
+        assert_eq!(None, parse_date_time("21-03-04T05:06:07Z"));
+    }
+
+    #[tokio::test]
+    async fn export_ntriples_document() {
+        let document = "<http://example.com/cow> <http://example.com/says> \"moo\\n\\\"moo\\\"\" .\n\
+                        <http://example.com/cow> <http://example.com/says> \"boe\"@nl .\n\
+                        <http://example.com/cow> <http://example.com/likes> _:grass .\n\
+                        <http://example.com/cow> <http://example.com/weight> \"550.5\"^^<http://www.w3.org/2001/XMLSchema#decimal> .\n\
+                        <http://example.com/cow> <http://example.com/legs> \"4\"^^<http://www.w3.org/2001/XMLSchema#integer> .\n\
+                        <http://example.com/cow> <http://example.com/born> \"2020-01-02T03:04:05Z\"^^<http://www.w3.org/2001/XMLSchema#dateTime> .\n\
+                        <http://example.com/cow> <http://example.com/ratio> \"0.5\"^^<http://www.w3.org/2001/XMLSchema#double> .\n\
+                        <http://example.com/cow> <http://example.com/happy> \"true\"^^<http://www.w3.org/2001/XMLSchema#boolean> .\n\
+                        _:grass <http://example.com/colour> \"green\" .\n\
+                        <http://example.com/odd\\u0020iri> <http://example.com/p> <http://example.com/cow> .\n";
+        let store = open_memory_store();
+        let layer = store
+            .import_ntriples(document.as_bytes(), "animals.nt")
+            .await
+            .unwrap();
+
+        let mut exported = Vec::new();
+        assert_eq!(
+            10,
+            layer
+                .export_ntriples(&mut exported, "animals.nt")
+                .await
+                .unwrap()
+        );
+        let exported = String::from_utf8(exported).unwrap();
+        let mut lines: Vec<_> = exported.lines().collect();
+        lines.sort();
+        let mut expected: Vec<_> = document
+            .lines()
+            .map(|line| line.replace("\"0.5\"", "\"5e-1\""))
+            .collect();
+        expected.sort();
+        assert_eq!(expected, lines);
+
+        // other scopes keep their skolem IRIs
+        let mut exported = Vec::new();
+        layer
+            .export_ntriples(&mut exported, "other.nt")
+            .await
+            .unwrap();
+        let reimported = store
+            .import_ntriples(exported.as_slice(), "other.nt")
+            .await
+            .unwrap();
+        let triples = |layer: &StoreLayer| {
+            let mut triples: Vec<_> = layer
+                .triples()
+                .map(|t| layer.id_triple_to_string(&t).unwrap())
+                .collect();
+            triples.sort();
+            triples
+        };
+        assert_eq!(triples(&layer), triples(&reimported));
+
+        let builder = layer.open_write().await.unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_lang_string(
+                "http://example.com/cow",
+                "http://example.com/says",
+                "boe",
+                "nl",
+            ))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node(
+                "http://example.com/cow",
+                "http://example.com/friend",
+                "http://example.com/duck",
+            ))
+            .unwrap();
+        builder
+            .add_string_quad(crate::layer::StringQuad::new_value(
+                "http://example.com/graph",
+                "http://example.com/duck",
+                "http://example.com/says",
+                "quack",
+            ))
+            .unwrap();
+        let child = builder.commit().await.unwrap();
+
+        let (mut additions, mut removals) = (Vec::new(), Vec::new());
+        let diff = layer.diff(&child).await.unwrap();
+        let skolemizer = Skolemizer::new("animals.nt");
+        assert_eq!(
+            (1, 1),
+            write_diff(&diff, &mut additions, &mut removals, &skolemizer)
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            "<http://example.com/cow> <http://example.com/friend> <http://example.com/duck> .\n",
+            String::from_utf8(additions).unwrap()
+        );
+        assert_eq!(
+            "<http://example.com/cow> <http://example.com/says> \"boe\"@nl .\n",
+            String::from_utf8(removals).unwrap()
+        );
+
+        let mut exported = Vec::new();
+        assert_eq!(
+            11,
+            child
+                .export_nquads(&mut exported, "animals.nt")
+                .await
+                .unwrap()
+        );
+        let exported = String::from_utf8(exported).unwrap();
+        assert!(exported.ends_with(
+            "<http://example.com/duck> <http://example.com/says> \"quack\" <http://example.com/graph> .\n"
+        ));
+    }
+}
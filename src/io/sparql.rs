@@ -0,0 +1,417 @@
+//! Writing query bindings as SPARQL 1.1 query results.
+//!
+//! Bindings of a `BasicGraphPattern` are written in the SPARQL Query
+//! Results JSON or XML format, with the variables of the pattern as
+//! the variables of the results. Ids are resolved through the layer
+//! the pattern was evaluated on: a variable used as a predicate
+//! anywhere in the pattern is resolved as a predicate, and any other
+//! variable as a node or value. Nodes are written as IRIs, skolem IRIs
+//! of the given skolemizer as blank nodes, and quoted triples as
+//! triple terms, as in SPARQL-star. Values are written as literals with
+//! their language tag or XSD datatype.
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::io;
+
+use futures::stream::{Stream, StreamExt};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use super::ntriples::{literal_parts, unquote_triple, LiteralSuffix, XSD_PREFIX};
+use crate::layer::{is_quoted_triple, Layer, ObjectType, Skolemizer, BLANK_NODE_PREFIX};
+use crate::query::{BasicGraphPattern, Bindings, Term};
+
+const RESULTS_NAMESPACE: &str = "http://www.w3.org/2005/sparql-results#";
+
+/// An RDF term bound to a variable.
+enum ResultTerm {
+    Iri(String),
+    Blank(String),
+    Literal(String, LiteralSuffix),
+    Triple(Box<[ResultTerm; 3]>),
+}
+
+/// Resolves the ids of bindings to terms.
+struct TermResolver<'a> {
+    layer: &'a dyn Layer,
+    skolemizer: &'a Skolemizer,
+    variables: Vec<String>,
+    predicate_variables: HashSet<String>,
+}
+
+impl<'a> TermResolver<'a> {
+    fn new(layer: &'a dyn Layer, pattern: &BasicGraphPattern, skolemizer: &'a Skolemizer) -> Self {
+        let predicate_variables = pattern
+            .patterns()
+            .iter()
+            .filter_map(|pattern| match &pattern.predicate {
+                Term::Variable(name) => Some(name.clone()),
+                Term::Id(_) => None,
+            })
+            .collect();
+
+        Self {
+            layer,
+            skolemizer,
+            variables: pattern.variables(),
+            predicate_variables,
+        }
+    }
+
+    /// The terms bound in a result, in the order of the variables.
+    fn terms(&self, bindings: &Bindings) -> io::Result<Vec<(&str, ResultTerm)>> {
+        let mut terms = Vec::with_capacity(self.variables.len());
+        for variable in self.variables.iter() {
+            let id = match bindings.get(variable) {
+                Some(id) => id,
+                None => continue,
+            };
+            let term = if self.predicate_variables.contains(variable) {
+                self.layer.id_predicate(id).map(ResultTerm::Iri)
+            } else {
+                self.layer.id_object(id).map(|object| self.object(object))
+            };
+            match term {
+                Some(term) => terms.push((variable.as_str(), term)),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("id {} bound to {} is not in the layer", id, variable),
+                    ))
+                }
+            }
+        }
+
+        Ok(terms)
+    }
+
+    fn node(&self, node: String) -> ResultTerm {
+        if is_quoted_triple(&node) {
+            if let Some(triple) = unquote_triple(&node) {
+                return ResultTerm::Triple(Box::new([
+                    self.node(triple.subject),
+                    ResultTerm::Iri(triple.predicate),
+                    self.object(triple.object),
+                ]));
+            }
+        }
+        match self.skolemizer.deskolemize_node(&node) {
+            Some(blank) => ResultTerm::Blank(blank[BLANK_NODE_PREFIX.len()..].to_string()),
+            None => ResultTerm::Iri(node),
+        }
+    }
+
+    fn object(&self, object: ObjectType) -> ResultTerm {
+        match object {
+            ObjectType::Node(node) => self.node(node),
+            object => {
+                let (lexical, suffix) = literal_parts(object.value().unwrap());
+                ResultTerm::Literal(lexical, suffix)
+            }
+        }
+    }
+}
+
+fn push_json_string(buf: &mut String, s: &str) {
+    buf.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            '\t' => buf.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(buf, "\\u{:04x}", c as u32).unwrap(),
+            c => buf.push(c),
+        }
+    }
+    buf.push('"');
+}
+
+fn push_json_term(buf: &mut String, term: &ResultTerm) {
+    match term {
+        ResultTerm::Iri(iri) => {
+            buf.push_str("{\"type\":\"uri\",\"value\":");
+            push_json_string(buf, iri);
+        }
+        ResultTerm::Blank(label) => {
+            buf.push_str("{\"type\":\"bnode\",\"value\":");
+            push_json_string(buf, label);
+        }
+        ResultTerm::Literal(lexical, suffix) => {
+            buf.push_str("{\"type\":\"literal\",\"value\":");
+            push_json_string(buf, lexical);
+            match suffix {
+                LiteralSuffix::None => {}
+                LiteralSuffix::Lang(lang) => {
+                    buf.push_str(",\"xml:lang\":");
+                    push_json_string(buf, lang);
+                }
+                LiteralSuffix::Datatype(datatype) => {
+                    buf.push_str(",\"datatype\":");
+                    push_json_string(buf, &format!("{}{}", XSD_PREFIX, datatype));
+                }
+            }
+        }
+        ResultTerm::Triple(parts) => {
+            buf.push_str("{\"type\":\"triple\",\"value\":{");
+            for (i, (name, part)) in ["subject", "predicate", "object"]
+                .iter()
+                .zip(parts.iter())
+                .enumerate()
+            {
+                if i > 0 {
+                    buf.push(',');
+                }
+                push_json_string(buf, name);
+                buf.push(':');
+                push_json_term(buf, part);
+            }
+            buf.push('}');
+        }
+    }
+    buf.push('}');
+}
+
+/// Write bindings of a basic graph pattern as a SPARQL Query Results JSON document.
+///
+/// Returns the amount of results written. The writer is flushed at
+/// the end.
+pub async fn write_results_json<W, S>(
+    layer: &dyn Layer,
+    pattern: &BasicGraphPattern,
+    bindings: S,
+    mut writer: W,
+    skolemizer: &Skolemizer,
+) -> io::Result<usize>
+where
+    W: AsyncWrite + Unpin,
+    S: Stream<Item = Bindings>,
+{
+    let resolver = TermResolver::new(layer, pattern, skolemizer);
+    let mut buf = String::from("{\"head\":{\"vars\":[");
+    for (i, variable) in resolver.variables.iter().enumerate() {
+        if i > 0 {
+            buf.push(',');
+        }
+        push_json_string(&mut buf, variable);
+    }
+    buf.push_str("]},\"results\":{\"bindings\":[");
+    writer.write_all(buf.as_bytes()).await?;
+
+    let mut bindings = Box::pin(bindings);
+    let mut count = 0;
+    while let Some(bindings) = bindings.next().await {
+        buf.clear();
+        if count > 0 {
+            buf.push(',');
+        }
+        buf.push_str("\n{");
+        for (i, (variable, term)) in resolver.terms(&bindings)?.iter().enumerate() {
+            if i > 0 {
+                buf.push(',');
+            }
+            push_json_string(&mut buf, variable);
+            buf.push(':');
+            push_json_term(&mut buf, term);
+        }
+        buf.push('}');
+        writer.write_all(buf.as_bytes()).await?;
+        count += 1;
+    }
+    writer.write_all(b"\n]}}\n").await?;
+    writer.flush().await?;
+
+    Ok(count)
+}
+
+fn push_xml_escaped(buf: &mut String, s: &str) {
+    for c in s.chars() {
+        match c {
+            '&' => buf.push_str("&amp;"),
+            '<' => buf.push_str("&lt;"),
+            '>' => buf.push_str("&gt;"),
+            '"' => buf.push_str("&quot;"),
+            c => buf.push(c),
+        }
+    }
+}
+
+fn push_xml_term(buf: &mut String, term: &ResultTerm) {
+    match term {
+        ResultTerm::Iri(iri) => {
+            buf.push_str("<uri>");
+            push_xml_escaped(buf, iri);
+            buf.push_str("</uri>");
+        }
+        ResultTerm::Blank(label) => {
+            buf.push_str("<bnode>");
+            push_xml_escaped(buf, label);
+            buf.push_str("</bnode>");
+        }
+        ResultTerm::Literal(lexical, suffix) => {
+            match suffix {
+                LiteralSuffix::None => buf.push_str("<literal>"),
+                LiteralSuffix::Lang(lang) => {
+                    buf.push_str("<literal xml:lang=\"");
+                    push_xml_escaped(buf, lang);
+                    buf.push_str("\">");
+                }
+                LiteralSuffix::Datatype(datatype) => {
+                    write!(buf, "<literal datatype=\"{}{}\">", XSD_PREFIX, datatype).unwrap()
+                }
+            }
+            push_xml_escaped(buf, lexical);
+            buf.push_str("</literal>");
+        }
+        ResultTerm::Triple(parts) => {
+            buf.push_str("<triple>");
+            for (name, part) in ["subject", "predicate", "object"].iter().zip(parts.iter()) {
+                write!(buf, "<{}>", name).unwrap();
+                push_xml_term(buf, part);
+                write!(buf, "</{}>", name).unwrap();
+            }
+            buf.push_str("</triple>");
+        }
+    }
+}
+
+/// Write bindings of a basic graph pattern as a SPARQL Query Results XML document.
+///
+/// Returns the amount of results written. The writer is flushed at
+/// the end.
+pub async fn write_results_xml<W, S>(
+    layer: &dyn Layer,
+    pattern: &BasicGraphPattern,
+    bindings: S,
+    mut writer: W,
+    skolemizer: &Skolemizer,
+) -> io::Result<usize>
+where
+    W: AsyncWrite + Unpin,
+    S: Stream<Item = Bindings>,
+{
+    let resolver = TermResolver::new(layer, pattern, skolemizer);
+    let mut buf = format!(
+        "<?xml version=\"1.0\"?>\n<sparql xmlns=\"{}\">\n<head>\n",
+        RESULTS_NAMESPACE
+    );
+    for variable in resolver.variables.iter() {
+        buf.push_str("<variable name=\"");
+        push_xml_escaped(&mut buf, variable);
+        buf.push_str("\"/>\n");
+    }
+    buf.push_str("</head>\n<results>\n");
+    writer.write_all(buf.as_bytes()).await?;
+
+    let mut bindings = Box::pin(bindings);
+    let mut count = 0;
+    while let Some(bindings) = bindings.next().await {
+        buf.clear();
+        buf.push_str("<result>");
+        for (variable, term) in resolver.terms(&bindings)? {
+            buf.push_str("<binding name=\"");
+            push_xml_escaped(&mut buf, variable);
+            buf.push_str("\">");
+            push_xml_term(&mut buf, &term);
+            buf.push_str("</binding>");
+        }
+        buf.push_str("</result>\n");
+        writer.write_all(buf.as_bytes()).await?;
+        count += 1;
+    }
+    writer.write_all(b"</results>\n</sparql>\n").await?;
+    writer.flush().await?;
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::{StringTriple, Value};
+    use crate::store::open_memory_store;
+
+    #[tokio::test]
+    async fn write_bindings_as_sparql_results() {
+        let skolemizer = Skolemizer::new("farm");
+        let grass = skolemizer.skolemize("grass");
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_lang_string(
+                "http://example.com/cow",
+                "http://example.com/says",
+                "\"moo\" & <boe>",
+                "en",
+            ))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node(
+                "http://example.com/cow",
+                "http://example.com/eats",
+                &grass,
+            ))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple {
+                subject: grass.clone(),
+                predicate: "http://example.com/height".to_string(),
+                object: ObjectType::from(Value::Integer(12)),
+            })
+            .unwrap();
+        let layer = builder.commit().await.unwrap();
+
+        let cow = layer.subject_id("http://example.com/cow").unwrap();
+        let pattern =
+            BasicGraphPattern::new().with_pattern(Term::Id(cow), Term::var("p"), Term::var("o"));
+
+        let mut json = Vec::new();
+        let count = write_results_json(
+            &layer,
+            &pattern,
+            pattern.evaluate(&layer),
+            &mut json,
+            &skolemizer,
+        )
+        .await
+        .unwrap();
+        assert_eq!(2, count);
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(serde_json::json!(["p", "o"]), json["head"]["vars"]);
+        let results = json["results"]["bindings"].as_array().unwrap();
+        assert!(results.contains(&serde_json::json!({
+            "p": {"type": "uri", "value": "http://example.com/says"},
+            "o": {"type": "literal", "value": "\"moo\" & <boe>", "xml:lang": "en"},
+        })));
+        assert!(results.contains(&serde_json::json!({
+            "p": {"type": "uri", "value": "http://example.com/eats"},
+            "o": {"type": "bnode", "value": "grass"},
+        })));
+
+        let pattern = BasicGraphPattern::new().with_pattern(
+            Term::var("s"),
+            Term::Id(layer.predicate_id("http://example.com/height").unwrap()),
+            Term::var("h"),
+        );
+        let mut xml = Vec::new();
+        write_results_xml(
+            &layer,
+            &pattern,
+            pattern.evaluate(&layer),
+            &mut xml,
+            &skolemizer,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            "<?xml version=\"1.0\"?>\n\
+             <sparql xmlns=\"http://www.w3.org/2005/sparql-results#\">\n\
+             <head>\n<variable name=\"s\"/>\n<variable name=\"h\"/>\n</head>\n\
+             <results>\n\
+             <result><binding name=\"s\"><bnode>grass</bnode></binding>\
+             <binding name=\"h\"><literal datatype=\"http://www.w3.org/2001/XMLSchema#integer\">12</literal></binding></result>\n\
+             </results>\n</sparql>\n",
+            String::from_utf8(xml).unwrap()
+        );
+    }
+}
@@ -0,0 +1,847 @@
+//! Reading Turtle documents.
+//!
+//! Turtle extends N-Triples with prefixed names, lists of predicates
+//! and objects sharing a subject, anonymous blank nodes, collections,
+//! and shorthands for numbers, booleans and `rdf:type`. Triples are
+//! stored the same way as for N-Triples, see the `ntriples` module.
+//! Collections become `rdf:first`/`rdf:rest` lists. Quoted triples
+//! and annotations of Turtle-star are supported, with an annotation
+//! adding both the triple and the statements about it. Anonymous blank
+//! nodes are skolemized with labels starting with `-`, which no blank
+//! node in a document can have.
+//!
+//! Relative IRIs are resolved against the base IRI, without removing
+//! `.` and `..` segments from the resulting path.
+//!
+//! Unlike in N-Triples, statements can span several lines, so the
+//! whole document is read into memory before it is parsed.
+//!
+//! This module requires the `turtle` feature.
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::ntriples::{typed_literal, unescape_iri, unescape_literal, ParseError, XSD_PREFIX};
+use crate::layer::{ObjectType, Skolemizer, StringTriple, Value};
+use crate::storage::PrefixMap;
+use crate::store::{NamedGraph, StoreLayer, StoreLayerBuilder, TransactionError};
+
+const RDF_PREFIX: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+
+/// Parse a Turtle document, passing every triple to `emit`.
+///
+/// Returns the prefixes declared in the document. Invalid documents
+/// result in an error of kind `InvalidData`, wrapping a `ParseError`.
+/// Errors returned by `emit` stop the parse, and are returned as is.
+pub fn parse(
+    document: &str,
+    skolemizer: &Skolemizer,
+    emit: impl FnMut(StringTriple) -> io::Result<()>,
+) -> io::Result<PrefixMap> {
+    let mut parser = Parser {
+        rest: document,
+        line: 1,
+        base: String::new(),
+        prefixes: PrefixMap::new(),
+        skolemizer,
+        anonymous: 0,
+        emit,
+    };
+    parser.document()?;
+
+    Ok(parser.prefixes)
+}
+
+/// Add all triples of a Turtle document to a builder.
+///
+/// Returns the prefixes declared in the document.
+pub async fn import<R: AsyncRead + Unpin>(
+    mut reader: R,
+    builder: &StoreLayerBuilder,
+    skolemizer: Skolemizer,
+) -> io::Result<PrefixMap> {
+    let mut document = String::new();
+    reader.read_to_string(&mut document).await?;
+
+    parse(&document, &skolemizer, |triple| {
+        builder.add_string_triple(triple)?;
+        Ok(())
+    })
+}
+
+impl NamedGraph {
+    /// Add all triples of a Turtle document to this graph, and add its prefixes to the prefixes of this graph.
+    ///
+    /// Blank nodes are skolemized with the given scope. The triples
+    /// are committed as a child layer of the current head, or as a
+    /// base layer if there is no head, in a transaction. Prefixes
+    /// declared in the document replace existing prefixes with the
+    /// same name.
+    pub async fn import_turtle<R: AsyncRead + Unpin>(
+        &self,
+        reader: R,
+        scope: &str,
+    ) -> Result<StoreLayer, TransactionError> {
+        let transaction = self.transaction().await?;
+        let declared = import(reader, transaction.builder(), Skolemizer::new(scope)).await?;
+        let mut prefixes = self.prefixes().await?;
+        prefixes.extend(declared);
+
+        transaction.with_prefixes(prefixes).commit().await
+    }
+}
+
+/// Whether the character can be part of a prefix or a blank node label.
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.') || !c.is_ascii()
+}
+
+/// Whether the IRI starts with a scheme, making it absolute.
+fn has_scheme(iri: &str) -> bool {
+    match iri.find(':') {
+        Some(end) => {
+            iri.starts_with(|c: char| c.is_ascii_alphabetic())
+                && iri[..end]
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        }
+        None => false,
+    }
+}
+
+/// Resolve a relative IRI against a base IRI, as described in the module documentation.
+fn resolve_iri(base: &str, iri: String) -> String {
+    if base.is_empty() || has_scheme(&iri) {
+        return iri;
+    }
+    let without = |base: &'_ str, delimiters: &[char]| match base.find(delimiters) {
+        Some(end) => base[..end].to_string(),
+        None => base.to_string(),
+    };
+    let scheme_end = base.find(':').map(|i| i + 1).unwrap_or(0);
+    let authority_end = match base[scheme_end..].strip_prefix("//") {
+        Some(rest) => scheme_end + 2 + rest.find(['/', '?', '#']).unwrap_or(rest.len()),
+        None => scheme_end,
+    };
+
+    if iri.is_empty() || iri.starts_with('#') {
+        without(base, &['#']) + &iri
+    } else if iri.starts_with('?') {
+        without(base, &['?', '#']) + &iri
+    } else if iri.starts_with("//") {
+        base[..scheme_end].to_string() + &iri
+    } else if iri.starts_with('/') {
+        base[..authority_end].to_string() + &iri
+    } else {
+        let path = without(base, &['?', '#']);
+        let directory_end = path[authority_end..]
+            .rfind('/')
+            .map(|i| authority_end + i + 1)
+            .unwrap_or(authority_end);
+        let mut result = path[..directory_end].to_string();
+        if directory_end == authority_end && authority_end > scheme_end {
+            // a base without a path
+            result.push('/');
+        }
+        result + &iri
+    }
+}
+
+struct Parser<'a, F> {
+    rest: &'a str,
+    line: usize,
+    base: String,
+    prefixes: PrefixMap,
+    skolemizer: &'a Skolemizer,
+    anonymous: usize,
+    emit: F,
+}
+
+impl<'a, F: FnMut(StringTriple) -> io::Result<()>> Parser<'a, F> {
+    fn error(&self, message: impl Into<String>) -> io::Error {
+        ParseError {
+            line: self.line,
+            message: message.into(),
+        }
+        .into()
+    }
+
+    /// A short description of what comes next, for errors.
+    fn found(&self) -> String {
+        if self.rest.is_empty() {
+            "end of document".to_string()
+        } else {
+            format!("{:?}", self.rest.chars().take(16).collect::<String>())
+        }
+    }
+
+    fn advance(&mut self, len: usize) {
+        self.line += self.rest[..len].matches('\n').count();
+        self.rest = &self.rest[len..];
+    }
+
+    /// Skip whitespace and comments.
+    fn skip_whitespace(&mut self) {
+        loop {
+            let len = self.rest.len() - self.rest.trim_start().len();
+            self.advance(len);
+            if !self.rest.starts_with('#') {
+                return;
+            }
+            let len = self.rest.find('\n').unwrap_or(self.rest.len());
+            self.advance(len);
+        }
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        let found = self.rest.starts_with(s);
+        if found {
+            self.advance(s.len());
+        }
+
+        found
+    }
+
+    /// Eat a keyword followed by whitespace, ignoring case.
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self
+            .rest
+            .get(..keyword.len())
+            .is_some_and(|s| s.eq_ignore_ascii_case(keyword))
+            && self.rest[keyword.len()..].starts_with(char::is_whitespace);
+        if found {
+            self.advance(keyword.len());
+        }
+
+        found
+    }
+
+    fn expect(&mut self, s: &str) -> io::Result<()> {
+        self.skip_whitespace();
+        if self.eat(s) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected {:?}, found {}", s, self.found())))
+        }
+    }
+
+    fn emit(&mut self, subject: &str, predicate: &str, object: ObjectType) -> io::Result<()> {
+        (self.emit)(StringTriple {
+            subject: subject.to_string(),
+            predicate: predicate.to_string(),
+            object,
+        })
+    }
+
+    fn document(&mut self) -> io::Result<()> {
+        loop {
+            self.skip_whitespace();
+            if self.rest.is_empty() {
+                return Ok(());
+            }
+            if self.eat("@prefix") {
+                self.prefix()?;
+                self.expect(".")?;
+            } else if self.eat("@base") {
+                self.base()?;
+                self.expect(".")?;
+            } else if self.eat_keyword("PREFIX") {
+                self.prefix()?;
+            } else if self.eat_keyword("BASE") {
+                self.base()?;
+            } else {
+                self.triples()?;
+                self.expect(".")?;
+            }
+        }
+    }
+
+    fn prefix(&mut self) -> io::Result<()> {
+        self.skip_whitespace();
+        let len = self
+            .rest
+            .find(|c| !is_name_char(c))
+            .filter(|&len| self.rest[len..].starts_with(':'))
+            .ok_or_else(|| self.error(format!("expected a prefix, found {}", self.found())))?;
+        let name = self.rest[..len].to_string();
+        self.advance(len + 1);
+        self.skip_whitespace();
+        let namespace = self.iri_ref()?;
+        self.prefixes.insert(name, namespace);
+
+        Ok(())
+    }
+
+    fn base(&mut self) -> io::Result<()> {
+        self.skip_whitespace();
+        self.base = self.iri_ref()?;
+
+        Ok(())
+    }
+
+    fn triples(&mut self) -> io::Result<()> {
+        if self.rest.starts_with('[') {
+            let subject = self.blank_node_property_list()?;
+            self.skip_whitespace();
+            if !self.rest.starts_with('.') {
+                self.predicate_object_list(&subject)?;
+            }
+        } else {
+            let subject = if self.rest.starts_with('(') {
+                self.collection()?
+            } else {
+                self.node()?
+            };
+            self.predicate_object_list(&subject)?;
+        }
+
+        Ok(())
+    }
+
+    fn predicate_object_list(&mut self, subject: &str) -> io::Result<()> {
+        loop {
+            self.skip_whitespace();
+            let predicate = self.verb()?;
+            loop {
+                self.skip_whitespace();
+                let object = self.object()?;
+                self.skip_whitespace();
+                if self.eat("{|") {
+                    let triple = StringTriple {
+                        subject: subject.to_string(),
+                        predicate: predicate.clone(),
+                        object,
+                    };
+                    let quoted = triple.quote();
+                    (self.emit)(triple)?;
+                    self.predicate_object_list(&quoted)?;
+                    self.expect("|}")?;
+                    self.skip_whitespace();
+                } else {
+                    self.emit(subject, &predicate, object)?;
+                }
+                if !self.eat(",") {
+                    break;
+                }
+            }
+
+            let mut more = false;
+            while self.eat(";") {
+                more = true;
+                self.skip_whitespace();
+            }
+            if !more || self.rest.is_empty() || self.rest.starts_with(['.', ']', '|']) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn verb(&mut self) -> io::Result<String> {
+        let is_a = self.rest.starts_with('a')
+            && !self.rest[1..].starts_with(|c: char| is_name_char(c) || c == ':');
+        if is_a {
+            self.advance(1);
+            Ok(format!("{}type", RDF_PREFIX))
+        } else {
+            self.iri()
+        }
+    }
+
+    fn object(&mut self) -> io::Result<ObjectType> {
+        match self.rest.chars().next() {
+            Some('[') => Ok(ObjectType::Node(self.blank_node_property_list()?)),
+            Some('(') => Ok(ObjectType::Node(self.collection()?)),
+            Some('"' | '\'') => self.literal(),
+            Some(c) if c.is_ascii_digit() || matches!(c, '+' | '-' | '.') => self.number(),
+            _ => {
+                for (keyword, value) in [("true", true), ("false", false)] {
+                    let found = self.rest.starts_with(keyword)
+                        && !self.rest[keyword.len()..]
+                            .starts_with(|c: char| is_name_char(c) || c == ':');
+                    if found {
+                        self.advance(keyword.len());
+                        return Ok(Value::Boolean(value).into());
+                    }
+                }
+
+                Ok(ObjectType::Node(self.node()?))
+            }
+        }
+    }
+
+    /// An IRI, prefixed name, labeled blank node or quoted triple.
+    fn node(&mut self) -> io::Result<String> {
+        if self.rest.starts_with("<<") {
+            return self.quoted_triple();
+        }
+        if !self.rest.starts_with("_:") {
+            return self.iri();
+        }
+
+        let rest = &self.rest[2..];
+        let end = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+        // a label can contain dots, but can't end with one
+        let label = rest[..end].trim_end_matches('.');
+        if label.is_empty() {
+            return Err(self.error("empty blank node label"));
+        }
+        let node = self.skolemizer.skolemize(label);
+        self.advance(2 + label.len());
+
+        Ok(node)
+    }
+
+    /// An IRI or prefixed name.
+    fn iri(&mut self) -> io::Result<String> {
+        if self.rest.starts_with('<') {
+            self.iri_ref()
+        } else {
+            self.prefixed_name()
+        }
+    }
+
+    fn iri_ref(&mut self) -> io::Result<String> {
+        if !self.eat("<") {
+            return Err(self.error(format!("expected an IRI, found {}", self.found())));
+        }
+        let end = self
+            .rest
+            .find('>')
+            .ok_or_else(|| self.error("unterminated IRI"))?;
+        let iri = &self.rest[..end];
+        if let Some(c) = iri
+            .chars()
+            .find(|c| c.is_whitespace() || matches!(c, '<' | '"' | '{' | '}'))
+        {
+            return Err(self.error(format!("invalid character {:?} in IRI", c)));
+        }
+        let iri = unescape_iri(iri).map_err(|message| self.error(message))?;
+        self.advance(end + 1);
+
+        Ok(resolve_iri(&self.base, iri))
+    }
+
+    fn prefixed_name(&mut self) -> io::Result<String> {
+        let colon = self
+            .rest
+            .find(|c| !is_name_char(c))
+            .filter(|&len| self.rest[len..].starts_with(':'))
+            .ok_or_else(|| self.error(format!("expected an IRI, found {}", self.found())))?;
+        let namespace = match self.prefixes.get(&self.rest[..colon]) {
+            Some(namespace) => namespace.clone(),
+            None => {
+                let prefix = &self.rest[..colon];
+                return Err(self.error(format!("undefined prefix {:?}", prefix)));
+            }
+        };
+
+        // the local name, with the length it has in the document after each character
+        let mut local = Vec::new();
+        let mut chars = self.rest[colon + 1..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some((i, c)) if c.is_ascii_punctuation() => {
+                        local.push((c, i + 1, true));
+                    }
+                    _ => return Err(self.error("invalid escape in prefixed name")),
+                },
+                c if is_name_char(c) || c == ':' || c == '%' => {
+                    local.push((c, i + c.len_utf8(), false));
+                }
+                _ => break,
+            }
+        }
+        // a local name can contain dots, but can't end with one
+        while let Some(('.', _, false)) = local.last() {
+            local.pop();
+        }
+        let len = local.last().map(|&(_, len, _)| len).unwrap_or(0);
+        let iri = namespace + &local.into_iter().map(|(c, _, _)| c).collect::<String>();
+        self.advance(colon + 1 + len);
+
+        Ok(iri)
+    }
+
+    /// A quoted triple, returning its node string.
+    ///
+    /// Anonymous blank nodes in a quoted triple can't have properties,
+    /// and collections aren't allowed.
+    fn quoted_triple(&mut self) -> io::Result<String> {
+        self.advance(2);
+        self.skip_whitespace();
+        let subject = if self.eat("[") {
+            self.expect("]")?;
+            self.anonymous()
+        } else {
+            self.node()?
+        };
+        self.skip_whitespace();
+        let predicate = self.verb()?;
+        self.skip_whitespace();
+        let object = if self.eat("[") {
+            self.expect("]")?;
+            ObjectType::Node(self.anonymous())
+        } else if self.rest.starts_with('(') {
+            return Err(self.error("unexpected collection in quoted triple"));
+        } else {
+            self.object()?
+        };
+        self.expect(">>")?;
+
+        Ok(StringTriple {
+            subject,
+            predicate,
+            object,
+        }
+        .quote())
+    }
+
+    fn anonymous(&mut self) -> String {
+        self.anonymous += 1;
+
+        self.skolemizer.skolemize(&format!("-{}", self.anonymous))
+    }
+
+    fn blank_node_property_list(&mut self) -> io::Result<String> {
+        self.advance(1);
+        let node = self.anonymous();
+        self.skip_whitespace();
+        if !self.rest.starts_with(']') {
+            self.predicate_object_list(&node)?;
+        }
+        self.expect("]")?;
+
+        Ok(node)
+    }
+
+    fn collection(&mut self) -> io::Result<String> {
+        self.advance(1);
+        let mut items = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.eat(")") {
+                break;
+            }
+            if self.rest.is_empty() {
+                return Err(self.error("unterminated collection"));
+            }
+            items.push(self.object()?);
+        }
+
+        let mut list = format!("{}nil", RDF_PREFIX);
+        let first = format!("{}first", RDF_PREFIX);
+        let rest = format!("{}rest", RDF_PREFIX);
+        for item in items.into_iter().rev() {
+            let node = self.anonymous();
+            self.emit(&node, &first, item)?;
+            self.emit(&node, &rest, ObjectType::Node(list))?;
+            list = node;
+        }
+
+        Ok(list)
+    }
+
+    fn literal(&mut self) -> io::Result<ObjectType> {
+        let quote = if self.rest.starts_with("\"\"\"") {
+            "\"\"\""
+        } else if self.rest.starts_with("'''") {
+            "'''"
+        } else {
+            &self.rest[..1]
+        };
+        let long = quote.len() == 3;
+        let start_line = self.line;
+        self.advance(quote.len());
+
+        let mut end = None;
+        let mut escaped = false;
+        for (i, c) in self.rest.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '\n' | '\r' if !long => break,
+                _ if self.rest[i..].starts_with(quote) => {
+                    // quotes right before the closing quotes of a long string are part of it
+                    let extra = if long {
+                        self.rest[i + quote.len()..]
+                            .chars()
+                            .take_while(|&c| quote.starts_with(c))
+                            .count()
+                            .min(2)
+                    } else {
+                        0
+                    };
+                    end = Some(i + extra);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let end = end.ok_or_else(|| ParseError {
+            line: start_line,
+            message: "unterminated string".to_string(),
+        })?;
+        let lexical = unescape_literal(&self.rest[..end]).map_err(|message| self.error(message))?;
+        self.advance(end + quote.len());
+
+        if self.eat("@") {
+            let end = self
+                .rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+                .unwrap_or(self.rest.len());
+            if end == 0 {
+                return Err(self.error("empty language tag"));
+            }
+            let lang = self.rest[..end].to_string();
+            self.advance(end);
+
+            Ok(ObjectType::new_lang_string(&lexical, &lang))
+        } else if self.eat("^^") {
+            let datatype = self.iri()?;
+
+            Ok(typed_literal(lexical, &datatype).into())
+        } else {
+            Ok(Value::String(lexical).into())
+        }
+    }
+
+    fn number(&mut self) -> io::Result<ObjectType> {
+        let bytes = self.rest.as_bytes();
+        let digits = |from: usize| {
+            bytes[from.min(bytes.len())..]
+                .iter()
+                .take_while(|b| b.is_ascii_digit())
+                .count()
+        };
+        let mut len = matches!(bytes[0], b'+' | b'-') as usize;
+        let integer_digits = digits(len);
+        len += integer_digits;
+        let mut datatype = "integer";
+        if bytes.get(len) == Some(&b'.') && digits(len + 1) > 0 {
+            len += 1 + digits(len + 1);
+            datatype = "decimal";
+        } else if integer_digits == 0 {
+            return Err(self.error(format!("expected a number, found {}", self.found())));
+        }
+        if matches!(bytes.get(len), Some(b'e' | b'E')) {
+            let mut exponent = len + 1;
+            if matches!(bytes.get(exponent), Some(b'+' | b'-')) {
+                exponent += 1;
+            }
+            let exponent_digits = digits(exponent);
+            if exponent_digits == 0 {
+                return Err(self.error("missing exponent"));
+            }
+            len = exponent + exponent_digits;
+            datatype = "double";
+        }
+        let lexical = self.rest[..len].to_string();
+        self.advance(len);
+
+        Ok(typed_literal(lexical, &format!("{}{}", XSD_PREFIX, datatype)).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::{Decimal, Layer};
+    use crate::store::open_memory_store;
+
+    fn parse_triples(document: &str) -> io::Result<(Vec<StringTriple>, PrefixMap)> {
+        let mut triples = Vec::new();
+        let prefixes = parse(document, &Skolemizer::new("test.ttl"), |triple| {
+            triples.push(triple);
+            Ok(())
+        })?;
+        triples.sort();
+
+        Ok((triples, prefixes))
+    }
+
+    #[test]
+    fn resolve_relative_iris() {
+        let base = "http://example.com/a/b?q#f";
+        let resolve = |iri: &str| resolve_iri(base, iri.to_string());
+        assert_eq!("http://example.com/a/c", resolve("c"));
+        assert_eq!("http://example.com/c", resolve("/c"));
+        assert_eq!("http://example.com/a/b?q#g", resolve("#g"));
+        assert_eq!("http://example.com/a/b?r", resolve("?r"));
+        assert_eq!("http://other.com/c", resolve("//other.com/c"));
+        assert_eq!("urn:x", resolve("urn:x"));
+        assert_eq!(
+            "http://example.com/c",
+            resolve_iri("http://example.com", "c".into())
+        );
+    }
+
+    #[test]
+    fn parse_turtle() {
+        let document = r#"
+            @prefix ex: <http://example.com/> .
+            PREFIX xsd: <http://www.w3.org/2001/XMLSchema#>
+            @base <http://example.com/base/> .
+
+            # the cow
+            ex:cow a ex:Animal ;
+                ex:says "moo", 'hello'@en ;
+                ex:legs 4 ;
+                ex:weight 550.5, 5.5e2 ;
+                ex:happy true ;
+                ex:born "2020"^^xsd:gYear ;
+                ex:friend _:duck, [ ex:says """a "long"
+            string""" ] ;
+                ex:likes ( <grass> ex:hay ) ;
+                .
+            _:duck ex:says "quack". ex:empty ex:list () .
+        "#;
+        let (triples, prefixes) = parse_triples(document).unwrap();
+        assert_eq!(2, prefixes.len());
+        assert_eq!(
+            Some("http://example.com/"),
+            prefixes.get("ex").map(|s| s.as_str())
+        );
+
+        let skolemizer = Skolemizer::new("test.ttl");
+        let cow = "http://example.com/cow";
+        let says = "http://example.com/says";
+        let mut expected = vec![
+            StringTriple::new_node(
+                cow,
+                &format!("{}type", RDF_PREFIX),
+                "http://example.com/Animal",
+            ),
+            StringTriple::new_value(cow, says, "moo"),
+            StringTriple::new_lang_string(cow, says, "hello", "en"),
+            StringTriple::new_literal(cow, "http://example.com/legs", 4),
+            StringTriple::new_literal(
+                cow,
+                "http://example.com/weight",
+                "550.5".parse::<Decimal>().unwrap(),
+            ),
+            StringTriple::new_literal(cow, "http://example.com/weight", 550.0),
+            StringTriple::new_literal(cow, "http://example.com/happy", true),
+            StringTriple::new_value(cow, "http://example.com/born", "2020"),
+            StringTriple::new_node(
+                cow,
+                "http://example.com/friend",
+                &skolemizer.skolemize("duck"),
+            ),
+            StringTriple::new_node(
+                cow,
+                "http://example.com/friend",
+                &skolemizer.skolemize("-1"),
+            ),
+            StringTriple::new_value(
+                &skolemizer.skolemize("-1"),
+                says,
+                "a \"long\"\n            string",
+            ),
+            StringTriple::new_node(cow, "http://example.com/likes", &skolemizer.skolemize("-3")),
+            StringTriple::new_node(
+                &skolemizer.skolemize("-3"),
+                &format!("{}first", RDF_PREFIX),
+                "http://example.com/base/grass",
+            ),
+            StringTriple::new_node(
+                &skolemizer.skolemize("-3"),
+                &format!("{}rest", RDF_PREFIX),
+                &skolemizer.skolemize("-2"),
+            ),
+            StringTriple::new_node(
+                &skolemizer.skolemize("-2"),
+                &format!("{}first", RDF_PREFIX),
+                "http://example.com/hay",
+            ),
+            StringTriple::new_node(
+                &skolemizer.skolemize("-2"),
+                &format!("{}rest", RDF_PREFIX),
+                &format!("{}nil", RDF_PREFIX),
+            ),
+            StringTriple::new_value(&skolemizer.skolemize("duck"), says, "quack"),
+            StringTriple::new_node(
+                "http://example.com/empty",
+                "http://example.com/list",
+                &format!("{}nil", RDF_PREFIX),
+            ),
+        ];
+        expected.sort();
+        assert_eq!(expected, triples);
+
+        let err =
+            parse_triples("@prefix ex: <http://example.com/> .\n\nex:a ex:b nope:c .").unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert_eq!(
+            "line 3: undefined prefix \"nope\"",
+            err.get_ref().unwrap().to_string()
+        );
+        assert!(parse_triples("<a> <b> \"unterminated\n\" .").is_err());
+        assert!(parse_triples("<a> <b> <c>").is_err());
+        assert!(parse_triples("<a> <b> ( <c> .").is_err());
+    }
+
+    #[test]
+    fn parse_quoted_triples() {
+        let document = r#"
+            @prefix : <http://example.com/> .
+            << :cow :weight 550 >> :source :scale ; :checkedBy << [] :is :farmer >> .
+            :duck :says "quack" {| :source :ear ; :heard 2 |} , "moo" .
+        "#;
+        let (triples, _) = parse_triples(document).unwrap();
+        let skolemizer = Skolemizer::new("test.ttl");
+        let ex = |name: &str| format!("http://example.com/{}", name);
+        let weight =
+            StringTriple::new_literal(&ex("cow"), &ex("weight"), Value::Integer(550)).quote();
+        let farmer =
+            StringTriple::new_node(&skolemizer.skolemize("-1"), &ex("is"), &ex("farmer")).quote();
+        let quack = StringTriple::new_value(&ex("duck"), &ex("says"), "quack");
+        let mut expected = vec![
+            StringTriple::new_node(&weight, &ex("source"), &ex("scale")),
+            StringTriple::new_node(&weight, &ex("checkedBy"), &farmer),
+            quack.clone(),
+            StringTriple::new_node(&quack.quote(), &ex("source"), &ex("ear")),
+            StringTriple::new_literal(&quack.quote(), &ex("heard"), Value::Integer(2)),
+            StringTriple::new_value(&ex("duck"), &ex("says"), "moo"),
+        ];
+        expected.sort();
+        assert_eq!(expected, triples);
+
+        assert!(parse_triples("<< <a> <b> ( <c> ) >> <d> <e> .").is_err());
+        assert!(parse_triples("<< <a> <b> <c> <d> <e> .").is_err());
+        assert!(parse_triples("<a> <b> <c> {| <d> <e> .").is_err());
+    }
+
+    #[tokio::test]
+    async fn import_turtle_into_graph() {
+        let store = open_memory_store();
+        let graph = store.create("animals").await.unwrap();
+        let mut prefixes = PrefixMap::new();
+        prefixes.insert("ex".to_string(), "http://old.example.com/".to_string());
+        prefixes.insert("rdf".to_string(), RDF_PREFIX.to_string());
+        graph.set_prefixes(prefixes).await.unwrap();
+
+        let document = "@prefix ex: <http://example.com/> .\nex:cow ex:says \"moo\" .";
+        let layer = graph
+            .import_turtle(document.as_bytes(), "animals.ttl")
+            .await
+            .unwrap();
+        assert_eq!(1, layer.triple_count());
+        let layer = graph
+            .import_turtle(
+                "<http://example.com/duck> <http://example.com/says> \"quack\" .".as_bytes(),
+                "ducks.ttl",
+            )
+            .await
+            .unwrap();
+        assert_eq!(2, layer.triple_count());
+        assert_eq!(
+            Some(layer.name()),
+            graph.head().await.unwrap().map(|l| l.name())
+        );
+
+        let prefixes = graph.prefixes().await.unwrap();
+        assert_eq!(2, prefixes.len());
+        assert_eq!("http://example.com/", prefixes["ex"]);
+    }
+}
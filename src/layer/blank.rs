@@ -0,0 +1,135 @@
+//! Blank node skolemization.
+//!
+//! Layers only store named nodes. Blank nodes are stored by replacing
+//! them with skolem IRIs, which are node strings starting with
+//! `SKOLEM_IRI_PREFIX`. The prefix is followed by a scope and the
+//! original label of the blank node:
+//!
+//! ```text
+//! https://terminusdb.com/.well-known/genid/<scope>/<label>
+//! ```
+//!
+//! The scope is derived from a string chosen by the importer, usually
+//! something identifying the imported document. Blank node labels are
+//! only meaningful within a single document, so importing two
+//! documents with different scopes keeps their blank nodes apart,
+//! while importing the same document twice with the same scope
+//! results in the same nodes. A `Skolemizer` with the same scope turns
+//! the skolem IRIs back into blank nodes on export.
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+
+use super::layer::*;
+
+/// The prefix of all node strings that represent blank nodes.
+pub const SKOLEM_IRI_PREFIX: &str = "https://terminusdb.com/.well-known/genid/";
+
+/// The prefix of blank node labels as they appear in RDF documents.
+pub const BLANK_NODE_PREFIX: &str = "_:";
+
+/// Returns true if the given node string is a skolem IRI, and therefore represents a blank node.
+pub fn is_skolem_iri(node: &str) -> bool {
+    node.starts_with(SKOLEM_IRI_PREFIX)
+}
+
+/// Converts between blank nodes and skolem IRIs for a single scope.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Skolemizer {
+    prefix: String,
+}
+
+impl Skolemizer {
+    /// Construct a skolemizer for the given scope.
+    ///
+    /// The same scope always results in the same skolem IRIs.
+    pub fn new(scope: &str) -> Self {
+        let digest = Sha256::digest(scope.as_bytes());
+        let mut prefix = SKOLEM_IRI_PREFIX.to_string();
+        for byte in &digest[..8] {
+            write!(prefix, "{:02x}", byte).unwrap();
+        }
+        prefix.push('/');
+
+        Self { prefix }
+    }
+
+    /// The skolem IRI for a blank node label, given without the `_:` prefix.
+    pub fn skolemize(&self, label: &str) -> String {
+        format!("{}{}", self.prefix, label)
+    }
+
+    /// Replace a node string with a skolem IRI if it is a blank node, meaning it starts with `_:`.
+    pub fn skolemize_node(&self, node: &str) -> String {
+        match node.strip_prefix(BLANK_NODE_PREFIX) {
+            Some(label) => self.skolemize(label),
+            None => node.to_string(),
+        }
+    }
+
+    /// Replace all blank nodes of a triple with skolem IRIs.
+    pub fn skolemize_triple(&self, triple: StringTriple) -> StringTriple {
+        StringTriple {
+            subject: self.skolemize_node(&triple.subject),
+            predicate: triple.predicate,
+            object: match triple.object {
+                ObjectType::Node(node) => ObjectType::Node(self.skolemize_node(&node)),
+                object => object,
+            },
+        }
+    }
+
+    /// The blank node, including the `_:` prefix, for a skolem IRI of this scope.
+    ///
+    /// Returns None for any other node, including skolem IRIs of
+    /// other scopes.
+    pub fn deskolemize_node(&self, node: &str) -> Option<String> {
+        node.strip_prefix(&self.prefix)
+            .map(|label| format!("{}{}", BLANK_NODE_PREFIX, label))
+    }
+
+    /// Replace all skolem IRIs of this scope in a triple with blank nodes.
+    pub fn deskolemize_triple(&self, triple: StringTriple) -> StringTriple {
+        let deskolemize = |node: String| self.deskolemize_node(&node).unwrap_or(node);
+        StringTriple {
+            subject: deskolemize(triple.subject),
+            predicate: triple.predicate,
+            object: match triple.object {
+                ObjectType::Node(node) => ObjectType::Node(deskolemize(node)),
+                object => object,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skolemization_round_trips() {
+        let skolemizer = Skolemizer::new("document.nt");
+        let triple = StringTriple::new_node("_:b0", "knows", "_:b1");
+        let skolemized = skolemizer.skolemize_triple(triple.clone());
+
+        assert!(is_skolem_iri(&skolemized.subject));
+        assert_eq!(
+            skolemized,
+            Skolemizer::new("document.nt").skolemize_triple(triple.clone())
+        );
+        assert_ne!(
+            skolemized,
+            Skolemizer::new("other.nt").skolemize_triple(triple.clone())
+        );
+        assert_eq!(triple, skolemizer.deskolemize_triple(skolemized.clone()));
+
+        // other scopes are kept as skolem IRIs
+        assert_eq!(
+            skolemized,
+            Skolemizer::new("other.nt").deskolemize_triple(skolemized.clone())
+        );
+
+        let named = StringTriple::new_value("cow", "says", "_:moo");
+        assert_eq!(named, skolemizer.skolemize_triple(named.clone()));
+        assert!(!is_skolem_iri("cow"));
+    }
+}
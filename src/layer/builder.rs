@@ -1,13 +1,143 @@
+use std::future::Future;
 use std::io;
+use std::sync::Arc;
 
 use futures::stream::TryStreamExt;
 use rayon::prelude::*;
+use tokio::sync::Semaphore;
 
 use super::layer::*;
+use super::spill::Spill;
 use crate::storage::*;
 use crate::structure::util;
 use crate::structure::*;
 
+/// How many parts of a layer may be built at the same time.
+///
+/// Once the triples of a layer are sorted, the dictionaries, the
+/// triple additions and removals and their indexes can be built
+/// largely independently of each other. A `Parallelism` with a level
+/// runs at most that many of these construction tasks at once, and
+/// does all sorting on a dedicated thread pool with that many
+/// threads. The default places no limit on construction tasks and
+/// sorts on the global thread pool.
+#[derive(Clone, Default)]
+pub struct Parallelism {
+    limit: Option<ParallelismLimit>,
+}
+
+#[derive(Clone)]
+struct ParallelismLimit {
+    level: usize,
+    permits: Arc<Semaphore>,
+    pool: Arc<rayon::ThreadPool>,
+}
+
+impl Parallelism {
+    /// Build at most `level` parts of a layer at the same time.
+    ///
+    /// A level of 0 is treated as 1.
+    pub fn new(level: usize) -> io::Result<Self> {
+        let level = level.max(1);
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(level)
+            .thread_name(|i| format!("layer-builder-{}", i))
+            .build()
+            .map_err(io::Error::other)?;
+
+        Ok(Self {
+            limit: Some(ParallelismLimit {
+                level,
+                permits: Arc::new(Semaphore::new(level)),
+                pool: Arc::new(pool),
+            }),
+        })
+    }
+
+    /// The maximum amount of parts built at the same time, or None if there is no limit.
+    pub fn level(&self) -> Option<usize> {
+        self.limit.as_ref().map(|limit| limit.level)
+    }
+
+    /// Run a cpu-bound operation, using the thread pool for any parallel iterators within it.
+    pub fn install<R: Send, OP: FnOnce() -> R + Send>(&self, op: OP) -> R {
+        match &self.limit {
+            Some(limit) => limit.pool.install(op),
+            None => op(),
+        }
+    }
+
+    /// Run a construction task on the runtime, once the level allows it.
+    ///
+    /// The task itself should not spawn further tasks through this
+    /// `Parallelism`, as it would hold on to its own slot while
+    /// waiting for another one.
+    pub async fn spawn<T: 'static + Send, Fut: 'static + Future<Output = io::Result<T>> + Send>(
+        &self,
+        task: Fut,
+    ) -> io::Result<T> {
+        let permit = match &self.limit {
+            Some(limit) => Some(
+                limit
+                    .permits
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("semaphore should never be closed"),
+            ),
+            None => None,
+        };
+
+        crate::runtime::spawn(async move {
+            let result = task.await;
+            std::mem::drop(permit);
+            result
+        })
+        .await?
+    }
+}
+
+/// Progress reported while a layer is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildProgress {
+    /// The changes have been sorted and resolved. Additions that
+    /// already exist in the parent and removals of triples that don't
+    /// exist in it are still included in these counts.
+    Sorted { additions: u64, removals: u64 },
+    /// The dictionaries have been written with this many new entries.
+    DictionariesWritten {
+        nodes: u64,
+        predicates: u64,
+        values: u64,
+    },
+    /// The triple additions and removals have been written.
+    TriplesWritten,
+    /// The object indexes and predicate wavelet trees have been built.
+    IndexesBuilt,
+    /// The layer is complete.
+    Done,
+}
+
+/// Reports the progress of writing a layer to a function, if there is one.
+#[derive(Clone, Default)]
+pub struct BuildProgressReporter(Option<Arc<dyn Fn(BuildProgress) + Send + Sync>>);
+
+impl BuildProgressReporter {
+    /// Report progress to the given function.
+    ///
+    /// The function is called from the task that is writing the
+    /// layer, so it should return quickly.
+    pub fn new<P: 'static + Fn(BuildProgress) + Send + Sync>(progress: P) -> Self {
+        Self(Some(Arc::new(progress)))
+    }
+
+    pub fn report(&self, progress: BuildProgress) {
+        if let Some(report) = &self.0 {
+            report(progress);
+        }
+    }
+}
+
 pub struct DictionarySetFileBuilder<F: 'static + FileStore> {
     node_dictionary_builder: PfcDictFileBuilder<F::Write>,
     predicate_dictionary_builder: PfcDictFileBuilder<F::Write>,
@@ -124,6 +254,38 @@ impl<F: 'static + FileLoad + FileStore> DictionarySetFileBuilder<F> {
         Ok(ids)
     }
 
+    /// Add all nodes, predicates and values and write the dictionaries.
+    ///
+    /// The three dictionaries are built concurrently. The same
+    /// ordering requirements as for the `add_<thing>` methods apply.
+    pub async fn build_all(
+        self,
+        nodes: Vec<String>,
+        predicates: Vec<String>,
+        values: Vec<String>,
+        parallelism: &Parallelism,
+    ) -> io::Result<()> {
+        async fn build<W: 'static + SyncableFile + Send>(
+            mut builder: PfcDictFileBuilder<W>,
+            entries: Vec<String>,
+        ) -> io::Result<()> {
+            for entry in entries {
+                builder.add(&entry).await?;
+            }
+
+            builder.finalize().await
+        }
+
+        let nodes_task = parallelism.spawn(build(self.node_dictionary_builder, nodes));
+        let predicates_task =
+            parallelism.spawn(build(self.predicate_dictionary_builder, predicates));
+        let values_task = parallelism.spawn(build(self.value_dictionary_builder, values));
+
+        futures::try_join!(nodes_task, predicates_task, values_task)?;
+
+        Ok(())
+    }
+
     pub async fn finalize(self) -> io::Result<()> {
         self.node_dictionary_builder.finalize().await?;
         self.predicate_dictionary_builder.finalize().await?;
@@ -293,19 +455,45 @@ pub async fn build_object_index<FLoad: 'static + FileLoad, F: 'static + FileLoad
     sp_o_files: AdjacencyListFiles<FLoad>,
     o_ps_files: AdjacencyListFiles<F>,
     objects_file: Option<F>,
+) -> io::Result<()> {
+    build_object_index_with_spill(sp_o_files, o_ps_files, objects_file, None).await
+}
+
+/// Build the object index, sorting the object-subject/predicate
+/// pairs in scratch files if a spill configuration is given.
+pub(crate) async fn build_object_index_with_spill<
+    FLoad: 'static + FileLoad,
+    F: 'static + FileLoad + FileStore,
+>(
+    sp_o_files: AdjacencyListFiles<FLoad>,
+    o_ps_files: AdjacencyListFiles<F>,
+    objects_file: Option<F>,
+    spill_config: Option<SpillConfig>,
 ) -> io::Result<()> {
     let build_sparse_index = objects_file.is_some();
     let mut aj_stream =
         adjacency_list_stream_pairs(sp_o_files.bitindex_files.bits_file, sp_o_files.nums_file)
             .await?;
+    let mut spill = spill_config.map(Spill::new);
     let mut pairs = Vec::new();
     let mut greatest_sp = 0;
     // gather up pairs
     while let Some((sp, object)) = aj_stream.try_next().await? {
         greatest_sp = sp;
         pairs.push((object, sp));
+        if let Some(spill) = spill.as_mut() {
+            if spill.should_spill(pairs.len()) {
+                spill.spill(std::mem::take(&mut pairs))?;
+            }
+        }
     }
-    pairs.par_sort_unstable();
+    let pairs: Box<dyn Iterator<Item = io::Result<(u64, u64)>> + Send> = match spill {
+        Some(spill) => Box::new(spill.merge_iter(pairs)?),
+        None => {
+            pairs.par_sort_unstable();
+            Box::new(pairs.into_iter().map(Ok))
+        }
+    };
 
     let aj_width = util::calculate_width(greatest_sp);
     let mut o_ps_adjacency_list_builder = AdjacencyListBuilder::new(
@@ -324,7 +512,8 @@ pub async fn build_object_index<FLoad: 'static + FileLoad, F: 'static + FileLoad
         let mut objects = Vec::new();
         let mut last_object = 0;
         let mut object_ix = 0;
-        for (object, sp) in pairs {
+        for pair in pairs {
+            let (object, sp) = pair?;
             if object > last_object {
                 object_ix += 1;
                 last_object = object;
@@ -343,9 +532,10 @@ pub async fn build_object_index<FLoad: 'static + FileLoad, F: 'static + FileLoad
         objects_builder.push_vec(objects).await?;
         objects_builder.finalize().await?;
     } else {
-        o_ps_adjacency_list_builder
-            .push_all(util::stream_iter_ok(pairs))
-            .await?;
+        for pair in pairs {
+            let (object, sp) = pair?;
+            o_ps_adjacency_list_builder.push(object, sp).await?;
+        }
     }
 
     o_ps_adjacency_list_builder.finalize().await
@@ -373,16 +563,67 @@ pub async fn build_indexes<FLoad: 'static + FileLoad, F: 'static + FileLoad + Fi
     objects_file: Option<F>,
     wavelet_files: BitIndexFiles<F>,
 ) -> io::Result<()> {
-    let object_index_task = tokio::spawn(build_object_index(sp_o_files, o_ps_files, objects_file));
-    let predicate_index_task = tokio::spawn(build_predicate_index(
+    build_indexes_with_parallelism(
+        s_p_files,
+        sp_o_files,
+        o_ps_files,
+        objects_file,
+        wavelet_files,
+        &Parallelism::default(),
+    )
+    .await
+}
+
+/// Build the object and predicate indexes concurrently, as allowed by the given parallelism.
+pub async fn build_indexes_with_parallelism<
+    FLoad: 'static + FileLoad,
+    F: 'static + FileLoad + FileStore,
+>(
+    s_p_files: AdjacencyListFiles<FLoad>,
+    sp_o_files: AdjacencyListFiles<FLoad>,
+    o_ps_files: AdjacencyListFiles<F>,
+    objects_file: Option<F>,
+    wavelet_files: BitIndexFiles<F>,
+    parallelism: &Parallelism,
+) -> io::Result<()> {
+    build_indexes_with_spill(
+        s_p_files,
+        sp_o_files,
+        o_ps_files,
+        objects_file,
+        wavelet_files,
+        parallelism,
+        None,
+    )
+    .await
+}
+
+pub(crate) async fn build_indexes_with_spill<
+    FLoad: 'static + FileLoad,
+    F: 'static + FileLoad + FileStore,
+>(
+    s_p_files: AdjacencyListFiles<FLoad>,
+    sp_o_files: AdjacencyListFiles<FLoad>,
+    o_ps_files: AdjacencyListFiles<F>,
+    objects_file: Option<F>,
+    wavelet_files: BitIndexFiles<F>,
+    parallelism: &Parallelism,
+    spill_config: Option<SpillConfig>,
+) -> io::Result<()> {
+    let object_index_task = parallelism.spawn(build_object_index_with_spill(
+        sp_o_files,
+        o_ps_files,
+        objects_file,
+        spill_config,
+    ));
+    let predicate_index_task = parallelism.spawn(build_predicate_index(
         s_p_files.nums_file,
         wavelet_files.bits_file,
         wavelet_files.blocks_file,
         wavelet_files.sblocks_file,
     ));
 
-    object_index_task.await??;
-    predicate_index_task.await??;
+    futures::try_join!(object_index_task, predicate_index_task)?;
 
     Ok(())
 }
@@ -0,0 +1,358 @@
+//! Bulk loading of base layers that are larger than memory.
+//!
+//! A bulk load reads a stream of string triples once, writing sorted
+//! runs of triples and of node, predicate and value strings to
+//! scratch files. The string runs are merged straight into the
+//! dictionaries. Since ids are handed out in dictionary order, and
+//! nodes are numbered before values, merging the triple runs results
+//! in triples that are already sorted by id, which are then written
+//! without any further sorting. The object index is built from
+//! sorted runs as well.
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+use futures::stream::{Stream, StreamExt};
+
+use super::internal::*;
+use super::spill::*;
+use crate::layer::{ObjectType, StringTriple};
+use crate::storage::*;
+use crate::structure::PfcDict;
+
+/// Progress reported during a bulk load.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BulkLoadProgress {
+    /// This many triples have been read and sorted so far.
+    Sorted(u64),
+    /// The dictionaries have been written with this many entries.
+    DictionariesWritten {
+        nodes: u64,
+        predicates: u64,
+        values: u64,
+    },
+    /// This many unique triples have been written so far.
+    TriplesWritten(u64),
+    /// The indexes have been built, and the layer is complete.
+    Done,
+}
+
+/// Configuration for a bulk load.
+#[derive(Clone)]
+pub struct BulkLoadConfig {
+    spill: SpillConfig,
+    progress: Option<Arc<dyn Fn(BulkLoadProgress) + Send + Sync>>,
+}
+
+impl BulkLoadConfig {
+    /// Sort in runs of at most `spill.threshold` items, which are
+    /// written to scratch files as configured.
+    ///
+    /// Progress is reported once per run.
+    pub fn new(spill: SpillConfig) -> Self {
+        Self {
+            spill,
+            progress: None,
+        }
+    }
+
+    /// Report progress to the given function.
+    pub fn with_progress<P: 'static + Fn(BulkLoadProgress) + Send + Sync>(
+        mut self,
+        progress: P,
+    ) -> Self {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+
+    fn report(&self, progress: BulkLoadProgress) {
+        if let Some(report) = &self.progress {
+            report(progress);
+        }
+    }
+}
+
+/// A string triple with its object as it is stored in the dictionaries.
+///
+/// This sorts in the same order as the ids the triple will end up with.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct StoredTriple {
+    subject: String,
+    predicate: String,
+    object_is_value: bool,
+    object: String,
+}
+
+impl From<StringTriple> for StoredTriple {
+    fn from(triple: StringTriple) -> Self {
+        let (object_is_value, object) = match triple.object {
+            ObjectType::Node(node) => (false, node),
+            object => (true, object.value_string().unwrap().into_owned()),
+        };
+
+        Self {
+            subject: triple.subject,
+            predicate: triple.predicate,
+            object_is_value,
+            object,
+        }
+    }
+}
+
+impl Spillable for StoredTriple {
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&[self.object_is_value as u8])?;
+        write_string(writer, &self.subject)?;
+        write_string(writer, &self.predicate)?;
+        write_string(writer, &self.object)
+    }
+
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<Option<Self>> {
+        let mut tag = [0];
+        match reader.read_exact(&mut tag) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        Ok(Some(Self {
+            object_is_value: tag[0] != 0,
+            subject: read_string(reader)?,
+            predicate: read_string(reader)?,
+            object: read_string(reader)?,
+        }))
+    }
+}
+
+/// A spill together with the items that have not been spilled yet.
+struct Runs<T> {
+    spill: Spill<T>,
+    pending: Vec<T>,
+}
+
+impl<T: 'static + Spillable + Send> Runs<T> {
+    fn new(config: &SpillConfig) -> Self {
+        Self {
+            spill: Spill::new(config.clone()),
+            pending: Vec::new(),
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.spill.should_spill(self.pending.len())
+    }
+
+    async fn spill(self) -> io::Result<Self> {
+        let Runs { mut spill, pending } = self;
+        crate::runtime::spawn_blocking(move || {
+            spill.spill(pending)?;
+
+            Ok(Runs {
+                spill,
+                pending: Vec::new(),
+            })
+        })
+        .await?
+    }
+
+    async fn spill_if_full(self) -> io::Result<Self> {
+        if self.is_full() {
+            self.spill().await
+        } else {
+            Ok(self)
+        }
+    }
+
+    fn merge(self) -> io::Result<MergedRuns<T>> {
+        self.spill.merge_iter(self.pending)
+    }
+}
+
+fn lookup(dict: &PfcDict, s: &str, offset: u64) -> io::Result<u64> {
+    match dict.id(s) {
+        Some(id) => Ok(id + 1 + offset),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("string {:?} missing from dictionary during bulk load", s),
+        )),
+    }
+}
+
+/// Build a base layer from a stream of triples that may be larger than memory.
+///
+/// Duplicate triples are allowed. The stream is consumed entirely
+/// before any layer files are written, so a failing stream leaves
+/// the files untouched.
+pub async fn bulk_build_base_layer<
+    F: 'static + FileLoad + FileStore + Clone,
+    S: Stream<Item = io::Result<StringTriple>> + Send,
+>(
+    files: &BaseLayerFiles<F>,
+    triples: S,
+    config: &BulkLoadConfig,
+) -> io::Result<()> {
+    let mut triples = Box::pin(triples);
+    let mut triple_runs: Runs<StoredTriple> = Runs::new(&config.spill);
+    let mut node_runs: Runs<String> = Runs::new(&config.spill);
+    let mut predicate_runs: Runs<String> = Runs::new(&config.spill);
+    let mut value_runs: Runs<String> = Runs::new(&config.spill);
+
+    let mut sorted = 0;
+    while let Some(triple) = triples.next().await {
+        let triple: StoredTriple = triple?.into();
+        node_runs.pending.push(triple.subject.clone());
+        predicate_runs.pending.push(triple.predicate.clone());
+        if triple.object_is_value {
+            value_runs.pending.push(triple.object.clone());
+        } else {
+            node_runs.pending.push(triple.object.clone());
+        }
+        triple_runs.pending.push(triple);
+
+        if triple_runs.is_full() {
+            sorted += triple_runs.pending.len() as u64;
+            triple_runs = triple_runs.spill().await?;
+            config.report(BulkLoadProgress::Sorted(sorted));
+        }
+        node_runs = node_runs.spill_if_full().await?;
+        predicate_runs = predicate_runs.spill_if_full().await?;
+        value_runs = value_runs.spill_if_full().await?;
+    }
+    sorted += triple_runs.pending.len() as u64;
+    config.report(BulkLoadProgress::Sorted(sorted));
+
+    let mut builder = BaseLayerFileBuilder::from_files(files).await?;
+    let (mut nodes, mut predicates, mut values) = (0, 0, 0);
+    for node in node_runs.merge()? {
+        nodes = builder.add_node(&node?).await?;
+    }
+    for predicate in predicate_runs.merge()? {
+        predicates = builder.add_predicate(&predicate?).await?;
+    }
+    for value in value_runs.merge()? {
+        values = builder.add_value(&value?).await?;
+    }
+    let mut builder = builder
+        .into_phase2()
+        .await?
+        .with_index_spill(config.spill.clone());
+    config.report(BulkLoadProgress::DictionariesWritten {
+        nodes,
+        predicates,
+        values,
+    });
+
+    let node_dict = PfcDict::parse(
+        files.node_dictionary_files.blocks_file.map().await?,
+        files.node_dictionary_files.offsets_file.map().await?,
+    )?;
+    let predicate_dict = PfcDict::parse(
+        files.predicate_dictionary_files.blocks_file.map().await?,
+        files.predicate_dictionary_files.offsets_file.map().await?,
+    )?;
+    let value_dict = PfcDict::parse(
+        files.value_dictionary_files.blocks_file.map().await?,
+        files.value_dictionary_files.offsets_file.map().await?,
+    )?;
+
+    let mut written = 0;
+    for triple in triple_runs.merge()? {
+        let triple = triple?;
+        let subject = lookup(&node_dict, &triple.subject, 0)?;
+        let predicate = lookup(&predicate_dict, &triple.predicate, 0)?;
+        let object = if triple.object_is_value {
+            lookup(&value_dict, &triple.object, nodes)?
+        } else {
+            lookup(&node_dict, &triple.object, 0)?
+        };
+        builder.add_triple(subject, predicate, object).await?;
+
+        written += 1;
+        if written % config.spill.threshold.max(1) as u64 == 0 {
+            config.report(BulkLoadProgress::TriplesWritten(written));
+        }
+    }
+    config.report(BulkLoadProgress::TriplesWritten(written));
+
+    builder.finalize().await?;
+    config.report(BulkLoadProgress::Done);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::internal::base::tests::base_layer_files;
+    use crate::layer::{Layer, LayerBuilder, SimpleLayerBuilder};
+    use std::sync::Mutex;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn bulk_load_matches_builder() {
+        let mut triples = Vec::new();
+        for i in 0..100 {
+            let subject = format!("node{}", i % 37);
+            triples.push(StringTriple::new_node(
+                &subject,
+                &format!("p{}", i % 3),
+                &format!("node{}", (i * 7) % 41),
+            ));
+            triples.push(StringTriple::new_value(
+                &subject,
+                "label",
+                &format!("value {}", i % 13),
+            ));
+            triples.push(StringTriple::new_lang_string(
+                &subject, "name", "naam", "nl",
+            ));
+        }
+
+        let dir = tempdir().unwrap();
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let progress2 = progress.clone();
+        let config = BulkLoadConfig::new(SpillConfig::new(16).in_directory(dir.path()))
+            .with_progress(move |p| progress2.lock().unwrap().push(p));
+        let files = base_layer_files();
+        bulk_build_base_layer(
+            &files,
+            futures::stream::iter(triples.clone().into_iter().map(Ok)),
+            &config,
+        )
+        .await
+        .unwrap();
+        let bulk = BaseLayer::load_from_files([1, 2, 3, 4, 5], &files)
+            .await
+            .unwrap();
+
+        let files = base_layer_files();
+        let mut builder = SimpleLayerBuilder::new([1, 2, 3, 4, 5], files.clone());
+        for triple in triples {
+            builder.add_string_triple(triple);
+        }
+        builder.commit().await.unwrap();
+        let built = BaseLayer::load_from_files([1, 2, 3, 4, 5], &files)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            built.triples().collect::<Vec<_>>(),
+            bulk.triples().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            built.triples_o(7).collect::<Vec<_>>(),
+            bulk.triples_o(7).collect::<Vec<_>>()
+        );
+        assert_eq!(built.node_and_value_count(), bulk.node_and_value_count());
+        assert!(bulk.string_triple_exists(&StringTriple::new_lang_string(
+            "node3", "name", "naam", "nl"
+        )));
+
+        let progress = progress.lock().unwrap();
+        assert_eq!(Some(&BulkLoadProgress::Done), progress.last());
+        assert!(progress.contains(&BulkLoadProgress::Sorted(300)));
+        assert!(progress.contains(&BulkLoadProgress::TriplesWritten(237)));
+
+        // all scratch files are gone
+        assert_eq!(0, std::fs::read_dir(dir.path()).unwrap().count());
+    }
+}
@@ -0,0 +1,134 @@
+//! Statistics about which predicates are used together.
+//!
+//! The predicates of a subject say a lot about what kind of thing the
+//! subject is, which is what schema inference is after. Counting, for
+//! every pair of predicates, how many subjects use both gives a
+//! co-occurrence matrix. It is computed in a single pass over the
+//! triples in SPO order, so only the predicates of the current
+//! subject are kept besides the matrix. The matrix only stores pairs
+//! that occur, so its size depends on the amount of predicates, not
+//! on the amount of subjects or triples.
+use std::collections::BTreeMap;
+
+use super::layer::IdTriple;
+
+/// For every pair of predicates, the amount of subjects using both, as returned by `Layer::predicate_cooccurrence`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PredicateCooccurrence {
+    subject_count: usize,
+    counts: BTreeMap<(u64, u64), usize>,
+}
+
+impl PredicateCooccurrence {
+    /// Count co-occurrences in triples given in SPO order.
+    ///
+    /// Triples only need to be grouped by subject, with the triples of
+    /// a subject ordered by predicate, as returned by `Layer::triples`.
+    pub fn from_triples(triples: impl Iterator<Item = IdTriple>) -> Self {
+        let mut cooccurrence = Self::default();
+        let mut subject = None;
+        let mut predicates = Vec::new();
+        for triple in triples {
+            if subject != Some(triple.subject) {
+                cooccurrence.add_subject(&predicates);
+                predicates.clear();
+                subject = Some(triple.subject);
+            }
+            if predicates.last() != Some(&triple.predicate) {
+                predicates.push(triple.predicate);
+            }
+        }
+        cooccurrence.add_subject(&predicates);
+
+        cooccurrence
+    }
+
+    fn add_subject(&mut self, predicates: &[u64]) {
+        if predicates.is_empty() {
+            return;
+        }
+        self.subject_count += 1;
+        for (i, &p1) in predicates.iter().enumerate() {
+            for &p2 in &predicates[i..] {
+                *self.counts.entry((p1, p2)).or_default() += 1;
+            }
+        }
+    }
+
+    /// The amount of subjects with at least one triple.
+    pub fn subject_count(&self) -> usize {
+        self.subject_count
+    }
+
+    /// The amount of subjects using both predicates.
+    ///
+    /// This is symmetric. For the same predicate twice, this is the
+    /// amount of subjects using that predicate.
+    pub fn count(&self, predicate1: u64, predicate2: u64) -> usize {
+        let key = (predicate1.min(predicate2), predicate1.max(predicate2));
+        self.counts.get(&key).copied().unwrap_or(0)
+    }
+
+    /// Iterator over all pairs of predicates used by at least one subject, with their count.
+    ///
+    /// Every pair is returned once, with the smallest predicate id
+    /// first, ordered by the ids. This includes pairs of the same
+    /// predicate twice.
+    pub fn pairs(&self) -> impl Iterator<Item = (u64, u64, usize)> + '_ {
+        self.counts
+            .iter()
+            .map(|(&(p1, p2), &count)| (p1, p2, count))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layer::*;
+    use crate::store::open_memory_store;
+
+    #[tokio::test]
+    async fn count_predicate_cooccurrence() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        for (subject, predicate, object) in [
+            ("cow", "name", "Daisy"),
+            ("cow", "says", "moo"),
+            ("cow", "says", "hello"),
+            ("duck", "name", "Donald"),
+            ("duck", "says", "quack"),
+            ("duck", "colour", "white"),
+            ("rock", "colour", "grey"),
+        ] {
+            builder
+                .add_string_triple(StringTriple::new_value(subject, predicate, object))
+                .unwrap();
+        }
+        builder
+            .add_string_triple(StringTriple::new_node("cow", "friend", "duck"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+        let builder = base.open_write().await.unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("rock", "colour", "grey"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pebble", "colour", "grey"))
+            .unwrap();
+        let layer = builder.commit().await.unwrap();
+
+        let p = |name: &str| layer.predicate_id(name).unwrap();
+        let cooccurrence = layer.predicate_cooccurrence();
+        assert_eq!(3, cooccurrence.subject_count());
+        assert_eq!(2, cooccurrence.count(p("name"), p("says")));
+        assert_eq!(2, cooccurrence.count(p("says"), p("name")));
+        assert_eq!(2, cooccurrence.count(p("says"), p("says")));
+        assert_eq!(2, cooccurrence.count(p("colour"), p("colour")));
+        assert_eq!(1, cooccurrence.count(p("colour"), p("says")));
+        assert_eq!(1, cooccurrence.count(p("friend"), p("name")));
+        assert_eq!(0, cooccurrence.count(p("friend"), p("colour")));
+        assert_eq!(9, cooccurrence.pairs().count());
+        assert!(cooccurrence
+            .pairs()
+            .all(|(p1, p2, count)| p1 <= p2 && count == cooccurrence.count(p1, p2)));
+    }
+}
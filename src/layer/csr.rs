@@ -0,0 +1,145 @@
+//! Export of the graph structure as compressed sparse rows.
+//!
+//! Graph libraries and GPU analytics generally take a graph as a
+//! compressed sparse row (CSR) matrix: the edges of every vertex are
+//! stored next to each other in one array, and a second array holds
+//! the offset at which the edges of each vertex start. Such a graph
+//! is built directly from the ids of a layer, without looking up any
+//! strings.
+use std::collections::HashSet;
+
+use super::layer::{IdTriple, Layer};
+
+/// The triples of a layer as a compressed sparse row adjacency matrix, as returned by `Layer::csr_graph`.
+///
+/// Vertices are numbered from 0, with vertex `i` standing for the
+/// node or value with id `i + 1`, so there is a vertex for every node
+/// and value known to the layer. Every triple is an edge from its
+/// subject to its object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsrGraph {
+    /// For every vertex, the position of its first edge in `indices`, followed by the total amount of edges.
+    ///
+    /// The edges of vertex `i` are at positions `indptr[i]` up to
+    /// `indptr[i + 1]`.
+    pub indptr: Vec<usize>,
+    /// The vertex every edge points to, ordered by vertex within the edges of each vertex.
+    pub indices: Vec<u64>,
+    /// The predicate id of every edge, in the same order as `indices`.
+    ///
+    /// Triples with different predicates between the same subject
+    /// and object are separate edges, ordered by predicate.
+    pub predicates: Vec<u64>,
+}
+
+impl CsrGraph {
+    /// Build the graph of all triples in the layer with one of the given predicates, or all triples if there are none given.
+    pub fn from_layer<L: Layer + ?Sized>(layer: &L, predicates: Option<&[u64]>) -> Self {
+        let vertex_count = layer.node_and_value_count();
+        let triples: Box<dyn Iterator<Item = IdTriple> + '_> = match predicates {
+            None => layer.triples(),
+            Some(predicates) => {
+                let predicates: HashSet<_> = predicates.iter().copied().collect();
+                Box::new(
+                    predicates
+                        .into_iter()
+                        .flat_map(|predicate| layer.triples_p(predicate)),
+                )
+            }
+        };
+        let edges: Vec<_> = triples.collect();
+
+        let mut indptr = vec![0; vertex_count + 1];
+        for edge in edges.iter() {
+            indptr[edge.subject as usize] += 1;
+        }
+        for vertex in 0..vertex_count {
+            indptr[vertex + 1] += indptr[vertex];
+        }
+
+        let mut rows: Vec<(u64, u64)> = vec![(0, 0); edges.len()];
+        let mut next = indptr.clone();
+        for edge in edges {
+            let position = &mut next[edge.subject as usize - 1];
+            rows[*position] = (edge.object - 1, edge.predicate);
+            *position += 1;
+        }
+        for vertex in 0..vertex_count {
+            rows[indptr[vertex]..indptr[vertex + 1]].sort_unstable();
+        }
+        let (indices, predicates) = rows.into_iter().unzip();
+
+        Self {
+            indptr,
+            indices,
+            predicates,
+        }
+    }
+
+    /// The amount of vertices in the graph.
+    pub fn vertex_count(&self) -> usize {
+        self.indptr.len() - 1
+    }
+
+    /// The amount of edges in the graph.
+    pub fn edge_count(&self) -> usize {
+        self.indices.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layer::*;
+    use crate::store::open_memory_store;
+
+    #[tokio::test]
+    async fn export_csr_graph() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        for (subject, predicate, object) in [
+            ("a", "road", "c"),
+            ("a", "road", "b"),
+            ("a", "ferry", "b"),
+            ("c", "road", "a"),
+        ] {
+            builder
+                .add_string_triple(StringTriple::new_node(subject, predicate, object))
+                .unwrap();
+        }
+        builder
+            .add_string_triple(StringTriple::new_value("b", "name", "Bee"))
+            .unwrap();
+        let layer = builder.commit().await.unwrap();
+
+        let vertex = |name: &str| layer.object_node_id(name).unwrap() - 1;
+        let p = |name: &str| layer.predicate_id(name).unwrap();
+        let (a, b, c) = (vertex("a"), vertex("b"), vertex("c"));
+        let bee = layer.object_value_id("Bee").unwrap() - 1;
+
+        let graph = layer.csr_graph(None);
+        assert_eq!(4, graph.vertex_count());
+        assert_eq!(5, graph.edge_count());
+        let edges = |graph: &CsrGraph, vertex: u64| {
+            let range = graph.indptr[vertex as usize]..graph.indptr[vertex as usize + 1];
+            graph.indices[range.clone()]
+                .iter()
+                .copied()
+                .zip(graph.predicates[range].iter().copied())
+                .collect::<Vec<_>>()
+        };
+        let mut a_edges = vec![(b, p("road")), (b, p("ferry")), (c, p("road"))];
+        a_edges.sort();
+        assert_eq!(a_edges, edges(&graph, a));
+        assert_eq!(vec![(bee, p("name"))], edges(&graph, b));
+        assert_eq!(vec![(a, p("road"))], edges(&graph, c));
+        assert!(edges(&graph, bee).is_empty());
+
+        let roads = layer.csr_graph(Some(&[p("road")]));
+        assert_eq!(4, roads.vertex_count());
+        assert_eq!(3, roads.edge_count());
+        let mut a_edges = vec![(b, p("road")), (c, p("road"))];
+        a_edges.sort();
+        assert_eq!(a_edges, edges(&roads, a));
+        assert!(edges(&roads, b).is_empty());
+    }
+}
@@ -0,0 +1,176 @@
+//! Differences between arbitrary layers.
+//!
+//! Layers that descend from a common ancestor agree on the ids of
+//! all dictionary entries in that ancestor. Triples that only use
+//! those shared ids are compared by merging the sorted triple
+//! iterators of both layers. Only triples that involve dictionary
+//! entries added after the layers diverged need to be converted to
+//! strings and looked up in the other layer.
+use std::iter::Peekable;
+use std::sync::Arc;
+
+use super::layer::*;
+
+/// The difference between two layers.
+#[derive(Clone)]
+pub struct LayerDiff {
+    from: Arc<dyn Layer>,
+    to: Arc<dyn Layer>,
+    shared: SharedIds,
+}
+
+#[derive(Clone, Copy)]
+struct SharedIds {
+    node_value_count: u64,
+    predicate_count: u64,
+}
+
+impl SharedIds {
+    fn contains(&self, triple: &IdTriple) -> bool {
+        triple.subject <= self.node_value_count
+            && triple.predicate <= self.predicate_count
+            && triple.object <= self.node_value_count
+    }
+}
+
+impl LayerDiff {
+    /// Prepare the difference between two layers.
+    ///
+    /// `common_ancestor` should be a layer that both `from` and `to`
+    /// descend from (or are), if there is one. The closer it is to
+    /// both layers, the fewer triples need to be compared as
+    /// strings.
+    pub fn new(
+        from: Arc<dyn Layer>,
+        to: Arc<dyn Layer>,
+        common_ancestor: Option<&dyn Layer>,
+    ) -> Self {
+        let shared = match common_ancestor {
+            None => SharedIds {
+                node_value_count: 0,
+                predicate_count: 0,
+            },
+            Some(ancestor) => SharedIds {
+                node_value_count: ancestor.node_and_value_count() as u64,
+                predicate_count: ancestor.predicate_count() as u64,
+            },
+        };
+
+        Self { from, to, shared }
+    }
+
+    /// Returns the layer this diff starts from.
+    pub fn from(&self) -> &Arc<dyn Layer> {
+        &self.from
+    }
+
+    /// Returns the layer this diff leads to.
+    pub fn to(&self) -> &Arc<dyn Layer> {
+        &self.to
+    }
+
+    /// Returns an iterator over all triples that are in `to` but not in `from`.
+    ///
+    /// The triples are expressed in ids of `to`.
+    pub fn additions(&self) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        Box::new(OneSidedDiff::new(
+            self.to.clone(),
+            self.from.clone(),
+            self.shared,
+        ))
+    }
+
+    /// Returns an iterator over all triples that are in `from` but not in `to`.
+    ///
+    /// The triples are expressed in ids of `from`.
+    pub fn removals(&self) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        Box::new(OneSidedDiff::new(
+            self.from.clone(),
+            self.to.clone(),
+            self.shared,
+        ))
+    }
+
+    /// Returns an iterator over all triples that are in `to` but not in `from`, as strings.
+    pub fn string_additions(&self) -> impl Iterator<Item = StringTriple> + Send {
+        let to = self.to.clone();
+        self.additions().map(move |t| {
+            to.id_triple_to_string(&t)
+                .expect("added triple should exist")
+        })
+    }
+
+    /// Returns an iterator over all triples that are in `from` but not in `to`, as strings.
+    pub fn string_removals(&self) -> impl Iterator<Item = StringTriple> + Send {
+        let from = self.from.clone();
+        self.removals().map(move |t| {
+            from.id_triple_to_string(&t)
+                .expect("removed triple should exist")
+        })
+    }
+}
+
+/// An iterator over the triples in one layer that are not in another.
+struct OneSidedDiff {
+    this: Box<dyn Iterator<Item = IdTriple> + Send>,
+    this_layer: Arc<dyn Layer>,
+    other: Peekable<Box<dyn Iterator<Item = IdTriple> + Send>>,
+    other_layer: Arc<dyn Layer>,
+    shared: SharedIds,
+}
+
+impl OneSidedDiff {
+    fn new(this_layer: Arc<dyn Layer>, other_layer: Arc<dyn Layer>, shared: SharedIds) -> Self {
+        let other: Box<dyn Iterator<Item = IdTriple> + Send> =
+            Box::new(other_layer.triples().filter(move |t| shared.contains(t)));
+        Self {
+            this: this_layer.triples(),
+            this_layer,
+            other: other.peekable(),
+            other_layer,
+            shared,
+        }
+    }
+
+    fn other_contains_shared(&mut self, triple: &IdTriple) -> bool {
+        // both iterators are sorted, so we only ever need to move forward
+        while let Some(other) = self.other.peek() {
+            if other < triple {
+                self.other.next();
+            } else {
+                return other == triple;
+            }
+        }
+
+        false
+    }
+
+    fn other_contains_unshared(&self, triple: &IdTriple) -> bool {
+        // triples using unshared ids in this layer may only match
+        // triples using unshared ids in the other layer, which use
+        // different ids for the same strings.
+        match self.this_layer.id_triple_to_string(triple) {
+            Some(triple) => self.other_layer.string_triple_exists(&triple),
+            None => false,
+        }
+    }
+}
+
+impl Iterator for OneSidedDiff {
+    type Item = IdTriple;
+
+    fn next(&mut self) -> Option<IdTriple> {
+        loop {
+            let triple = self.this.next()?;
+            let in_other = if self.shared.contains(&triple) {
+                self.other_contains_shared(&triple)
+            } else {
+                self.other_contains_unshared(&triple)
+            };
+
+            if !in_other {
+                return Some(triple);
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! Filtering dictionary entries by pattern.
+//!
+//! Layers can find the ids of all nodes, predicates or string values
+//! matching a regular expression, as in `Layer::node_ids_matching`.
+//! Expressions are matched against the dictionary entries as they
+//! are decoded, so no strings are allocated for entries that don't
+//! match. Expressions are byte-based `Regex`es, which still match
+//! UTF-8 text by default. Glob patterns can be turned into such an
+//! expression with `glob_regex`.
+pub use regex::bytes::Regex;
+
+/// Build a regular expression matching the same strings as the given glob pattern.
+///
+/// In the pattern, `*` matches any amount of characters, `?`
+/// matches exactly one character, and `[...]` matches one character
+/// from the given set, or not from the set if it starts with `!`.
+/// Sets may contain ranges like `a-z`.
+/// Everything else matches itself. The pattern has to match the
+/// whole string.
+pub fn glob_regex(glob: &str) -> Result<Regex, regex::Error> {
+    let mut expression = String::from("(?s)^");
+    let mut chars = glob.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => expression.push_str(".*"),
+            '?' => expression.push('.'),
+            '[' => {
+                let rest = chars.as_str();
+                // a ] at the start of the set is part of the set
+                let skip = if rest.starts_with("!]") {
+                    2
+                } else {
+                    rest.starts_with(']') as usize
+                };
+                match rest[skip..].find(']') {
+                    Some(end) => {
+                        let set = &rest[..skip + end];
+                        expression.push('[');
+                        let set = match set.strip_prefix('!') {
+                            Some(set) => {
+                                expression.push('^');
+                                set
+                            }
+                            None => set,
+                        };
+                        for c in set.chars() {
+                            if matches!(c, '\\' | '[' | ']' | '^' | '&' | '~') {
+                                expression.push('\\');
+                            }
+                            expression.push(c);
+                        }
+                        expression.push(']');
+                        chars = rest[skip + end + 1..].chars();
+                    }
+                    None => expression.push_str(r"\["),
+                }
+            }
+            c => expression.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    expression.push('$');
+
+    Regex::new(&expression)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_globs() {
+        let matches = |glob: &str, s: &str| glob_regex(glob).unwrap().is_match(s.as_bytes());
+        assert!(matches("*", ""));
+        assert!(matches("cow*", "cowbell"));
+        assert!(!matches("cow*", "a cow"));
+        assert!(matches("c?w", "cow"));
+        assert!(matches("c?w", "cäw"));
+        assert!(!matches("c?w", "cw"));
+        assert!(matches("[bc]ow", "bow"));
+        assert!(!matches("[!bc]ow", "bow"));
+        assert!(matches("[!bc]ow", "how"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("[a-c]at", "bat"));
+        assert!(!matches("[a-c]at", "cow"));
+        assert!(matches("a.b+[", "a.b+["));
+        assert!(!matches("a.b", "axb"));
+        assert!(matches("*\n*", "two\nlines"));
+    }
+}
@@ -143,7 +143,7 @@ pub async fn construct_idmaps_from_structures<F: 'static + FileLoad + FileStore>
         sorted_iterator(predicate_iters, entry_comparator).map(|(id, _)| id);
 
     let node_value_width = util::calculate_width(node_offset as u64);
-    let node_value_build_task = tokio::spawn(build_wavelet_tree_from_iter(
+    let node_value_build_task = crate::runtime::spawn(build_wavelet_tree_from_iter(
         node_value_width,
         sorted_node_value_iter,
         idmap_files.node_value_idmap_files.bits_file,
@@ -151,7 +151,7 @@ pub async fn construct_idmaps_from_structures<F: 'static + FileLoad + FileStore>
         idmap_files.node_value_idmap_files.sblocks_file,
     ));
     let predicate_width = util::calculate_width(predicate_offset as u64);
-    let predicate_build_task = tokio::spawn(build_wavelet_tree_from_iter(
+    let predicate_build_task = crate::runtime::spawn(build_wavelet_tree_from_iter(
         predicate_width,
         sorted_predicate_iter,
         idmap_files.predicate_idmap_files.bits_file,
@@ -7,7 +7,10 @@ use futures::task::{Context, Poll};
 use super::super::builder::*;
 use super::super::id_map::*;
 use super::super::layer::*;
-use crate::layer::InternalLayer;
+use crate::layer::internal::{
+    adjacency_list_triple_count, predicate_stats_from_files, write_predicate_stats, InternalLayer,
+    LayerPredicateStats,
+};
 use crate::storage::*;
 use crate::structure::*;
 
@@ -37,6 +40,9 @@ pub struct BaseLayer {
     pub(super) o_ps_adjacency_list: AdjacencyList,
 
     pub(super) predicate_wavelet_tree: WaveletTree,
+
+    pub(super) counts: LayerCounts,
+    pub(super) predicate_stats: Option<LayerPredicateStats>,
 }
 
 impl BaseLayer {
@@ -117,6 +123,21 @@ impl BaseLayer {
             predicate_wavelet_tree_width,
         );
 
+        let triple_count =
+            adjacency_list_triple_count(&sp_o_adjacency_list, &predicate_wavelet_tree);
+        let counts = LayerCounts {
+            node_count: node_dictionary.len(),
+            predicate_count: predicate_dictionary.len(),
+            value_count: value_dictionary.len(),
+            triple_addition_count: triple_count,
+            triple_removal_count: 0,
+            layer_triple_addition_count: triple_count,
+            layer_triple_removal_count: 0,
+        };
+        let predicate_stats = maps
+            .predicate_stats_map
+            .and_then(|map| LayerPredicateStats::from_bytes(&map).ok());
+
         InternalLayer::Base(BaseLayer {
             name,
             node_dictionary,
@@ -135,6 +156,9 @@ impl BaseLayer {
             o_ps_adjacency_list,
 
             predicate_wavelet_tree,
+
+            counts,
+            predicate_stats,
         })
     }
 }
@@ -149,6 +173,7 @@ pub struct BaseLayerFileBuilder<F: 'static + FileLoad + FileStore> {
     files: BaseLayerFiles<F>,
 
     builder: DictionarySetFileBuilder<F>,
+    parallelism: Parallelism,
 }
 
 impl<F: 'static + FileLoad + FileStore + Clone> BaseLayerFileBuilder<F> {
@@ -164,9 +189,16 @@ impl<F: 'static + FileLoad + FileStore + Clone> BaseLayerFileBuilder<F> {
         Ok(BaseLayerFileBuilder {
             files: files.clone(),
             builder,
+            parallelism: Parallelism::default(),
         })
     }
 
+    /// Limit how many parts of the layer are built at the same time.
+    pub fn with_parallelism(mut self, parallelism: Parallelism) -> Self {
+        self.parallelism = parallelism;
+        self
+    }
+
     /// Add a node string.
     ///
     /// Panics if the given node string is not a lexical successor of the previous node string.
@@ -244,10 +276,46 @@ impl<F: 'static + FileLoad + FileStore + Clone> BaseLayerFileBuilder<F> {
 
     /// Turn this builder into a phase 2 builder that will take triple data.
     pub async fn into_phase2(self) -> io::Result<BaseLayerFileBuilderPhase2<F>> {
-        let BaseLayerFileBuilder { files, builder } = self;
+        let BaseLayerFileBuilder {
+            files,
+            builder,
+            parallelism,
+        } = self;
 
         builder.finalize().await?;
 
+        Self::open_phase2(files, parallelism).await
+    }
+
+    /// Add all the given nodes, predicates and values, and turn this
+    /// builder into a phase 2 builder that will take triple data.
+    ///
+    /// The dictionaries are built concurrently. Panics if any of the
+    /// given strings are not in lexical order, or if previously added
+    /// strings are a lexical successor of any of them.
+    pub async fn into_phase2_with_dictionaries(
+        self,
+        nodes: Vec<String>,
+        predicates: Vec<String>,
+        values: Vec<String>,
+    ) -> io::Result<BaseLayerFileBuilderPhase2<F>> {
+        let BaseLayerFileBuilder {
+            files,
+            builder,
+            parallelism,
+        } = self;
+
+        builder
+            .build_all(nodes, predicates, values, &parallelism)
+            .await?;
+
+        Self::open_phase2(files, parallelism).await
+    }
+
+    async fn open_phase2(
+        files: BaseLayerFiles<F>,
+        parallelism: Parallelism,
+    ) -> io::Result<BaseLayerFileBuilderPhase2<F>> {
         let node_dict_blocks_map = files.node_dictionary_files.blocks_file.map().await?;
         let node_dict_offsets_map = files.node_dictionary_files.offsets_file.map().await?;
         let predicate_dict_blocks_map = files.predicate_dictionary_files.blocks_file.map().await?;
@@ -265,7 +333,8 @@ impl<F: 'static + FileLoad + FileStore + Clone> BaseLayerFileBuilder<F> {
         let num_predicates = pred_dict.len();
         let num_values = val_dict.len();
 
-        BaseLayerFileBuilderPhase2::new(files, num_nodes, num_predicates, num_values).await
+        BaseLayerFileBuilderPhase2::new(files, num_nodes, num_predicates, num_values, parallelism)
+            .await
     }
 }
 
@@ -277,6 +346,9 @@ pub struct BaseLayerFileBuilderPhase2<F: 'static + FileLoad + FileStore> {
     files: BaseLayerFiles<F>,
 
     builder: TripleFileBuilder<F>,
+    parallelism: Parallelism,
+    index_spill: Option<SpillConfig>,
+    progress: BuildProgressReporter,
 }
 
 impl<F: 'static + FileLoad + FileStore> BaseLayerFileBuilderPhase2<F> {
@@ -286,6 +358,7 @@ impl<F: 'static + FileLoad + FileStore> BaseLayerFileBuilderPhase2<F> {
         num_nodes: usize,
         num_predicates: usize,
         num_values: usize,
+        parallelism: Parallelism,
     ) -> io::Result<Self> {
         let builder = TripleFileBuilder::new(
             files.s_p_adjacency_list_files.clone(),
@@ -297,7 +370,25 @@ impl<F: 'static + FileLoad + FileStore> BaseLayerFileBuilderPhase2<F> {
         )
         .await?;
 
-        Ok(BaseLayerFileBuilderPhase2 { files, builder })
+        Ok(BaseLayerFileBuilderPhase2 {
+            files,
+            builder,
+            parallelism,
+            index_spill: None,
+            progress: BuildProgressReporter::default(),
+        })
+    }
+
+    /// Report when the triples have been written and the indexes have been built.
+    pub fn with_progress(mut self, progress: BuildProgressReporter) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Sort the data for the indexes in scratch files as configured.
+    pub(crate) fn with_index_spill(mut self, config: SpillConfig) -> Self {
+        self.index_spill = Some(config);
+        self
     }
 
     /// Add the given subject, predicate and object.
@@ -330,15 +421,32 @@ impl<F: 'static + FileLoad + FileStore> BaseLayerFileBuilderPhase2<F> {
         let sp_o_adjacency_list_files = self.files.sp_o_adjacency_list_files;
         let o_ps_adjacency_list_files = self.files.o_ps_adjacency_list_files;
         let predicate_wavelet_tree_files = self.files.predicate_wavelet_tree_files;
+        let predicate_stats_file = self.files.predicate_stats_file;
 
         self.builder.finalize().await?;
+        self.progress.report(BuildProgress::TriplesWritten);
 
-        build_indexes(
-            s_p_adjacency_list_files,
+        build_indexes_with_spill(
+            s_p_adjacency_list_files.clone(),
             sp_o_adjacency_list_files,
-            o_ps_adjacency_list_files,
+            o_ps_adjacency_list_files.clone(),
             None,
             predicate_wavelet_tree_files,
+            &self.parallelism,
+            self.index_spill,
+        )
+        .await?;
+        self.progress.report(BuildProgress::IndexesBuilt);
+
+        let additions =
+            predicate_stats_from_files(&s_p_adjacency_list_files, &o_ps_adjacency_list_files)
+                .await?;
+        write_predicate_stats(
+            &predicate_stats_file,
+            &LayerPredicateStats {
+                additions,
+                ..Default::default()
+            },
         )
         .await
     }
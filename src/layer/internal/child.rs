@@ -5,6 +5,10 @@
 //! this layer needs for its additions.
 use super::super::builder::*;
 use super::super::id_map::*;
+use crate::layer::internal::{
+    adjacency_list_triple_count, predicate_stats_from_files, write_predicate_stats,
+    LayerPredicateStats,
+};
 use crate::layer::*;
 use crate::storage::*;
 use crate::structure::*;
@@ -49,6 +53,9 @@ pub struct ChildLayer {
 
     pub(super) pos_predicate_wavelet_tree: WaveletTree,
     pub(super) neg_predicate_wavelet_tree: WaveletTree,
+
+    pub(super) counts: LayerCounts,
+    pub(super) predicate_stats: Option<LayerPredicateStats>,
 }
 
 impl ChildLayer {
@@ -163,6 +170,24 @@ impl ChildLayer {
             neg_predicate_wavelet_tree_width,
         );
 
+        let parent_counts = parent.all_counts();
+        let additions =
+            adjacency_list_triple_count(&pos_sp_o_adjacency_list, &pos_predicate_wavelet_tree);
+        let removals =
+            adjacency_list_triple_count(&neg_sp_o_adjacency_list, &neg_predicate_wavelet_tree);
+        let counts = LayerCounts {
+            node_count: parent_counts.node_count + node_dictionary.len(),
+            predicate_count: parent_counts.predicate_count + predicate_dictionary.len(),
+            value_count: parent_counts.value_count + value_dictionary.len(),
+            triple_addition_count: parent_counts.triple_addition_count + additions,
+            triple_removal_count: parent_counts.triple_removal_count + removals,
+            layer_triple_addition_count: additions,
+            layer_triple_removal_count: removals,
+        };
+        let predicate_stats = maps
+            .predicate_stats_map
+            .and_then(|map| LayerPredicateStats::from_bytes(&map).ok());
+
         InternalLayer::Child(ChildLayer {
             name,
             parent,
@@ -192,6 +217,9 @@ impl ChildLayer {
 
             pos_predicate_wavelet_tree,
             neg_predicate_wavelet_tree,
+
+            counts,
+            predicate_stats,
         })
     }
 }
@@ -206,6 +234,7 @@ pub struct ChildLayerFileBuilder<F: 'static + FileLoad + FileStore + Clone + Sen
     parent: Arc<dyn Layer>,
     files: ChildLayerFiles<F>,
     builder: DictionarySetFileBuilder<F>,
+    parallelism: Parallelism,
 }
 
 impl<F: 'static + FileLoad + FileStore + Clone + Send + Sync> ChildLayerFileBuilder<F> {
@@ -225,9 +254,16 @@ impl<F: 'static + FileLoad + FileStore + Clone + Send + Sync> ChildLayerFileBuil
             parent,
             files: files.clone(),
             builder,
+            parallelism: Parallelism::default(),
         })
     }
 
+    /// Limit how many parts of the layer are built at the same time.
+    pub fn with_parallelism(mut self, parallelism: Parallelism) -> Self {
+        self.parallelism = parallelism;
+        self
+    }
+
     /// Add a node string.
     ///
     /// Does nothing if the node already exists in the parent, and
@@ -339,10 +375,74 @@ impl<F: 'static + FileLoad + FileStore + Clone + Send + Sync> ChildLayerFileBuil
             parent,
             files,
             builder,
+            parallelism,
         } = self;
 
         builder.finalize().await?;
 
+        Self::open_phase2(parent, files, parallelism).await
+    }
+
+    /// Add all the given nodes, predicates and values, and turn this
+    /// builder into a phase 2 builder that will take triple data.
+    ///
+    /// The dictionaries are built concurrently. Panics if any of the
+    /// given strings are not in lexical order, or if previously added
+    /// strings are a lexical successor of any of them. Skips any
+    /// strings that are already part of the parent.
+    pub async fn into_phase2_with_dictionaries(
+        self,
+        nodes: Vec<String>,
+        predicates: Vec<String>,
+        values: Vec<String>,
+    ) -> io::Result<ChildLayerFileBuilderPhase2<F>> {
+        let ChildLayerFileBuilder {
+            parent,
+            files,
+            builder,
+            parallelism,
+        } = self;
+
+        let (nodes, predicates, values) = parallelism.install(|| {
+            let (nodes, (predicates, values)) = rayon::join(
+                || {
+                    nodes
+                        .into_par_iter()
+                        .filter(|node| parent.subject_id(node).is_none())
+                        .collect()
+                },
+                || {
+                    rayon::join(
+                        || {
+                            predicates
+                                .into_par_iter()
+                                .filter(|predicate| parent.predicate_id(predicate).is_none())
+                                .collect()
+                        },
+                        || {
+                            values
+                                .into_par_iter()
+                                .filter(|value| parent.object_value_id(value).is_none())
+                                .collect()
+                        },
+                    )
+                },
+            );
+
+            (nodes, predicates, values)
+        });
+        builder
+            .build_all(nodes, predicates, values, &parallelism)
+            .await?;
+
+        Self::open_phase2(parent, files, parallelism).await
+    }
+
+    async fn open_phase2(
+        parent: Arc<dyn Layer>,
+        files: ChildLayerFiles<F>,
+        parallelism: Parallelism,
+    ) -> io::Result<ChildLayerFileBuilderPhase2<F>> {
         let node_dict_blocks_map = files.node_dictionary_files.blocks_file.map().await?;
         let node_dict_offsets_map = files.node_dictionary_files.offsets_file.map().await?;
         let predicate_dict_blocks_map = files.predicate_dictionary_files.blocks_file.map().await?;
@@ -360,7 +460,15 @@ impl<F: 'static + FileLoad + FileStore + Clone + Send + Sync> ChildLayerFileBuil
         let num_predicates = pred_dict.len();
         let num_values = val_dict.len();
 
-        ChildLayerFileBuilderPhase2::new(parent, files, num_nodes, num_predicates, num_values).await
+        ChildLayerFileBuilderPhase2::new(
+            parent,
+            files,
+            num_nodes,
+            num_predicates,
+            num_values,
+            parallelism,
+        )
+        .await
     }
 }
 
@@ -375,6 +483,8 @@ pub struct ChildLayerFileBuilderPhase2<F: 'static + FileLoad + FileStore + Clone
 
     pos_builder: TripleFileBuilder<F>,
     neg_builder: TripleFileBuilder<F>,
+    parallelism: Parallelism,
+    progress: BuildProgressReporter,
 }
 
 impl<F: 'static + FileLoad + FileStore + Clone + Send + Sync> ChildLayerFileBuilderPhase2<F> {
@@ -385,6 +495,7 @@ impl<F: 'static + FileLoad + FileStore + Clone + Send + Sync> ChildLayerFileBuil
         num_nodes: usize,
         num_predicates: usize,
         num_values: usize,
+        parallelism: Parallelism,
     ) -> io::Result<Self> {
         let parent_counts = parent.all_counts();
         let pos_builder = TripleFileBuilder::new(
@@ -413,9 +524,17 @@ impl<F: 'static + FileLoad + FileStore + Clone + Send + Sync> ChildLayerFileBuil
 
             pos_builder,
             neg_builder,
+            parallelism,
+            progress: BuildProgressReporter::default(),
         })
     }
 
+    /// Report when the triples have been written and the indexes have been built.
+    pub fn with_progress(mut self, progress: BuildProgressReporter) -> Self {
+        self.progress = progress;
+        self
+    }
+
     async fn add_triple_unchecked(
         &mut self,
         subject: u64,
@@ -515,33 +634,113 @@ impl<F: 'static + FileLoad + FileStore + Clone + Send + Sync> ChildLayerFileBuil
         Ok(())
     }
 
+    /// Add and remove the given triples, then write the layer data to storage.
+    ///
+    /// Additions and removals are written concurrently. The triples
+    /// should be sorted and come after any triples that were already
+    /// added or removed. Additions that are already part of the
+    /// parent, and removals that the parent doesn't know about, are
+    /// skipped.
+    pub async fn finalize_with_id_triples(
+        self,
+        additions: Vec<IdTriple>,
+        removals: Vec<IdTriple>,
+    ) -> io::Result<()> {
+        let ChildLayerFileBuilderPhase2 {
+            parent,
+            files,
+            mut pos_builder,
+            mut neg_builder,
+            parallelism,
+            progress,
+        } = self;
+
+        let (additions, removals): (Vec<_>, Vec<_>) = parallelism.install(|| {
+            rayon::join(
+                || {
+                    additions
+                        .into_par_iter()
+                        .filter(|t| !parent.triple_exists(t.subject, t.predicate, t.object))
+                        .collect()
+                },
+                || {
+                    removals
+                        .into_par_iter()
+                        .filter(|t| parent.triple_exists(t.subject, t.predicate, t.object))
+                        .collect()
+                },
+            )
+        });
+
+        let pos_task = parallelism.spawn(async move {
+            for t in additions {
+                pos_builder
+                    .add_triple(t.subject, t.predicate, t.object)
+                    .await?;
+            }
+            pos_builder.finalize().await
+        });
+        let neg_task = parallelism.spawn(async move {
+            for t in removals {
+                neg_builder
+                    .add_triple(t.subject, t.predicate, t.object)
+                    .await?;
+            }
+            neg_builder.finalize().await
+        });
+
+        futures::try_join!(pos_task, neg_task)?;
+        progress.report(BuildProgress::TriplesWritten);
+
+        let predicate_stats_file = files.predicate_stats_file;
+        let pos_s_p_adjacency_list_files = files.pos_s_p_adjacency_list_files.clone();
+        let pos_o_ps_adjacency_list_files = files.pos_o_ps_adjacency_list_files.clone();
+        let neg_s_p_adjacency_list_files = files.neg_s_p_adjacency_list_files.clone();
+        let neg_o_ps_adjacency_list_files = files.neg_o_ps_adjacency_list_files.clone();
+
+        let pos_indexes_task = build_indexes_with_parallelism(
+            files.pos_s_p_adjacency_list_files,
+            files.pos_sp_o_adjacency_list_files,
+            files.pos_o_ps_adjacency_list_files,
+            Some(files.pos_objects_file),
+            files.pos_predicate_wavelet_tree_files,
+            &parallelism,
+        );
+        let neg_indexes_task = build_indexes_with_parallelism(
+            files.neg_s_p_adjacency_list_files,
+            files.neg_sp_o_adjacency_list_files,
+            files.neg_o_ps_adjacency_list_files,
+            Some(files.neg_objects_file),
+            files.neg_predicate_wavelet_tree_files,
+            &parallelism,
+        );
+
+        futures::try_join!(pos_indexes_task, neg_indexes_task)?;
+        progress.report(BuildProgress::IndexesBuilt);
+
+        let (additions, removals) = futures::try_join!(
+            predicate_stats_from_files(
+                &pos_s_p_adjacency_list_files,
+                &pos_o_ps_adjacency_list_files
+            ),
+            predicate_stats_from_files(
+                &neg_s_p_adjacency_list_files,
+                &neg_o_ps_adjacency_list_files
+            ),
+        )?;
+        write_predicate_stats(
+            &predicate_stats_file,
+            &LayerPredicateStats {
+                additions,
+                removals,
+            },
+        )
+        .await
+    }
+
     /// Write the layer data to storage.
     pub async fn finalize(self) -> io::Result<()> {
-        let pos_task = tokio::spawn(self.pos_builder.finalize());
-        let neg_task = tokio::spawn(self.neg_builder.finalize());
-
-        pos_task.await??;
-        neg_task.await??;
-
-        let pos_indexes_task = tokio::spawn(build_indexes(
-            self.files.pos_s_p_adjacency_list_files,
-            self.files.pos_sp_o_adjacency_list_files,
-            self.files.pos_o_ps_adjacency_list_files,
-            Some(self.files.pos_objects_file),
-            self.files.pos_predicate_wavelet_tree_files,
-        ));
-        let neg_indexes_task = tokio::spawn(build_indexes(
-            self.files.neg_s_p_adjacency_list_files,
-            self.files.neg_sp_o_adjacency_list_files,
-            self.files.neg_o_ps_adjacency_list_files,
-            Some(self.files.neg_objects_file),
-            self.files.neg_predicate_wavelet_tree_files,
-        ));
-
-        pos_indexes_task.await??;
-        neg_indexes_task.await??;
-
-        Ok(())
+        self.finalize_with_id_triples(Vec::new(), Vec::new()).await
     }
 }
 
@@ -0,0 +1,289 @@
+//! Cardinality estimation for triple patterns.
+//!
+//! Estimates are computed from the index structures of a layer
+//! without iterating over any triples. Patterns with a subject, a
+//! subject and predicate, or an object are counted exactly for each
+//! layer, using the bit index ranks of the adjacency lists. Patterns
+//! with only a predicate are estimated from the amount of
+//! subject-predicate pairs for that predicate in the wavelet tree,
+//! multiplied by the amount of objects per pair in a sample of those
+//! pairs. Remaining patterns are bounded by the smallest count of
+//! their parts.
+use super::*;
+
+/// The maximum amount of subject-predicate pairs that is looked at when estimating predicate counts.
+const PREDICATE_SAMPLE_SIZE: usize = 64;
+
+/// The index structures for either the additions or the removals of a layer.
+struct TripleIndex<'a> {
+    subjects: Option<&'a MonotonicLogArray>,
+    objects: Option<&'a MonotonicLogArray>,
+    s_p_adjacency_list: &'a AdjacencyList,
+    sp_o_adjacency_list: &'a AdjacencyList,
+    o_ps_adjacency_list: &'a AdjacencyList,
+    predicate_wavelet_tree: &'a WaveletTree,
+}
+
+/// The start and end position of the row for the given (1-based) index.
+fn row(adjacency_list: &AdjacencyList, index: u64) -> Option<(u64, u64)> {
+    if index == 0 || index > adjacency_list.left_count() as u64 {
+        None
+    } else {
+        Some((
+            adjacency_list.offset_for(index),
+            adjacency_list.offset_for(index + 1),
+        ))
+    }
+}
+
+/// The (1-based) index of an element, using a mapping if there is one.
+fn index_of(mapping: Option<&MonotonicLogArray>, element: u64) -> Option<u64> {
+    match mapping {
+        None => Some(element),
+        Some(mapping) => mapping.index_of(element).map(|i| i as u64 + 1),
+    }
+}
+
+impl<'a> TripleIndex<'a> {
+    fn subject_row(&self, subject: u64) -> Option<(u64, u64)> {
+        let (start, end) = row(self.s_p_adjacency_list, index_of(self.subjects, subject)?)?;
+        if end - start == 1 && self.s_p_adjacency_list.num_at_pos(start) == 0 {
+            // a gap in a base layer
+            None
+        } else {
+            Some((start, end))
+        }
+    }
+
+    /// The amount of triples between the given positions in the s_p adjacency list.
+    fn pair_range_triple_count(&self, start: u64, end: u64) -> usize {
+        (self.sp_o_adjacency_list.offset_for(end + 1)
+            - self.sp_o_adjacency_list.offset_for(start + 1)) as usize
+    }
+
+    fn count_s(&self, subject: u64) -> usize {
+        match self.subject_row(subject) {
+            Some((start, end)) => self.pair_range_triple_count(start, end),
+            None => 0,
+        }
+    }
+
+    fn count_sp(&self, subject: u64, predicate: u64) -> usize {
+        let (mut start, mut end) = match self.subject_row(subject) {
+            Some(row) => row,
+            None => return 0,
+        };
+        while start < end {
+            let mid = (start + end) / 2;
+            match self.s_p_adjacency_list.num_at_pos(mid).cmp(&predicate) {
+                std::cmp::Ordering::Equal => return self.pair_range_triple_count(mid, mid + 1),
+                std::cmp::Ordering::Less => start = mid + 1,
+                std::cmp::Ordering::Greater => end = mid,
+            }
+        }
+
+        0
+    }
+
+    fn count_p(&self, predicate: u64) -> usize {
+        let lookup = match self.predicate_wavelet_tree.lookup(predicate) {
+            Some(lookup) => lookup,
+            None => return 0,
+        };
+        let pairs = lookup.len();
+        let samples = pairs.min(PREDICATE_SAMPLE_SIZE);
+        let sampled_triples: usize = (0..samples)
+            .map(|i| {
+                let position = lookup.entry(i * pairs / samples);
+                self.pair_range_triple_count(position, position + 1)
+            })
+            .sum();
+
+        sampled_triples * pairs / samples
+    }
+
+    fn count_o(&self, object: u64) -> usize {
+        let (start, end) = match index_of(self.objects, object)
+            .and_then(|index| row(self.o_ps_adjacency_list, index))
+        {
+            Some(row) => row,
+            None => return 0,
+        };
+        if end - start == 1 && self.o_ps_adjacency_list.num_at_pos(start) == 0 {
+            // an object without any triples in a base layer
+            0
+        } else {
+            (end - start) as usize
+        }
+    }
+
+    fn estimate(&self, subject: Option<u64>, predicate: Option<u64>, object: Option<u64>) -> usize {
+        match (subject, predicate, object) {
+            (Some(subject), Some(predicate), _) => {
+                let count = self.count_sp(subject, predicate);
+                match object {
+                    Some(object) => count.min(self.count_o(object)).min(1),
+                    None => count,
+                }
+            }
+            (Some(subject), None, Some(object)) => self.count_s(subject).min(self.count_o(object)),
+            (Some(subject), None, None) => self.count_s(subject),
+            (None, Some(predicate), Some(object)) => {
+                self.count_p(predicate).min(self.count_o(object))
+            }
+            (None, Some(predicate), None) => self.count_p(predicate),
+            (None, None, Some(object)) => self.count_o(object),
+            (None, None, None) => {
+                adjacency_list_triple_count(self.sp_o_adjacency_list, self.predicate_wavelet_tree)
+            }
+        }
+    }
+}
+
+impl InternalLayer {
+    fn pos_triple_index(&self) -> TripleIndex<'_> {
+        TripleIndex {
+            subjects: self.pos_subjects(),
+            objects: self.pos_objects(),
+            s_p_adjacency_list: self.pos_s_p_adjacency_list(),
+            sp_o_adjacency_list: self.pos_sp_o_adjacency_list(),
+            o_ps_adjacency_list: self.pos_o_ps_adjacency_list(),
+            predicate_wavelet_tree: self.pos_predicate_wavelet_tree(),
+        }
+    }
+
+    fn neg_triple_index(&self) -> Option<TripleIndex<'_>> {
+        Some(TripleIndex {
+            subjects: self.neg_subjects(),
+            objects: self.neg_objects(),
+            s_p_adjacency_list: self.neg_s_p_adjacency_list()?,
+            sp_o_adjacency_list: self.neg_sp_o_adjacency_list()?,
+            o_ps_adjacency_list: self.neg_o_ps_adjacency_list()?,
+            predicate_wavelet_tree: self.neg_predicate_wavelet_tree()?,
+        })
+    }
+
+    /// An estimate of the amount of triples added in this layer that match the given pattern.
+    pub fn internal_triple_additions_estimate(
+        &self,
+        subject: Option<u64>,
+        predicate: Option<u64>,
+        object: Option<u64>,
+    ) -> usize {
+        self.pos_triple_index().estimate(subject, predicate, object)
+    }
+
+    /// An estimate of the amount of triples removed in this layer that match the given pattern.
+    pub fn internal_triple_removals_estimate(
+        &self,
+        subject: Option<u64>,
+        predicate: Option<u64>,
+        object: Option<u64>,
+    ) -> usize {
+        self.neg_triple_index()
+            .map(|index| index.estimate(subject, predicate, object))
+            .unwrap_or(0)
+    }
+
+    /// An estimate of the amount of triples matching the given pattern in this layer and its ancestors.
+    pub(super) fn estimate_layer_stack_count(
+        &self,
+        subject: Option<u64>,
+        predicate: Option<u64>,
+        object: Option<u64>,
+    ) -> usize {
+        if subject == Some(0) || predicate == Some(0) || object == Some(0) {
+            return 0;
+        }
+
+        let mut additions = 0;
+        let mut removals = 0;
+        let mut layer = Some(self);
+        while let Some(l) = layer {
+            additions += l.internal_triple_additions_estimate(subject, predicate, object);
+            removals += l.internal_triple_removals_estimate(subject, predicate, object);
+            layer = l.immediate_parent();
+        }
+
+        additions.saturating_sub(removals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layer::*;
+    use crate::store::open_memory_store;
+
+    #[tokio::test]
+    async fn estimates_match_exact_counts() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        for i in 0..20 {
+            let subject = format!("node{}", i);
+            builder
+                .add_string_triple(StringTriple::new_value(&subject, "name", &subject))
+                .unwrap();
+            builder
+                .add_string_triple(StringTriple::new_node(&subject, "likes", "node0"))
+                .unwrap();
+            if i % 2 == 0 {
+                builder
+                    .add_string_triple(StringTriple::new_node(&subject, "likes", "node1"))
+                    .unwrap();
+            }
+        }
+        let base = builder.commit().await.unwrap();
+
+        let builder = base.open_write().await.unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_node("node2", "likes", "node0"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_node("node2", "likes", "node1"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("node3", "likes", "node1"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("node3", "age", "3"))
+            .unwrap();
+        let layer = builder.commit().await.unwrap();
+
+        let node2 = layer.subject_id("node2").unwrap();
+        let node3 = layer.subject_id("node3").unwrap();
+        let node1 = layer.object_node_id("node1").unwrap();
+        let likes = layer.predicate_id("likes").unwrap();
+        let age = layer.predicate_id("age").unwrap();
+
+        let exact_patterns = [
+            (None, None, None),
+            (Some(node2), None, None),
+            (Some(node3), None, None),
+            (Some(node2), Some(likes), None),
+            (Some(node3), Some(likes), None),
+            (Some(node3), Some(age), None),
+            (None, None, Some(node1)),
+            (Some(node3), Some(likes), Some(node1)),
+            (Some(node2), Some(likes), Some(node1)),
+        ];
+        for (s, p, o) in exact_patterns {
+            assert_eq!(
+                layer.triples_matching(s, p, o).count(),
+                layer.estimate_count(s, p, o),
+                "pattern {:?}",
+                (s, p, o)
+            );
+        }
+
+        // few enough pairs to be sampled completely
+        assert_eq!(
+            layer.triples_p(likes).count(),
+            layer.estimate_count(None, Some(likes), None)
+        );
+        assert_eq!(1, layer.estimate_count(None, Some(age), None));
+
+        assert!(layer.estimate_count(Some(node3), None, Some(node1)) >= 1);
+        assert_eq!(0, layer.estimate_count(Some(0), None, None));
+        assert_eq!(0, layer.estimate_count(None, Some(12345), None));
+    }
+}
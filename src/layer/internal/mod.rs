@@ -1,19 +1,34 @@
 pub mod base;
 pub mod child;
+mod estimate;
 mod object_iterator;
 mod predicate_iterator;
+mod predicate_stats;
 pub mod rollup;
+mod sample;
 mod subject_iterator;
 
+use super::filter::Regex;
 use super::id_map::*;
 use super::layer::*;
+use super::typed::value_string_text;
 use crate::structure::*;
 use std::convert::TryInto;
+use std::ops::Bound;
+
+/// The amount of triples in an sp_o adjacency list, not counting the placeholder entries.
+pub(crate) fn adjacency_list_triple_count(
+    sp_o_adjacency_list: &AdjacencyList,
+    predicates: &WaveletTree,
+) -> usize {
+    sp_o_adjacency_list.right_count() - predicates.lookup(0).map(|l| l.len()).unwrap_or(0)
+}
 
 pub use base::*;
 pub use child::*;
 pub use object_iterator::*;
 pub use predicate_iterator::*;
+pub(crate) use predicate_stats::*;
 pub use rollup::*;
 pub use subject_iterator::*;
 
@@ -242,6 +257,19 @@ impl InternalLayer {
         self.predicate_dictionary().len()
     }
 
+    /// Whether the given function holds for this layer or any of its ancestors.
+    fn any_layer<F: Fn(&InternalLayer) -> bool>(&self, f: F) -> bool {
+        let mut layer = Some(self);
+        while let Some(l) = layer {
+            if f(l) {
+                return true;
+            }
+            layer = l.immediate_parent();
+        }
+
+        false
+    }
+
     pub fn predicate_dict_id(&self, predicate: &str) -> Option<u64> {
         self.predicate_dictionary().id(predicate)
     }
@@ -481,20 +509,18 @@ impl InternalLayer {
     }
 
     pub fn internal_triple_layer_addition_count(&self) -> usize {
-        self.pos_sp_o_adjacency_list().right_count()
-            - self
-                .pos_predicate_wavelet_tree()
-                .lookup(0)
-                .map(|l| l.len())
-                .unwrap_or(0)
+        self.all_counts().layer_triple_addition_count
     }
 
     pub fn internal_triple_layer_removal_count(&self) -> usize {
-        match self.neg_sp_o_adjacency_list() {
-            None => 0,
-            Some(adjacency_list) => adjacency_list.right_count()
-                - self.neg_predicate_wavelet_tree().expect("negative wavelet tree should exist when negative sp_o adjacency list exists")
-                .lookup(0).map(|l|l.len()).unwrap_or(0)
+        self.all_counts().layer_triple_removal_count
+    }
+
+    fn counts(&self) -> &LayerCounts {
+        match self {
+            Base(base) => &base.counts,
+            Child(child) => &child.counts,
+            Rollup(rollup) => rollup.internal.counts(),
         }
     }
 
@@ -637,6 +663,110 @@ impl Layer for InternalLayer {
         id_option.map(|id| 1 + id + parent_option.map_or(0, |p| p.node_and_value_count() as u64))
     }
 
+    fn subject_exists(&self, subject: &str) -> bool {
+        self.any_layer(|layer| layer.node_dict_id(subject).is_some())
+    }
+
+    fn predicate_exists(&self, predicate: &str) -> bool {
+        self.any_layer(|layer| layer.predicate_dict_id(predicate).is_some())
+    }
+
+    fn object_node_exists(&self, object: &str) -> bool {
+        self.any_layer(|layer| layer.node_dict_id(object).is_some())
+    }
+
+    fn object_value_exists(&self, object: &str) -> bool {
+        self.any_layer(|layer| layer.value_dict_id(object).is_some())
+    }
+
+    fn value_ids_in_range(&self, start: Bound<&str>, end: Bound<&str>) -> Vec<u64> {
+        let mut result = Vec::new();
+        let mut current_option: Option<&InternalLayer> = Some(self);
+        while let Some(current) = current_option {
+            let parent = current.immediate_parent();
+            let parent_count = parent.map_or(0, |p| p.node_and_value_count() as u64);
+            let node_dict_len = current.node_dict_len() as u64;
+            let id_map = current.node_value_id_map();
+            result.extend(
+                current
+                    .value_dictionary()
+                    .index_range(start, end)
+                    .map(|i| 1 + id_map.inner_to_outer(i as u64 + node_dict_len) + parent_count),
+            );
+
+            current_option = parent;
+        }
+        result.sort_unstable();
+
+        result
+    }
+
+    fn node_ids_matching(&self, regex: &Regex) -> Vec<u64> {
+        let mut result = Vec::new();
+        let mut current_option: Option<&InternalLayer> = Some(self);
+        while let Some(current) = current_option {
+            let parent = current.immediate_parent();
+            let parent_count = parent.map_or(0, |p| p.node_and_value_count() as u64);
+            let id_map = current.node_value_id_map();
+            result.extend(
+                current
+                    .node_dictionary()
+                    .regex_indexes(regex)
+                    .map(|i| 1 + id_map.inner_to_outer(i as u64) + parent_count),
+            );
+
+            current_option = parent;
+        }
+        result.sort_unstable();
+
+        result
+    }
+
+    fn predicate_ids_matching(&self, regex: &Regex) -> Vec<u64> {
+        let mut result = Vec::new();
+        let mut current_option: Option<&InternalLayer> = Some(self);
+        while let Some(current) = current_option {
+            let parent = current.immediate_parent();
+            let parent_count = parent.map_or(0, |p| p.predicate_count() as u64);
+            let id_map = current.predicate_id_map();
+            result.extend(
+                current
+                    .predicate_dictionary()
+                    .regex_indexes(regex)
+                    .map(|i| 1 + id_map.inner_to_outer(i as u64) + parent_count),
+            );
+
+            current_option = parent;
+        }
+        result.sort_unstable();
+
+        result
+    }
+
+    fn value_ids_matching(&self, regex: &Regex) -> Vec<u64> {
+        let mut result = Vec::new();
+        let mut current_option: Option<&InternalLayer> = Some(self);
+        while let Some(current) = current_option {
+            let parent = current.immediate_parent();
+            let parent_count = parent.map_or(0, |p| p.node_and_value_count() as u64);
+            let node_dict_len = current.node_dict_len() as u64;
+            let id_map = current.node_value_id_map();
+            result.extend(
+                current
+                    .value_dictionary()
+                    .filter_indexes(|value| {
+                        value_string_text(value).is_some_and(|text| regex.is_match(text))
+                    })
+                    .map(|i| 1 + id_map.inner_to_outer(i as u64 + node_dict_len) + parent_count),
+            );
+
+            current_option = parent;
+        }
+        result.sort_unstable();
+
+        result
+    }
+
     fn id_subject(&self, id: u64) -> Option<String> {
         if id == 0 {
             return None;
@@ -732,7 +862,7 @@ impl Layer for InternalLayer {
                 corrected_id -= current_layer.node_dict_len() as u64;
                 return current_layer
                     .value_dict_get(corrected_id.try_into().unwrap())
-                    .map(ObjectType::Value);
+                    .map(ObjectType::from_value_string);
             } else {
                 return current_layer
                     .node_dict_get(corrected_id.try_into().unwrap())
@@ -748,47 +878,15 @@ impl Layer for InternalLayer {
     }
 
     fn triple_addition_count(&self) -> usize {
-        let mut additions = self.internal_triple_layer_addition_count();
-
-        let mut parent = self.immediate_parent();
-        while parent.is_some() {
-            additions += parent.unwrap().internal_triple_layer_addition_count();
-
-            parent = parent.unwrap().immediate_parent();
-        }
-
-        additions
+        self.counts().triple_addition_count
     }
 
     fn triple_removal_count(&self) -> usize {
-        let mut removals = self.internal_triple_layer_removal_count();
-
-        let mut parent = self.immediate_parent();
-        while parent.is_some() {
-            removals += parent.unwrap().internal_triple_layer_removal_count();
-
-            parent = parent.unwrap().immediate_parent();
-        }
-
-        removals
+        self.counts().triple_removal_count
     }
 
     fn all_counts(&self) -> LayerCounts {
-        let mut node_count = self.node_dict_len();
-        let mut predicate_count = self.predicate_dict_len();
-        let mut value_count = self.value_dict_len();
-        let mut parent_option = self.immediate_parent();
-        while let Some(parent) = parent_option {
-            node_count += parent.node_dict_len();
-            predicate_count += parent.predicate_dict_len();
-            value_count += parent.value_dict_len();
-            parent_option = parent.immediate_parent();
-        }
-        LayerCounts {
-            node_count,
-            predicate_count,
-            value_count,
-        }
+        *self.counts()
     }
 
     fn triple_exists(&self, subject: u64, predicate: u64, object: u64) -> bool {
@@ -817,6 +915,53 @@ impl Layer for InternalLayer {
         }
     }
 
+    fn ancestor_counts(&self, name: [u32; 5]) -> Option<LayerCounts> {
+        let mut layer = Some(self);
+        while let Some(l) = layer {
+            if l.name() == name {
+                return Some(l.all_counts());
+            }
+            layer = l.immediate_parent();
+        }
+
+        None
+    }
+
+    fn common_ancestor_counts(&self, other: &dyn Layer) -> Option<LayerCounts> {
+        let mut layer = Some(self);
+        while let Some(l) = layer {
+            if let Some(counts) = other.ancestor_counts(l.name()) {
+                return Some(counts);
+            }
+            layer = l.immediate_parent();
+        }
+
+        None
+    }
+
+    fn estimate_count(
+        &self,
+        subject: Option<u64>,
+        predicate: Option<u64>,
+        object: Option<u64>,
+    ) -> usize {
+        match (subject, predicate, object) {
+            (Some(subject), Some(predicate), Some(object)) => {
+                self.triple_exists(subject, predicate, object) as usize
+            }
+            (None, None, None) => self.triple_count(),
+            _ => self.estimate_layer_stack_count(subject, predicate, object),
+        }
+    }
+
+    fn predicate_stats(&self, predicate: u64) -> PredicateStats {
+        self.layer_stack_predicate_stats(predicate)
+    }
+
+    fn sample_triples(&self, n: usize, seed: u64) -> Vec<IdTriple> {
+        self.sample_layer_stack_triples(n, seed)
+    }
+
     fn triples(&self) -> Box<dyn Iterator<Item = IdTriple> + Send> {
         Box::new(InternalTripleSubjectIterator::from_layer(self))
     }
@@ -829,6 +974,18 @@ impl Layer for InternalLayer {
         )
     }
 
+    fn triples_subject_range(
+        &self,
+        start: u64,
+        end: u64,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        Box::new(
+            InternalTripleSubjectIterator::from_layer(self)
+                .seek_subject(start)
+                .take_while(move |t| t.subject < end),
+        )
+    }
+
     fn triples_sp(
         &self,
         subject: u64,
@@ -953,6 +1110,32 @@ mod tests {
         builder.commit().unwrap()
     }
 
+    #[test]
+    fn strings_exist_in_ancestry() {
+        let store = open_sync_memory_store();
+        let base_layer = create_base_layer(&store);
+        let builder = base_layer.open_write().unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("horse", "says", "neigh"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_node("cow", "likes", "duck"))
+            .unwrap();
+        let layer = builder.commit().unwrap();
+
+        assert!(layer.subject_exists("cow"));
+        assert!(layer.subject_exists("horse"));
+        assert!(!layer.subject_exists("pig"));
+        assert!(layer.predicate_exists("likes"));
+        assert!(!layer.predicate_exists("hates"));
+        assert!(layer.object_node_exists("duck"));
+        assert!(!layer.object_node_exists("moo"));
+        assert!(layer.object_value_exists("neigh"));
+        assert!(layer.object_value_exists("moo"));
+        assert!(!layer.object_value_exists("duck"));
+        assert!(!base_layer.object_value_exists("neigh"));
+    }
+
     #[test]
     fn base_layer_addition_count() {
         let store = open_sync_memory_store();
@@ -981,6 +1164,40 @@ mod tests {
         assert_eq!(1, layer.triple_layer_removal_count().unwrap());
     }
 
+    #[test]
+    fn child_layer_all_counts() {
+        let store = open_sync_memory_store();
+        let base_layer = create_base_layer(&store);
+        let builder = base_layer.open_write().unwrap();
+
+        builder
+            .remove_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("horse", "says", "neigh"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("horse", "likes", "cow"))
+            .unwrap();
+
+        let layer = builder.commit().unwrap();
+
+        assert_eq!(
+            LayerCounts {
+                node_count: 3,
+                predicate_count: 2,
+                value_count: 3,
+                triple_addition_count: 5,
+                triple_removal_count: 1,
+                layer_triple_addition_count: 2,
+                layer_triple_removal_count: 1,
+            },
+            layer.all_counts()
+        );
+        assert_eq!(4, layer.all_counts().triple_count());
+        assert_eq!(4, layer.triple_count());
+    }
+
     use crate::layer::base::tests::*;
     #[tokio::test]
     async fn base_layer_with_gaps_addition_count() {
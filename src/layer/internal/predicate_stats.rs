@@ -0,0 +1,371 @@
+//! Per-predicate statistics for layers.
+//!
+//! When a layer is built, the amount of triples, distinct subjects and
+//! distinct objects is counted for every predicate that the layer adds
+//! or removes. These counts are stored in a separate file of the
+//! layer, so query planners can get at them without looking at any
+//! triples. Layers that were built before this file existed have
+//! their statistics computed from the indexes when they are asked for.
+use std::collections::BTreeMap;
+use std::io;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use super::*;
+use crate::storage::{AdjacencyListFiles, FileLoad, FileStore, SyncableFile};
+
+const PREDICATE_STATS_VERSION: u8 = 1;
+
+/// The statistics of every predicate in either the additions or the removals of a layer.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) struct PredicateStatsTable(Vec<(u64, PredicateStats)>);
+
+impl PredicateStatsTable {
+    /// Count triples, distinct subjects and distinct objects per predicate.
+    pub fn compute(
+        s_p_adjacency_list: &AdjacencyList,
+        o_ps_adjacency_list: &AdjacencyList,
+    ) -> Self {
+        let mut stats: BTreeMap<u64, PredicateStats> = BTreeMap::new();
+
+        // every subject-predicate pair is a distinct subject for its predicate
+        for predicate in s_p_adjacency_list.nums().iter() {
+            if predicate != 0 {
+                stats.entry(predicate).or_default().subject_count += 1;
+            }
+        }
+
+        let mut current_object = 0;
+        let mut object_predicates = Vec::new();
+        let mut count_object_predicates = |predicates: &mut Vec<u64>| {
+            predicates.sort_unstable();
+            predicates.dedup();
+            for predicate in predicates.drain(..) {
+                stats.entry(predicate).or_default().object_count += 1;
+            }
+        };
+        let mut triple_counts: BTreeMap<u64, usize> = BTreeMap::new();
+        for (object, sp) in o_ps_adjacency_list.iter() {
+            if object != current_object {
+                count_object_predicates(&mut object_predicates);
+                current_object = object;
+            }
+            let predicate = s_p_adjacency_list.num_at_pos(sp - 1);
+            *triple_counts.entry(predicate).or_default() += 1;
+            object_predicates.push(predicate);
+        }
+        count_object_predicates(&mut object_predicates);
+
+        for (predicate, count) in triple_counts {
+            stats.entry(predicate).or_default().triple_count = count;
+        }
+
+        Self(stats.into_iter().collect())
+    }
+
+    /// The statistics for the given predicate, which are all zero if the predicate does not occur.
+    pub fn get(&self, predicate: u64) -> PredicateStats {
+        match self.0.binary_search_by_key(&predicate, |(p, _)| *p) {
+            Ok(index) => self.0[index].1,
+            Err(_) => PredicateStats::default(),
+        }
+    }
+
+    fn write_to(&self, bytes: &mut Vec<u8>) {
+        bytes.write_u64::<BigEndian>(self.0.len() as u64).unwrap();
+        for (predicate, stats) in self.0.iter() {
+            for num in [
+                *predicate,
+                stats.triple_count as u64,
+                stats.subject_count as u64,
+                stats.object_count as u64,
+            ] {
+                bytes.write_u64::<BigEndian>(num).unwrap();
+            }
+        }
+    }
+
+    fn read_from(reader: &mut &[u8]) -> io::Result<Self> {
+        let len = reader.read_u64::<BigEndian>()?;
+        let mut entries = Vec::new();
+        for _ in 0..len {
+            let predicate = reader.read_u64::<BigEndian>()?;
+            let stats = PredicateStats {
+                triple_count: reader.read_u64::<BigEndian>()? as usize,
+                subject_count: reader.read_u64::<BigEndian>()? as usize,
+                object_count: reader.read_u64::<BigEndian>()? as usize,
+            };
+            entries.push((predicate, stats));
+        }
+
+        Ok(Self(entries))
+    }
+}
+
+/// The per-predicate statistics of the additions and removals of a layer.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) struct LayerPredicateStats {
+    pub additions: PredicateStatsTable,
+    pub removals: PredicateStatsTable,
+}
+
+impl LayerPredicateStats {
+    /// Encode these statistics as they are stored in a layer file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![PREDICATE_STATS_VERSION];
+        self.additions.write_to(&mut bytes);
+        self.removals.write_to(&mut bytes);
+
+        bytes
+    }
+
+    /// Decode statistics that were encoded with `to_bytes`.
+    pub fn from_bytes(mut bytes: &[u8]) -> io::Result<Self> {
+        let reader = &mut bytes;
+        let version = reader.read_u8()?;
+        if version != PREDICATE_STATS_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported predicate statistics version {}", version),
+            ));
+        }
+
+        let additions = PredicateStatsTable::read_from(reader)?;
+        let removals = PredicateStatsTable::read_from(reader)?;
+        if !reader.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid predicate statistics",
+            ));
+        }
+
+        Ok(Self {
+            additions,
+            removals,
+        })
+    }
+}
+
+async fn load_adjacency_list<F: 'static + FileLoad + FileStore>(
+    files: &AdjacencyListFiles<F>,
+) -> io::Result<AdjacencyList> {
+    let maps = files.map_all().await?;
+
+    Ok(AdjacencyList::parse(
+        maps.nums_map,
+        maps.bitindex_maps.bits_map,
+        maps.bitindex_maps.blocks_map,
+        maps.bitindex_maps.sblocks_map,
+    ))
+}
+
+/// Compute the statistics for a set of written triple files.
+pub(crate) async fn predicate_stats_from_files<F: 'static + FileLoad + FileStore>(
+    s_p_adjacency_list_files: &AdjacencyListFiles<F>,
+    o_ps_adjacency_list_files: &AdjacencyListFiles<F>,
+) -> io::Result<PredicateStatsTable> {
+    let s_p_adjacency_list = load_adjacency_list(s_p_adjacency_list_files).await?;
+    let o_ps_adjacency_list = load_adjacency_list(o_ps_adjacency_list_files).await?;
+
+    Ok(PredicateStatsTable::compute(
+        &s_p_adjacency_list,
+        &o_ps_adjacency_list,
+    ))
+}
+
+/// Write the predicate statistics of a layer to the given file.
+pub(crate) async fn write_predicate_stats<F: FileStore>(
+    file: &F,
+    stats: &LayerPredicateStats,
+) -> io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut writer = file.open_write().await?;
+    writer.write_all(&stats.to_bytes()).await?;
+    writer.flush().await?;
+    writer.sync_all().await
+}
+
+impl InternalLayer {
+    /// The stored statistics of this layer, if it has any.
+    fn stored_predicate_stats(&self) -> Option<&LayerPredicateStats> {
+        match self {
+            InternalLayer::Base(base) => base.predicate_stats.as_ref(),
+            InternalLayer::Child(child) => child.predicate_stats.as_ref(),
+            InternalLayer::Rollup(rollup) => rollup.internal.stored_predicate_stats(),
+        }
+    }
+
+    /// The statistics of the given predicate in the triples added by this layer.
+    pub fn internal_predicate_addition_stats(&self, predicate: u64) -> PredicateStats {
+        match self.stored_predicate_stats() {
+            Some(stats) => stats.additions.get(predicate),
+            None => predicate_stats_from_index(
+                self.pos_sp_o_adjacency_list(),
+                self.pos_predicate_wavelet_tree(),
+                predicate,
+            ),
+        }
+    }
+
+    /// The statistics of the given predicate in the triples removed by this layer.
+    pub fn internal_predicate_removal_stats(&self, predicate: u64) -> PredicateStats {
+        match self.stored_predicate_stats() {
+            Some(stats) => stats.removals.get(predicate),
+            None => match (
+                self.neg_sp_o_adjacency_list(),
+                self.neg_predicate_wavelet_tree(),
+            ) {
+                (Some(sp_o), Some(predicates)) => {
+                    predicate_stats_from_index(sp_o, predicates, predicate)
+                }
+                _ => PredicateStats::default(),
+            },
+        }
+    }
+
+    /// The statistics of the given predicate in this layer, taking all ancestors into account.
+    pub(super) fn layer_stack_predicate_stats(&self, predicate: u64) -> PredicateStats {
+        let mut additions = PredicateStats::default();
+        let mut removals = PredicateStats::default();
+        let mut layer = Some(self);
+        while let Some(l) = layer {
+            additions += l.internal_predicate_addition_stats(predicate);
+            removals += l.internal_predicate_removal_stats(predicate);
+            layer = l.immediate_parent();
+        }
+
+        additions.subtract_removals(removals)
+    }
+}
+
+/// Compute the statistics of a single predicate from the indexes of a layer.
+fn predicate_stats_from_index(
+    sp_o_adjacency_list: &AdjacencyList,
+    predicate_wavelet_tree: &WaveletTree,
+    predicate: u64,
+) -> PredicateStats {
+    let lookup = match predicate_wavelet_tree.lookup(predicate) {
+        Some(lookup) if predicate != 0 => lookup,
+        _ => return PredicateStats::default(),
+    };
+
+    let mut triple_count = 0;
+    let mut objects = Vec::new();
+    for i in 0..lookup.len() {
+        let position = lookup.entry(i);
+        let start = sp_o_adjacency_list.offset_for(position + 1);
+        let end = sp_o_adjacency_list.offset_for(position + 2);
+        triple_count += (end - start) as usize;
+        objects.extend((start..end).map(|pos| sp_o_adjacency_list.num_at_pos(pos)));
+    }
+    objects.sort_unstable();
+    objects.dedup();
+
+    PredicateStats {
+        triple_count,
+        subject_count: lookup.len(),
+        object_count: objects.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::open_memory_store;
+    use std::collections::HashSet;
+
+    fn exact_stats(layer: &dyn Layer, predicate: u64) -> PredicateStats {
+        let triples: Vec<_> = layer.triples_p(predicate).collect();
+        let subjects: HashSet<_> = triples.iter().map(|t| t.subject).collect();
+        let objects: HashSet<_> = triples.iter().map(|t| t.object).collect();
+
+        PredicateStats {
+            triple_count: triples.len(),
+            subject_count: subjects.len(),
+            object_count: objects.len(),
+        }
+    }
+
+    #[tokio::test]
+    async fn stored_stats_match_triples() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        for i in 0..10 {
+            let subject = format!("node{}", i);
+            builder
+                .add_string_triple(StringTriple::new_node(&subject, "likes", "node0"))
+                .unwrap();
+            builder
+                .add_string_triple(StringTriple::new_node(&subject, "likes", "node1"))
+                .unwrap();
+            builder
+                .add_string_triple(StringTriple::new_value(&subject, "name", &subject))
+                .unwrap();
+        }
+        let base = builder.commit().await.unwrap();
+
+        let likes = base.predicate_id("likes").unwrap();
+        let name = base.predicate_id("name").unwrap();
+        for predicate in [likes, name] {
+            assert_eq!(
+                exact_stats(&base, predicate),
+                base.predicate_stats(predicate)
+            );
+        }
+        assert_eq!(
+            PredicateStats {
+                triple_count: 20,
+                subject_count: 10,
+                object_count: 2
+            },
+            base.predicate_stats(likes)
+        );
+
+        let builder = base.open_write().await.unwrap();
+        for i in 0..5 {
+            let subject = format!("node{}", i);
+            builder
+                .remove_string_triple(StringTriple::new_node(&subject, "likes", "node1"))
+                .unwrap();
+        }
+        builder
+            .add_string_triple(StringTriple::new_node("node10", "likes", "node2"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("node10", "knows", "node2"))
+            .unwrap();
+        let child = builder.commit().await.unwrap();
+
+        let knows = child.predicate_id("knows").unwrap();
+        assert_eq!(exact_stats(&child, knows), child.predicate_stats(knows));
+        assert_eq!(exact_stats(&child, name), child.predicate_stats(name));
+        let stats = child.predicate_stats(likes);
+        assert_eq!(16, stats.triple_count);
+        // distinct counts are estimates once triples have been removed
+        assert!((6..=11).contains(&stats.subject_count));
+        assert!((1..=3).contains(&stats.object_count));
+        assert_eq!(PredicateStats::default(), child.predicate_stats(12345));
+    }
+
+    #[tokio::test]
+    async fn computed_stats_match_stored_stats() {
+        let layer = crate::layer::internal::base::tests::example_base_layer().await;
+        let stored = layer.stored_predicate_stats().cloned().unwrap();
+        for predicate in 0..=layer.predicate_count() as u64 + 1 {
+            assert_eq!(
+                stored.additions.get(predicate),
+                predicate_stats_from_index(
+                    layer.pos_sp_o_adjacency_list(),
+                    layer.pos_predicate_wavelet_tree(),
+                    predicate
+                )
+            );
+        }
+
+        assert_eq!(
+            stored,
+            LayerPredicateStats::from_bytes(&stored.to_bytes()).unwrap()
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! Uniform random sampling of triples.
+//!
+//! Every triple addition in a layer stack has a position in the sp_o
+//! adjacency list of the layer that added it. Positions are drawn
+//! uniformly over all layers, and turned into triples using the ranks
+//! of the bit indexes of the adjacency lists, so no triples have to be
+//! iterated over. Positions that don't hold a triple of the whole
+//! stack are rejected and drawn again. These are the placeholders for
+//! gaps in base layers, triples that were removed by a later layer,
+//! and triples that were added again by a later layer, which only
+//! count at that later layer. This leaves exactly one position for
+//! every triple. When the sample is a large part of all triples,
+//! rejections get frequent, so all triples are scanned once instead.
+use std::collections::HashSet;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::*;
+
+impl InternalLayer {
+    /// The triple added at the given position in the sp_o adjacency list of this layer, or None for a placeholder.
+    fn triple_addition_at(&self, position: u64) -> Option<IdTriple> {
+        let (pair, object) = self.pos_sp_o_adjacency_list().pair_at_pos(position);
+        let (subject_index, predicate) = self.pos_s_p_adjacency_list().pair_at_pos(pair - 1);
+        if predicate == 0 || object == 0 {
+            return None;
+        }
+        let subject = match self.pos_subjects() {
+            None => subject_index,
+            Some(subjects) => subjects.entry(subject_index as usize - 1),
+        };
+
+        Some(IdTriple::new(subject, predicate, object))
+    }
+
+    pub(crate) fn sample_layer_stack_triples(&self, n: usize, seed: u64) -> Vec<IdTriple> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        if n.saturating_mul(2) >= self.triple_count() {
+            let mut sample = reservoir_sample(self.triples(), n, &mut rng);
+            sample.sort_unstable();
+
+            return sample;
+        }
+
+        let layers = self.immediate_layers();
+        let mut ends = Vec::with_capacity(layers.len());
+        let mut total = 0;
+        for layer in layers.iter() {
+            total += layer.pos_sp_o_adjacency_list().right_count() as u64;
+            ends.push(total);
+        }
+
+        let mut drawn = HashSet::new();
+        let mut sample = Vec::with_capacity(n);
+        while sample.len() < n {
+            let position = rng.gen_range(0..total);
+            if !drawn.insert(position) {
+                continue;
+            }
+            let index = ends.partition_point(|&end| end <= position);
+            let start = if index == 0 { 0 } else { ends[index - 1] };
+            let triple = match layers[index].triple_addition_at(position - start) {
+                Some(triple) => triple,
+                None => continue,
+            };
+            let added_later = layers[index + 1..].iter().any(|layer| {
+                layer.internal_triple_addition_exists(
+                    triple.subject,
+                    triple.predicate,
+                    triple.object,
+                )
+            });
+            if !added_later && self.id_triple_exists(triple) {
+                sample.push(triple);
+            }
+        }
+        sample.sort_unstable();
+
+        sample
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layer::*;
+    use crate::store::open_memory_store;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn sample_triples_uniformly() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        for i in 0..40 {
+            builder
+                .add_string_triple(StringTriple::new_node(
+                    &format!("node{}", i),
+                    "next",
+                    &format!("node{}", i + 1),
+                ))
+                .unwrap();
+        }
+        let base = builder.commit().await.unwrap();
+        let builder = base.open_write().await.unwrap();
+        for i in 0..20 {
+            builder
+                .remove_string_triple(StringTriple::new_node(
+                    &format!("node{}", i),
+                    "next",
+                    &format!("node{}", i + 1),
+                ))
+                .unwrap();
+        }
+        let child = builder.commit().await.unwrap();
+        let builder = child.open_write().await.unwrap();
+        for i in 10..15 {
+            builder
+                .add_string_triple(StringTriple::new_node(
+                    &format!("node{}", i),
+                    "next",
+                    &format!("node{}", i + 1),
+                ))
+                .unwrap();
+        }
+        builder
+            .add_string_triple(StringTriple::new_value("node40", "name", "last"))
+            .unwrap();
+        let layer = builder.commit().await.unwrap();
+        assert_eq!(26, layer.triple_count());
+
+        let sample = layer.sample_triples(5, 42);
+        assert_eq!(sample, layer.sample_triples(5, 42));
+        assert_eq!(5, sample.len());
+        assert!(sample.windows(2).all(|w| w[0] < w[1]));
+        assert!(sample.iter().all(|t| layer.id_triple_exists(*t)));
+        let all: Vec<_> = layer.triples().collect();
+        assert_eq!(all, layer.sample_triples(100, 42));
+        let half = layer.sample_triples(13, 42);
+        assert_eq!(13, half.len());
+        assert!(half.iter().all(|t| layer.id_triple_exists(*t)));
+
+        let mut counts = HashMap::new();
+        for seed in 0..2600 {
+            for triple in layer.sample_triples(1, seed) {
+                *counts.entry(triple).or_insert(0) += 1;
+            }
+        }
+        assert_eq!(26, counts.len());
+        assert!(counts.values().all(|&count| (50..150).contains(&count)));
+
+        // staging layers scan their triples instead
+        let staging = StagingLayer::new(layer.clone_boxed().into());
+        let sample = staging.sample_triples(5, 42);
+        assert_eq!(5, sample.len());
+        assert!(sample.iter().all(|t| layer.id_triple_exists(*t)));
+    }
+}
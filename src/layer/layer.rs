@@ -1,6 +1,18 @@
 //! Common data structures and traits for all layer types.
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::hash::Hash;
+use std::ops::Bound;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+
+use super::cooccurrence::PredicateCooccurrence;
+use super::csr::CsrGraph;
+use super::filter::Regex;
+use super::typed::*;
 
 /// A layer containing dictionary entries and triples.
 ///
@@ -27,6 +39,115 @@ pub trait Layer: Send + Sync {
     fn object_node_id(&self, object: &str) -> Option<u64>;
     /// The numerical id of a value object, or None if the value object cannot be found.
     fn object_value_id(&self, object: &str) -> Option<u64>;
+
+    /// Whether the given subject is known to this layer or its ancestors.
+    ///
+    /// This only consults the dictionaries, so it is cheaper than
+    /// `subject_id`. Note that a string remains known after all triples
+    /// using it have been removed.
+    fn subject_exists(&self, subject: &str) -> bool {
+        self.subject_id(subject).is_some()
+    }
+
+    /// Whether the given predicate is known to this layer or its ancestors.
+    ///
+    /// See `subject_exists`.
+    fn predicate_exists(&self, predicate: &str) -> bool {
+        self.predicate_id(predicate).is_some()
+    }
+
+    /// Whether the given node object is known to this layer or its ancestors.
+    ///
+    /// See `subject_exists`.
+    fn object_node_exists(&self, object: &str) -> bool {
+        self.object_node_id(object).is_some()
+    }
+
+    /// Whether the given value object is known to this layer or its ancestors.
+    ///
+    /// See `subject_exists`.
+    fn object_value_exists(&self, object: &str) -> bool {
+        self.object_value_id(object).is_some()
+    }
+
+    /// The ids of all values within the given bounds, ordered by id.
+    ///
+    /// Values are compared by their bytes, which makes this a range
+    /// scan over the value dictionaries of this layer and its
+    /// ancestors.
+    fn value_ids_in_range(&self, start: Bound<&str>, end: Bound<&str>) -> Vec<u64>;
+
+    /// The ids of all strings with the given language tag, ordered by id.
+    fn lang_string_ids(&self, lang: &str) -> Vec<u64> {
+        let (start, end) = encoded_lang_range(lang);
+
+        self.value_ids_in_range(
+            start.as_ref().map(String::as_str),
+            end.as_ref().map(String::as_str),
+        )
+    }
+
+    /// The ids of all typed values of the same type within the given bounds, ordered by id.
+    ///
+    /// An unbounded end covers all values of the type of the other
+    /// end. If the bounds are of different types, or both are
+    /// unbounded, no ids are returned.
+    fn typed_value_ids_in_range(
+        &self,
+        start: Bound<&TypedValue>,
+        end: Bound<&TypedValue>,
+    ) -> Vec<u64> {
+        match encoded_typed_range(start, end) {
+            Some((start, end)) => self.value_ids_in_range(
+                start.as_ref().map(String::as_str),
+                end.as_ref().map(String::as_str),
+            ),
+            None => Vec::new(),
+        }
+    }
+
+    /// Iterator over all triples with the given predicate and a typed value within the given bounds as their object.
+    ///
+    /// The bounds are interpreted as in `typed_value_ids_in_range`.
+    /// The matching values are looked up in the value dictionaries,
+    /// and their triples in the object index, so values outside the
+    /// bounds are never looked at. Triples are ordered by object id,
+    /// then by subject id.
+    fn objects_in_range(
+        &self,
+        predicate: u64,
+        start: Bound<&TypedValue>,
+        end: Bound<&TypedValue>,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        let layer = self.clone_boxed();
+        Box::new(
+            self.typed_value_ids_in_range(start, end)
+                .into_iter()
+                .flat_map(move |object| layer.triples_o(object))
+                .filter(move |triple| triple.predicate == predicate),
+        )
+    }
+
+    /// The ids of all nodes matching the given regular expression, ordered by id.
+    ///
+    /// The expression is matched against the dictionary entries of
+    /// this layer and its ancestors as they are decoded, without
+    /// resolving them to strings first. Note that, just like in
+    /// `subject_exists`, nodes that are no longer used by any triple
+    /// are still matched.
+    fn node_ids_matching(&self, regex: &Regex) -> Vec<u64>;
+
+    /// The ids of all predicates matching the given regular expression, ordered by id.
+    ///
+    /// See `node_ids_matching`.
+    fn predicate_ids_matching(&self, regex: &Regex) -> Vec<u64>;
+
+    /// The ids of all string values whose text matches the given regular expression, ordered by id.
+    ///
+    /// For strings with a language tag, only the text is matched.
+    /// Typed values are never matched. See `node_ids_matching`.
+    fn value_ids_matching(&self, regex: &Regex) -> Vec<u64>;
+
     /// The subject corresponding to a numerical id, or None if it cannot be found.
     fn id_subject(&self, id: u64) -> Option<String>;
     /// The predicate corresponding to a numerical id, or None if it cannot be found.
@@ -56,19 +177,52 @@ pub trait Layer: Send + Sync {
     }
 
     /// Iterator over all triples known to this layer.
+    ///
+    /// Triples are returned in SPO order, that is, ordered by
+    /// subject, then predicate, then object id. This is also the
+    /// order of `IdTriple`'s `Ord` implementation. The same goes for
+    /// the other iterators over triples with a given subject.
     fn triples(&self) -> Box<dyn Iterator<Item = IdTriple> + Send>;
 
+    /// Iterator over all triples with the given subject, in SPO order.
     fn triples_s(&self, subject: u64) -> Box<dyn Iterator<Item = IdTriple> + Send>;
+    /// Iterator over all triples with a subject from `start` up to but not including `end`, in SPO order.
+    fn triples_subject_range(
+        &self,
+        start: u64,
+        end: u64,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send>;
+    /// Iterator over all triples with the given subject and predicate, in SPO order.
     fn triples_sp(&self, subject: u64, predicate: u64)
         -> Box<dyn Iterator<Item = IdTriple> + Send>;
 
+    /// Iterator over all triples known to this layer, in POS order.
+    ///
+    /// Triples are ordered by predicate, then object, then subject
+    /// id. The triples of each predicate are looked up through the
+    /// predicate index, and sorted in memory one predicate at a
+    /// time, so memory use is bounded by the amount of triples of
+    /// the largest predicate.
+    fn triples_pos(&self) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        let predicates: Vec<_> = (1..=self.predicate_count() as u64)
+            .map(|predicate| self.triples_p(predicate))
+            .collect();
+        Box::new(predicates.into_iter().flat_map(|triples| {
+            let mut triples: Vec<_> = triples.collect();
+            triples.sort_unstable_by_key(|t| (t.object, t.subject));
+            triples
+        }))
+    }
+
     /// Convert a `StringTriple` to an `IdTriple`, returning None if any of the strings in the triple could not be resolved.
     fn string_triple_to_id(&self, triple: &StringTriple) -> Option<IdTriple> {
         self.subject_id(&triple.subject).and_then(|subject| {
             self.predicate_id(&triple.predicate).and_then(|predicate| {
                 match &triple.object {
                     ObjectType::Node(node) => self.object_node_id(&node),
-                    ObjectType::Value(value) => self.object_value_id(&value),
+                    object => object
+                        .value_string()
+                        .and_then(|value| self.object_value_id(&value)),
                 }
                 .map(|object| IdTriple {
                     subject,
@@ -79,10 +233,186 @@ pub trait Layer: Send + Sync {
         })
     }
 
+    /// Iterator over all triples with the given predicate, ordered by subject, then object id.
     fn triples_p(&self, predicate: u64) -> Box<dyn Iterator<Item = IdTriple> + Send>;
 
+    /// Iterator over all triples with the given object, ordered by subject, then predicate id.
     fn triples_o(&self, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send>;
 
+    /// Iterator over all triples matching the given pattern, where None matches anything.
+    ///
+    /// The most suitable index is picked for each combination of
+    /// wildcards.
+    fn triples_matching(
+        &self,
+        subject: Option<u64>,
+        predicate: Option<u64>,
+        object: Option<u64>,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        match (subject, predicate, object) {
+            (Some(subject), Some(predicate), Some(object)) => {
+                if self.triple_exists(subject, predicate, object) {
+                    Box::new(std::iter::once(IdTriple::new(subject, predicate, object)))
+                } else {
+                    Box::new(std::iter::empty())
+                }
+            }
+            (Some(subject), Some(predicate), None) => self.triples_sp(subject, predicate),
+            (Some(subject), None, Some(object)) => {
+                Box::new(self.triples_s(subject).filter(move |t| t.object == object))
+            }
+            (Some(subject), None, None) => self.triples_s(subject),
+            (None, Some(predicate), Some(object)) => Box::new(
+                self.triples_o(object)
+                    .filter(move |t| t.predicate == predicate),
+            ),
+            (None, Some(predicate), None) => self.triples_p(predicate),
+            (None, None, Some(object)) => self.triples_o(object),
+            (None, None, None) => self.triples(),
+        }
+    }
+
+    /// An estimate of the amount of triples matching the given pattern, where None matches anything.
+    ///
+    /// This does not iterate over any triples, making it cheap enough
+    /// to use for picking a join order. Fully bound and fully unbound
+    /// patterns are counted exactly, as are patterns with only a
+    /// subject, a subject and a predicate, or only an object. Other
+    /// patterns are approximated from per-predicate statistics and
+    /// index ranks.
+    fn estimate_count(
+        &self,
+        subject: Option<u64>,
+        predicate: Option<u64>,
+        object: Option<u64>,
+    ) -> usize;
+
+    /// Statistics about the triples with the given predicate, taking all ancestors into account.
+    ///
+    /// The triple count is exact. The subject and object counts are
+    /// exact for a layer without ancestors, and estimated from the
+    /// counts of each layer otherwise.
+    fn predicate_stats(&self, predicate: u64) -> PredicateStats;
+
+    /// The exact amount of triples matching the given pattern, where None matches anything.
+    ///
+    /// Patterns that `estimate_count` counts exactly, as well as
+    /// patterns with only a predicate, are counted from the index
+    /// structures without iterating over any triples. Other patterns
+    /// are counted by iterating over their matches.
+    fn count_matching(
+        &self,
+        subject: Option<u64>,
+        predicate: Option<u64>,
+        object: Option<u64>,
+    ) -> usize {
+        match (subject, predicate, object) {
+            (None, Some(predicate), None) => self.predicate_stats(predicate).triple_count,
+            (Some(_), None, Some(_)) | (None, Some(_), Some(_)) => {
+                self.triples_matching(subject, predicate, object).count()
+            }
+            _ => self.estimate_count(subject, predicate, object),
+        }
+    }
+
+    /// The amount of triples matching the given subject and object for each predicate, where None matches anything.
+    ///
+    /// Predicates without any matching triples are left out. Without
+    /// a subject or object, this comes straight from the per-predicate
+    /// statistics, without iterating over any triples. Otherwise, only
+    /// the triples of the given subject or object are iterated over.
+    fn count_by_predicate(
+        &self,
+        subject: Option<u64>,
+        object: Option<u64>,
+    ) -> BTreeMap<u64, usize> {
+        let mut counts = BTreeMap::new();
+        match (subject, object) {
+            (None, None) => {
+                for predicate in 1..=self.predicate_count() as u64 {
+                    let count = self.predicate_stats(predicate).triple_count;
+                    if count != 0 {
+                        counts.insert(predicate, count);
+                    }
+                }
+            }
+            _ => {
+                for triple in self.triples_matching(subject, None, object) {
+                    *counts.entry(triple.predicate).or_default() += 1;
+                }
+            }
+        }
+
+        counts
+    }
+
+    /// For every pair of predicates, the amount of subjects using both.
+    ///
+    /// This iterates over all triples once, keeping only the
+    /// predicates of one subject at a time besides the result. See
+    /// `PredicateCooccurrence` for details.
+    fn predicate_cooccurrence(&self) -> PredicateCooccurrence {
+        PredicateCooccurrence::from_triples(self.triples())
+    }
+
+    /// The triples with one of the given predicates, or all triples if there are none given, as a compressed sparse row adjacency matrix.
+    ///
+    /// See `CsrGraph` for how vertices are numbered.
+    fn csr_graph(&self, predicates: Option<&[u64]>) -> CsrGraph {
+        CsrGraph::from_layer(self, predicates)
+    }
+
+    /// A uniform random sample of n distinct triples, or all triples if there are no more than n.
+    ///
+    /// The sample is ordered, and the same seed gives the same sample
+    /// for the same layer. Stored layers draw positions in their
+    /// indexes instead of iterating over their triples, unless the
+    /// sample is a large part of all triples. Other layers scan all
+    /// their triples once.
+    fn sample_triples(&self, n: usize, seed: u64) -> Vec<IdTriple> {
+        let mut sample = reservoir_sample(self.triples(), n, &mut StdRng::seed_from_u64(seed));
+        sample.sort_unstable();
+
+        sample
+    }
+
+    /// The ids of all objects reachable from the given subject by following the given predicate one or more times.
+    ///
+    /// This is the transitive closure of the predicate, starting at
+    /// the subject. The subject itself is only included if it can be
+    /// reached through a cycle.
+    fn reachable_from(&self, subject: u64, predicate: u64) -> HashSet<u64> {
+        self.reachable_from_within(subject, predicate, usize::MAX)
+    }
+
+    /// The ids of all objects reachable from the given subject by following the given predicate at least once and at most `max_depth` times.
+    ///
+    /// See `reachable_from`.
+    fn reachable_from_within(
+        &self,
+        subject: u64,
+        predicate: u64,
+        max_depth: usize,
+    ) -> HashSet<u64> {
+        let mut reached = HashSet::new();
+        let mut frontier = vec![subject];
+        let mut depth = 0;
+        while !frontier.is_empty() && depth < max_depth {
+            let mut next = Vec::new();
+            for node in frontier {
+                for triple in self.triples_sp(node, predicate) {
+                    if reached.insert(triple.object) {
+                        next.push(triple.object);
+                    }
+                }
+            }
+            frontier = next;
+            depth += 1;
+        }
+
+        reached
+    }
+
     /// Convert all known strings in the given string triple to ids.
     fn string_triple_to_partially_resolved(&self, triple: StringTriple) -> PartiallyResolvedTriple {
         PartiallyResolvedTriple {
@@ -99,8 +429,9 @@ pub trait Layer: Send + Sync {
                     .object_node_id(&node)
                     .map(PossiblyResolved::Resolved)
                     .unwrap_or(PossiblyResolved::Unresolved(triple.object)),
-                ObjectType::Value(value) => self
-                    .object_value_id(&value)
+                object => object
+                    .value_string()
+                    .and_then(|value| self.object_value_id(&value))
                     .map(PossiblyResolved::Resolved)
                     .unwrap_or(PossiblyResolved::Unresolved(triple.object)),
             },
@@ -120,6 +451,91 @@ pub trait Layer: Send + Sync {
         })
     }
 
+    /// The counts of the given layer, if it is this layer or one of its ancestors.
+    fn ancestor_counts(&self, name: [u32; 5]) -> Option<LayerCounts>;
+
+    /// The counts of the nearest layer that both this layer and the other layer descend from, if any.
+    fn common_ancestor_counts(&self, other: &dyn Layer) -> Option<LayerCounts>;
+
+    /// Translate a subject id of this layer into the id of the same subject in the other layer.
+    ///
+    /// Ids that were assigned in a common ancestor of both layers are
+    /// the same in both layers, and are translated without looking at
+    /// any strings. Other ids are translated by looking up their
+    /// string in the other layer. Returns None if the other layer
+    /// does not know about the subject.
+    fn translate_subject_id(&self, id: u64, other: &dyn Layer) -> Option<u64> {
+        if id == 0 {
+            return None;
+        }
+        if let Some(counts) = self.common_ancestor_counts(other) {
+            if id <= (counts.node_count + counts.value_count) as u64 {
+                return Some(id);
+            }
+        }
+
+        other.subject_id(&self.id_subject(id)?)
+    }
+
+    /// Translate a predicate id of this layer into the id of the same predicate in the other layer.
+    ///
+    /// See `translate_subject_id`.
+    fn translate_predicate_id(&self, id: u64, other: &dyn Layer) -> Option<u64> {
+        if id == 0 {
+            return None;
+        }
+        if let Some(counts) = self.common_ancestor_counts(other) {
+            if id <= counts.predicate_count as u64 {
+                return Some(id);
+            }
+        }
+
+        other.predicate_id(&self.id_predicate(id)?)
+    }
+
+    /// Translate an object id of this layer into the id of the same object in the other layer.
+    ///
+    /// See `translate_subject_id`.
+    fn translate_object_id(&self, id: u64, other: &dyn Layer) -> Option<u64> {
+        if id == 0 {
+            return None;
+        }
+        if let Some(counts) = self.common_ancestor_counts(other) {
+            if id <= (counts.node_count + counts.value_count) as u64 {
+                return Some(id);
+            }
+        }
+
+        match self.id_object(id)? {
+            ObjectType::Node(node) => other.object_node_id(&node),
+            value => other.object_value_id(&value.value_string()?),
+        }
+    }
+
+    /// A SHA-256 digest of all triples in this layer, taking all ancestors into account.
+    ///
+    /// The digest only depends on the strings of the triples, not on
+    /// their ids or on how they are spread over layers. Two layers
+    /// with the same triples therefore have the same digest, even if
+    /// they were built in a different way. Named graph quads are not
+    /// included. The triples are added to a `TripleHashSum` while they
+    /// are traversed, so computing the digest takes a fixed amount of
+    /// memory, regardless of the size of the layer.
+    fn content_digest(&self) -> [u8; 32] {
+        self.triples()
+            .par_bridge()
+            .fold(TripleHashSum::default, |mut sum, triple| {
+                let triple = self
+                    .id_triple_to_string(&triple)
+                    .expect("layer triple should resolve to strings");
+                sum.add(triple);
+
+                sum
+            })
+            .reduce(TripleHashSum::default, TripleHashSum::combine)
+            .finish()
+    }
+
     /// Returns the total amount of triple additions in this layer and all its parents.
     fn triple_addition_count(&self) -> usize;
 
@@ -130,16 +546,271 @@ pub trait Layer: Send + Sync {
     fn triple_count(&self) -> usize {
         self.triple_addition_count() - self.triple_removal_count()
     }
+
+    /// Returns true if every triple of this layer also exists in the other layer.
+    ///
+    /// Like `content_digest`, this compares the strings of the
+    /// triples, so the layers don't need to share any
+    /// history. Comparison stops at the first triple that is missing
+    /// from the other layer. If all ids of this layer were assigned
+    /// by a common ancestor, the ids are the same in both layers, and
+    /// the triples of both layers are traversed together in SPO
+    /// order. Otherwise, each triple is translated to the ids of the
+    /// other layer and looked up.
+    fn is_subset_of(&self, other: &dyn Layer) -> bool {
+        if self.triple_count() > other.triple_count() {
+            return false;
+        }
+
+        let counts = self.all_counts();
+        let shared_ids = self
+            .common_ancestor_counts(other)
+            .map(|common| {
+                common.node_count + common.value_count == counts.node_count + counts.value_count
+                    && common.predicate_count == counts.predicate_count
+            })
+            .unwrap_or(false);
+
+        if shared_ids {
+            let mut theirs = other.triples().peekable();
+            self.triples().all(|triple| {
+                while let Some(other_triple) = theirs.next_if(|t| *t <= triple) {
+                    if other_triple == triple {
+                        return true;
+                    }
+                }
+
+                false
+            })
+        } else {
+            self.triples().all(|triple| {
+                match (
+                    self.translate_subject_id(triple.subject, other),
+                    self.translate_predicate_id(triple.predicate, other),
+                    self.translate_object_id(triple.object, other),
+                ) {
+                    (Some(subject), Some(predicate), Some(object)) => {
+                        other.triple_exists(subject, predicate, object)
+                    }
+                    _ => false,
+                }
+            })
+        }
+    }
+
+    /// Returns true if this layer and the other layer contain the same triples.
+    ///
+    /// See `is_subset_of`.
+    fn same_content(&self, other: &dyn Layer) -> bool {
+        self.triple_count() == other.triple_count() && self.is_subset_of(other)
+    }
+}
+
+/// Feed a tagged string triple into a hasher.
+pub(crate) fn hash_string_triple(hasher: &mut Sha256, tag: u8, triple: StringTriple) {
+    let (object_tag, object) = match triple.object {
+        ObjectType::Node(node) => (0, node),
+        object => (1, object.value_string().unwrap().into_owned()),
+    };
+    hasher.update([tag, object_tag]);
+    for s in [triple.subject, triple.predicate, object].iter() {
+        hasher.update((s.len() as u64).to_be_bytes());
+        hasher.update(s.as_bytes());
+    }
+}
+
+/// The amount of 16-bit lanes in a `TripleHashSum`.
+const TRIPLE_HASH_SUM_LANES: usize = 1024;
+
+/// An order-independent sum of triple hashes, as used by `Layer::content_digest`.
+///
+/// This is an LtHash: every triple is hashed into a vector of 16-bit
+/// lanes, and the vectors of all triples are added lane by lane,
+/// wrapping around on overflow. Unlike a plain sum of hashes, finding
+/// two different sets of triples with the same sum is as hard as
+/// solving a lattice problem.
+#[derive(Clone)]
+pub(crate) struct TripleHashSum {
+    count: u64,
+    lanes: Vec<u16>,
+}
+
+impl Default for TripleHashSum {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            lanes: vec![0; TRIPLE_HASH_SUM_LANES],
+        }
+    }
+}
+
+impl TripleHashSum {
+    /// Add a triple to the sum.
+    pub(crate) fn add(&mut self, triple: StringTriple) {
+        let mut hasher = Sha256::new();
+        hash_string_triple(&mut hasher, 0, triple);
+        let seed = hasher.finalize();
+
+        // every block of lanes is filled by hashing the triple hash
+        // together with the block number.
+        for (block, lanes) in self.lanes.chunks_mut(16).enumerate() {
+            let mut hasher = Sha256::new();
+            hasher.update(seed);
+            hasher.update((block as u32).to_be_bytes());
+            let hash = hasher.finalize();
+            for (lane, bytes) in lanes.iter_mut().zip(hash.chunks(2)) {
+                *lane = lane.wrapping_add(u16::from_be_bytes([bytes[0], bytes[1]]));
+            }
+        }
+        self.count += 1;
+    }
+
+    /// The sum of the triples of both sums.
+    pub(crate) fn combine(mut self, other: Self) -> Self {
+        for (lane, other) in self.lanes.iter_mut().zip(other.lanes) {
+            *lane = lane.wrapping_add(other);
+        }
+        self.count += other.count;
+
+        self
+    }
+
+    /// A SHA-256 hash of the amount of triples and their sum.
+    pub(crate) fn finish(self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.count.to_be_bytes());
+        for lane in self.lanes {
+            hasher.update(lane.to_be_bytes());
+        }
+
+        hasher.finalize().into()
+    }
+}
+
+/// Pick n of the triples, each with the same probability, in a single pass.
+pub(crate) fn reservoir_sample(
+    triples: impl Iterator<Item = IdTriple>,
+    n: usize,
+    rng: &mut impl Rng,
+) -> Vec<IdTriple> {
+    let mut sample = Vec::with_capacity(n);
+    for (seen, triple) in triples.enumerate() {
+        if seen < n {
+            sample.push(triple);
+        } else {
+            let index = rng.gen_range(0..=seen);
+            if index < n {
+                sample[index] = triple;
+            }
+        }
+    }
+
+    sample
 }
 
+/// The amount of subject ranges that are scanned per thread by `par_triples`.
+///
+/// Subjects aren't spread evenly over the id space, so there are
+/// more ranges than threads, allowing idle threads to steal work.
+const SUBJECT_RANGES_PER_THREAD: u64 = 8;
+
+/// Parallel iteration over all triples of a layer.
+///
+/// This is implemented for every layer.
+pub trait ParallelTriples: Layer {
+    /// Parallel iterator over all triples known to this layer.
+    ///
+    /// The subject id space is split into ranges, which are scanned
+    /// on the rayon thread pool. For stored layers, collecting this
+    /// iterator into a `Vec` gives the same order as `triples`.
+    fn par_triples(&self) -> impl ParallelIterator<Item = IdTriple> + '_ {
+        let count = self.node_and_value_count() as u64;
+        let ranges = (rayon::current_num_threads() as u64 * SUBJECT_RANGES_PER_THREAD)
+            .clamp(1, count.max(1));
+        (0..ranges).into_par_iter().flat_map_iter(move |range| {
+            let start = 1 + range * count / ranges;
+            let end = 1 + (range + 1) * count / ranges;
+            self.triples_subject_range(start, end)
+        })
+    }
+}
+
+impl<L: Layer + ?Sized> ParallelTriples for L {}
+
+/// Counts of the contents of a layer.
+///
+/// Unless noted otherwise, these include all ancestors of the
+/// layer. They are computed when a layer is loaded, so retrieving
+/// them is cheap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LayerCounts {
     pub node_count: usize,
     pub predicate_count: usize,
     pub value_count: usize,
+    pub triple_addition_count: usize,
+    pub triple_removal_count: usize,
+    /// The amount of triples added by this layer alone.
+    pub layer_triple_addition_count: usize,
+    /// The amount of triples removed by this layer alone.
+    pub layer_triple_removal_count: usize,
+}
+
+impl LayerCounts {
+    /// The amount of triples in the layer, taking all removals into account.
+    pub fn triple_count(&self) -> usize {
+        self.triple_addition_count - self.triple_removal_count
+    }
+}
+
+/// Statistics about the triples with a particular predicate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PredicateStats {
+    /// The amount of triples with the predicate.
+    pub triple_count: usize,
+    /// The amount of distinct subjects of these triples.
+    pub subject_count: usize,
+    /// The amount of distinct objects of these triples.
+    pub object_count: usize,
+}
+
+impl PredicateStats {
+    /// Take removed triples into account.
+    ///
+    /// The triple count stays exact, but it is not known which
+    /// subjects and objects are gone entirely, so their counts are
+    /// assumed to shrink in proportion to the triple count.
+    pub(crate) fn subtract_removals(self, removals: PredicateStats) -> PredicateStats {
+        let triple_count = self.triple_count.saturating_sub(removals.triple_count);
+        if removals.triple_count == 0 {
+            return self;
+        } else if triple_count == 0 {
+            return PredicateStats::default();
+        }
+        let estimate = |count: usize| {
+            ((count as u128 * triple_count as u128 / self.triple_count as u128) as usize).max(1)
+        };
+
+        PredicateStats {
+            triple_count,
+            subject_count: estimate(self.subject_count),
+            object_count: estimate(self.object_count),
+        }
+    }
+}
+
+impl std::ops::AddAssign for PredicateStats {
+    fn add_assign(&mut self, other: PredicateStats) {
+        self.triple_count += other.triple_count;
+        self.subject_count += other.subject_count;
+        self.object_count += other.object_count;
+    }
 }
 
 /// A triple, stored as numerical ids.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct IdTriple {
     pub subject: u64,
     pub predicate: u64,
@@ -168,6 +839,7 @@ impl IdTriple {
 
 /// A triple stored as strings.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StringTriple {
     pub subject: String,
     pub predicate: String,
@@ -197,6 +869,38 @@ impl StringTriple {
         }
     }
 
+    /// Construct a triple with a typed value object.
+    pub fn new_typed(subject: &str, predicate: &str, object: TypedValue) -> StringTriple {
+        StringTriple {
+            subject: subject.to_owned(),
+            predicate: predicate.to_owned(),
+            object: object.into(),
+        }
+    }
+
+    /// Construct a triple with a literal value object.
+    pub fn new_literal<V: Into<Value>>(subject: &str, predicate: &str, object: V) -> StringTriple {
+        StringTriple {
+            subject: subject.to_owned(),
+            predicate: predicate.to_owned(),
+            object: object.into().into(),
+        }
+    }
+
+    /// Construct a triple with a language-tagged string object.
+    pub fn new_lang_string(
+        subject: &str,
+        predicate: &str,
+        object: &str,
+        lang: &str,
+    ) -> StringTriple {
+        StringTriple {
+            subject: subject.to_owned(),
+            predicate: predicate.to_owned(),
+            object: ObjectType::new_lang_string(object, lang),
+        }
+    }
+
     /// Convert this triple to a `PartiallyResolvedTriple`, marking each field as unresolved.
     pub fn to_unresolved(self) -> PartiallyResolvedTriple {
         PartiallyResolvedTriple {
@@ -274,7 +978,9 @@ impl PartiallyResolvedTriple {
         };
         let object = match self.object.as_ref() {
             PossiblyResolved::Unresolved(ObjectType::Node(n)) => *node_map.get(n)?,
-            PossiblyResolved::Unresolved(ObjectType::Value(v)) => *value_map.get(v)?,
+            PossiblyResolved::Unresolved(object) => {
+                *value_map.get(object.value_string()?.as_ref())?
+            }
             PossiblyResolved::Resolved(id) => id,
         };
 
@@ -322,23 +1028,137 @@ impl PartiallyResolvedTriple {
 /// node or a value, and will return this information in queries. It
 /// is possible to have the same string appear both as a node and a
 /// value, without this leading to conflicts.
+///
+/// A value may carry a language tag, in which case it is a
+/// `LangString`. Language tags are expected to consist of ASCII
+/// letters, digits and dashes.
 #[derive(Debug, Clone, PartialOrd, PartialEq, Eq, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ObjectType {
     Node(String),
     Value(String),
+    LangString { value: String, lang: String },
+}
+
+impl ObjectType {
+    /// Construct a language-tagged string.
+    pub fn new_lang_string(value: &str, lang: &str) -> ObjectType {
+        ObjectType::LangString {
+            value: value.to_owned(),
+            lang: lang.to_owned(),
+        }
+    }
+
+    /// Decode this object as a typed value.
+    ///
+    /// Returns None if this is a node, a plain value or a language-tagged string.
+    pub fn typed_value(&self) -> Option<TypedValue> {
+        match self {
+            ObjectType::Value(v) => TypedValue::decode(v),
+            _ => None,
+        }
+    }
+
+    /// This object as a literal value.
+    ///
+    /// Returns None if this is a node.
+    pub fn value(&self) -> Option<Value> {
+        match self {
+            ObjectType::Node(_) => None,
+            ObjectType::Value(v) => Some(match TypedValue::decode(v) {
+                Some(typed) => typed.into(),
+                None => Value::String(v.clone()),
+            }),
+            ObjectType::LangString { value, lang } => Some(Value::lang_string(value, lang)),
+        }
+    }
+
+    /// The string under which this object is stored in the value dictionary.
+    ///
+    /// Returns None if this is a node.
+    pub(crate) fn value_string(&self) -> Option<Cow<'_, str>> {
+        match self {
+            ObjectType::Node(_) => None,
+            ObjectType::Value(value) => Some(Cow::Borrowed(value)),
+            ObjectType::LangString { value, lang } => {
+                Some(Cow::Owned(encode_lang_string(value, lang)))
+            }
+        }
+    }
+
+    /// Construct an object from a string stored in the value dictionary.
+    pub(crate) fn from_value_string(value: String) -> ObjectType {
+        match decode_lang_string(&value) {
+            Some((value, lang)) => ObjectType::new_lang_string(value, lang),
+            None => ObjectType::Value(value),
+        }
+    }
+}
+
+impl From<Value> for ObjectType {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::String(s) => ObjectType::Value(s),
+            Value::LangString { value, lang } => ObjectType::LangString { value, lang },
+            value => value
+                .typed_value()
+                .expect("non-string value should be typed")
+                .into(),
+        }
+    }
+}
+
+impl From<TypedValue> for ObjectType {
+    fn from(value: TypedValue) -> Self {
+        ObjectType::Value(value.encode())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::layer::filter::glob_regex;
     use crate::layer::internal::base::tests::base_layer_files;
     use crate::layer::internal::base::BaseLayer;
     use crate::layer::internal::child::tests::child_layer_files;
     use crate::layer::internal::child::ChildLayer;
     use crate::layer::internal::InternalLayer;
     use crate::layer::simple_builder::{LayerBuilder, SimpleLayerBuilder};
+    use crate::layer::staging::StagingLayer;
     use std::sync::Arc;
 
+    #[tokio::test]
+    async fn match_triple_patterns() {
+        let layer = crate::layer::internal::base::tests::example_base_layer().await;
+        let all: Vec<_> = layer.triples().collect();
+
+        let ids = [None, Some(1), Some(2), Some(3), Some(6), Some(10)];
+        for &subject in ids.iter() {
+            for &predicate in ids.iter() {
+                for &object in ids.iter() {
+                    let expected: Vec<_> = all
+                        .iter()
+                        .filter(|t| {
+                            subject.map(|s| s == t.subject).unwrap_or(true)
+                                && predicate.map(|p| p == t.predicate).unwrap_or(true)
+                                && object.map(|o| o == t.object).unwrap_or(true)
+                        })
+                        .cloned()
+                        .collect();
+                    let mut result: Vec<_> =
+                        layer.triples_matching(subject, predicate, object).collect();
+                    result.sort();
+
+                    assert_eq!(
+                        expected, result,
+                        "{:?} {:?} {:?}",
+                        subject, predicate, object
+                    );
+                }
+            }
+        }
+    }
+
     #[tokio::test]
     async fn find_triple_after_adjacent_removal() {
         let files = base_layer_files();
@@ -445,4 +1265,427 @@ mod tests {
 
         assert_eq!(vec![StringTriple::new_value("cow", "says", "moo")], triples);
     }
+
+    #[tokio::test]
+    async fn lang_strings_survive_roundtrip() {
+        let files = base_layer_files();
+        let mut builder = SimpleLayerBuilder::new([1, 2, 3, 4, 5], files.clone());
+        builder.add_string_triple(StringTriple::new_lang_string("cat", "label", "chat", "fr"));
+        builder.add_string_triple(StringTriple::new_lang_string("cat", "label", "cat", "en"));
+        builder.add_string_triple(StringTriple::new_value("cat", "label", "chat"));
+        builder.commit().await.unwrap();
+        let base: Arc<InternalLayer> = Arc::new(
+            BaseLayer::load_from_files([1, 2, 3, 4, 5], &files)
+                .await
+                .unwrap()
+                .into(),
+        );
+
+        let files = child_layer_files();
+        let mut builder =
+            SimpleLayerBuilder::from_parent([5, 4, 3, 2, 1], base.clone(), files.clone());
+        builder.add_string_triple(StringTriple::new_lang_string("dog", "label", "chien", "fr"));
+        builder.add_string_triple(StringTriple::new_lang_string(
+            "dog", "label", "dog", "en-GB",
+        ));
+        builder.commit().await.unwrap();
+        let child: Arc<InternalLayer> = Arc::new(
+            ChildLayer::load_from_files([5, 4, 3, 2, 1], base, &files)
+                .await
+                .unwrap()
+                .into(),
+        );
+
+        assert!(child
+            .string_triple_exists(&StringTriple::new_lang_string("cat", "label", "chat", "fr")));
+        assert!(child.string_triple_exists(&StringTriple::new_value("cat", "label", "chat")));
+        assert!(!child
+            .string_triple_exists(&StringTriple::new_lang_string("cat", "label", "chat", "en")));
+
+        let objects = |lang| {
+            let mut objects: Vec<_> = child
+                .lang_string_ids(lang)
+                .into_iter()
+                .map(|id| child.id_object(id).unwrap())
+                .collect();
+            objects.sort();
+            objects
+        };
+        assert_eq!(
+            vec![
+                ObjectType::new_lang_string("chat", "fr"),
+                ObjectType::new_lang_string("chien", "fr")
+            ],
+            objects("fr")
+        );
+        assert_eq!(
+            vec![ObjectType::new_lang_string("cat", "en")],
+            objects("en")
+        );
+        assert_eq!(
+            vec![ObjectType::new_lang_string("dog", "en-GB")],
+            objects("en-GB")
+        );
+        assert!(objects("e").is_empty());
+    }
+
+    #[tokio::test]
+    async fn content_digest_ignores_layer_structure() {
+        let triples = [
+            StringTriple::new_value("cow", "says", "moo"),
+            StringTriple::new_node("cow", "likes", "duck"),
+            StringTriple::new_value("duck", "says", "quack"),
+        ];
+
+        let files = base_layer_files();
+        let mut builder = SimpleLayerBuilder::new([1, 2, 3, 4, 5], files.clone());
+        for triple in triples.iter() {
+            builder.add_string_triple(triple.clone());
+        }
+        builder.commit().await.unwrap();
+        let single = BaseLayer::load_from_files([1, 2, 3, 4, 5], &files)
+            .await
+            .unwrap();
+
+        let files = base_layer_files();
+        let mut builder = SimpleLayerBuilder::new([1, 2, 3, 4, 6], files.clone());
+        builder.add_string_triple(triples[2].clone());
+        builder.add_string_triple(StringTriple::new_value("duck", "says", "duck"));
+        builder.commit().await.unwrap();
+        let base = Arc::new(
+            BaseLayer::load_from_files([1, 2, 3, 4, 6], &files)
+                .await
+                .unwrap(),
+        );
+        let files = child_layer_files();
+        let mut builder =
+            SimpleLayerBuilder::from_parent([6, 4, 3, 2, 1], base.clone(), files.clone());
+        builder.add_string_triple(triples[0].clone());
+        builder.add_string_triple(triples[1].clone());
+        builder.remove_string_triple(StringTriple::new_value("duck", "says", "duck"));
+        builder.commit().await.unwrap();
+        let child = ChildLayer::load_from_files([6, 4, 3, 2, 1], base.clone(), &files)
+            .await
+            .unwrap();
+
+        assert_eq!(single.content_digest(), child.content_digest());
+        assert_ne!(single.content_digest(), base.content_digest());
+
+        // a node and a value with the same string are different objects
+        let files = base_layer_files();
+        let mut builder = SimpleLayerBuilder::new([1, 2, 3, 4, 7], files.clone());
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        builder.add_string_triple(StringTriple::new_value("cow", "likes", "duck"));
+        builder.add_string_triple(StringTriple::new_value("duck", "says", "quack"));
+        builder.commit().await.unwrap();
+        let values = BaseLayer::load_from_files([1, 2, 3, 4, 7], &files)
+            .await
+            .unwrap();
+        assert_ne!(single.content_digest(), values.content_digest());
+    }
+
+    #[tokio::test]
+    async fn parallel_scan_matches_sequential_scan() {
+        let files = base_layer_files();
+        let mut builder = SimpleLayerBuilder::new([1, 2, 3, 4, 5], files.clone());
+        for i in 0..100 {
+            builder.add_string_triple(StringTriple::new_value(
+                &format!("node{}", i),
+                "says",
+                &format!("value{}", i % 7),
+            ));
+            builder.add_string_triple(StringTriple::new_node(
+                &format!("node{}", i),
+                "likes",
+                &format!("node{}", (i * 3) % 100),
+            ));
+        }
+        builder.commit().await.unwrap();
+        let base: Arc<InternalLayer> = Arc::new(
+            BaseLayer::load_from_files([1, 2, 3, 4, 5], &files)
+                .await
+                .unwrap()
+                .into(),
+        );
+
+        let files = child_layer_files();
+        let mut builder =
+            SimpleLayerBuilder::from_parent([5, 4, 3, 2, 1], base.clone(), files.clone());
+        for i in (0..100).step_by(3) {
+            builder.remove_string_triple(StringTriple::new_value(
+                &format!("node{}", i),
+                "says",
+                &format!("value{}", i % 7),
+            ));
+        }
+        builder.add_string_triple(StringTriple::new_value("node100", "says", "value100"));
+        builder.commit().await.unwrap();
+        let child: InternalLayer = ChildLayer::load_from_files([5, 4, 3, 2, 1], base, &files)
+            .await
+            .unwrap()
+            .into();
+
+        let sequential: Vec<_> = child.triples().collect();
+        let parallel: Vec<_> = child.par_triples().collect();
+        assert_eq!(167, sequential.len());
+        assert_eq!(sequential, parallel);
+    }
+
+    #[tokio::test]
+    async fn reach_objects_through_predicate() {
+        let files = base_layer_files();
+        let mut builder = SimpleLayerBuilder::new([1, 2, 3, 4, 5], files.clone());
+        for (subject, object) in [
+            ("duck", "bird"),
+            ("bird", "animal"),
+            ("animal", "thing"),
+            ("thing", "thing"),
+            ("cow", "mammal"),
+            ("mammal", "animal"),
+        ] {
+            builder.add_string_triple(StringTriple::new_node(subject, "subclass_of", object));
+        }
+        builder.add_string_triple(StringTriple::new_node("duck", "eats", "plant"));
+        builder.add_string_triple(StringTriple::new_value("duck", "subclass_of", "a value"));
+        builder.commit().await.unwrap();
+        let layer: InternalLayer = BaseLayer::load_from_files([1, 2, 3, 4, 5], &files)
+            .await
+            .unwrap()
+            .into();
+
+        let id = |node: &str| layer.object_node_id(node).unwrap();
+        let subclass_of = layer.predicate_id("subclass_of").unwrap();
+        let ids = |nodes: &[&str]| nodes.iter().map(|n| id(n)).collect::<HashSet<_>>();
+
+        let mut expected = ids(&["bird", "animal", "thing"]);
+        expected.insert(layer.object_value_id("a value").unwrap());
+        assert_eq!(expected, layer.reachable_from(id("duck"), subclass_of));
+        assert_eq!(
+            ids(&["thing"]),
+            layer.reachable_from(id("thing"), subclass_of)
+        );
+        assert_eq!(
+            ids(&["mammal", "animal"]),
+            layer.reachable_from_within(id("cow"), subclass_of, 2)
+        );
+        assert!(layer
+            .reachable_from_within(id("cow"), subclass_of, 0)
+            .is_empty());
+        assert!(layer.reachable_from(id("plant"), subclass_of).is_empty());
+    }
+
+    #[tokio::test]
+    async fn find_objects_in_typed_range() {
+        let files = base_layer_files();
+        let mut builder = SimpleLayerBuilder::new([1, 2, 3, 4, 5], files.clone());
+        for (animal, legs) in [("cow", 4), ("duck", 2), ("snake", 0), ("spider", 8)] {
+            builder.add_string_triple(StringTriple::new_literal(animal, "legs", legs));
+            builder.add_string_triple(StringTriple::new_literal(animal, "eyes", legs));
+        }
+        builder.add_string_triple(StringTriple::new_literal("cow", "legs", "four"));
+        builder.add_string_triple(StringTriple::new_literal("pig", "legs", 4));
+        builder.commit().await.unwrap();
+        let layer: InternalLayer = BaseLayer::load_from_files([1, 2, 3, 4, 5], &files)
+            .await
+            .unwrap()
+            .into();
+
+        let legs = layer.predicate_id("legs").unwrap();
+        let subjects = |start: Bound<&TypedValue>, end: Bound<&TypedValue>| {
+            layer
+                .objects_in_range(legs, start, end)
+                .map(|t| layer.id_subject(t.subject).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            vec!["duck", "cow", "pig"],
+            subjects(
+                Bound::Included(&TypedValue::Integer(1)),
+                Bound::Excluded(&TypedValue::Integer(8))
+            )
+        );
+        assert_eq!(
+            vec!["spider"],
+            subjects(Bound::Excluded(&TypedValue::Integer(4)), Bound::Unbounded)
+        );
+        assert!(subjects(
+            Bound::Included(&TypedValue::Boolean(false)),
+            Bound::Unbounded
+        )
+        .is_empty());
+    }
+
+    #[tokio::test]
+    async fn find_ids_by_regex() {
+        let files = base_layer_files();
+        let mut builder = SimpleLayerBuilder::new([1, 2, 3, 4, 5], files.clone());
+        builder.add_string_triple(StringTriple::new_node("cow", "likes", "cowbell"));
+        builder.add_string_triple(StringTriple::new_node("pig", "likes", "mud"));
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        builder.add_string_triple(StringTriple::new_lang_string("cow", "label", "Kuh", "de"));
+        builder.add_string_triple(StringTriple::new_literal("cow", "legs", 4));
+        builder.commit().await.unwrap();
+        let base: Arc<InternalLayer> = Arc::new(
+            BaseLayer::load_from_files([1, 2, 3, 4, 5], &files)
+                .await
+                .unwrap()
+                .into(),
+        );
+
+        let files = child_layer_files();
+        let mut builder =
+            SimpleLayerBuilder::from_parent([5, 4, 3, 2, 1], base.clone(), files.clone());
+        builder.add_string_triple(StringTriple::new_node("cowboy", "lassos", "cow"));
+        builder.add_string_triple(StringTriple::new_value("cowboy", "says", "yeehaw"));
+        builder.commit().await.unwrap();
+        let child: Arc<InternalLayer> = Arc::new(
+            ChildLayer::load_from_files([5, 4, 3, 2, 1], base, &files)
+                .await
+                .unwrap()
+                .into(),
+        );
+
+        let regex = |expression| Regex::new(expression).unwrap();
+        let node = |node| child.object_node_id(node).unwrap();
+        let mut expected = vec![node("cow"), node("cowbell"), node("cowboy")];
+        expected.sort();
+        assert_eq!(expected, child.node_ids_matching(&regex("^cow")));
+        assert_eq!(
+            vec![node("mud")],
+            child.node_ids_matching(&glob_regex("?u?").unwrap())
+        );
+        let mut expected = vec![
+            child.predicate_id("lassos").unwrap(),
+            child.predicate_id("legs").unwrap(),
+            child.predicate_id("likes").unwrap(),
+        ];
+        expected.sort();
+        assert_eq!(
+            expected,
+            child.predicate_ids_matching(&glob_regex("l*s").unwrap())
+        );
+
+        let value = |value: &ObjectType| child.object_value_id(&value.value_string().unwrap());
+        let kuh = ObjectType::new_lang_string("Kuh", "de");
+        let mut expected = vec![
+            value(&kuh).unwrap(),
+            value(&ObjectType::Value("moo".to_owned())).unwrap(),
+        ];
+        expected.sort();
+        assert_eq!(expected, child.value_ids_matching(&regex("(?i)^[km]")));
+        assert!(child.value_ids_matching(&regex("de")).is_empty());
+        assert!(child.value_ids_matching(&regex("4|i")).is_empty());
+
+        let mut staging = StagingLayer::new(child.clone());
+        staging.add_string_triple(StringTriple::new_node("cowgirl", "lassos", "cow"));
+        staging.add_string_triple(StringTriple::new_value("cowgirl", "says", "yeehaw!"));
+        let cowgirl = staging.subject_id("cowgirl").unwrap();
+        assert_eq!(
+            Some(&cowgirl),
+            staging.node_ids_matching(&regex("^cow")).last()
+        );
+        assert_eq!(2, staging.value_ids_matching(&regex("^yee")).len());
+    }
+
+    #[tokio::test]
+    async fn count_triples_by_predicate() {
+        let files = base_layer_files();
+        let mut builder = SimpleLayerBuilder::new([1, 2, 3, 4, 5], files.clone());
+        for animal in ["cow", "duck", "pig", "sheep"] {
+            builder.add_string_triple(StringTriple::new_node(animal, "a", "animal"));
+            builder.add_string_triple(StringTriple::new_node(animal, "lives_on", "farm"));
+        }
+        builder.add_string_triple(StringTriple::new_node("duck", "likes", "pig"));
+        builder.add_string_triple(StringTriple::new_node("duck", "likes", "farm"));
+        builder.commit().await.unwrap();
+        let base: Arc<InternalLayer> = Arc::new(
+            BaseLayer::load_from_files([1, 2, 3, 4, 5], &files)
+                .await
+                .unwrap()
+                .into(),
+        );
+
+        let files = child_layer_files();
+        let mut builder =
+            SimpleLayerBuilder::from_parent([5, 4, 3, 2, 1], base.clone(), files.clone());
+        builder.remove_string_triple(StringTriple::new_node("pig", "a", "animal"));
+        builder.remove_string_triple(StringTriple::new_node("duck", "likes", "farm"));
+        builder.add_string_triple(StringTriple::new_node("pig", "likes", "farm"));
+        builder.commit().await.unwrap();
+        let child: InternalLayer = ChildLayer::load_from_files([5, 4, 3, 2, 1], base, &files)
+            .await
+            .unwrap()
+            .into();
+
+        let id = |node: &str| child.object_node_id(node);
+        let a = child.predicate_id("a").unwrap();
+        let lives_on = child.predicate_id("lives_on").unwrap();
+        let likes = child.predicate_id("likes").unwrap();
+        for (subject, predicate, object) in [
+            (None, None, None),
+            (None, Some(a), None),
+            (None, Some(likes), None),
+            (id("duck"), None, None),
+            (id("pig"), Some(likes), None),
+            (None, None, id("farm")),
+            (id("duck"), None, id("pig")),
+            (None, Some(likes), id("farm")),
+            (id("cow"), Some(a), id("animal")),
+        ] {
+            assert_eq!(
+                child.triples_matching(subject, predicate, object).count(),
+                child.count_matching(subject, predicate, object)
+            );
+        }
+
+        let counts: BTreeMap<_, _> = vec![(a, 3), (lives_on, 4), (likes, 2)]
+            .into_iter()
+            .collect();
+        assert_eq!(counts, child.count_by_predicate(None, None));
+        let counts: BTreeMap<_, _> = vec![(lives_on, 4), (likes, 1)].into_iter().collect();
+        assert_eq!(counts, child.count_by_predicate(None, id("farm")));
+        let counts: BTreeMap<_, _> = vec![(lives_on, 1), (likes, 1)].into_iter().collect();
+        assert_eq!(counts, child.count_by_predicate(id("pig"), None));
+    }
+
+    #[tokio::test]
+    async fn triples_are_ordered() {
+        let files = base_layer_files();
+        let mut builder = SimpleLayerBuilder::new([1, 2, 3, 4, 5], files.clone());
+        builder.add_string_triple(StringTriple::new_node("pig", "likes", "cow"));
+        builder.add_string_triple(StringTriple::new_node("cow", "likes", "pig"));
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        builder.add_string_triple(StringTriple::new_value("pig", "says", "oink"));
+        builder.commit().await.unwrap();
+        let base: Arc<InternalLayer> = Arc::new(
+            BaseLayer::load_from_files([1, 2, 3, 4, 5], &files)
+                .await
+                .unwrap()
+                .into(),
+        );
+
+        let files = child_layer_files();
+        let mut builder =
+            SimpleLayerBuilder::from_parent([5, 4, 3, 2, 1], base.clone(), files.clone());
+        builder.add_string_triple(StringTriple::new_node("duck", "likes", "cow"));
+        builder.add_string_triple(StringTriple::new_node("cow", "likes", "duck"));
+        builder.add_string_triple(StringTriple::new_value("duck", "says", "quack"));
+        builder.add_string_triple(StringTriple::new_value("horse", "eats", "hay"));
+        builder.remove_string_triple(StringTriple::new_value("pig", "says", "oink"));
+        builder.commit().await.unwrap();
+        let child: InternalLayer = ChildLayer::load_from_files([5, 4, 3, 2, 1], base, &files)
+            .await
+            .unwrap()
+            .into();
+
+        let spo: Vec<_> = child.triples().collect();
+        let mut expected = spo.clone();
+        expected.sort();
+        assert_eq!(7, spo.len());
+        assert_eq!(expected, spo);
+
+        let pos: Vec<_> = child.triples_pos().collect();
+        expected.sort_by_key(|t| (t.predicate, t.object, t.subject));
+        assert_eq!(expected, pos);
+    }
 }
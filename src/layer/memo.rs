@@ -0,0 +1,194 @@
+//! Memoizing dictionary lookups in a parent layer.
+//!
+//! A child layer builder resolves the strings of every added triple
+//! against the dictionaries of its parent, once when the triple is
+//! added and once more on commit. Imports tend to repeat the same few
+//! predicates, and often the same subjects, for many triples in a row,
+//! so the builder remembers the ids it found, including strings that
+//! turned out not to be in the parent.
+use std::collections::HashMap;
+
+use super::layer::*;
+
+/// The amount of strings remembered per dictionary before the memo starts over.
+const MEMO_CAPACITY: usize = 1 << 14;
+
+/// A bounded map from strings to their id in a dictionary, if any.
+#[derive(Clone, Default)]
+struct BoundedMemo {
+    ids: HashMap<String, Option<u64>>,
+}
+
+impl BoundedMemo {
+    fn get(&self, s: &str) -> Option<Option<u64>> {
+        self.ids.get(s).copied()
+    }
+
+    fn get_or_insert_with<L: FnOnce(&str) -> Option<u64>>(
+        &mut self,
+        s: &str,
+        lookup: L,
+    ) -> Option<u64> {
+        if let Some(id) = self.get(s) {
+            return id;
+        }
+
+        // forgetting everything is crude, but cheap, and recent
+        // strings are quickly remembered again.
+        if self.ids.len() >= MEMO_CAPACITY {
+            self.ids.clear();
+        }
+        let id = lookup(s);
+        self.ids.insert(s.to_string(), id);
+
+        id
+    }
+
+    fn resolve<T, L>(&self, s: &str, unresolved: T, lookup: L) -> PossiblyResolved<T>
+    where
+        T: Clone + PartialEq + Eq + PartialOrd + Ord + std::hash::Hash,
+        L: FnOnce(&str) -> Option<u64>,
+    {
+        match self.get(s).unwrap_or_else(|| lookup(s)) {
+            Some(id) => PossiblyResolved::Resolved(id),
+            None => PossiblyResolved::Unresolved(unresolved),
+        }
+    }
+}
+
+/// Memoized lookups of subjects, predicates and objects in a parent layer.
+#[derive(Clone, Default)]
+pub(crate) struct DictionaryMemo {
+    subjects: BoundedMemo,
+    predicates: BoundedMemo,
+    nodes: BoundedMemo,
+    values: BoundedMemo,
+}
+
+impl DictionaryMemo {
+    /// Returns true if the parent contains the given triple, remembering the ids of its strings.
+    pub(crate) fn string_triple_exists(
+        &mut self,
+        parent: &dyn Layer,
+        triple: &StringTriple,
+    ) -> bool {
+        let subject = match self
+            .subjects
+            .get_or_insert_with(&triple.subject, |s| parent.subject_id(s))
+        {
+            Some(subject) => subject,
+            None => return false,
+        };
+        let predicate = match self
+            .predicates
+            .get_or_insert_with(&triple.predicate, |p| parent.predicate_id(p))
+        {
+            Some(predicate) => predicate,
+            None => return false,
+        };
+        let object = match &triple.object {
+            ObjectType::Node(node) => self
+                .nodes
+                .get_or_insert_with(node, |n| parent.object_node_id(n)),
+            object => object.value_string().and_then(|value| {
+                self.values
+                    .get_or_insert_with(&value, |v| parent.object_value_id(v))
+            }),
+        };
+
+        object.is_some_and(|object| {
+            parent.id_triple_exists(IdTriple::new(subject, predicate, object))
+        })
+    }
+
+    /// Convert all strings of a triple that are in the parent to ids.
+    ///
+    /// This is `Layer::string_triple_to_partially_resolved`, looking
+    /// in the memo first. Strings that aren't in the memo are looked
+    /// up in the parent without being remembered, so that the memo
+    /// can be shared by threads resolving triples in parallel.
+    pub(crate) fn string_triple_to_partially_resolved(
+        &self,
+        parent: &dyn Layer,
+        triple: StringTriple,
+    ) -> PartiallyResolvedTriple {
+        let subject = self
+            .subjects
+            .resolve(&triple.subject, triple.subject.clone(), |s| {
+                parent.subject_id(s)
+            });
+        let predicate = self
+            .predicates
+            .resolve(&triple.predicate, triple.predicate.clone(), |p| {
+                parent.predicate_id(p)
+            });
+        let object = match &triple.object {
+            ObjectType::Node(node) => self
+                .nodes
+                .resolve(node, triple.object.clone(), |n| parent.object_node_id(n)),
+            object => match object.value_string() {
+                Some(value) => self
+                    .values
+                    .resolve(&value, triple.object.clone(), |v| parent.object_value_id(v)),
+                None => PossiblyResolved::Unresolved(triple.object.clone()),
+            },
+        };
+
+        PartiallyResolvedTriple {
+            subject,
+            predicate,
+            object,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::internal::{BaseLayer, InternalLayer};
+    use crate::layer::simple_builder::{LayerBuilder, SimpleLayerBuilder};
+    use crate::storage::memory::base_layer_memory_files;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn memoized_lookups_match_parent() {
+        let files = base_layer_memory_files();
+        let mut builder = SimpleLayerBuilder::new([1, 2, 3, 4, 5], files.clone());
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        builder.add_string_triple(StringTriple::new_node("cow", "likes", "duck"));
+        builder.commit().await.unwrap();
+        let layer = BaseLayer::load_from_files([1, 2, 3, 4, 5], &files)
+            .await
+            .unwrap();
+        let parent: Arc<InternalLayer> = Arc::new(layer);
+
+        let mut memo = DictionaryMemo::default();
+        let moo = StringTriple::new_value("cow", "says", "moo");
+        let quack = StringTriple::new_value("duck", "says", "quack");
+        for _ in 0..2 {
+            assert!(memo.string_triple_exists(&*parent, &moo));
+            assert!(memo
+                .string_triple_exists(&*parent, &StringTriple::new_node("cow", "likes", "duck")));
+            assert!(!memo.string_triple_exists(&*parent, &quack));
+            assert!(!memo
+                .string_triple_exists(&*parent, &StringTriple::new_value("cow", "likes", "duck")));
+        }
+        assert_eq!(
+            parent.string_triple_to_partially_resolved(quack.clone()),
+            memo.string_triple_to_partially_resolved(&*parent, quack)
+        );
+        assert_eq!(
+            parent.string_triple_to_partially_resolved(moo.clone()),
+            memo.string_triple_to_partially_resolved(&*parent, moo)
+        );
+
+        for i in 0..MEMO_CAPACITY + 10 {
+            memo.string_triple_exists(
+                &*parent,
+                &StringTriple::new_value(&format!("pig{}", i), "says", "oink"),
+            );
+        }
+        assert!(memo.subjects.ids.len() <= MEMO_CAPACITY);
+        assert!(memo.string_triple_exists(&*parent, &StringTriple::new_value("cow", "says", "moo")));
+    }
+}
@@ -0,0 +1,204 @@
+//! Descriptive metadata for layers.
+//!
+//! A layer can optionally carry a commit message, an author, a
+//! timestamp and arbitrary key/value pairs. These are stored in a
+//! separate file of the layer, so they are not part of the triple
+//! data, and do not influence the content name of a layer.
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use super::spill::{read_string, write_string};
+use crate::storage::{FileLoad, FileStore, SyncableFile};
+
+const METADATA_VERSION: u8 = 1;
+
+/// Descriptive metadata for a layer.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LayerMetadata {
+    /// A message describing the changes in the layer.
+    pub message: Option<String>,
+    /// Who made the changes in the layer.
+    pub author: Option<String>,
+    /// When the layer was made.
+    pub timestamp: Option<SystemTime>,
+    /// Any other properties.
+    pub properties: BTreeMap<String, String>,
+}
+
+impl LayerMetadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_message<S: Into<String>>(mut self, message: S) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+
+    pub fn with_author<S: Into<String>>(mut self, author: S) -> Self {
+        self.author = Some(author.into());
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: SystemTime) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn with_property<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.properties.insert(key.into(), value.into());
+        self
+    }
+
+    /// Encode this metadata as it is stored in a layer file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![METADATA_VERSION];
+        write_optional_string(&mut bytes, self.message.as_deref());
+        write_optional_string(&mut bytes, self.author.as_deref());
+        match self.timestamp {
+            None => bytes.push(0),
+            Some(timestamp) => {
+                // timestamps before the epoch are stored as negative durations
+                let (negative, duration) = match timestamp.duration_since(UNIX_EPOCH) {
+                    Ok(duration) => (false, duration),
+                    Err(e) => (true, e.duration()),
+                };
+                bytes.push(if negative { 2 } else { 1 });
+                bytes.write_u64::<BigEndian>(duration.as_secs()).unwrap();
+                bytes
+                    .write_u32::<BigEndian>(duration.subsec_nanos())
+                    .unwrap();
+            }
+        }
+        bytes
+            .write_u64::<BigEndian>(self.properties.len() as u64)
+            .unwrap();
+        for (key, value) in self.properties.iter() {
+            write_string(&mut bytes, key).unwrap();
+            write_string(&mut bytes, value).unwrap();
+        }
+
+        bytes
+    }
+
+    /// Decode metadata that was encoded with `to_bytes`.
+    pub fn from_bytes(mut bytes: &[u8]) -> io::Result<Self> {
+        let reader = &mut bytes;
+        let version = reader.read_u8()?;
+        if version != METADATA_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported layer metadata version {}", version),
+            ));
+        }
+
+        let message = read_optional_string(reader)?;
+        let author = read_optional_string(reader)?;
+        let timestamp = match reader.read_u8()? {
+            0 => None,
+            tag @ (1 | 2) => {
+                let duration = Duration::new(
+                    reader.read_u64::<BigEndian>()?,
+                    reader.read_u32::<BigEndian>()?,
+                );
+                if tag == 1 {
+                    UNIX_EPOCH.checked_add(duration)
+                } else {
+                    UNIX_EPOCH.checked_sub(duration)
+                }
+            }
+            _ => return Err(invalid_metadata()),
+        };
+        let mut properties = BTreeMap::new();
+        for _ in 0..reader.read_u64::<BigEndian>()? {
+            let key = read_string(reader)?;
+            let value = read_string(reader)?;
+            properties.insert(key, value);
+        }
+        if !reader.is_empty() {
+            return Err(invalid_metadata());
+        }
+
+        Ok(Self {
+            message,
+            author,
+            timestamp,
+            properties,
+        })
+    }
+}
+
+fn invalid_metadata() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid layer metadata")
+}
+
+fn write_optional_string(bytes: &mut Vec<u8>, s: Option<&str>) {
+    match s {
+        None => bytes.push(0),
+        Some(s) => {
+            bytes.push(1);
+            write_string(bytes, s).unwrap();
+        }
+    }
+}
+
+fn read_optional_string<R: Read>(reader: &mut R) -> io::Result<Option<String>> {
+    match reader.read_u8()? {
+        0 => Ok(None),
+        1 => read_string(reader).map(Some),
+        _ => Err(invalid_metadata()),
+    }
+}
+
+/// Write the metadata of a layer to the given file.
+pub async fn write_metadata<F: FileStore>(file: &F, metadata: &LayerMetadata) -> io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut writer = file.open_write().await?;
+    writer.write_all(&metadata.to_bytes()).await?;
+    writer.flush().await?;
+    writer.sync_all().await
+}
+
+/// Read the metadata of a layer from the given file, if it exists.
+pub async fn read_metadata<F: FileLoad>(file: &F) -> io::Result<Option<LayerMetadata>> {
+    if !file.exists().await? {
+        return Ok(None);
+    }
+
+    LayerMetadata::from_bytes(&file.map().await?).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_roundtrip() {
+        let metadata = LayerMetadata::new()
+            .with_message("add some animals\nand their sounds")
+            .with_timestamp(UNIX_EPOCH + Duration::new(1_600_000_000, 123))
+            .with_property("ticket", "ANIMALS-1")
+            .with_property("source", "farm.csv");
+        assert_eq!(
+            metadata,
+            LayerMetadata::from_bytes(&metadata.to_bytes()).unwrap()
+        );
+
+        let before_epoch = LayerMetadata::new()
+            .with_author("someone")
+            .with_timestamp(UNIX_EPOCH - Duration::from_secs(86400));
+        assert_eq!(
+            before_epoch,
+            LayerMetadata::from_bytes(&before_epoch.to_bytes()).unwrap()
+        );
+
+        let mut truncated = metadata.to_bytes();
+        truncated.pop();
+        assert!(LayerMetadata::from_bytes(&truncated).is_err());
+    }
+}
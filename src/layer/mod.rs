@@ -3,13 +3,40 @@
 //! Databases in terminus-store are stacks of layers. The first layer
 //! in such a stack is a base layer, which contains an intial data
 //! set. On top of that, each layer stores additions and removals.
+mod blank;
 pub mod builder;
+mod bulk;
+mod cooccurrence;
+mod csr;
+mod diff;
+mod filter;
 pub mod id_map;
 mod internal;
 mod layer;
+mod memo;
+mod metadata;
+mod quad;
+mod quoted;
 mod simple_builder;
+mod spill;
+mod staging;
+mod text_index;
+mod typed;
 
+pub use blank::*;
+pub use builder::{BuildProgress, BuildProgressReporter, Parallelism};
+pub use bulk::*;
+pub use cooccurrence::*;
+pub use csr::*;
+pub use diff::*;
+pub use filter::*;
 pub use id_map::*;
 pub use internal::*;
 pub use layer::*;
+pub use metadata::*;
+pub use quad::*;
+pub use quoted::*;
 pub use simple_builder::*;
+pub use staging::*;
+pub use text_index::*;
+pub use typed::*;
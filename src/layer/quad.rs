@@ -0,0 +1,435 @@
+//! Named graph support.
+//!
+//! Besides its triples, which make up the default graph, a layer can
+//! store quads, which are triples in a named graph. Quads are kept
+//! apart from the default graph: adding a quad does not add its
+//! triple to the default graph, and vice versa.
+//!
+//! The strings of a quad are stored in the regular dictionaries of
+//! the layer. Graph names are stored in a separate graph dictionary
+//! per layer, which only contains the graphs that layer changes. The
+//! quads themselves are stored as sorted (graph, subject, predicate,
+//! object) entries in a logarray of additions and one of removals.
+//! All these files are optional, so layers without quads are stored
+//! exactly as before.
+use std::collections::{BTreeSet, HashSet};
+use std::io;
+
+use super::layer::*;
+use crate::storage::*;
+use crate::structure::*;
+
+/// A triple in a named graph, stored as strings.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StringQuad {
+    pub graph: String,
+    pub subject: String,
+    pub predicate: String,
+    pub object: ObjectType,
+}
+
+impl StringQuad {
+    /// Construct a quad with a node object.
+    pub fn new_node(graph: &str, subject: &str, predicate: &str, object: &str) -> Self {
+        Self::from_triple(graph, StringTriple::new_node(subject, predicate, object))
+    }
+
+    /// Construct a quad with a value object.
+    pub fn new_value(graph: &str, subject: &str, predicate: &str, object: &str) -> Self {
+        Self::from_triple(graph, StringTriple::new_value(subject, predicate, object))
+    }
+
+    /// Construct a quad placing the given triple in the given graph.
+    pub fn from_triple(graph: &str, triple: StringTriple) -> Self {
+        StringQuad {
+            graph: graph.to_owned(),
+            subject: triple.subject,
+            predicate: triple.predicate,
+            object: triple.object,
+        }
+    }
+
+    /// Returns the triple part of this quad.
+    pub fn to_triple(&self) -> StringTriple {
+        StringTriple {
+            subject: self.subject.clone(),
+            predicate: self.predicate.clone(),
+            object: self.object.clone(),
+        }
+    }
+
+    /// Split this quad into its graph and its triple.
+    pub fn into_parts(self) -> (String, StringTriple) {
+        (
+            self.graph,
+            StringTriple {
+                subject: self.subject,
+                predicate: self.predicate,
+                object: self.object,
+            },
+        )
+    }
+}
+
+const QUAD_WIDTH: usize = 4;
+
+fn quad_key(graph: u64, triple: IdTriple) -> [u64; QUAD_WIDTH] {
+    [graph, triple.subject, triple.predicate, triple.object]
+}
+
+/// A sorted list of quads, each stored as four consecutive logarray entries.
+#[derive(Clone)]
+struct QuadArray(LogArray);
+
+impl QuadArray {
+    fn len(&self) -> usize {
+        self.0.len() / QUAD_WIDTH
+    }
+
+    fn get(&self, index: usize) -> [u64; QUAD_WIDTH] {
+        let offset = index * QUAD_WIDTH;
+        [
+            self.0.entry(offset),
+            self.0.entry(offset + 1),
+            self.0.entry(offset + 2),
+            self.0.entry(offset + 3),
+        ]
+    }
+
+    /// Returns the index of the first quad that is not less than the given key.
+    fn lower_bound(&self, key: &[u64]) -> usize {
+        let mut min = 0;
+        let mut max = self.len();
+        while min < max {
+            let mid = (min + max) / 2;
+            if self.get(mid)[..key.len()] < *key {
+                min = mid + 1;
+            } else {
+                max = mid;
+            }
+        }
+
+        min
+    }
+
+    fn contains(&self, key: [u64; QUAD_WIDTH]) -> bool {
+        let index = self.lower_bound(&key);
+        index < self.len() && self.get(index) == key
+    }
+
+    fn triples_in_graph(&self, graph: u64) -> impl Iterator<Item = IdTriple> + '_ {
+        let start = self.lower_bound(&[graph]);
+        (start..self.len())
+            .map(move |index| self.get(index))
+            .take_while(move |quad| quad[0] == graph)
+            .map(|quad| IdTriple::new(quad[1], quad[2], quad[3]))
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (u64, IdTriple)> + '_ {
+        (0..self.len()).map(move |index| {
+            let quad = self.get(index);
+            (quad[0], IdTriple::new(quad[1], quad[2], quad[3]))
+        })
+    }
+}
+
+/// The quads added and removed by a single layer.
+#[derive(Clone)]
+pub struct LayerQuads {
+    name: [u32; 5],
+    graphs: PfcDict,
+    additions: QuadArray,
+    removals: QuadArray,
+}
+
+impl LayerQuads {
+    /// Load the quads of a layer, returning None if the layer has no quads.
+    pub async fn load_from_files<F: FileLoad + FileStore>(
+        name: [u32; 5],
+        files: &QuadFiles<F>,
+    ) -> io::Result<Option<Self>> {
+        if !files.graph_dictionary_files.blocks_file.exists().await? {
+            return Ok(None);
+        }
+
+        let graphs = files.graph_dictionary_files.map_all().await?;
+        let graphs = PfcDict::parse(graphs.blocks_map, graphs.offsets_map)?;
+        let additions = LogArray::parse(files.additions_file.map().await?)?;
+        let removals = LogArray::parse(files.removals_file.map().await?)?;
+
+        Ok(Some(Self {
+            name,
+            graphs,
+            additions: QuadArray(additions),
+            removals: QuadArray(removals),
+        }))
+    }
+
+    /// Returns the name of the layer these quads belong to.
+    pub fn name(&self) -> [u32; 5] {
+        self.name
+    }
+
+    fn graph_id(&self, graph: &str) -> Option<u64> {
+        self.graphs.id(graph)
+    }
+
+    fn graph_name(&self, id: u64) -> String {
+        self.graphs
+            .get(id as usize)
+            .expect("quad should refer to a known graph")
+    }
+
+    /// Iterator over the quads added in this layer, as graph names and triples.
+    pub fn additions(&self) -> impl Iterator<Item = (String, IdTriple)> + '_ {
+        self.additions
+            .iter()
+            .map(move |(graph, triple)| (self.graph_name(graph), triple))
+    }
+
+    /// Iterator over the quads removed in this layer, as graph names and triples.
+    pub fn removals(&self) -> impl Iterator<Item = (String, IdTriple)> + '_ {
+        self.removals
+            .iter()
+            .map(move |(graph, triple)| (self.graph_name(graph), triple))
+    }
+}
+
+/// The quads stored in a layer and all its ancestors.
+#[derive(Clone, Default)]
+pub struct QuadStack {
+    // ordered from the base layer up
+    layers: Vec<LayerQuads>,
+}
+
+impl QuadStack {
+    /// Construct a quad stack from the quads of a layer stack, ordered from the base layer up.
+    pub fn new(layers: Vec<LayerQuads>) -> Self {
+        Self { layers }
+    }
+
+    /// Returns the quads stored in the given layer of this stack, if any.
+    pub fn layer(&self, name: [u32; 5]) -> Option<&LayerQuads> {
+        self.layers.iter().find(|layer| layer.name == name)
+    }
+
+    /// Returns true if the given quad exists.
+    pub fn id_quad_exists(&self, graph: &str, triple: IdTriple) -> bool {
+        for layer in self.layers.iter().rev() {
+            if let Some(graph_id) = layer.graph_id(graph) {
+                let key = quad_key(graph_id, triple);
+                if layer.additions.contains(key) {
+                    return true;
+                } else if layer.removals.contains(key) {
+                    return false;
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Returns true if the given quad exists, resolving its strings through the given layer.
+    pub fn string_quad_exists(&self, layer: &dyn Layer, quad: &StringQuad) -> bool {
+        layer
+            .string_triple_to_id(&quad.to_triple())
+            .map(|triple| self.id_quad_exists(&quad.graph, triple))
+            .unwrap_or(false)
+    }
+
+    /// Returns all triples in the given graph, in sorted order.
+    pub fn graph_triples(&self, graph: &str) -> Vec<IdTriple> {
+        let mut result = BTreeSet::new();
+        for layer in self.layers.iter() {
+            if let Some(graph_id) = layer.graph_id(graph) {
+                result.extend(layer.additions.triples_in_graph(graph_id));
+                for triple in layer.removals.triples_in_graph(graph_id) {
+                    result.remove(&triple);
+                }
+            }
+        }
+
+        result.into_iter().collect()
+    }
+
+    /// Returns the names of all graphs that contain at least one triple, in sorted order.
+    pub fn graphs(&self) -> Vec<String> {
+        let mut candidates = BTreeSet::new();
+        for layer in self.layers.iter() {
+            candidates.extend(layer.graphs.strings());
+        }
+
+        candidates
+            .into_iter()
+            .filter(|graph| !self.graph_triples(graph).is_empty())
+            .collect()
+    }
+}
+
+fn width_for(max: u64) -> u8 {
+    (64 - max.max(1).leading_zeros()) as u8
+}
+
+/// Write the quads of a layer.
+///
+/// Quads that are both added and removed are expected to have been
+/// dropped already. If there are no quads at all, nothing is written.
+pub async fn write_quads<F: 'static + FileLoad + FileStore>(
+    files: &QuadFiles<F>,
+    additions: Vec<(String, IdTriple)>,
+    removals: Vec<(String, IdTriple)>,
+) -> io::Result<()> {
+    if additions.is_empty() && removals.is_empty() {
+        return Ok(());
+    }
+
+    let graphs: BTreeSet<&str> = additions
+        .iter()
+        .chain(removals.iter())
+        .map(|(graph, _)| graph.as_str())
+        .collect();
+    let graphs: Vec<&str> = graphs.into_iter().collect();
+    let graph_id = |graph: &str| graphs.binary_search(&graph).unwrap() as u64;
+
+    let to_entries = |quads: &[(String, IdTriple)]| {
+        let quads: BTreeSet<[u64; QUAD_WIDTH]> = quads
+            .iter()
+            .map(|(graph, triple)| quad_key(graph_id(graph), *triple))
+            .collect();
+        let entries: Vec<u64> = quads.into_iter().flat_map(|quad| quad.to_vec()).collect();
+        entries
+    };
+    let addition_entries = to_entries(&additions);
+    let removal_entries = to_entries(&removals);
+
+    let mut dict_builder = PfcDictFileBuilder::new(
+        files
+            .graph_dictionary_files
+            .blocks_file
+            .open_write()
+            .await?,
+        files
+            .graph_dictionary_files
+            .offsets_file
+            .open_write()
+            .await?,
+    );
+    for graph in graphs.iter() {
+        dict_builder.add(graph).await?;
+    }
+    dict_builder.finalize().await?;
+
+    for (file, entries) in [
+        (&files.additions_file, addition_entries),
+        (&files.removals_file, removal_entries),
+    ] {
+        let width = width_for(entries.iter().copied().max().unwrap_or(0));
+        let mut builder = LogArrayFileBuilder::new(file.open_write().await?, width);
+        builder.push_vec(entries).await?;
+        builder.finalize().await?;
+    }
+
+    Ok(())
+}
+
+/// Drop the quads that appear in both lists, and deduplicate them.
+pub(crate) fn cancel_quads(
+    additions: Vec<StringQuad>,
+    removals: Vec<StringQuad>,
+) -> (Vec<StringQuad>, Vec<StringQuad>) {
+    let additions: HashSet<_> = additions.into_iter().collect();
+    let removals: HashSet<_> = removals.into_iter().collect();
+
+    (
+        additions.difference(&removals).cloned().collect(),
+        removals.difference(&additions).cloned().collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackedStore;
+
+    fn quad_files() -> QuadFiles<MemoryBackedStore> {
+        QuadFiles {
+            graph_dictionary_files: DictionaryFiles {
+                blocks_file: MemoryBackedStore::new(),
+                offsets_file: MemoryBackedStore::new(),
+            },
+            additions_file: MemoryBackedStore::new(),
+            removals_file: MemoryBackedStore::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn write_and_query_quad_stack() {
+        let base_files = quad_files();
+        write_quads(
+            &base_files,
+            vec![
+                ("people".to_string(), IdTriple::new(1, 1, 2)),
+                ("animals".to_string(), IdTriple::new(3, 1, 4)),
+                ("people".to_string(), IdTriple::new(2, 1, 1)),
+                ("animals".to_string(), IdTriple::new(4, 2, 5)),
+            ],
+            Vec::new(),
+        )
+        .await
+        .unwrap();
+        let child_files = quad_files();
+        write_quads(
+            &child_files,
+            vec![("plants".to_string(), IdTriple::new(6, 1, 7))],
+            vec![
+                ("animals".to_string(), IdTriple::new(3, 1, 4)),
+                ("animals".to_string(), IdTriple::new(4, 2, 5)),
+            ],
+        )
+        .await
+        .unwrap();
+        let empty_files = quad_files();
+        write_quads(&empty_files, Vec::new(), Vec::new())
+            .await
+            .unwrap();
+
+        let base = LayerQuads::load_from_files([1, 1, 1, 1, 1], &base_files)
+            .await
+            .unwrap()
+            .unwrap();
+        let child = LayerQuads::load_from_files([2, 2, 2, 2, 2], &child_files)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(LayerQuads::load_from_files([3, 3, 3, 3, 3], &empty_files)
+            .await
+            .unwrap()
+            .is_none());
+
+        let stack = QuadStack::new(vec![base.clone()]);
+        assert_eq!(vec!["animals", "people"], stack.graphs());
+        assert_eq!(
+            vec![IdTriple::new(1, 1, 2), IdTriple::new(2, 1, 1)],
+            stack.graph_triples("people")
+        );
+
+        let stack = QuadStack::new(vec![base, child]);
+        assert_eq!(vec!["people", "plants"], stack.graphs());
+        assert!(stack.graph_triples("animals").is_empty());
+        assert!(stack.id_quad_exists("people", IdTriple::new(1, 1, 2)));
+        assert!(!stack.id_quad_exists("people", IdTriple::new(6, 1, 7)));
+        assert!(stack.id_quad_exists("plants", IdTriple::new(6, 1, 7)));
+        assert!(!stack.id_quad_exists("animals", IdTriple::new(3, 1, 4)));
+        assert_eq!(
+            vec![
+                ("animals".to_string(), IdTriple::new(3, 1, 4)),
+                ("animals".to_string(), IdTriple::new(4, 2, 5))
+            ],
+            stack
+                .layer([2, 2, 2, 2, 2])
+                .unwrap()
+                .removals()
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,98 @@
+//! Quoted triples (RDF-star).
+//!
+//! RDF-star allows a triple to be the subject or object of another
+//! triple, to say something about the statement itself, like where it
+//! came from or how certain it is. Layers store a quoted triple as a
+//! node, whose string is the triple written as an N-Triples-star term:
+//!
+//! ```text
+//! << <subject> <predicate> "object" >>
+//! ```
+//!
+//! As the node string only depends on the triple, a quoted triple gets
+//! a single id in the node dictionary, however many triples mention
+//! it, and it is looked up like any other node. Every statement about
+//! a triple is then a single triple, where reification takes four
+//! triples and an extra node for the first statement.
+//!
+//! Quoting a triple doesn't add it. A quoted triple can be mentioned
+//! whether or not the layer contains the triple itself.
+use super::layer::*;
+use crate::io::ntriples::{quote_triple, unquote_triple};
+
+/// Returns true if the given node string is a quoted triple.
+///
+/// IRIs can't contain a `<`, so no IRI is mistaken for a quoted triple.
+pub fn is_quoted_triple(node: &str) -> bool {
+    node.starts_with("<<")
+}
+
+impl StringTriple {
+    /// The node string standing for this triple when it is quoted.
+    ///
+    /// Blank nodes inside the triple are expected to be skolemized
+    /// already, and are written as their skolem IRIs. Nodes of the
+    /// triple can be quoted triples themselves.
+    pub fn quote(&self) -> String {
+        quote_triple(self)
+    }
+
+    /// The triple quoted by a node string, or None if the node isn't a quoted triple.
+    pub fn unquote(node: &str) -> Option<StringTriple> {
+        if !is_quoted_triple(node) {
+            return None;
+        }
+
+        unquote_triple(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layer::*;
+    use crate::store::open_memory_store;
+
+    #[tokio::test]
+    async fn store_statements_about_triples() {
+        let triple = StringTriple::new_literal(
+            "http://example.com/cow",
+            "http://example.com/weight",
+            Value::Integer(550),
+        );
+        let quoted = triple.quote();
+        assert!(is_quoted_triple(&quoted));
+        assert_eq!(Some(triple.clone()), StringTriple::unquote(&quoted));
+        assert_eq!(None, StringTriple::unquote("http://example.com/cow"));
+
+        let nested = StringTriple::new_node(
+            "http://example.com/alice",
+            "http://example.com/said",
+            &quoted,
+        );
+        assert_eq!(Some(nested.clone()), StringTriple::unquote(&nested.quote()));
+
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        builder.add_string_triple(triple.clone()).unwrap();
+        for (predicate, source) in [
+            ("http://example.com/source", "http://example.com/scale"),
+            ("http://example.com/checkedBy", "http://example.com/farmer"),
+        ] {
+            builder
+                .add_string_triple(StringTriple::new_node(&quoted, predicate, source))
+                .unwrap();
+        }
+        builder.add_string_triple(nested.clone()).unwrap();
+        let layer = builder.commit().await.unwrap();
+
+        // the quoted triple is a single node, used both as subject and object
+        let id = layer.subject_id(&quoted).unwrap();
+        assert_eq!(Some(id), layer.object_node_id(&quoted));
+        assert_eq!(2, layer.triples_s(id).count());
+        assert_eq!(1, layer.triples_o(id).count());
+        assert_eq!(
+            Some(triple),
+            StringTriple::unquote(&layer.id_subject(id).unwrap())
+        );
+    }
+}
@@ -9,17 +9,33 @@
 //! any format (numerical, string, or a mixture), store them in
 //! memory, then does the required sorting and id conversion on
 //! commit.
+//!
+//! Long running imports can bound the memory use of a builder by
+//! spilling triples to disk, and can checkpoint those spilled triples
+//! so that the builder can be resumed after a crash.
+use super::builder::{BuildProgress, BuildProgressReporter, Parallelism};
 use super::internal::*;
 use super::layer::*;
+use super::memo::DictionaryMemo;
+use super::metadata::*;
+use super::quad::*;
+use super::spill::TripleSpill;
+use super::text_index::*;
 use crate::storage::*;
+use std::borrow::Cow;
+use std::collections::hash_map::RandomState;
 use std::collections::{HashMap, HashSet};
-use std::io;
+use std::fmt::Write as _;
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::sync::Arc;
 
-use futures::future::Future;
+use futures::future::{self, Future};
 
 use rayon::prelude::*;
+use thiserror::Error;
 
 /// A layer builder trait with no generic typing.
 ///
@@ -31,13 +47,60 @@ pub trait LayerBuilder: Send + Sync {
     /// Return the parent if it exists
     fn parent(&self) -> Option<Arc<dyn Layer>>;
     /// Add a string triple
-    fn add_string_triple(&mut self, triple: StringTriple);
+    ///
+    /// Returns true if the triple is new, meaning that it neither
+    /// exists in the parent layers nor was added to this builder
+    /// before.
+    fn add_string_triple(&mut self, triple: StringTriple) -> bool;
     /// Add an id triple
-    fn add_id_triple(&mut self, triple: IdTriple);
+    ///
+    /// Returns true if the triple is new, like `add_string_triple`.
+    fn add_id_triple(&mut self, triple: IdTriple) -> bool;
     /// Remove a string triple
     fn remove_string_triple(&mut self, triple: StringTriple);
     /// Remove an id triple
     fn remove_id_triple(&mut self, triple: IdTriple);
+    /// Remove all triples of the parent layers matching the given pattern, where None matches anything.
+    ///
+    /// Triples added to this builder are not affected. Returns the
+    /// amount of removed triples. Without a parent, this does nothing.
+    fn remove_matching(
+        &mut self,
+        subject: Option<u64>,
+        predicate: Option<u64>,
+        object: Option<u64>,
+    ) -> usize {
+        let parent = match self.parent() {
+            Some(parent) => parent,
+            None => return 0,
+        };
+        let mut count = 0;
+        for triple in parent.triples_matching(subject, predicate, object) {
+            self.remove_id_triple(triple);
+            count += 1;
+        }
+
+        count
+    }
+    /// Add a quad to a named graph
+    fn add_string_quad(&mut self, quad: StringQuad);
+    /// Remove a quad from a named graph
+    fn remove_string_quad(&mut self, quad: StringQuad);
+    /// Set the descriptive metadata to store with the layer
+    fn set_metadata(&mut self, metadata: LayerMetadata);
+    /// Report the progress of committing this layer
+    fn set_progress(&mut self, progress: BuildProgressReporter);
+    /// Durably store the string triples added and removed so far.
+    ///
+    /// After a crash, a builder for the same layer can pick up from
+    /// the last checkpoint. Builders that don't support this return
+    /// an `Unsupported` error.
+    fn checkpoint(&mut self) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this layer builder can not be checkpointed",
+        ))
+    }
     /// Commit the layer to storage
     fn commit(self) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>>;
     /// Commit a boxed layer to storage
@@ -57,6 +120,70 @@ pub struct SimpleLayerBuilder<F: 'static + FileLoad + FileStore + Clone> {
     id_additions: Vec<IdTriple>,
     removals: Vec<StringTriple>,
     id_removals: Vec<IdTriple>,
+    addition_spill: Option<TripleSpill>,
+    removal_spill: Option<TripleSpill>,
+    spill_error: Option<Arc<io::Error>>,
+    space_check: Option<Arc<dyn SpaceCheck>>,
+    quad_additions: Vec<StringQuad>,
+    quad_removals: Vec<StringQuad>,
+    quad_files: Option<QuadFiles<F>>,
+    parallelism: Parallelism,
+    checkpoint_directory: Option<PathBuf>,
+    removal_validation: RemovalValidation,
+    metadata: Option<LayerMetadata>,
+    metadata_file: Option<F>,
+    text_index_file: Option<F>,
+    progress: BuildProgressReporter,
+    staged_fingerprints: HashSet<u64>,
+    fingerprint_state: RandomState,
+    memo: DictionaryMemo,
+}
+
+/// What a child layer builder does on commit with removals of triples that don't exist in its parent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RemovalValidation {
+    /// Silently drop the removal.
+    #[default]
+    Drop,
+    /// Fail the commit with an `InvalidRemovalsError`.
+    Strict,
+}
+
+/// An error indicating that a strictly validating builder removed triples that don't exist.
+///
+/// This is returned wrapped in an `io::Error` with kind
+/// `io::ErrorKind::InvalidInput`. Use
+/// `InvalidRemovalsError::from_io_error` to get at it.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("{count} removed triples do not exist in the parent layer, including {:?}", .triples.first())]
+pub struct InvalidRemovalsError {
+    /// The amount of removed triples that do not exist.
+    pub count: usize,
+    /// The removed triples that do not exist, leaving out id triples with ids unknown to the parent.
+    pub triples: Vec<StringTriple>,
+}
+
+impl InvalidRemovalsError {
+    /// Retrieve the invalid removals error from an `io::Error`, if this io error was caused by one.
+    pub fn from_io_error(error: &io::Error) -> Option<&InvalidRemovalsError> {
+        error
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<InvalidRemovalsError>())
+    }
+}
+
+impl From<InvalidRemovalsError> for io::Error {
+    fn from(error: InvalidRemovalsError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidInput, error)
+    }
+}
+
+/// The file in a checkpoint directory listing the runs of the last checkpoint.
+const CHECKPOINT_MANIFEST: &str = "manifest";
+
+/// Returns true if the given directory contains a builder checkpoint.
+pub(crate) fn has_checkpoint(directory: &Path) -> bool {
+    directory.join(CHECKPOINT_MANIFEST).is_file()
 }
 
 impl<F: 'static + FileLoad + FileStore + Clone> SimpleLayerBuilder<F> {
@@ -70,6 +197,23 @@ impl<F: 'static + FileLoad + FileStore + Clone> SimpleLayerBuilder<F> {
             id_additions: Vec::with_capacity(0),
             removals: Vec::new(),
             id_removals: Vec::with_capacity(0),
+            addition_spill: None,
+            removal_spill: None,
+            spill_error: None,
+            space_check: None,
+            quad_additions: Vec::new(),
+            quad_removals: Vec::new(),
+            quad_files: None,
+            parallelism: Parallelism::default(),
+            checkpoint_directory: None,
+            removal_validation: RemovalValidation::default(),
+            metadata: None,
+            metadata_file: None,
+            text_index_file: None,
+            progress: BuildProgressReporter::default(),
+            staged_fingerprints: HashSet::new(),
+            fingerprint_state: RandomState::new(),
+            memo: DictionaryMemo::default(),
         }
     }
 
@@ -83,8 +227,204 @@ impl<F: 'static + FileLoad + FileStore + Clone> SimpleLayerBuilder<F> {
             id_additions: Vec::new(),
             removals: Vec::new(),
             id_removals: Vec::new(),
+            addition_spill: None,
+            removal_spill: None,
+            spill_error: None,
+            space_check: None,
+            quad_additions: Vec::new(),
+            quad_removals: Vec::new(),
+            quad_files: None,
+            parallelism: Parallelism::default(),
+            checkpoint_directory: None,
+            removal_validation: RemovalValidation::default(),
+            metadata: None,
+            metadata_file: None,
+            text_index_file: None,
+            progress: BuildProgressReporter::default(),
+            staged_fingerprints: HashSet::new(),
+            fingerprint_state: RandomState::new(),
+            memo: DictionaryMemo::default(),
+        }
+    }
+
+    /// Spill string triples to scratch files as configured.
+    ///
+    /// Once more than the configured amount of triples has been
+    /// added or removed, they are sorted and written to a scratch
+    /// file, so the memory use of this builder stays bounded while
+    /// triples are being added. On commit, the deduplicated set of
+    /// triples is read back from these files.
+    pub fn with_spill(mut self, config: SpillConfig) -> Self {
+        self.addition_spill = Some(TripleSpill::new(config.clone()));
+        self.removal_spill = Some(TripleSpill::new(config));
+
+        self
+    }
+
+    /// Check for available space before writing any layer files.
+    ///
+    /// On commit, the size of the layer is estimated before anything
+    /// is written, and the given check is run with that
+    /// estimate. If it fails, commit fails without having written any
+    /// files.
+    pub fn with_space_check(mut self, check: Arc<dyn SpaceCheck>) -> Self {
+        self.space_check = Some(check);
+
+        self
+    }
+
+    /// Store named graph quads in the given files.
+    ///
+    /// Without these files, committing a builder that has quads fails.
+    pub fn with_quad_files(mut self, files: QuadFiles<F>) -> Self {
+        self.quad_files = Some(files);
+
+        self
+    }
+
+    /// Store the metadata of this layer in the given file.
+    ///
+    /// Without this file, committing a builder that has metadata fails.
+    pub fn with_metadata_file(mut self, file: F) -> Self {
+        self.metadata_file = Some(file);
+
+        self
+    }
+
+    /// Build a full-text index over the new string values of this layer, and store it in the given file.
+    ///
+    /// See `TextIndex` for details.
+    pub fn with_text_index_file(mut self, file: F) -> Self {
+        self.text_index_file = Some(file);
+
+        self
+    }
+
+    /// Report the progress of committing this builder.
+    pub fn with_progress(mut self, progress: BuildProgressReporter) -> Self {
+        self.progress = progress;
+
+        self
+    }
+
+    /// Limit how many parts of the layer are built at the same time on commit.
+    ///
+    /// See `Parallelism` for details.
+    pub fn with_parallelism(mut self, parallelism: Parallelism) -> Self {
+        self.parallelism = parallelism;
+
+        self
+    }
+
+    /// Set what happens on commit to removals of triples that don't exist in the parent.
+    ///
+    /// By default, such removals are dropped. This has no effect on
+    /// base layer builders.
+    pub fn with_removal_validation(mut self, validation: RemovalValidation) -> Self {
+        self.removal_validation = validation;
+
+        self
+    }
+
+    /// Keep spilled triples in the given directory, so that they can be checkpointed.
+    ///
+    /// On `checkpoint`, all triples are spilled, and the spilled runs
+    /// are moved into this directory. If the directory already
+    /// contains a checkpoint, the triples of that checkpoint are added
+    /// to this builder. This is how an interrupted import is
+    /// resumed. The directory is created on the first checkpoint, and
+    /// removed once the layer is committed.
+    ///
+    /// This has to be called after `with_spill` and before adding any
+    /// triples. Without a spill configuration, triples are only
+    /// spilled on `checkpoint`.
+    pub fn with_checkpoint_directory<P: Into<PathBuf>>(mut self, directory: P) -> io::Result<Self> {
+        if !(self.additions.is_empty() && self.removals.is_empty())
+            || self
+                .addition_spill
+                .iter()
+                .chain(self.removal_spill.iter())
+                .any(|spill| !spill.runs().is_empty())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "checkpoint directory must be set before adding triples",
+            ));
+        }
+
+        let directory = directory.into();
+        let config = match &self.addition_spill {
+            Some(spill) => spill.config().clone(),
+            None => SpillConfig::new(usize::MAX),
+        };
+        let mut addition_spill = TripleSpill::new(config.clone());
+        let mut removal_spill = TripleSpill::new(config);
+
+        let mut listed = HashSet::new();
+        listed.insert(CHECKPOINT_MANIFEST.to_string());
+        match std::fs::read_to_string(directory.join(CHECKPOINT_MANIFEST)) {
+            Ok(manifest) => {
+                for line in manifest.lines() {
+                    let (spill, file) = match line.split_once(' ') {
+                        Some(("a", file)) => (&mut addition_spill, file),
+                        Some(("r", file)) => (&mut removal_spill, file),
+                        _ => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!("unexpected line in checkpoint manifest: {:?}", line),
+                            ))
+                        }
+                    };
+                    spill.adopt_run(ScratchFile::open_kept(directory.join(file))?);
+                    listed.insert(file.to_string());
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        if directory.is_dir() {
+            // anything else is left over from an interrupted checkpoint
+            for entry in std::fs::read_dir(&directory)? {
+                let entry = entry?;
+                if !listed.contains(entry.file_name().to_string_lossy().as_ref()) {
+                    std::fs::remove_file(entry.path())?;
+                }
+            }
+        }
+
+        self.addition_spill = Some(addition_spill);
+        self.removal_spill = Some(removal_spill);
+        self.checkpoint_directory = Some(directory);
+
+        Ok(self)
+    }
+
+    fn spill_if_needed(
+        spill: &mut Option<TripleSpill>,
+        triples: &mut Vec<StringTriple>,
+        error: &mut Option<Arc<io::Error>>,
+    ) {
+        if let Some(spill) = spill {
+            if error.is_none() && spill.should_spill(triples.len()) {
+                if let Err(e) = spill.spill(std::mem::take(triples)) {
+                    // adding triples can't fail, so we report this on commit
+                    *error = Some(Arc::new(e));
+                }
+            }
         }
     }
+
+    /// Remember an added triple, returning false if it was added before.
+    ///
+    /// Only a fingerprint of each triple is kept, so that this doesn't
+    /// undo the memory bound of spilling. In the very unlikely case of
+    /// a fingerprint collision, a new triple is reported as a
+    /// duplicate. The triple is still added either way.
+    fn stage_addition<T: Hash>(&mut self, triple: &T) -> bool {
+        self.staged_fingerprints
+            .insert(self.fingerprint_state.hash_one(triple))
+    }
 }
 
 impl<F: 'static + FileLoad + FileStore + Clone> LayerBuilder for SimpleLayerBuilder<F> {
@@ -96,22 +436,128 @@ impl<F: 'static + FileLoad + FileStore + Clone> LayerBuilder for SimpleLayerBuil
         self.parent.clone()
     }
 
-    fn add_string_triple(&mut self, triple: StringTriple) {
+    fn add_string_triple(&mut self, triple: StringTriple) -> bool {
+        let new = match self.parent.as_ref() {
+            Some(parent) if self.memo.string_triple_exists(&**parent, &triple) => false,
+            _ => self.stage_addition(&triple),
+        };
         self.additions.push(triple);
+        Self::spill_if_needed(
+            &mut self.addition_spill,
+            &mut self.additions,
+            &mut self.spill_error,
+        );
+
+        new
     }
 
-    fn add_id_triple(&mut self, triple: IdTriple) {
+    fn add_id_triple(&mut self, triple: IdTriple) -> bool {
+        // id triples are fingerprinted as string triples where
+        // possible, so that adding the same triple in both forms is
+        // recognized.
+        let new = match self.parent.as_ref() {
+            Some(parent) if parent.id_triple_exists(triple) => false,
+            Some(parent) => match parent.id_triple_to_string(&triple) {
+                Some(string_triple) => self.stage_addition(&string_triple),
+                None => self.stage_addition(&triple),
+            },
+            None => self.stage_addition(&triple),
+        };
         self.id_additions.push(triple);
+
+        new
     }
 
     fn remove_string_triple(&mut self, triple: StringTriple) {
         self.removals.push(triple);
+        Self::spill_if_needed(
+            &mut self.removal_spill,
+            &mut self.removals,
+            &mut self.spill_error,
+        );
     }
 
     fn remove_id_triple(&mut self, triple: IdTriple) {
         self.id_removals.push(triple);
     }
 
+    fn add_string_quad(&mut self, quad: StringQuad) {
+        self.quad_additions.push(quad);
+    }
+
+    fn remove_string_quad(&mut self, quad: StringQuad) {
+        self.quad_removals.push(quad);
+    }
+
+    fn set_metadata(&mut self, metadata: LayerMetadata) {
+        self.metadata = Some(metadata);
+    }
+
+    fn set_progress(&mut self, progress: BuildProgressReporter) {
+        self.progress = progress;
+    }
+
+    fn checkpoint(&mut self) -> io::Result<()> {
+        let directory = self.checkpoint_directory.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "this layer builder has no checkpoint directory",
+            )
+        })?;
+        if let Some(e) = &self.spill_error {
+            return Err(io::Error::new(
+                e.kind(),
+                format!("failed to spill triples to disk: {}", e),
+            ));
+        }
+        if !(self.id_additions.is_empty()
+            && self.id_removals.is_empty()
+            && self.quad_additions.is_empty()
+            && self.quad_removals.is_empty())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "only string triples can be checkpointed",
+            ));
+        }
+
+        let addition_spill = self
+            .addition_spill
+            .as_mut()
+            .expect("checkpointing builder should spill");
+        let removal_spill = self
+            .removal_spill
+            .as_mut()
+            .expect("checkpointing builder should spill");
+        std::fs::create_dir_all(directory)?;
+        if !self.additions.is_empty() {
+            addition_spill.spill(std::mem::take(&mut self.additions))?;
+        }
+        if !self.removals.is_empty() {
+            removal_spill.spill(std::mem::take(&mut self.removals))?;
+        }
+
+        addition_spill.keep_runs_in(directory)?;
+        removal_spill.keep_runs_in(directory)?;
+
+        let mut manifest = String::new();
+        for (kind, spill) in [("a", &*addition_spill), ("r", &*removal_spill)] {
+            for run in spill.runs() {
+                let file = run.path().file_name().expect("run should have a file name");
+                writeln!(manifest, "{} {}", kind, file.to_string_lossy())
+                    .expect("writing to a string should succeed");
+            }
+        }
+
+        // replace the manifest atomically, so a crash leaves either the old or the new checkpoint
+        let tmp_path = directory.join(format!("{}.tmp", CHECKPOINT_MANIFEST));
+        let mut tmp = std::fs::File::create(&tmp_path)?;
+        tmp.write_all(manifest.as_bytes())?;
+        tmp.sync_all()?;
+        std::fs::rename(&tmp_path, directory.join(CHECKPOINT_MANIFEST))?;
+        std::fs::File::open(directory)?.sync_all()
+    }
+
     fn commit(self) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
         let SimpleLayerBuilder {
             name: _,
@@ -121,46 +567,106 @@ impl<F: 'static + FileLoad + FileStore + Clone> LayerBuilder for SimpleLayerBuil
             id_additions,
             removals,
             id_removals,
+            addition_spill,
+            removal_spill,
+            spill_error,
+            space_check,
+            quad_additions,
+            quad_removals,
+            quad_files,
+            parallelism,
+            checkpoint_directory,
+            removal_validation,
+            metadata,
+            metadata_file,
+            text_index_file,
+            progress,
+            staged_fingerprints: _,
+            fingerprint_state: _,
+            memo,
         } = self;
 
-        let (mut additions, mut removals) = rayon::join(
-            || {
-                let mut additions: Vec<_> = match parent.as_ref() {
-                    None => additions
-                        .into_iter()
-                        .map(|triple| triple.to_unresolved())
-                        .collect(),
-                    Some(parent) => additions
-                        .into_par_iter()
-                        .map(move |triple| parent.string_triple_to_partially_resolved(triple))
-                        .collect(),
-                };
+        if let Some(e) = spill_error {
+            return Box::pin(future::err(io::Error::new(
+                e.kind(),
+                format!("failed to spill triples to disk: {}", e),
+            )));
+        }
+
+        if metadata.is_some() && metadata_file.is_none() {
+            return Box::pin(future::err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "this layer builder can not store metadata",
+            )));
+        }
 
-                additions.extend(id_additions.into_iter().map(|triple| triple.to_resolved()));
-                additions.par_sort_unstable();
-                additions.dedup();
+        let (quad_additions, quad_removals) = cancel_quads(quad_additions, quad_removals);
+        if quad_files.is_none() && !(quad_additions.is_empty() && quad_removals.is_empty()) {
+            return Box::pin(future::err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "this layer builder can not store quads",
+            )));
+        }
 
-                additions
+        let additions = match addition_spill {
+            Some(spill) => match spill.merge(additions) {
+                Ok(additions) => additions,
+                Err(e) => return Box::pin(future::err(e)),
             },
-            || {
-                let mut removals: Vec<_> = match parent.as_ref() {
-                    None => removals
-                        .into_iter()
-                        .map(|triple| triple.to_unresolved())
-                        .collect(),
-                    Some(parent) => removals
-                        .into_par_iter()
-                        .map(move |triple| parent.string_triple_to_partially_resolved(triple))
-                        .collect(),
-                };
+            None => additions,
+        };
+        let removals = match removal_spill {
+            Some(spill) => match spill.merge(removals) {
+                Ok(removals) => removals,
+                Err(e) => return Box::pin(future::err(e)),
+            },
+            None => removals,
+        };
 
-                removals.extend(id_removals.into_iter().map(|triple| triple.to_resolved()));
-                removals.par_sort_unstable();
-                removals.dedup();
+        let (mut additions, mut removals) = parallelism.install(|| {
+            rayon::join(
+                || {
+                    let mut additions: Vec<_> = match parent.as_ref() {
+                        None => additions
+                            .into_iter()
+                            .map(|triple| triple.to_unresolved())
+                            .collect(),
+                        Some(parent) => additions
+                            .into_par_iter()
+                            .map(|triple| {
+                                memo.string_triple_to_partially_resolved(&**parent, triple)
+                            })
+                            .collect(),
+                    };
 
-                removals
-            },
-        );
+                    additions.extend(id_additions.into_iter().map(|triple| triple.to_resolved()));
+                    additions.par_sort_unstable();
+                    additions.dedup();
+
+                    additions
+                },
+                || {
+                    let mut removals: Vec<_> = match parent.as_ref() {
+                        None => removals
+                            .into_iter()
+                            .map(|triple| triple.to_unresolved())
+                            .collect(),
+                        Some(parent) => removals
+                            .into_par_iter()
+                            .map(|triple| {
+                                memo.string_triple_to_partially_resolved(&**parent, triple)
+                            })
+                            .collect(),
+                    };
+
+                    removals.extend(id_removals.into_iter().map(|triple| triple.to_resolved()));
+                    removals.par_sort_unstable();
+                    removals.dedup();
+
+                    removals
+                },
+            )
+        });
 
         // there's now a sorted list of additions and a sorted list of
         // removals, all as resolved as they can possibly be at this
@@ -173,47 +679,136 @@ impl<F: 'static + FileLoad + FileStore + Clone> LayerBuilder for SimpleLayerBuil
 
         zero_equivalents(&mut additions, &mut removals);
 
+        if let Some(parent) = parent.as_ref() {
+            if removal_validation == RemovalValidation::Strict {
+                let missing: Vec<usize> = parallelism.install(|| {
+                    removals
+                        .par_iter()
+                        .enumerate()
+                        .filter(|(_, triple)| !removal_exists(&**parent, triple))
+                        .map(|(ix, _)| ix)
+                        .collect()
+                });
+
+                if !missing.is_empty() {
+                    let count = missing.len();
+                    let triples = missing
+                        .into_iter()
+                        .filter_map(|ix| partially_resolved_to_string(&**parent, &removals[ix]))
+                        .collect();
+                    return Box::pin(future::err(InvalidRemovalsError { count, triples }.into()));
+                }
+            }
+        }
+
         // in addition, all removals that aren't resolved at this
         // point are actually no-ops.
         if parent.is_some() {
-            removals
-                .par_iter_mut()
-                .for_each(|triple| triple.make_resolved_or_zero())
+            parallelism.install(|| {
+                removals
+                    .par_iter_mut()
+                    .for_each(|triple| triple.make_resolved_or_zero())
+            });
         }
 
+        // no-ops have been set to (0,0,0) by now
+        let no_op = Some(IdTriple::new(0, 0, 0));
+        progress.report(BuildProgress::Sorted {
+            additions: additions
+                .iter()
+                .filter(|t| t.as_resolved() != no_op)
+                .count() as u64,
+            removals: removals.iter().filter(|t| t.as_resolved() != no_op).count() as u64,
+        });
+
+        // quads go into the same dictionaries as triples. Removed
+        // quads that the parent can't resolve are no-ops.
+        let (quad_graphs, quad_triples): (Vec<_>, Vec<_>) = quad_additions
+            .into_iter()
+            .map(|quad| {
+                let (graph, triple) = quad.into_parts();
+                let triple = match parent.as_ref() {
+                    None => triple.to_unresolved(),
+                    Some(parent) => parent.string_triple_to_partially_resolved(triple),
+                };
+                (graph, triple)
+            })
+            .unzip();
+        let quad_removals: Vec<_> = match parent.as_ref() {
+            None => Vec::new(),
+            Some(parent) => quad_removals
+                .into_iter()
+                .filter_map(|quad| {
+                    let (graph, triple) = quad.into_parts();
+                    parent
+                        .string_triple_to_id(&triple)
+                        .map(|triple| (graph, triple))
+                })
+                .collect(),
+        };
+
         // collect all strings we don't yet know about
         let (unresolved_nodes, unresolved_predicates, unresolved_values) =
-            collect_unresolved_strings(&additions);
+            parallelism.install(|| collect_unresolved_strings(&additions, &quad_triples));
+
+        let estimated_size = space_check.as_ref().map(|_| {
+            let strings = unresolved_nodes
+                .iter()
+                .chain(unresolved_predicates.iter())
+                .chain(unresolved_values.iter());
+            let (string_bytes, string_count) =
+                strings.fold((0, 0), |(bytes, count), s| (bytes + s.len(), count + 1));
+            estimate_layer_size(
+                string_bytes,
+                string_count,
+                additions.len() + removals.len() + quad_triples.len() + quad_removals.len(),
+            )
+        });
+
+        let dictionaries_written = BuildProgress::DictionariesWritten {
+            nodes: unresolved_nodes.len() as u64,
+            predicates: unresolved_predicates.len() as u64,
+            values: unresolved_values.len() as u64,
+        };
 
         // time to build things
-        Box::pin(async {
-            match parent {
+        Box::pin(async move {
+            if let (Some(check), Some(estimated_size)) = (space_check, estimated_size) {
+                check.check(estimated_size).await?;
+            }
+
+            let (quad_additions, value_map) = match parent {
                 Some(parent) => {
                     let files = files.into_child();
-                    let mut builder =
-                        ChildLayerFileBuilder::from_files(parent.clone(), &files).await?;
-
-                    let node_ids = builder.add_nodes(unresolved_nodes.clone()).await?;
-                    let predicate_ids = builder
-                        .add_predicates(unresolved_predicates.clone())
-                        .await?;
-                    let value_ids = builder.add_values(unresolved_values.clone()).await?;
-
-                    let mut builder = builder.into_phase2().await?;
+                    let builder = ChildLayerFileBuilder::from_files(parent.clone(), &files)
+                        .await?
+                        .with_parallelism(parallelism.clone());
+
+                    // none of these strings are known to the parent, so
+                    // they are numbered in order starting from 1.
+                    let builder = builder
+                        .into_phase2_with_dictionaries(
+                            unresolved_nodes.clone(),
+                            unresolved_predicates.clone(),
+                            unresolved_values.clone(),
+                        )
+                        .await?
+                        .with_progress(progress.clone());
+                    progress.report(dictionaries_written);
 
                     let counts = parent.all_counts();
                     let parent_node_offset = counts.node_count as u64 + counts.value_count as u64;
                     let parent_predicate_offset = counts.predicate_count as u64;
                     let mut node_map = HashMap::new();
-                    for (node, id) in unresolved_nodes.into_iter().zip(node_ids) {
+                    for (node, id) in unresolved_nodes.into_iter().zip(1..) {
                         node_map.insert(node, id + parent_node_offset);
                     }
                     let mut predicate_map = HashMap::new();
-                    for (predicate, id) in unresolved_predicates.into_iter().zip(predicate_ids) {
+                    for (predicate, id) in unresolved_predicates.into_iter().zip(1..) {
                         predicate_map.insert(predicate, id + parent_predicate_offset);
                     }
                     let mut value_map = HashMap::new();
-                    for (value, id) in unresolved_values.into_iter().zip(value_ids) {
+                    for (value, id) in unresolved_values.into_iter().zip(1..) {
                         value_map.insert(value, id + parent_node_offset + node_map.len() as u64);
                     }
 
@@ -224,40 +819,53 @@ impl<F: 'static + FileLoad + FileStore + Clone> LayerBuilder for SimpleLayerBuil
                                 .expect("triple should have been resolvable")
                         })
                         .collect();
-                    add_triples.par_sort_unstable();
+                    parallelism.install(|| add_triples.par_sort_unstable());
                     let remove_triples: Vec<_> = removals
                         .into_iter()
                         .filter_map(|r| r.as_resolved())
                         .collect();
 
-                    // TODO this should be in parallel
-                    builder.add_id_triples(add_triples).await?;
-                    builder.remove_id_triples(remove_triples).await?;
-                    builder.finalize().await
+                    builder
+                        .finalize_with_id_triples(add_triples, remove_triples)
+                        .await?;
+
+                    let quad_additions = resolve_quads(
+                        quad_graphs,
+                        quad_triples,
+                        &node_map,
+                        &predicate_map,
+                        &value_map,
+                    );
+
+                    (quad_additions, value_map)
                 }
                 None => {
                     // TODO almost same as above, should be more generic
                     let files = files.into_base();
-                    let mut builder = BaseLayerFileBuilder::from_files(&files).await?;
-
-                    let node_ids = builder.add_nodes(unresolved_nodes.clone()).await?;
-                    let predicate_ids = builder
-                        .add_predicates(unresolved_predicates.clone())
-                        .await?;
-                    let value_ids = builder.add_values(unresolved_values.clone()).await?;
-
-                    let mut builder = builder.into_phase2().await?;
+                    let builder = BaseLayerFileBuilder::from_files(&files)
+                        .await?
+                        .with_parallelism(parallelism.clone());
+
+                    let mut builder = builder
+                        .into_phase2_with_dictionaries(
+                            unresolved_nodes.clone(),
+                            unresolved_predicates.clone(),
+                            unresolved_values.clone(),
+                        )
+                        .await?
+                        .with_progress(progress.clone());
+                    progress.report(dictionaries_written);
 
                     let mut node_map = HashMap::new();
-                    for (node, id) in unresolved_nodes.into_iter().zip(node_ids) {
+                    for (node, id) in unresolved_nodes.into_iter().zip(1..) {
                         node_map.insert(node, id);
                     }
                     let mut predicate_map = HashMap::new();
-                    for (predicate, id) in unresolved_predicates.into_iter().zip(predicate_ids) {
+                    for (predicate, id) in unresolved_predicates.into_iter().zip(1..) {
                         predicate_map.insert(predicate, id);
                     }
                     let mut value_map = HashMap::new();
-                    for (value, id) in unresolved_values.into_iter().zip(value_ids) {
+                    for (value, id) in unresolved_values.into_iter().zip(1..) {
                         value_map.insert(value, id + node_map.len() as u64);
                     }
 
@@ -268,12 +876,48 @@ impl<F: 'static + FileLoad + FileStore + Clone> LayerBuilder for SimpleLayerBuil
                                 .expect("triple should have been resolvable")
                         })
                         .collect();
-                    add_triples.par_sort_unstable();
+                    parallelism.install(|| add_triples.par_sort_unstable());
 
                     builder.add_id_triples(add_triples).await?;
-                    builder.finalize().await
+                    builder.finalize().await?;
+
+                    let quad_additions = resolve_quads(
+                        quad_graphs,
+                        quad_triples,
+                        &node_map,
+                        &predicate_map,
+                        &value_map,
+                    );
+
+                    (quad_additions, value_map)
+                }
+            };
+
+            if let Some(quad_files) = quad_files {
+                write_quads(&quad_files, quad_additions, quad_removals).await?;
+            }
+            if let (Some(metadata), Some(file)) = (metadata, metadata_file) {
+                write_metadata(&file, &metadata).await?;
+            }
+            if let Some(file) = text_index_file {
+                let index = TextIndex::from_values(
+                    value_map.iter().map(|(value, id)| (*id, value.as_str())),
+                );
+                write_text_index(&file, &index).await?;
+            }
+
+            // the checkpoint is not needed anymore now that the layer is complete
+            if let Some(directory) = checkpoint_directory {
+                match crate::runtime::spawn_blocking(move || std::fs::remove_dir_all(directory))
+                    .await?
+                {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
                 }
             }
+            progress.report(BuildProgress::Done);
+
+            Ok(())
         })
     }
 
@@ -320,13 +964,71 @@ fn zero_equivalents(
     }
 }
 
+/// Returns true if the removed triple exists in the parent, or if the removal was crossed off.
+fn removal_exists(parent: &dyn Layer, triple: &PartiallyResolvedTriple) -> bool {
+    match triple.as_resolved() {
+        Some(IdTriple {
+            subject: 0,
+            predicate: 0,
+            object: 0,
+        }) => true,
+        Some(triple) => parent.id_triple_exists(triple),
+        None => false,
+    }
+}
+
+fn partially_resolved_to_string(
+    parent: &dyn Layer,
+    triple: &PartiallyResolvedTriple,
+) -> Option<StringTriple> {
+    let subject = match triple.subject.as_ref() {
+        PossiblyResolved::Unresolved(s) => s.clone(),
+        PossiblyResolved::Resolved(id) => parent.id_subject(id)?,
+    };
+    let predicate = match triple.predicate.as_ref() {
+        PossiblyResolved::Unresolved(p) => p.clone(),
+        PossiblyResolved::Resolved(id) => parent.id_predicate(id)?,
+    };
+    let object = match triple.object.as_ref() {
+        PossiblyResolved::Unresolved(o) => o.clone(),
+        PossiblyResolved::Resolved(id) => parent.id_object(id)?,
+    };
+
+    Some(StringTriple {
+        subject,
+        predicate,
+        object,
+    })
+}
+
+fn resolve_quads(
+    graphs: Vec<String>,
+    triples: Vec<PartiallyResolvedTriple>,
+    node_map: &HashMap<String, u64>,
+    predicate_map: &HashMap<String, u64>,
+    value_map: &HashMap<String, u64>,
+) -> Vec<(String, IdTriple)> {
+    graphs
+        .into_iter()
+        .zip(triples)
+        .map(|(graph, triple)| {
+            let triple = triple
+                .resolve_with(node_map, predicate_map, value_map)
+                .expect("quad should have been resolvable");
+            (graph, triple)
+        })
+        .collect()
+}
+
 fn collect_unresolved_strings(
     triples: &[PartiallyResolvedTriple],
+    quad_triples: &[PartiallyResolvedTriple],
 ) -> (Vec<String>, Vec<String>, Vec<String>) {
     let (unresolved_nodes, (unresolved_predicates, unresolved_values)) = rayon::join(
         || {
             let unresolved_nodes_set: HashSet<_> = triples
                 .par_iter()
+                .chain(quad_triples.par_iter())
                 .filter_map(|triple| {
                     let subject = match triple.subject.is_resolved() {
                         true => None,
@@ -360,6 +1062,7 @@ fn collect_unresolved_strings(
                 || {
                     let unresolved_predicates_set: HashSet<_> = triples
                         .par_iter()
+                        .chain(quad_triples.par_iter())
                         .filter_map(|triple| match triple.predicate.is_resolved() {
                             true => None,
                             false => Some(triple.predicate.as_ref().unwrap_unresolved().to_owned()),
@@ -374,12 +1077,15 @@ fn collect_unresolved_strings(
                 || {
                     let unresolved_values_set: HashSet<_> = triples
                         .par_iter()
+                        .chain(quad_triples.par_iter())
                         .filter_map(|triple| match triple.object.is_resolved() {
                             true => None,
-                            false => match triple.object.as_ref().unwrap_unresolved() {
-                                ObjectType::Value(value) => Some(value.to_owned()),
-                                _ => None,
-                            },
+                            false => triple
+                                .object
+                                .as_ref()
+                                .unwrap_unresolved()
+                                .value_string()
+                                .map(Cow::into_owned),
                         })
                         .collect();
                     let mut unresolved_values: Vec<_> = unresolved_values_set.into_iter().collect();
@@ -742,4 +1448,108 @@ mod tests {
 
         assert!(child_layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
     }
+
+    async fn child_with_removals(validation: RemovalValidation) -> io::Result<Arc<InternalLayer>> {
+        let base_layer = example_base_layer().await;
+        let files = new_child_files();
+        let name = [0, 0, 0, 0, 0];
+        let mut builder = SimpleLayerBuilder::from_parent(name, base_layer.clone(), files.clone())
+            .with_removal_validation(validation);
+
+        builder.remove_string_triple(StringTriple::new_value("pig", "says", "oink"));
+        // all parts are known to the parent, but the triple doesn't exist
+        builder.remove_string_triple(StringTriple::new_value("cow", "says", "oink"));
+        // the parent doesn't know crows
+        builder.remove_string_triple(StringTriple::new_value("crow", "says", "caw"));
+        // removing a triple that is also added is fine
+        builder.remove_string_triple(StringTriple::new_value("duck", "says", "moo"));
+        builder.add_string_triple(StringTriple::new_value("duck", "says", "moo"));
+
+        builder.commit().await?;
+
+        Ok(Arc::new(
+            ChildLayer::load_from_files(name, base_layer, &files)
+                .await?
+                .into(),
+        ))
+    }
+
+    #[tokio::test]
+    async fn validate_removals_on_child_layer() {
+        let layer = child_with_removals(RemovalValidation::Drop).await.unwrap();
+        assert_eq!(1, layer.internal_triple_layer_removal_count());
+        assert!(!layer.string_triple_exists(&StringTriple::new_value("pig", "says", "oink")));
+
+        let error = child_with_removals(RemovalValidation::Strict)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(io::ErrorKind::InvalidInput, error.kind());
+        let error = InvalidRemovalsError::from_io_error(&error).unwrap();
+        assert_eq!(2, error.count);
+        let mut triples = error.triples.clone();
+        triples.sort();
+        assert_eq!(
+            vec![
+                StringTriple::new_value("cow", "says", "oink"),
+                StringTriple::new_value("crow", "says", "caw"),
+            ],
+            triples
+        );
+    }
+
+    #[tokio::test]
+    async fn child_layer_reports_progress() {
+        let base_layer = example_base_layer().await;
+        let files = new_child_files();
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let events2 = events.clone();
+        let mut builder = SimpleLayerBuilder::from_parent([0, 0, 0, 0, 0], base_layer, files)
+            .with_progress(BuildProgressReporter::new(move |p| {
+                events2.lock().unwrap().push(p)
+            }));
+
+        builder.add_string_triple(StringTriple::new_value("horse", "says", "neigh"));
+        builder.add_string_triple(StringTriple::new_node("horse", "likes", "cow"));
+        builder.remove_string_triple(StringTriple::new_value("duck", "says", "quack"));
+        builder.commit().await.unwrap();
+
+        assert_eq!(
+            vec![
+                BuildProgress::Sorted {
+                    additions: 2,
+                    removals: 1
+                },
+                BuildProgress::DictionariesWritten {
+                    nodes: 1,
+                    predicates: 1,
+                    values: 1
+                },
+                BuildProgress::TriplesWritten,
+                BuildProgress::IndexesBuilt,
+                BuildProgress::Done,
+            ],
+            *events.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn adding_triples_reports_novelty() {
+        let base_layer = example_base_layer().await;
+        let files = new_child_files();
+        let mut builder =
+            SimpleLayerBuilder::from_parent([0, 0, 0, 0, 0], base_layer.clone(), files);
+
+        assert!(!builder.add_string_triple(StringTriple::new_value("cow", "says", "moo")));
+        assert!(builder.add_string_triple(StringTriple::new_value("horse", "says", "neigh")));
+        assert!(!builder.add_string_triple(StringTriple::new_value("horse", "says", "neigh")));
+
+        let cow = base_layer.subject_id("cow").unwrap();
+        let pig = base_layer.subject_id("pig").unwrap();
+        let says = base_layer.predicate_id("says").unwrap();
+        let moo = base_layer.object_value_id("moo").unwrap();
+        assert!(!builder.add_id_triple(IdTriple::new(cow, says, moo)));
+        assert!(builder.add_id_triple(IdTriple::new(pig, says, moo)));
+        assert!(!builder.add_string_triple(StringTriple::new_value("pig", "says", "moo")));
+    }
 }
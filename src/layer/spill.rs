@@ -0,0 +1,339 @@
+//! Spilling of builder triples to scratch files.
+//!
+//! A `TripleSpill` keeps sorted runs of string triples in scratch
+//! files. These runs are merged back into a single sorted and
+//! deduplicated list of triples on commit, so that a builder only
+//! ever needs to keep a limited amount of triples in memory while
+//! triples are being added.
+//!
+//! The same mechanism is available for anything else that needs to
+//! be sorted externally through `Spill`.
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use super::layer::*;
+use crate::storage::{ScratchFile, SpillConfig};
+
+const NODE_TAG: u8 = 0;
+const VALUE_TAG: u8 = 1;
+
+/// An item that can be written to and read back from a sorted run.
+pub trait Spillable: Ord + Sized {
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()>;
+    /// Read the next item, returning None at the end of the run.
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<Option<Self>>;
+}
+
+/// Sorted runs of items in scratch files.
+pub struct Spill<T> {
+    config: SpillConfig,
+    runs: Vec<ScratchFile>,
+    _item: std::marker::PhantomData<fn() -> T>,
+}
+
+pub type TripleSpill = Spill<StringTriple>;
+
+impl<T> Clone for Spill<T> {
+    fn clone(&self) -> Self {
+        Self {
+            config: self.config.clone(),
+            runs: self.runs.clone(),
+            _item: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<T: Spillable> Spill<T> {
+    pub fn new(config: SpillConfig) -> Self {
+        Self {
+            config,
+            runs: Vec::new(),
+            _item: std::marker::PhantomData,
+        }
+    }
+
+    pub fn config(&self) -> &SpillConfig {
+        &self.config
+    }
+
+    /// Add a run that was spilled earlier, and which must already be sorted.
+    pub fn adopt_run(&mut self, run: ScratchFile) {
+        self.runs.push(run);
+    }
+
+    /// The scratch files holding the runs spilled so far.
+    pub fn runs(&self) -> &[ScratchFile] {
+        &self.runs
+    }
+
+    /// Move all runs that aren't kept yet to the given directory, and keep them there.
+    ///
+    /// See `ScratchFile::keep_in`.
+    pub fn keep_runs_in(&mut self, directory: &Path) -> io::Result<()> {
+        for run in self.runs.iter_mut() {
+            if !run.is_kept() {
+                *run = run.keep_in(directory)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if the given amount of items should be spilled.
+    pub fn should_spill(&self, len: usize) -> bool {
+        len >= self.config.threshold
+    }
+
+    /// Sort the given items and write them to a new scratch file.
+    pub fn spill(&mut self, mut items: Vec<T>) -> io::Result<()> {
+        items.sort_unstable();
+        items.dedup();
+
+        let file = self.config.scratch_file()?;
+        let mut writer = BufWriter::new(File::create(file.path())?);
+        for item in items {
+            item.write_to(&mut writer)?;
+        }
+        writer.flush()?;
+
+        self.runs.push(file);
+
+        Ok(())
+    }
+
+    /// Merge all spilled runs with the given in-memory items,
+    /// returning the result sorted and without duplicates.
+    pub fn merge(self, items: Vec<T>) -> io::Result<Vec<T>> {
+        self.merge_iter(items)?.collect()
+    }
+
+    /// Merge all spilled runs with the given in-memory items into an
+    /// iterator over the sorted items without duplicates.
+    ///
+    /// Only one item per run is kept in memory at a time. The scratch
+    /// files are removed once the iterator is dropped.
+    pub fn merge_iter(self, mut items: Vec<T>) -> io::Result<MergedRuns<T>> {
+        items.sort_unstable();
+        items.dedup();
+
+        let mut readers = Vec::with_capacity(self.runs.len());
+        for run in &self.runs {
+            readers.push(BufReader::new(File::open(run.path())?));
+        }
+
+        let mut heap = BinaryHeap::new();
+        for (ix, reader) in readers.iter_mut().enumerate() {
+            if let Some(item) = T::read_from(reader)? {
+                heap.push(Reverse((item, ix)));
+            }
+        }
+        let mut in_memory = items.into_iter();
+        if let Some(item) = in_memory.next() {
+            heap.push(Reverse((item, readers.len())));
+        }
+
+        Ok(MergedRuns {
+            _runs: self.runs,
+            readers,
+            in_memory,
+            heap,
+        })
+    }
+}
+
+/// An iterator over the merged runs of a `Spill`.
+pub struct MergedRuns<T> {
+    _runs: Vec<ScratchFile>,
+    readers: Vec<BufReader<File>>,
+    in_memory: std::vec::IntoIter<T>,
+    heap: BinaryHeap<Reverse<(T, usize)>>,
+}
+
+impl<T: Spillable> MergedRuns<T> {
+    fn pop(&mut self) -> io::Result<Option<T>> {
+        let (item, ix) = match self.heap.pop() {
+            Some(Reverse(entry)) => entry,
+            None => return Ok(None),
+        };
+        let next = if ix == self.readers.len() {
+            self.in_memory.next()
+        } else {
+            T::read_from(&mut self.readers[ix])?
+        };
+        if let Some(next) = next {
+            self.heap.push(Reverse((next, ix)));
+        }
+
+        Ok(Some(item))
+    }
+
+    fn next_unique(&mut self) -> io::Result<Option<T>> {
+        let item = match self.pop()? {
+            Some(item) => item,
+            None => return Ok(None),
+        };
+        // every run is deduplicated, but the same item may appear in several runs
+        while matches!(self.heap.peek(), Some(Reverse((next, _))) if *next == item) {
+            self.pop()?;
+        }
+
+        Ok(Some(item))
+    }
+}
+
+impl<T: Spillable> Iterator for MergedRuns<T> {
+    type Item = io::Result<T>;
+
+    fn next(&mut self) -> Option<io::Result<T>> {
+        self.next_unique().transpose()
+    }
+}
+
+impl Spillable for StringTriple {
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_triple(writer, self)
+    }
+
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<Option<Self>> {
+        read_triple(reader)
+    }
+}
+
+impl Spillable for String {
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_string(writer, self)
+    }
+
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<Option<Self>> {
+        match reader.read_u64::<BigEndian>() {
+            Ok(len) => read_string_bytes(reader, len as usize).map(Some),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Spillable for (u64, u64) {
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_u64::<BigEndian>(self.0)?;
+        writer.write_u64::<BigEndian>(self.1)
+    }
+
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<Option<Self>> {
+        let first = match reader.read_u64::<BigEndian>() {
+            Ok(first) => first,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let second = reader.read_u64::<BigEndian>()?;
+
+        Ok(Some((first, second)))
+    }
+}
+
+pub fn write_string<W: Write>(writer: &mut W, s: &str) -> io::Result<()> {
+    writer.write_u64::<BigEndian>(s.len() as u64)?;
+    writer.write_all(s.as_bytes())
+}
+
+fn write_triple<W: Write>(writer: &mut W, triple: &StringTriple) -> io::Result<()> {
+    let (tag, object) = match &triple.object {
+        ObjectType::Node(node) => (NODE_TAG, Cow::Borrowed(node.as_str())),
+        object => (VALUE_TAG, object.value_string().unwrap()),
+    };
+    writer.write_u8(tag)?;
+    write_string(writer, &triple.subject)?;
+    write_string(writer, &triple.predicate)?;
+    write_string(writer, &object)
+}
+
+pub fn read_string<R: Read>(reader: &mut R) -> io::Result<String> {
+    let len = reader.read_u64::<BigEndian>()? as usize;
+    read_string_bytes(reader, len)
+}
+
+fn read_string_bytes<R: Read>(reader: &mut R, len: usize) -> io::Result<String> {
+    let mut buf = vec![0; len];
+    reader.read_exact(&mut buf)?;
+
+    String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn read_triple<R: Read>(reader: &mut R) -> io::Result<Option<StringTriple>> {
+    let tag = match reader.read_u8() {
+        Ok(tag) => tag,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let subject = read_string(reader)?;
+    let predicate = read_string(reader)?;
+    let object = read_string(reader)?;
+    let object = match tag {
+        NODE_TAG => ObjectType::Node(object),
+        VALUE_TAG => ObjectType::from_value_string(object),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected object type in spilled triple",
+            ))
+        }
+    };
+
+    Ok(Some(StringTriple {
+        subject,
+        predicate,
+        object,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn spill_and_merge() {
+        let dir = tempdir().unwrap();
+        let mut spill = TripleSpill::new(SpillConfig::new(2).in_directory(dir.path()));
+
+        spill
+            .spill(vec![
+                StringTriple::new_value("pig", "says", "oink"),
+                StringTriple::new_value("cow", "says", "moo"),
+            ])
+            .unwrap();
+        spill
+            .spill(vec![
+                StringTriple::new_node("cow", "likes", "pig"),
+                StringTriple::new_value("cow", "says", "moo"),
+            ])
+            .unwrap();
+        assert_eq!(2, std::fs::read_dir(dir.path()).unwrap().count());
+
+        let merged = spill
+            .merge(vec![
+                StringTriple::new_value("duck", "says", "quack"),
+                StringTriple::new_value("pig", "says", "oink"),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                StringTriple::new_node("cow", "likes", "pig"),
+                StringTriple::new_value("cow", "says", "moo"),
+                StringTriple::new_value("duck", "says", "quack"),
+                StringTriple::new_value("pig", "says", "oink"),
+            ],
+            merged
+        );
+
+        // the scratch files are gone once the spill is consumed
+        assert_eq!(0, std::fs::read_dir(dir.path()).unwrap().count());
+    }
+}
@@ -0,0 +1,597 @@
+//! An in-memory overlay for changes that have not been committed yet.
+//!
+//! A staging layer wraps a committed layer and keeps track of triples
+//! that were added or removed on top of it. It implements `Layer`, so
+//! the staged changes can be queried in the same way as a committed
+//! layer. Strings that are unknown to the parent get temporary ids
+//! that follow the ids of the parent. These ids are only valid for
+//! this staging layer, and will generally differ from the ids that
+//! are assigned when the changes are committed.
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+use super::filter::Regex;
+use super::layer::*;
+use super::typed::value_string_text;
+use crate::structure::util::sorted_iterator;
+
+/// A layer with uncommitted changes on top of a committed parent.
+#[derive(Clone)]
+pub struct StagingLayer {
+    name: [u32; 5],
+    parent: Arc<dyn Layer>,
+    parent_counts: LayerCounts,
+
+    node_ids: HashMap<String, u64>,
+    value_ids: HashMap<String, u64>,
+    node_values: Vec<ObjectType>,
+    staged_node_count: usize,
+    predicate_ids: HashMap<String, u64>,
+    predicates: Vec<String>,
+
+    additions: Arc<BTreeSet<IdTriple>>,
+    removals: Arc<BTreeSet<IdTriple>>,
+}
+
+impl StagingLayer {
+    /// Create a staging layer without any changes on top of the given parent.
+    ///
+    /// The staging layer gets a random name, so that it can be told
+    /// apart from its parent.
+    pub fn new(parent: Arc<dyn Layer>) -> Self {
+        let parent_counts = parent.all_counts();
+        Self {
+            name: rand::random(),
+            parent,
+            parent_counts,
+
+            node_ids: HashMap::new(),
+            value_ids: HashMap::new(),
+            node_values: Vec::new(),
+            staged_node_count: 0,
+            predicate_ids: HashMap::new(),
+            predicates: Vec::new(),
+
+            additions: Arc::new(BTreeSet::new()),
+            removals: Arc::new(BTreeSet::new()),
+        }
+    }
+
+    /// The committed layer these changes are staged on.
+    pub fn parent(&self) -> &Arc<dyn Layer> {
+        &self.parent
+    }
+
+    /// Returns true if no changes have been staged.
+    pub fn is_empty(&self) -> bool {
+        self.additions.is_empty() && self.removals.is_empty()
+    }
+
+    fn parent_node_and_value_count(&self) -> u64 {
+        (self.parent_counts.node_count + self.parent_counts.value_count) as u64
+    }
+
+    fn staged_node_value(&self, id: u64) -> Option<&ObjectType> {
+        let parent_count = self.parent_node_and_value_count();
+        if id <= parent_count {
+            None
+        } else {
+            self.node_values.get((id - parent_count - 1) as usize)
+        }
+    }
+
+    fn node_value_id_or_stage(&mut self, object: ObjectType) -> u64 {
+        let existing = match &object {
+            ObjectType::Node(node) => self.object_node_id(node),
+            value => value
+                .value_string()
+                .and_then(|value| self.object_value_id(&value)),
+        };
+        if let Some(id) = existing {
+            return id;
+        }
+
+        let id = self.parent_node_and_value_count() + self.node_values.len() as u64 + 1;
+        match &object {
+            ObjectType::Node(node) => {
+                self.staged_node_count += 1;
+                self.node_ids.insert(node.clone(), id);
+            }
+            value => {
+                self.value_ids
+                    .insert(value.value_string().unwrap().into_owned(), id);
+            }
+        }
+        self.node_values.push(object);
+
+        id
+    }
+
+    fn predicate_id_or_stage(&mut self, predicate: String) -> u64 {
+        if let Some(id) = self.predicate_id(&predicate) {
+            return id;
+        }
+
+        self.predicates.push(predicate.clone());
+        let id = (self.parent_counts.predicate_count + self.predicates.len()) as u64;
+        self.predicate_ids.insert(predicate, id);
+
+        id
+    }
+
+    fn check_ids(&self, triple: IdTriple) -> io::Result<()> {
+        if self.id_subject(triple.subject).is_none()
+            || self.id_predicate(triple.predicate).is_none()
+            || self.id_object(triple.object).is_none()
+        {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "triple contains ids that are unknown to the staging layer",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Stage the addition of a string triple.
+    ///
+    /// Returns true if the triple is new, meaning that it neither
+    /// exists in the parent nor was staged before.
+    pub fn add_string_triple(&mut self, triple: StringTriple) -> bool {
+        let subject = self.node_value_id_or_stage(ObjectType::Node(triple.subject));
+        let predicate = self.predicate_id_or_stage(triple.predicate);
+        let object = self.node_value_id_or_stage(triple.object);
+
+        self.stage_addition(IdTriple::new(subject, predicate, object))
+    }
+
+    /// Stage the addition of an id triple.
+    ///
+    /// The ids have to be known to the parent or to this staging
+    /// layer. Returns true if the triple is new, like
+    /// `add_string_triple`.
+    pub fn add_id_triple(&mut self, triple: IdTriple) -> io::Result<bool> {
+        self.check_ids(triple)?;
+
+        Ok(self.stage_addition(triple))
+    }
+
+    /// Stage the removal of a string triple.
+    ///
+    /// Removing a triple that does not exist does nothing.
+    pub fn remove_string_triple(&mut self, triple: StringTriple) {
+        if let Some(triple) = self.string_triple_to_id(&triple) {
+            self.stage_removal(triple);
+        }
+    }
+
+    /// Stage the removal of an id triple.
+    ///
+    /// Removing a triple that does not exist does nothing.
+    pub fn remove_id_triple(&mut self, triple: IdTriple) {
+        self.stage_removal(triple);
+    }
+
+    fn stage_addition(&mut self, triple: IdTriple) -> bool {
+        if self.removals.contains(&triple) {
+            Arc::make_mut(&mut self.removals).remove(&triple);
+            false
+        } else if self.parent.id_triple_exists(triple) || self.additions.contains(&triple) {
+            false
+        } else {
+            Arc::make_mut(&mut self.additions).insert(triple)
+        }
+    }
+
+    fn stage_removal(&mut self, triple: IdTriple) {
+        if self.additions.contains(&triple) {
+            Arc::make_mut(&mut self.additions).remove(&triple);
+        } else if self.parent.id_triple_exists(triple) {
+            Arc::make_mut(&mut self.removals).insert(triple);
+        }
+    }
+
+    /// The staged additions, in subject, predicate, object order.
+    pub fn staged_additions(&self) -> impl Iterator<Item = IdTriple> + '_ {
+        self.additions.iter().copied()
+    }
+
+    /// The staged removals, in subject, predicate, object order.
+    pub fn staged_removals(&self) -> impl Iterator<Item = IdTriple> + '_ {
+        self.removals.iter().copied()
+    }
+
+    /// The staged additions as string triples.
+    pub fn staged_string_additions(&self) -> impl Iterator<Item = StringTriple> + '_ {
+        self.staged_additions().map(move |triple| {
+            self.id_triple_to_string(&triple)
+                .expect("staged triple should resolve to strings")
+        })
+    }
+
+    /// The staged removals as string triples.
+    pub fn staged_string_removals(&self) -> impl Iterator<Item = StringTriple> + '_ {
+        self.staged_removals().map(move |triple| {
+            self.id_triple_to_string(&triple)
+                .expect("staged triple should resolve to strings")
+        })
+    }
+
+    /// Leave out staged removals from an iterator of parent triples, and add matching staged additions.
+    fn overlay<I: 'static + Iterator<Item = IdTriple> + Send>(
+        &self,
+        parent_triples: I,
+        matches: impl Fn(&IdTriple) -> bool,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        let removals = self.removals.clone();
+        let additions: Vec<_> = self.additions.iter().copied().filter(matches).collect();
+        let parent_triples: Box<dyn Iterator<Item = IdTriple> + Send> =
+            Box::new(parent_triples.filter(move |triple| !removals.contains(triple)));
+        let additions: Box<dyn Iterator<Item = IdTriple> + Send> = Box::new(additions.into_iter());
+
+        // merging keeps the triples in order for iterators that are in order
+        Box::new(sorted_iterator(
+            vec![parent_triples, additions],
+            |triples| match triples {
+                [Some(parent), Some(addition)] if addition < parent => Some(1),
+                [Some(_), _] => Some(0),
+                [None, Some(_)] => Some(1),
+                _ => None,
+            },
+        ))
+    }
+}
+
+impl Layer for StagingLayer {
+    fn name(&self) -> [u32; 5] {
+        self.name
+    }
+
+    fn parent_name(&self) -> Option<[u32; 5]> {
+        Some(self.parent.name())
+    }
+
+    fn node_and_value_count(&self) -> usize {
+        self.parent_node_and_value_count() as usize + self.node_values.len()
+    }
+
+    fn predicate_count(&self) -> usize {
+        self.parent_counts.predicate_count + self.predicates.len()
+    }
+
+    fn subject_id(&self, subject: &str) -> Option<u64> {
+        self.object_node_id(subject)
+    }
+
+    fn predicate_id(&self, predicate: &str) -> Option<u64> {
+        self.parent
+            .predicate_id(predicate)
+            .or_else(|| self.predicate_ids.get(predicate).copied())
+    }
+
+    fn object_node_id(&self, object: &str) -> Option<u64> {
+        self.parent
+            .object_node_id(object)
+            .or_else(|| self.node_ids.get(object).copied())
+    }
+
+    fn object_value_id(&self, object: &str) -> Option<u64> {
+        self.parent
+            .object_value_id(object)
+            .or_else(|| self.value_ids.get(object).copied())
+    }
+
+    fn value_ids_in_range(&self, start: Bound<&str>, end: Bound<&str>) -> Vec<u64> {
+        let mut ids = self.parent.value_ids_in_range(start, end);
+        let parent_count = self.parent_node_and_value_count();
+        for (index, object) in self.node_values.iter().enumerate() {
+            if let Some(value) = object.value_string() {
+                if RangeBounds::<str>::contains(&(start, end), &*value) {
+                    ids.push(parent_count + index as u64 + 1);
+                }
+            }
+        }
+
+        ids
+    }
+
+    fn node_ids_matching(&self, regex: &Regex) -> Vec<u64> {
+        let mut ids = self.parent.node_ids_matching(regex);
+        let parent_count = self.parent_node_and_value_count();
+        for (index, object) in self.node_values.iter().enumerate() {
+            if let ObjectType::Node(node) = object {
+                if regex.is_match(node.as_bytes()) {
+                    ids.push(parent_count + index as u64 + 1);
+                }
+            }
+        }
+
+        ids
+    }
+
+    fn predicate_ids_matching(&self, regex: &Regex) -> Vec<u64> {
+        let mut ids = self.parent.predicate_ids_matching(regex);
+        let parent_count = self.parent_counts.predicate_count as u64;
+        for (index, predicate) in self.predicates.iter().enumerate() {
+            if regex.is_match(predicate.as_bytes()) {
+                ids.push(parent_count + index as u64 + 1);
+            }
+        }
+
+        ids
+    }
+
+    fn value_ids_matching(&self, regex: &Regex) -> Vec<u64> {
+        let mut ids = self.parent.value_ids_matching(regex);
+        let parent_count = self.parent_node_and_value_count();
+        for (index, object) in self.node_values.iter().enumerate() {
+            let text = match object {
+                ObjectType::Node(_) => None,
+                ObjectType::Value(value) => value_string_text(value.as_bytes()),
+                ObjectType::LangString { value, .. } => Some(value.as_bytes()),
+            };
+            if text.is_some_and(|text| regex.is_match(text)) {
+                ids.push(parent_count + index as u64 + 1);
+            }
+        }
+
+        ids
+    }
+
+    fn id_subject(&self, id: u64) -> Option<String> {
+        match self.staged_node_value(id) {
+            Some(ObjectType::Node(node)) => Some(node.clone()),
+            Some(_) => None,
+            None => self.parent.id_subject(id),
+        }
+    }
+
+    fn id_predicate(&self, id: u64) -> Option<String> {
+        let parent_count = self.parent_counts.predicate_count as u64;
+        if id <= parent_count {
+            self.parent.id_predicate(id)
+        } else {
+            self.predicates
+                .get((id - parent_count - 1) as usize)
+                .cloned()
+        }
+    }
+
+    fn id_object(&self, id: u64) -> Option<ObjectType> {
+        match self.staged_node_value(id) {
+            Some(object) => Some(object.clone()),
+            None => self.parent.id_object(id),
+        }
+    }
+
+    fn all_counts(&self) -> LayerCounts {
+        LayerCounts {
+            node_count: self.parent_counts.node_count + self.staged_node_count,
+            predicate_count: self.predicate_count(),
+            value_count: self.parent_counts.value_count + self.node_values.len()
+                - self.staged_node_count,
+            triple_addition_count: self.triple_addition_count(),
+            triple_removal_count: self.triple_removal_count(),
+            layer_triple_addition_count: self.additions.len(),
+            layer_triple_removal_count: self.removals.len(),
+        }
+    }
+
+    fn clone_boxed(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+
+    fn triple_exists(&self, subject: u64, predicate: u64, object: u64) -> bool {
+        let triple = IdTriple::new(subject, predicate, object);
+        self.additions.contains(&triple)
+            || (!self.removals.contains(&triple) && self.parent.id_triple_exists(triple))
+    }
+
+    fn triples(&self) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.overlay(self.parent.triples(), |_| true)
+    }
+
+    fn triples_s(&self, subject: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.overlay(self.parent.triples_s(subject), |t| t.subject == subject)
+    }
+
+    fn triples_subject_range(
+        &self,
+        start: u64,
+        end: u64,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.overlay(self.parent.triples_subject_range(start, end), |t| {
+            (start..end).contains(&t.subject)
+        })
+    }
+
+    fn triples_sp(
+        &self,
+        subject: u64,
+        predicate: u64,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.overlay(self.parent.triples_sp(subject, predicate), |t| {
+            t.subject == subject && t.predicate == predicate
+        })
+    }
+
+    fn triples_p(&self, predicate: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.overlay(self.parent.triples_p(predicate), |t| {
+            t.predicate == predicate
+        })
+    }
+
+    fn triples_o(&self, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.overlay(self.parent.triples_o(object), |t| t.object == object)
+    }
+
+    fn estimate_count(
+        &self,
+        subject: Option<u64>,
+        predicate: Option<u64>,
+        object: Option<u64>,
+    ) -> usize {
+        let matches = |t: &&IdTriple| {
+            subject.map(|s| t.subject == s).unwrap_or(true)
+                && predicate.map(|p| t.predicate == p).unwrap_or(true)
+                && object.map(|o| t.object == o).unwrap_or(true)
+        };
+
+        (self.parent.estimate_count(subject, predicate, object)
+            + self.additions.iter().filter(matches).count())
+        .saturating_sub(self.removals.iter().filter(matches).count())
+    }
+
+    fn predicate_stats(&self, predicate: u64) -> PredicateStats {
+        let staged_stats = |triples: &BTreeSet<IdTriple>| {
+            let mut subjects = HashSet::new();
+            let mut objects = HashSet::new();
+            let mut triple_count = 0;
+            for triple in triples.iter().filter(|t| t.predicate == predicate) {
+                subjects.insert(triple.subject);
+                objects.insert(triple.object);
+                triple_count += 1;
+            }
+
+            PredicateStats {
+                triple_count,
+                subject_count: subjects.len(),
+                object_count: objects.len(),
+            }
+        };
+
+        let mut stats = self.parent.predicate_stats(predicate);
+        stats += staged_stats(&self.additions);
+        stats.subtract_removals(staged_stats(&self.removals))
+    }
+
+    fn ancestor_counts(&self, name: [u32; 5]) -> Option<LayerCounts> {
+        if name == self.name {
+            Some(self.all_counts())
+        } else {
+            self.parent.ancestor_counts(name)
+        }
+    }
+
+    fn common_ancestor_counts(&self, other: &dyn Layer) -> Option<LayerCounts> {
+        other
+            .ancestor_counts(self.name)
+            .or_else(|| self.parent.common_ancestor_counts(other))
+    }
+
+    fn triple_addition_count(&self) -> usize {
+        self.parent_counts.triple_addition_count + self.additions.len()
+    }
+
+    fn triple_removal_count(&self) -> usize {
+        self.parent_counts.triple_removal_count + self.removals.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::open_memory_store;
+
+    #[tokio::test]
+    async fn staged_changes_are_visible_before_commit() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+
+        let mut staging = base.open_staging();
+        assert!(staging.is_empty());
+        staging.add_string_triple(StringTriple::new_value("duck", "says", "quack"));
+        staging.add_string_triple(StringTriple::new_node("duck", "likes", "cow"));
+        staging.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        staging.remove_string_triple(StringTriple::new_value("pig", "says", "oink"));
+        staging.add_string_triple(StringTriple::new_value("horse", "says", "neigh"));
+        staging.remove_string_triple(StringTriple::new_value("horse", "says", "neigh"));
+
+        assert!(staging.string_triple_exists(&StringTriple::new_value("duck", "says", "quack")));
+        assert!(staging.string_triple_exists(&StringTriple::new_node("duck", "likes", "cow")));
+        assert!(staging.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        assert!(!staging.string_triple_exists(&StringTriple::new_value("pig", "says", "oink")));
+        assert!(!staging.string_triple_exists(&StringTriple::new_value("horse", "says", "neigh")));
+        assert!(!base.string_triple_exists(&StringTriple::new_value("duck", "says", "quack")));
+
+        assert_eq!(2, staging.staged_additions().count());
+        assert_eq!(1, staging.staged_removals().count());
+        assert_eq!(3, staging.triple_count());
+        assert_eq!(3, staging.triples().count());
+
+        let duck = staging.subject_id("duck").unwrap();
+        let cow = staging.object_node_id("cow").unwrap();
+        let says = staging.predicate_id("says").unwrap();
+        assert_eq!(2, staging.triples_s(duck).count());
+        assert_eq!(2, staging.triples_p(says).count());
+        assert_eq!(
+            vec![duck],
+            staging
+                .triples_o(cow)
+                .map(|t| t.subject)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(2, staging.estimate_count(Some(duck), None, None));
+        assert_eq!(
+            Some("quack".to_string()),
+            staging
+                .triples_sp(duck, says)
+                .next()
+                .and_then(|t| staging.id_object(t.object))
+                .and_then(|o| o.value_string().map(|v| v.into_owned()))
+        );
+
+        let builder = base.open_write().await.unwrap();
+        builder.apply_staged(&staging).unwrap();
+        let layer = builder.commit().await.unwrap();
+
+        let mut expected: Vec<_> = staging
+            .triples()
+            .map(|t| staging.id_triple_to_string(&t).unwrap())
+            .collect();
+        let mut actual: Vec<_> = layer
+            .triples()
+            .map(|t| layer.id_triple_to_string(&t).unwrap())
+            .collect();
+        expected.sort();
+        actual.sort();
+        assert_eq!(expected, actual);
+    }
+
+    #[tokio::test]
+    async fn staged_triples_are_ordered() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+
+        let mut staging = base.open_staging();
+        staging.add_string_triple(StringTriple::new_node("cow", "likes", "pig"));
+        staging.add_string_triple(StringTriple::new_node("duck", "likes", "cow"));
+        staging.add_string_triple(StringTriple::new_value("cow", "eats", "grass"));
+
+        let spo: Vec<_> = staging.triples().collect();
+        let mut expected = spo.clone();
+        expected.sort();
+        assert_eq!(5, spo.len());
+        assert_eq!(expected, spo);
+
+        let pos: Vec<_> = staging.triples_pos().collect();
+        expected.sort_by_key(|t| (t.predicate, t.object, t.subject));
+        assert_eq!(expected, pos);
+    }
+}
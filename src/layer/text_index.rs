@@ -0,0 +1,225 @@
+//! Full-text indexes over string values.
+//!
+//! When text indexing is enabled on a layer store, every layer it
+//! builds gets an inverted index over the words in the string values
+//! that the layer adds to the value dictionary. This index is stored
+//! in a separate file of the layer.
+//!
+//! Since ids are stable across a layer stack, the indexes of all
+//! layers in a stack together cover all values of the top layer.
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use super::spill::{read_string, write_string};
+use super::{ObjectType, Value};
+use crate::storage::{FileLoad, FileStore, SyncableFile};
+
+const TEXT_INDEX_VERSION: u8 = 1;
+
+/// Split a text into lowercased words.
+pub(crate) fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// The inverted index over the words in the string values added by a single layer.
+///
+/// Values are split into words on anything that is not
+/// alphanumeric, and words are lowercased. Typed values other than
+/// strings are not indexed. Values stay in the index when all triples
+/// using them are removed, just like they stay in the value
+/// dictionary.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TextIndex {
+    value_count: u64,
+    /// For every word, the ids of the values containing it, with how often they contain it, ordered by id.
+    postings: BTreeMap<String, Vec<(u64, u32)>>,
+}
+
+impl TextIndex {
+    /// Index the given values, as they are stored in the value dictionary, with their ids.
+    pub fn from_values<'a, I: IntoIterator<Item = (u64, &'a str)>>(values: I) -> Self {
+        let mut index = Self::default();
+        let mut values: Vec<_> = values.into_iter().collect();
+        values.sort_unstable_by_key(|(id, _)| *id);
+        for (id, value) in values {
+            let text = match ObjectType::from_value_string(value.to_owned()).value() {
+                Some(Value::String(text)) | Some(Value::LangString { value: text, .. }) => text,
+                _ => continue,
+            };
+
+            let mut words: HashMap<String, u32> = HashMap::new();
+            for word in tokenize(&text) {
+                *words.entry(word).or_default() += 1;
+            }
+            if words.is_empty() {
+                continue;
+            }
+            index.value_count += 1;
+            for (word, count) in words {
+                index.postings.entry(word).or_default().push((id, count));
+            }
+        }
+
+        index
+    }
+
+    /// The amount of values that contain at least one word.
+    pub fn value_count(&self) -> u64 {
+        self.value_count
+    }
+
+    /// The ids of the values containing the given word, with how often they contain it, ordered by id.
+    ///
+    /// The word is expected to be lowercase.
+    pub fn postings(&self, word: &str) -> &[(u64, u32)] {
+        self.postings.get(word).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Encode this index as it is stored in a layer file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![TEXT_INDEX_VERSION];
+        bytes.write_u64::<BigEndian>(self.value_count).unwrap();
+        bytes
+            .write_u64::<BigEndian>(self.postings.len() as u64)
+            .unwrap();
+        for (word, postings) in self.postings.iter() {
+            write_string(&mut bytes, word).unwrap();
+            bytes.write_u64::<BigEndian>(postings.len() as u64).unwrap();
+            for (id, count) in postings.iter() {
+                bytes.write_u64::<BigEndian>(*id).unwrap();
+                bytes.write_u32::<BigEndian>(*count).unwrap();
+            }
+        }
+
+        bytes
+    }
+
+    /// Decode an index that was encoded with `to_bytes`.
+    pub fn from_bytes(mut bytes: &[u8]) -> io::Result<Self> {
+        let reader = &mut bytes;
+        let version = reader.read_u8()?;
+        if version != TEXT_INDEX_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported text index version {}", version),
+            ));
+        }
+
+        let value_count = reader.read_u64::<BigEndian>()?;
+        let mut postings = BTreeMap::new();
+        for _ in 0..reader.read_u64::<BigEndian>()? {
+            let word = read_string(reader)?;
+            let len = reader.read_u64::<BigEndian>()?;
+            let mut entries = Vec::new();
+            for _ in 0..len {
+                entries.push((
+                    reader.read_u64::<BigEndian>()?,
+                    reader.read_u32::<BigEndian>()?,
+                ));
+            }
+            postings.insert(word, entries);
+        }
+        if !reader.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid text index",
+            ));
+        }
+
+        Ok(Self {
+            value_count,
+            postings,
+        })
+    }
+}
+
+/// Rank the values in the given indexes by how well they match the words of the query.
+///
+/// Every query word that a value contains adds to its score, weighed
+/// by how often the value contains it and by how rare the word is
+/// over all indexes. Values without any of the words are left out.
+/// The result is ordered by descending score, then by id.
+pub fn rank_text_matches(indexes: &[TextIndex], query: &str) -> Vec<(u64, f64)> {
+    let value_count: u64 = indexes.iter().map(|index| index.value_count).sum();
+    let mut words: Vec<_> = tokenize(query).collect();
+    words.sort();
+    words.dedup();
+
+    let mut scores: HashMap<u64, f64> = HashMap::new();
+    for word in words {
+        let matches = || indexes.iter().flat_map(|index| index.postings(&word));
+        let document_count = matches().count();
+        if document_count == 0 {
+            continue;
+        }
+        let weight = (1.0 + value_count as f64 / document_count as f64).ln();
+        for (id, count) in matches() {
+            *scores.entry(*id).or_default() += *count as f64 * weight;
+        }
+    }
+
+    let mut result: Vec<_> = scores.into_iter().collect();
+    result.sort_by(|(id1, score1), (id2, score2)| score2.total_cmp(score1).then(id1.cmp(id2)));
+
+    result
+}
+
+/// Write the text index of a layer to the given file.
+pub async fn write_text_index<F: FileStore>(file: &F, index: &TextIndex) -> io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut writer = file.open_write().await?;
+    writer.write_all(&index.to_bytes()).await?;
+    writer.flush().await?;
+    writer.sync_all().await
+}
+
+/// Read the text index of a layer from the given file, if it exists.
+pub async fn read_text_index<F: FileLoad>(file: &F) -> io::Result<Option<TextIndex>> {
+    if !file.exists().await? {
+        return Ok(None);
+    }
+
+    TextIndex::from_bytes(&file.map().await?).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::TypedValue;
+
+    #[test]
+    fn rank_values_by_query_words() {
+        let lang_string = ObjectType::new_lang_string("Le chat noir", "fr")
+            .value_string()
+            .unwrap()
+            .into_owned();
+        let number = TypedValue::Integer(42).encode();
+        let values = vec![
+            (5, "The black cat sat on the black mat"),
+            (3, "a cat"),
+            (8, "Dogs, not cats!"),
+            (9, lang_string.as_str()),
+            (10, number.as_str()),
+            (11, "..."),
+        ];
+        let index = TextIndex::from_values(values);
+        assert_eq!(4, index.value_count());
+        assert_eq!(&[(5, 2)], index.postings("black"));
+        assert_eq!(&[(3, 1), (5, 1)], index.postings("cat"));
+        assert_eq!(&[(9, 1)], index.postings("chat"));
+        assert!(index.postings("42").is_empty());
+        assert_eq!(index, TextIndex::from_bytes(&index.to_bytes()).unwrap());
+
+        let other = TextIndex::from_values(vec![(20, "black dog")]);
+        let ranked: Vec<_> = rank_text_matches(&[index, other], "Black CAT")
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(vec![5, 3, 20], ranked);
+    }
+}
@@ -0,0 +1,834 @@
+//! Typed literal values.
+//!
+//! Typed values are stored in the value dictionary like any other
+//! value, but in an encoding that sorts the same way as the values
+//! themselves. Since dictionaries are sorted, this allows range
+//! queries over typed values to be answered with a range scan over
+//! the dictionary rather than by decoding every value.
+//!
+//! An encoded typed value starts with the byte `0x01`, followed by a
+//! single character identifying the type and an ASCII payload. Plain
+//! values starting with `0x01` are therefore reserved for typed
+//! values.
+//!
+//! Language-tagged strings are stored in the value dictionary the
+//! same way, as `0x01`, `@`, the language tag, `0x01` and the string
+//! itself. This keeps all strings with the same language tag in one
+//! contiguous range of the dictionary.
+use std::fmt;
+use std::ops::Bound;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use thiserror::Error;
+
+const TYPED_PREFIX: char = '\u{1}';
+
+const BOOLEAN_TAG: char = 'b';
+const DECIMAL_TAG: char = 'd';
+const DOUBLE_TAG: char = 'f';
+const INTEGER_TAG: char = 'i';
+const DATETIME_TAG: char = 't';
+
+const LANG_TAG: char = '@';
+const LANG_SEPARATOR: char = '\u{1}';
+
+const SIGN_BIT: u64 = 1 << 63;
+const DECIMAL_EXP_BIAS: i64 = 0x8000;
+
+/// A literal value of a native datatype.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TypedValue {
+    Boolean(bool),
+    Integer(i64),
+    Decimal(Decimal),
+    Double(f64),
+    DateTime(DateTime),
+}
+
+impl TypedValue {
+    fn tag(&self) -> char {
+        match self {
+            TypedValue::Boolean(_) => BOOLEAN_TAG,
+            TypedValue::Integer(_) => INTEGER_TAG,
+            TypedValue::Decimal(_) => DECIMAL_TAG,
+            TypedValue::Double(_) => DOUBLE_TAG,
+            TypedValue::DateTime(_) => DATETIME_TAG,
+        }
+    }
+
+    /// Encode this value as a value string.
+    ///
+    /// Encoded values of the same type sort in the same order as the
+    /// values they encode. Doubles are ordered by their total order,
+    /// with `-0.0` treated as `0.0`.
+    pub fn encode(&self) -> String {
+        let mut result = String::new();
+        result.push(TYPED_PREFIX);
+        result.push(self.tag());
+        match self {
+            TypedValue::Boolean(b) => result.push(if *b { '1' } else { '0' }),
+            TypedValue::Integer(i) => result.push_str(&encode_integer(*i)),
+            TypedValue::Decimal(d) => result.push_str(&d.encode()),
+            TypedValue::Double(f) => result.push_str(&encode_double(*f)),
+            TypedValue::DateTime(dt) => {
+                result.push_str(&encode_integer(dt.seconds));
+                result.push_str(&format!("{:08X}", dt.nanos));
+            }
+        }
+
+        result
+    }
+
+    /// Decode a value string produced by `encode`.
+    ///
+    /// Returns None if the string is not an encoded typed value.
+    pub fn decode(s: &str) -> Option<TypedValue> {
+        let mut chars = s.chars();
+        if chars.next()? != TYPED_PREFIX {
+            return None;
+        }
+        let tag = chars.next()?;
+        let payload = chars.as_str();
+        match tag {
+            BOOLEAN_TAG => match payload {
+                "0" => Some(TypedValue::Boolean(false)),
+                "1" => Some(TypedValue::Boolean(true)),
+                _ => None,
+            },
+            INTEGER_TAG => decode_integer(payload).map(TypedValue::Integer),
+            DECIMAL_TAG => Decimal::decode(payload).map(TypedValue::Decimal),
+            DOUBLE_TAG => decode_double(payload).map(TypedValue::Double),
+            DATETIME_TAG => {
+                if payload.len() != 24 || !payload.is_char_boundary(16) {
+                    return None;
+                }
+                let seconds = decode_integer(&payload[..16])?;
+                let nanos = decode_hex(&payload[16..])?;
+                if nanos >= 1_000_000_000 {
+                    return None;
+                }
+
+                Some(TypedValue::DateTime(DateTime::new(seconds, nanos as u32)))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<bool> for TypedValue {
+    fn from(b: bool) -> Self {
+        TypedValue::Boolean(b)
+    }
+}
+
+impl From<i64> for TypedValue {
+    fn from(i: i64) -> Self {
+        TypedValue::Integer(i)
+    }
+}
+
+impl From<Decimal> for TypedValue {
+    fn from(d: Decimal) -> Self {
+        TypedValue::Decimal(d)
+    }
+}
+
+impl From<f64> for TypedValue {
+    fn from(f: f64) -> Self {
+        TypedValue::Double(f)
+    }
+}
+
+impl From<DateTime> for TypedValue {
+    fn from(dt: DateTime) -> Self {
+        TypedValue::DateTime(dt)
+    }
+}
+
+/// A literal value in the object position of a triple.
+///
+/// This combines plain strings, language-tagged strings and typed
+/// values, so that literals don't have to be encoded by hand. A value
+/// is stored as an `ObjectType`, and can be read back from one with
+/// `ObjectType::value`. Plain strings starting with `0x01` are
+/// reserved for the encoding of the other kinds of values.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Value {
+    String(String),
+    LangString { value: String, lang: String },
+    Boolean(bool),
+    Integer(i64),
+    Decimal(Decimal),
+    Double(f64),
+    DateTime(DateTime),
+}
+
+impl Value {
+    /// Construct a language-tagged string.
+    pub fn lang_string(value: &str, lang: &str) -> Value {
+        Value::LangString {
+            value: value.to_owned(),
+            lang: lang.to_owned(),
+        }
+    }
+
+    /// The typed value this value maps onto, or None if it is a plain or language-tagged string.
+    pub fn typed_value(&self) -> Option<TypedValue> {
+        match self {
+            Value::String(_) | Value::LangString { .. } => None,
+            Value::Boolean(b) => Some(TypedValue::Boolean(*b)),
+            Value::Integer(i) => Some(TypedValue::Integer(*i)),
+            Value::Decimal(d) => Some(TypedValue::Decimal(d.clone())),
+            Value::Double(f) => Some(TypedValue::Double(*f)),
+            Value::DateTime(dt) => Some(TypedValue::DateTime(*dt)),
+        }
+    }
+}
+
+impl From<TypedValue> for Value {
+    fn from(value: TypedValue) -> Self {
+        match value {
+            TypedValue::Boolean(b) => Value::Boolean(b),
+            TypedValue::Integer(i) => Value::Integer(i),
+            TypedValue::Decimal(d) => Value::Decimal(d),
+            TypedValue::Double(f) => Value::Double(f),
+            TypedValue::DateTime(dt) => Value::DateTime(dt),
+        }
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_owned())
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Boolean(b)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Self {
+        Value::Integer(i)
+    }
+}
+
+impl From<Decimal> for Value {
+    fn from(d: Decimal) -> Self {
+        Value::Decimal(d)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(f: f64) -> Self {
+        Value::Double(f)
+    }
+}
+
+impl From<DateTime> for Value {
+    fn from(dt: DateTime) -> Self {
+        Value::DateTime(dt)
+    }
+}
+
+impl From<SystemTime> for Value {
+    fn from(time: SystemTime) -> Self {
+        Value::DateTime(time.into())
+    }
+}
+
+/// Encode the bounds of a range over typed values.
+///
+/// An unbounded end is replaced with the bound of all values of the
+/// type of the other end. Returns None if both ends are unbounded or
+/// of different types.
+pub(crate) fn encoded_typed_range(
+    start: Bound<&TypedValue>,
+    end: Bound<&TypedValue>,
+) -> Option<(Bound<String>, Bound<String>)> {
+    let tag = |bound: Bound<&TypedValue>| match bound {
+        Bound::Included(v) | Bound::Excluded(v) => Some(v.tag()),
+        Bound::Unbounded => None,
+    };
+    let tag = match (tag(start), tag(end)) {
+        (Some(start), Some(end)) if start == end => start,
+        (Some(tag), None) | (None, Some(tag)) => tag,
+        _ => return None,
+    };
+    let type_start = format!("{}{}", TYPED_PREFIX, tag);
+    let type_end = format!("{}{}", TYPED_PREFIX, (tag as u8 + 1) as char);
+
+    let start = match start {
+        Bound::Unbounded => Bound::Included(type_start),
+        bound => bound.map(TypedValue::encode),
+    };
+    let end = match end {
+        Bound::Unbounded => Bound::Excluded(type_end),
+        bound => bound.map(TypedValue::encode),
+    };
+
+    Some((start, end))
+}
+
+/// Encode a language-tagged string as a value string.
+pub(crate) fn encode_lang_string(value: &str, lang: &str) -> String {
+    format!(
+        "{}{}{}{}{}",
+        TYPED_PREFIX, LANG_TAG, lang, LANG_SEPARATOR, value
+    )
+}
+
+/// Decode a value string into the string and its language tag.
+///
+/// Returns None if the value string is not a language-tagged string.
+pub(crate) fn decode_lang_string(s: &str) -> Option<(&str, &str)> {
+    let rest = s.strip_prefix(TYPED_PREFIX)?.strip_prefix(LANG_TAG)?;
+    let (lang, value) = rest.split_once(LANG_SEPARATOR)?;
+
+    Some((value, lang))
+}
+
+/// The text of a string or language-tagged string, given the bytes of its value string.
+///
+/// Returns None for typed values.
+pub(crate) fn value_string_text(bytes: &[u8]) -> Option<&[u8]> {
+    match bytes.strip_prefix(&[TYPED_PREFIX as u8]) {
+        None => Some(bytes),
+        Some(rest) => {
+            let rest = rest.strip_prefix(&[LANG_TAG as u8])?;
+            let separator = rest.iter().position(|&b| b == LANG_SEPARATOR as u8)?;
+
+            Some(&rest[separator + 1..])
+        }
+    }
+}
+
+/// The bounds of all encoded strings with the given language tag.
+pub(crate) fn encoded_lang_range(lang: &str) -> (Bound<String>, Bound<String>) {
+    let prefix = format!("{}{}{}", TYPED_PREFIX, LANG_TAG, lang);
+    (
+        Bound::Included(format!("{}{}", prefix, LANG_SEPARATOR)),
+        Bound::Excluded(format!("{}{}", prefix, (LANG_SEPARATOR as u8 + 1) as char)),
+    )
+}
+
+fn decode_hex(s: &str) -> Option<u64> {
+    if s.bytes()
+        .all(|b| b.is_ascii_digit() || (b'A'..=b'F').contains(&b))
+    {
+        u64::from_str_radix(s, 16).ok()
+    } else {
+        None
+    }
+}
+
+fn encode_integer(i: i64) -> String {
+    format!("{:016X}", (i as u64) ^ SIGN_BIT)
+}
+
+fn decode_integer(s: &str) -> Option<i64> {
+    if s.len() != 16 {
+        return None;
+    }
+
+    decode_hex(s).map(|u| (u ^ SIGN_BIT) as i64)
+}
+
+fn encode_double(f: f64) -> String {
+    let f = if f == 0.0 {
+        0.0
+    } else if f.is_nan() {
+        f64::NAN
+    } else {
+        f
+    };
+    let bits = f.to_bits();
+    let bits = if bits & SIGN_BIT != 0 {
+        !bits
+    } else {
+        bits | SIGN_BIT
+    };
+
+    format!("{:016X}", bits)
+}
+
+fn decode_double(s: &str) -> Option<f64> {
+    if s.len() != 16 {
+        return None;
+    }
+    let bits = decode_hex(s)?;
+    let bits = if bits & SIGN_BIT != 0 {
+        bits & !SIGN_BIT
+    } else {
+        !bits
+    };
+
+    Some(f64::from_bits(bits))
+}
+
+/// A point in time, as seconds and nanoseconds since the unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DateTime {
+    seconds: i64,
+    nanos: u32,
+}
+
+impl DateTime {
+    /// Construct a new DateTime.
+    ///
+    /// Panics if `nanos` is not less than one second.
+    pub fn new(seconds: i64, nanos: u32) -> Self {
+        assert!(nanos < 1_000_000_000, "nanos must be less than a second");
+        Self { seconds, nanos }
+    }
+
+    /// Seconds since the unix epoch, rounded down.
+    pub fn seconds(&self) -> i64 {
+        self.seconds
+    }
+
+    /// Nanoseconds after `seconds`.
+    pub fn nanos(&self) -> u32 {
+        self.nanos
+    }
+}
+
+impl From<SystemTime> for DateTime {
+    fn from(time: SystemTime) -> Self {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(d) => DateTime::new(d.as_secs() as i64, d.subsec_nanos()),
+            Err(e) => {
+                let d = e.duration();
+                if d.subsec_nanos() == 0 {
+                    DateTime::new(-(d.as_secs() as i64), 0)
+                } else {
+                    DateTime::new(-(d.as_secs() as i64) - 1, 1_000_000_000 - d.subsec_nanos())
+                }
+            }
+        }
+    }
+}
+
+/// Checks that `nanos` is less than a second, like `DateTime::new`.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for DateTime {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        struct Parts {
+            seconds: i64,
+            nanos: u32,
+        }
+
+        let parts = Parts::deserialize(deserializer)?;
+        if parts.nanos >= 1_000_000_000 {
+            return Err(serde::de::Error::custom("nanos must be less than a second"));
+        }
+
+        Ok(DateTime::new(parts.seconds, parts.nanos))
+    }
+}
+
+impl From<DateTime> for SystemTime {
+    fn from(dt: DateTime) -> Self {
+        if dt.seconds >= 0 {
+            UNIX_EPOCH + Duration::new(dt.seconds as u64, dt.nanos)
+        } else {
+            UNIX_EPOCH - Duration::from_secs(dt.seconds.unsigned_abs())
+                + Duration::from_nanos(dt.nanos as u64)
+        }
+    }
+}
+
+/// An error that occurs when parsing a decimal.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ParseDecimalError {
+    #[error("decimal is empty")]
+    Empty,
+    #[error("invalid character {0:?} in decimal")]
+    InvalidCharacter(char),
+    #[error("decimal is too large")]
+    TooLarge,
+}
+
+/// An arbitrary precision decimal number.
+///
+/// Decimals are kept in their canonical form, without leading or
+/// trailing zeros and without a sign on zero.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Decimal(String);
+
+impl Decimal {
+    /// The canonical string representation of this decimal.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    fn is_negative(&self) -> bool {
+        self.0.starts_with('-')
+    }
+
+    /// The exponent of this decimal, such that its value is
+    /// `0.digits * 10^exponent` for its significant digits.
+    fn exponent(&self) -> i64 {
+        let unsigned = self.0.trim_start_matches('-');
+        let (int_part, frac_part) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+        if int_part != "0" {
+            int_part.len() as i64
+        } else {
+            frac_part.trim_start_matches('0').len() as i64 - frac_part.len() as i64
+        }
+    }
+
+    fn encode(&self) -> String {
+        if self.0 == "0" {
+            return "B".to_string();
+        }
+        let exp = self.exponent();
+        let digits = self.significant_digits();
+        if self.is_negative() {
+            let complement: String = digits.bytes().map(|d| (b'9' - d + b'0') as char).collect();
+            format!("A{:04X}{}~", 0xFFFF - (exp + DECIMAL_EXP_BIAS), complement)
+        } else {
+            format!("C{:04X}{}", exp + DECIMAL_EXP_BIAS, digits)
+        }
+    }
+
+    fn significant_digits(&self) -> String {
+        let unsigned = self.0.trim_start_matches('-');
+        let digits: String = unsigned.chars().filter(|c| *c != '.').collect();
+        digits
+            .trim_start_matches('0')
+            .trim_end_matches('0')
+            .to_string()
+    }
+
+    fn decode(s: &str) -> Option<Decimal> {
+        if s == "B" {
+            return Some(Decimal("0".to_string()));
+        }
+        if s.len() < 6 || !s.is_char_boundary(5) {
+            return None;
+        }
+        let exp = decode_hex(&s[1..5])? as i64;
+        let (negative, exp, digits) = match &s[..1] {
+            "A" => {
+                let digits = s[5..].strip_suffix('~')?;
+                if !digits.bytes().all(|d| d.is_ascii_digit()) {
+                    return None;
+                }
+                let digits: String = digits.bytes().map(|d| (b'9' - d + b'0') as char).collect();
+                (true, 0xFFFF - exp - DECIMAL_EXP_BIAS, digits)
+            }
+            "C" => (false, exp - DECIMAL_EXP_BIAS, s[5..].to_string()),
+            _ => return None,
+        };
+        if !digits.bytes().all(|d| d.is_ascii_digit())
+            || digits.starts_with('0')
+            || digits.ends_with('0')
+        {
+            return None;
+        }
+
+        let len = digits.len() as i64;
+        let mut result = String::new();
+        if negative {
+            result.push('-');
+        }
+        if exp <= 0 {
+            result.push_str("0.");
+            result.extend(std::iter::repeat_n('0', -exp as usize));
+            result.push_str(&digits);
+        } else if exp >= len {
+            result.push_str(&digits);
+            result.extend(std::iter::repeat_n('0', (exp - len) as usize));
+        } else {
+            result.push_str(&digits[..exp as usize]);
+            result.push('.');
+            result.push_str(&digits[exp as usize..]);
+        }
+
+        Some(Decimal(result))
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = ParseDecimalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, unsigned) = match s.strip_prefix('-') {
+            Some(unsigned) => (true, unsigned),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (int_part, frac_part) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+        if int_part.is_empty() && frac_part.is_empty() {
+            return Err(ParseDecimalError::Empty);
+        }
+        if let Some(c) = int_part
+            .chars()
+            .chain(frac_part.chars())
+            .find(|c| !c.is_ascii_digit())
+        {
+            return Err(ParseDecimalError::InvalidCharacter(c));
+        }
+
+        let int_part = int_part.trim_start_matches('0');
+        let frac_part = frac_part.trim_end_matches('0');
+        if int_part.len() + frac_part.len() >= DECIMAL_EXP_BIAS as usize {
+            return Err(ParseDecimalError::TooLarge);
+        }
+
+        let mut result = String::new();
+        if negative && !(int_part.is_empty() && frac_part.is_empty()) {
+            result.push('-');
+        }
+        if int_part.is_empty() {
+            result.push('0');
+        } else {
+            result.push_str(int_part);
+        }
+        if !frac_part.is_empty() {
+            result.push('.');
+            result.push_str(frac_part);
+        }
+
+        Ok(Decimal(result))
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Decimals are serialized as their canonical string representation.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Decimal {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Decimal {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Decimal::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::{Layer, ObjectType, StringTriple};
+    use crate::store::open_memory_store;
+
+    fn decimal(s: &str) -> TypedValue {
+        TypedValue::Decimal(s.parse().unwrap())
+    }
+
+    #[test]
+    fn typed_values_roundtrip_in_order() {
+        let ordered = vec![
+            vec![TypedValue::Boolean(false), TypedValue::Boolean(true)],
+            vec![i64::MIN, -300, -1, 0, 1, 2, 256, i64::MAX]
+                .into_iter()
+                .map(TypedValue::Integer)
+                .collect(),
+            vec![
+                f64::NEG_INFINITY,
+                -1.5e10,
+                -2.0,
+                -0.25,
+                0.0,
+                f64::MIN_POSITIVE,
+                0.5,
+                3.0,
+                f64::INFINITY,
+            ]
+            .into_iter()
+            .map(TypedValue::Double)
+            .collect(),
+            vec![
+                "-1000", "-12.5", "-12", "-0.12", "-0.1", "-0.0012", "0", "0.0012", "0.1", "0.12",
+                "12", "12.5", "1000",
+            ]
+            .into_iter()
+            .map(decimal)
+            .collect(),
+            vec![
+                DateTime::new(-100, 5),
+                DateTime::new(-1, 999_999_999),
+                DateTime::new(0, 0),
+                DateTime::new(0, 1),
+                DateTime::new(1_600_000_000, 0),
+            ]
+            .into_iter()
+            .map(TypedValue::DateTime)
+            .collect(),
+        ];
+
+        for values in ordered {
+            let encoded: Vec<_> = values.iter().map(TypedValue::encode).collect();
+            for (value, encoded) in values.iter().zip(encoded.iter()) {
+                assert!(!encoded.contains('\0'));
+                assert_eq!(Some(value.clone()), TypedValue::decode(encoded));
+            }
+            for pair in encoded.windows(2) {
+                assert!(pair[0] < pair[1], "{:?}", pair);
+            }
+        }
+    }
+
+    #[test]
+    fn decimals_are_canonical() {
+        assert_eq!("12.5", "+012.500".parse::<Decimal>().unwrap().as_str());
+        assert_eq!("0", "-0.000".parse::<Decimal>().unwrap().as_str());
+        assert_eq!("-0.01", "-.010".parse::<Decimal>().unwrap().as_str());
+        assert_eq!(encode_double(-0.0), encode_double(0.0));
+        assert_eq!(Err(ParseDecimalError::Empty), "-".parse::<Decimal>());
+        assert_eq!(
+            Err(ParseDecimalError::InvalidCharacter('e')),
+            "1e5".parse::<Decimal>()
+        );
+        assert_eq!(None, TypedValue::decode("plain value"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_roundtrip() {
+        let values = vec![
+            Value::lang_string("hallo", "nl"),
+            Value::Decimal("12.50".parse().unwrap()),
+            Value::DateTime(DateTime::new(-1, 500)),
+        ];
+        let json = serde_json::to_string(&values).unwrap();
+        assert_eq!(values, serde_json::from_str::<Vec<Value>>(&json).unwrap());
+        assert_eq!(
+            r#"{"Decimal":"12.5"}"#,
+            serde_json::to_string(&decimal("12.50")).unwrap()
+        );
+
+        let triple = StringTriple::new_literal("cow", "weight", Value::Integer(550));
+        let json = serde_json::to_string(&triple).unwrap();
+        assert_eq!(triple, serde_json::from_str(&json).unwrap());
+
+        assert!(serde_json::from_str::<Decimal>("\"1e5\"").is_err());
+        assert!(serde_json::from_str::<DateTime>(r#"{"seconds":0,"nanos":1000000000}"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn literal_values_roundtrip() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        let values: Vec<Value> = vec![
+            "moo".into(),
+            Value::lang_string("meuh", "fr"),
+            true.into(),
+            42.into(),
+            "12.50".parse::<Decimal>().unwrap().into(),
+            2.5.into(),
+            DateTime::new(1_600_000_000, 5).into(),
+        ];
+        for value in values.iter() {
+            builder
+                .add_string_triple(StringTriple::new_literal("cow", "says", value.clone()))
+                .unwrap();
+        }
+        let layer = builder.commit().await.unwrap();
+
+        let mut stored: Vec<_> = layer
+            .triples_s(layer.subject_id("cow").unwrap())
+            .map(|t| layer.id_object(t.object).unwrap().value().unwrap())
+            .collect();
+        assert_eq!(values.len(), stored.len());
+        for value in values {
+            let position = stored.iter().position(|v| *v == value).unwrap();
+            stored.remove(position);
+        }
+        assert_eq!(
+            Some(ObjectType::Value("moo".to_string())),
+            layer.id_object(layer.object_value_id("moo").unwrap())
+        );
+        assert_eq!(None, ObjectType::Node("cow".to_string()).value());
+    }
+
+    #[tokio::test]
+    async fn query_typed_value_ranges() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        for i in 0..10 {
+            builder
+                .add_string_triple(StringTriple::new_typed("a", "num", (i * 10).into()))
+                .unwrap();
+        }
+        builder
+            .add_string_triple(StringTriple::new_typed("a", "flag", true.into()))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("a", "name", "foo"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+
+        let builder = base.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_typed("b", "num", 35.into()))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_typed("b", "num", (-5).into()))
+            .unwrap();
+        let child = builder.commit().await.unwrap();
+
+        let values = |start: Bound<TypedValue>, end: Bound<TypedValue>| {
+            let mut result: Vec<_> = child
+                .typed_value_ids_in_range(start.as_ref(), end.as_ref())
+                .into_iter()
+                .map(|id| child.id_object(id).unwrap().typed_value().unwrap())
+                .collect();
+            result.sort_by_key(|v| v.encode());
+            result
+        };
+        let ints = |v: &[i64]| {
+            v.iter()
+                .map(|i| TypedValue::Integer(*i))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            ints(&[30, 35, 40, 50]),
+            values(Bound::Included(30.into()), Bound::Excluded(60.into()))
+        );
+        assert_eq!(
+            ints(&[-5, 0, 10]),
+            values(Bound::Unbounded, Bound::Included(10.into()))
+        );
+        assert_eq!(
+            ints(&[80, 90]),
+            values(Bound::Excluded(70.into()), Bound::Unbounded)
+        );
+        assert_eq!(
+            vec![TypedValue::Boolean(true)],
+            values(Bound::Included(false.into()), Bound::Unbounded)
+        );
+        assert!(values(Bound::Included(0.into()), Bound::Included(true.into())).is_empty());
+
+        let ids = child.value_ids_in_range(Bound::Included("foo"), Bound::Included("foo"));
+        assert_eq!(1, ids.len());
+        assert_eq!(
+            Some(ObjectType::Value("foo".to_string())),
+            child.id_object(ids[0])
+        );
+    }
+}
@@ -20,6 +20,17 @@
 //! which embeds its own tokio runtime, exposing a purely synchronous
 //! API.
 //!
+//! Storing data in directories requires the `fs` feature, which is
+//! enabled by default. Without it, the crate compiles to `wasm32`,
+//! leaving the memory backend and any storage backend implemented by
+//! the user. These run under any executor, such as the one of
+//! `wasm-bindgen-futures`, but the directory store and the sync
+//! wrapper are not available:
+//!
+//! ```text
+//! cargo build --target wasm32-unknown-unknown --no-default-features
+//! ```
+//!
 //! Most users will probably only need to use the types and functions
 //! in the `store` module (or `store::sync` for the synchronous
 //! version). This module provides a high-level API which should be
@@ -28,15 +39,37 @@
 //! The `structure`, `layer`, and `storage` module expose the inner
 //! workings of terminus-store. They are useful for implementing new
 //! storage backends, or writing analysis and recovery tools.
+//!
+//! The `query` module answers queries made up of several triple
+//! patterns over a layer, working in terms of the ids of that layer.
+//!
+//! The `io` module reads RDF documents into layers.
+//!
+//! With the `ffi` feature, the `ffi` module exposes the synchronous
+//! API through a C interface, for embedding in other languages. With
+//! the `python` feature, the `python` module does the same as a
+//! Python extension module.
 #[macro_use]
 extern crate lazy_static;
 
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod io;
 pub mod layer;
+#[cfg(feature = "python")]
+pub mod python;
 //pub mod logging;
+pub mod query;
+mod runtime;
 pub mod storage;
 pub mod store;
 pub mod structure;
 
 pub use layer::Layer;
-pub use store::sync::{open_sync_directory_store, open_sync_memory_store};
-pub use store::{open_directory_store, open_memory_store};
+pub use store::open_memory_store;
+#[cfg(feature = "fs")]
+pub use store::sync::{
+    open_sync_directory_store, open_sync_memory_store, open_sync_read_only_directory_store,
+};
+#[cfg(feature = "fs")]
+pub use store::{open_directory_store, open_read_only_directory_store};
@@ -0,0 +1,379 @@
+//! Python bindings for scripting imports and audits against a store.
+//!
+//! The bindings expose stores, graphs, layers and layer builders as
+//! the Python classes `Store`, `NamedGraph`, `Layer` and
+//! `LayerBuilder` of the `terminus_store` module. All calls block
+//! until they are done, releasing the GIL while they wait. Triples
+//! are returned as tuples of `(subject, predicate, object,
+//! object_is_node)`, where the object of a typed value is given as
+//! its lexical form. I/O errors are raised as `OSError`.
+//!
+//! ```python
+//! from terminus_store import Store
+//!
+//! store = Store("/path/to/store")
+//! graph = store.create("farm")
+//! builder = store.create_base_layer()
+//! builder.add_triple("cow", "says", "moo")
+//! builder.add_triple("cow", "likes", "duck", object_is_node=True)
+//! graph.set_head(builder.commit())
+//!
+//! for subject, predicate, obj, is_node in graph.head().triples(predicate="says"):
+//!     print(subject, obj)
+//! ```
+//!
+//! This module requires the `python` feature. To build an extension
+//! module, run `cargo rustc --release --features
+//! python,pyo3/extension-module --crate-type cdylib`, and rename the
+//! resulting library to `terminus_store.so` (or `terminus_store.pyd`
+//! on Windows).
+use std::sync::Mutex;
+
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use tokio::fs::File;
+use tokio::io::BufReader;
+
+use crate::io::ntriples::literal_parts;
+use crate::layer::{Layer, ObjectType, StringTriple, Value};
+use crate::storage::name_to_string;
+use crate::store::sync::task_sync;
+use crate::store::{
+    open_directory_store, open_memory_store, NamedGraph, Store, StoreLayer, StoreLayerBuilder,
+};
+
+type Triple = (String, String, String, bool);
+
+fn string_triple(
+    subject: &str,
+    predicate: &str,
+    object: &str,
+    object_is_node: bool,
+) -> StringTriple {
+    if object_is_node {
+        StringTriple::new_node(subject, predicate, object)
+    } else {
+        StringTriple::new_value(subject, predicate, object)
+    }
+}
+
+/// A store of graphs, in memory or in a directory.
+#[pyclass(name = "Store", module = "terminus_store")]
+pub struct PyStore {
+    inner: Store,
+}
+
+#[pymethods]
+impl PyStore {
+    /// Open the store in the given directory, or a new store in memory if no directory is given.
+    #[new]
+    #[pyo3(signature = (path=None))]
+    fn new(path: Option<&str>) -> Self {
+        let inner = match path {
+            Some(path) => open_directory_store(path),
+            None => open_memory_store(),
+        };
+
+        Self { inner }
+    }
+
+    /// Create a graph, or open it if it already exists.
+    fn create(&self, py: Python<'_>, name: &str) -> PyResult<PyNamedGraph> {
+        let inner = py.allow_threads(|| task_sync(self.inner.create(name)))?;
+
+        Ok(PyNamedGraph { inner })
+    }
+
+    /// Open a graph, returning None if it doesn't exist.
+    fn open(&self, py: Python<'_>, name: &str) -> PyResult<Option<PyNamedGraph>> {
+        let inner = py.allow_threads(|| task_sync(self.inner.open(name)))?;
+
+        Ok(inner.map(|inner| PyNamedGraph { inner }))
+    }
+
+    /// The names of all graphs in this store.
+    fn graphs(&self, py: Python<'_>) -> PyResult<Vec<String>> {
+        let graphs = py.allow_threads(|| task_sync(self.inner.graphs()))?;
+
+        Ok(graphs
+            .iter()
+            .map(|graph| graph.name().to_string())
+            .collect())
+    }
+
+    /// Start building a new base layer.
+    fn create_base_layer(&self, py: Python<'_>) -> PyResult<PyLayerBuilder> {
+        let inner = py.allow_threads(|| task_sync(self.inner.create_base_layer()))?;
+
+        Ok(PyLayerBuilder { inner })
+    }
+
+    /// Create a base layer with all triples of an N-Triples file.
+    ///
+    /// Blank nodes are skolemized with the given scope.
+    #[pyo3(signature = (path, scope=""))]
+    fn import_ntriples(&self, py: Python<'_>, path: &str, scope: &str) -> PyResult<PyLayer> {
+        let inner = py.allow_threads(|| {
+            task_sync(async {
+                let file = File::open(path).await?;
+                self.inner
+                    .import_ntriples(BufReader::new(file), scope)
+                    .await
+            })
+        })?;
+
+        Ok(PyLayer { inner })
+    }
+}
+
+/// A named graph, pointing at a layer.
+#[pyclass(name = "NamedGraph", module = "terminus_store")]
+pub struct PyNamedGraph {
+    inner: NamedGraph,
+}
+
+#[pymethods]
+impl PyNamedGraph {
+    #[getter]
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    /// The layer this graph points at, or None if it doesn't point at a layer yet.
+    fn head(&self, py: Python<'_>) -> PyResult<Option<PyLayer>> {
+        let inner = py.allow_threads(|| task_sync(self.inner.head()))?;
+
+        Ok(inner.map(|inner| PyLayer { inner }))
+    }
+
+    /// Point this graph at a layer, returning False if the layer doesn't descend from the current head.
+    fn set_head(&self, py: Python<'_>, layer: &PyLayer) -> PyResult<bool> {
+        Ok(py.allow_threads(|| task_sync(self.inner.set_head(&layer.inner)))?)
+    }
+}
+
+/// A committed layer.
+#[pyclass(name = "Layer", module = "terminus_store")]
+pub struct PyLayer {
+    inner: StoreLayer,
+}
+
+#[pymethods]
+impl PyLayer {
+    /// The name of this layer, as a hexadecimal string.
+    #[getter]
+    fn name(&self) -> String {
+        name_to_string(self.inner.name())
+    }
+
+    /// Start building a child layer of this layer.
+    fn open_write(&self, py: Python<'_>) -> PyResult<PyLayerBuilder> {
+        let inner = py.allow_threads(|| task_sync(self.inner.open_write()))?;
+
+        Ok(PyLayerBuilder { inner })
+    }
+
+    /// Iterate over the triples matching a pattern, where None matches anything.
+    ///
+    /// The object is a node if `object_is_node` is True, and a string value otherwise.
+    #[pyo3(signature = (subject=None, predicate=None, object=None, object_is_node=false))]
+    fn triples(
+        &self,
+        subject: Option<&str>,
+        predicate: Option<&str>,
+        object: Option<&str>,
+        object_is_node: bool,
+    ) -> PyTriples {
+        let object = object.map(|object| {
+            if object_is_node {
+                ObjectType::Node(object.to_string())
+            } else {
+                ObjectType::from(Value::from(object.to_string()))
+            }
+        });
+        let triples = self.inner.query_iter(subject, predicate, object);
+
+        PyTriples {
+            inner: Mutex::new(triples),
+        }
+    }
+
+    fn __iter__(&self) -> PyTriples {
+        self.triples(None, None, None, false)
+    }
+
+    /// Returns True if this layer contains the given triple.
+    #[pyo3(signature = (subject, predicate, object, object_is_node=false))]
+    fn contains(&self, subject: &str, predicate: &str, object: &str, object_is_node: bool) -> bool {
+        self.inner
+            .string_triple_exists(&string_triple(subject, predicate, object, object_is_node))
+    }
+
+    /// Create a child layer adding all triples of an N-Triples file to this layer.
+    ///
+    /// Blank nodes are skolemized with the given scope.
+    #[pyo3(signature = (path, scope=""))]
+    fn import_ntriples(&self, py: Python<'_>, path: &str, scope: &str) -> PyResult<PyLayer> {
+        let inner = py.allow_threads(|| {
+            task_sync(async {
+                let file = File::open(path).await?;
+                self.inner
+                    .import_ntriples(BufReader::new(file), scope)
+                    .await
+            })
+        })?;
+
+        Ok(PyLayer { inner })
+    }
+
+    /// Write all triples of this layer to an N-Triples file, returning the amount of triples written.
+    ///
+    /// Skolem IRIs of the given scope are written as blank nodes.
+    #[pyo3(signature = (path, scope=""))]
+    fn export_ntriples(&self, py: Python<'_>, path: &str, scope: &str) -> PyResult<usize> {
+        Ok(py.allow_threads(|| {
+            task_sync(async {
+                let file = File::create(path).await?;
+                self.inner.export_ntriples(file, scope).await
+            })
+        })?)
+    }
+}
+
+/// A builder for a new layer.
+#[pyclass(name = "LayerBuilder", module = "terminus_store")]
+pub struct PyLayerBuilder {
+    inner: StoreLayerBuilder,
+}
+
+#[pymethods]
+impl PyLayerBuilder {
+    /// Add a triple. The object is a node if `object_is_node` is True, and a string value otherwise.
+    #[pyo3(signature = (subject, predicate, object, object_is_node=false))]
+    fn add_triple(
+        &self,
+        subject: &str,
+        predicate: &str,
+        object: &str,
+        object_is_node: bool,
+    ) -> PyResult<()> {
+        self.inner
+            .add_string_triple(string_triple(subject, predicate, object, object_is_node))?;
+
+        Ok(())
+    }
+
+    /// Remove a triple. The object is a node if `object_is_node` is True, and a string value otherwise.
+    #[pyo3(signature = (subject, predicate, object, object_is_node=false))]
+    fn remove_triple(
+        &self,
+        subject: &str,
+        predicate: &str,
+        object: &str,
+        object_is_node: bool,
+    ) -> PyResult<()> {
+        self.inner.remove_string_triple(string_triple(
+            subject,
+            predicate,
+            object,
+            object_is_node,
+        ))?;
+
+        Ok(())
+    }
+
+    /// Commit this builder, returning the new layer.
+    fn commit(&self, py: Python<'_>) -> PyResult<PyLayer> {
+        let inner = py.allow_threads(|| task_sync(self.inner.commit()))?;
+
+        Ok(PyLayer { inner })
+    }
+}
+
+/// An iterator over triples, as returned by `Layer.triples`.
+#[pyclass(name = "Triples", module = "terminus_store")]
+pub struct PyTriples {
+    inner: Mutex<Box<dyn Iterator<Item = StringTriple> + Send>>,
+}
+
+#[pymethods]
+impl PyTriples {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&self) -> PyResult<Option<Triple>> {
+        let mut triples = self
+            .inner
+            .lock()
+            .map_err(|_| PyRuntimeError::new_err("triple iterator was poisoned"))?;
+
+        Ok(triples.next().map(|triple| match triple.object {
+            ObjectType::Node(node) => (triple.subject, triple.predicate, node, true),
+            object => (
+                triple.subject,
+                triple.predicate,
+                literal_parts(object.value().unwrap()).0,
+                false,
+            ),
+        }))
+    }
+}
+
+/// The `terminus_store` Python module.
+#[pymodule]
+pub fn terminus_store(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyStore>()?;
+    module.add_class::<PyNamedGraph>()?;
+    module.add_class::<PyLayer>()?;
+    module.add_class::<PyLayerBuilder>()?;
+    module.add_class::<PyTriples>()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyDict;
+    use std::ffi::CString;
+
+    #[test]
+    fn script_store_from_python() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let module = PyModule::new(py, "terminus_store").unwrap();
+            terminus_store(&module).unwrap();
+            let globals = PyDict::new(py);
+            globals.set_item("terminus_store", module).unwrap();
+            let script = CString::new(
+                r#"
+store = terminus_store.Store()
+assert store.open("farm") is None
+graph = store.create("farm")
+builder = store.create_base_layer()
+builder.add_triple("cow", "says", "moo")
+builder.add_triple("cow", "likes", "duck", object_is_node=True)
+builder.add_triple("duck", "says", "quack")
+assert graph.set_head(builder.commit())
+
+builder = graph.head().open_write()
+builder.remove_triple("duck", "says", "quack")
+layer = builder.commit()
+assert graph.set_head(layer)
+
+assert store.graphs() == ["farm"]
+assert len(layer.name) == 40
+assert layer.contains("cow", "likes", "duck", object_is_node=True)
+assert not layer.contains("cow", "likes", "duck")
+assert list(layer.triples(predicate="says")) == [("cow", "says", "moo", False)]
+assert list(layer.triples(object="moo")) == [("cow", "says", "moo", False)]
+assert list(layer.triples(object="duck")) == []
+assert sorted(layer) == [("cow", "likes", "duck", True), ("cow", "says", "moo", False)]
+"#,
+            )
+            .unwrap();
+            py.run(&script, Some(&globals), None).unwrap();
+        });
+    }
+}
@@ -0,0 +1,433 @@
+//! Evaluation of basic graph patterns.
+//!
+//! A basic graph pattern is a set of triple patterns that all have to
+//! match, where a variable used in several patterns has to match the
+//! same id in each of them. Patterns are evaluated one by one, in an
+//! order picked by a `QueryPlan`, and each pattern is joined with the
+//! bindings of the patterns before it.
+//!
+//! Every triple index returns its triples ordered by the first
+//! position that the pattern leaves open, in subject, predicate,
+//! object order. When the bindings so far are ordered by the same
+//! variable that the next pattern is ordered by, the two can be
+//! merged with a single pass over both. Otherwise, the next pattern is
+//! looked up in the indexes once for each binding so far, with all
+//! variables it shares with that binding filled in.
+use std::collections::VecDeque;
+use std::iter::Peekable;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures::stream::Stream;
+
+use crate::layer::{IdTriple, Layer};
+
+/// A subject, predicate or object in a triple pattern.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Term {
+    /// A variable, matching any id.
+    Variable(String),
+    /// A fixed id.
+    Id(u64),
+}
+
+impl Term {
+    /// A variable with the given name.
+    pub fn var(name: &str) -> Self {
+        Term::Variable(name.to_owned())
+    }
+}
+
+impl From<u64> for Term {
+    fn from(id: u64) -> Self {
+        Term::Id(id)
+    }
+}
+
+/// A triple in which any part may be a variable.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TriplePattern {
+    pub subject: Term,
+    pub predicate: Term,
+    pub object: Term,
+}
+
+impl TriplePattern {
+    /// Construct a new triple pattern.
+    pub fn new(subject: Term, predicate: Term, object: Term) -> Self {
+        Self {
+            subject,
+            predicate,
+            object,
+        }
+    }
+}
+
+/// The ids matched by the variables of a query, for a single result.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bindings {
+    pub(super) variables: Arc<[String]>,
+    pub(super) values: Vec<u64>,
+}
+
+impl Bindings {
+    /// The id bound to the given variable, or None if the query does not use the variable.
+    pub fn get(&self, variable: &str) -> Option<u64> {
+        self.variables
+            .iter()
+            .position(|v| v == variable)
+            .map(|index| self.values[index])
+    }
+
+    /// The variables and their ids, in the order the variables first appear in the query.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.variables
+            .iter()
+            .map(String::as_str)
+            .zip(self.values.iter().copied())
+    }
+}
+
+/// A stream of bindings, as returned by `BasicGraphPattern::evaluate`.
+pub type BindingStream = Pin<Box<dyn Stream<Item = Bindings> + Send>>;
+
+/// A conjunction of triple patterns, as described in the module documentation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BasicGraphPattern {
+    patterns: Vec<TriplePattern>,
+}
+
+impl BasicGraphPattern {
+    /// A basic graph pattern without any triple patterns, which matches once without binding anything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a triple pattern.
+    pub fn with_pattern(mut self, subject: Term, predicate: Term, object: Term) -> Self {
+        self.patterns
+            .push(TriplePattern::new(subject, predicate, object));
+        self
+    }
+
+    /// The triple patterns, in the order they were added.
+    pub fn patterns(&self) -> &[TriplePattern] {
+        &self.patterns
+    }
+
+    /// The variables used in this pattern, in order of first appearance.
+    pub fn variables(&self) -> Vec<String> {
+        let mut variables: Vec<String> = Vec::new();
+        for pattern in self.patterns.iter() {
+            for term in [&pattern.subject, &pattern.predicate, &pattern.object] {
+                if let Term::Variable(name) = term {
+                    if !variables.contains(name) {
+                        variables.push(name.clone());
+                    }
+                }
+            }
+        }
+
+        variables
+    }
+
+    /// Stream the bindings of all matches of this pattern in the given layer.
+    ///
+    /// The patterns are evaluated in the order picked by `plan`.
+    pub fn evaluate(&self, layer: &dyn Layer) -> BindingStream {
+        self.plan(layer).evaluate(layer)
+    }
+
+    /// Iterator over the bindings of all matches of this pattern in the given layer.
+    ///
+    /// This is the synchronous version of `evaluate`.
+    pub fn evaluate_iter(&self, layer: &dyn Layer) -> Box<dyn Iterator<Item = Bindings> + Send> {
+        self.plan(layer).evaluate_iter(layer)
+    }
+
+    /// The amount of matches of this pattern in the given layer.
+    ///
+    /// A single triple pattern without repeated variables is counted
+    /// with `Layer::count_matching`, often without iterating over any
+    /// triples. Other patterns are counted by evaluating them.
+    pub fn count(&self, layer: &dyn Layer) -> usize {
+        let variables = self.variables();
+        if let [pattern] = self.patterns.as_slice() {
+            let variable_count = [&pattern.subject, &pattern.predicate, &pattern.object]
+                .iter()
+                .filter(|term| matches!(term, Term::Variable(_)))
+                .count();
+            if variable_count == variables.len() {
+                let (subject, predicate, object) =
+                    CompiledPattern::new(pattern, &variables).resolve(|_| None);
+                return layer.count_matching(subject, predicate, object);
+            }
+        }
+
+        self.evaluate_iter(layer).count()
+    }
+}
+
+/// Partially bound variables, indexed by variable.
+pub(super) type Row = Vec<Option<u64>>;
+pub(super) type Rows = Box<dyn Iterator<Item = Row> + Send>;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(super) enum Slot {
+    Variable(usize),
+    Id(u64),
+}
+
+/// A triple pattern with its variables replaced by their index.
+#[derive(Clone, Copy)]
+pub(super) struct CompiledPattern(pub(super) [Slot; 3]);
+
+impl CompiledPattern {
+    pub(super) fn new(pattern: &TriplePattern, variables: &[String]) -> Self {
+        let slot = |term: &Term| match term {
+            Term::Id(id) => Slot::Id(*id),
+            Term::Variable(name) => Slot::Variable(
+                variables
+                    .iter()
+                    .position(|v| v == name)
+                    .expect("variable should be known"),
+            ),
+        };
+
+        Self([
+            slot(&pattern.subject),
+            slot(&pattern.predicate),
+            slot(&pattern.object),
+        ])
+    }
+
+    /// The variable that index lookups for this pattern alone are ordered by.
+    pub(super) fn ordered_by(&self) -> Option<usize> {
+        self.0.iter().find_map(|slot| match slot {
+            Slot::Variable(variable) => Some(*variable),
+            _ => None,
+        })
+    }
+
+    /// The ids of this pattern, given the ids of the variables.
+    pub(super) fn resolve(
+        &self,
+        variable_id: impl Fn(usize) -> Option<u64>,
+    ) -> (Option<u64>, Option<u64>, Option<u64>) {
+        let resolve = |slot: &Slot| match slot {
+            Slot::Id(id) => Some(*id),
+            Slot::Variable(variable) => variable_id(*variable),
+        };
+
+        (
+            resolve(&self.0[0]),
+            resolve(&self.0[1]),
+            resolve(&self.0[2]),
+        )
+    }
+
+    /// Bind the variables of this pattern to the given triple, or None if the triple conflicts with the row.
+    pub(super) fn extend(&self, row: &[Option<u64>], triple: IdTriple) -> Option<Row> {
+        let mut row = row.to_vec();
+        for (slot, id) in self.0.iter().zip(triple_ids(triple)) {
+            match slot {
+                Slot::Id(fixed) if *fixed != id => return None,
+                Slot::Variable(variable) => match row[*variable] {
+                    Some(bound) if bound != id => return None,
+                    _ => row[*variable] = Some(id),
+                },
+                _ => {}
+            }
+        }
+
+        Some(row)
+    }
+}
+
+fn triple_ids(triple: IdTriple) -> [u64; 3] {
+    [triple.subject, triple.predicate, triple.object]
+}
+
+/// A merge join of rows and the triples of a pattern that are both ordered by the same variable.
+pub(super) struct MergeJoin {
+    rows: Peekable<Rows>,
+    triples: Peekable<Box<dyn Iterator<Item = IdTriple> + Send>>,
+    pattern: CompiledPattern,
+    variable: usize,
+    position: usize,
+    group: Vec<IdTriple>,
+    group_id: Option<u64>,
+    output: VecDeque<Row>,
+}
+
+impl MergeJoin {
+    pub(super) fn new(rows: Rows, layer: &Arc<dyn Layer>, pattern: CompiledPattern) -> Self {
+        let variable = pattern
+            .ordered_by()
+            .expect("merge joined pattern should have a variable");
+        let position = pattern
+            .0
+            .iter()
+            .position(|slot| *slot == Slot::Variable(variable))
+            .unwrap();
+        let (subject, predicate, object) = pattern.resolve(|_| None);
+
+        Self {
+            rows: rows.peekable(),
+            triples: layer
+                .triples_matching(subject, predicate, object)
+                .peekable(),
+            pattern,
+            variable,
+            position,
+            group: Vec::new(),
+            group_id: None,
+            output: VecDeque::new(),
+        }
+    }
+}
+
+impl Iterator for MergeJoin {
+    type Item = Row;
+
+    fn next(&mut self) -> Option<Row> {
+        loop {
+            if let Some(row) = self.output.pop_front() {
+                return Some(row);
+            }
+
+            let row_id = self.rows.peek()?[self.variable];
+            if row_id.is_some() && row_id == self.group_id {
+                let row = self.rows.next().unwrap();
+                let pattern = self.pattern;
+                self.output.extend(
+                    self.group
+                        .iter()
+                        .filter_map(|triple| pattern.extend(&row, *triple)),
+                );
+                continue;
+            }
+
+            let row_id = row_id.expect("merge joined variable should be bound");
+            let triple_id = triple_ids(*self.triples.peek()?)[self.position];
+            if row_id < triple_id {
+                self.rows.next();
+            } else if triple_id < row_id {
+                self.triples.next();
+            } else {
+                self.group.clear();
+                let position = self.position;
+                while let Some(triple) = self
+                    .triples
+                    .next_if(|triple| triple_ids(*triple)[position] == triple_id)
+                {
+                    self.group.push(triple);
+                }
+                self.group_id = Some(triple_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::StringTriple;
+    use crate::store::open_memory_store;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn evaluate_joins_over_shared_variables() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        for (subject, predicate, object) in [
+            ("cow", "a", "animal"),
+            ("pig", "a", "animal"),
+            ("duck", "a", "animal"),
+            ("barn", "a", "building"),
+            ("cow", "lives_in", "barn"),
+            ("pig", "lives_in", "barn"),
+            ("duck", "lives_in", "pond"),
+            ("barn", "colour", "red"),
+            ("cow", "likes", "cow"),
+        ] {
+            builder
+                .add_string_triple(StringTriple::new_node(subject, predicate, object))
+                .unwrap();
+        }
+        let base = builder.commit().await.unwrap();
+        let builder = base.open_write().await.unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_node("pig", "lives_in", "barn"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("sheep", "a", "animal"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("sheep", "lives_in", "barn"))
+            .unwrap();
+        let layer = builder.commit().await.unwrap();
+
+        let node = |name| layer.object_node_id(name).unwrap();
+        let predicate = |name| layer.predicate_id(name).unwrap();
+        let names = |bindings: Vec<Bindings>, variable| {
+            let mut names: Vec<_> = bindings
+                .iter()
+                .map(|b| layer.id_subject(b.get(variable).unwrap()).unwrap())
+                .collect();
+            names.sort();
+            names
+        };
+
+        // a star join on the subject, merged
+        let pattern = BasicGraphPattern::new()
+            .with_pattern(Term::var("x"), predicate("a").into(), node("animal").into())
+            .with_pattern(Term::var("x"), predicate("lives_in").into(), Term::var("y"));
+        let bindings: Vec<_> = pattern.evaluate(&layer).collect().await;
+        assert_eq!(vec!["x", "y"], pattern.variables());
+        assert_eq!(bindings.len(), pattern.count(&layer));
+        assert_eq!(vec!["cow", "duck", "sheep"], names(bindings.clone(), "x"));
+
+        // a chain from object to subject, looked up for each binding
+        let pattern = pattern.with_pattern(
+            Term::var("y"),
+            predicate("colour").into(),
+            node("red").into(),
+        );
+        let bindings: Vec<_> = pattern.evaluate(&layer).collect().await;
+        assert_eq!(vec!["cow", "sheep"], names(bindings.clone(), "x"));
+        assert!(bindings.iter().all(|b| b.get("y") == Some(node("barn"))));
+        assert_eq!(
+            vec![("x", node("cow")), ("y", node("barn"))],
+            bindings
+                .iter()
+                .find(|b| b.get("x") == Some(node("cow")))
+                .unwrap()
+                .iter()
+                .collect::<Vec<_>>()
+        );
+
+        // a variable used twice in one pattern
+        let pattern =
+            BasicGraphPattern::new().with_pattern(Term::var("x"), Term::var("p"), Term::var("x"));
+        let bindings: Vec<_> = pattern.evaluate_iter(&layer).collect();
+        assert_eq!(vec!["cow"], names(bindings, "x"));
+        assert_eq!(1, pattern.count(&layer));
+        let pattern = BasicGraphPattern::new().with_pattern(
+            Term::var("x"),
+            predicate("a").into(),
+            Term::var("y"),
+        );
+        assert_eq!(pattern.evaluate_iter(&layer).count(), pattern.count(&layer));
+
+        assert_eq!(1, BasicGraphPattern::new().evaluate(&layer).count().await);
+        let pattern = BasicGraphPattern::new()
+            .with_pattern(
+                Term::var("x"),
+                predicate("a").into(),
+                node("building").into(),
+            )
+            .with_pattern(Term::var("x"), predicate("lives_in").into(), Term::var("y"));
+        assert_eq!(0, pattern.evaluate(&layer).count().await);
+    }
+}
@@ -0,0 +1,27 @@
+//! Queries over layers.
+//!
+//! The layer API answers single triple patterns. This module builds on
+//! top of that, answering queries made up of several patterns, like a
+//! basic graph pattern or a property path. Queries work entirely on
+//! ids, so constants in a query have to be resolved through a layer
+//! first, using for example `Layer::subject_id`, and results can be
+//! resolved back to strings through the same layer.
+//!
+//! Query results are computed lazily. Both the iterators and the
+//! streams returned by queries only look at the indexes when the next
+//! result is asked for, without collecting intermediate results, so
+//! taking the first few results of a query over a large layer is
+//! cheap.
+mod bgp;
+mod path;
+mod plan;
+mod rules;
+mod stream;
+mod traversal;
+
+pub use bgp::*;
+pub use path::*;
+pub use plan::*;
+pub use rules::*;
+pub(crate) use stream::IterStream;
+pub use traversal::*;
@@ -0,0 +1,293 @@
+//! Evaluation of property paths.
+//!
+//! A property path describes a route through the graph in terms of
+//! predicates, like a SPARQL property path. Paths are evaluated
+//! starting from a known node, as a pipeline of iterators over the
+//! triple indexes. No intermediate results are collected, except for
+//! the nodes visited by a repeated path, which are kept to make sure
+//! every node is only returned and followed once.
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
+
+use crate::layer::Layer;
+
+/// A path through the graph, as described in the module documentation.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PropertyPath {
+    /// A single triple with the given predicate.
+    Predicate(u64),
+    /// The path, followed from object to subject.
+    Inverse(Arc<PropertyPath>),
+    /// The first path, followed by the second path.
+    Sequence(Arc<PropertyPath>, Arc<PropertyPath>),
+    /// Either of the paths.
+    Alternative(Arc<PropertyPath>, Arc<PropertyPath>),
+    /// The path, followed any amount of times, including none at all.
+    ZeroOrMore(Arc<PropertyPath>),
+    /// The path, followed at least once.
+    OneOrMore(Arc<PropertyPath>),
+}
+
+impl PropertyPath {
+    /// A path following a single triple with the given predicate.
+    pub fn predicate(predicate: u64) -> Self {
+        PropertyPath::Predicate(predicate)
+    }
+
+    /// This path, followed from object to subject.
+    pub fn inverse(self) -> Self {
+        PropertyPath::Inverse(Arc::new(self))
+    }
+
+    /// This path, followed by the other path.
+    pub fn then(self, other: PropertyPath) -> Self {
+        PropertyPath::Sequence(Arc::new(self), Arc::new(other))
+    }
+
+    /// Either this path or the other path.
+    pub fn or(self, other: PropertyPath) -> Self {
+        PropertyPath::Alternative(Arc::new(self), Arc::new(other))
+    }
+
+    /// This path, followed any amount of times, including none at all.
+    pub fn zero_or_more(self) -> Self {
+        PropertyPath::ZeroOrMore(Arc::new(self))
+    }
+
+    /// This path, followed at least once.
+    pub fn one_or_more(self) -> Self {
+        PropertyPath::OneOrMore(Arc::new(self))
+    }
+
+    /// Iterator over the ids of the nodes and values at the end of this path, when starting at the given subject.
+    ///
+    /// Like in SPARQL, sequences and alternatives return an end
+    /// point once for each way it can be reached, while repeated
+    /// paths return each end point only once.
+    pub fn objects(&self, layer: &dyn Layer, subject: u64) -> Box<dyn Iterator<Item = u64> + Send> {
+        follow(
+            layer.clone_boxed().into(),
+            Arc::new(self.clone()),
+            subject,
+            Direction::Forward,
+        )
+    }
+
+    /// Iterator over the ids of the nodes at the start of this path, when ending at the given object.
+    ///
+    /// See `objects`.
+    pub fn subjects(&self, layer: &dyn Layer, object: u64) -> Box<dyn Iterator<Item = u64> + Send> {
+        follow(
+            layer.clone_boxed().into(),
+            Arc::new(self.clone()),
+            object,
+            Direction::Backward,
+        )
+    }
+
+    /// Returns true if this path leads from the given subject to the given object.
+    pub fn connects(&self, layer: &dyn Layer, subject: u64, object: u64) -> bool {
+        self.objects(layer, subject).any(|id| id == object)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Backward,
+}
+
+impl Direction {
+    fn reverse(self) -> Self {
+        match self {
+            Direction::Forward => Direction::Backward,
+            Direction::Backward => Direction::Forward,
+        }
+    }
+}
+
+fn follow(
+    layer: Arc<dyn Layer>,
+    path: Arc<PropertyPath>,
+    start: u64,
+    direction: Direction,
+) -> Box<dyn Iterator<Item = u64> + Send> {
+    match &*path {
+        PropertyPath::Predicate(predicate) => {
+            let predicate = *predicate;
+            match direction {
+                Direction::Forward => {
+                    Box::new(layer.triples_sp(start, predicate).map(|t| t.object))
+                }
+                Direction::Backward => Box::new(
+                    layer
+                        .triples_o(start)
+                        .filter(move |t| t.predicate == predicate)
+                        .map(|t| t.subject),
+                ),
+            }
+        }
+        PropertyPath::Inverse(path) => follow(layer, path.clone(), start, direction.reverse()),
+        PropertyPath::Sequence(first, second) => {
+            let (first, second) = match direction {
+                Direction::Forward => (first.clone(), second.clone()),
+                Direction::Backward => (second.clone(), first.clone()),
+            };
+            Box::new(
+                follow(layer.clone(), first, start, direction)
+                    .flat_map(move |id| follow(layer.clone(), second.clone(), id, direction)),
+            )
+        }
+        PropertyPath::Alternative(first, second) => Box::new(
+            follow(layer.clone(), first.clone(), start, direction).chain(follow(
+                layer,
+                second.clone(),
+                start,
+                direction,
+            )),
+        ),
+        PropertyPath::ZeroOrMore(path) => {
+            Box::new(Repeat::new(layer, path.clone(), start, direction, true))
+        }
+        PropertyPath::OneOrMore(path) => {
+            Box::new(Repeat::new(layer, path.clone(), start, direction, false))
+        }
+    }
+}
+
+/// Breadth-first iterator over everything reachable by repeating a path.
+struct Repeat {
+    layer: Arc<dyn Layer>,
+    path: Arc<PropertyPath>,
+    direction: Direction,
+    visited: HashSet<u64>,
+    queue: VecDeque<u64>,
+    current: Box<dyn Iterator<Item = u64> + Send>,
+}
+
+impl Repeat {
+    fn new(
+        layer: Arc<dyn Layer>,
+        path: Arc<PropertyPath>,
+        start: u64,
+        direction: Direction,
+        include_start: bool,
+    ) -> Self {
+        // when included, the start is returned and queued like any other node
+        let mut queue = VecDeque::new();
+        let current: Box<dyn Iterator<Item = u64> + Send> = if include_start {
+            Box::new(std::iter::once(start))
+        } else {
+            queue.push_back(start);
+            Box::new(std::iter::empty())
+        };
+
+        Self {
+            layer,
+            path,
+            direction,
+            visited: HashSet::new(),
+            queue,
+            current,
+        }
+    }
+}
+
+impl Iterator for Repeat {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        loop {
+            for id in self.current.by_ref() {
+                if self.visited.insert(id) {
+                    self.queue.push_back(id);
+                    return Some(id);
+                }
+            }
+
+            let next = self.queue.pop_front()?;
+            self.current = follow(self.layer.clone(), self.path.clone(), next, self.direction);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::StringTriple;
+    use crate::store::open_memory_store;
+
+    #[tokio::test]
+    async fn follow_property_paths() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        for (subject, predicate, object) in [
+            ("duck", "a", "bird"),
+            ("bird", "subclass_of", "animal"),
+            ("animal", "subclass_of", "thing"),
+            ("thing", "subclass_of", "thing"),
+            ("cow", "a", "mammal"),
+            ("mammal", "subclass_of", "animal"),
+            ("cow", "likes", "duck"),
+            ("farmer", "owns", "cow"),
+        ] {
+            builder
+                .add_string_triple(StringTriple::new_node(subject, predicate, object))
+                .unwrap();
+        }
+        let layer = builder.commit().await.unwrap();
+
+        let id = |name: &str| layer.object_node_id(name).unwrap();
+        let predicate = |name| PropertyPath::predicate(layer.predicate_id(name).unwrap());
+        let names = |ids: Box<dyn Iterator<Item = u64> + Send>| {
+            let mut names: Vec<_> = ids.map(|id| layer.id_subject(id).unwrap()).collect();
+            names.sort();
+            names
+        };
+
+        let types = predicate("a").then(predicate("subclass_of").zero_or_more());
+        assert_eq!(
+            vec!["animal", "mammal", "thing"],
+            names(types.objects(&layer, id("cow")))
+        );
+        assert_eq!(
+            vec!["cow", "duck"],
+            names(types.subjects(&layer, id("animal")))
+        );
+        assert!(types.connects(&layer, id("duck"), id("thing")));
+        assert!(!types.connects(&layer, id("duck"), id("mammal")));
+
+        let superclasses = predicate("subclass_of").one_or_more();
+        assert_eq!(
+            vec!["animal", "thing"],
+            names(superclasses.objects(&layer, id("bird")))
+        );
+        assert_eq!(
+            vec!["thing"],
+            names(superclasses.objects(&layer, id("thing")))
+        );
+        assert_eq!(
+            vec!["thing"],
+            names(
+                predicate("subclass_of")
+                    .zero_or_more()
+                    .objects(&layer, id("thing"))
+            )
+        );
+
+        let related = predicate("likes").or(predicate("owns").inverse());
+        assert_eq!(
+            vec!["duck", "farmer"],
+            names(related.objects(&layer, id("cow")))
+        );
+        assert_eq!(
+            vec!["farmer"],
+            names(
+                predicate("a")
+                    .inverse()
+                    .then(predicate("owns").inverse())
+                    .objects(&layer, id("mammal"))
+            )
+        );
+    }
+}
@@ -0,0 +1,313 @@
+//! Planning the evaluation of basic graph patterns.
+//!
+//! The order in which the patterns of a basic graph pattern are
+//! evaluated has a large effect on how many triples are looked at.
+//! Plans are made with a simple cost model that estimates, for each
+//! pattern, how many triples it matches for every binding of the
+//! patterns before it. These estimates come from `Layer::estimate_count`
+//! and the per-predicate statistics of the layer, so planning does not
+//! look at any triples.
+//!
+//! The first pattern is the one with the fewest estimated matches.
+//! After that, the pattern that adds the fewest estimated bindings is
+//! picked from the patterns that share a variable with the patterns
+//! before it, so that no cross products are made unless the patterns
+//! are not connected at all. For each pattern, a merge join is used if
+//! the bindings so far are ordered the right way and merging is
+//! estimated to be no more expensive than a lookup per binding.
+use std::fmt;
+use std::sync::Arc;
+
+use super::bgp::*;
+use super::IterStream;
+use crate::layer::Layer;
+
+/// How a step of a plan is joined with the bindings of the steps before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Join {
+    /// The pattern is looked up in the indexes once for every binding so far.
+    Lookup,
+    /// The pattern is scanned once, and merged with the bindings so far, which are ordered by the given variable.
+    Merge(String),
+}
+
+/// A single pattern in a plan.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanStep {
+    /// The pattern evaluated in this step.
+    pub pattern: TriplePattern,
+    /// How the pattern is joined with the steps before it.
+    pub join: Join,
+    /// The estimated amount of matches of the pattern for every binding of the steps before it.
+    pub estimated_matches: f64,
+    /// The estimated amount of bindings after this step.
+    pub estimated_bindings: f64,
+}
+
+/// The order and join methods for evaluating a basic graph pattern, as returned by `BasicGraphPattern::plan`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryPlan {
+    variables: Arc<[String]>,
+    steps: Vec<PlanStep>,
+}
+
+impl BasicGraphPattern {
+    /// Decide in what order, and with which joins, to evaluate the patterns over the given layer.
+    ///
+    /// See the module documentation of `plan` for how this is decided.
+    pub fn plan(&self, layer: &dyn Layer) -> QueryPlan {
+        let variables: Arc<[String]> = self.variables().into();
+        let mut remaining: Vec<_> = self
+            .patterns()
+            .iter()
+            .map(|pattern| (pattern, CompiledPattern::new(pattern, &variables)))
+            .collect();
+        let mut bound = vec![false; variables.len()];
+        let mut ordered_by = None;
+        let mut bindings = 1.0;
+        let mut steps = Vec::with_capacity(remaining.len());
+        while !remaining.is_empty() {
+            let connected = |pattern: &CompiledPattern| {
+                pattern.0.iter().any(|slot| match slot {
+                    Slot::Variable(variable) => bound[*variable],
+                    Slot::Id(_) => false,
+                })
+            };
+            let any_connected = remaining.iter().any(|(_, pattern)| connected(pattern));
+            let (index, matches) = remaining
+                .iter()
+                .enumerate()
+                .filter(|(_, (_, pattern))| !any_connected || connected(pattern))
+                .map(|(index, (_, pattern))| (index, estimate_matches(layer, pattern, &bound)))
+                // the first of several equally good patterns is picked
+                .fold(
+                    None,
+                    |best: Option<(usize, f64)>, (index, matches)| match best {
+                        Some((_, best_matches)) if best_matches <= matches => best,
+                        _ => Some((index, matches)),
+                    },
+                )
+                .expect("there should be a remaining pattern");
+            let (pattern, compiled) = remaining.remove(index);
+
+            let join = match ordered_by {
+                Some(variable) if compiled.ordered_by() == Some(variable) => {
+                    let scan = estimate_matches(layer, &compiled, &vec![false; bound.len()]);
+                    if bindings + scan <= bindings * (1.0 + matches) {
+                        Join::Merge(variables[variable].clone())
+                    } else {
+                        Join::Lookup
+                    }
+                }
+                None => {
+                    // only fully bound patterns came before, so there's at most one binding, binding nothing
+                    ordered_by = compiled.ordered_by();
+                    Join::Lookup
+                }
+                _ => Join::Lookup,
+            };
+            for slot in compiled.0.iter() {
+                if let Slot::Variable(variable) = slot {
+                    bound[*variable] = true;
+                }
+            }
+            bindings *= matches;
+            steps.push(PlanStep {
+                pattern: pattern.clone(),
+                join,
+                estimated_matches: matches,
+                estimated_bindings: bindings,
+            });
+        }
+
+        QueryPlan { variables, steps }
+    }
+}
+
+/// Estimate how many triples match the pattern for a single binding of the given variables.
+fn estimate_matches(layer: &dyn Layer, pattern: &CompiledPattern, bound: &[bool]) -> f64 {
+    let (subject, predicate, object) = pattern.resolve(|_| None);
+    let stats = predicate.map(|predicate| layer.predicate_stats(predicate));
+    let mut matches = layer.estimate_count(subject, predicate, object) as f64;
+    for (position, slot) in pattern.0.iter().enumerate() {
+        let variable = match slot {
+            Slot::Variable(variable) if bound[*variable] => *variable,
+            _ => continue,
+        };
+        // a variable used twice in the pattern only narrows it down once
+        if pattern.0[..position].contains(&Slot::Variable(variable)) {
+            continue;
+        }
+        let distinct = match (position, stats) {
+            (0, Some(stats)) => stats.subject_count,
+            (2, Some(stats)) => stats.object_count,
+            (1, _) => layer.predicate_count(),
+            _ => layer.node_and_value_count(),
+        };
+        matches /= distinct.max(1) as f64;
+    }
+
+    matches
+}
+
+impl QueryPlan {
+    /// The steps of this plan, in the order they are evaluated.
+    pub fn steps(&self) -> &[PlanStep] {
+        &self.steps
+    }
+
+    /// Stream the bindings of all matches of the planned pattern in the given layer.
+    ///
+    /// The plan can be evaluated over any layer, but the estimates
+    /// it was made with are only valid for the layer it was made for.
+    pub fn evaluate(&self, layer: &dyn Layer) -> BindingStream {
+        Box::pin(IterStream::new(self.evaluate_iter(layer)))
+    }
+
+    /// Iterator over the bindings of all matches of the planned pattern in the given layer.
+    ///
+    /// This is the synchronous version of `evaluate`.
+    pub fn evaluate_iter(&self, layer: &dyn Layer) -> Box<dyn Iterator<Item = Bindings> + Send> {
+        let variables = self.variables.clone();
+        let layer: Arc<dyn Layer> = layer.clone_boxed().into();
+
+        let mut rows: Rows = Box::new(std::iter::once(vec![None; variables.len()]));
+        for step in self.steps.iter() {
+            let pattern = CompiledPattern::new(&step.pattern, &variables);
+            rows = match step.join {
+                Join::Merge(_) => Box::new(MergeJoin::new(rows, &layer, pattern)),
+                Join::Lookup => {
+                    let layer = layer.clone();
+                    Box::new(rows.flat_map(move |row| {
+                        let (subject, predicate, object) = pattern.resolve(|v| row[v]);
+                        layer
+                            .triples_matching(subject, predicate, object)
+                            .filter_map(move |triple| pattern.extend(&row, triple))
+                    }))
+                }
+            };
+        }
+
+        Box::new(rows.map(move |row| {
+            Bindings {
+                variables: variables.clone(),
+                values: row
+                    .into_iter()
+                    .map(|id| id.expect("all variables should be bound"))
+                    .collect(),
+            }
+        }))
+    }
+}
+
+impl fmt::Display for QueryPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let term = |term: &Term| match term {
+            Term::Variable(name) => format!("?{}", name),
+            Term::Id(id) => id.to_string(),
+        };
+        for (index, step) in self.steps.iter().enumerate() {
+            let join = match &step.join {
+                Join::Lookup => "lookup".to_owned(),
+                Join::Merge(variable) => format!("merge on ?{}", variable),
+            };
+            writeln!(
+                f,
+                "{}. {} {} {} ({}, ~{:.1} matches, ~{:.1} bindings)",
+                index + 1,
+                term(&step.pattern.subject),
+                term(&step.pattern.predicate),
+                term(&step.pattern.object),
+                join,
+                step.estimated_matches,
+                step.estimated_bindings
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::StringTriple;
+    use crate::store::open_memory_store;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn plan_selective_patterns_first() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        for i in 0..50 {
+            let animal = format!("animal{}", i);
+            builder
+                .add_string_triple(StringTriple::new_node(&animal, "a", "animal"))
+                .unwrap();
+            builder
+                .add_string_triple(StringTriple::new_node(&animal, "lives_in", "barn"))
+                .unwrap();
+        }
+        builder
+            .add_string_triple(StringTriple::new_value("animal7", "name", "Bessie"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("barn", "colour", "red"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("shed", "colour", "red"))
+            .unwrap();
+        let layer = builder.commit().await.unwrap();
+
+        let node = |name| Term::Id(layer.object_node_id(name).unwrap());
+        let predicate = |name| Term::Id(layer.predicate_id(name).unwrap());
+        let pattern = BasicGraphPattern::new()
+            .with_pattern(Term::var("barn"), predicate("colour"), node("red"))
+            .with_pattern(Term::var("x"), predicate("a"), node("animal"))
+            .with_pattern(Term::var("x"), predicate("lives_in"), Term::var("barn"))
+            .with_pattern(
+                Term::var("x"),
+                predicate("name"),
+                Term::Id(layer.object_value_id("Bessie").unwrap()),
+            );
+
+        let plan = pattern.plan(&layer);
+        let order: Vec<_> = plan
+            .steps()
+            .iter()
+            .map(|step| step.pattern.clone())
+            .collect();
+        assert_eq!(
+            vec![
+                pattern.patterns()[3].clone(),
+                pattern.patterns()[1].clone(),
+                pattern.patterns()[2].clone(),
+                pattern.patterns()[0].clone(),
+            ],
+            order
+        );
+        assert!(plan.steps().iter().all(|step| step.join == Join::Lookup));
+        assert_eq!(1.0, plan.steps()[0].estimated_matches);
+        assert_eq!(4, plan.to_string().lines().count());
+
+        let bindings: Vec<_> = pattern.evaluate(&layer).collect().await;
+        assert_eq!(1, bindings.len());
+        assert_eq!(layer.subject_id("animal7"), bindings[0].get("x"));
+        assert_eq!(layer.subject_id("barn"), bindings[0].get("barn"));
+        assert_eq!(
+            vec![
+                ("barn", bindings[0].get("barn").unwrap()),
+                ("x", bindings[0].get("x").unwrap())
+            ],
+            bindings[0].iter().collect::<Vec<_>>()
+        );
+
+        // with many bindings on both sides, merging is cheaper
+        let pattern = BasicGraphPattern::new()
+            .with_pattern(Term::var("x"), predicate("a"), node("animal"))
+            .with_pattern(Term::var("x"), predicate("lives_in"), Term::var("y"));
+        let plan = pattern.plan(&layer);
+        assert_eq!(Join::Merge("x".to_owned()), plan.steps()[1].join);
+        assert_eq!(50, plan.evaluate_iter(&layer).count());
+    }
+}
@@ -0,0 +1,290 @@
+//! Inference with Horn rules over triple patterns.
+//!
+//! A rule says that whenever its body, a basic graph pattern, matches,
+//! the triple described by its head holds as well. For example, the
+//! subclass closure is the rule
+//! `(?x, subclass_of, ?z) :- (?x, subclass_of, ?y), (?y, subclass_of, ?z)`.
+//!
+//! Rules are applied with semi-naive evaluation. The first round
+//! evaluates every rule against the layer. After that, every round
+//! only looks for matches involving at least one triple inferred in
+//! the round before, by evaluating the rest of the body for each new
+//! triple matching a body pattern. This stops once a round doesn't
+//! infer anything new. Inferred triples are kept in a staging layer
+//! on top of the layer, so that later rounds can use them.
+use std::collections::HashMap;
+use std::io;
+
+use thiserror::Error;
+
+use super::bgp::*;
+use crate::layer::{IdTriple, Layer, StagingLayer};
+
+/// The error returned when creating a rule with a head variable that its body doesn't bind.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("variable {0} in the head of the rule is not bound by its body")]
+pub struct UnboundVariableError(pub String);
+
+/// A rule inferring a triple from a basic graph pattern, as described in the module documentation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    head: TriplePattern,
+    body: BasicGraphPattern,
+    /// Whether the subject of the head may be bound to a value, which can't be a subject.
+    subject_may_be_value: bool,
+}
+
+impl Rule {
+    /// Create a rule inferring the head whenever the body matches.
+    ///
+    /// All variables in the head have to be used in the body.
+    pub fn new(head: TriplePattern, body: BasicGraphPattern) -> Result<Self, UnboundVariableError> {
+        let variables = body.variables();
+        for term in [&head.subject, &head.predicate, &head.object] {
+            if let Term::Variable(name) = term {
+                if !variables.contains(name) {
+                    return Err(UnboundVariableError(name.clone()));
+                }
+            }
+        }
+        let subject_may_be_value = matches!(head.subject, Term::Variable(_))
+            && body
+                .patterns()
+                .iter()
+                .any(|pattern| pattern.object == head.subject);
+
+        Ok(Self {
+            head,
+            body,
+            subject_may_be_value,
+        })
+    }
+
+    /// The triple pattern inferred by this rule.
+    pub fn head(&self) -> &TriplePattern {
+        &self.head
+    }
+
+    /// The pattern that has to match for this rule to infer anything.
+    pub fn body(&self) -> &BasicGraphPattern {
+        &self.body
+    }
+
+    /// The triple inferred for the given variable ids, or None if it would have a value as its subject.
+    fn instantiate(
+        &self,
+        layer: &dyn Layer,
+        variable_id: impl Fn(&str) -> u64,
+    ) -> Option<IdTriple> {
+        let id = |term: &Term| match term {
+            Term::Id(id) => *id,
+            Term::Variable(name) => variable_id(name),
+        };
+        let triple = IdTriple::new(
+            id(&self.head.subject),
+            id(&self.head.predicate),
+            id(&self.head.object),
+        );
+        if self.subject_may_be_value && layer.id_subject(triple.subject).is_none() {
+            return None;
+        }
+
+        Some(triple)
+    }
+
+    /// All triples inferred by this rule that involve at least one of the given triples.
+    fn infer_from(&self, layer: &dyn Layer, triples: &[IdTriple]) -> Vec<IdTriple> {
+        let mut inferred = Vec::new();
+        for (index, pattern) in self.body.patterns().iter().enumerate() {
+            for triple in triples {
+                let bound = match bind(pattern, *triple) {
+                    Some(bound) => bound,
+                    None => continue,
+                };
+                let rest = self
+                    .body
+                    .patterns()
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| *i != index)
+                    .fold(BasicGraphPattern::new(), |rest, (_, pattern)| {
+                        let term = |term: &Term| substitute(term, &bound);
+                        rest.with_pattern(
+                            term(&pattern.subject),
+                            term(&pattern.predicate),
+                            term(&pattern.object),
+                        )
+                    });
+                for bindings in rest.evaluate_iter(layer) {
+                    let variable_id =
+                        |name: &str| bound.get(name).copied().or_else(|| bindings.get(name));
+                    inferred.extend(self.instantiate(layer, |name| {
+                        variable_id(name).expect("head variable should be bound")
+                    }));
+                }
+            }
+        }
+
+        inferred
+    }
+}
+
+/// The ids of the variables in the pattern when matching the triple, or None if it doesn't match.
+fn bind(pattern: &TriplePattern, triple: IdTriple) -> Option<HashMap<String, u64>> {
+    let mut bound = HashMap::new();
+    for (term, id) in [
+        (&pattern.subject, triple.subject),
+        (&pattern.predicate, triple.predicate),
+        (&pattern.object, triple.object),
+    ] {
+        match term {
+            Term::Id(expected) if *expected != id => return None,
+            Term::Id(_) => {}
+            Term::Variable(name) => {
+                if *bound.entry(name.clone()).or_insert(id) != id {
+                    return None;
+                }
+            }
+        }
+    }
+
+    Some(bound)
+}
+
+fn substitute(term: &Term, bound: &HashMap<String, u64>) -> Term {
+    match term {
+        Term::Variable(name) => match bound.get(name) {
+            Some(id) => Term::Id(*id),
+            None => term.clone(),
+        },
+        id => id.clone(),
+    }
+}
+
+/// All triples that follow from the triples of the layer by repeatedly applying the rules, but are not in the layer yet.
+///
+/// The result is ordered and contains no duplicates. See the module
+/// documentation for how the rules are applied. Rules can only infer
+/// triples between ids known to the layer. Matches that would put a
+/// value in the subject position of an inferred triple are skipped.
+pub fn infer(layer: &dyn Layer, rules: &[Rule]) -> io::Result<Vec<IdTriple>> {
+    let mut staging = StagingLayer::new(layer.clone_boxed().into());
+    let mut inferred = Vec::new();
+
+    let mut delta = Vec::new();
+    for rule in rules {
+        for bindings in rule.body.evaluate_iter(&staging) {
+            delta.extend(rule.instantiate(&staging, |name| {
+                bindings.get(name).expect("head variable should be bound")
+            }));
+        }
+    }
+
+    loop {
+        let mut added = Vec::new();
+        for triple in delta {
+            if staging.add_id_triple(triple)? {
+                added.push(triple);
+            }
+        }
+        if added.is_empty() {
+            break;
+        }
+        inferred.extend_from_slice(&added);
+
+        delta = rules
+            .iter()
+            .flat_map(|rule| rule.infer_from(&staging, &added))
+            .filter(|triple| !staging.id_triple_exists(*triple))
+            .collect();
+    }
+    inferred.sort_unstable();
+
+    Ok(inferred)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::StringTriple;
+    use crate::store::open_memory_store;
+
+    #[tokio::test]
+    async fn materialize_subclass_closure() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        for (subject, predicate, object) in [
+            ("duck", "a", "bird"),
+            ("bird", "subclass_of", "animal"),
+            ("animal", "subclass_of", "thing"),
+            ("cow", "a", "mammal"),
+            ("mammal", "subclass_of", "animal"),
+            ("rex", "a", "dog"),
+            ("farmer", "owns", "cow"),
+        ] {
+            builder
+                .add_string_triple(StringTriple::new_node(subject, predicate, object))
+                .unwrap();
+        }
+        builder
+            .add_string_triple(StringTriple::new_value("farmer", "name", "Jo"))
+            .unwrap();
+        let layer = builder.commit().await.unwrap();
+
+        let id = |name: &str| Term::Id(layer.object_node_id(name).unwrap());
+        let predicate = |name: &str| Term::Id(layer.predicate_id(name).unwrap());
+        let var = Term::var;
+        let subclass_closure = Rule::new(
+            TriplePattern::new(var("x"), predicate("subclass_of"), var("z")),
+            BasicGraphPattern::new()
+                .with_pattern(var("x"), predicate("subclass_of"), var("y"))
+                .with_pattern(var("y"), predicate("subclass_of"), var("z")),
+        )
+        .unwrap();
+        let inherited_types = Rule::new(
+            TriplePattern::new(var("x"), predicate("a"), var("c")),
+            BasicGraphPattern::new()
+                .with_pattern(var("x"), predicate("a"), var("s"))
+                .with_pattern(var("s"), predicate("subclass_of"), var("c")),
+        )
+        .unwrap();
+        // a value can't be the subject of an inferred triple
+        let named = Rule::new(
+            TriplePattern::new(var("n"), predicate("a"), id("thing")),
+            BasicGraphPattern::new().with_pattern(var("x"), predicate("name"), var("n")),
+        )
+        .unwrap();
+        assert_eq!(Vec::<IdTriple>::new(), infer(&layer, &[named]).unwrap());
+        assert_eq!(
+            Err(UnboundVariableError("z".to_owned())),
+            Rule::new(
+                TriplePattern::new(var("x"), predicate("a"), var("z")),
+                BasicGraphPattern::new().with_pattern(var("x"), predicate("a"), var("y")),
+            )
+        );
+
+        let rules = [subclass_closure, inherited_types];
+        let mut inferred: Vec<_> = infer(&layer, &rules)
+            .unwrap()
+            .into_iter()
+            .map(|triple| layer.id_triple_to_string(&triple).unwrap())
+            .collect();
+        inferred.sort();
+        let expected: Vec<_> = [
+            ("bird", "subclass_of", "thing"),
+            ("cow", "a", "animal"),
+            ("cow", "a", "thing"),
+            ("duck", "a", "animal"),
+            ("duck", "a", "thing"),
+            ("mammal", "subclass_of", "thing"),
+        ]
+        .iter()
+        .map(|&(s, p, o)| StringTriple::new_node(s, p, o))
+        .collect();
+        assert_eq!(expected, inferred);
+
+        let child = layer.materialize(&rules).await.unwrap();
+        assert_eq!(layer.triple_count() + 6, child.triple_count());
+        assert!(infer(&child, &rules).unwrap().is_empty());
+    }
+}
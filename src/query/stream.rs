@@ -0,0 +1,89 @@
+//! Streams pulling from query iterators.
+//!
+//! Query results are computed by iterators over the triple indexes,
+//! which only do work when the next result is asked for. The streams
+//! returned by queries wrap these iterators without buffering, so
+//! results are only computed as fast as they are consumed, and
+//! dropping a stream early stops any further index access.
+//!
+//! Since the iterators are synchronous, a stream that is polled in a
+//! loop by a consumer that never has to wait itself would keep the
+//! executor thread busy until the whole result is computed. To let
+//! other tasks run in between, the stream gives back control to the
+//! executor after every `YIELD_BUDGET` results.
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::stream::Stream;
+
+/// The amount of results returned before giving control back to the executor.
+const YIELD_BUDGET: usize = 128;
+
+/// A stream returning the items of an iterator one at a time, as they are polled.
+pub(crate) struct IterStream<I> {
+    iter: I,
+    budget: usize,
+}
+
+impl<I: Iterator + Unpin> IterStream<I> {
+    pub(crate) fn new(iter: I) -> Self {
+        Self {
+            iter,
+            budget: YIELD_BUDGET,
+        }
+    }
+}
+
+impl<I: Iterator + Unpin> Stream for IterStream<I> {
+    type Item = I::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<I::Item>> {
+        let self_ = self.get_mut();
+        if self_.budget == 0 {
+            self_.budget = YIELD_BUDGET;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+
+        self_.budget -= 1;
+        Poll::Ready(self_.iter.next())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn pull_items_lazily() {
+        let pulled = Arc::new(AtomicUsize::new(0));
+        let counter = pulled.clone();
+        let iter = (0..1_000_000).inspect(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let first: Vec<_> = IterStream::new(iter).take(10).collect().await;
+        assert_eq!((0..10).collect::<Vec<_>>(), first);
+        assert_eq!(10, pulled.load(Ordering::SeqCst));
+
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut stream = IterStream::new(0..1000);
+        for i in 0..YIELD_BUDGET {
+            assert_eq!(Poll::Ready(Some(i)), stream.poll_next_unpin(&mut cx));
+        }
+        assert_eq!(Poll::Pending, stream.poll_next_unpin(&mut cx));
+        assert_eq!(
+            Poll::Ready(Some(YIELD_BUDGET)),
+            stream.poll_next_unpin(&mut cx)
+        );
+        assert_eq!(1000 - YIELD_BUDGET - 1, stream.count().await);
+    }
+}
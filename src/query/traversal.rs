@@ -0,0 +1,364 @@
+//! Breadth-first and depth-first traversal of the graph.
+//!
+//! A traversal starts at a node and follows triples with a selected
+//! set of predicates, or all predicates, from subject to object,
+//! object to subject, or both. Neighbours are looked up in the triple
+//! indexes one node at a time, and only ids are handled. The nodes
+//! visited so far are kept in a bit set indexed by id, which is small
+//! since ids are dense.
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use crate::layer::Layer;
+
+/// The direction in which a traversal follows triples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// From subject to object.
+    Forward,
+    /// From object to subject.
+    Backward,
+    /// Both from subject to object and from object to subject.
+    Both,
+}
+
+/// A description of which triples to follow, to traverse the graph from a node.
+///
+/// By default, triples with any predicate are followed forward,
+/// without a limit on the depth.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Traversal {
+    predicates: Option<Vec<u64>>,
+    direction: Direction,
+    max_depth: usize,
+}
+
+impl Default for Traversal {
+    fn default() -> Self {
+        Self {
+            predicates: None,
+            direction: Direction::Forward,
+            max_depth: usize::MAX,
+        }
+    }
+}
+
+impl Traversal {
+    /// A traversal following triples with any predicate forward, without a limit on the depth.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only follow triples with the given predicate, in addition to any predicates given before.
+    pub fn with_predicate(mut self, predicate: u64) -> Self {
+        self.predicates.get_or_insert_with(Vec::new).push(predicate);
+        self
+    }
+
+    /// Follow triples in the given direction.
+    pub fn with_direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    /// Don't go further than the given amount of triples from the start.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Iterator over all nodes reachable from the start, with their depth, in breadth-first order.
+    ///
+    /// The start itself is returned first, with a depth of 0. Every
+    /// node is returned only once, at the smallest depth it can be
+    /// reached at.
+    pub fn breadth_first(&self, layer: &dyn Layer, start: u64) -> BreadthFirst {
+        let mut visited = VisitedSet::default();
+        visited.insert(start);
+        BreadthFirst {
+            neighbours: Neighbours::new(layer, self),
+            max_depth: self.max_depth,
+            visited,
+            queue: vec![(start, 0)].into(),
+        }
+    }
+
+    /// Iterator over all nodes reachable from the start, with their depth, in depth-first order.
+    ///
+    /// The start itself is returned first, with a depth of 0. Every
+    /// node is returned only once, and neighbours are visited in the
+    /// order of the index they come from. The depth of a node is the
+    /// length of the path the search took to reach it.
+    pub fn depth_first(&self, layer: &dyn Layer, start: u64) -> DepthFirst {
+        DepthFirst {
+            neighbours: Neighbours::new(layer, self),
+            max_depth: self.max_depth,
+            visited: VisitedSet::default(),
+            stack: vec![(start, 0)],
+        }
+    }
+
+    /// A shortest path from one node to another, including both ends, or None if there is no path.
+    ///
+    /// Paths are as short as possible in the amount of triples
+    /// followed. If there are several shortest paths, the first one
+    /// found in breadth-first order is returned.
+    pub fn shortest_path(&self, layer: &dyn Layer, from: u64, to: u64) -> Option<Vec<u64>> {
+        let neighbours = Neighbours::new(layer, self);
+        let mut visited = VisitedSet::default();
+        visited.insert(from);
+        let mut previous = HashMap::new();
+        let mut queue: VecDeque<_> = vec![(from, 0)].into();
+        while let Some((node, depth)) = queue.pop_front() {
+            if node == to {
+                let mut path = vec![to];
+                let mut node = to;
+                while let Some(&before) = previous.get(&node) {
+                    path.push(before);
+                    node = before;
+                }
+                path.reverse();
+
+                return Some(path);
+            }
+            if depth == self.max_depth {
+                continue;
+            }
+            for neighbour in neighbours.of(node) {
+                if visited.insert(neighbour) {
+                    previous.insert(neighbour, node);
+                    queue.push_back((neighbour, depth + 1));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// A set of ids, stored as one bit per id.
+#[derive(Default)]
+struct VisitedSet {
+    words: Vec<u64>,
+}
+
+impl VisitedSet {
+    /// Add an id to the set, returning true if it wasn't in the set before.
+    fn insert(&mut self, id: u64) -> bool {
+        let word = (id / 64) as usize;
+        let mask = 1 << (id % 64);
+        if word >= self.words.len() {
+            self.words.resize(word + 1, 0);
+        }
+        let inserted = self.words[word] & mask == 0;
+        self.words[word] |= mask;
+
+        inserted
+    }
+}
+
+/// Looks up the neighbours of a node, according to a traversal.
+struct Neighbours {
+    layer: Arc<dyn Layer>,
+    predicates: Option<Vec<u64>>,
+    direction: Direction,
+}
+
+impl Neighbours {
+    fn new(layer: &dyn Layer, traversal: &Traversal) -> Self {
+        Self {
+            layer: layer.clone_boxed().into(),
+            predicates: traversal.predicates.clone(),
+            direction: traversal.direction,
+        }
+    }
+
+    fn of(&self, node: u64) -> impl Iterator<Item = u64> + '_ {
+        let forward: Box<dyn Iterator<Item = u64> + Send> = match self.direction {
+            Direction::Backward => Box::new(std::iter::empty()),
+            _ => match &self.predicates {
+                None => Box::new(self.layer.triples_s(node).map(|t| t.object)),
+                Some(predicates) => Box::new(
+                    predicates
+                        .clone()
+                        .into_iter()
+                        .flat_map({
+                            let layer = self.layer.clone();
+                            move |predicate| layer.triples_sp(node, predicate)
+                        })
+                        .map(|t| t.object),
+                ),
+            },
+        };
+        let backward: Box<dyn Iterator<Item = u64> + Send> = match self.direction {
+            Direction::Forward => Box::new(std::iter::empty()),
+            _ => Box::new(
+                self.layer
+                    .triples_o(node)
+                    .filter(move |t| self.follows(t.predicate))
+                    .map(|t| t.subject),
+            ),
+        };
+
+        forward.chain(backward)
+    }
+
+    fn follows(&self, predicate: u64) -> bool {
+        match &self.predicates {
+            None => true,
+            Some(predicates) => predicates.contains(&predicate),
+        }
+    }
+}
+
+/// Iterator over the nodes reachable from a start node in breadth-first order, as returned by `Traversal::breadth_first`.
+pub struct BreadthFirst {
+    neighbours: Neighbours,
+    max_depth: usize,
+    visited: VisitedSet,
+    queue: VecDeque<(u64, usize)>,
+}
+
+impl Iterator for BreadthFirst {
+    type Item = (u64, usize);
+
+    fn next(&mut self) -> Option<(u64, usize)> {
+        let (node, depth) = self.queue.pop_front()?;
+        if depth < self.max_depth {
+            for neighbour in self.neighbours.of(node) {
+                if self.visited.insert(neighbour) {
+                    self.queue.push_back((neighbour, depth + 1));
+                }
+            }
+        }
+
+        Some((node, depth))
+    }
+}
+
+/// Iterator over the nodes reachable from a start node in depth-first order, as returned by `Traversal::depth_first`.
+pub struct DepthFirst {
+    neighbours: Neighbours,
+    max_depth: usize,
+    visited: VisitedSet,
+    stack: Vec<(u64, usize)>,
+}
+
+impl Iterator for DepthFirst {
+    type Item = (u64, usize);
+
+    fn next(&mut self) -> Option<(u64, usize)> {
+        loop {
+            let (node, depth) = self.stack.pop()?;
+            if !self.visited.insert(node) {
+                continue;
+            }
+            if depth < self.max_depth {
+                // pushed in reverse, so that the first neighbour is visited first
+                let start = self.stack.len();
+                self.stack
+                    .extend(self.neighbours.of(node).map(|n| (n, depth + 1)));
+                self.stack[start..].reverse();
+            }
+
+            return Some((node, depth));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::StringTriple;
+    use crate::store::open_memory_store;
+
+    #[tokio::test]
+    async fn traverse_graph() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        for (subject, predicate, object) in [
+            ("a", "road", "b"),
+            ("a", "road", "c"),
+            ("b", "road", "d"),
+            ("c", "road", "d"),
+            ("d", "road", "e"),
+            ("e", "road", "a"),
+            ("a", "ferry", "e"),
+            ("f", "road", "a"),
+        ] {
+            builder
+                .add_string_triple(StringTriple::new_node(subject, predicate, object))
+                .unwrap();
+        }
+        let layer = builder.commit().await.unwrap();
+
+        let id = |name: &str| layer.object_node_id(name).unwrap();
+        let names = |nodes: Vec<u64>| {
+            nodes
+                .into_iter()
+                .map(|node| layer.id_subject(node).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let road = layer.predicate_id("road").unwrap();
+        let roads = Traversal::new().with_predicate(road);
+
+        let (nodes, depths): (Vec<_>, Vec<_>) = roads.breadth_first(&layer, id("a")).unzip();
+        assert_eq!(vec!["a", "b", "c", "d", "e"], names(nodes));
+        assert_eq!(vec![0, 1, 1, 2, 3], depths);
+        let (nodes, depths): (Vec<_>, Vec<_>) = roads.depth_first(&layer, id("a")).unzip();
+        assert_eq!(vec!["a", "b", "d", "e", "c"], names(nodes));
+        assert_eq!(vec![0, 1, 2, 3, 1], depths);
+        let nodes: Vec<_> = roads
+            .clone()
+            .with_max_depth(1)
+            .depth_first(&layer, id("a"))
+            .map(|(node, _)| node)
+            .collect();
+        assert_eq!(vec!["a", "b", "c"], names(nodes));
+
+        assert_eq!(
+            vec!["a", "b", "d", "e"],
+            names(roads.shortest_path(&layer, id("a"), id("e")).unwrap())
+        );
+        assert_eq!(
+            vec!["a", "e"],
+            names(
+                Traversal::new()
+                    .shortest_path(&layer, id("a"), id("e"))
+                    .unwrap()
+            )
+        );
+        assert_eq!(
+            vec!["a"],
+            names(roads.shortest_path(&layer, id("a"), id("a")).unwrap())
+        );
+        assert_eq!(None, roads.shortest_path(&layer, id("a"), id("f")));
+        assert_eq!(
+            None,
+            roads
+                .clone()
+                .with_max_depth(2)
+                .shortest_path(&layer, id("a"), id("e"))
+        );
+
+        let back_roads = roads.clone().with_direction(Direction::Backward);
+        assert_eq!(
+            vec!["a", "e", "f", "d", "b", "c"],
+            names(
+                back_roads
+                    .breadth_first(&layer, id("a"))
+                    .map(|(node, _)| node)
+                    .collect()
+            )
+        );
+        assert_eq!(
+            vec!["f", "a"],
+            names(
+                roads
+                    .with_direction(Direction::Both)
+                    .shortest_path(&layer, id("f"), id("a"))
+                    .unwrap()
+            )
+        );
+    }
+}
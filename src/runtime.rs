@@ -0,0 +1,114 @@
+//! Running tasks on whatever executor the store is used from.
+//!
+//! Within a tokio runtime, spawned tasks run on that runtime, and
+//! blocking work is moved off the async worker threads. Outside of
+//! one, for example under a wasm executor, spawned tasks run as part
+//! of the future that awaits them, and blocking work runs in place.
+//! The current time is read from the platform the same way.
+use std::io;
+use std::time::SystemTime;
+
+use futures::future::{self, Either, Future, FutureExt};
+use tokio::runtime::Handle;
+
+/// Spawn a task, returning a future for its result.
+pub(crate) fn spawn<F>(task: F) -> impl Future<Output = io::Result<F::Output>>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    match Handle::try_current() {
+        Ok(handle) => Either::Left(handle.spawn(task).map(|r| r.map_err(io::Error::from))),
+        Err(_) => Either::Right(task.map(Ok)),
+    }
+}
+
+/// Run a blocking function, returning a future for its result.
+pub(crate) fn spawn_blocking<F, T>(f: F) -> impl Future<Output = io::Result<T>>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    match Handle::try_current() {
+        Ok(handle) => Either::Left(handle.spawn_blocking(f).map(|r| r.map_err(io::Error::from))),
+        Err(_) => Either::Right(future::ready(Ok(f()))),
+    }
+}
+
+/// Run a blocking function from within an async context.
+///
+/// On a multi-threaded tokio runtime, the current worker thread hands
+/// off its other tasks while the function runs.
+pub(crate) fn block_in_place<F: FnOnce() -> T, T>(f: F) -> T {
+    #[cfg(feature = "fs")]
+    if let Ok(handle) = Handle::try_current() {
+        if handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread {
+            return tokio::task::block_in_place(f);
+        }
+    }
+
+    f()
+}
+
+/// The current time.
+///
+/// `SystemTime::now` panics on `wasm32-unknown-unknown`, which has no
+/// clock of its own. There, the time is retrieved from JavaScript.
+pub(crate) fn now() -> SystemTime {
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    {
+        use web_time::web::SystemTimeExt;
+        web_time::SystemTime::now().to_std()
+    }
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    {
+        SystemTime::now()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tasks_run_without_tokio() {
+        let result = futures::executor::block_on(async {
+            let task = spawn(async { 1 + 1 });
+            let blocking = spawn_blocking(|| 2 + 2);
+            (
+                task.await.unwrap(),
+                blocking.await.unwrap(),
+                block_in_place(|| 3),
+            )
+        });
+
+        assert_eq!((2, 4, 3), result);
+    }
+
+    #[test]
+    fn memory_store_runs_without_tokio() {
+        use crate::layer::{Layer, StringTriple};
+        use crate::store::open_memory_store;
+
+        futures::executor::block_on(async {
+            let store = open_memory_store();
+            let graph = store.create("farm").await.unwrap();
+            let builder = store.create_base_layer().await.unwrap();
+            builder
+                .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+                .unwrap();
+            let layer = builder.commit().await.unwrap();
+            assert!(graph.set_head(&layer).await.unwrap());
+
+            let builder = layer.open_write().await.unwrap();
+            builder
+                .add_string_triple(StringTriple::new_node("cow", "likes", "duck"))
+                .unwrap();
+            let layer = builder.commit().await.unwrap();
+            layer.rollup().await.unwrap();
+
+            assert_eq!(2, layer.triples().count());
+            assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        });
+    }
+}
@@ -1,7 +1,9 @@
 use super::layer::*;
+use super::metrics::*;
 use crate::layer::*;
 use crate::structure::PfcDict;
 use futures::future::{self, Future};
+use futures::stream::Stream;
 use std::collections::HashMap;
 use std::io;
 use std::pin::Pin;
@@ -12,6 +14,11 @@ pub trait LayerCache: 'static + Send + Sync {
     fn cache_layer(&self, layer: Arc<InternalLayer>);
 
     fn invalidate(&self, name: [u32; 5]);
+
+    /// The amount of layers currently held by this cache.
+    fn cached_layer_count(&self) -> usize {
+        0
+    }
 }
 
 pub struct NoCache;
@@ -33,15 +40,30 @@ lazy_static! {
 // locking isn't really ideal but the lock window will be relatively small so it shouldn't hurt performance too much except on heavy updates.
 // ideally we should be using some concurrent hashmap implementation instead.
 // furthermore, there should be some logic to remove stale entries, like a periodic pass. right now, there isn't.
-#[derive(Default)]
 pub struct LockingHashMapLayerCache {
     cache: RwLock<HashMap<[u32; 5], Weak<InternalLayer>>>,
+    metrics: Arc<dyn StorageMetrics>,
+}
+
+impl Default for LockingHashMapLayerCache {
+    fn default() -> Self {
+        Self {
+            cache: Default::default(),
+            metrics: NOMETRICS.clone(),
+        }
+    }
 }
 
 impl LockingHashMapLayerCache {
     pub fn new() -> Self {
         Default::default()
     }
+
+    /// Report cache hits and misses to the given metrics.
+    pub fn with_metrics(mut self, metrics: Arc<dyn StorageMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
 }
 
 impl LayerCache for LockingHashMapLayerCache {
@@ -54,7 +76,7 @@ impl LayerCache for LockingHashMapLayerCache {
         let result = cache.get(&name).map(|c| c.to_owned());
         std::mem::drop(cache);
 
-        match result {
+        let result = match result {
             None => None,
             Some(weak) => match weak.upgrade() {
                 None => {
@@ -66,7 +88,14 @@ impl LayerCache for LockingHashMapLayerCache {
                 }
                 Some(result) => Some(result),
             },
+        };
+
+        match result {
+            Some(_) => self.metrics.cache_hit(),
+            None => self.metrics.cache_miss(),
         }
+
+        result
     }
 
     fn cache_layer(&self, layer: Arc<InternalLayer>) {
@@ -86,6 +115,16 @@ impl LayerCache for LockingHashMapLayerCache {
 
         cache.remove(&name);
     }
+
+    fn cached_layer_count(&self) -> usize {
+        let cache = self
+            .cache
+            .read()
+            .expect("rwlock read should always succeed");
+
+        // entries for dropped layers are only removed lazily
+        cache.values().filter(|l| l.strong_count() != 0).count()
+    }
 }
 
 #[derive(Clone)]
@@ -265,6 +304,14 @@ impl LayerStore for CachedLayerStore {
         self.inner.create_base_layer()
     }
 
+    fn bulk_build_base_layer(
+        &self,
+        triples: Pin<Box<dyn Stream<Item = io::Result<StringTriple>> + Send>>,
+        config: BulkLoadConfig,
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        self.inner.bulk_build_base_layer(triples, config)
+    }
+
     fn create_child_layer(
         &self,
         parent: [u32; 5],
@@ -281,6 +328,22 @@ impl LayerStore for CachedLayerStore {
         self.inner.create_child_layer_with_cache(parent, cache)
     }
 
+    fn resume_layer_builder(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<Box<dyn LayerBuilder>>>> + Send>> {
+        self.inner
+            .resume_layer_builder_with_cache(name, self.cache.clone())
+    }
+
+    fn resume_layer_builder_with_cache(
+        &self,
+        name: [u32; 5],
+        cache: Arc<dyn LayerCache>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<Box<dyn LayerBuilder>>>> + Send>> {
+        self.inner.resume_layer_builder_with_cache(name, cache)
+    }
+
     fn perform_rollup(
         &self,
         layer: Arc<InternalLayer>,
@@ -361,6 +424,37 @@ impl LayerStore for CachedLayerStore {
         self.inner.layer_is_ancestor_of(descendant, ancestor)
     }
 
+    fn truncate_history(
+        &self,
+        layer: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<[u32; 5]>>> + Send>> {
+        let inner = self.inner.clone();
+        let cache = self.cache.clone();
+        Box::pin(async move {
+            let result = inner.truncate_history(layer).await?;
+            // make sure the layer is loaded with its new parent next time
+            cache.invalidate(layer);
+
+            Ok(result)
+        })
+    }
+
+    fn collect_garbage(
+        &self,
+        keep: Vec<[u32; 5]>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Vec<[u32; 5]>>> + Send>> {
+        let inner = self.inner.clone();
+        let cache = self.cache.clone();
+        Box::pin(async move {
+            let removed = inner.collect_garbage(keep).await?;
+            for &name in removed.iter() {
+                cache.invalidate(name);
+            }
+
+            Ok(removed)
+        })
+    }
+
     fn triple_addition_exists(
         &self,
         layer: [u32; 5],
@@ -585,6 +679,31 @@ impl LayerStore for CachedLayerStore {
         self.inner.triple_layer_removal_count(layer)
     }
 
+    fn layer_metadata(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<LayerMetadata>>> + Send>> {
+        self.inner.layer_metadata(name)
+    }
+
+    fn layer_text_index(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<TextIndex>>> + Send>> {
+        self.inner.layer_text_index(name)
+    }
+
+    fn layer_file_size(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<u64>> + Send>> {
+        self.inner.layer_file_size(name)
+    }
+
+    fn cached_layer_count(&self) -> Option<usize> {
+        Some(self.cache.cached_layer_count())
+    }
+
     fn retrieve_layer_stack_names(
         &self,
         name: [u32; 5],
@@ -599,6 +718,22 @@ impl LayerStore for CachedLayerStore {
     ) -> Pin<Box<dyn Future<Output = io::Result<Vec<[u32; 5]>>> + Send>> {
         self.inner.retrieve_layer_stack_names_upto(name, upto)
     }
+
+    fn layer_quads(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<QuadStack>> + Send>> {
+        self.inner.layer_quads(name)
+    }
+
+    fn finalize_layer(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        // the layer may be renamed, so forget about any cached layer under its old name
+        self.cache.invalidate(name);
+        self.inner.finalize_layer(name)
+    }
 }
 
 #[cfg(test)]
@@ -713,6 +848,30 @@ pub mod tests {
         assert_eq!(1, Arc::weak_count(&layer));
     }
 
+    #[tokio::test]
+    async fn cached_layer_store_reports_hits_and_misses() {
+        use std::sync::atomic::Ordering;
+        let metrics = Arc::new(CountingMetrics::new());
+        let store = CachedLayerStore::new(
+            MemoryLayerStore::new(),
+            LockingHashMapLayerCache::new().with_metrics(metrics.clone()),
+        );
+        let mut builder = store.create_base_layer().await.unwrap();
+        let base_name = builder.name();
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        builder.commit_boxed().await.unwrap();
+
+        let misses = metrics.cache_misses.load(Ordering::Relaxed);
+        let hits = metrics.cache_hits.load(Ordering::Relaxed);
+        let _layer = store.get_layer(base_name).await.unwrap().unwrap();
+        assert!(metrics.cache_misses.load(Ordering::Relaxed) > misses);
+
+        let misses = metrics.cache_misses.load(Ordering::Relaxed);
+        let _layer2 = store.get_layer(base_name).await.unwrap().unwrap();
+        assert_eq!(hits + 1, metrics.cache_hits.load(Ordering::Relaxed));
+        assert_eq!(misses, metrics.cache_misses.load(Ordering::Relaxed));
+    }
+
     #[test]
     fn retrieve_layer_stack_names_retrieves_correctly() {
         //let store = CachedLayerStore::new(MemoryLayerStore::new());
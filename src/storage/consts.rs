@@ -82,6 +82,15 @@ pub struct Filenames {
     pub neg_predicate_wavelet_tree_bit_index_blocks: &'static str,
     pub neg_predicate_wavelet_tree_bit_index_sblocks: &'static str,
 
+    pub graph_dictionary_blocks: &'static str,
+    pub graph_dictionary_offsets: &'static str,
+    pub quad_additions: &'static str,
+    pub quad_removals: &'static str,
+
+    pub metadata: &'static str,
+    pub predicate_stats: &'static str,
+    pub text_index: &'static str,
+
     pub parent: &'static str,
     pub rollup: &'static str,
 }
@@ -178,6 +187,15 @@ pub const FILENAMES: Filenames = Filenames {
     neg_predicate_wavelet_tree_bit_index_sblocks:
         "neg_predicate_wavelet_tree_bit_index_sblocks.logarray",
 
+    graph_dictionary_blocks: "graph_dictionary_blocks.pfc",
+    graph_dictionary_offsets: "graph_dictionary_offsets.logarray",
+    quad_additions: "quad_additions.logarray",
+    quad_removals: "quad_removals.logarray",
+
+    metadata: "metadata.bin",
+    predicate_stats: "predicate_stats.bin",
+    text_index: "text_index.bin",
+
     parent: "parent.hex",
     rollup: "rollup.hex",
 };
@@ -191,13 +209,20 @@ pub const SHARED_REQUIRED_FILES: [&'static str; 6] = [
     FILENAMES.value_dictionary_offsets,
 ];
 
-pub const SHARED_OPTIONAL_FILES: [&'static str; 7] = [
+pub const SHARED_OPTIONAL_FILES: [&'static str; 14] = [
     FILENAMES.node_value_idmap_bits,
     FILENAMES.node_value_idmap_bit_index_blocks,
     FILENAMES.node_value_idmap_bit_index_sblocks,
     FILENAMES.predicate_idmap_bits,
     FILENAMES.predicate_idmap_bit_index_blocks,
     FILENAMES.predicate_idmap_bit_index_sblocks,
+    FILENAMES.graph_dictionary_blocks,
+    FILENAMES.graph_dictionary_offsets,
+    FILENAMES.quad_additions,
+    FILENAMES.quad_removals,
+    FILENAMES.metadata,
+    FILENAMES.predicate_stats,
+    FILENAMES.text_index,
     FILENAMES.rollup,
 ];
 
@@ -262,3 +287,15 @@ pub const CHILD_LAYER_OPTIONAL_FILES: [&'static str; 4] = [
     FILENAMES.neg_subjects,
     FILENAMES.neg_objects,
 ];
+
+/// Iterate over the names of all files that can be part of a layer.
+pub fn all_layer_files() -> impl Iterator<Item = &'static str> {
+    SHARED_REQUIRED_FILES
+        .iter()
+        .chain(SHARED_OPTIONAL_FILES.iter())
+        .chain(BASE_LAYER_REQUIRED_FILES.iter())
+        .chain(BASE_LAYER_OPTIONAL_FILES.iter())
+        .chain(CHILD_LAYER_REQUIRED_FILES.iter())
+        .chain(CHILD_LAYER_OPTIONAL_FILES.iter())
+        .cloned()
+}
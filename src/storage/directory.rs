@@ -1,23 +1,45 @@
 //! Directory-based implementation of storage traits.
 
 use bytes::{Bytes, BytesMut};
-use futures::{future, Future};
+use futures::task::{Context, Poll};
+use futures::{future, stream, Future};
 use locking::*;
+use notify::{RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::ffi::OsString;
 use std::io::{self, SeekFrom};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
 use tokio::fs::{self, *};
-use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufWriter};
+use tokio::io::{
+    AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt, BufWriter,
+};
 
 use async_trait::async_trait;
 
+use super::consts::{all_layer_files, FILENAMES};
 use super::*;
+use crate::layer::{Parallelism, RemovalValidation};
 
 const PREFIX_DIR_SIZE: usize = 3;
 
 #[derive(Clone)]
 pub struct FileBackedStore {
     path: PathBuf,
+    read_only: bool,
+    durability: Durability,
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    io_uring: Option<super::uring::UringFile>,
+    metrics: Arc<dyn StorageMetrics>,
+}
+
+fn read_only_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "store was opened in read-only mode",
+    )
 }
 
 #[async_trait]
@@ -36,15 +58,131 @@ impl SyncableFile for BufWriter<File> {
     }
 }
 
+/// How much effort is spent on making sure written layers survive a crash.
+///
+/// Syncing files to disk is slow, and it is not always needed. For
+/// example, a bulk import that is simply restarted on failure does
+/// not need every intermediate layer to be durable.
+///
+/// Label files are always written in a crash-safe way, regardless of
+/// this setting.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Durability {
+    /// Every layer file is synced to disk once written, and the
+    /// directory entry of a layer is synced when it is finalized.
+    #[default]
+    Full,
+    /// Only the directory entry of a layer is synced when it is
+    /// finalized. The contents of layer files are left to the
+    /// operating system to write out.
+    Directory,
+    /// Nothing is synced to disk at all.
+    None,
+}
+
+/// A writer for a `FileBackedStore`.
+pub struct FileBackedStoreWriter {
+    inner: Metered<BufWriter<File>>,
+    sync: bool,
+}
+
+impl AsyncWrite for FileBackedStoreWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[async_trait]
+impl SyncableFile for FileBackedStoreWriter {
+    async fn sync_all(self) -> io::Result<()> {
+        if self.sync {
+            self.inner.sync_all().await
+        } else {
+            Ok(())
+        }
+    }
+}
+
 impl FileBackedStore {
     pub fn new<P: Into<PathBuf>>(path: P) -> FileBackedStore {
-        FileBackedStore { path: path.into() }
+        FileBackedStore {
+            path: path.into(),
+            read_only: false,
+            durability: Durability::default(),
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            io_uring: None,
+            metrics: NOMETRICS.clone(),
+        }
+    }
+
+    /// Create a file handle that refuses to be opened for writing.
+    pub fn new_read_only<P: Into<PathBuf>>(path: P) -> FileBackedStore {
+        FileBackedStore {
+            path: path.into(),
+            read_only: true,
+            durability: Durability::default(),
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            io_uring: None,
+            metrics: NOMETRICS.clone(),
+        }
+    }
+
+    /// Report all IO on this file to the given metrics.
+    pub fn with_metrics(mut self, metrics: Arc<dyn StorageMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Only sync this file to disk if the given durability asks for it.
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    /// Map this file into memory through io_uring rather than through tokio's file operations.
+    ///
+    /// The file is opened once, on the first map, and the handle is
+    /// kept by this file and its clones. Streaming reads and writes
+    /// are unaffected.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    pub fn with_io_uring(mut self) -> Self {
+        self.io_uring = Some(super::uring::UringFile::new(self.path.clone()));
+        self
+    }
+
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    async fn map_range_with_io_uring(
+        &self,
+        file: &super::uring::UringFile,
+        offset: usize,
+        len: usize,
+    ) -> io::Result<Bytes> {
+        let opened = !file.is_open();
+        let result = file.read_range(offset, len).await?;
+        if opened {
+            self.metrics.file_opened_for_read();
+        }
+        self.metrics.bytes_read(len);
+
+        Ok(result)
     }
 }
 
 #[async_trait]
 impl FileLoad for FileBackedStore {
-    type Read = File;
+    type Read = Metered<File>;
 
     async fn exists(&self) -> io::Result<bool> {
         let metadata = tokio::fs::metadata(&self.path).await;
@@ -56,18 +194,29 @@ impl FileLoad for FileBackedStore {
         Ok(m.len() as usize)
     }
 
-    async fn open_read_from(&self, offset: usize) -> io::Result<File> {
+    async fn open_read_from(&self, offset: usize) -> io::Result<Metered<File>> {
         let mut options = tokio::fs::OpenOptions::new();
         options.read(true);
         let mut file = options.open(&self.path).await?;
+        self.metrics.file_opened_for_read();
 
         file.seek(SeekFrom::Start(offset as u64)).await?;
 
-        Ok(file)
+        Ok(Metered::new(file, self.metrics.clone()))
     }
 
     async fn map(&self) -> io::Result<Bytes> {
         let size = self.size().await?;
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        {
+            match &self.io_uring {
+                Some(file) if size != 0 => {
+                    return self.map_range_with_io_uring(file, 0, size).await;
+                }
+                _ => {}
+            }
+        }
+
         if size == 0 {
             Ok(Bytes::new())
         } else {
@@ -86,29 +235,343 @@ impl FileLoad for FileBackedStore {
             Ok(b.freeze())
         }
     }
+
+    async fn map_range(&self, offset: usize, len: usize) -> io::Result<Bytes> {
+        check_range(offset, len, self.size().await?)?;
+        if len == 0 {
+            return Ok(Bytes::new());
+        }
+
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        {
+            if let Some(file) = &self.io_uring {
+                return self.map_range_with_io_uring(file, offset, len).await;
+            }
+        }
+
+        let mut f = self.open_read_from(offset).await?;
+        let mut b = BytesMut::new();
+        b.resize(len, 0);
+        f.read_exact(&mut b[..]).await?;
+        Ok(b.freeze())
+    }
 }
 
 #[async_trait]
 impl FileStore for FileBackedStore {
-    type Write = BufWriter<File>;
+    type Write = FileBackedStoreWriter;
+
+    async fn open_write(&self) -> io::Result<FileBackedStoreWriter> {
+        if self.read_only {
+            return Err(read_only_error());
+        }
 
-    async fn open_write(&self) -> io::Result<BufWriter<File>> {
         let mut options = tokio::fs::OpenOptions::new();
         options.read(true).write(true).create(true);
         let file = options.open(&self.path).await?;
+        self.metrics.file_opened_for_write();
+
+        Ok(FileBackedStoreWriter {
+            inner: Metered::new(BufWriter::new(file), self.metrics.clone()),
+            sync: self.durability == Durability::Full,
+        })
+    }
+}
+
+/// How layer directories are laid out inside a directory layer store.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DirectoryLayout {
+    /// Every layer directory is placed directly inside the store directory.
+    Flat,
+    /// Every layer directory is placed inside a subdirectory named
+    /// after the first given amount of characters of the layer name.
+    ///
+    /// This keeps directories small for stores with very many
+    /// layers, as some filesystems deal badly with huge directories.
+    Sharded(usize),
+}
+
+impl Default for DirectoryLayout {
+    fn default() -> Self {
+        DirectoryLayout::Sharded(PREFIX_DIR_SIZE)
+    }
+}
+
+impl DirectoryLayout {
+    fn layer_path(&self, root: &Path, name: [u32; 5]) -> PathBuf {
+        let mut p = root.to_path_buf();
+        let name_str = name_to_string(name);
+        if let DirectoryLayout::Sharded(prefix_len) = self {
+            p.push(&name_str[0..*prefix_len]);
+        }
+        p.push(name_str);
+
+        p
+    }
 
-        Ok(BufWriter::new(file))
+    async fn layers(&self, root: &Path) -> io::Result<Vec<[u32; 5]>> {
+        let mut result = Vec::new();
+        match self {
+            DirectoryLayout::Flat => layer_directories_in(root, &mut result).await?,
+            DirectoryLayout::Sharded(prefix_len) => {
+                let mut shards = fs::read_dir(root).await?;
+                while let Some(shard) = shards.next_entry().await? {
+                    if shard.file_type().await?.is_dir() && shard.file_name().len() == *prefix_len {
+                        layer_directories_in(&shard.path(), &mut result).await?;
+                    }
+                }
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+/// Collect the names of all layer directories directly inside the given directory.
+///
+/// Anything that doesn't look like a layer directory is skipped, as
+/// layer directories may live side by side with label files and
+/// shard directories.
+async fn layer_directories_in(path: &Path, result: &mut Vec<[u32; 5]>) -> io::Result<()> {
+    let mut stream = fs::read_dir(path).await?;
+    while let Some(direntry) = stream.next_entry().await? {
+        if !direntry.file_type().await?.is_dir() {
+            continue;
+        }
+
+        if let Some(name) = direntry
+            .file_name()
+            .to_str()
+            .and_then(|name| string_to_name(name).ok())
+        {
+            result.push(name);
+        }
     }
+
+    Ok(())
 }
 
 #[derive(Clone)]
 pub struct DirectoryLayerStore {
     path: PathBuf,
+    read_only: bool,
+    layout: DirectoryLayout,
+    spill: Option<SpillConfig>,
+    space_check: Option<DiskSpaceCheck>,
+    parallelism: Option<Parallelism>,
+    content_addressed: bool,
+    removal_validation: RemovalValidation,
+    text_index: bool,
+    durability: Durability,
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    io_uring: bool,
+    metrics: Arc<dyn StorageMetrics>,
 }
 
 impl DirectoryLayerStore {
     pub fn new<P: Into<PathBuf>>(path: P) -> DirectoryLayerStore {
-        DirectoryLayerStore { path: path.into() }
+        DirectoryLayerStore {
+            path: path.into(),
+            read_only: false,
+            layout: DirectoryLayout::default(),
+            spill: None,
+            space_check: None,
+            parallelism: None,
+            content_addressed: false,
+            removal_validation: RemovalValidation::Drop,
+            text_index: false,
+            durability: Durability::default(),
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            io_uring: false,
+            metrics: NOMETRICS.clone(),
+        }
+    }
+
+    /// Open a layer store that can only be read from.
+    ///
+    /// Layers are immutable once written, so a read-only layer store
+    /// can safely be used on a directory that another process is
+    /// writing to. Any attempt to create a layer directory or to open
+    /// a layer file for writing results in a
+    /// `io::ErrorKind::PermissionDenied` error. In particular, this
+    /// means that no layer builders can be created.
+    pub fn new_read_only<P: Into<PathBuf>>(path: P) -> DirectoryLayerStore {
+        DirectoryLayerStore {
+            path: path.into(),
+            read_only: true,
+            layout: DirectoryLayout::default(),
+            spill: None,
+            space_check: None,
+            parallelism: None,
+            content_addressed: false,
+            removal_validation: RemovalValidation::Drop,
+            text_index: false,
+            durability: Durability::default(),
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            io_uring: false,
+            metrics: NOMETRICS.clone(),
+        }
+    }
+
+    /// Use the given layout for layer directories.
+    ///
+    /// Panics if the layout is sharded with a prefix that is empty or
+    /// spans the full layer name.
+    pub fn with_layout(mut self, layout: DirectoryLayout) -> Self {
+        if let DirectoryLayout::Sharded(prefix_len) = layout {
+            assert!(
+                prefix_len > 0 && prefix_len < 40,
+                "shard prefix length should be between 1 and 39"
+            );
+        }
+
+        self.layout = layout;
+        self
+    }
+
+    /// Let layer builders created by this store spill their triples to scratch files.
+    ///
+    /// See `SimpleLayerBuilder::with_spill` for details.
+    pub fn with_spill(mut self, config: SpillConfig) -> Self {
+        self.spill = Some(config);
+        self
+    }
+
+    /// Limit how many parts of a layer builders created by this store build at the same time.
+    ///
+    /// See `Parallelism` for details.
+    pub fn with_parallelism(mut self, parallelism: Parallelism) -> Self {
+        self.parallelism = Some(parallelism);
+        self
+    }
+
+    /// Check for free space before layer builders created by this store write their files.
+    ///
+    /// See `SimpleLayerBuilder::with_space_check` for details.
+    pub fn with_space_check(mut self) -> Self {
+        if self.space_check.is_none() {
+            self.space_check = Some(DiskSpaceCheck::new(self.path.clone()));
+        }
+        self
+    }
+
+    /// Refuse to write layers that would grow this store beyond the given amount of bytes.
+    ///
+    /// This also enables the free space check. The size of the store
+    /// is determined by walking its directory on every commit.
+    pub fn with_quota(mut self, quota: u64) -> Self {
+        self.space_check = Some(DiskSpaceCheck::new(self.path.clone()).with_quota(quota));
+        self
+    }
+
+    /// Report all file IO done by this store to the given metrics.
+    pub fn with_metrics(mut self, metrics: Arc<dyn StorageMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Name committed layers after their content rather than randomly.
+    ///
+    /// See `LayerStore::layer_content_name` for how this name is
+    /// derived. Layers with the same content are only stored once.
+    pub fn with_content_addressing(mut self) -> Self {
+        self.content_addressed = true;
+        self
+    }
+
+    pub fn is_content_addressed(&self) -> bool {
+        self.content_addressed
+    }
+
+    /// Set what child layer builders created by this store do with removals of triples that don't exist.
+    ///
+    /// See `SimpleLayerBuilder::with_removal_validation` for details.
+    pub fn with_removal_validation(mut self, validation: RemovalValidation) -> Self {
+        self.removal_validation = validation;
+        self
+    }
+
+    /// Build a full-text index over the string values of every layer built by this store.
+    ///
+    /// See `TextIndex` for details.
+    pub fn with_text_index(mut self) -> Self {
+        self.text_index = true;
+        self
+    }
+
+    /// Set how much effort is spent on making written layers durable.
+    ///
+    /// The default is `Durability::Full`.
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    pub fn durability(&self) -> Durability {
+        self.durability
+    }
+
+    /// Load layer files through io_uring.
+    ///
+    /// See `FileBackedStore::with_io_uring`.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    pub fn with_io_uring(mut self) -> Self {
+        self.io_uring = true;
+        self
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn layout(&self) -> DirectoryLayout {
+        self.layout
+    }
+
+    fn layer_path(&self, name: [u32; 5]) -> PathBuf {
+        self.layout.layer_path(&self.path, name)
+    }
+
+    /// Move all layers that are laid out according to `from` to this store's layout.
+    ///
+    /// This returns the number of layers that were moved. Shard
+    /// directories of the old layout are removed once they are
+    /// empty. No other process should use the store while it is
+    /// being migrated.
+    pub async fn migrate_layout(&self, from: DirectoryLayout) -> io::Result<usize> {
+        if self.read_only {
+            return Err(read_only_error());
+        }
+
+        let mut moved = 0;
+        for layer in from.layers(&self.path).await? {
+            let old_path = from.layer_path(&self.path, layer);
+            let new_path = self.layer_path(layer);
+            if old_path == new_path || fs::metadata(&new_path).await.is_ok() {
+                continue;
+            }
+
+            if let Some(parent) = new_path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            fs::rename(old_path, new_path).await?;
+            moved += 1;
+        }
+
+        if let DirectoryLayout::Sharded(prefix_len) = from {
+            if from != self.layout {
+                let mut shards = fs::read_dir(&self.path).await?;
+                while let Some(shard) = shards.next_entry().await? {
+                    if shard.file_type().await?.is_dir() && shard.file_name().len() == prefix_len {
+                        // this fails if the directory isn't empty,
+                        // which is fine, as then it isn't an old shard.
+                        let _ = fs::remove_dir(shard.path()).await;
+                    }
+                }
+            }
+        }
+
+        Ok(moved)
     }
 }
 
@@ -116,23 +579,98 @@ impl PersistentLayerStore for DirectoryLayerStore {
     type File = FileBackedStore;
     fn directories(&self) -> Pin<Box<dyn Future<Output = io::Result<Vec<[u32; 5]>>> + Send>> {
         let path = self.path.clone();
+        let layout = self.layout;
+        Box::pin(async move { layout.layers(&path).await })
+    }
+
+    fn spill_config(&self) -> Option<SpillConfig> {
+        self.spill.clone()
+    }
+
+    fn space_check(&self) -> Option<Arc<dyn SpaceCheck>> {
+        self.space_check
+            .clone()
+            .map(|check| Arc::new(check) as Arc<dyn SpaceCheck>)
+    }
+
+    fn parallelism(&self) -> Option<Parallelism> {
+        self.parallelism.clone()
+    }
+
+    fn removal_validation(&self) -> RemovalValidation {
+        self.removal_validation
+    }
+
+    fn text_index(&self) -> bool {
+        self.text_index
+    }
+
+    fn checkpoint_directory(&self, name: [u32; 5]) -> Option<PathBuf> {
+        Some(self.layer_path(name).join("checkpoint"))
+    }
+
+    fn content_addressed(&self) -> bool {
+        self.content_addressed
+    }
+
+    fn remove_directory(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        if self.read_only {
+            return Box::pin(future::err(read_only_error()));
+        }
+
+        let p = self.layer_path(name);
+        Box::pin(async move { fs::remove_dir_all(p).await })
+    }
+
+    fn sync_directory(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        if self.read_only || self.durability == Durability::None {
+            return Box::pin(future::ok(()));
+        }
+
+        let p = self.layer_path(name);
+        let root = self.path.clone();
         Box::pin(async move {
-            let mut stream = fs::read_dir(path).await?;
-            let mut result = Vec::new();
-            while let Some(direntry) = stream.next_entry().await? {
-                if direntry.file_type().await?.is_dir() {
-                    let os_name = direntry.file_name();
-                    let name = os_name.to_str().ok_or_else(|| {
-                        io::Error::new(
-                            io::ErrorKind::InvalidData,
-                            "unexpected non-utf8 directory name",
-                        )
-                    })?;
-                    result.push(string_to_name(name)?);
+            sync_dir(&p).await?;
+            // sync the parents up to the store directory, as a shard directory may be new too
+            let mut dir = p.parent();
+            while let Some(d) = dir {
+                sync_dir(d).await?;
+                if d == root {
+                    break;
                 }
+                dir = d.parent();
             }
 
-            Ok(result)
+            Ok(())
+        })
+    }
+
+    fn rename_directory(
+        &self,
+        from: [u32; 5],
+        to: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        if self.read_only {
+            return Box::pin(future::err(read_only_error()));
+        }
+
+        let from = self.layer_path(from);
+        let to = self.layer_path(to);
+        Box::pin(async move {
+            if fs::metadata(&to).await.is_ok() {
+                fs::remove_dir_all(from).await
+            } else {
+                if let Some(parent) = to.parent() {
+                    fs::create_dir_all(parent).await?;
+                }
+                fs::rename(from, to).await
+            }
         })
     }
 
@@ -140,10 +678,11 @@ impl PersistentLayerStore for DirectoryLayerStore {
         &self,
         name: [u32; 5],
     ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
-        let mut p = self.path.clone();
-        let name_str = name_to_string(name);
-        p.push(&name_str[0..PREFIX_DIR_SIZE]);
-        p.push(name_str);
+        if self.read_only {
+            return Box::pin(future::err(read_only_error()));
+        }
+
+        let p = self.layer_path(name);
 
         Box::pin(async move {
             fs::create_dir_all(p).await?;
@@ -156,10 +695,7 @@ impl PersistentLayerStore for DirectoryLayerStore {
         &self,
         name: [u32; 5],
     ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>> {
-        let mut p = self.path.clone();
-        let name = name_to_string(name);
-        p.push(&name[0..PREFIX_DIR_SIZE]);
-        p.push(name);
+        let p = self.layer_path(name);
 
         Box::pin(async move {
             match fs::metadata(p).await {
@@ -174,12 +710,22 @@ impl PersistentLayerStore for DirectoryLayerStore {
         directory: [u32; 5],
         name: &str,
     ) -> Pin<Box<dyn Future<Output = io::Result<Self::File>> + Send>> {
-        let mut p = self.path.clone();
-        let dir_name = name_to_string(directory);
-        p.push(&dir_name[0..PREFIX_DIR_SIZE]);
-        p.push(dir_name);
+        let mut p = self.layer_path(directory);
         p.push(name);
-        Box::pin(future::ok(FileBackedStore::new(p)))
+        let file = if self.read_only {
+            FileBackedStore::new_read_only(p)
+        } else {
+            FileBackedStore::new(p)
+        }
+        .with_durability(self.durability);
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        let file = if self.io_uring {
+            file.with_io_uring()
+        } else {
+            file
+        };
+
+        Box::pin(future::ok(file.with_metrics(self.metrics.clone())))
     }
 
     fn file_exists(
@@ -187,10 +733,7 @@ impl PersistentLayerStore for DirectoryLayerStore {
         directory: [u32; 5],
         file: &str,
     ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>> {
-        let mut p = self.path.clone();
-        let dir_name = name_to_string(directory);
-        p.push(&dir_name[0..PREFIX_DIR_SIZE]);
-        p.push(dir_name);
+        let mut p = self.layer_path(directory);
         p.push(file);
 
         Box::pin(async move {
@@ -202,14 +745,171 @@ impl PersistentLayerStore for DirectoryLayerStore {
     }
 }
 
+/// How the files of cloned layers ended up in the target store.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClonedFiles {
+    /// Files that were hard linked, taking no extra space at all.
+    pub linked: usize,
+    /// Files that had to be copied.
+    ///
+    /// On Linux, copying is done with `copy_file_range`, which on
+    /// filesystems that support it results in a reflink rather than
+    /// an actual copy.
+    pub copied: usize,
+}
+
+impl ClonedFiles {
+    fn add(&mut self, other: ClonedFiles) {
+        self.linked += other.linked;
+        self.copied += other.copied;
+    }
+}
+
+/// Clone a single file, preferring a hard link over a copy.
+async fn clone_file(from: &Path, to: &Path, sync: bool) -> io::Result<ClonedFiles> {
+    if fs::hard_link(from, to).await.is_ok() {
+        return Ok(ClonedFiles {
+            linked: 1,
+            copied: 0,
+        });
+    }
+
+    fs::copy(from, to).await?;
+    if sync {
+        File::sync_all(&File::open(to).await?).await?;
+    }
+
+    Ok(ClonedFiles {
+        linked: 0,
+        copied: 1,
+    })
+}
+
+/// Clone the files of a layer from one directory layer store into another.
+///
+/// Layer files never change once written, so when both stores are
+/// on the same filesystem, the files are hard linked rather than
+/// copied, which makes cloning nearly free. Otherwise, the files are
+/// copied. The layer appears in the target store completely or not
+/// at all.
+///
+/// The rollup file of the layer is not cloned, as the rollup layer
+/// may not exist in the target store. `clone_all_layers` does clone
+/// rollup files.
+pub async fn clone_layer_files(
+    from: &DirectoryLayerStore,
+    to: &DirectoryLayerStore,
+    layer: [u32; 5],
+) -> io::Result<ClonedFiles> {
+    if to.read_only {
+        return Err(read_only_error());
+    }
+
+    let source = from.layer_path(layer);
+    let temp = to.create_directory().await?;
+    let temp_path = to.layer_path(temp);
+    let sync = to.durability == Durability::Full;
+    let mut result = ClonedFiles::default();
+    for file_name in all_layer_files() {
+        if file_name == FILENAMES.rollup {
+            continue;
+        }
+
+        let file = source.join(file_name);
+        if fs::metadata(&file).await.is_err() {
+            continue;
+        }
+
+        match clone_file(&file, &temp_path.join(file_name), sync).await {
+            Ok(cloned) => result.add(cloned),
+            Err(e) => {
+                let _ = fs::remove_dir_all(&temp_path).await;
+                return Err(e);
+            }
+        }
+    }
+
+    to.rename_directory(temp, layer).await?;
+    to.sync_directory(layer).await?;
+
+    Ok(result)
+}
+
+/// Clone all layers of one directory layer store into another, skipping layers that already exist.
+///
+/// See `clone_layer_files`. Rollup files are copied once all
+/// layers are in place. This is a cheap way to fork a store.
+pub async fn clone_all_layers(
+    from: &DirectoryLayerStore,
+    to: &DirectoryLayerStore,
+) -> io::Result<ClonedFiles> {
+    let mut result = ClonedFiles::default();
+    let layers = from.directories().await?;
+    for layer in layers.iter() {
+        if !to.directory_exists(*layer).await? {
+            result.add(clone_layer_files(from, to, *layer).await?);
+        }
+    }
+
+    for layer in layers {
+        let source = from.layer_path(layer).join(FILENAMES.rollup);
+        let target = to.layer_path(layer).join(FILENAMES.rollup);
+        if fs::metadata(&source).await.is_ok() && fs::metadata(&target).await.is_err() {
+            // rollup files can be overwritten in place, so they should never be shared
+            fs::copy(source, target).await?;
+            result.copied += 1;
+        }
+    }
+
+    Ok(result)
+}
+
 #[derive(Clone)]
 pub struct DirectoryLabelStore {
     path: PathBuf,
+    read_only: bool,
+    lock_policy: LockPolicy,
 }
 
 impl DirectoryLabelStore {
     pub fn new<P: Into<PathBuf>>(path: P) -> DirectoryLabelStore {
-        DirectoryLabelStore { path: path.into() }
+        DirectoryLabelStore {
+            path: path.into(),
+            read_only: false,
+            lock_policy: LockPolicy::Block,
+        }
+    }
+
+    /// Open a label store that can only be read from.
+    ///
+    /// Labels are read under a shared lock, so readers will never
+    /// observe a half-written label file, but no exclusive lock is
+    /// ever taken. Creating, updating or deleting a label results in
+    /// a `io::ErrorKind::PermissionDenied` error.
+    pub fn new_read_only<P: Into<PathBuf>>(path: P) -> DirectoryLabelStore {
+        DirectoryLabelStore {
+            path: path.into(),
+            read_only: true,
+            lock_policy: LockPolicy::Block,
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Set what to do when a label file is locked by another process.
+    ///
+    /// By default, label operations block until the lock becomes
+    /// available. With any other policy, a label operation may fail
+    /// with an error from which a `LockError` can be retrieved.
+    pub fn with_lock_policy(mut self, policy: LockPolicy) -> Self {
+        self.lock_policy = policy;
+        self
+    }
+
+    pub fn lock_policy(&self) -> LockPolicy {
+        self.lock_policy
     }
 }
 
@@ -256,49 +956,401 @@ fn get_label_from_data(name: String, data: &[u8]) -> io::Result<Label> {
     }
 }
 
-async fn get_label_from_file<P: Into<PathBuf>>(path: P) -> io::Result<Label> {
-    let path: PathBuf = path.into();
-    let label = path.file_stem().unwrap().to_str().unwrap().to_owned();
+/// The path of the journal file for the given label file.
+///
+/// Label updates are first written to this journal, then applied to
+/// the label file itself, after which the journal is removed. Should
+/// a crash happen halfway through updating the label file, the
+/// journal will be replayed the next time the label is read.
+fn label_journal_path(path: &Path) -> PathBuf {
+    let mut p = path.as_os_str().to_owned();
+    p.push(".journal");
+    p.into()
+}
 
-    let mut file = LockedFile::open(path).await?;
-    let mut data = Vec::new();
-    file.read_to_end(&mut data).await?;
+/// The path of the history file for the given label file.
+///
+/// Every label update is appended to this file as a line containing
+/// the new version, the previous layer, the new layer and the time
+/// of the update in milliseconds since the unix epoch. A missing
+/// layer is written as `-`.
+fn label_history_path(path: &Path) -> PathBuf {
+    let mut p = path.as_os_str().to_owned();
+    p.push(".history");
+    p.into()
+}
 
-    get_label_from_data(label, &data)
+fn label_history_line(entry: &LabelHistoryEntry) -> Vec<u8> {
+    let layer_string = |layer: Option<[u32; 5]>| match layer {
+        None => "-".to_string(),
+        Some(layer) => layer::name_to_string(layer),
+    };
+    let millis = entry
+        .timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+
+    format!(
+        "{} {} {} {}\n",
+        entry.version,
+        layer_string(entry.previous_layer),
+        layer_string(entry.layer),
+        millis
+    )
+    .into_bytes()
 }
 
-async fn get_label_from_exclusive_locked_file<P: Into<PathBuf>>(
-    path: P,
-) -> io::Result<(Label, ExclusiveLockedFile)> {
-    let path: PathBuf = path.into();
-    let label = path.file_stem().unwrap().to_str().unwrap().to_owned();
+fn parse_label_history_line(line: &[u8]) -> io::Result<LabelHistoryEntry> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "invalid label history line ({:?})",
+                String::from_utf8_lossy(line)
+            ),
+        )
+    };
+    let line = std::str::from_utf8(line).map_err(|_| invalid())?;
+    let parts: Vec<&str> = line.trim_end().split(' ').collect();
+    if parts.len() != 4 {
+        return Err(invalid());
+    }
+    let parse_layer = |s: &str| match s {
+        "-" => Ok(None),
+        s => layer::string_to_name(s).map(Some),
+    };
+    let version = parts[0].parse().map_err(|_| invalid())?;
+    let millis = parts[3].parse().map_err(|_| invalid())?;
+
+    Ok(LabelHistoryEntry {
+        version,
+        previous_layer: parse_layer(parts[1])?,
+        layer: parse_layer(parts[2])?,
+        timestamp: UNIX_EPOCH + Duration::from_millis(millis),
+    })
+}
 
-    let mut file = ExclusiveLockedFile::open(path).await?;
-    let mut data = Vec::new();
-    file.read_to_end(&mut data).await?;
+/// Append an entry to the history of the given label file.
+///
+/// The label file is expected to be exclusively locked.
+async fn append_label_history(path: &Path, entry: &LabelHistoryEntry) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(label_history_path(path))
+        .await?;
+    file.write_all(&label_history_line(entry)).await?;
+    file.flush().await?;
+    File::sync_all(&file).await
+}
 
-    let label = get_label_from_data(label, &data)?;
-    file.seek(SeekFrom::Start(0)).await?;
+async fn remove_label_history(path: &Path) -> io::Result<()> {
+    match fs::remove_file(label_history_path(path)).await {
+        Ok(()) => Ok(()),
+        Err(e) => match e.kind() {
+            io::ErrorKind::NotFound => Ok(()),
+            _ => Err(e),
+        },
+    }
+}
 
-    Ok((label, file))
+/// The path of the prefix file for the given label file.
+///
+/// Every prefix is written as a line containing the prefix and the
+/// IRI it abbreviates, separated by a space.
+fn label_prefixes_path(path: &Path) -> PathBuf {
+    let mut p = path.as_os_str().to_owned();
+    p.push(".prefixes");
+    p.into()
 }
 
-#[async_trait]
-impl LabelStore for DirectoryLabelStore {
-    async fn labels(&self) -> io::Result<Vec<Label>> {
-        let mut stream = fs::read_dir(self.path.clone()).await?;
-        let mut result = Vec::new();
-        while let Some(direntry) = stream.next_entry().await? {
-            if direntry.file_type().await?.is_file() {
-                let os_name = direntry.file_name();
-                let name = os_name.to_str().ok_or_else(|| {
-                    io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "unexpected non-utf8 directory name",
-                    )
-                })?;
+fn parse_label_prefixes(data: &[u8]) -> io::Result<PrefixMap> {
+    let invalid = |line: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid label prefix line ({:?})", line),
+        )
+    };
+    let data = std::str::from_utf8(data).map_err(|_| invalid("<non-utf8>"))?;
+    let mut prefixes = PrefixMap::new();
+    for line in data.lines() {
+        let (prefix, iri) = line.split_once(' ').ok_or_else(|| invalid(line))?;
+        prefixes.insert(prefix.to_owned(), iri.to_owned());
+    }
+
+    Ok(prefixes)
+}
+
+/// Replace the prefixes of the given label file.
+///
+/// The prefixes are written to a temporary file which is then moved
+/// into place, so a crash leaves either the old or the new prefixes.
+/// The label file is expected to be exclusively locked.
+async fn write_label_prefixes(path: &Path, prefixes: &PrefixMap) -> io::Result<()> {
+    let mut contents = String::new();
+    for (prefix, iri) in prefixes.iter() {
+        if prefix.contains(char::is_whitespace) || iri.contains(char::is_whitespace) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("prefix {:?} for {:?} contains whitespace", prefix, iri),
+            ));
+        }
+        contents.push_str(&format!("{} {}\n", prefix, iri));
+    }
+
+    let prefixes_path = label_prefixes_path(path);
+    let mut tmp_path = prefixes_path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let mut file = File::create(&tmp_path).await?;
+    file.write_all(contents.as_bytes()).await?;
+    file.flush().await?;
+    File::sync_all(&file).await?;
+    std::mem::drop(file);
+    fs::rename(&tmp_path, &prefixes_path).await?;
+
+    sync_directory(path).await
+}
+
+async fn remove_label_prefixes(path: &Path) -> io::Result<()> {
+    match fs::remove_file(label_prefixes_path(path)).await {
+        Ok(()) => Ok(()),
+        Err(e) => match e.kind() {
+            io::ErrorKind::NotFound => Ok(()),
+            _ => Err(e),
+        },
+    }
+}
+
+fn label_contents(label: &Label) -> Vec<u8> {
+    match label.layer {
+        None => format!("{}\n\n", label.version).into_bytes(),
+        Some(layer) => {
+            format!("{}\n{}\n", label.version, layer::name_to_string(layer)).into_bytes()
+        }
+    }
+}
+
+/// A complete label journal, as returned by `read_label_journal`.
+struct LabelJournal {
+    contents: Vec<u8>,
+    /// The transaction file of the multi-label update this journal is part of, if any.
+    transaction: Option<PathBuf>,
+}
+
+/// Split off the transaction line that starts the journals written by `set_labels_if`.
+fn split_label_journal(data: &[u8]) -> (Option<&str>, &[u8]) {
+    if let Some(pos) = data.iter().position(|&b| b == b'\n') {
+        if data[..pos].ends_with(b".transaction") {
+            if let Ok(transaction) = std::str::from_utf8(&data[..pos]) {
+                return (Some(transaction), &data[pos + 1..]);
+            }
+        }
+    }
+
+    (None, data)
+}
+
+/// Read the journal for the given label file, returning its contents
+/// only if it contains a complete label.
+///
+/// An incomplete journal is the result of a crash before the label
+/// file itself was touched, so it can safely be ignored. The same
+/// goes for a journal that is part of a multi-label update whose
+/// transaction file was never written.
+async fn read_label_journal(path: &Path) -> io::Result<Option<LabelJournal>> {
+    let data = match fs::read(label_journal_path(path)).await {
+        Ok(data) => data,
+        Err(e) => match e.kind() {
+            io::ErrorKind::NotFound => return Ok(None),
+            _ => return Err(e),
+        },
+    };
+    let (transaction, contents) = split_label_journal(&data);
+    if get_label_from_data(String::new(), contents).is_err() {
+        return Ok(None);
+    }
+    let transaction = match transaction {
+        None => None,
+        Some(transaction) => {
+            let transaction = path.with_file_name(transaction);
+            if fs::metadata(&transaction).await.is_err() {
+                return Ok(None);
+            }
+
+            Some(transaction)
+        }
+    };
+
+    Ok(Some(LabelJournal {
+        contents: contents.to_vec(),
+        transaction,
+    }))
+}
+
+async fn write_label_journal(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut journal = File::create(label_journal_path(path)).await?;
+    journal.write_all(contents).await?;
+    journal.flush().await?;
+    File::sync_all(&journal).await
+}
+
+/// Remove a transaction file once none of its labels has a journal left to replay.
+async fn finish_label_transaction(transaction: &Path) -> io::Result<()> {
+    let names = match fs::read_to_string(transaction).await {
+        Ok(names) => names,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for name in names.lines() {
+        let p = transaction.with_file_name(format!("{}.label", name));
+        if fs::metadata(label_journal_path(&p)).await.is_ok() {
+            return Ok(());
+        }
+    }
+
+    match fs::remove_file(transaction).await {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+async fn remove_label_journal(path: &Path) -> io::Result<()> {
+    match fs::remove_file(label_journal_path(path)).await {
+        Ok(()) => Ok(()),
+        Err(e) => match e.kind() {
+            io::ErrorKind::NotFound => Ok(()),
+            _ => Err(e),
+        },
+    }
+}
+
+/// Sync the directory containing the given path.
+async fn sync_directory(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) => sync_dir(dir).await,
+        None => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+async fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::sync_all(&File::open(dir).await?).await
+}
+
+#[cfg(not(unix))]
+async fn sync_dir(_dir: &Path) -> io::Result<()> {
+    // directories cannot be opened for syncing on this platform
+    Ok(())
+}
+
+fn notify_error(error: notify::Error) -> io::Error {
+    match error.kind {
+        notify::ErrorKind::Io(e) => e,
+        _ => io::Error::other(error),
+    }
+}
+
+async fn overwrite_locked_label_file(
+    file: &mut ExclusiveLockedFile,
+    contents: &[u8],
+) -> io::Result<()> {
+    file.seek(SeekFrom::Start(0)).await?;
+    file.write_all(contents).await?;
+    file.truncate().await?;
+    file.flush().await?;
+    file.sync_all().await
+}
+
+/// Write a label update in a crash-safe way.
+///
+/// The file is expected to be exclusively locked.
+async fn write_locked_label_file(
+    path: &Path,
+    file: &mut ExclusiveLockedFile,
+    contents: &[u8],
+) -> io::Result<()> {
+    write_label_journal(path, contents).await?;
+    sync_directory(path).await?;
+
+    overwrite_locked_label_file(file, contents).await?;
+
+    remove_label_journal(path).await
+}
+
+async fn get_label_from_file(
+    path: PathBuf,
+    read_only: bool,
+    policy: LockPolicy,
+) -> io::Result<Label> {
+    let label = path.file_stem().unwrap().to_str().unwrap().to_owned();
+
+    let mut file = LockedFile::open_with_policy(path.clone(), policy).await?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).await?;
+
+    // While we hold the shared lock, no writer can be busy with this
+    // label, so a complete journal is a leftover from a crash.
+    if let Some(journal) = read_label_journal(&path).await? {
+        if read_only {
+            // we are not allowed to replay the journal, but its
+            // contents are what the label will be once it is.
+            return get_label_from_data(label, &journal.contents);
+        }
+
+        std::mem::drop(file);
+        let (label, _file) = get_label_from_exclusive_locked_file(path, policy).await?;
+
+        return Ok(label);
+    }
+
+    get_label_from_data(label, &data)
+}
+
+async fn get_label_from_exclusive_locked_file(
+    path: PathBuf,
+    policy: LockPolicy,
+) -> io::Result<(Label, ExclusiveLockedFile)> {
+    let label = path.file_stem().unwrap().to_str().unwrap().to_owned();
+
+    let mut file = ExclusiveLockedFile::open_with_policy(path.clone(), policy).await?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).await?;
+
+    let journal = read_label_journal(&path).await?;
+    if let Some(journal) = &journal {
+        overwrite_locked_label_file(&mut file, &journal.contents).await?;
+        data = journal.contents.clone();
+    }
+    remove_label_journal(&path).await?;
+    if let Some(transaction) = journal.and_then(|j| j.transaction) {
+        finish_label_transaction(&transaction).await?;
+    }
+
+    let label = get_label_from_data(label, &data)?;
+    file.seek(SeekFrom::Start(0)).await?;
+
+    Ok((label, file))
+}
+
+#[async_trait]
+impl LabelStore for DirectoryLabelStore {
+    async fn labels(&self) -> io::Result<Vec<Label>> {
+        let mut stream = fs::read_dir(self.path.clone()).await?;
+        let mut result = Vec::new();
+        while let Some(direntry) = stream.next_entry().await? {
+            if direntry.file_type().await?.is_file() {
+                let os_name = direntry.file_name();
+                let name = os_name.to_str().ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "unexpected non-utf8 directory name",
+                    )
+                })?;
                 if name.ends_with(".label") {
-                    let label = get_label_from_file(name).await?;
+                    let label =
+                        get_label_from_file(direntry.path(), self.read_only, self.lock_policy)
+                            .await?;
                     result.push(label);
                 }
             }
@@ -308,6 +1360,10 @@ impl LabelStore for DirectoryLabelStore {
     }
 
     async fn create_label(&self, label: &str) -> io::Result<Label> {
+        if self.read_only {
+            return Err(read_only_error());
+        }
+
         let mut p = self.path.clone();
         p.push(format!("{}.label", label));
         let contents = "0\n\n".to_string().into_bytes();
@@ -318,7 +1374,15 @@ impl LabelStore for DirectoryLabelStore {
             )),
             Err(e) => match e.kind() {
                 io::ErrorKind::NotFound => {
-                    let mut file = ExclusiveLockedFile::create_and_open(p).await?;
+                    // a journal can only be left behind by a label
+                    // that was since deleted, and should not be
+                    // replayed onto this new label.
+                    remove_label_journal(&p).await?;
+                    remove_label_history(&p).await?;
+                    remove_label_prefixes(&p).await?;
+                    let mut file =
+                        ExclusiveLockedFile::create_and_open_with_policy(p, self.lock_policy)
+                            .await?;
                     file.write_all(&contents).await?;
                     file.flush().await?;
                     file.sync_all().await?;
@@ -334,7 +1398,7 @@ impl LabelStore for DirectoryLabelStore {
         let mut p = self.path.clone();
         p.push(format!("{}.label", label));
 
-        match get_label_from_file(p).await {
+        match get_label_from_file(p, self.read_only, self.lock_policy).await {
             Ok(label) => Ok(Some(label)),
             Err(e) => match e.kind() {
                 io::ErrorKind::NotFound => Ok(None),
@@ -343,35 +1407,148 @@ impl LabelStore for DirectoryLabelStore {
         }
     }
 
+    async fn get_labels(&self, names: &[&str]) -> io::Result<Vec<Option<Label>>> {
+        // Hold a shared lock on every label while reading them. Writers
+        // need an exclusive lock, and multi-label updates keep theirs
+        // until every label is written, so nothing changes in between.
+        let mut unique: Vec<&str> = names.to_vec();
+        unique.sort_unstable();
+        unique.dedup();
+        let mut locks = Vec::with_capacity(unique.len());
+        for name in unique.iter() {
+            let p = self.path.join(format!("{}.label", name));
+            match LockedFile::open_with_policy(p.clone(), self.lock_policy).await {
+                Ok(file) => locks.push((*name, p, Some(file))),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => locks.push((*name, p, None)),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut labels = HashMap::with_capacity(locks.len());
+        for (name, p, file) in locks.iter_mut() {
+            let file = match file {
+                Some(file) => file,
+                None => continue,
+            };
+            let mut data = Vec::new();
+            file.read_to_end(&mut data).await?;
+            // no writer is busy with this label, so a complete journal
+            // is a leftover from a crash, and holds the current label.
+            // It is replayed on the next regular read.
+            let label = match read_label_journal(p).await? {
+                Some(journal) => get_label_from_data(name.to_string(), &journal.contents)?,
+                None => get_label_from_data(name.to_string(), &data)?,
+            };
+            labels.insert(*name, label);
+        }
+
+        Ok(names.iter().map(|name| labels.get(name).cloned()).collect())
+    }
+
     async fn set_label_option(
         &self,
         label: &Label,
         layer: Option<[u32; 5]>,
     ) -> io::Result<Option<Label>> {
+        if self.read_only {
+            return Err(read_only_error());
+        }
+
         let new_label = label.with_updated_layer(layer);
-        let contents = match new_label.layer {
-            None => format!("{}\n\n", new_label.version).into_bytes(),
-            Some(layer) => {
-                format!("{}\n{}\n", new_label.version, layer::name_to_string(layer)).into_bytes()
-            }
-        };
+        let contents = label_contents(&new_label);
 
         let mut p = self.path.clone();
         p.push(format!("{}.label", label.name));
-        let (retrieved_label, mut file) = get_label_from_exclusive_locked_file(p).await?;
+        let (retrieved_label, mut file) =
+            get_label_from_exclusive_locked_file(p.clone(), self.lock_policy).await?;
         if retrieved_label == *label {
             // all good, let's a go
-            file.truncate().await?;
-            file.write_all(&contents).await?;
-            file.flush().await?;
-            file.sync_all().await?;
+            write_locked_label_file(&p, &mut file, &contents).await?;
+            // a crash right here loses this history entry, but
+            // never the label update itself.
+            append_label_history(&p, &LabelHistoryEntry::new(label, &new_label)).await?;
             Ok(Some(new_label))
         } else {
             Ok(None)
         }
     }
 
+    async fn set_labels_if(&self, updates: Vec<LabelUpdate>) -> Result<Vec<Label>, SetLabelsError> {
+        if self.read_only {
+            return Err(read_only_error().into());
+        }
+        check_unique_label_updates(&updates)?;
+
+        // Lock the labels in order of their names, so that concurrent
+        // multi-label updates cannot deadlock.
+        let mut order: Vec<usize> = (0..updates.len()).collect();
+        order.sort_by(|&i1, &i2| updates[i1].name.cmp(&updates[i2].name));
+        let mut locked = Vec::with_capacity(updates.len());
+        for &i in order.iter() {
+            let update = &updates[i];
+            let p = self.path.join(format!("{}.label", update.name));
+            let (label, file) =
+                get_label_from_exclusive_locked_file(p.clone(), self.lock_policy).await?;
+            if label.layer != update.expected_layer {
+                return Err(SetLabelsError::Conflict {
+                    label: update.name.clone(),
+                    conflict: LabelConflict {
+                        current_head: label.layer,
+                    },
+                });
+            }
+            let new_label = label.with_updated_layer(update.new_layer);
+            locked.push((i, p, file, label, new_label));
+        }
+
+        // Prepare a journal for every label. These journals refer to
+        // a transaction file, and are only replayed once it exists.
+        let transaction_name = format!(
+            "{}.transaction",
+            layer::name_to_string(rand::random::<[u32; 5]>())
+        );
+        let transaction = self.path.join(&transaction_name);
+        for (_, p, _, _, new_label) in locked.iter() {
+            let mut contents = format!("{}\n", transaction_name).into_bytes();
+            contents.extend(label_contents(new_label));
+            write_label_journal(p, &contents).await?;
+        }
+        sync_dir(&self.path).await?;
+
+        // Writing the transaction file commits the update. From here
+        // on, a crash leaves every label to be replayed on its next
+        // read.
+        let mut names = String::new();
+        for (_, _, _, label, _) in locked.iter() {
+            names.push_str(&label.name);
+            names.push('\n');
+        }
+        let mut file = File::create(&transaction).await?;
+        file.write_all(names.as_bytes()).await?;
+        file.flush().await?;
+        File::sync_all(&file).await?;
+        std::mem::drop(file);
+        sync_dir(&self.path).await?;
+
+        for (_, p, file, label, new_label) in locked.iter_mut() {
+            overwrite_locked_label_file(file, &label_contents(new_label)).await?;
+            remove_label_journal(p).await?;
+            append_label_history(p, &LabelHistoryEntry::new(label, new_label)).await?;
+        }
+        fs::remove_file(&transaction).await?;
+
+        locked.sort_by_key(|(i, ..)| *i);
+        Ok(locked
+            .into_iter()
+            .map(|(_, _, _, _, new_label)| new_label)
+            .collect())
+    }
+
     async fn delete_label(&self, name: &str) -> io::Result<bool> {
+        if self.read_only {
+            return Err(read_only_error());
+        }
+
         let mut p = self.path.clone();
         p.push(format!("{}.label", name));
 
@@ -384,14 +1561,179 @@ impl LabelStore for DirectoryLabelStore {
         // though the file will be gone afterwards. This is
         // indistinguishable from the case where the read/write and
         // the remove happened in reverse order.
-        match tokio::fs::remove_file(p).await {
-            Ok(()) => Ok(true),
+        match tokio::fs::remove_file(&p).await {
+            Ok(()) => {
+                remove_label_journal(&p).await?;
+                remove_label_history(&p).await?;
+                remove_label_prefixes(&p).await?;
+                Ok(true)
+            }
             Err(e) => match e.kind() {
                 io::ErrorKind::NotFound => Ok(false),
                 _ => Err(e),
             },
         }
     }
+
+    async fn rename_label(&self, name: &str, new_name: &str) -> io::Result<Label> {
+        if self.read_only {
+            return Err(read_only_error());
+        }
+
+        let p = self.path.join(format!("{}.label", name));
+        let new_p = self.path.join(format!("{}.label", new_name));
+        // holding the lock keeps writers out while the label moves,
+        // and replays any leftover journal, so the file is current.
+        let (label, _file) =
+            get_label_from_exclusive_locked_file(p.clone(), self.lock_policy).await?;
+        remove_label_journal(&new_p).await?;
+        // unlike a rename, linking never replaces an existing label
+        match fs::hard_link(&p, &new_p).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "database already exists",
+                ))
+            }
+            Err(e) => return Err(e),
+        }
+        fs::remove_file(&p).await?;
+        remove_label_history(&new_p).await?;
+        remove_label_prefixes(&new_p).await?;
+        for path in [label_history_path, label_prefixes_path] {
+            match fs::rename(path(&p), path(&new_p)).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        sync_directory(&new_p).await?;
+
+        Ok(Label {
+            name: new_name.to_owned(),
+            ..label
+        })
+    }
+
+    async fn label_history(&self, name: &str) -> io::Result<LabelHistoryStream> {
+        let p = self.path.join(format!("{}.label", name));
+        // holding a shared lock while opening the history keeps
+        // writers from appending a partial line in between.
+        let file = {
+            let _lock = LockedFile::open_with_policy(p.clone(), self.lock_policy).await?;
+            match File::open(label_history_path(&p)).await {
+                Ok(file) => Some(file),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e),
+            }
+        };
+        let file = match file {
+            Some(file) => file,
+            None => return Ok(Box::pin(stream::empty())),
+        };
+
+        Ok(Box::pin(stream::unfold(
+            Some(tokio::io::BufReader::new(file)),
+            |state| async move {
+                let mut reader = state?;
+                let mut line = Vec::new();
+                match reader.read_until(b'\n', &mut line).await {
+                    // a line without a newline was still being
+                    // written, or was interrupted by a crash.
+                    Ok(_) if line.last() != Some(&b'\n') => None,
+                    Ok(_) => Some((parse_label_history_line(&line), Some(reader))),
+                    Err(e) => Some((Err(e), None)),
+                }
+            },
+        )))
+    }
+
+    async fn label_prefixes(&self, name: &str) -> io::Result<PrefixMap> {
+        let p = self.path.join(format!("{}.label", name));
+        // the shared lock keeps writers from replacing the prefixes while we read them
+        let _lock = LockedFile::open_with_policy(p.clone(), self.lock_policy).await?;
+        match fs::read(label_prefixes_path(&p)).await {
+            Ok(data) => parse_label_prefixes(&data),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(PrefixMap::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn set_label_prefixes(&self, name: &str, prefixes: PrefixMap) -> io::Result<()> {
+        if self.read_only {
+            return Err(read_only_error());
+        }
+
+        let p = self.path.join(format!("{}.label", name));
+        let _lock = ExclusiveLockedFile::open_with_policy(p.clone(), self.lock_policy).await?;
+
+        write_label_prefixes(&p, &prefixes).await
+    }
+
+    async fn watch_label(&self, name: &str) -> io::Result<LabelWatchStream> {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let file_name = OsString::from(format!("{}.label", name));
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let relevant = match &event {
+                    Ok(event) => event
+                        .paths
+                        .iter()
+                        .any(|p| p.file_name() == Some(&file_name)),
+                    Err(_) => true,
+                };
+                if relevant {
+                    // an error here means the stream was dropped
+                    let _ = sender.send(event);
+                }
+            })
+            .map_err(notify_error)?;
+        watcher
+            .watch(&self.path, RecursiveMode::NonRecursive)
+            .map_err(notify_error)?;
+
+        // we only read the label after starting to watch it, so no
+        // change can get lost in between.
+        let version = match self.get_label(name).await? {
+            Some(label) => label.version,
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "label not found")),
+        };
+
+        let store = self.clone();
+        let name = name.to_owned();
+        Ok(Box::pin(stream::unfold(
+            Some((watcher, receiver, version)),
+            move |state| {
+                let store = store.clone();
+                let name = name.clone();
+                async move {
+                    let (watcher, mut receiver, version) = state?;
+                    while let Some(event) = receiver.recv().await {
+                        if let Err(e) = event {
+                            return Some((Err(notify_error(e)), None));
+                        }
+
+                        // A single label update results in several
+                        // file events. Rather than interpreting them,
+                        // we just read the label, which will block
+                        // until any ongoing update has finished.
+                        match store.get_label(&name).await {
+                            Ok(Some(label)) if label.version > version => {
+                                let version = label.version;
+                                return Some((Ok(label), Some((watcher, receiver, version))));
+                            }
+                            Ok(Some(_)) => {}
+                            Ok(None) => return None,
+                            Err(e) => return Some((Err(e), None)),
+                        }
+                    }
+
+                    None
+                }
+            },
+        )))
+    }
 }
 
 #[cfg(test)]
@@ -436,6 +1778,34 @@ mod tests {
         assert_eq!(&vec![1, 2, 3][..], &map.as_ref()[..]);
     }
 
+    #[tokio::test]
+    async fn write_and_map_range_file_backed() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("foo");
+        let file = FileBackedStore::new(file_path);
+
+        let mut w = file.open_write().await.unwrap();
+        w.write_all(&[1, 2, 3, 4, 5]).await.unwrap();
+        w.flush().await.unwrap();
+
+        let map = file.map_range(1, 3).await.unwrap();
+        assert_eq!(&[2, 3, 4][..], &map[..]);
+        assert!(file.map_range(2, 0).await.unwrap().is_empty());
+
+        let mut buf = Vec::new();
+        file.read_range(3, 2)
+            .await
+            .unwrap()
+            .read_to_end(&mut buf)
+            .await
+            .unwrap();
+        assert_eq!(vec![4, 5], buf);
+
+        let err = file.map_range(3, 3).await.unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+        assert!(file.read_range(6, 0).await.is_err());
+    }
+
     #[tokio::test]
     async fn write_and_map_large_file_backed() {
         let dir = tempdir().unwrap();
@@ -702,4 +2072,842 @@ mod tests {
 
         assert!(store.delete_label("foo").await.unwrap());
     }
+
+    #[tokio::test]
+    async fn read_only_label_store_reads_labels() {
+        let dir = tempdir().unwrap();
+        let store = DirectoryLabelStore::new(dir.path());
+        let label = store.create_label("foo").await.unwrap();
+        let label = store
+            .set_label(&label, [1, 2, 3, 4, 5])
+            .await
+            .unwrap()
+            .unwrap();
+
+        let read_only = DirectoryLabelStore::new_read_only(dir.path());
+        assert_eq!(Some(label), read_only.get_label("foo").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn read_only_label_store_refuses_writes() {
+        let dir = tempdir().unwrap();
+        let store = DirectoryLabelStore::new(dir.path());
+        let label = store.create_label("foo").await.unwrap();
+
+        let read_only = DirectoryLabelStore::new_read_only(dir.path());
+        let err = read_only.create_label("bar").await.unwrap_err();
+        assert_eq!(io::ErrorKind::PermissionDenied, err.kind());
+        let err = read_only
+            .set_label(&label, [1, 2, 3, 4, 5])
+            .await
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::PermissionDenied, err.kind());
+        let err = read_only.delete_label("foo").await.unwrap_err();
+        assert_eq!(io::ErrorKind::PermissionDenied, err.kind());
+
+        assert_eq!(Some(label), store.get_label("foo").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn read_only_layer_store_refuses_builders() {
+        let dir = tempdir().unwrap();
+        let store = DirectoryLayerStore::new(dir.path());
+        let mut builder = store.create_base_layer().await.unwrap();
+        let base_name = builder.name();
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        builder.commit_boxed().await.unwrap();
+
+        let read_only = DirectoryLayerStore::new_read_only(dir.path());
+        let layer = read_only.get_layer(base_name).await.unwrap().unwrap();
+        assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+
+        let err = read_only.create_base_layer().await.err().unwrap();
+        assert_eq!(io::ErrorKind::PermissionDenied, err.kind());
+        let err = read_only.create_child_layer(base_name).await.err().unwrap();
+        assert_eq!(io::ErrorKind::PermissionDenied, err.kind());
+    }
+
+    #[tokio::test]
+    async fn interrupted_label_update_is_replayed_from_journal() {
+        let dir = tempdir().unwrap();
+        let store = DirectoryLabelStore::new(dir.path());
+        let label = store.create_label("foo").await.unwrap();
+
+        // simulate a crash after writing the journal, halfway through
+        // rewriting the label file
+        let label_path = dir.path().join("foo.label");
+        let new_label = label.with_updated_layer(Some([1, 2, 3, 4, 5]));
+        fs::write(label_journal_path(&label_path), label_contents(&new_label))
+            .await
+            .unwrap();
+        fs::write(&label_path, b"1\n0000").await.unwrap();
+
+        assert_eq!(
+            Some(new_label.clone()),
+            store.get_label("foo").await.unwrap()
+        );
+        assert!(fs::metadata(label_journal_path(&label_path)).await.is_err());
+        assert_eq!(
+            label_contents(&new_label),
+            fs::read(&label_path).await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn directory_set_labels_if() {
+        let dir = tempdir().unwrap();
+        let store = DirectoryLabelStore::new(dir.path());
+        store.create_label("foo").await.unwrap();
+        store.create_label("bar").await.unwrap();
+
+        let labels = store
+            .set_labels_if(vec![
+                LabelUpdate::new("foo", None, Some([1, 2, 3, 4, 5])),
+                LabelUpdate::new("bar", None, Some([6, 7, 8, 9, 10])),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![Some([1, 2, 3, 4, 5]), Some([6, 7, 8, 9, 10])],
+            labels.iter().map(|l| l.layer).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            labels,
+            vec![
+                store.get_label("foo").await.unwrap().unwrap(),
+                store.get_label("bar").await.unwrap().unwrap()
+            ]
+        );
+
+        match store
+            .set_labels_if(vec![
+                LabelUpdate::new("foo", Some([1, 2, 3, 4, 5]), None),
+                LabelUpdate::new("bar", None, None),
+            ])
+            .await
+        {
+            Err(SetLabelsError::Conflict { label, conflict }) => {
+                assert_eq!("bar", label);
+                assert_eq!(Some([6, 7, 8, 9, 10]), conflict.current_head);
+            }
+            _ => panic!("expected a conflict"),
+        }
+        assert_eq!(labels[0], store.get_label("foo").await.unwrap().unwrap());
+        assert!(store
+            .set_labels_if(vec![
+                LabelUpdate::new("foo", None, None),
+                LabelUpdate::new("foo", None, None),
+            ])
+            .await
+            .is_err());
+
+        // nothing but the labels and their history is left behind
+        let mut entries = fs::read_dir(dir.path()).await.unwrap();
+        while let Some(entry) = entries.next_entry().await.unwrap() {
+            let name = entry.file_name().into_string().unwrap();
+            assert!(name.ends_with(".label") || name.ends_with(".history"));
+        }
+    }
+
+    #[tokio::test]
+    async fn interrupted_multi_label_update_is_all_or_nothing() {
+        let dir = tempdir().unwrap();
+        let store = DirectoryLabelStore::new(dir.path());
+        let foo = store.create_label("foo").await.unwrap();
+        let bar = store.create_label("bar").await.unwrap();
+        let foo_path = dir.path().join("foo.label");
+        let bar_path = dir.path().join("bar.label");
+        let new_foo = foo.with_updated_layer(Some([1, 2, 3, 4, 5]));
+        let new_bar = bar.with_updated_layer(Some([6, 7, 8, 9, 10]));
+        for (path, label) in [(&foo_path, &new_foo), (&bar_path, &new_bar)] {
+            let mut contents = b"abc.transaction\n".to_vec();
+            contents.extend(label_contents(label));
+            fs::write(label_journal_path(path), contents).await.unwrap();
+        }
+
+        // without the transaction file, the update never happened
+        assert_eq!(Some(foo), store.get_label("foo").await.unwrap());
+        assert_eq!(Some(bar), store.get_label("bar").await.unwrap());
+
+        // with it, every label is replayed on its next read
+        let transaction = dir.path().join("abc.transaction");
+        fs::write(&transaction, b"bar\nfoo\n").await.unwrap();
+        assert_eq!(Some(new_bar), store.get_label("bar").await.unwrap());
+        assert!(fs::metadata(label_journal_path(&bar_path)).await.is_err());
+        // foo still has to be replayed
+        assert!(fs::metadata(&transaction).await.is_ok());
+        assert_eq!(Some(new_foo), store.get_label("foo").await.unwrap());
+        assert!(fs::metadata(&transaction).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn incomplete_label_journal_is_discarded() {
+        let dir = tempdir().unwrap();
+        let store = DirectoryLabelStore::new(dir.path());
+        let label = store.create_label("foo").await.unwrap();
+
+        // simulate a crash while the journal was being written
+        let label_path = dir.path().join("foo.label");
+        fs::write(label_journal_path(&label_path), b"1\n0000")
+            .await
+            .unwrap();
+
+        assert_eq!(Some(label.clone()), store.get_label("foo").await.unwrap());
+        let new_label = store
+            .set_label(&label, [1, 2, 3, 4, 5])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some(new_label), store.get_label("foo").await.unwrap());
+        assert!(fs::metadata(label_journal_path(&label_path)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn read_only_label_store_reads_through_journal() {
+        let dir = tempdir().unwrap();
+        let store = DirectoryLabelStore::new(dir.path());
+        let label = store.create_label("foo").await.unwrap();
+
+        let label_path = dir.path().join("foo.label");
+        let new_label = label.with_updated_layer(Some([1, 2, 3, 4, 5]));
+        fs::write(label_journal_path(&label_path), label_contents(&new_label))
+            .await
+            .unwrap();
+
+        let read_only = DirectoryLabelStore::new_read_only(dir.path());
+        assert_eq!(Some(new_label), read_only.get_label("foo").await.unwrap());
+        // the journal is left alone
+        assert!(fs::metadata(label_journal_path(&label_path)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn list_directory_labels() {
+        let dir = tempdir().unwrap();
+        let store = DirectoryLabelStore::new(dir.path());
+        let foo = store.create_label("foo").await.unwrap();
+        let bar = store.create_label("bar").await.unwrap();
+        let bar = store
+            .set_label(&bar, [1, 2, 3, 4, 5])
+            .await
+            .unwrap()
+            .unwrap();
+
+        let mut labels = store.labels().await.unwrap();
+        labels.sort_by(|l1, l2| l1.name.cmp(&l2.name));
+        assert_eq!(vec![bar, foo], labels);
+    }
+
+    #[tokio::test]
+    async fn nonblocking_label_store_fails_on_locked_label() {
+        let dir = tempdir().unwrap();
+        let store = DirectoryLabelStore::new(dir.path()).with_lock_policy(LockPolicy::NonBlocking);
+        let label = store.create_label("foo").await.unwrap();
+
+        let label_path = dir.path().join("foo.label");
+        let f = ExclusiveLockedFile::open(label_path).await.unwrap();
+
+        let err = store.get_label("foo").await.unwrap_err();
+        assert_eq!(io::ErrorKind::WouldBlock, err.kind());
+        assert_eq!(Some(&LockError::WouldBlock), LockError::from_io_error(&err));
+
+        let err = store.set_label(&label, [1, 2, 3, 4, 5]).await.unwrap_err();
+        assert_eq!(io::ErrorKind::WouldBlock, err.kind());
+
+        std::mem::drop(f);
+        assert!(store
+            .set_label(&label, [1, 2, 3, 4, 5])
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn timeout_label_store_times_out_on_locked_label() {
+        let dir = tempdir().unwrap();
+        let timeout = std::time::Duration::from_millis(50);
+        let store =
+            DirectoryLabelStore::new(dir.path()).with_lock_policy(LockPolicy::Timeout(timeout));
+        let label = store.create_label("foo").await.unwrap();
+
+        let label_path = dir.path().join("foo.label");
+        let _f = LockedFile::open(label_path).await.unwrap();
+
+        // shared locks don't get in each other's way
+        assert_eq!(Some(label.clone()), store.get_label("foo").await.unwrap());
+
+        let err = store.set_label(&label, [1, 2, 3, 4, 5]).await.unwrap_err();
+        assert_eq!(io::ErrorKind::TimedOut, err.kind());
+        assert_eq!(
+            Some(&LockError::TimedOut(timeout)),
+            LockError::from_io_error(&err)
+        );
+    }
+
+    #[tokio::test]
+    async fn timeout_label_store_waits_for_lock() {
+        let dir = tempdir().unwrap();
+        let store = DirectoryLabelStore::new(dir.path())
+            .with_lock_policy(LockPolicy::Timeout(std::time::Duration::from_secs(10)));
+        let label = store.create_label("foo").await.unwrap();
+
+        let label_path = dir.path().join("foo.label");
+        let f = ExclusiveLockedFile::open(label_path).await.unwrap();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            std::mem::drop(f);
+        });
+
+        assert!(store
+            .set_label(&label, [1, 2, 3, 4, 5])
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    async fn label_history<S: LabelStore>(store: &S, name: &str) -> Vec<LabelHistoryEntry> {
+        use futures::StreamExt;
+        store
+            .label_history(name)
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<io::Result<_>>()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn directory_label_history() {
+        let dir = tempdir().unwrap();
+        let store = DirectoryLabelStore::new(dir.path());
+        let label = store.create_label("foo").await.unwrap();
+        assert!(label_history(&store, "foo").await.is_empty());
+
+        let label = store
+            .set_label(&label, [1, 2, 3, 4, 5])
+            .await
+            .unwrap()
+            .unwrap();
+        store.clear_label(&label).await.unwrap().unwrap();
+        let history = label_history(&store, "foo").await;
+        assert_eq!(2, history.len());
+        assert_eq!(
+            (1, None, Some([1, 2, 3, 4, 5])),
+            (
+                history[0].version,
+                history[0].previous_layer,
+                history[0].layer
+            )
+        );
+        assert_eq!(
+            (2, Some([1, 2, 3, 4, 5]), None),
+            (
+                history[1].version,
+                history[1].previous_layer,
+                history[1].layer
+            )
+        );
+        assert!(history[0].timestamp <= history[1].timestamp);
+
+        // a partially written line is ignored
+        let history_path = label_history_path(&dir.path().join("foo.label"));
+        let mut contents = fs::read(&history_path).await.unwrap();
+        contents.extend_from_slice(b"3 -");
+        fs::write(&history_path, contents).await.unwrap();
+        assert_eq!(history, label_history(&store, "foo").await);
+
+        store.rename_label("foo", "bar").await.unwrap();
+        assert_eq!(history, label_history(&store, "bar").await);
+
+        store.delete_label("bar").await.unwrap();
+        assert!(fs::metadata(&history_path).await.is_err());
+        assert_eq!(
+            io::ErrorKind::NotFound,
+            store.label_history("bar").await.err().unwrap().kind()
+        );
+        store.create_label("bar").await.unwrap();
+        assert!(label_history(&store, "bar").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn directory_label_prefixes() {
+        let dir = tempdir().unwrap();
+        let store = DirectoryLabelStore::new(dir.path());
+        store.create_label("foo").await.unwrap();
+        assert!(store.label_prefixes("foo").await.unwrap().is_empty());
+
+        let mut prefixes = PrefixMap::new();
+        prefixes.insert(
+            "rdf".to_string(),
+            "http://www.w3.org/1999/02/22-rdf-syntax-ns#".to_string(),
+        );
+        prefixes.insert("".to_string(), "http://example.com/".to_string());
+        store
+            .set_label_prefixes("foo", prefixes.clone())
+            .await
+            .unwrap();
+        assert_eq!(prefixes, store.label_prefixes("foo").await.unwrap());
+
+        let mut invalid = PrefixMap::new();
+        invalid.insert("ex".to_string(), "http://example.com/a b".to_string());
+        assert_eq!(
+            io::ErrorKind::InvalidInput,
+            store
+                .set_label_prefixes("foo", invalid)
+                .await
+                .err()
+                .unwrap()
+                .kind()
+        );
+        assert_eq!(prefixes, store.label_prefixes("foo").await.unwrap());
+
+        store.rename_label("foo", "bar").await.unwrap();
+        assert_eq!(prefixes, store.label_prefixes("bar").await.unwrap());
+        store.delete_label("bar").await.unwrap();
+        assert_eq!(
+            io::ErrorKind::NotFound,
+            store.label_prefixes("bar").await.err().unwrap().kind()
+        );
+        assert_eq!(
+            io::ErrorKind::NotFound,
+            store
+                .set_label_prefixes("bar", PrefixMap::new())
+                .await
+                .err()
+                .unwrap()
+                .kind()
+        );
+        store.create_label("bar").await.unwrap();
+        assert!(store.label_prefixes("bar").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn directory_watch_label() {
+        use futures::StreamExt;
+        use std::time::Duration;
+        use tokio::time::timeout;
+
+        let dir = tempdir().unwrap();
+        let store = DirectoryLabelStore::new(dir.path());
+        let foo = store.create_label("foo").await.unwrap();
+        let bar = store.create_label("bar").await.unwrap();
+
+        let mut watch = store.watch_label("foo").await.unwrap();
+        store.set_label(&bar, [1, 1, 1, 1, 1]).await.unwrap();
+
+        // a different store on the same directory, as if it were
+        // another process
+        let other_store = DirectoryLabelStore::new(dir.path());
+        let foo = other_store
+            .set_label(&foo, [6, 7, 8, 9, 10])
+            .await
+            .unwrap()
+            .unwrap();
+        let next = timeout(Duration::from_secs(10), watch.next())
+            .await
+            .unwrap();
+        assert_eq!(foo, next.unwrap().unwrap());
+
+        let foo = other_store.clear_label(&foo).await.unwrap().unwrap();
+        let next = timeout(Duration::from_secs(10), watch.next())
+            .await
+            .unwrap();
+        assert_eq!(foo, next.unwrap().unwrap());
+
+        other_store.delete_label("foo").await.unwrap();
+        let next = timeout(Duration::from_secs(10), watch.next())
+            .await
+            .unwrap();
+        assert!(next.is_none());
+    }
+
+    async fn create_layer_with_triple<S: LayerStore>(store: &S, triple: StringTriple) -> [u32; 5] {
+        let mut builder = store.create_base_layer().await.unwrap();
+        let name = builder.name();
+        builder.add_string_triple(triple);
+        builder.commit_boxed().await.unwrap();
+
+        name
+    }
+
+    #[tokio::test]
+    async fn flat_directory_layout() {
+        let dir = tempdir().unwrap();
+        let store = DirectoryLayerStore::new(dir.path()).with_layout(DirectoryLayout::Flat);
+        let name =
+            create_layer_with_triple(&store, StringTriple::new_value("cow", "says", "moo")).await;
+
+        assert!(dir.path().join(name_to_string(name)).is_dir());
+        assert_eq!(vec![name], store.layers().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn sharded_directory_layout() {
+        let dir = tempdir().unwrap();
+        let store = DirectoryLayerStore::new(dir.path()).with_layout(DirectoryLayout::Sharded(2));
+        let name =
+            create_layer_with_triple(&store, StringTriple::new_value("cow", "says", "moo")).await;
+
+        let name_str = name_to_string(name);
+        assert!(dir.path().join(&name_str[..2]).join(&name_str).is_dir());
+        assert_eq!(vec![name], store.layers().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn migrate_flat_to_sharded_and_back() {
+        let dir = tempdir().unwrap();
+        let flat_store = DirectoryLayerStore::new(dir.path()).with_layout(DirectoryLayout::Flat);
+        let label_store = DirectoryLabelStore::new(dir.path());
+        label_store.create_label("foo").await.unwrap();
+        let name1 =
+            create_layer_with_triple(&flat_store, StringTriple::new_value("cow", "says", "moo"))
+                .await;
+        let name2 =
+            create_layer_with_triple(&flat_store, StringTriple::new_value("pig", "says", "oink"))
+                .await;
+
+        let sharded_store = DirectoryLayerStore::new(dir.path());
+        assert!(sharded_store.layers().await.unwrap().is_empty());
+        assert_eq!(
+            2,
+            sharded_store
+                .migrate_layout(DirectoryLayout::Flat)
+                .await
+                .unwrap()
+        );
+        assert!(flat_store.layers().await.unwrap().is_empty());
+        let mut layers = sharded_store.layers().await.unwrap();
+        layers.sort();
+        let mut expected = vec![name1, name2];
+        expected.sort();
+        assert_eq!(expected, layers);
+        let layer = sharded_store.get_layer(name1).await.unwrap().unwrap();
+        assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+
+        assert_eq!(
+            2,
+            flat_store
+                .migrate_layout(DirectoryLayout::default())
+                .await
+                .unwrap()
+        );
+        assert!(sharded_store.layers().await.unwrap().is_empty());
+        let layer = flat_store.get_layer(name2).await.unwrap().unwrap();
+        assert!(layer.string_triple_exists(&StringTriple::new_value("pig", "says", "oink")));
+
+        // only layer directories and label files remain
+        let mut entries = std::fs::read_dir(dir.path()).unwrap().count();
+        entries -= 1; // the label file
+        assert_eq!(2, entries);
+    }
+
+    #[tokio::test]
+    async fn refuse_layers_exceeding_quota() {
+        let dir = tempdir().unwrap();
+        let store = DirectoryLayerStore::new(dir.path()).with_quota(1 << 20);
+
+        let mut builder = store.create_base_layer().await.unwrap();
+        let base_name = builder.name();
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        builder.commit_boxed().await.unwrap();
+
+        let mut builder = store.create_child_layer(base_name).await.unwrap();
+        let child_name = builder.name();
+        let big_value = "o".repeat(1 << 20);
+        builder.add_string_triple(StringTriple::new_value("cow", "says", &big_value));
+        let error = builder.commit_boxed().await.unwrap_err();
+        assert_eq!(io::ErrorKind::QuotaExceeded, error.kind());
+        match SpaceError::from_io_error(&error) {
+            Some(SpaceError::QuotaExceeded { quota, .. }) => assert_eq!(1 << 20, *quota),
+            e => panic!("expected a quota error, got {:?}", e),
+        }
+
+        // nothing but the parent file, written on creation, exists for the refused layer
+        let files: Vec<_> = std::fs::read_dir(store.layer_path(child_name))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(vec![OsString::from(FILENAMES.parent)], files);
+    }
+
+    #[tokio::test]
+    async fn create_layers_with_spilling_builders() {
+        let dir = tempdir().unwrap();
+        let scratch_dir = tempdir().unwrap();
+        let store = DirectoryLayerStore::new(dir.path())
+            .with_spill(SpillConfig::new(2).in_directory(scratch_dir.path()));
+
+        let mut builder = store.create_base_layer().await.unwrap();
+        let base_name = builder.name();
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        builder.add_string_triple(StringTriple::new_value("pig", "says", "oink"));
+        builder.add_string_triple(StringTriple::new_value("duck", "says", "quack"));
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        builder.add_string_triple(StringTriple::new_node("cow", "likes", "pig"));
+        assert_eq!(2, std::fs::read_dir(scratch_dir.path()).unwrap().count());
+        builder.commit_boxed().await.unwrap();
+
+        let mut builder = store.create_child_layer(base_name).await.unwrap();
+        let child_name = builder.name();
+        builder.remove_string_triple(StringTriple::new_value("duck", "says", "quack"));
+        builder.remove_string_triple(StringTriple::new_node("cow", "likes", "pig"));
+        builder.remove_string_triple(StringTriple::new_node("cow", "hates", "pig"));
+        builder.add_string_triple(StringTriple::new_node("cow", "hates", "pig"));
+        builder.commit_boxed().await.unwrap();
+        assert_eq!(0, std::fs::read_dir(scratch_dir.path()).unwrap().count());
+
+        let layer = store.get_layer(child_name).await.unwrap().unwrap();
+        let mut triples: Vec<_> = layer
+            .triples()
+            .map(|t| layer.id_triple_to_string(&t).unwrap())
+            .collect();
+        triples.sort();
+        assert_eq!(
+            vec![
+                StringTriple::new_value("cow", "says", "moo"),
+                StringTriple::new_value("pig", "says", "oink"),
+            ],
+            triples
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn create_layers_with_limited_parallelism() {
+        for level in [1, 3] {
+            let dir = tempdir().unwrap();
+            let store = DirectoryLayerStore::new(dir.path())
+                .with_parallelism(Parallelism::new(level).unwrap());
+
+            let mut builder = store.create_base_layer().await.unwrap();
+            let base_name = builder.name();
+            builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+            builder.add_string_triple(StringTriple::new_value("duck", "says", "quack"));
+            builder.add_string_triple(StringTriple::new_node("cow", "likes", "pig"));
+            builder.commit_boxed().await.unwrap();
+
+            let mut builder = store.create_child_layer(base_name).await.unwrap();
+            let child_name = builder.name();
+            builder.remove_string_triple(StringTriple::new_value("duck", "says", "quack"));
+            builder.add_string_triple(StringTriple::new_value("pig", "says", "oink"));
+            builder.add_string_triple(StringTriple::new_node("pig", "likes", "cow"));
+            builder.commit_boxed().await.unwrap();
+
+            let layer = store.get_layer(child_name).await.unwrap().unwrap();
+            let mut triples: Vec<_> = layer
+                .triples()
+                .map(|t| layer.id_triple_to_string(&t).unwrap())
+                .collect();
+            triples.sort();
+            assert_eq!(
+                vec![
+                    StringTriple::new_node("cow", "likes", "pig"),
+                    StringTriple::new_value("cow", "says", "moo"),
+                    StringTriple::new_node("pig", "likes", "cow"),
+                    StringTriple::new_value("pig", "says", "oink"),
+                ],
+                triples
+            );
+            let cow = layer.subject_id("cow").unwrap();
+            assert_eq!(1, layer.triples_o(cow).count());
+        }
+    }
+
+    #[tokio::test]
+    async fn resume_builders_from_checkpoint() {
+        let dir = tempdir().unwrap();
+        let scratch_dir = tempdir().unwrap();
+        let store = DirectoryLayerStore::new(dir.path())
+            .with_spill(SpillConfig::new(2).in_directory(scratch_dir.path()));
+
+        let mut builder = store.create_base_layer().await.unwrap();
+        let base_name = builder.name();
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        builder.add_string_triple(StringTriple::new_value("duck", "says", "quack"));
+        builder.add_string_triple(StringTriple::new_node("cow", "likes", "duck"));
+        builder.checkpoint().unwrap();
+        assert_eq!(0, std::fs::read_dir(scratch_dir.path()).unwrap().count());
+        // these get spilled, but are not part of the checkpoint
+        builder.add_string_triple(StringTriple::new_value("horse", "says", "neigh"));
+        builder.add_string_triple(StringTriple::new_value("sheep", "says", "baa"));
+        // simulate a crash
+        std::mem::forget(builder);
+
+        let mut builder = store
+            .resume_layer_builder(base_name)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(base_name, builder.name());
+        builder.add_string_triple(StringTriple::new_value("pig", "says", "oink"));
+        builder.commit_boxed().await.unwrap();
+        assert!(!store.layer_path(base_name).join("checkpoint").exists());
+        assert!(store
+            .resume_layer_builder(base_name)
+            .await
+            .unwrap()
+            .is_none());
+
+        let mut builder = store.create_child_layer(base_name).await.unwrap();
+        let child_name = builder.name();
+        builder.remove_string_triple(StringTriple::new_value("duck", "says", "quack"));
+        builder.checkpoint().unwrap();
+        std::mem::drop(builder);
+
+        let mut builder = store
+            .resume_layer_builder(child_name)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(base_name, builder.parent().unwrap().name());
+        builder.add_string_triple(StringTriple::new_node("pig", "likes", "cow"));
+        builder.commit_boxed().await.unwrap();
+
+        let layer = store.get_layer(child_name).await.unwrap().unwrap();
+        let mut triples: Vec<_> = layer
+            .triples()
+            .map(|t| layer.id_triple_to_string(&t).unwrap())
+            .collect();
+        triples.sort();
+        assert_eq!(
+            vec![
+                StringTriple::new_node("cow", "likes", "duck"),
+                StringTriple::new_value("cow", "says", "moo"),
+                StringTriple::new_node("pig", "likes", "cow"),
+                StringTriple::new_value("pig", "says", "oink"),
+            ],
+            triples
+        );
+    }
+
+    #[tokio::test]
+    async fn directory_store_reports_metrics() {
+        use std::sync::atomic::Ordering;
+        let dir = tempdir().unwrap();
+        let metrics = Arc::new(CountingMetrics::new());
+        let store = DirectoryLayerStore::new(dir.path()).with_metrics(metrics.clone());
+
+        let name =
+            create_layer_with_triple(&store, StringTriple::new_value("cow", "says", "moo")).await;
+        assert!(metrics.files_opened_for_write.load(Ordering::Relaxed) > 0);
+        let written = metrics.bytes_written.load(Ordering::Relaxed);
+        assert!(written > 0);
+
+        let file = store
+            .get_file(name, FILENAMES.node_dictionary_blocks)
+            .await
+            .unwrap();
+        let read_before = metrics.bytes_read.load(Ordering::Relaxed);
+        let opened_before = metrics.files_opened_for_read.load(Ordering::Relaxed);
+        let size = file.size().await.unwrap();
+        file.map().await.unwrap();
+        assert_eq!(
+            read_before + size as u64,
+            metrics.bytes_read.load(Ordering::Relaxed)
+        );
+        assert_eq!(
+            opened_before + 1,
+            metrics.files_opened_for_read.load(Ordering::Relaxed)
+        );
+    }
+
+    #[tokio::test]
+    async fn content_addressed_layers_are_deduplicated() {
+        let dir = tempdir().unwrap();
+        let store = DirectoryLayerStore::new(dir.path()).with_content_addressing();
+
+        let name1 =
+            create_layer_with_triple(&store, StringTriple::new_value("cow", "says", "moo")).await;
+        let name2 =
+            create_layer_with_triple(&store, StringTriple::new_value("cow", "says", "moo")).await;
+        assert_ne!(name1, name2);
+        assert_eq!(2, store.layers().await.unwrap().len());
+
+        let final1 = store.finalize_layer(name1).await.unwrap();
+        let final2 = store.finalize_layer(name2).await.unwrap();
+        assert_eq!(final1, final2);
+        assert_eq!(vec![final1], store.layers().await.unwrap());
+        assert_eq!(
+            Some(final1),
+            store.layer_content_name(final1).await.unwrap()
+        );
+
+        let layer = store.get_layer(final1).await.unwrap().unwrap();
+        assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+    }
+
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    #[tokio::test]
+    async fn directory_store_with_io_uring() {
+        let dir = tempdir().unwrap();
+        let store = DirectoryLayerStore::new(dir.path()).with_io_uring();
+        let name =
+            create_layer_with_triple(&store, StringTriple::new_value("cow", "says", "moo")).await;
+
+        let layer = store.get_layer(name).await.unwrap().unwrap();
+        assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+
+        let file = store
+            .get_file(name, FILENAMES.node_dictionary_blocks)
+            .await
+            .unwrap();
+        let contents = file.map().await.unwrap();
+        assert_eq!(&contents[1..3], &file.map_range(1, 2).await.unwrap()[..]);
+    }
+
+    #[tokio::test]
+    async fn directory_store_without_durability() {
+        let dir = tempdir().unwrap();
+        let store = DirectoryLayerStore::new(dir.path()).with_durability(Durability::None);
+        let name =
+            create_layer_with_triple(&store, StringTriple::new_value("cow", "says", "moo")).await;
+        assert_eq!(name, store.finalize_layer(name).await.unwrap());
+
+        let layer = store.get_layer(name).await.unwrap().unwrap();
+        assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+    }
+
+    #[tokio::test]
+    async fn finalize_syncs_sharded_layer_directory() {
+        let dir = tempdir().unwrap();
+        for durability in [Durability::Full, Durability::Directory].iter() {
+            let store = DirectoryLayerStore::new(dir.path()).with_durability(*durability);
+            let name =
+                create_layer_with_triple(&store, StringTriple::new_value("cow", "says", "moo"))
+                    .await;
+            assert_eq!(name, store.finalize_layer(name).await.unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn clone_layers_between_directory_stores() {
+        let dir = tempdir().unwrap();
+        let from = DirectoryLayerStore::new(dir.path().join("from"));
+        let to = DirectoryLayerStore::new(dir.path().join("to"));
+        let base =
+            create_layer_with_triple(&from, StringTriple::new_value("cow", "says", "moo")).await;
+        let mut builder = from.create_child_layer(base).await.unwrap();
+        let child = builder.name();
+        builder.add_string_triple(StringTriple::new_value("pig", "says", "oink"));
+        builder.commit_boxed().await.unwrap();
+        let layer = from.get_layer(child).await.unwrap().unwrap();
+        let rollup = Arc::new(from.clone()).rollup(layer).await.unwrap();
+
+        let cloned = clone_all_layers(&from, &to).await.unwrap();
+        // both stores live on the same filesystem
+        assert!(cloned.linked > 0);
+        assert_eq!(1, cloned.copied);
+        assert!(to.layer_has_rollup(child).await.unwrap());
+        assert_eq!(rollup, to.read_rollup_file(child).await.unwrap());
+
+        let layer = to.get_layer(child).await.unwrap().unwrap();
+        assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        assert!(layer.string_triple_exists(&StringTriple::new_value("pig", "says", "oink")));
+
+        // cloning again doesn't do anything
+        assert_eq!(
+            ClonedFiles::default(),
+            clone_all_layers(&from, &to).await.unwrap()
+        );
+    }
 }
@@ -3,7 +3,7 @@
 use std::io;
 
 use bytes::Bytes;
-use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, Take};
 
 use async_trait::async_trait;
 
@@ -41,6 +41,40 @@ pub trait FileLoad: Clone + Send + Sync {
             }
         }
     }
+
+    /// Map `len` bytes of this file, starting at `offset`.
+    ///
+    /// It is an error for the range to extend beyond the end of the file.
+    async fn map_range(&self, offset: usize, len: usize) -> io::Result<Bytes> {
+        check_range(offset, len, self.size().await?)?;
+        let mapped = self.map().await?;
+
+        Ok(mapped.slice(offset..offset + len))
+    }
+
+    /// Open this file for reading `len` bytes, starting at `offset`.
+    ///
+    /// It is an error for the range to extend beyond the end of the file.
+    async fn read_range(&self, offset: usize, len: usize) -> io::Result<Take<Self::Read>> {
+        check_range(offset, len, self.size().await?)?;
+        let read = self.open_read_from(offset).await?;
+
+        Ok(read.take(len as u64))
+    }
+}
+
+/// Check that the range of `len` bytes starting at `offset` fits in a file of the given size.
+pub(crate) fn check_range(offset: usize, len: usize, size: usize) -> io::Result<()> {
+    match offset.checked_add(len) {
+        Some(end) if end <= size => Ok(()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!(
+                "range of {} bytes at offset {} extends beyond the end of a file of {} bytes",
+                len, offset, size
+            ),
+        )),
+    }
 }
 
 /// The files required for storing a layer
@@ -104,6 +138,8 @@ pub struct BaseLayerFiles<F: 'static + FileLoad + FileStore> {
     pub o_ps_adjacency_list_files: AdjacencyListFiles<F>,
 
     pub predicate_wavelet_tree_files: BitIndexFiles<F>,
+
+    pub predicate_stats_file: F,
 }
 
 #[derive(Clone)]
@@ -123,6 +159,8 @@ pub struct BaseLayerMaps {
     pub o_ps_adjacency_list_maps: AdjacencyListMaps,
 
     pub predicate_wavelet_tree_maps: BitIndexMaps,
+
+    pub predicate_stats_map: Option<Bytes>,
 }
 
 impl<F: FileLoad + FileStore> BaseLayerFiles<F> {
@@ -142,6 +180,8 @@ impl<F: FileLoad + FileStore> BaseLayerFiles<F> {
 
         let predicate_wavelet_tree_maps = self.predicate_wavelet_tree_files.map_all().await?;
 
+        let predicate_stats_map = self.predicate_stats_file.map_if_exists().await?;
+
         Ok(BaseLayerMaps {
             node_dictionary_maps,
             predicate_dictionary_maps,
@@ -157,6 +197,8 @@ impl<F: FileLoad + FileStore> BaseLayerFiles<F> {
             o_ps_adjacency_list_maps,
 
             predicate_wavelet_tree_maps,
+
+            predicate_stats_map,
         })
     }
 }
@@ -183,6 +225,8 @@ pub struct ChildLayerFiles<F: 'static + FileLoad + FileStore + Clone + Send + Sy
 
     pub pos_predicate_wavelet_tree_files: BitIndexFiles<F>,
     pub neg_predicate_wavelet_tree_files: BitIndexFiles<F>,
+
+    pub predicate_stats_file: F,
 }
 
 #[derive(Clone)]
@@ -207,6 +251,8 @@ pub struct ChildLayerMaps {
 
     pub pos_predicate_wavelet_tree_maps: BitIndexMaps,
     pub neg_predicate_wavelet_tree_maps: BitIndexMaps,
+
+    pub predicate_stats_map: Option<Bytes>,
 }
 
 impl<F: FileLoad + FileStore + Clone> ChildLayerFiles<F> {
@@ -235,6 +281,8 @@ impl<F: FileLoad + FileStore + Clone> ChildLayerFiles<F> {
         let neg_predicate_wavelet_tree_maps =
             self.neg_predicate_wavelet_tree_files.map_all().await?;
 
+        let predicate_stats_map = self.predicate_stats_file.map_if_exists().await?;
+
         Ok(ChildLayerMaps {
             node_dictionary_maps,
             predicate_dictionary_maps,
@@ -256,6 +304,8 @@ impl<F: FileLoad + FileStore + Clone> ChildLayerFiles<F> {
 
             pos_predicate_wavelet_tree_maps,
             neg_predicate_wavelet_tree_maps,
+
+            predicate_stats_map,
         })
     }
 }
@@ -285,6 +335,16 @@ impl<F: 'static + FileLoad + FileStore> DictionaryFiles<F> {
     }
 }
 
+/// The files storing the named graph quads of a layer.
+///
+/// These files are optional. They only exist for layers that add or remove quads.
+#[derive(Clone)]
+pub struct QuadFiles<F: 'static + FileLoad + FileStore> {
+    pub graph_dictionary_files: DictionaryFiles<F>,
+    pub additions_file: F,
+    pub removals_file: F,
+}
+
 #[derive(Clone)]
 pub struct IdMapMaps {
     pub node_value_idmap_maps: Option<BitIndexMaps>,
@@ -1,6 +1,11 @@
+use std::collections::BTreeMap;
 use std::io;
+use std::pin::Pin;
+use std::time::SystemTime;
 
 use async_trait::async_trait;
+use futures::Stream;
+use thiserror::Error;
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Label {
@@ -34,6 +39,96 @@ impl Label {
     }
 }
 
+/// The label did not point at the layer it was expected to point at.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("label does not point at the expected layer")]
+pub struct LabelConflict {
+    /// The layer the label actually points at.
+    pub current_head: Option<[u32; 5]>,
+}
+
+/// An error returned by a conditional label update.
+#[derive(Error, Debug)]
+pub enum SetLabelError {
+    #[error(transparent)]
+    Conflict(#[from] LabelConflict),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// A conditional update of a single label, as part of `LabelStore::set_labels_if`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LabelUpdate {
+    /// The name of the label to update.
+    pub name: String,
+    /// The layer the label is expected to point at.
+    pub expected_layer: Option<[u32; 5]>,
+    /// The layer the label should point at afterwards.
+    pub new_layer: Option<[u32; 5]>,
+}
+
+impl LabelUpdate {
+    pub fn new(name: &str, expected_layer: Option<[u32; 5]>, new_layer: Option<[u32; 5]>) -> Self {
+        Self {
+            name: name.to_owned(),
+            expected_layer,
+            new_layer,
+        }
+    }
+}
+
+/// An error returned by a conditional update of several labels.
+#[derive(Error, Debug)]
+pub enum SetLabelsError {
+    /// One of the labels did not point at the layer it was expected to point at.
+    #[error("label {label} does not point at the expected layer")]
+    Conflict {
+        label: String,
+        conflict: LabelConflict,
+    },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// A stream of label updates, as returned by `LabelStore::watch_label`.
+pub type LabelWatchStream = Pin<Box<dyn Stream<Item = io::Result<Label>> + Send>>;
+
+/// A change of the layer a label points at, as recorded in the label history.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LabelHistoryEntry {
+    /// The label version after the change.
+    pub version: u64,
+    /// The layer the label pointed at before the change.
+    pub previous_layer: Option<[u32; 5]>,
+    /// The layer the label pointed at after the change.
+    pub layer: Option<[u32; 5]>,
+    /// When the change was made.
+    pub timestamp: SystemTime,
+}
+
+impl LabelHistoryEntry {
+    /// The history entry for updating `label` to `new_label`, made right now.
+    pub fn new(label: &Label, new_label: &Label) -> Self {
+        Self {
+            version: new_label.version,
+            previous_layer: label.layer,
+            layer: new_label.layer,
+            timestamp: crate::runtime::now(),
+        }
+    }
+}
+
+/// A stream of label history entries, oldest first, as returned by `LabelStore::label_history`.
+pub type LabelHistoryStream = Pin<Box<dyn Stream<Item = io::Result<LabelHistoryEntry>> + Send>>;
+
+/// Namespace prefixes of a label, mapping each prefix to the IRI it abbreviates.
+///
+/// For example, `rdf` could map to
+/// `http://www.w3.org/1999/02/22-rdf-syntax-ns#`. Prefixes are not
+/// used by the store itself, but are kept for tools that serialize
+/// the contents of a database.
+pub type PrefixMap = BTreeMap<String, String>;
+
 #[async_trait]
 pub trait LabelStore: Send + Sync {
     async fn labels(&self) -> io::Result<Vec<Label>>;
@@ -53,4 +148,182 @@ pub trait LabelStore: Send + Sync {
     async fn clear_label(&self, label: &Label) -> io::Result<Option<Label>> {
         self.set_label_option(label, None).await
     }
+
+    /// Point the label with the given name at `new_layer`, provided it currently points at `expected_layer`.
+    ///
+    /// Unlike `set_label_option`, this does not require the caller
+    /// to hold on to the exact label version. If the label points
+    /// somewhere else, a `LabelConflict` is returned containing the
+    /// current head, so that callers can retry without having to
+    /// re-read the label.
+    async fn set_label_if(
+        &self,
+        name: &str,
+        expected_layer: Option<[u32; 5]>,
+        new_layer: Option<[u32; 5]>,
+    ) -> Result<Label, SetLabelError> {
+        loop {
+            let label = self
+                .get_label(name)
+                .await?
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "label not found"))?;
+            if label.layer != expected_layer {
+                return Err(LabelConflict {
+                    current_head: label.layer,
+                }
+                .into());
+            }
+
+            if let Some(new_label) = self.set_label_option(&label, new_layer).await? {
+                return Ok(new_label);
+            }
+
+            // the label version changed between reading and
+            // writing. It may still point at the expected layer
+            // though, so check again.
+        }
+    }
+
+    /// Retrieve several labels at once, as they were at a single moment.
+    ///
+    /// No update of any of these labels, including one made with
+    /// `set_labels_if`, is seen halfway through. Returns the labels in
+    /// the order of the given names, with None for labels that do not
+    /// exist.
+    async fn get_labels(&self, _names: &[&str]) -> io::Result<Vec<Option<Label>>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this label store does not support reading several labels at once",
+        ))
+    }
+
+    /// Apply several conditional label updates atomically.
+    ///
+    /// Either all labels are updated, or none are. If any label does
+    /// not point at its expected layer, nothing is updated and a
+    /// `SetLabelsError::Conflict` is returned for that label. Each
+    /// label may only appear once. Returns the updated labels in the
+    /// order of the given updates.
+    async fn set_labels_if(
+        &self,
+        _updates: Vec<LabelUpdate>,
+    ) -> Result<Vec<Label>, SetLabelsError> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this label store does not support updating several labels at once",
+        )
+        .into())
+    }
+
+    /// Rename a label, keeping the layer it points at and its version.
+    ///
+    /// Fails with a `NotFound` error if the label does not exist, and
+    /// with an `InvalidInput` error if a label with the new name
+    /// already exists.
+    async fn rename_label(&self, _name: &str, _new_name: &str) -> io::Result<Label> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this label store does not support renaming labels",
+        ))
+    }
+
+    /// Retrieve the history of the label with the given name.
+    ///
+    /// Every successful update of a label is recorded, oldest first.
+    /// The history moves along when a label is renamed, and is
+    /// removed when the label is deleted. Fails with a `NotFound`
+    /// error if the label does not exist.
+    async fn label_history(&self, _name: &str) -> io::Result<LabelHistoryStream> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this label store does not support label history",
+        ))
+    }
+
+    /// Retrieve the namespace prefixes of the label with the given name.
+    ///
+    /// A label without prefixes has an empty prefix map. Prefixes
+    /// move along when a label is renamed, and are removed when the
+    /// label is deleted. Fails with a `NotFound` error if the label
+    /// does not exist.
+    async fn label_prefixes(&self, _name: &str) -> io::Result<PrefixMap> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this label store does not support prefixes",
+        ))
+    }
+
+    /// Replace the namespace prefixes of the label with the given name.
+    ///
+    /// This does not change the label version. Fails with a
+    /// `NotFound` error if the label does not exist.
+    async fn set_label_prefixes(&self, _name: &str, _prefixes: PrefixMap) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this label store does not support prefixes",
+        ))
+    }
+
+    /// Watch the label with the given name for changes.
+    ///
+    /// The returned stream yields the label every time it is
+    /// updated after this call was made, including updates made by
+    /// other processes if the store supports this. Updates in quick
+    /// succession may be coalesced, in which case only the latest
+    /// version is yielded. The stream ends when the label is deleted.
+    async fn watch_label(&self, _name: &str) -> io::Result<LabelWatchStream> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this label store does not support watching labels",
+        ))
+    }
+}
+
+/// Fail with an `InvalidInput` error if several of the given updates are for the same label.
+pub(crate) fn check_unique_label_updates(updates: &[LabelUpdate]) -> io::Result<()> {
+    let mut names: Vec<_> = updates.iter().map(|u| &u.name).collect();
+    names.sort();
+    if names.windows(2).any(|w| w[0] == w[1]) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the same label was updated more than once",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Copy all labels in `from` to `to`, overwriting labels that already exist in `to`.
+///
+/// Label versions are not copied. Labels in `to` will get a new
+/// version if they are updated.
+pub async fn copy_all_labels<S: LabelStore + ?Sized, T: LabelStore + ?Sized>(
+    from: &S,
+    to: &T,
+) -> io::Result<()> {
+    copy_labels(from.labels().await?, to).await
+}
+
+/// Make the given labels point at the same layers in `to`, creating them if needed.
+pub async fn copy_labels<T: LabelStore + ?Sized>(labels: Vec<Label>, to: &T) -> io::Result<()> {
+    for label in labels {
+        let mut target = match to.get_label(&label.name).await? {
+            Some(target) => target,
+            None => to.create_label(&label.name).await?,
+        };
+
+        while target.layer != label.layer {
+            match to.set_label_option(&target, label.layer).await? {
+                Some(_) => break,
+                None => {
+                    // concurrently updated, try again
+                    target = to.get_label(&label.name).await?.ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::NotFound, "label was deleted while copying")
+                    })?;
+                }
+            }
+        }
+    }
+
+    Ok(())
 }
@@ -1,14 +1,18 @@
 use super::cache::*;
-use super::consts::FILENAMES;
+use super::consts::{all_layer_files, FILENAMES};
 use super::delta::*;
 use super::file::*;
 use super::pack::Packable;
+use super::scratch::SpillConfig;
+use super::space::SpaceCheck;
 use crate::layer::{
-    layer_triple_exists, BaseLayer, ChildLayer, IdMap, IdTriple, InternalLayer,
+    bulk_build_base_layer, has_checkpoint, hash_string_triple, layer_triple_exists, read_metadata,
+    read_text_index, BaseLayer, BulkLoadConfig, ChildLayer, IdMap, IdTriple, InternalLayer,
     InternalLayerTripleObjectIterator, InternalLayerTriplePredicateIterator,
-    InternalLayerTripleSubjectIterator, InternalTripleStackIterator, LayerBuilder,
-    OptInternalLayerTriplePredicateIterator, OptInternalLayerTripleSubjectIterator, RollupLayer,
-    SimpleLayerBuilder,
+    InternalLayerTripleSubjectIterator, InternalTripleStackIterator, Layer, LayerBuilder,
+    LayerMetadata, LayerQuads, OptInternalLayerTriplePredicateIterator,
+    OptInternalLayerTripleSubjectIterator, Parallelism, QuadStack, RemovalValidation, RollupLayer,
+    SimpleLayerBuilder, StringTriple, TextIndex,
 };
 use crate::structure::bitarray::bitarray_len_from_file;
 use crate::structure::logarray::logarray_file_get_length_and_width;
@@ -17,11 +21,15 @@ use crate::structure::{
     WaveletTree,
 };
 
+use std::collections::HashSet;
 use std::convert::TryInto;
 use std::io;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use futures::future::{self, Future};
+use futures::stream::Stream;
+use sha2::{Digest, Sha256};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 use std::pin::Pin;
@@ -136,6 +144,40 @@ pub trait LayerStore: 'static + Packable + Send + Sync {
         self.create_child_layer_with_cache(parent, NOCACHE.clone())
     }
 
+    /// Reopen the builder of an uncommitted layer from its last checkpoint.
+    ///
+    /// This returns None if the layer has no checkpoint, either
+    /// because it was never checkpointed or because it has already
+    /// been committed.
+    fn resume_layer_builder_with_cache(
+        &self,
+        _name: [u32; 5],
+        _cache: Arc<dyn LayerCache>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<Box<dyn LayerBuilder>>>> + Send>> {
+        Box::pin(future::ok(None))
+    }
+    fn resume_layer_builder(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<Box<dyn LayerBuilder>>>> + Send>> {
+        self.resume_layer_builder_with_cache(name, NOCACHE.clone())
+    }
+
+    /// Build a base layer from a stream of triples that may be larger than memory.
+    ///
+    /// See `bulk_build_base_layer` for details. The new layer still
+    /// needs to be passed to `finalize_layer`.
+    fn bulk_build_base_layer(
+        &self,
+        _triples: Pin<Box<dyn Stream<Item = io::Result<StringTriple>> + Send>>,
+        _config: BulkLoadConfig,
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        Box::pin(future::err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this layer store does not support bulk loading",
+        )))
+    }
+
     fn perform_rollup(
         &self,
         layer: Arc<InternalLayer>,
@@ -262,6 +304,51 @@ pub trait LayerStore: 'static + Packable + Send + Sync {
         ancestor: [u32; 5],
     ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>>;
 
+    /// Drop the history before the given layer.
+    ///
+    /// The parent of the given layer is rolled up together with all
+    /// its ancestors into a base layer. Every layer that had that
+    /// parent, including the given layer, gets the base layer as its
+    /// parent instead. A rollup keeps all ids the same, so these
+    /// layers stay valid. The old ancestors are not deleted, but the
+    /// given layer and its descendants no longer need them.
+    ///
+    /// Layers that are already loaded keep using their old
+    /// ancestors. In stores that use content addressing, the layers
+    /// that get a new parent no longer have their content name.
+    ///
+    /// Returns the name of the new parent, or None if the given layer
+    /// has no parent.
+    fn truncate_history(
+        &self,
+        _layer: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<[u32; 5]>>> + Send>> {
+        Box::pin(future::err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this layer store can not truncate history",
+        )))
+    }
+
+    /// Remove all layers that are not needed by the given layers.
+    ///
+    /// A layer is needed if it is one of the given layers, an
+    /// ancestor of a needed layer, or a rollup of a needed
+    /// layer. Layers that have not been committed yet are left
+    /// alone, but layers that are committed during garbage collection
+    /// may be removed, as may layers that are only referenced by
+    /// layer handles rather than by labels.
+    ///
+    /// Returns the names of the removed layers.
+    fn collect_garbage(
+        &self,
+        _keep: Vec<[u32; 5]>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Vec<[u32; 5]>>> + Send>> {
+        Box::pin(future::err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this layer store can not collect garbage",
+        )))
+    }
+
     fn triple_addition_exists(
         &self,
         layer: [u32; 5],
@@ -359,6 +446,24 @@ pub trait LayerStore: 'static + Packable + Send + Sync {
         upto: [u32; 5],
     ) -> Pin<Box<dyn Future<Output = io::Result<Vec<[u32; 5]>>> + Send>>;
 
+    /// The total size in bytes of the files that make up the given layer.
+    ///
+    /// Rollup layers that were registered for this layer are not included.
+    fn layer_file_size(
+        &self,
+        _name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<u64>> + Send>> {
+        Box::pin(future::err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this layer store can not report layer file sizes",
+        )))
+    }
+
+    /// The amount of layers this store currently holds in memory for reuse, or None if it does not cache layers.
+    fn cached_layer_count(&self) -> Option<usize> {
+        None
+    }
+
     fn layer_changes<'a>(
         &'a self,
         name: [u32; 5],
@@ -399,6 +504,126 @@ pub trait LayerStore: 'static + Packable + Send + Sync {
             ))
         })
     }
+
+    /// Calculate the name of the given layer as derived from its content.
+    ///
+    /// This is a hash over the name of the parent layer, and the
+    /// triples and quads added and removed by this layer. Two layers with the
+    /// same parent and the same changes will always have the same
+    /// content name, no matter what store they were built in. For
+    /// stores that use content addressing, a layer is valid if its
+    /// content name equals its name.
+    ///
+    /// Returns None if the layer does not exist.
+    fn layer_content_name(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<[u32; 5]>>> + Send>> {
+        let layer = self.get_layer(name);
+        let quads = self.layer_quads(name);
+        Box::pin(async move {
+            let layer = match layer.await? {
+                None => return Ok(None),
+                Some(layer) => layer,
+            };
+            let quads = quads.await?;
+
+            Ok(Some(content_name(&layer, quads.layer(name))))
+        })
+    }
+
+    /// Load the descriptive metadata of the given layer, if it has any.
+    fn layer_metadata(
+        &self,
+        _name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<LayerMetadata>>> + Send>> {
+        Box::pin(future::ok(None))
+    }
+
+    /// Load the full-text index of the given layer, if it has one.
+    ///
+    /// This only covers the values added by the layer itself. See
+    /// `TextIndex` for details.
+    fn layer_text_index(
+        &self,
+        _name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<TextIndex>>> + Send>> {
+        Box::pin(future::ok(None))
+    }
+
+    /// Load the named graph quads of the given layer and all its ancestors.
+    ///
+    /// Stores that do not support quads return an empty stack.
+    fn layer_quads(
+        &self,
+        _name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<QuadStack>> + Send>> {
+        Box::pin(future::ok(QuadStack::default()))
+    }
+
+    /// Give a freshly committed layer its final name, and make sure it is durably stored.
+    ///
+    /// For most stores this just returns the name the layer was
+    /// built under. Stores that use content addressing will rename
+    /// the layer to its content name instead. If a layer with that
+    /// name already exists, the new layer is dropped in favor of the
+    /// existing one.
+    fn finalize_layer(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        Box::pin(future::ok(name))
+    }
+}
+
+/// Hash the parent name and the changes of a layer into a layer name.
+fn content_name(layer: &InternalLayer, quads: Option<&LayerQuads>) -> [u32; 5] {
+    let mut hasher = Sha256::new();
+    match layer.parent_name() {
+        Some(parent) => {
+            hasher.update([1]);
+            for part in parent.iter() {
+                hasher.update(part.to_be_bytes());
+            }
+        }
+        None => hasher.update([0]),
+    }
+
+    for triple in layer.internal_triple_additions() {
+        let triple = layer
+            .id_triple_to_string(&triple)
+            .expect("layer triple should resolve to strings");
+        hash_string_triple(&mut hasher, b'+', triple);
+    }
+    for triple in layer.internal_triple_removals() {
+        let triple = layer
+            .id_triple_to_string(&triple)
+            .expect("layer triple should resolve to strings");
+        hash_string_triple(&mut hasher, b'-', triple);
+    }
+
+    if let Some(quads) = quads {
+        let quad_changes = quads
+            .additions()
+            .map(|quad| (b'>', quad))
+            .chain(quads.removals().map(|quad| (b'<', quad)));
+        for (tag, (graph, triple)) in quad_changes {
+            let triple = layer
+                .id_triple_to_string(&triple)
+                .expect("layer quad should resolve to strings");
+            hasher.update((graph.len() as u64).to_be_bytes());
+            hasher.update(graph.as_bytes());
+            hash_string_triple(&mut hasher, tag, triple);
+        }
+    }
+
+    let hash = hasher.finalize();
+    let mut name = [0; 5];
+    for (part, bytes) in name.iter_mut().zip(hash.chunks(4)) {
+        *part = u32::from_be_bytes(bytes.try_into().unwrap());
+    }
+
+    name
 }
 
 pub trait PersistentLayerStore: 'static + Send + Sync + Clone {
@@ -428,6 +653,84 @@ pub trait PersistentLayerStore: 'static + Send + Sync + Clone {
         file: &str,
     ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>>;
 
+    /// The configuration for spilling triples to disk in builders created by this store, if any.
+    fn spill_config(&self) -> Option<SpillConfig> {
+        None
+    }
+
+    /// The check for available space in builders created by this store, if any.
+    fn space_check(&self) -> Option<Arc<dyn SpaceCheck>> {
+        None
+    }
+
+    /// The parallelism used to build layers in builders created by this store, if any.
+    fn parallelism(&self) -> Option<Parallelism> {
+        None
+    }
+
+    /// What child layer builders created by this store do with removals of triples that don't exist.
+    fn removal_validation(&self) -> RemovalValidation {
+        RemovalValidation::default()
+    }
+
+    /// Whether builders created by this store build a full-text index over their string values.
+    fn text_index(&self) -> bool {
+        false
+    }
+
+    /// The directory to checkpoint the builder of the given layer in, if any.
+    ///
+    /// See `SimpleLayerBuilder::with_checkpoint_directory`.
+    fn checkpoint_directory(&self, _name: [u32; 5]) -> Option<PathBuf> {
+        None
+    }
+
+    /// Whether committed layers should be renamed to their content name.
+    ///
+    /// See `LayerStore::layer_content_name`.
+    fn content_addressed(&self) -> bool {
+        false
+    }
+
+    /// Make sure the given layer directory is durably stored.
+    ///
+    /// This is called when a layer is finalized.
+    fn sync_directory(
+        &self,
+        _name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        Box::pin(future::ok(()))
+    }
+
+    /// Remove a layer directory and all files in it.
+    ///
+    /// This does not check whether the layer is still in use.
+    fn remove_directory(
+        &self,
+        _name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        Box::pin(future::err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this store does not support removing layers",
+        )))
+    }
+
+    /// Rename a layer directory.
+    ///
+    /// As layer directories with the same content name are
+    /// interchangeable, the directory `from` is just removed if
+    /// `to` already exists.
+    fn rename_directory(
+        &self,
+        _from: [u32; 5],
+        _to: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        Box::pin(future::err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this store does not support renaming layers",
+        )))
+    }
+
     fn layer_has_rollup(
         &self,
         name: [u32; 5],
@@ -480,6 +783,7 @@ pub trait PersistentLayerStore: 'static + Send + Sync + Clone {
                 FILENAMES.base_predicate_wavelet_tree_bits,
                 FILENAMES.base_predicate_wavelet_tree_bit_index_blocks,
                 FILENAMES.base_predicate_wavelet_tree_bit_index_sblocks,
+                FILENAMES.predicate_stats,
             ];
 
             let mut files = Vec::with_capacity(filenames.len());
@@ -547,6 +851,8 @@ pub trait PersistentLayerStore: 'static + Send + Sync + Clone {
                     blocks_file: files[27].clone(),
                     sblocks_file: files[28].clone(),
                 },
+
+                predicate_stats_file: files[29].clone(),
             })
         })
     }
@@ -605,6 +911,7 @@ pub trait PersistentLayerStore: 'static + Send + Sync + Clone {
                 FILENAMES.neg_predicate_wavelet_tree_bits,
                 FILENAMES.neg_predicate_wavelet_tree_bit_index_blocks,
                 FILENAMES.neg_predicate_wavelet_tree_bit_index_sblocks,
+                FILENAMES.predicate_stats,
             ];
 
             let mut files = Vec::with_capacity(filenames.len());
@@ -702,6 +1009,29 @@ pub trait PersistentLayerStore: 'static + Send + Sync + Clone {
                     blocks_file: files[44].clone(),
                     sblocks_file: files[45].clone(),
                 },
+
+                predicate_stats_file: files[46].clone(),
+            })
+        })
+    }
+
+    fn quad_files(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<QuadFiles<Self::File>>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            Ok(QuadFiles {
+                graph_dictionary_files: DictionaryFiles {
+                    blocks_file: self_
+                        .get_file(name, FILENAMES.graph_dictionary_blocks)
+                        .await?,
+                    offsets_file: self_
+                        .get_file(name, FILENAMES.graph_dictionary_offsets)
+                        .await?,
+                },
+                additions_file: self_.get_file(name, FILENAMES.quad_additions).await?,
+                removals_file: self_.get_file(name, FILENAMES.quad_removals).await?,
             })
         })
     }
@@ -1587,6 +1917,69 @@ pub fn bytes_to_name(bytes: &[u8]) -> Result<[u32; 5], std::io::Error> {
     }
 }
 
+/// Copy all layers in `from` that do not yet exist in `to`.
+///
+/// Layers are copied file by file, so this works between any two
+/// kinds of persistent layer store.
+pub async fn copy_all_layers<S: PersistentLayerStore, T: PersistentLayerStore>(
+    from: &S,
+    to: &T,
+) -> io::Result<()> {
+    for layer in from.directories().await? {
+        if to.directory_exists(layer).await? {
+            // layers are immutable, so an existing layer is the same layer
+            continue;
+        }
+
+        to.create_named_directory(layer).await?;
+        copy_layer_files(from, layer, to, layer).await?;
+    }
+
+    Ok(())
+}
+
+/// Copy all files of layer `from_layer` in `from` into the existing directory `to_layer` in `to`.
+async fn copy_layer_files<S: PersistentLayerStore, T: PersistentLayerStore>(
+    from: &S,
+    from_layer: [u32; 5],
+    to: &T,
+    to_layer: [u32; 5],
+) -> io::Result<()> {
+    for file_name in all_layer_files() {
+        if !from.file_exists(from_layer, file_name).await? {
+            continue;
+        }
+
+        let contents = from.get_file(from_layer, file_name).await?.map().await?;
+        let mut writer = to.get_file(to_layer, file_name).await?.open_write().await?;
+        writer.write_all(&contents).await?;
+        writer.flush().await?;
+        writer.sync_all().await?;
+    }
+
+    Ok(())
+}
+
+/// Copy a single layer from `from` to `to`, in such a way that it either appears completely or not at all.
+///
+/// The layer is first copied under a temporary name, which is then
+/// renamed to the real name, so `to` has to support
+/// `PersistentLayerStore::rename_directory`.
+pub async fn copy_layer_atomically<S: PersistentLayerStore, T: PersistentLayerStore>(
+    from: &S,
+    to: &T,
+    layer: [u32; 5],
+) -> io::Result<()> {
+    let temp = to.create_directory().await?;
+    if let Err(e) = copy_layer_files(from, layer, to, temp).await {
+        // best effort cleanup, the original error is more interesting
+        let _ = to.remove_directory(temp).await;
+        return Err(e);
+    }
+
+    to.rename_directory(temp, layer).await
+}
+
 impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStore<File = F>>
     LayerStore for T
 {
@@ -1937,7 +2330,32 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
         Box::pin(async move {
             let dir_name = self_.create_directory().await?;
             let files = self_.base_layer_files(dir_name).await?;
-            Ok(Box::new(SimpleLayerBuilder::new(dir_name, files)) as Box<dyn LayerBuilder>)
+            let quad_files = self_.quad_files(dir_name).await?;
+            let metadata_file = self_.get_file(dir_name, FILENAMES.metadata).await?;
+            let builder = configure_builder(
+                &self_,
+                SimpleLayerBuilder::new(dir_name, files)
+                    .with_quad_files(quad_files)
+                    .with_metadata_file(metadata_file),
+            )?;
+            let builder = with_text_index_file(&self_, dir_name, builder).await?;
+
+            Ok(Box::new(builder) as Box<dyn LayerBuilder>)
+        })
+    }
+
+    fn bulk_build_base_layer(
+        &self,
+        triples: Pin<Box<dyn Stream<Item = io::Result<StringTriple>> + Send>>,
+        config: BulkLoadConfig,
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            let dir_name = self_.create_directory().await?;
+            let files = self_.base_layer_files(dir_name).await?;
+            bulk_build_base_layer(&files, triples, &config).await?;
+
+            Ok(dir_name)
         })
     }
 
@@ -1947,14 +2365,60 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
         cache: Arc<dyn LayerCache>,
     ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
         let create_files = self.create_child_layer_files_with_cache(parent, cache);
+        let self_ = self.clone();
         Box::pin(async move {
             let (layer_dir, parent_layer, child_layer_files) = create_files.await?;
+            let quad_files = self_.quad_files(layer_dir).await?;
+            let metadata_file = self_.get_file(layer_dir, FILENAMES.metadata).await?;
+            let builder = configure_builder(
+                &self_,
+                SimpleLayerBuilder::from_parent(layer_dir, parent_layer, child_layer_files)
+                    .with_quad_files(quad_files)
+                    .with_metadata_file(metadata_file),
+            )?;
+            let builder = with_text_index_file(&self_, layer_dir, builder).await?;
+
+            Ok(Box::new(builder) as Box<dyn LayerBuilder>)
+        })
+    }
 
-            Ok(Box::new(SimpleLayerBuilder::from_parent(
-                layer_dir,
-                parent_layer,
-                child_layer_files,
-            )) as Box<dyn LayerBuilder>)
+    fn resume_layer_builder_with_cache(
+        &self,
+        name: [u32; 5],
+        cache: Arc<dyn LayerCache>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<Box<dyn LayerBuilder>>>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            match self_.checkpoint_directory(name) {
+                Some(directory) if has_checkpoint(&directory) => {}
+                _ => return Ok(None),
+            }
+
+            let builder = if self_.layer_has_parent(name).await? {
+                let parent = self_.read_parent_file(name).await?;
+                let parent_layer = self_
+                    .get_layer_with_cache(parent, cache)
+                    .await?
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::NotFound, "parent layer not found")
+                    })?;
+                let files = self_.child_layer_files(name).await?;
+                SimpleLayerBuilder::from_parent(name, parent_layer, files)
+            } else {
+                let files = self_.base_layer_files(name).await?;
+                SimpleLayerBuilder::new(name, files)
+            };
+            let quad_files = self_.quad_files(name).await?;
+            let metadata_file = self_.get_file(name, FILENAMES.metadata).await?;
+            let builder = configure_builder(
+                &self_,
+                builder
+                    .with_quad_files(quad_files)
+                    .with_metadata_file(metadata_file),
+            )?;
+            let builder = with_text_index_file(&self_, name, builder).await?;
+
+            Ok(Some(Box::new(builder) as Box<dyn LayerBuilder>))
         })
     }
 
@@ -2097,6 +2561,87 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
         })
     }
 
+    fn truncate_history(
+        &self,
+        layer: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<[u32; 5]>>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            if !self_.directory_exists(layer).await? {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "layer not found"));
+            }
+            if !self_.layer_has_parent(layer).await? {
+                return Ok(None);
+            }
+
+            let parent = self_.read_parent_file(layer).await?;
+            let parent_layer = self_
+                .get_layer(parent)
+                .await?
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "parent layer not found"))?;
+            let base = self_.perform_rollup(parent_layer).await?;
+            if base == parent {
+                // the parent is a base layer, so there is no history to drop
+                return Ok(Some(parent));
+            }
+            self_.register_rollup(parent, base).await?;
+
+            for name in self_.directories().await? {
+                if name != base
+                    && self_.layer_has_parent(name).await?
+                    && self_.read_parent_file(name).await? == parent
+                {
+                    self_.write_parent_file(name, base).await?;
+                }
+            }
+
+            Ok(Some(base))
+        })
+    }
+
+    fn collect_garbage(
+        &self,
+        keep: Vec<[u32; 5]>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Vec<[u32; 5]>>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            let mut needed = HashSet::new();
+            let mut todo = keep;
+            while let Some(name) = todo.pop() {
+                if !needed.insert(name) || !self_.directory_exists(name).await? {
+                    continue;
+                }
+                if self_.layer_has_parent(name).await? {
+                    todo.push(self_.read_parent_file(name).await?);
+                }
+                if self_.layer_has_rollup(name).await? {
+                    todo.push(self_.read_rollup_file(name).await?);
+                }
+            }
+
+            let mut removed = Vec::new();
+            for name in self_.directories().await? {
+                // dictionaries are the first files written on commit, so a
+                // layer without them is still being built. Layers whose
+                // commit is underway are not recognized here, which is
+                // why `Store::collect_garbage` keeps commits from running
+                // concurrently.
+                if needed.contains(&name)
+                    || !self_
+                        .file_exists(name, FILENAMES.node_dictionary_blocks)
+                        .await?
+                {
+                    continue;
+                }
+
+                self_.remove_directory(name).await?;
+                removed.push(name);
+            }
+
+            Ok(removed)
+        })
+    }
+
     fn triple_addition_exists(
         &self,
         layer: [u32; 5],
@@ -2401,6 +2946,27 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
         })
     }
 
+    fn layer_file_size(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<u64>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            if !self_.directory_exists(name).await? {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "layer not found"));
+            }
+
+            let mut size = 0;
+            for file_name in all_layer_files() {
+                if self_.file_exists(name, file_name).await? {
+                    size += self_.get_file(name, file_name).await?.size().await? as u64;
+                }
+            }
+
+            Ok(size)
+        })
+    }
+
     fn retrieve_layer_stack_names(
         &self,
         name: [u32; 5],
@@ -2450,6 +3016,99 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
             Ok(result)
         })
     }
+
+    fn layer_metadata(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<LayerMetadata>>> + Send>> {
+        let get_file = self.get_file(name, FILENAMES.metadata);
+        Box::pin(async move { read_metadata(&get_file.await?).await })
+    }
+
+    fn layer_text_index(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<TextIndex>>> + Send>> {
+        let get_file = self.get_file(name, FILENAMES.text_index);
+        Box::pin(async move { read_text_index(&get_file.await?).await })
+    }
+
+    fn layer_quads(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<QuadStack>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            let mut layers = Vec::new();
+            for name in self_.retrieve_layer_stack_names(name).await? {
+                let files = self_.quad_files(name).await?;
+                if let Some(quads) = LayerQuads::load_from_files(name, &files).await? {
+                    layers.push(quads);
+                }
+            }
+
+            Ok(QuadStack::new(layers))
+        })
+    }
+
+    fn finalize_layer(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            let mut name = name;
+            if self_.content_addressed() {
+                let content_name = self_.layer_content_name(name).await?.ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "layer to finalize not found")
+                })?;
+                if content_name != name {
+                    self_.rename_directory(name, content_name).await?;
+                    name = content_name;
+                }
+            }
+
+            self_.sync_directory(name).await?;
+
+            Ok(name)
+        })
+    }
+}
+
+/// Apply the builder configuration of a store to a new or resumed builder.
+fn configure_builder<S: PersistentLayerStore>(
+    store: &S,
+    mut builder: SimpleLayerBuilder<S::File>,
+) -> io::Result<SimpleLayerBuilder<S::File>> {
+    if let Some(config) = store.spill_config() {
+        builder = builder.with_spill(config);
+    }
+    if let Some(check) = store.space_check() {
+        builder = builder.with_space_check(check);
+    }
+    if let Some(parallelism) = store.parallelism() {
+        builder = builder.with_parallelism(parallelism);
+    }
+    builder = builder.with_removal_validation(store.removal_validation());
+    if let Some(directory) = store.checkpoint_directory(builder.name()) {
+        builder = builder.with_checkpoint_directory(directory)?;
+    }
+
+    Ok(builder)
+}
+
+/// Give a new or resumed builder a file for its text index, if the store builds text indexes.
+async fn with_text_index_file<S: PersistentLayerStore>(
+    store: &S,
+    name: [u32; 5],
+    builder: SimpleLayerBuilder<S::File>,
+) -> io::Result<SimpleLayerBuilder<S::File>> {
+    if !store.text_index() {
+        return Ok(builder);
+    }
+
+    let file = store.get_file(name, FILENAMES.text_index).await?;
+    Ok(builder.with_text_index_file(file))
 }
 
 pub(crate) async fn file_triple_exists<F: FileLoad + FileStore>(
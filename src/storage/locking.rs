@@ -7,13 +7,111 @@ use std::io::Read;
 use std::io::{self, SeekFrom};
 use std::path::*;
 use std::pin::Pin;
+use std::time::{Duration, Instant};
+use thiserror::Error;
 use tokio::fs;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt, ReadBuf};
 use tokio::task::{spawn_blocking, JoinHandle};
 
+/// The interval at which a lock is retried when waiting with a timeout.
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What to do when a file lock is held by someone else, for example another process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum LockPolicy {
+    /// Wait for as long as it takes for the lock to become available.
+    #[default]
+    Block,
+    /// Fail immediately with `LockError::WouldBlock`.
+    NonBlocking,
+    /// Wait at most the given duration, then fail with `LockError::TimedOut`.
+    Timeout(Duration),
+}
+
+/// An error acquiring a file lock.
+///
+/// This is returned wrapped in an `io::Error`, with kind
+/// `io::ErrorKind::WouldBlock` or `io::ErrorKind::TimedOut`
+/// respectively. Use `LockError::from_io_error` to get at it.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum LockError {
+    #[error("file is locked by someone else")]
+    WouldBlock,
+    #[error("timed out after {0:?} waiting for a file lock")]
+    TimedOut(Duration),
+}
+
+impl LockError {
+    /// Retrieve the lock error from an `io::Error`, if this io error was caused by one.
+    pub fn from_io_error(error: &io::Error) -> Option<&LockError> {
+        error.get_ref().and_then(|e| e.downcast_ref::<LockError>())
+    }
+}
+
+impl From<LockError> for io::Error {
+    fn from(error: LockError) -> io::Error {
+        let kind = match error {
+            LockError::WouldBlock => io::ErrorKind::WouldBlock,
+            LockError::TimedOut(_) => io::ErrorKind::TimedOut,
+        };
+
+        io::Error::new(kind, error)
+    }
+}
+
+fn try_lock(file: &std::fs::File, exclusive: bool) -> io::Result<bool> {
+    let result = if exclusive {
+        FileExt::try_lock_exclusive(file)
+    } else {
+        FileExt::try_lock_shared(file)
+    };
+
+    match result {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == lock_contended_error().kind() => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+async fn lock_file(
+    file: std::fs::File,
+    exclusive: bool,
+    policy: LockPolicy,
+) -> io::Result<std::fs::File> {
+    if try_lock(&file, exclusive)? {
+        return Ok(file);
+    }
+
+    match policy {
+        LockPolicy::Block => {
+            if exclusive {
+                LockedFileLockFuture::new_exclusive(file).await
+            } else {
+                LockedFileLockFuture::new_shared(file).await
+            }
+        }
+        LockPolicy::NonBlocking => Err(LockError::WouldBlock.into()),
+        LockPolicy::Timeout(timeout) => {
+            // A blocking lock cannot be cancelled, so instead we
+            // poll for the lock until we run out of time.
+            let start = Instant::now();
+            loop {
+                let elapsed = start.elapsed();
+                if elapsed >= timeout {
+                    return Err(LockError::TimedOut(timeout).into());
+                }
+                tokio::time::sleep(LOCK_RETRY_INTERVAL.min(timeout - elapsed)).await;
+                if try_lock(&file, exclusive)? {
+                    return Ok(file);
+                }
+            }
+        }
+    }
+}
+
 pub struct LockedFileLockFuture {
     file: Option<std::fs::File>,
-    spawn: Option<JoinHandle<()>>,
+    spawn: Option<JoinHandle<io::Result<()>>>,
     exclusive: bool,
 }
 
@@ -50,21 +148,23 @@ impl Future for LockedFileLockFuture {
                 let exclusive = self.exclusive;
                 self.spawn = Some(spawn_blocking(move || {
                     if exclusive {
-                        file.lock_exclusive()
-                            .expect("failed to acquire exclusive lock")
+                        FileExt::lock_exclusive(&file)
                     } else {
-                        file.lock_shared()
-                            .expect("failed to acquire exclusive lock")
+                        FileExt::lock_shared(&file)
                     }
                 }));
             }
 
             match Pin::new(&mut self.spawn.as_mut().unwrap()).poll(cx) {
-                Poll::Ready(Ok(_)) => {
+                Poll::Ready(Ok(Ok(()))) => {
                     let mut file = None;
                     std::mem::swap(&mut file, &mut self.file);
                     Poll::Ready(Ok(file.unwrap()))
                 }
+                Poll::Ready(Ok(Err(e))) => {
+                    self.file = None;
+                    Poll::Ready(Err(e))
+                }
                 Poll::Pending => Poll::Pending,
                 Poll::Ready(Err(_)) => {
                     panic!("polled LockedFileLockFuture outside of a tokio context")
@@ -83,16 +183,20 @@ pub struct LockedFile {
 
 impl LockedFile {
     pub async fn open<P: 'static + AsRef<Path> + Send>(path: P) -> io::Result<Self> {
+        Self::open_with_policy(path, LockPolicy::Block).await
+    }
+
+    pub async fn open_with_policy<P: 'static + AsRef<Path> + Send>(
+        path: P,
+        policy: LockPolicy,
+    ) -> io::Result<Self> {
         let file = fs::OpenOptions::new()
             .read(true)
             .open(path)
             .await?
             .into_std()
             .await;
-        let file = match file.try_lock_shared() {
-            Ok(()) => file,
-            Err(_) => LockedFileLockFuture::new_shared(file).await?,
-        };
+        let file = lock_file(file, false, policy).await?;
 
         Ok(LockedFile {
             file: Some(fs::File::from_std(file)),
@@ -160,6 +264,13 @@ pub struct ExclusiveLockedFile {
 }
 impl ExclusiveLockedFile {
     pub async fn create_and_open<P: 'static + AsRef<Path> + Send>(path: P) -> io::Result<Self> {
+        Self::create_and_open_with_policy(path, LockPolicy::Block).await
+    }
+
+    pub async fn create_and_open_with_policy<P: 'static + AsRef<Path> + Send>(
+        path: P,
+        policy: LockPolicy,
+    ) -> io::Result<Self> {
         let file = fs::OpenOptions::new()
             .create_new(true)
             .read(false)
@@ -169,10 +280,7 @@ impl ExclusiveLockedFile {
             .into_std()
             .await;
 
-        let file = match file.try_lock_exclusive() {
-            Ok(()) => file,
-            Err(_) => LockedFileLockFuture::new_exclusive(file).await?,
-        };
+        let file = lock_file(file, true, policy).await?;
 
         Ok(ExclusiveLockedFile {
             file: Some(fs::File::from_std(file)),
@@ -180,6 +288,13 @@ impl ExclusiveLockedFile {
     }
 
     pub async fn open<P: 'static + AsRef<Path> + Send>(path: P) -> io::Result<Self> {
+        Self::open_with_policy(path, LockPolicy::Block).await
+    }
+
+    pub async fn open_with_policy<P: 'static + AsRef<Path> + Send>(
+        path: P,
+        policy: LockPolicy,
+    ) -> io::Result<Self> {
         let file = fs::OpenOptions::new()
             .read(true)
             .write(true)
@@ -188,10 +303,7 @@ impl ExclusiveLockedFile {
             .into_std()
             .await;
 
-        let file = match file.try_lock_exclusive() {
-            Ok(()) => file,
-            Err(_) => LockedFileLockFuture::new_exclusive(file).await?,
-        };
+        let file = lock_file(file, true, policy).await?;
 
         Ok(ExclusiveLockedFile {
             file: Some(fs::File::from_std(file)),
@@ -5,15 +5,20 @@ use std::io;
 use std::pin::Pin;
 use std::sync::{Arc, RwLock};
 
+use futures::stream;
 use futures::task::{Context, Poll};
 use futures::Future;
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::broadcast;
 
 use async_trait::async_trait;
 
+#[cfg(feature = "fs")]
+use super::directory::{DirectoryLabelStore, DirectoryLayerStore};
 use super::file::*;
 use super::label::*;
 use super::layer::*;
+use crate::layer::RemovalValidation;
 
 use bytes::{Bytes, BytesMut};
 enum MemoryBackedStoreContents {
@@ -177,17 +182,64 @@ impl FileLoad for MemoryBackedStore {
             MemoryBackedStoreContents::Existent(bytes) => Ok(bytes.clone()),
         }
     }
+
+    async fn map_range(&self, offset: usize, len: usize) -> io::Result<Bytes> {
+        match &*self.contents.read().unwrap() {
+            MemoryBackedStoreContents::Nonexistent => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "tried to open a nonexistent memory file for reading",
+            )),
+            MemoryBackedStoreContents::Existent(bytes) => {
+                check_range(offset, len, bytes.len())?;
+                Ok(bytes.slice(offset..offset + len))
+            }
+        }
+    }
 }
 
 #[derive(Clone, Default)]
 pub struct MemoryLayerStore {
     layers: futures_locks::RwLock<HashMap<[u32; 5], HashMap<String, MemoryBackedStore>>>,
+    content_addressed: bool,
+    removal_validation: RemovalValidation,
 }
 
 impl MemoryLayerStore {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// Name committed layers after their content rather than randomly.
+    ///
+    /// See `LayerStore::layer_content_name` for how this name is derived.
+    pub fn with_content_addressing(mut self) -> Self {
+        self.content_addressed = true;
+        self
+    }
+
+    /// Set what child layer builders created by this store do with removals of triples that don't exist.
+    ///
+    /// See `SimpleLayerBuilder::with_removal_validation` for details.
+    pub fn with_removal_validation(mut self, validation: RemovalValidation) -> Self {
+        self.removal_validation = validation;
+        self
+    }
+
+    /// Copy all layers in this store to the given directory store.
+    ///
+    /// Layers that already exist in the directory store are skipped.
+    #[cfg(feature = "fs")]
+    pub async fn persist_to(&self, target: &DirectoryLayerStore) -> io::Result<()> {
+        copy_all_layers(self, target).await
+    }
+
+    /// Copy all layers in the given directory store into this store.
+    ///
+    /// Layers that already exist in this store are skipped.
+    #[cfg(feature = "fs")]
+    pub async fn load_from(&self, source: &DirectoryLayerStore) -> io::Result<()> {
+        copy_all_layers(source, self).await
+    }
 }
 
 impl PersistentLayerStore for MemoryLayerStore {
@@ -218,6 +270,44 @@ impl PersistentLayerStore for MemoryLayerStore {
         Box::pin(async move { Ok(guard.await.contains_key(&name)) })
     }
 
+    fn content_addressed(&self) -> bool {
+        self.content_addressed
+    }
+
+    fn removal_validation(&self) -> RemovalValidation {
+        self.removal_validation
+    }
+
+    fn remove_directory(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        let guard = self.layers.write();
+        Box::pin(async move {
+            match guard.await.remove(&name) {
+                Some(_) => Ok(()),
+                None => Err(io::Error::new(io::ErrorKind::NotFound, "layer not found")),
+            }
+        })
+    }
+
+    fn rename_directory(
+        &self,
+        from: [u32; 5],
+        to: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        let guard = self.layers.write();
+        Box::pin(async move {
+            let mut layers = guard.await;
+            let files = layers
+                .remove(&from)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "layer not found"))?;
+            layers.entry(to).or_insert(files);
+
+            Ok(())
+        })
+    }
+
     fn file_exists(
         &self,
         directory: [u32; 5],
@@ -266,14 +356,55 @@ impl PersistentLayerStore for MemoryLayerStore {
     }
 }
 
-#[derive(Clone, Default)]
+/// The amount of label changes a slow watcher may fall behind before it starts skipping versions.
+const LABEL_CHANGE_CAPACITY: usize = 64;
+
+#[derive(Clone)]
 pub struct MemoryLabelStore {
     labels: futures_locks::RwLock<HashMap<String, Label>>,
+    histories: Arc<RwLock<HashMap<String, Vec<LabelHistoryEntry>>>>,
+    prefixes: Arc<RwLock<HashMap<String, PrefixMap>>>,
+    changes: broadcast::Sender<(String, Option<Label>)>,
 }
 
 impl MemoryLabelStore {
     pub fn new() -> MemoryLabelStore {
-        Default::default()
+        let (changes, _) = broadcast::channel(LABEL_CHANGE_CAPACITY);
+        MemoryLabelStore {
+            labels: Default::default(),
+            histories: Default::default(),
+            prefixes: Default::default(),
+            changes,
+        }
+    }
+
+    /// Copy all labels in this store to the given directory store.
+    ///
+    /// Labels that already exist in the directory store are
+    /// overwritten. The layers these labels point at should be
+    /// persisted first, using `MemoryLayerStore::persist_to`.
+    #[cfg(feature = "fs")]
+    pub async fn persist_to(&self, target: &DirectoryLabelStore) -> io::Result<()> {
+        copy_all_labels(self, target).await
+    }
+
+    /// Copy all labels in the given directory store into this store.
+    ///
+    /// Labels that already exist in this store are overwritten.
+    #[cfg(feature = "fs")]
+    pub async fn load_from(&self, source: &DirectoryLabelStore) -> io::Result<()> {
+        copy_all_labels(source, self).await
+    }
+
+    fn notify(&self, name: String, label: Option<Label>) {
+        // an error here just means nobody is watching
+        let _ = self.changes.send((name, label));
+    }
+}
+
+impl Default for MemoryLabelStore {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -305,6 +436,14 @@ impl LabelStore for MemoryLabelStore {
         Ok(labels.get(&name).cloned())
     }
 
+    async fn get_labels(&self, names: &[&str]) -> io::Result<Vec<Option<Label>>> {
+        let labels = self.labels.read().await;
+        Ok(names
+            .iter()
+            .map(|name| labels.get(*name).cloned())
+            .collect())
+    }
+
     async fn set_label_option(
         &self,
         label: &Label,
@@ -323,7 +462,14 @@ impl LabelStore for MemoryLabelStore {
                 if old_label.version + 1 != new_label.version {
                     Ok(None)
                 } else {
+                    self.histories
+                        .write()
+                        .unwrap()
+                        .entry(new_label.name.clone())
+                        .or_default()
+                        .push(LabelHistoryEntry::new(old_label, &new_label));
                     labels.insert(new_label.name.clone(), new_label.clone());
+                    self.notify(new_label.name.clone(), Some(new_label.clone()));
 
                     Ok(Some(new_label))
                 }
@@ -331,10 +477,171 @@ impl LabelStore for MemoryLabelStore {
         }
     }
 
+    async fn set_labels_if(&self, updates: Vec<LabelUpdate>) -> Result<Vec<Label>, SetLabelsError> {
+        check_unique_label_updates(&updates)?;
+        let mut labels = self.labels.write().await;
+
+        let mut new_labels = Vec::with_capacity(updates.len());
+        for update in updates.iter() {
+            let label = labels
+                .get(&update.name)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "label not found"))?;
+            if label.layer != update.expected_layer {
+                return Err(SetLabelsError::Conflict {
+                    label: update.name.clone(),
+                    conflict: LabelConflict {
+                        current_head: label.layer,
+                    },
+                });
+            }
+            new_labels.push(label.with_updated_layer(update.new_layer));
+        }
+
+        let mut histories = self.histories.write().unwrap();
+        for new_label in new_labels.iter() {
+            let old_label = labels.insert(new_label.name.clone(), new_label.clone());
+            histories
+                .entry(new_label.name.clone())
+                .or_default()
+                .push(LabelHistoryEntry::new(&old_label.unwrap(), new_label));
+            self.notify(new_label.name.clone(), Some(new_label.clone()));
+        }
+
+        Ok(new_labels)
+    }
+
     async fn delete_label(&self, name: &str) -> io::Result<bool> {
         let mut labels = self.labels.write().await;
 
-        Ok(labels.remove(name).is_some())
+        let deleted = labels.remove(name).is_some();
+        if deleted {
+            self.histories.write().unwrap().remove(name);
+            self.prefixes.write().unwrap().remove(name);
+            self.notify(name.to_owned(), None);
+        }
+
+        Ok(deleted)
+    }
+
+    async fn rename_label(&self, name: &str, new_name: &str) -> io::Result<Label> {
+        let mut labels = self.labels.write().await;
+        if labels.contains_key(new_name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "label already exists",
+            ));
+        }
+        let mut label = labels
+            .remove(name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "label not found"))?;
+        label.name = new_name.to_owned();
+        labels.insert(label.name.clone(), label.clone());
+        let mut histories = self.histories.write().unwrap();
+        if let Some(history) = histories.remove(name) {
+            histories.insert(label.name.clone(), history);
+        }
+        std::mem::drop(histories);
+        let mut prefixes = self.prefixes.write().unwrap();
+        if let Some(map) = prefixes.remove(name) {
+            prefixes.insert(label.name.clone(), map);
+        }
+        std::mem::drop(prefixes);
+        self.notify(name.to_owned(), None);
+
+        Ok(label)
+    }
+
+    async fn label_history(&self, name: &str) -> io::Result<LabelHistoryStream> {
+        let labels = self.labels.read().await;
+        if !labels.contains_key(name) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "label not found"));
+        }
+        let history = self
+            .histories
+            .read()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .unwrap_or_default();
+
+        Ok(Box::pin(stream::iter(history.into_iter().map(Ok))))
+    }
+
+    async fn label_prefixes(&self, name: &str) -> io::Result<PrefixMap> {
+        let labels = self.labels.read().await;
+        if !labels.contains_key(name) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "label not found"));
+        }
+
+        Ok(self
+            .prefixes
+            .read()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn set_label_prefixes(&self, name: &str, prefixes: PrefixMap) -> io::Result<()> {
+        let labels = self.labels.read().await;
+        if !labels.contains_key(name) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "label not found"));
+        }
+        self.prefixes
+            .write()
+            .unwrap()
+            .insert(name.to_owned(), prefixes);
+
+        Ok(())
+    }
+
+    async fn watch_label(&self, name: &str) -> io::Result<LabelWatchStream> {
+        // subscribe while holding the lock, so no change can slip
+        // in between reading the label and subscribing.
+        let labels = self.labels.read().await;
+        let version = match labels.get(name) {
+            Some(label) => label.version,
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "label not found")),
+        };
+        let receiver = self.changes.subscribe();
+        std::mem::drop(labels);
+
+        let store = self.clone();
+        let name = name.to_owned();
+        Ok(Box::pin(stream::unfold(
+            Some((receiver, version)),
+            move |state| {
+                let store = store.clone();
+                let name = name.clone();
+                async move {
+                    let (mut receiver, version) = state?;
+                    loop {
+                        let label = match receiver.recv().await {
+                            Ok((changed, label)) if changed == name => label,
+                            Ok(_) => continue,
+                            Err(broadcast::error::RecvError::Lagged(_)) => {
+                                // we missed some changes, so just look
+                                // at what the label is now.
+                                match store.get_label(&name).await {
+                                    Ok(label) => label,
+                                    Err(e) => return Some((Err(e), None)),
+                                }
+                            }
+                            Err(broadcast::error::RecvError::Closed) => return None,
+                        };
+
+                        match label {
+                            None => return None,
+                            Some(label) if label.version > version => {
+                                let version = label.version;
+                                return Some((Ok(label), Some((receiver, version))));
+                            }
+                            Some(_) => {}
+                        }
+                    }
+                }
+            },
+        )))
     }
 }
 
@@ -399,6 +706,8 @@ pub fn base_layer_memory_files() -> BaseLayerFiles<MemoryBackedStore> {
             blocks_file: MemoryBackedStore::new(),
             sblocks_file: MemoryBackedStore::new(),
         },
+
+        predicate_stats_file: MemoryBackedStore::new(),
     }
 }
 
@@ -494,6 +803,8 @@ pub fn child_layer_memory_files() -> ChildLayerFiles<MemoryBackedStore> {
             blocks_file: MemoryBackedStore::new(),
             sblocks_file: MemoryBackedStore::new(),
         },
+
+        predicate_stats_file: MemoryBackedStore::new(),
     }
 }
 
@@ -521,6 +832,29 @@ mod tests {
         assert_eq!(vec![1, 2, 3], buf);
     }
 
+    #[tokio::test]
+    async fn write_and_map_range_memory_backed() {
+        let file = MemoryBackedStore::new();
+        let mut w = file.open_write().await.unwrap();
+        w.write_all(&[1, 2, 3, 4, 5]).await.unwrap();
+        w.sync_all().await.unwrap();
+
+        let map = file.map_range(1, 3).await.unwrap();
+        assert_eq!(&[2, 3, 4][..], &map[..]);
+
+        let mut buf = Vec::new();
+        file.read_range(3, 2)
+            .await
+            .unwrap()
+            .read_to_end(&mut buf)
+            .await
+            .unwrap();
+        assert_eq!(vec![4, 5], buf);
+
+        assert!(file.map_range(3, 3).await.is_err());
+        assert!(file.read_range(6, 0).await.is_err());
+    }
+
     #[tokio::test]
     async fn write_and_map_memory_backed() {
         let file = MemoryBackedStore::new();
@@ -636,4 +970,149 @@ mod tests {
 
         assert!(!store.delete_label("foo").await.unwrap());
     }
+
+    #[tokio::test]
+    async fn memory_set_label_if_expected_succeeds() {
+        let store = MemoryLabelStore::new();
+        store.create_label("foo").await.unwrap();
+
+        let foo = store
+            .set_label_if("foo", None, Some([6, 7, 8, 9, 10]))
+            .await
+            .unwrap();
+        assert_eq!(Some([6, 7, 8, 9, 10]), foo.layer);
+        let foo = store
+            .set_label_if("foo", Some([6, 7, 8, 9, 10]), Some([1, 1, 1, 1, 1]))
+            .await
+            .unwrap();
+        assert_eq!(Some([1, 1, 1, 1, 1]), foo.layer);
+        assert_eq!(2, foo.version);
+    }
+
+    #[tokio::test]
+    async fn memory_set_label_if_unexpected_conflicts() {
+        let store = MemoryLabelStore::new();
+        let foo = store.create_label("foo").await.unwrap();
+        store.set_label(&foo, [6, 7, 8, 9, 10]).await.unwrap();
+
+        match store.set_label_if("foo", None, Some([1, 1, 1, 1, 1])).await {
+            Err(SetLabelError::Conflict(conflict)) => {
+                assert_eq!(Some([6, 7, 8, 9, 10]), conflict.current_head)
+            }
+            _ => panic!("expected a label conflict"),
+        }
+
+        assert_eq!(
+            Some([6, 7, 8, 9, 10]),
+            store.get_label("foo").await.unwrap().unwrap().layer
+        );
+    }
+
+    #[tokio::test]
+    async fn memory_set_label_if_nonexistent_label_errors() {
+        let store = MemoryLabelStore::new();
+
+        match store.set_label_if("foo", None, Some([1, 1, 1, 1, 1])).await {
+            Err(SetLabelError::Io(e)) => assert_eq!(io::ErrorKind::NotFound, e.kind()),
+            _ => panic!("expected a not found error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn memory_watch_label() {
+        use futures::StreamExt;
+        let store = MemoryLabelStore::new();
+        let foo = store.create_label("foo").await.unwrap();
+        let bar = store.create_label("bar").await.unwrap();
+
+        let mut watch = store.watch_label("foo").await.unwrap();
+        store.set_label(&bar, [1, 1, 1, 1, 1]).await.unwrap();
+        let foo = store
+            .set_label(&foo, [6, 7, 8, 9, 10])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(foo, watch.next().await.unwrap().unwrap());
+
+        let foo = store.clear_label(&foo).await.unwrap().unwrap();
+        assert_eq!(foo, watch.next().await.unwrap().unwrap());
+
+        store.delete_label("foo").await.unwrap();
+        assert!(watch.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn memory_watch_nonexistent_label_errors() {
+        let store = MemoryLabelStore::new();
+        let err = store.watch_label("foo").await.err().unwrap();
+        assert_eq!(io::ErrorKind::NotFound, err.kind());
+    }
+
+    #[tokio::test]
+    async fn persist_memory_store_to_directory_and_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let layer_store = MemoryLayerStore::new();
+        let label_store = MemoryLabelStore::new();
+
+        let mut builder = layer_store.create_base_layer().await.unwrap();
+        let base_name = builder.name();
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        builder.commit_boxed().await.unwrap();
+
+        let mut builder = layer_store.create_child_layer(base_name).await.unwrap();
+        let child_name = builder.name();
+        builder.add_string_triple(StringTriple::new_value("pig", "says", "oink"));
+        builder.commit_boxed().await.unwrap();
+
+        let foo = label_store.create_label("foo").await.unwrap();
+        label_store.set_label(&foo, child_name).await.unwrap();
+        label_store.create_label("bar").await.unwrap();
+
+        let directory_layer_store = DirectoryLayerStore::new(dir.path());
+        let directory_label_store = DirectoryLabelStore::new(dir.path());
+        layer_store
+            .persist_to(&directory_layer_store)
+            .await
+            .unwrap();
+        label_store
+            .persist_to(&directory_label_store)
+            .await
+            .unwrap();
+
+        let foo = directory_label_store
+            .get_label("foo")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some(child_name), foo.layer);
+        let bar = directory_label_store
+            .get_label("bar")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(None, bar.layer);
+        let layer = directory_layer_store
+            .get_layer(child_name)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        assert!(layer.string_triple_exists(&StringTriple::new_value("pig", "says", "oink")));
+
+        let layer_store = MemoryLayerStore::new();
+        let label_store = MemoryLabelStore::new();
+        layer_store.load_from(&directory_layer_store).await.unwrap();
+        label_store.load_from(&directory_label_store).await.unwrap();
+
+        let mut layers = layer_store.layers().await.unwrap();
+        layers.sort();
+        let mut expected = vec![base_name, child_name];
+        expected.sort();
+        assert_eq!(expected, layers);
+        let foo = label_store.get_label("foo").await.unwrap().unwrap();
+        assert_eq!(Some(child_name), foo.layer);
+        let layer = layer_store.get_layer(child_name).await.unwrap().unwrap();
+        assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        assert!(layer.string_triple_exists(&StringTriple::new_value("pig", "says", "oink")));
+    }
 }
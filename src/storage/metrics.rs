@@ -0,0 +1,151 @@
+//! Instrumentation hooks for storage backends.
+//!
+//! Storage backends report the IO they do into a `StorageMetrics`
+//! implementation. By default this is `NoMetrics`, which ignores
+//! everything. Embedders can supply their own implementation to
+//! forward these numbers to their monitoring system of choice.
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use super::file::SyncableFile;
+
+/// A sink for storage metrics.
+///
+/// All methods have a no-op default implementation, so implementors
+/// only need to implement what they are interested in. These methods
+/// are called in the IO path, so they should be cheap.
+pub trait StorageMetrics: 'static + Send + Sync {
+    /// A file was opened for reading.
+    fn file_opened_for_read(&self) {}
+    /// A file was opened for writing.
+    fn file_opened_for_write(&self) {}
+    /// The given amount of bytes was read from a file.
+    fn bytes_read(&self, _count: usize) {}
+    /// The given amount of bytes was written to a file.
+    fn bytes_written(&self, _count: usize) {}
+    /// A layer was found in the layer cache.
+    fn cache_hit(&self) {}
+    /// A layer was not found in the layer cache.
+    fn cache_miss(&self) {}
+}
+
+/// Metrics that go nowhere.
+pub struct NoMetrics;
+
+impl StorageMetrics for NoMetrics {}
+
+lazy_static! {
+    pub static ref NOMETRICS: Arc<dyn StorageMetrics> = Arc::new(NoMetrics);
+}
+
+/// Metrics that are simply counted.
+#[derive(Default, Debug)]
+pub struct CountingMetrics {
+    pub files_opened_for_read: AtomicU64,
+    pub files_opened_for_write: AtomicU64,
+    pub bytes_read: AtomicU64,
+    pub bytes_written: AtomicU64,
+    pub cache_hits: AtomicU64,
+    pub cache_misses: AtomicU64,
+}
+
+impl CountingMetrics {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl StorageMetrics for CountingMetrics {
+    fn file_opened_for_read(&self) {
+        self.files_opened_for_read.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn file_opened_for_write(&self) {
+        self.files_opened_for_write.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn bytes_read(&self, count: usize) {
+        self.bytes_read.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    fn bytes_written(&self, count: usize) {
+        self.bytes_written
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    fn cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn cache_miss(&self) {
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// A reader or writer that reports the amount of bytes passing through it.
+pub struct Metered<T> {
+    inner: T,
+    metrics: Arc<dyn StorageMetrics>,
+}
+
+impl<T> Metered<T> {
+    pub fn new(inner: T, metrics: Arc<dyn StorageMetrics>) -> Self {
+        Self { inner, metrics }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Metered<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            self.metrics.bytes_read(buf.filled().len() - before);
+        }
+
+        result
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Metered<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(count)) = result {
+            self.metrics.bytes_written(count);
+        }
+
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[async_trait]
+impl<T: SyncableFile> SyncableFile for Metered<T> {
+    async fn sync_all(self) -> io::Result<()> {
+        self.inner.sync_all().await
+    }
+}
@@ -21,22 +21,40 @@
 //!
 //! A label store is a set of files. The file name is of the format
 //! `foo.label`, for database `foo`. This file contains the name of
-//! the layer this label is pointing at.
+//! the layer this label is pointing at. Every change of that layer is
+//! also appended to `foo.label.history`. Namespace prefixes of the
+//! database are kept in `foo.label.prefixes`.
 mod cache;
 mod consts;
+#[cfg(feature = "fs")]
 pub mod directory;
 mod file;
 mod label;
 #[macro_use]
 mod layer;
 pub mod delta;
+#[cfg(feature = "fs")]
 mod locking;
 pub mod memory;
+mod metrics;
 pub mod pack;
+#[cfg(feature = "remote")]
+pub mod remote;
+mod scratch;
+mod space;
+mod tiered;
+#[cfg(all(feature = "fs", feature = "io-uring", target_os = "linux"))]
+mod uring;
 
 pub use cache::*;
 pub use delta::*;
 pub use file::*;
 pub use label::*;
 pub use layer::*;
+#[cfg(feature = "fs")]
+pub use locking::{LockError, LockPolicy};
+pub use metrics::*;
 pub use pack::*;
+pub use scratch::*;
+pub use space::*;
+pub use tiered::*;
@@ -2,7 +2,6 @@ use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
 use std::io::{self, Read};
 use std::path::PathBuf;
-use std::time::{SystemTime, UNIX_EPOCH};
 
 use async_trait::async_trait;
 
@@ -20,6 +19,9 @@ use tokio::io::AsyncWriteExt;
 #[async_trait]
 pub trait Packable {
     /// Export the given layers by creating a pack, a Vec<u8> that can later be used with `import_layers` on a different store.
+    ///
+    /// Exporting the same layers in the same order always results in
+    /// the same pack.
     async fn export_layers(
         &self,
         layer_ids: Box<dyn Iterator<Item = [u32; 5]> + Send>,
@@ -43,10 +45,11 @@ impl<T: PersistentLayerStore> Packable for T {
         &self,
         layer_ids: Box<dyn Iterator<Item = [u32; 5]> + Send>,
     ) -> io::Result<Vec<u8>> {
-        let mtime = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+        // layer files never change, so rather than the time of export,
+        // all entries get the same fixed mtime. Exporting the same
+        // layers again then results in the exact same pack, which is
+        // what allows remote transfers to be resumed.
+        let mtime = 0;
 
         let mut enc = GzEncoder::new(Vec::new(), Compression::default());
         {
@@ -71,8 +74,10 @@ impl<T: PersistentLayerStore> Packable for T {
             self.create_named_directory(id).await?;
         }
 
-        let handle = tokio::runtime::Handle::current();
-        tokio::task::block_in_place(|| {
+        // the archive is read synchronously, so the files are first
+        // collected, and written out afterwards.
+        let files = crate::runtime::block_in_place(|| {
+            let mut files = Vec::new();
             let cursor = io::Cursor::new(pack);
             let tar = GzDecoder::new(cursor);
             let mut archive = Archive::new(tar);
@@ -112,20 +117,22 @@ impl<T: PersistentLayerStore> Packable for T {
                     let mut content = Vec::with_capacity(header.size()? as usize);
                     entry.read_to_end(&mut content)?;
 
-                    handle.block_on(async move {
-                        let file = self.get_file(layer_id_arr, &file_name).await?;
-                        let mut writer = file.open_write().await?;
-                        writer.write_all(&content).await?;
-                        writer.flush().await?;
-                        writer.sync_all().await?;
-
-                        Ok::<_, io::Error>(())
-                    })?;
+                    files.push((layer_id_arr, file_name, content));
                 }
             }
 
-            Ok(())
-        })
+            Ok::<_, io::Error>(files)
+        })?;
+
+        for (layer_id, file_name, content) in files {
+            let file = self.get_file(layer_id, &file_name).await?;
+            let mut writer = file.open_write().await?;
+            writer.write_all(&content).await?;
+            writer.flush().await?;
+            writer.sync_all().await?;
+        }
+
+        Ok(())
     }
 }
 
@@ -148,7 +155,7 @@ async fn tar_append_file<S: PersistentLayerStore, W: io::Write>(
         header.set_mode(0o644);
         header.set_size(file.size().await? as u64);
         header.set_mtime(mtime);
-        tokio::task::block_in_place(|| tar.append_data(&mut header, path, cursor).unwrap());
+        crate::runtime::block_in_place(|| tar.append_data(&mut header, path, cursor).unwrap());
 
         Ok(())
     } else {
@@ -178,7 +185,7 @@ async fn tar_append_file_if_exists<S: PersistentLayerStore, W: io::Write>(
         header.set_mode(0o644);
         header.set_size(file.size().await? as u64);
         header.set_mtime(mtime);
-        tokio::task::block_in_place(|| tar.append_data(&mut header, path, cursor).unwrap());
+        crate::runtime::block_in_place(|| tar.append_data(&mut header, path, cursor).unwrap());
     }
 
     Ok(())
@@ -198,7 +205,7 @@ async fn tar_append_layer<W: io::Write, S: PersistentLayerStore>(
     let layer_name = name_to_string(layer);
     let mut path = PathBuf::new();
     path.push(layer_name);
-    tokio::task::block_in_place(|| {
+    crate::runtime::block_in_place(|| {
         tar.append_data(&mut header, &path, std::io::empty())
             .unwrap()
     });
@@ -374,5 +381,11 @@ mod tests {
             ],
             triples
         );
+
+        let export_again = store1
+            .export_layers(Box::new(vec![base_name, child_name].into_iter()))
+            .await
+            .unwrap();
+        assert!(export == export_again);
     }
 }
@@ -0,0 +1,540 @@
+//! A layer store that fetches missing layers from a remote store.
+//!
+//! Layers are retrieved over HTTP(S) as packs, as created by
+//! `Packable::export_layers`. For a layer named `<layer>` in
+//! hexadecimal, the remote is expected to serve a pack containing
+//! just that layer at `<base url>/pack/<layer>`, or respond with 404
+//! if it doesn't know about the layer. The response should carry a
+//! `x-terminus-pack-sha256` header containing the hex-encoded
+//! SHA-256 checksum of the entire pack (see `pack_checksum`), and
+//! should honor `Range: bytes=<offset>-` requests so that interrupted
+//! transfers can be resumed. If the checksum of a resumed transfer
+//! differs from the one it started with, the transfer starts over.
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures::future::Future;
+use futures::StreamExt;
+use reqwest::header::{HeaderValue, RANGE};
+use reqwest::{Client, StatusCode};
+use sha2::{Digest, Sha256};
+
+use super::consts::*;
+use super::layer::*;
+use super::pack::Packable;
+use super::scratch::SpillConfig;
+use super::space::SpaceCheck;
+use crate::layer::{Parallelism, RemovalValidation};
+
+/// The header a remote uses to send the checksum of a pack.
+pub const PACK_CHECKSUM_HEADER: &str = "x-terminus-pack-sha256";
+
+/// The default amount of times a transfer is resumed before giving up.
+const DEFAULT_RETRIES: usize = 3;
+
+/// Calculate the checksum of a pack, as sent in the `x-terminus-pack-sha256` header.
+pub fn pack_checksum(pack: &[u8]) -> String {
+    Sha256::digest(pack)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn remote_error(error: reqwest::Error) -> io::Error {
+    io::Error::other(error)
+}
+
+/// A layer store which hydrates layers it doesn't have from a remote store.
+///
+/// All layers are stored in the local store. Whenever a layer is
+/// requested that the local store doesn't have, it is fetched from
+/// the remote and imported into the local store. Ancestors are
+/// fetched in the same lazy way, so only layers that are actually
+/// needed and missing locally are ever transferred.
+#[derive(Clone)]
+pub struct RemoteLayerStore<S: PersistentLayerStore> {
+    local: S,
+    base_url: String,
+    client: Client,
+    retries: usize,
+    fetch_lock: futures_locks::Mutex<()>,
+}
+
+impl<S: PersistentLayerStore> RemoteLayerStore<S> {
+    pub fn new<U: Into<String>>(local: S, base_url: U) -> Self {
+        Self {
+            local,
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            client: Client::new(),
+            retries: DEFAULT_RETRIES,
+            fetch_lock: futures_locks::Mutex::new(()),
+        }
+    }
+
+    /// Use the given HTTP client, for example to set timeouts or authentication headers.
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Set how many times an interrupted transfer is resumed before giving up.
+    pub fn with_retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub fn local(&self) -> &S {
+        &self.local
+    }
+
+    fn pack_url(&self, name: [u32; 5]) -> String {
+        format!("{}/pack/{}", self.base_url, name_to_string(name))
+    }
+
+    /// Download the pack for the given layer, resuming the transfer if it is interrupted.
+    ///
+    /// Returns None if the remote does not know about this layer.
+    async fn download_pack(&self, name: [u32; 5]) -> io::Result<Option<Vec<u8>>> {
+        let url = self.pack_url(name);
+        let mut pack: Vec<u8> = Vec::new();
+        let mut checksum: Option<String> = None;
+        let mut attempt = 0;
+        loop {
+            let mut request = self.client.get(&url);
+            if !pack.is_empty() {
+                request = request.header(RANGE, format!("bytes={}-", pack.len()));
+            }
+
+            let result = async {
+                let response = request.send().await.map_err(remote_error)?;
+                match response.status() {
+                    StatusCode::NOT_FOUND => return Ok(false),
+                    // the remote ignored our range request, so we start over
+                    StatusCode::OK => pack.clear(),
+                    StatusCode::PARTIAL_CONTENT if !pack.is_empty() => {}
+                    status => {
+                        return Err(io::Error::other(format!(
+                            "unexpected status {} while fetching layer pack",
+                            status
+                        )))
+                    }
+                }
+
+                let response_checksum = response
+                    .headers()
+                    .get(PACK_CHECKSUM_HEADER)
+                    .and_then(|h: &HeaderValue| h.to_str().ok())
+                    .map(|h| h.to_ascii_lowercase())
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidData, "remote sent no pack checksum")
+                    })?;
+                match &checksum {
+                    Some(checksum) if checksum != &response_checksum => {
+                        // the pack changed in between attempts, so what
+                        // we have so far is useless. Start over.
+                        return Err(io::Error::new(
+                            io::ErrorKind::Interrupted,
+                            "pack checksum changed while resuming transfer",
+                        ));
+                    }
+                    _ => checksum = Some(response_checksum),
+                }
+
+                let mut body = response.bytes_stream();
+                while let Some(chunk) = body.next().await {
+                    pack.extend_from_slice(&chunk.map_err(remote_error)?);
+                }
+
+                Ok(true)
+            }
+            .await;
+
+            match result {
+                Ok(false) => return Ok(None),
+                Ok(true) => break,
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => return Err(e),
+                Err(e) => {
+                    if e.kind() == io::ErrorKind::Interrupted {
+                        pack.clear();
+                        checksum = None;
+                    }
+                    attempt += 1;
+                    if attempt > self.retries {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+
+        if Some(pack_checksum(&pack)) != checksum {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "checksum mismatch for fetched layer pack",
+            ));
+        }
+
+        Ok(Some(pack))
+    }
+
+    /// Returns true if the given layer and all the files it needs exist locally.
+    ///
+    /// A pack is imported one file at a time, so an interrupted
+    /// import leaves a layer directory behind with files missing.
+    async fn has_complete_layer(&self, name: [u32; 5]) -> io::Result<bool> {
+        if !self.local.directory_exists(name).await? {
+            return Ok(false);
+        }
+
+        let layer_files = if self.local.file_exists(name, FILENAMES.parent).await? {
+            &CHILD_LAYER_REQUIRED_FILES[..]
+        } else {
+            &BASE_LAYER_REQUIRED_FILES[..]
+        };
+        for file in SHARED_REQUIRED_FILES.iter().chain(layer_files) {
+            if !self.local.file_exists(name, file).await? {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Make sure the given layer exists locally, fetching it if needed.
+    ///
+    /// A layer that is only partially there locally, because an
+    /// earlier import was interrupted, is fetched again. Returns false
+    /// if neither the local store nor the remote have this layer.
+    pub async fn fetch_layer(&self, name: [u32; 5]) -> io::Result<bool> {
+        if self.has_complete_layer(name).await? {
+            return Ok(true);
+        }
+
+        // only fetch one layer at a time, so the same layer is never imported twice concurrently
+        let _guard = self.fetch_lock.lock().await;
+        if self.has_complete_layer(name).await? {
+            return Ok(true);
+        }
+
+        match self.download_pack(name).await? {
+            Some(pack) => {
+                self.local
+                    .import_layers(&pack, Box::new(std::iter::once(name)))
+                    .await?;
+
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Fetch the given layer and all its ancestors that are missing locally.
+    ///
+    /// Returns false if the layer could not be found.
+    pub async fn pull(&self, name: [u32; 5]) -> io::Result<bool> {
+        let mut current = name;
+        loop {
+            if !self.fetch_layer(current).await? {
+                if current == name {
+                    return Ok(false);
+                }
+
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "ancestor of fetched layer not found on remote",
+                ));
+            }
+
+            if self.local.layer_has_parent(current).await? {
+                current = self.local.read_parent_file(current).await?;
+            } else {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+impl<S: PersistentLayerStore> PersistentLayerStore for RemoteLayerStore<S> {
+    type File = S::File;
+
+    fn directories(&self) -> Pin<Box<dyn Future<Output = io::Result<Vec<[u32; 5]>>> + Send>> {
+        self.local.directories()
+    }
+
+    fn create_named_directory(
+        &self,
+        id: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        self.local.create_named_directory(id)
+    }
+
+    fn directory_exists(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move { self_.fetch_layer(name).await })
+    }
+
+    fn get_file(
+        &self,
+        directory: [u32; 5],
+        name: &str,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Self::File>> + Send>> {
+        let self_ = self.clone();
+        let name = name.to_owned();
+        Box::pin(async move {
+            self_.fetch_layer(directory).await?;
+            self_.local.get_file(directory, &name).await
+        })
+    }
+
+    fn file_exists(
+        &self,
+        directory: [u32; 5],
+        file: &str,
+    ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>> {
+        let self_ = self.clone();
+        let file = file.to_owned();
+        Box::pin(async move {
+            if self_.fetch_layer(directory).await? {
+                self_.local.file_exists(directory, &file).await
+            } else {
+                Ok(false)
+            }
+        })
+    }
+
+    fn spill_config(&self) -> Option<SpillConfig> {
+        self.local.spill_config()
+    }
+
+    fn space_check(&self) -> Option<Arc<dyn SpaceCheck>> {
+        self.local.space_check()
+    }
+
+    fn parallelism(&self) -> Option<Parallelism> {
+        self.local.parallelism()
+    }
+
+    fn removal_validation(&self) -> RemovalValidation {
+        self.local.removal_validation()
+    }
+
+    fn text_index(&self) -> bool {
+        self.local.text_index()
+    }
+
+    fn checkpoint_directory(&self, name: [u32; 5]) -> Option<PathBuf> {
+        self.local.checkpoint_directory(name)
+    }
+
+    fn content_addressed(&self) -> bool {
+        self.local.content_addressed()
+    }
+
+    fn sync_directory(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        self.local.sync_directory(name)
+    }
+
+    fn remove_directory(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        self.local.remove_directory(name)
+    }
+
+    fn rename_directory(
+        &self,
+        from: [u32; 5],
+        to: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        self.local.rename_directory(from, to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::*;
+    use crate::storage::memory::MemoryLayerStore;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// How the test remote sends the checksum of a pack.
+    #[derive(Clone, Copy, PartialEq)]
+    enum Checksum {
+        Correct,
+        Wrong,
+        /// Wrong in the first response for each layer only, as if the pack changed.
+        Changed,
+    }
+
+    /// Serve packs from the given store, cutting off the first response for each layer halfway.
+    async fn serve(store: MemoryLayerStore, checksum: Checksum) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let requests2 = requests.clone();
+        tokio::spawn(async move {
+            let mut interrupted = std::collections::HashSet::new();
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                requests2.fetch_add(1, Ordering::SeqCst);
+                let mut stream = BufReader::new(stream);
+                let mut path = String::new();
+                let mut offset = 0;
+                let mut line = String::new();
+                loop {
+                    line.clear();
+                    stream.read_line(&mut line).await.unwrap();
+                    let trimmed = line.trim_end();
+                    if trimmed.is_empty() {
+                        break;
+                    } else if let Some(request) = trimmed.strip_prefix("GET ") {
+                        path = request.split(' ').next().unwrap().to_string();
+                    } else if let Some(range) = trimmed.to_lowercase().strip_prefix("range: bytes=")
+                    {
+                        offset = range.trim_end_matches('-').parse().unwrap();
+                    }
+                }
+
+                let name = string_to_name(path.trim_start_matches("/pack/")).unwrap();
+                let stream = stream.get_mut();
+                if !store.directory_exists(name).await.unwrap() {
+                    stream
+                        .write_all(b"HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                        .await
+                        .unwrap();
+                    continue;
+                }
+
+                let pack = store
+                    .export_layers(Box::new(std::iter::once(name)))
+                    .await
+                    .unwrap();
+                let first = !interrupted.contains(&name);
+                let checksum = match checksum {
+                    Checksum::Wrong => pack_checksum(b"something else"),
+                    Checksum::Changed if first => pack_checksum(b"an older pack"),
+                    _ => pack_checksum(&pack),
+                };
+                let status = if offset == 0 {
+                    "200 OK"
+                } else {
+                    "206 Partial Content"
+                };
+                let body = &pack[offset..];
+                let header = format!(
+                    "HTTP/1.1 {}\r\ncontent-length: {}\r\n{}: {}\r\nconnection: close\r\n\r\n",
+                    status,
+                    body.len(),
+                    PACK_CHECKSUM_HEADER,
+                    checksum
+                );
+                stream.write_all(header.as_bytes()).await.unwrap();
+                if interrupted.insert(name) {
+                    stream.write_all(&body[..body.len() / 2]).await.unwrap();
+                } else {
+                    stream.write_all(body).await.unwrap();
+                }
+            }
+        });
+
+        (format!("http://{}", address), requests)
+    }
+
+    async fn create_stack(store: &MemoryLayerStore) -> ([u32; 5], [u32; 5]) {
+        let mut builder = store.create_base_layer().await.unwrap();
+        let base = builder.name();
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        builder.commit_boxed().await.unwrap();
+
+        let mut builder = store.create_child_layer(base).await.unwrap();
+        let child = builder.name();
+        builder.add_string_triple(StringTriple::new_value("pig", "says", "oink"));
+        builder.commit_boxed().await.unwrap();
+
+        (base, child)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn fetch_missing_layers_from_remote() {
+        let remote = MemoryLayerStore::new();
+        let (base, child) = create_stack(&remote).await;
+        let (url, requests) = serve(remote, Checksum::Correct).await;
+
+        let local = MemoryLayerStore::new();
+        let store = RemoteLayerStore::new(local.clone(), url);
+
+        let layer = store.get_layer(child).await.unwrap().unwrap();
+        assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        assert!(layer.string_triple_exists(&StringTriple::new_value("pig", "says", "oink")));
+
+        let mut layers = local.directories().await.unwrap();
+        layers.sort();
+        let mut expected = vec![base, child];
+        expected.sort();
+        assert_eq!(expected, layers);
+
+        // both transfers were interrupted once, and then resumed
+        assert_eq!(4, requests.load(Ordering::SeqCst));
+
+        // layers that exist locally are not fetched again
+        store.pull(child).await.unwrap();
+        assert_eq!(4, requests.load(Ordering::SeqCst));
+
+        assert!(store.get_layer([1, 2, 3, 4, 5]).await.unwrap().is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reject_pack_with_bad_checksum() {
+        let remote = MemoryLayerStore::new();
+        let (base, _) = create_stack(&remote).await;
+        let (url, _) = serve(remote, Checksum::Wrong).await;
+
+        let local = MemoryLayerStore::new();
+        let store = RemoteLayerStore::new(local.clone(), url);
+        let err = store.pull(base).await.unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert!(local.directories().await.unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn restart_transfer_when_pack_changed() {
+        let remote = MemoryLayerStore::new();
+        let (base, _) = create_stack(&remote).await;
+        let (url, requests) = serve(remote, Checksum::Changed).await;
+
+        let local = MemoryLayerStore::new();
+        let store = RemoteLayerStore::new(local.clone(), url);
+        assert!(store.pull(base).await.unwrap());
+        let layer = store.get_layer(base).await.unwrap().unwrap();
+        assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+
+        // the resumed transfer noticed the change, and started over
+        assert_eq!(3, requests.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn fetch_partially_imported_layer_again() {
+        let remote = MemoryLayerStore::new();
+        let (base, _) = create_stack(&remote).await;
+        let (url, requests) = serve(remote, Checksum::Correct).await;
+
+        let local = MemoryLayerStore::new();
+        local.create_named_directory(base).await.unwrap();
+        let store = RemoteLayerStore::new(local.clone(), url);
+        assert!(store.pull(base).await.unwrap());
+        assert_eq!(2, requests.load(Ordering::SeqCst));
+
+        let layer = store.get_layer(base).await.unwrap().unwrap();
+        assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+    }
+}
@@ -0,0 +1,216 @@
+//! Scratch files for data that is too big to keep in memory.
+//!
+//! A scratch file lives in a temporary directory, and is removed as
+//! soon as the last handle to it is dropped, unless it was kept.
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+#[cfg(feature = "fs")]
+use async_trait::async_trait;
+#[cfg(feature = "fs")]
+use bytes::Bytes;
+#[cfg(feature = "fs")]
+use tokio::fs::File;
+
+#[cfg(feature = "fs")]
+use super::directory::{Durability, FileBackedStore, FileBackedStoreWriter};
+#[cfg(feature = "fs")]
+use super::file::*;
+#[cfg(feature = "fs")]
+use super::metrics::Metered;
+
+/// Configuration for spilling data from layer builders to scratch files.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpillConfig {
+    /// The amount of triples a builder may keep in memory before they are spilled to disk.
+    pub threshold: usize,
+    /// The directory to create scratch files in. If not set, the system temporary directory is used.
+    pub directory: Option<PathBuf>,
+}
+
+impl SpillConfig {
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            directory: None,
+        }
+    }
+
+    pub fn in_directory<P: Into<PathBuf>>(mut self, directory: P) -> Self {
+        self.directory = Some(directory.into());
+        self
+    }
+
+    /// Create a new scratch file as specified by this configuration.
+    pub fn scratch_file(&self) -> io::Result<ScratchFile> {
+        match &self.directory {
+            Some(directory) => ScratchFile::new_in(directory),
+            None => ScratchFile::new(),
+        }
+    }
+}
+
+struct ScratchPath {
+    path: PathBuf,
+    keep: AtomicBool,
+}
+
+impl Drop for ScratchPath {
+    fn drop(&mut self) {
+        if !self.keep.load(Ordering::Relaxed) {
+            // nothing sensible can be done if this fails
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// A temporary file which is removed once the last handle to it is dropped.
+#[derive(Clone)]
+pub struct ScratchFile {
+    path: Arc<ScratchPath>,
+    #[cfg(feature = "fs")]
+    file: FileBackedStore,
+}
+
+impl ScratchFile {
+    /// Create an empty scratch file in the system temporary directory.
+    pub fn new() -> io::Result<Self> {
+        Self::new_in(std::env::temp_dir())
+    }
+
+    /// Create an empty scratch file in the given directory.
+    pub fn new_in<P: AsRef<Path>>(directory: P) -> io::Result<Self> {
+        loop {
+            let name: u64 = rand::random();
+            let path = directory
+                .as_ref()
+                .join(format!("terminus-store-scratch-{:016x}", name));
+            match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(_) => return Ok(Self::from_path(path, false)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Open an existing scratch file that was kept earlier.
+    ///
+    /// The file stays kept, so it is not removed when dropped.
+    pub fn open_kept<P: Into<PathBuf>>(path: P) -> io::Result<Self> {
+        let path = path.into();
+        if !path.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("scratch file {} not found", path.display()),
+            ));
+        }
+
+        Ok(Self::from_path(path, true))
+    }
+
+    fn from_path(path: PathBuf, keep: bool) -> Self {
+        Self {
+            // scratch files do not need to survive a crash
+            #[cfg(feature = "fs")]
+            file: FileBackedStore::new(path.clone()).with_durability(Durability::None),
+            path: Arc::new(ScratchPath {
+                path,
+                keep: AtomicBool::new(keep),
+            }),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path.path
+    }
+
+    /// Move this file to the given directory and make sure it is durably stored there.
+    ///
+    /// The returned file is kept, so it is not removed when
+    /// dropped. Whoever keeps a scratch file is responsible for
+    /// removing it later. This handle should not be used anymore.
+    pub fn keep_in<P: AsRef<Path>>(&self, directory: P) -> io::Result<Self> {
+        let file_name = self.path().file_name().expect("scratch file has a name");
+        let path = directory.as_ref().join(file_name);
+        if std::fs::rename(self.path(), &path).is_err() {
+            // the directory may be on another file system. The
+            // original is removed when this handle is dropped.
+            std::fs::copy(self.path(), &path)?;
+        }
+        std::fs::File::open(&path)?.sync_all()?;
+
+        Ok(Self::from_path(path, true))
+    }
+
+    /// Returns true if this file will not be removed when dropped.
+    pub fn is_kept(&self) -> bool {
+        self.path.keep.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(feature = "fs")]
+#[async_trait]
+impl FileLoad for ScratchFile {
+    type Read = Metered<File>;
+
+    async fn exists(&self) -> io::Result<bool> {
+        self.file.exists().await
+    }
+
+    async fn size(&self) -> io::Result<usize> {
+        self.file.size().await
+    }
+
+    async fn open_read_from(&self, offset: usize) -> io::Result<Metered<File>> {
+        self.file.open_read_from(offset).await
+    }
+
+    async fn map(&self) -> io::Result<Bytes> {
+        self.file.map().await
+    }
+
+    async fn map_range(&self, offset: usize, len: usize) -> io::Result<Bytes> {
+        self.file.map_range(offset, len).await
+    }
+}
+
+#[cfg(feature = "fs")]
+#[async_trait]
+impl FileStore for ScratchFile {
+    type Write = FileBackedStoreWriter;
+
+    async fn open_write(&self) -> io::Result<FileBackedStoreWriter> {
+        self.file.open_write().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn scratch_file_is_removed_on_drop() {
+        let dir = tempdir().unwrap();
+        let file = ScratchFile::new_in(dir.path()).unwrap();
+        let path = file.path().to_path_buf();
+
+        let mut w = file.open_write().await.unwrap();
+        w.write_all(&[1, 2, 3]).await.unwrap();
+        w.flush().await.unwrap();
+        assert_eq!(&[1, 2, 3][..], &file.map().await.unwrap()[..]);
+
+        let clone = file.clone();
+        std::mem::drop(file);
+        assert!(path.exists());
+        std::mem::drop(clone);
+        assert!(!path.exists());
+    }
+}
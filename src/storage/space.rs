@@ -0,0 +1,195 @@
+//! Checks for available disk space.
+//!
+//! Running out of disk space halfway through writing a layer leaves
+//! a partially written layer behind. To prevent this, a layer
+//! builder can estimate how much space its files are going to take,
+//! and check if that space is available before it writes anything.
+use std::io;
+#[cfg(feature = "fs")]
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use thiserror::Error;
+#[cfg(feature = "fs")]
+use tokio::fs;
+
+/// An error indicating that a layer will not fit.
+///
+/// This is returned wrapped in an `io::Error`, with kind
+/// `io::ErrorKind::StorageFull` or
+/// `io::ErrorKind::QuotaExceeded` respectively. Use
+/// `SpaceError::from_io_error` to get at it.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum SpaceError {
+    #[error("not enough free space: {required} bytes required, but only {available} available")]
+    InsufficientSpace { required: u64, available: u64 },
+    #[error("quota exceeded: {required} bytes required, but {used} of {quota} bytes are in use")]
+    QuotaExceeded {
+        required: u64,
+        used: u64,
+        quota: u64,
+    },
+}
+
+impl SpaceError {
+    /// Retrieve the space error from an `io::Error`, if this io error was caused by one.
+    pub fn from_io_error(error: &io::Error) -> Option<&SpaceError> {
+        error.get_ref().and_then(|e| e.downcast_ref::<SpaceError>())
+    }
+}
+
+impl From<SpaceError> for io::Error {
+    fn from(error: SpaceError) -> io::Error {
+        let kind = match error {
+            SpaceError::InsufficientSpace { .. } => io::ErrorKind::StorageFull,
+            SpaceError::QuotaExceeded { .. } => io::ErrorKind::QuotaExceeded,
+        };
+
+        io::Error::new(kind, error)
+    }
+}
+
+/// A check that is run before a layer builder writes its files.
+#[async_trait]
+pub trait SpaceCheck: 'static + Send + Sync {
+    /// Check that the given amount of bytes can be written, returning a `SpaceError` if not.
+    async fn check(&self, required: u64) -> io::Result<()>;
+}
+
+/// Estimate how much space the files of a layer are going to take.
+///
+/// This is a rough upper bound, based on the size of the new
+/// dictionary entries and the amount of triples in the layer.
+pub fn estimate_layer_size(string_bytes: usize, string_count: usize, triple_count: usize) -> u64 {
+    // every layer consists of a few dozen small files
+    const FIXED_OVERHEAD: u64 = 64 * 1024;
+    // dictionary entries are front-coded and have their length prefixed
+    const STRING_OVERHEAD: u64 = 16;
+    // triples are stored in several indexes, none of which take more than a few words per triple
+    const TRIPLE_SIZE: u64 = 64;
+
+    FIXED_OVERHEAD
+        + string_bytes as u64
+        + string_count as u64 * STRING_OVERHEAD
+        + triple_count as u64 * TRIPLE_SIZE
+}
+
+/// Checks free space on the filesystem of a directory, and optionally a quota on the directory itself.
+///
+/// This requires the `fs` feature.
+#[cfg(feature = "fs")]
+#[derive(Clone, Debug)]
+pub struct DiskSpaceCheck {
+    path: PathBuf,
+    quota: Option<u64>,
+}
+
+#[cfg(feature = "fs")]
+impl DiskSpaceCheck {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            quota: None,
+        }
+    }
+
+    /// Also refuse to grow the directory beyond the given amount of bytes.
+    ///
+    /// Checking the quota requires walking the entire directory to
+    /// determine its current size.
+    pub fn with_quota(mut self, quota: u64) -> Self {
+        self.quota = Some(quota);
+        self
+    }
+
+    pub fn quota(&self) -> Option<u64> {
+        self.quota
+    }
+}
+
+#[cfg(feature = "fs")]
+async fn directory_size(path: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    let mut to_visit = vec![path.to_path_buf()];
+    while let Some(dir) = to_visit.pop() {
+        let mut entries = match fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if metadata.is_dir() {
+                to_visit.push(entry.path());
+            } else {
+                size += metadata.len();
+            }
+        }
+    }
+
+    Ok(size)
+}
+
+#[cfg(feature = "fs")]
+#[async_trait]
+impl SpaceCheck for DiskSpaceCheck {
+    async fn check(&self, required: u64) -> io::Result<()> {
+        let path = self.path.clone();
+        let available = tokio::task::spawn_blocking(move || fs2::available_space(path))
+            .await
+            .map_err(io::Error::other)??;
+        if available < required {
+            return Err(SpaceError::InsufficientSpace {
+                required,
+                available,
+            }
+            .into());
+        }
+
+        if let Some(quota) = self.quota {
+            let used = directory_size(&self.path).await?;
+            if used.saturating_add(required) > quota {
+                return Err(SpaceError::QuotaExceeded {
+                    required,
+                    used,
+                    quota,
+                }
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn quota_is_checked() {
+        let dir = tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("sub").join("file"), [0; 1000]).unwrap();
+
+        let check = DiskSpaceCheck::new(dir.path()).with_quota(1500);
+        check.check(500).await.unwrap();
+        let error = check.check(501).await.unwrap_err();
+        assert_eq!(io::ErrorKind::QuotaExceeded, error.kind());
+        assert_eq!(
+            Some(&SpaceError::QuotaExceeded {
+                required: 501,
+                used: 1000,
+                quota: 1500
+            }),
+            SpaceError::from_io_error(&error)
+        );
+
+        let error = DiskSpaceCheck::new(dir.path())
+            .check(u64::MAX)
+            .await
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::StorageFull, error.kind());
+    }
+}
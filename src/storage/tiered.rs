@@ -0,0 +1,345 @@
+//! Layer storage split over a hot and a cold tier.
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures::future::Future;
+
+use super::layer::*;
+use super::scratch::SpillConfig;
+use super::space::SpaceCheck;
+use crate::layer::{Parallelism, RemovalValidation};
+
+/// A layer store that keeps recent layers in a fast hot store, and older layers in a cheaper cold store.
+///
+/// New layers are always created in the hot store. Layers can be
+/// moved between the two stores with `demote` and `promote`. Reads
+/// go through the hot store: when a file is requested from a layer
+/// that only exists in the cold store, the layer is promoted first.
+///
+/// Loading a layer requires loading all its ancestors, unless the
+/// layer has been rolled up. Demoting the ancestors of a rolled up
+/// layer therefore keeps them out of the hot store until they are
+/// explicitly requested.
+///
+/// The cold store needs to support `PersistentLayerStore::rename_directory`,
+/// as layers are moved in a way that makes them appear completely or
+/// not at all.
+#[derive(Clone)]
+pub struct TieredLayerStore<H: PersistentLayerStore, C: PersistentLayerStore> {
+    hot: H,
+    cold: C,
+    promote_lock: futures_locks::Mutex<()>,
+}
+
+impl<H: PersistentLayerStore, C: PersistentLayerStore> TieredLayerStore<H, C> {
+    pub fn new(hot: H, cold: C) -> Self {
+        Self {
+            hot,
+            cold,
+            promote_lock: futures_locks::Mutex::new(()),
+        }
+    }
+
+    pub fn hot(&self) -> &H {
+        &self.hot
+    }
+
+    pub fn cold(&self) -> &C {
+        &self.cold
+    }
+
+    /// Returns true if the given layer is in the hot store.
+    pub async fn is_hot(&self, name: [u32; 5]) -> io::Result<bool> {
+        self.hot.directory_exists(name).await
+    }
+
+    /// Copy the given layer from the cold store into the hot store.
+    ///
+    /// Returns false if the layer doesn't exist in either store.
+    pub async fn promote(&self, name: [u32; 5]) -> io::Result<bool> {
+        if self.hot.directory_exists(name).await? {
+            return Ok(true);
+        }
+
+        let _guard = self.promote_lock.lock().await;
+        if self.hot.directory_exists(name).await? {
+            return Ok(true);
+        }
+        if !self.cold.directory_exists(name).await? {
+            return Ok(false);
+        }
+
+        copy_layer_atomically(&self.cold, &self.hot, name).await?;
+
+        Ok(true)
+    }
+
+    /// Move the given layer from the hot store to the cold store.
+    ///
+    /// Returns false if the layer doesn't exist in either store.
+    pub async fn demote(&self, name: [u32; 5]) -> io::Result<bool> {
+        let _guard = self.promote_lock.lock().await;
+        if !self.hot.directory_exists(name).await? {
+            return self.cold.directory_exists(name).await;
+        }
+
+        if !self.cold.directory_exists(name).await? {
+            copy_layer_atomically(&self.hot, &self.cold, name).await?;
+        }
+        self.hot.remove_directory(name).await?;
+
+        Ok(true)
+    }
+
+    /// Demote all ancestors of the given layer, except for the given amount of most recent ones.
+    ///
+    /// Returns the amount of layers that were demoted.
+    pub async fn demote_ancestors(&self, name: [u32; 5], keep: usize) -> io::Result<usize> {
+        // walk the stores directly, as reading through this store would promote the ancestors
+        let mut stack = Vec::new();
+        let mut current = name;
+        loop {
+            let parent = if self.hot.directory_exists(current).await? {
+                if !self.hot.layer_has_parent(current).await? {
+                    break;
+                }
+                self.hot.read_parent_file(current).await?
+            } else {
+                if !self.cold.layer_has_parent(current).await? {
+                    break;
+                }
+                self.cold.read_parent_file(current).await?
+            };
+            stack.push(parent);
+            current = parent;
+        }
+
+        // the stack starts at the most recent ancestor
+        let stack = stack.into_iter().skip(keep);
+
+        let mut demoted = 0;
+        for layer in stack {
+            if self.hot.directory_exists(layer).await? {
+                self.demote(layer).await?;
+                demoted += 1;
+            }
+        }
+
+        Ok(demoted)
+    }
+}
+
+impl<H: PersistentLayerStore, C: PersistentLayerStore> PersistentLayerStore
+    for TieredLayerStore<H, C>
+{
+    type File = H::File;
+
+    fn directories(&self) -> Pin<Box<dyn Future<Output = io::Result<Vec<[u32; 5]>>> + Send>> {
+        let hot = self.hot.directories();
+        let cold = self.cold.directories();
+        Box::pin(async move {
+            let mut result = hot.await?;
+            result.extend(cold.await?);
+            result.sort();
+            result.dedup();
+
+            Ok(result)
+        })
+    }
+
+    fn create_named_directory(
+        &self,
+        id: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        self.hot.create_named_directory(id)
+    }
+
+    fn directory_exists(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>> {
+        let hot = self.hot.directory_exists(name);
+        let cold = self.cold.directory_exists(name);
+        Box::pin(async move { Ok(hot.await? || cold.await?) })
+    }
+
+    fn get_file(
+        &self,
+        directory: [u32; 5],
+        name: &str,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Self::File>> + Send>> {
+        let self_ = self.clone();
+        let name = name.to_owned();
+        Box::pin(async move {
+            self_.promote(directory).await?;
+            self_.hot.get_file(directory, &name).await
+        })
+    }
+
+    fn file_exists(
+        &self,
+        directory: [u32; 5],
+        file: &str,
+    ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>> {
+        let self_ = self.clone();
+        let file = file.to_owned();
+        Box::pin(async move {
+            if self_.hot.directory_exists(directory).await? {
+                self_.hot.file_exists(directory, &file).await
+            } else {
+                // no need to promote just to check for existence
+                self_.cold.file_exists(directory, &file).await
+            }
+        })
+    }
+
+    fn spill_config(&self) -> Option<SpillConfig> {
+        self.hot.spill_config()
+    }
+
+    fn space_check(&self) -> Option<Arc<dyn SpaceCheck>> {
+        self.hot.space_check()
+    }
+
+    fn parallelism(&self) -> Option<Parallelism> {
+        self.hot.parallelism()
+    }
+
+    fn removal_validation(&self) -> RemovalValidation {
+        self.hot.removal_validation()
+    }
+
+    fn text_index(&self) -> bool {
+        self.hot.text_index()
+    }
+
+    fn checkpoint_directory(&self, name: [u32; 5]) -> Option<PathBuf> {
+        self.hot.checkpoint_directory(name)
+    }
+
+    fn content_addressed(&self) -> bool {
+        self.hot.content_addressed()
+    }
+
+    fn sync_directory(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        self.hot.sync_directory(name)
+    }
+
+    fn remove_directory(
+        &self,
+        name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            let mut found = false;
+            if self_.hot.directory_exists(name).await? {
+                self_.hot.remove_directory(name).await?;
+                found = true;
+            }
+            if self_.cold.directory_exists(name).await? {
+                self_.cold.remove_directory(name).await?;
+                found = true;
+            }
+
+            if found {
+                Ok(())
+            } else {
+                Err(io::Error::new(io::ErrorKind::NotFound, "layer not found"))
+            }
+        })
+    }
+
+    fn rename_directory(
+        &self,
+        from: [u32; 5],
+        to: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        // only ever called on freshly committed layers, which are hot
+        self.hot.rename_directory(from, to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::*;
+    use crate::storage::directory::DirectoryLayerStore;
+    use crate::storage::memory::MemoryLayerStore;
+    use tempfile::tempdir;
+
+    async fn create_stack<S: LayerStore>(store: &S, count: usize) -> Vec<[u32; 5]> {
+        let mut result = Vec::new();
+        for i in 0..count {
+            let mut builder = match result.last() {
+                None => store.create_base_layer().await.unwrap(),
+                Some(parent) => store.create_child_layer(*parent).await.unwrap(),
+            };
+            result.push(builder.name());
+            builder.add_string_triple(StringTriple::new_value("cow", "says", &i.to_string()));
+            builder.commit_boxed().await.unwrap();
+        }
+
+        result
+    }
+
+    #[tokio::test]
+    async fn demote_and_promote_layers() {
+        let dir = tempdir().unwrap();
+        let hot = MemoryLayerStore::new();
+        let cold = DirectoryLayerStore::new(dir.path());
+        let store = TieredLayerStore::new(hot.clone(), cold.clone());
+
+        let stack = create_stack(&store, 4).await;
+        assert_eq!(2, store.demote_ancestors(stack[3], 1).await.unwrap());
+        assert!(!store.is_hot(stack[0]).await.unwrap());
+        assert!(!store.is_hot(stack[1]).await.unwrap());
+        assert!(store.is_hot(stack[2]).await.unwrap());
+        assert!(store.is_hot(stack[3]).await.unwrap());
+        assert_eq!(2, cold.directories().await.unwrap().len());
+
+        let mut layers = store.layers().await.unwrap();
+        layers.sort();
+        let mut expected = stack.clone();
+        expected.sort();
+        assert_eq!(expected, layers);
+
+        // reading a layer brings its ancestors back into the hot store
+        let layer = store.get_layer(stack[3]).await.unwrap().unwrap();
+        assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "0")));
+        assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "3")));
+        assert!(store.is_hot(stack[0]).await.unwrap());
+        assert_eq!(4, hot.directories().await.unwrap().len());
+
+        // demoting a layer already in the cold store just drops the hot copy
+        assert!(store.demote(stack[0]).await.unwrap());
+        assert_eq!(3, hot.directories().await.unwrap().len());
+        assert!(store.promote(stack[0]).await.unwrap());
+        assert_eq!(4, hot.directories().await.unwrap().len());
+
+        assert!(!store.promote([1, 2, 3, 4, 5]).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn rolled_up_ancestors_stay_cold() {
+        let dir = tempdir().unwrap();
+        let store = TieredLayerStore::new(
+            MemoryLayerStore::new(),
+            DirectoryLayerStore::new(dir.path()),
+        );
+        let stack = create_stack(&store, 3).await;
+        let layer = store.get_layer(stack[2]).await.unwrap().unwrap();
+        let store = std::sync::Arc::new(store);
+        store.clone().rollup(layer).await.unwrap();
+
+        assert_eq!(2, store.demote_ancestors(stack[2], 0).await.unwrap());
+        let layer = store.get_layer(stack[2]).await.unwrap().unwrap();
+        assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "0")));
+        assert!(!store.is_hot(stack[0]).await.unwrap());
+        assert!(!store.is_hot(stack[1]).await.unwrap());
+    }
+}
@@ -0,0 +1,230 @@
+//! Reading files through io_uring.
+//!
+//! Reads are handed to a single background thread which owns an
+//! io_uring instance. This thread submits all reads it has received
+//! in one go, and hands back the results as they complete. This
+//! saves a round trip through the blocking thread pool for every
+//! read, which is what tokio's fs implementation does. Files are
+//! opened once, and their handle is kept for all later reads.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+
+use bytes::Bytes;
+use io_uring::{opcode, types, IoUring};
+use tokio::sync::{oneshot, OnceCell};
+
+/// The amount of reads that can be in flight at the same time.
+const RING_SIZE: u32 = 256;
+
+struct ReadRequest {
+    file: Arc<File>,
+    offset: u64,
+    buf: Vec<u8>,
+    done: usize,
+    reply: oneshot::Sender<io::Result<Vec<u8>>>,
+}
+
+impl ReadRequest {
+    fn entry(&mut self, id: u64) -> io_uring::squeue::Entry {
+        let remaining = std::cmp::min(self.buf.len() - self.done, u32::MAX as usize);
+        // unsafe justification: the buffer is owned by this request,
+        // which is kept alive in the driver until the read completes.
+        let ptr = unsafe { self.buf.as_mut_ptr().add(self.done) };
+        opcode::Read::new(types::Fd(self.file.as_raw_fd()), ptr, remaining as u32)
+            .offset(self.offset + self.done as u64)
+            .build()
+            .user_data(id)
+    }
+}
+
+lazy_static! {
+    static ref DRIVER: Mutex<Option<mpsc::Sender<ReadRequest>>> = Mutex::new(None);
+}
+
+fn driver() -> io::Result<mpsc::Sender<ReadRequest>> {
+    let mut driver = DRIVER.lock().unwrap();
+    if let Some(sender) = &*driver {
+        return Ok(sender.clone());
+    }
+
+    let ring = IoUring::new(RING_SIZE)?;
+    let (sender, receiver) = mpsc::channel();
+    std::thread::Builder::new()
+        .name("terminus-store-uring".to_string())
+        .spawn(move || drive(ring, receiver))?;
+    *driver = Some(sender.clone());
+
+    Ok(sender)
+}
+
+fn drive(mut ring: IoUring, receiver: mpsc::Receiver<ReadRequest>) {
+    let mut in_flight: HashMap<u64, ReadRequest> = HashMap::new();
+    let mut queued: Vec<ReadRequest> = Vec::new();
+    let mut next_id: u64 = 0;
+    loop {
+        if in_flight.is_empty() && queued.is_empty() {
+            match receiver.recv() {
+                Ok(request) => queued.push(request),
+                // all senders are gone, so nothing will ever be read again
+                Err(_) => return,
+            }
+        }
+        queued.extend(receiver.try_iter());
+
+        while in_flight.len() < RING_SIZE as usize {
+            let mut request = match queued.pop() {
+                Some(request) => request,
+                None => break,
+            };
+            let id = next_id;
+            next_id = next_id.wrapping_add(1);
+            let entry = request.entry(id);
+            // unsafe justification: the entry refers to a buffer
+            // owned by the request, which we keep until completion.
+            if unsafe { ring.submission().push(&entry) }.is_err() {
+                queued.push(request);
+                break;
+            }
+            in_flight.insert(id, request);
+        }
+
+        if let Err(e) = ring.submit_and_wait(1) {
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            // the ring is unusable. Fail everything and stop, so
+            // that the next read starts a fresh driver.
+            *DRIVER.lock().unwrap() = None;
+            for (_, request) in in_flight.drain() {
+                let _ = request
+                    .reply
+                    .send(Err(io::Error::new(e.kind(), e.to_string())));
+            }
+            for request in queued.drain(..) {
+                let _ = request
+                    .reply
+                    .send(Err(io::Error::new(e.kind(), e.to_string())));
+            }
+            return;
+        }
+
+        let completed: Vec<_> = ring
+            .completion()
+            .map(|cqe| (cqe.user_data(), cqe.result()))
+            .collect();
+        for (id, result) in completed {
+            let mut request = in_flight.remove(&id).expect("unknown read completed");
+            if result < 0 {
+                let error = io::Error::from_raw_os_error(-result);
+                if error.kind() == io::ErrorKind::Interrupted {
+                    queued.push(request);
+                } else {
+                    let _ = request.reply.send(Err(error));
+                }
+            } else if result == 0 {
+                let _ = request.reply.send(Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "file ended before the requested range was read",
+                )));
+            } else {
+                request.done += result as usize;
+                if request.done == request.buf.len() {
+                    let _ = request.reply.send(Ok(request.buf));
+                } else {
+                    // short read, read the rest
+                    queued.push(request);
+                }
+            }
+        }
+    }
+}
+
+/// A file that is read through io_uring.
+///
+/// The file is opened on the first read, and the handle is shared by
+/// all clones.
+#[derive(Clone)]
+pub struct UringFile {
+    path: PathBuf,
+    file: Arc<OnceCell<Arc<File>>>,
+}
+
+impl UringFile {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            file: Default::default(),
+        }
+    }
+
+    /// Returns true if the file has been opened already.
+    pub fn is_open(&self) -> bool {
+        self.file.initialized()
+    }
+
+    async fn file(&self) -> io::Result<Arc<File>> {
+        self.file
+            .get_or_try_init(|| async {
+                let file = tokio::fs::File::open(&self.path).await?;
+
+                Ok(Arc::new(file.into_std().await))
+            })
+            .await
+            .cloned()
+    }
+
+    /// Read the given range of the file.
+    pub async fn read_range(&self, offset: usize, len: usize) -> io::Result<Bytes> {
+        if len == 0 {
+            return Ok(Bytes::new());
+        }
+
+        let (reply, result) = oneshot::channel();
+        let request = ReadRequest {
+            file: self.file().await?,
+            offset: offset as u64,
+            buf: vec![0; len],
+            done: 0,
+            reply,
+        };
+        driver()?
+            .send(request)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "io_uring driver stopped"))?;
+
+        let buf = result
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "io_uring driver stopped"))??;
+
+        Ok(Bytes::from(buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn read_ranges_through_uring() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("file");
+        let contents: Vec<u8> = (0..10000).map(|i| i as u8).collect();
+        std::fs::write(&path, &contents).unwrap();
+
+        let file = UringFile::new(path);
+        assert!(!file.is_open());
+        let reads: Vec<_> = (0..100).map(|i| file.read_range(i * 100, 100)).collect();
+        let results = futures::future::join_all(reads).await;
+        for (i, result) in results.into_iter().enumerate() {
+            assert_eq!(&contents[i * 100..(i + 1) * 100], &result.unwrap()[..]);
+        }
+        assert!(file.is_open());
+
+        let err = file.read_range(9990, 20).await.unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+    }
+}
@@ -0,0 +1,249 @@
+//! Configuration for opening a store on a directory.
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use super::Store;
+use crate::layer::Parallelism;
+use crate::storage::directory::{
+    DirectoryLabelStore, DirectoryLayerStore, DirectoryLayout, Durability,
+};
+use crate::storage::{
+    CachedLayerStore, LockPolicy, LockingHashMapLayerCache, NoCache, SpillConfig, StorageMetrics,
+};
+
+/// A builder for a store on a directory.
+///
+/// `open_directory_store` and `open_read_only_directory_store` open
+/// a store with the default configuration. This allows changing any
+/// part of that configuration before opening the store:
+///
+/// ```no_run
+/// # use terminus_store::store::DirectoryStoreConfig;
+/// # use terminus_store::storage::LockPolicy;
+/// # use terminus_store::storage::directory::Durability;
+/// # use std::time::Duration;
+/// let store = DirectoryStoreConfig::new("/path/to/store")
+///     .with_durability(Durability::Directory)
+///     .with_lock_policy(LockPolicy::Timeout(Duration::from_secs(5)))
+///     .open();
+/// ```
+///
+/// Use `SyncStore::wrap` on the result to get a synchronous store.
+#[derive(Clone)]
+pub struct DirectoryStoreConfig {
+    path: PathBuf,
+    read_only: bool,
+    cache: bool,
+    layout: DirectoryLayout,
+    durability: Durability,
+    lock_policy: LockPolicy,
+    spill: Option<SpillConfig>,
+    parallelism: Option<Parallelism>,
+    quota: Option<u64>,
+    content_addressed: bool,
+    text_index: bool,
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    io_uring: bool,
+    metrics: Option<Arc<dyn StorageMetrics>>,
+}
+
+impl DirectoryStoreConfig {
+    /// The default configuration for a store in the given directory.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            read_only: false,
+            cache: true,
+            layout: DirectoryLayout::default(),
+            durability: Durability::default(),
+            lock_policy: LockPolicy::default(),
+            spill: None,
+            parallelism: None,
+            quota: None,
+            content_addressed: false,
+            text_index: false,
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            io_uring: false,
+            metrics: None,
+        }
+    }
+
+    /// Never write to the directory.
+    ///
+    /// See `open_read_only_directory_store` for details.
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    /// Load layers from disk every time they are retrieved, rather than sharing layers that are still in use.
+    pub fn without_cache(mut self) -> Self {
+        self.cache = false;
+        self
+    }
+
+    /// See `DirectoryLayerStore::with_layout`.
+    pub fn with_layout(mut self, layout: DirectoryLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// See `DirectoryLayerStore::with_durability`.
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    /// See `DirectoryLabelStore::with_lock_policy`.
+    pub fn with_lock_policy(mut self, policy: LockPolicy) -> Self {
+        self.lock_policy = policy;
+        self
+    }
+
+    /// See `DirectoryLayerStore::with_spill`.
+    pub fn with_spill(mut self, config: SpillConfig) -> Self {
+        self.spill = Some(config);
+        self
+    }
+
+    /// See `DirectoryLayerStore::with_parallelism`.
+    pub fn with_parallelism(mut self, parallelism: Parallelism) -> Self {
+        self.parallelism = Some(parallelism);
+        self
+    }
+
+    /// See `DirectoryLayerStore::with_quota`.
+    pub fn with_quota(mut self, quota: u64) -> Self {
+        self.quota = Some(quota);
+        self
+    }
+
+    /// See `DirectoryLayerStore::with_content_addressing`.
+    pub fn with_content_addressing(mut self) -> Self {
+        self.content_addressed = true;
+        self
+    }
+
+    /// See `DirectoryLayerStore::with_text_index`.
+    pub fn with_text_index(mut self) -> Self {
+        self.text_index = true;
+        self
+    }
+
+    /// Read layer files through io_uring rather than through regular file reads.
+    ///
+    /// See `FileBackedStore::with_io_uring`.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    pub fn with_io_uring(mut self) -> Self {
+        self.io_uring = true;
+        self
+    }
+
+    /// Report file IO, as well as cache hits and misses, to the given metrics.
+    pub fn with_metrics(mut self, metrics: Arc<dyn StorageMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Open the store.
+    ///
+    /// Like `open_directory_store`, this does not touch the
+    /// directory yet. Errors are reported by the first operation on
+    /// the store instead.
+    pub fn open(self) -> Store {
+        let (label_store, mut layer_store) = if self.read_only {
+            (
+                DirectoryLabelStore::new_read_only(self.path.clone()),
+                DirectoryLayerStore::new_read_only(self.path),
+            )
+        } else {
+            (
+                DirectoryLabelStore::new(self.path.clone()),
+                DirectoryLayerStore::new(self.path),
+            )
+        };
+        let label_store = label_store.with_lock_policy(self.lock_policy);
+
+        layer_store = layer_store
+            .with_layout(self.layout)
+            .with_durability(self.durability);
+        if let Some(spill) = self.spill {
+            layer_store = layer_store.with_spill(spill);
+        }
+        if let Some(parallelism) = self.parallelism {
+            layer_store = layer_store.with_parallelism(parallelism);
+        }
+        if let Some(quota) = self.quota {
+            layer_store = layer_store.with_quota(quota);
+        }
+        if self.content_addressed {
+            layer_store = layer_store.with_content_addressing();
+        }
+        if self.text_index {
+            layer_store = layer_store.with_text_index();
+        }
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        if self.io_uring {
+            layer_store = layer_store.with_io_uring();
+        }
+        if let Some(metrics) = &self.metrics {
+            layer_store = layer_store.with_metrics(metrics.clone());
+        }
+
+        if !self.cache {
+            return Store::new(label_store, CachedLayerStore::new(layer_store, NoCache));
+        }
+        let mut cache = LockingHashMapLayerCache::new();
+        if let Some(metrics) = self.metrics {
+            cache = cache.with_metrics(metrics);
+        }
+
+        Store::new(label_store, CachedLayerStore::new(layer_store, cache))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::{Layer, StringTriple};
+    use std::io;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn open_configured_directory_store() {
+        let dir = tempdir().unwrap();
+        let store = DirectoryStoreConfig::new(dir.path())
+            .with_layout(DirectoryLayout::Flat)
+            .with_durability(Durability::None)
+            .with_lock_policy(LockPolicy::NonBlocking)
+            .without_cache()
+            .open();
+        let graph = store.create("foo").await.unwrap();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer = builder.commit().await.unwrap();
+        graph.set_head(&layer).await.unwrap();
+
+        // the flat layout puts the layer directly in the store directory
+        let layer_dir = dir
+            .path()
+            .join(crate::storage::name_to_string(layer.name()));
+        assert!(layer_dir.is_dir());
+
+        let store = DirectoryStoreConfig::new(dir.path())
+            .with_layout(DirectoryLayout::Flat)
+            .read_only()
+            .open();
+        let head = store.open("foo").await.unwrap().unwrap().head().await;
+        assert!(head
+            .unwrap()
+            .unwrap()
+            .string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        assert_eq!(
+            io::ErrorKind::PermissionDenied,
+            store.create("bar").await.err().unwrap().kind()
+        );
+    }
+}
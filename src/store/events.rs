@@ -0,0 +1,198 @@
+//! Events emitted by a store after it changed the head of a graph.
+//!
+//! Downstream consumers such as indexers or caches can subscribe to
+//! these with `Store::subscribe` instead of polling labels. Events
+//! are only emitted for head changes made through the `Store` they
+//! were subscribed to, or one of its clones. Changes made by other
+//! processes can be followed with `NamedGraph::watch` instead.
+use std::pin::Pin;
+
+use futures::stream::{self, Stream};
+use thiserror::Error;
+use tokio::sync::broadcast;
+
+use super::{PatchCounts, Store};
+
+/// The amount of commit events a slow subscriber may fall behind before it starts missing events.
+pub(crate) const COMMIT_EVENT_CAPACITY: usize = 256;
+
+/// A change of the head of a graph, as emitted by a store after a successful commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitEvent {
+    /// The name of the graph.
+    pub graph: String,
+    /// The head before the change.
+    pub old_head: Option<[u32; 5]>,
+    /// The head after the change.
+    pub new_head: Option<[u32; 5]>,
+    /// The amount of triples added and removed by the layers between the old and the new head.
+    ///
+    /// These are summed over all layers from the old head up to the
+    /// new one, so a triple that was added in one of these layers and
+    /// removed in another is counted in both. This is None if the old
+    /// head is not an ancestor of the new one, as with a reset.
+    pub counts: Option<PatchCounts>,
+}
+
+/// A subscriber fell behind, and missed the given amount of commit events.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("{0} commit events were missed")]
+pub struct MissedCommitEvents(pub u64);
+
+/// A stream of commit events, as returned by `Store::subscribe`.
+pub type CommitEventStream =
+    Pin<Box<dyn Stream<Item = Result<CommitEvent, MissedCommitEvents>> + Send>>;
+
+impl Store {
+    /// Subscribe to the commit events of this store.
+    ///
+    /// The returned stream yields an event for every head change
+    /// made through this store after this call was made. A
+    /// subscriber that falls too far behind gets a
+    /// `MissedCommitEvents` error, after which it continues with the
+    /// oldest event that is still available.
+    pub fn subscribe(&self) -> CommitEventStream {
+        let receiver = self.commit_events.subscribe();
+        Box::pin(stream::unfold(receiver, |mut receiver| async move {
+            match receiver.recv().await {
+                Ok(event) => Some((Ok(event), receiver)),
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    Some((Err(MissedCommitEvents(missed)), receiver))
+                }
+                Err(broadcast::error::RecvError::Closed) => None,
+            }
+        }))
+    }
+
+    /// Emit a commit event for a head change that was just made.
+    pub(crate) async fn notify_commit(
+        &self,
+        graph: &str,
+        old_head: Option<[u32; 5]>,
+        new_head: Option<[u32; 5]>,
+    ) {
+        if self.commit_events.receiver_count() == 0 || old_head == new_head {
+            return;
+        }
+
+        // the commit already happened, so failing to count its
+        // changes should not fail it.
+        let counts = self.commit_counts(old_head, new_head).await.unwrap_or(None);
+        // an error here just means all subscribers went away
+        let _ = self.commit_events.send(CommitEvent {
+            graph: graph.to_owned(),
+            old_head,
+            new_head,
+            counts,
+        });
+    }
+
+    async fn commit_counts(
+        &self,
+        old_head: Option<[u32; 5]>,
+        new_head: Option<[u32; 5]>,
+    ) -> std::io::Result<Option<PatchCounts>> {
+        let new_head = match new_head {
+            None => return Ok(None),
+            Some(new_head) => new_head,
+        };
+        let names = self
+            .layer_store
+            .retrieve_layer_stack_names(new_head)
+            .await?;
+        let start = match old_head {
+            None => 0,
+            Some(old_head) => match names.iter().position(|&name| name == old_head) {
+                None => return Ok(None),
+                Some(pos) => pos + 1,
+            },
+        };
+
+        let mut counts = PatchCounts::default();
+        for &name in &names[start..] {
+            counts.additions += self.layer_store.triple_layer_addition_count(name).await?;
+            counts.removals += self.layer_store.triple_layer_removal_count(name).await?;
+        }
+
+        Ok(Some(counts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::{Layer, StringTriple};
+    use crate::store::open_memory_store;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn subscribe_to_commits() {
+        let store = open_memory_store();
+        let graph = store.create("foo").await.unwrap();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+        let builder = base.open_write().await.unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let child = builder.commit().await.unwrap();
+
+        let mut events = store.subscribe();
+        graph.set_head(&base).await.unwrap();
+        graph.set_head_if(Some(&base), &child).await.unwrap();
+        store.reset("foo", base.name(), None).await.unwrap();
+
+        assert_eq!(
+            CommitEvent {
+                graph: "foo".to_string(),
+                old_head: None,
+                new_head: Some(base.name()),
+                counts: Some(PatchCounts {
+                    additions: 2,
+                    removals: 0
+                }),
+            },
+            events.next().await.unwrap().unwrap()
+        );
+        assert_eq!(
+            Some(PatchCounts {
+                additions: 0,
+                removals: 1
+            }),
+            events.next().await.unwrap().unwrap().counts
+        );
+        let reset = events.next().await.unwrap().unwrap();
+        assert_eq!(
+            (Some(child.name()), Some(base.name()), None),
+            (reset.old_head, reset.new_head, reset.counts)
+        );
+    }
+
+    #[tokio::test]
+    async fn slow_subscribers_miss_events() {
+        let store = open_memory_store();
+        let graph = store.create("foo").await.unwrap();
+        let mut events = store.subscribe();
+        let mut layer = store
+            .create_base_layer()
+            .await
+            .unwrap()
+            .commit()
+            .await
+            .unwrap();
+        graph.set_head(&layer).await.unwrap();
+        for _ in 0..COMMIT_EVENT_CAPACITY {
+            layer = layer.open_write().await.unwrap().commit().await.unwrap();
+            graph.set_head(&layer).await.unwrap();
+        }
+
+        assert_eq!(Err(MissedCommitEvents(1)), events.next().await.unwrap());
+        assert!(events.next().await.unwrap().is_ok());
+    }
+}
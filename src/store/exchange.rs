@@ -0,0 +1,298 @@
+//! Exchanging layers with another store.
+//!
+//! To send layers to another store, the receiving store first lists
+//! the layers it holds with `Store::layer_names`. The sending store
+//! then creates a pack stream of just the layers reachable from its
+//! graphs that the receiver is missing with `Store::create_pack_for`,
+//! which the receiving store reads with `Store::apply_pack`. Heads
+//! are not part of the exchange, so it works for both pushing and
+//! pulling. How the list and the stream are transported is up to
+//! the caller.
+//!
+//! A pack stream consists of:
+//! - a header: the bytes `TSPK`, followed by the protocol version as a single byte.
+//! - a frame for every layer: the byte 1, the layer name, the byte 1
+//!   followed by the name of the parent layer or the byte 0 for a base
+//!   layer, the length of the layer pack as a big-endian u64, the
+//!   layer pack as created by `Packable::export_layers`, and the
+//!   SHA-256 checksum of the layer pack.
+//! - a trailer: the byte 0, followed by the amount of layer frames as a big-endian u64.
+//!
+//! Layer names are written as 5 big-endian u32s. Parents are always
+//! sent before their children.
+//!
+//! Every layer is verified and imported as soon as its frame has
+//! been read. When a transfer is interrupted, the layers received so
+//! far are kept, so listing the layers of the receiver again and
+//! creating a new pack stream resumes the transfer.
+use std::collections::HashSet;
+use std::io;
+use std::pin::Pin;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::stream::{self, Stream, StreamExt};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::Store;
+
+/// The version of the pack stream format written by `Store::create_pack_for`.
+pub const PACK_PROTOCOL_VERSION: u8 = 1;
+
+const PACK_MAGIC: &[u8; 4] = b"TSPK";
+const TRAILER_TAG: u8 = 0;
+const LAYER_TAG: u8 = 1;
+
+/// A stream of the bytes of a pack stream, as returned by `Store::create_pack_for`.
+pub type PackStream = Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>;
+
+fn put_name(buf: &mut BytesMut, name: [u32; 5]) {
+    for part in name {
+        buf.put_u32(part);
+    }
+}
+
+async fn read_name<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<[u32; 5]> {
+    let mut name = [0; 5];
+    for part in name.iter_mut() {
+        *part = reader.read_u32().await?;
+    }
+
+    Ok(name)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Store {
+    /// The names of all layers in this store, to send to a store that is going to create a pack stream for it.
+    pub async fn layer_names(&self) -> io::Result<Vec<[u32; 5]>> {
+        self.layer_store.layers().await
+    }
+
+    /// Create a pack stream of all layers reachable from the graphs of this store that are not in `remote_have`.
+    ///
+    /// The layers to send are determined right away, and exported one
+    /// at a time as the stream is read. See the `exchange` module for
+    /// the format of the stream.
+    pub async fn create_pack_for(&self, remote_have: &[[u32; 5]]) -> io::Result<PackStream> {
+        let labels = self.label_store.labels().await?;
+        let mut seen: HashSet<[u32; 5]> = remote_have.iter().copied().collect();
+        let mut missing = Vec::new();
+        for layer in labels.iter().filter_map(|label| label.layer) {
+            for name in self.layer_store.retrieve_layer_stack_names(layer).await? {
+                if seen.insert(name) {
+                    missing.push(name);
+                }
+            }
+        }
+
+        let count = missing.len() as u64;
+        let mut header = BytesMut::with_capacity(5);
+        header.put_slice(PACK_MAGIC);
+        header.put_u8(PACK_PROTOCOL_VERSION);
+        let mut trailer = BytesMut::with_capacity(9);
+        trailer.put_u8(TRAILER_TAG);
+        trailer.put_u64(count);
+
+        let store = self.clone();
+        let frames = stream::iter(missing).then(move |name| {
+            let store = store.clone();
+            async move { store.layer_frame(name).await }
+        });
+
+        Ok(Box::pin(
+            stream::once(async { Ok(header.freeze()) })
+                .chain(frames)
+                .chain(stream::once(async { Ok(trailer.freeze()) })),
+        ))
+    }
+
+    async fn layer_frame(&self, name: [u32; 5]) -> io::Result<Bytes> {
+        let parent = self.layer_store.get_layer_parent_name(name).await?;
+        let pack = self
+            .layer_store
+            .export_layers(Box::new(std::iter::once(name)))
+            .await?;
+
+        let mut frame = BytesMut::with_capacity(pack.len() + 87);
+        frame.put_u8(LAYER_TAG);
+        put_name(&mut frame, name);
+        match parent {
+            Some(parent) => {
+                frame.put_u8(1);
+                put_name(&mut frame, parent);
+            }
+            None => frame.put_u8(0),
+        }
+        frame.put_u64(pack.len() as u64);
+        frame.put_slice(&pack);
+        frame.put_slice(&Sha256::digest(&pack));
+
+        Ok(frame.freeze())
+    }
+
+    /// Import all layers of a pack stream created by `Store::create_pack_for`, returning the names of the imported layers.
+    ///
+    /// Layers this store already has are skipped. Fails with an
+    /// `InvalidData` error if the stream is corrupt, or if it contains
+    /// a layer whose parent is neither in this store nor earlier in
+    /// the stream, and with an `Unsupported` error if it was written
+    /// with a newer version of the protocol. Layers imported before
+    /// the failure are kept.
+    pub async fn apply_pack<R: AsyncRead + Unpin>(
+        &self,
+        mut reader: R,
+    ) -> io::Result<Vec<[u32; 5]>> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic).await?;
+        if &magic != PACK_MAGIC {
+            return Err(invalid_data("not a pack stream".to_string()));
+        }
+        let version = reader.read_u8().await?;
+        if version > PACK_PROTOCOL_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("unsupported pack protocol version {}", version),
+            ));
+        }
+
+        let mut have: HashSet<[u32; 5]> = self.layer_names().await?.into_iter().collect();
+        let mut imported = Vec::new();
+        let mut frames = 0;
+        loop {
+            match reader.read_u8().await? {
+                TRAILER_TAG => break,
+                LAYER_TAG => {}
+                tag => return Err(invalid_data(format!("unknown frame tag {}", tag))),
+            }
+            frames += 1;
+
+            let name = read_name(&mut reader).await?;
+            let parent = match reader.read_u8().await? {
+                0 => None,
+                _ => Some(read_name(&mut reader).await?),
+            };
+            let len = reader.read_u64().await?;
+            let mut pack = Vec::new();
+            (&mut reader).take(len).read_to_end(&mut pack).await?;
+            if (pack.len() as u64) < len {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let mut checksum = [0; 32];
+            reader.read_exact(&mut checksum).await?;
+            if Sha256::digest(&pack)[..] != checksum[..] {
+                return Err(invalid_data(format!(
+                    "checksum mismatch for layer {}",
+                    crate::storage::name_to_string(name)
+                )));
+            }
+
+            if have.contains(&name) {
+                continue;
+            }
+            if let Some(parent) = parent {
+                if !have.contains(&parent) {
+                    return Err(invalid_data(format!(
+                        "parent of layer {} is missing",
+                        crate::storage::name_to_string(name)
+                    )));
+                }
+            }
+            self.layer_store
+                .import_layers(&pack, Box::new(std::iter::once(name)))
+                .await?;
+            have.insert(name);
+            imported.push(name);
+        }
+
+        if reader.read_u64().await? != frames {
+            return Err(invalid_data(
+                "pack stream trailer does not match its frames".to_string(),
+            ));
+        }
+
+        Ok(imported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layer::{Layer, StringTriple};
+    use crate::store::open_memory_store;
+    use futures::TryStreamExt;
+
+    #[tokio::test]
+    async fn exchange_missing_layers() {
+        let source = open_memory_store();
+        let graph = source.create("farm").await.unwrap();
+        let builder = source.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+        let builder = base.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let child = builder.commit().await.unwrap();
+        graph.set_head(&child).await.unwrap();
+
+        // the target already has the base layer
+        let target = open_memory_store();
+        let have = target.layer_names().await.unwrap();
+        let stream: Vec<u8> = source
+            .create_pack_for(&have)
+            .await
+            .unwrap()
+            .map_ok(|bytes| bytes.to_vec())
+            .try_concat()
+            .await
+            .unwrap();
+        let partial = source.create_pack_for(&[base.name()]).await.unwrap();
+        let partial: Vec<u8> = partial
+            .map_ok(|bytes| bytes.to_vec())
+            .try_concat()
+            .await
+            .unwrap();
+
+        // a layer whose parent is missing is refused
+        let error = target.apply_pack(&partial[..]).await.unwrap_err();
+        assert_eq!(std::io::ErrorKind::InvalidData, error.kind());
+
+        // an interrupted transfer keeps the layers received so far
+        let error = target
+            .apply_pack(&stream[..stream.len() - 20])
+            .await
+            .unwrap_err();
+        assert_eq!(std::io::ErrorKind::UnexpectedEof, error.kind());
+        let have = target.layer_names().await.unwrap();
+        assert_eq!(vec![base.name()], have);
+
+        let resumed: Vec<u8> = source
+            .create_pack_for(&have)
+            .await
+            .unwrap()
+            .map_ok(|bytes| bytes.to_vec())
+            .try_concat()
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![child.name()],
+            target.apply_pack(&resumed[..]).await.unwrap()
+        );
+        let layer = target
+            .get_layer_from_id(child.name())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        assert!(layer.string_triple_exists(&StringTriple::new_value("duck", "says", "quack")));
+
+        let mut corrupt = stream;
+        corrupt[4] = super::PACK_PROTOCOL_VERSION + 1;
+        let error = target.apply_pack(&corrupt[..]).await.unwrap_err();
+        assert_eq!(std::io::ErrorKind::Unsupported, error.kind());
+    }
+}
@@ -0,0 +1,172 @@
+//! Several stores behind a single interface.
+//!
+//! A federated store is made up of member stores, each with a prefix
+//! for the names of its databases. A database is looked up in the
+//! member with the longest prefix of its name, so that the same name
+//! always refers to the same database. Layers are looked up in each
+//! member in turn. This allows reading from, for example, a local
+//! store and a read-only copy of a remote store, without copying data
+//! between them.
+use std::io;
+
+use super::{NamedGraph, Store, StoreLayer};
+
+/// A set of stores, presented as one, as described in the module documentation.
+#[derive(Clone, Default)]
+pub struct FederatedStore {
+    members: Vec<(String, Store)>,
+}
+
+impl FederatedStore {
+    /// A federated store without any members.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a member store, whose databases are known under the given prefix.
+    ///
+    /// A database `foo` in the member store is known as `{prefix}foo`
+    /// in the federated store. An empty prefix makes the member the
+    /// fallback for all names without a more specific prefix. If two
+    /// members have the same prefix, the one added first is used for
+    /// databases.
+    pub fn with_member(mut self, prefix: &str, store: Store) -> Self {
+        self.members.push((prefix.to_owned(), store));
+        self
+    }
+
+    /// The member stores and their prefixes, in the order they were added.
+    pub fn members(&self) -> impl Iterator<Item = (&str, &Store)> {
+        self.members
+            .iter()
+            .map(|(prefix, store)| (prefix.as_str(), store))
+    }
+
+    /// The member store responsible for the database with the given name, and the name of the database in that store.
+    ///
+    /// Returns None if no member has a prefix of the name.
+    pub fn resolve<'a>(&self, label: &'a str) -> Option<(&Store, &'a str)> {
+        let mut result: Option<(&str, &Store)> = None;
+        for (prefix, store) in self.members.iter() {
+            if label.starts_with(prefix.as_str())
+                && result.map(|(p, _)| p.len() < prefix.len()).unwrap_or(true)
+            {
+                result = Some((prefix, store));
+            }
+        }
+
+        result.map(|(prefix, store)| (store, &label[prefix.len()..]))
+    }
+
+    /// Open the database with the given name in the member responsible for it.
+    ///
+    /// Returns None if the database does not exist, or if no member
+    /// is responsible for it.
+    pub async fn open(&self, label: &str) -> io::Result<Option<NamedGraph>> {
+        match self.resolve(label) {
+            None => Ok(None),
+            Some((store, label)) => store.open(label).await,
+        }
+    }
+
+    /// List the names of all databases in all members, ordered by name.
+    ///
+    /// Names are prefixed with the prefix of their member. A database
+    /// that is shadowed by a member with a longer prefix is left out,
+    /// as opening it by name would open another database.
+    pub async fn graphs(&self) -> io::Result<Vec<String>> {
+        let mut names = Vec::new();
+        for (prefix, store) in self.members.iter() {
+            for graph in store.graphs().await? {
+                let name = format!("{}{}", prefix, graph.name());
+                let shadowed = match self.resolve(&name) {
+                    Some((resolved, _)) => !std::ptr::eq(resolved, store),
+                    None => true,
+                };
+                if !shadowed {
+                    names.push(name);
+                }
+            }
+        }
+        names.sort();
+
+        Ok(names)
+    }
+
+    /// Retrieve the layer with the given name from the first member that has it.
+    pub async fn get_layer_from_id(&self, layer: [u32; 5]) -> io::Result<Option<StoreLayer>> {
+        for (_, store) in self.members.iter() {
+            if let Some(layer) = store.get_layer_from_id(layer).await? {
+                return Ok(Some(layer));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::{Layer, StringTriple};
+    use crate::store::open_memory_store;
+
+    #[tokio::test]
+    async fn resolve_graphs_and_layers_across_members() {
+        let local = open_memory_store();
+        let remote = open_memory_store();
+        let archive = open_memory_store();
+        for (store, names) in [
+            (&local, vec!["foo", "remote/bar"]),
+            (&remote, vec!["bar", "baz"]),
+            (&archive, vec!["old"]),
+        ] {
+            for name in names {
+                store.create(name).await.unwrap();
+            }
+        }
+        let builder = remote.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer = builder.commit().await.unwrap();
+        remote
+            .open("bar")
+            .await
+            .unwrap()
+            .unwrap()
+            .set_head(&layer)
+            .await
+            .unwrap();
+
+        let federated = FederatedStore::new()
+            .with_member("", local)
+            .with_member("remote/", remote)
+            .with_member("archive/", archive);
+
+        assert_eq!(
+            vec!["archive/old", "foo", "remote/bar", "remote/baz"],
+            federated.graphs().await.unwrap()
+        );
+        let bar = federated.open("remote/bar").await.unwrap().unwrap();
+        assert_eq!("bar", bar.name());
+        assert_eq!(
+            Some(layer.name()),
+            bar.head().await.unwrap().map(|l| l.name())
+        );
+        assert_eq!("foo", federated.open("foo").await.unwrap().unwrap().name());
+        assert!(federated.open("remote/foo").await.unwrap().is_none());
+
+        let found = federated
+            .get_layer_from_id(layer.name())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(found.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        assert!(federated
+            .get_layer_from_id([1, 2, 3, 4, 5])
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
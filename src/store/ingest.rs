@@ -0,0 +1,142 @@
+//! Adding triples from an async stream.
+use std::io;
+
+use futures::stream::{Stream, StreamExt};
+
+use super::StoreLayerBuilder;
+use crate::layer::{IdTriple, StringTriple};
+
+/// The amount of triples that `add_triples` takes from its stream before adding them.
+const ADD_TRIPLES_BATCH_SIZE: usize = 1024;
+
+/// A triple to add with `StoreLayerBuilder::add_triples`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuilderTriple {
+    String(StringTriple),
+    /// An id triple, which may use temporary ids from interning, as with `add_id_triple`.
+    Id(IdTriple),
+}
+
+impl From<StringTriple> for BuilderTriple {
+    fn from(triple: StringTriple) -> Self {
+        Self::String(triple)
+    }
+}
+
+impl From<IdTriple> for BuilderTriple {
+    fn from(triple: IdTriple) -> Self {
+        Self::Id(triple)
+    }
+}
+
+impl StoreLayerBuilder {
+    /// Add all triples of a stream, returning the amount of triples that were new.
+    ///
+    /// Triples are taken from the stream in batches, and every batch
+    /// is added on a blocking thread, since adding triples may spill
+    /// them to scratch files. The next batch is only taken from the
+    /// stream once the previous one has been added, so a stream that
+    /// is faster than the builder is held back rather than buffered.
+    ///
+    /// Stops at the first error from the stream or from adding a
+    /// triple. Triples taken from the stream before the error remain
+    /// added.
+    pub async fn add_triples<T, S>(&self, triples: S) -> io::Result<usize>
+    where
+        T: Into<BuilderTriple>,
+        S: Stream<Item = io::Result<T>>,
+    {
+        let mut triples = Box::pin(triples);
+        let mut count = 0;
+        loop {
+            let mut batch = Vec::with_capacity(ADD_TRIPLES_BATCH_SIZE);
+            let mut result = Ok(());
+            let mut done = true;
+            while let Some(triple) = triples.next().await {
+                match triple {
+                    Ok(triple) => batch.push(triple.into()),
+                    Err(e) => {
+                        result = Err(e);
+                        break;
+                    }
+                }
+                if batch.len() == ADD_TRIPLES_BATCH_SIZE {
+                    done = false;
+                    break;
+                }
+            }
+
+            let builder = self.clone();
+            count += crate::runtime::spawn_blocking(move || builder.add_batch(batch)).await??;
+            result?;
+            if done {
+                return Ok(count);
+            }
+        }
+    }
+
+    fn add_batch(&self, batch: Vec<BuilderTriple>) -> io::Result<usize> {
+        let mut count = 0;
+        for triple in batch {
+            let new = match triple {
+                BuilderTriple::String(triple) => self.add_string_triple(triple)?,
+                BuilderTriple::Id(triple) => self.add_id_triple(triple)?,
+            };
+            if new {
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::Layer;
+    use crate::store::open_memory_store;
+    use futures::stream;
+
+    #[tokio::test]
+    async fn add_triples_from_stream() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        let says = builder.intern_predicate("says").unwrap();
+        let moo = builder.intern_value("moo").unwrap();
+        let cows: Vec<_> = (0..3000)
+            .map(|i| {
+                let cow = builder.intern_node(&format!("cow{}", i)).unwrap();
+                Ok(BuilderTriple::from(IdTriple::new(cow, says, moo)))
+            })
+            .collect();
+        let ducks = (0..10).map(|i| {
+            Ok(BuilderTriple::from(StringTriple::new_value(
+                &format!("duck{}", i % 5),
+                "says",
+                "quack",
+            )))
+        });
+        let count = builder
+            .add_triples(stream::iter(cows.into_iter().chain(ducks)))
+            .await
+            .unwrap();
+        assert_eq!(3005, count);
+
+        let error = builder
+            .add_triples(stream::iter(vec![
+                Ok(StringTriple::new_value("pig", "says", "oink")),
+                Err(io::Error::new(io::ErrorKind::ConnectionReset, "gone")),
+                Ok(StringTriple::new_value("sheep", "says", "baa")),
+            ]))
+            .await
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::ConnectionReset, error.kind());
+
+        let layer = builder.commit().await.unwrap();
+        assert_eq!(3006, layer.triple_addition_count());
+        assert!(layer.string_triple_exists(&StringTriple::new_value("cow2999", "says", "moo")));
+        assert!(layer.string_triple_exists(&StringTriple::new_value("pig", "says", "oink")));
+        assert!(!layer.string_triple_exists(&StringTriple::new_value("sheep", "says", "baa")));
+    }
+}
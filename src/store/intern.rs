@@ -0,0 +1,308 @@
+//! Interning strings in a layer builder ahead of the triples that use them.
+//!
+//! Applications that generate large batches of triples often use the
+//! same few strings over and over. Interning these once, and then
+//! adding id triples, saves looking up the same strings for every
+//! triple.
+//!
+//! Strings that are known to the parent of the builder are interned
+//! as their id in the parent. Other strings get temporary ids that
+//! follow the ids of the parent, just like in a `StagingLayer`. These
+//! temporary ids are only valid for id triples added to or removed
+//! from the same builder. The layer that is committed assigns its own
+//! ids to new strings.
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+
+use super::{StoreLayerBuilder, Transaction};
+use crate::layer::{IdTriple, Layer, ObjectType, StringTriple};
+
+/// Strings interned in a layer builder that are not known to its parent.
+pub(crate) struct InternedStrings {
+    parent_node_value_count: u64,
+    parent_predicate_count: u64,
+    node_ids: HashMap<String, u64>,
+    value_ids: HashMap<String, u64>,
+    node_values: Vec<ObjectType>,
+    predicate_ids: HashMap<String, u64>,
+    predicates: Vec<String>,
+}
+
+impl InternedStrings {
+    pub(crate) fn new(parent: Option<&Arc<dyn Layer>>) -> Self {
+        let (parent_node_value_count, parent_predicate_count) = match parent {
+            Some(parent) => (
+                parent.node_and_value_count() as u64,
+                parent.predicate_count() as u64,
+            ),
+            None => (0, 0),
+        };
+
+        Self {
+            parent_node_value_count,
+            parent_predicate_count,
+            node_ids: HashMap::new(),
+            value_ids: HashMap::new(),
+            node_values: Vec::new(),
+            predicate_ids: HashMap::new(),
+            predicates: Vec::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.node_values.is_empty() && self.predicates.is_empty()
+    }
+
+    fn intern_node_value(&mut self, object: ObjectType) -> u64 {
+        let ids = match &object {
+            ObjectType::Node(_) => &mut self.node_ids,
+            _ => &mut self.value_ids,
+        };
+        let key = match &object {
+            ObjectType::Node(node) => node.clone(),
+            value => value
+                .value_string()
+                .expect("non-node object should have a value string")
+                .into_owned(),
+        };
+        if let Some(&id) = ids.get(&key) {
+            return id;
+        }
+
+        let id = self.parent_node_value_count + self.node_values.len() as u64 + 1;
+        ids.insert(key, id);
+        self.node_values.push(object);
+
+        id
+    }
+
+    fn intern_predicate(&mut self, predicate: &str) -> u64 {
+        if let Some(&id) = self.predicate_ids.get(predicate) {
+            return id;
+        }
+
+        self.predicates.push(predicate.to_owned());
+        let id = self.parent_predicate_count + self.predicates.len() as u64;
+        self.predicate_ids.insert(predicate.to_owned(), id);
+
+        id
+    }
+
+    fn is_temporary(&self, triple: &IdTriple) -> bool {
+        triple.subject > self.parent_node_value_count
+            || triple.object > self.parent_node_value_count
+            || triple.predicate > self.parent_predicate_count
+    }
+
+    fn node_value(&self, parent: Option<&Arc<dyn Layer>>, id: u64) -> Option<ObjectType> {
+        if id > self.parent_node_value_count {
+            self.node_values
+                .get((id - self.parent_node_value_count - 1) as usize)
+                .cloned()
+        } else {
+            parent.and_then(|parent| parent.id_object(id))
+        }
+    }
+
+    fn predicate(&self, parent: Option<&Arc<dyn Layer>>, id: u64) -> Option<String> {
+        if id > self.parent_predicate_count {
+            self.predicates
+                .get((id - self.parent_predicate_count - 1) as usize)
+                .cloned()
+        } else {
+            parent.and_then(|parent| parent.id_predicate(id))
+        }
+    }
+
+    /// Turn an id triple using temporary ids into a string triple.
+    fn to_string_triple(
+        &self,
+        parent: Option<&Arc<dyn Layer>>,
+        triple: IdTriple,
+    ) -> io::Result<StringTriple> {
+        let unknown_id = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("triple {:?} contains an unknown id", triple),
+            )
+        };
+        let subject = match self.node_value(parent, triple.subject) {
+            Some(ObjectType::Node(subject)) => subject,
+            Some(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("triple {:?} has a value as its subject", triple),
+                ))
+            }
+            None => return Err(unknown_id()),
+        };
+        let predicate = self
+            .predicate(parent, triple.predicate)
+            .ok_or_else(unknown_id)?;
+        let object = self
+            .node_value(parent, triple.object)
+            .ok_or_else(unknown_id)?;
+
+        Ok(StringTriple {
+            subject,
+            predicate,
+            object,
+        })
+    }
+}
+
+impl StoreLayerBuilder {
+    fn with_interned<R, F: FnOnce(&mut InternedStrings) -> R>(&self, f: F) -> io::Result<R> {
+        if self.committed() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "builder has already been committed",
+            ));
+        }
+        let mut interned = self
+            .interned
+            .write()
+            .expect("rwlock write should always succeed");
+
+        Ok(f(&mut interned))
+    }
+
+    /// Get an id for the given node, to use in id triples added to or removed from this builder.
+    ///
+    /// If the parent knows the node, this is its id in the parent.
+    /// Otherwise, this is a temporary id that is only valid for this
+    /// builder. Interning the same node again returns the same id.
+    pub fn intern_node(&self, node: &str) -> io::Result<u64> {
+        if let Some(id) = self.parent.as_ref().and_then(|p| p.object_node_id(node)) {
+            return Ok(id);
+        }
+
+        self.with_interned(|interned| interned.intern_node_value(ObjectType::Node(node.to_owned())))
+    }
+
+    /// Get an id for the given predicate, to use in id triples added to or removed from this builder.
+    ///
+    /// See `intern_node`.
+    pub fn intern_predicate(&self, predicate: &str) -> io::Result<u64> {
+        if let Some(id) = self.parent.as_ref().and_then(|p| p.predicate_id(predicate)) {
+            return Ok(id);
+        }
+
+        self.with_interned(|interned| interned.intern_predicate(predicate))
+    }
+
+    /// Get an id for the given value, to use in id triples added to or removed from this builder.
+    ///
+    /// See `intern_node`.
+    pub fn intern_value(&self, value: &str) -> io::Result<u64> {
+        if let Some(id) = self.parent.as_ref().and_then(|p| p.object_value_id(value)) {
+            return Ok(id);
+        }
+
+        self.with_interned(|interned| {
+            interned.intern_node_value(ObjectType::Value(value.to_owned()))
+        })
+    }
+
+    /// Turn an id triple into a string triple if it uses temporary ids from interning.
+    ///
+    /// Returns the id triple unchanged as an `Err` otherwise.
+    pub(crate) fn resolve_interned(
+        &self,
+        triple: IdTriple,
+    ) -> io::Result<Result<StringTriple, IdTriple>> {
+        let interned = self
+            .interned
+            .read()
+            .expect("rwlock read should always succeed");
+        if interned.is_empty() || !interned.is_temporary(&triple) {
+            return Ok(Err(triple));
+        }
+
+        interned
+            .to_string_triple(self.parent.as_ref(), triple)
+            .map(Ok)
+    }
+}
+
+impl Transaction {
+    /// Get an id for the given node, to use in id triples added to the builder of this transaction.
+    ///
+    /// See `StoreLayerBuilder::intern_node`.
+    pub fn intern_node(&self, node: &str) -> io::Result<u64> {
+        self.builder().intern_node(node)
+    }
+
+    /// Get an id for the given predicate, to use in id triples added to the builder of this transaction.
+    ///
+    /// See `StoreLayerBuilder::intern_node`.
+    pub fn intern_predicate(&self, predicate: &str) -> io::Result<u64> {
+        self.builder().intern_predicate(predicate)
+    }
+
+    /// Get an id for the given value, to use in id triples added to the builder of this transaction.
+    ///
+    /// See `StoreLayerBuilder::intern_node`.
+    pub fn intern_value(&self, value: &str) -> io::Result<u64> {
+        self.builder().intern_value(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layer::{IdTriple, Layer, StringTriple};
+    use crate::store::open_memory_store;
+
+    #[tokio::test]
+    async fn add_triples_with_interned_ids() {
+        let store = open_memory_store();
+        let graph = store.create("foo").await.unwrap();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        graph
+            .set_head(&builder.commit().await.unwrap())
+            .await
+            .unwrap();
+
+        let transaction = graph.transaction().await.unwrap();
+        let head = transaction.head().unwrap().clone();
+        let cow = transaction.intern_node("cow").unwrap();
+        let says = transaction.intern_predicate("says").unwrap();
+        let moo = transaction.intern_value("moo").unwrap();
+        assert_eq!(head.subject_id("cow"), Some(cow));
+        assert_eq!(head.object_value_id("moo"), Some(moo));
+
+        let pig = transaction.intern_node("pig").unwrap();
+        let oink = transaction.intern_value("oink").unwrap();
+        let likes = transaction.intern_predicate("likes").unwrap();
+        assert!(pig > head.node_and_value_count() as u64);
+        assert_eq!(pig, transaction.intern_node("pig").unwrap());
+        assert_ne!(pig, oink);
+
+        let builder = transaction.builder();
+        assert!(builder
+            .add_id_triple(IdTriple::new(pig, says, oink))
+            .unwrap());
+        assert!(builder
+            .add_id_triple(IdTriple::new(pig, likes, cow))
+            .unwrap());
+        assert!(!builder
+            .add_id_triple(IdTriple::new(cow, says, moo))
+            .unwrap());
+        builder
+            .remove_id_triple(IdTriple::new(pig, likes, cow))
+            .unwrap();
+        // a value can't be a subject
+        assert!(builder
+            .add_id_triple(IdTriple::new(oink, says, moo))
+            .is_err());
+        let layer = transaction.commit().await.unwrap();
+
+        assert!(layer.string_triple_exists(&StringTriple::new_value("pig", "says", "oink")));
+        assert!(!layer.string_triple_exists(&StringTriple::new_node("pig", "likes", "cow")));
+        assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+    }
+}
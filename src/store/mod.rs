@@ -1,28 +1,90 @@
 //! High-level API for working with terminus-store.
 //!
 //! It is expected that most users of this library will work exclusively with the types contained in this module.
+#[cfg(feature = "fs")]
+mod config;
+mod events;
+mod exchange;
+mod federated;
+mod ingest;
+mod intern;
+mod pin;
+mod query;
+mod snapshot;
+mod stats;
+#[cfg(feature = "fs")]
 pub mod sync;
-
+mod transaction;
+
+#[cfg(feature = "fs")]
+pub use config::*;
+pub use events::*;
+pub use exchange::*;
+pub use federated::*;
+pub use ingest::*;
+pub use pin::*;
+pub use query::*;
+pub use snapshot::*;
+pub use stats::*;
+pub use transaction::*;
+
+use std::collections::{BTreeMap, HashSet};
+use std::ops::Bound;
+#[cfg(feature = "fs")]
 use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
-
-use crate::layer::{IdTriple, Layer, LayerBuilder, LayerCounts, ObjectType, StringTriple};
-use crate::storage::directory::{DirectoryLabelStore, DirectoryLayerStore};
+use std::time::SystemTime;
+
+use crate::layer::{
+    rank_text_matches, BuildProgressReporter, BulkLoadConfig, IdTriple, Layer, LayerBuilder,
+    LayerCounts, LayerDiff, LayerMetadata, ObjectType, PredicateStats, QuadStack, Regex,
+    StagingLayer, StringQuad, StringTriple,
+};
+use crate::query::{infer, Rule};
 use crate::storage::memory::{MemoryLabelStore, MemoryLayerStore};
-use crate::storage::{CachedLayerStore, LabelStore, LayerStore, LockingHashMapLayerCache};
+use crate::storage::{
+    copy_labels, CachedLayerStore, LabelHistoryStream, LabelStore, LabelUpdate, LabelWatchStream,
+    LayerStore, LockingHashMapLayerCache, PrefixMap, SetLabelError, SetLabelsError,
+};
 
 use std::io;
 use std::pin::Pin;
 
 use rayon::prelude::*;
+use tokio::sync::broadcast;
 
 use futures::future::Future;
+use futures::stream::{Stream, StreamExt};
 
 /// A store, storing a set of layers and database labels pointing to these layers.
 #[derive(Clone)]
 pub struct Store {
     label_store: Arc<dyn LabelStore>,
     layer_store: Arc<dyn LayerStore>,
+    commit_events: broadcast::Sender<CommitEvent>,
+    layer_sizes: LayerSizes,
+    pinned_layers: PinnedLayers,
+    committed_layers: PinnedLayers,
+    gc_lock: Arc<tokio::sync::RwLock<()>>,
+}
+
+/// A point in the history of a database, as used by `Store::open_graph_at`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryPoint {
+    /// The given moment in time.
+    Time(SystemTime),
+    /// The moment the label of the database got the given version.
+    ///
+    /// Every head change increases the version by one, so this is
+    /// the head after that many changes. Versions beyond the current
+    /// one result in the current head.
+    Version(u64),
+}
+
+impl From<SystemTime> for HistoryPoint {
+    fn from(time: SystemTime) -> Self {
+        HistoryPoint::Time(time)
+    }
 }
 
 /// A wrapper over a SimpleLayerBuilder, providing a thread-safe sharable interface.
@@ -40,23 +102,21 @@ pub struct StoreLayerBuilder {
     builder: Arc<RwLock<Option<Box<dyn LayerBuilder>>>>,
     name: [u32; 5],
     store: Store,
+    interned: Arc<RwLock<intern::InternedStrings>>,
 }
 
 impl StoreLayerBuilder {
     async fn new(store: Store) -> io::Result<Self> {
         let builder = store.layer_store.create_base_layer().await?;
 
-        Ok(Self {
-            parent: builder.parent(),
-            name: builder.name(),
-            builder: Arc::new(RwLock::new(Some(builder))),
-            store,
-        })
+        Ok(Self::wrap(builder, store))
     }
 
     fn wrap(builder: Box<dyn LayerBuilder>, store: Store) -> Self {
+        let parent = builder.parent();
         StoreLayerBuilder {
-            parent: builder.parent(),
+            interned: Arc::new(RwLock::new(intern::InternedStrings::new(parent.as_ref()))),
+            parent,
             name: builder.name(),
             builder: Arc::new(RwLock::new(Some(builder))),
             store,
@@ -93,13 +153,24 @@ impl StoreLayerBuilder {
     }
 
     /// Add a string triple.
-    pub fn add_string_triple(&self, triple: StringTriple) -> Result<(), io::Error> {
+    ///
+    /// Returns true if the triple is new, meaning that it neither
+    /// exists in the parent layers nor was added to this builder
+    /// before.
+    pub fn add_string_triple(&self, triple: StringTriple) -> Result<bool, io::Error> {
         self.with_builder(move |b| b.add_string_triple(triple))
     }
 
     /// Add an id triple.
-    pub fn add_id_triple(&self, triple: IdTriple) -> Result<(), io::Error> {
-        self.with_builder(move |b| b.add_id_triple(triple))
+    ///
+    /// The ids may be temporary ids from interning strings with
+    /// `intern_node`, `intern_predicate` and `intern_value`. Returns
+    /// true if the triple is new, like `add_string_triple`.
+    pub fn add_id_triple(&self, triple: IdTriple) -> Result<bool, io::Error> {
+        match self.resolve_interned(triple)? {
+            Ok(triple) => self.add_string_triple(triple),
+            Err(triple) => self.with_builder(move |b| b.add_id_triple(triple)),
+        }
     }
 
     /// Remove a string triple.
@@ -108,8 +179,62 @@ impl StoreLayerBuilder {
     }
 
     /// Remove an id triple.
+    ///
+    /// Like with `add_id_triple`, the ids may be temporary ids from interning.
     pub fn remove_id_triple(&self, triple: IdTriple) -> Result<(), io::Error> {
-        self.with_builder(move |b| b.remove_id_triple(triple))
+        match self.resolve_interned(triple)? {
+            Ok(triple) => self.remove_string_triple(triple),
+            Err(triple) => self.with_builder(move |b| b.remove_id_triple(triple)),
+        }
+    }
+
+    /// Remove all triples of the parent layers matching the given pattern, where None matches anything.
+    ///
+    /// Triples added to this builder are not affected. Returns the
+    /// amount of removed triples.
+    pub fn remove_matching(
+        &self,
+        subject: Option<u64>,
+        predicate: Option<u64>,
+        object: Option<u64>,
+    ) -> Result<usize, io::Error> {
+        self.with_builder(move |b| b.remove_matching(subject, predicate, object))
+    }
+
+    /// Add a quad to a named graph.
+    pub fn add_string_quad(&self, quad: StringQuad) -> Result<(), io::Error> {
+        self.with_builder(move |b| b.add_string_quad(quad))
+    }
+
+    /// Remove a quad from a named graph.
+    pub fn remove_string_quad(&self, quad: StringQuad) -> Result<(), io::Error> {
+        self.with_builder(move |b| b.remove_string_quad(quad))
+    }
+
+    /// Set the descriptive metadata to store with this layer.
+    ///
+    /// Metadata is not part of the content name of a layer. If the
+    /// store uses content addressing and a layer with the same content
+    /// already exists, that layer keeps its own metadata.
+    pub fn set_metadata(&self, metadata: LayerMetadata) -> Result<(), io::Error> {
+        self.with_builder(move |b| b.set_metadata(metadata))
+    }
+
+    /// Set a reporter that is called as the commit of this layer progresses.
+    ///
+    /// The reporter is called from the task that is performing the
+    /// commit, so it should return quickly.
+    pub fn set_progress(&self, progress: BuildProgressReporter) -> Result<(), io::Error> {
+        self.with_builder(move |b| b.set_progress(progress))
+    }
+
+    /// Durably store the string triples added and removed so far.
+    ///
+    /// If the process is interrupted before commit, the builder can
+    /// be reopened from this checkpoint with
+    /// `Store::resume_layer_builder`.
+    pub fn checkpoint(&self) -> Result<(), io::Error> {
+        self.with_builder(|b| b.checkpoint())?
     }
 
     /// Returns true if this layer has been committed, and false otherwise.
@@ -121,7 +246,17 @@ impl StoreLayerBuilder {
     }
 
     /// Commit the layer to storage without loading the resulting layer.
+    ///
+    /// The layer is not finalized. This means it is not renamed to
+    /// its content name, even if the layer store uses content
+    /// addressing, and its directory entry is not synced to disk.
+    /// Use `commit` for that.
     pub async fn commit_no_load(&self) -> io::Result<()> {
+        let _guard = self.store.gc_lock.read().await;
+        self.commit_files().await
+    }
+
+    async fn commit_files(&self) -> io::Result<()> {
         let mut builder = None;
         {
             let mut guard = self
@@ -143,12 +278,19 @@ impl StoreLayerBuilder {
     }
 
     /// Commit the layer to storage.
+    ///
+    /// If the layer store uses content addressing, the resulting
+    /// layer will have a different name than this builder. The
+    /// returned layer is kept from garbage collection for as long as
+    /// it or one of its clones is around, so that it can be attached
+    /// to a database first.
     pub async fn commit(&self) -> io::Result<StoreLayer> {
-        let name = self.name;
-        self.commit_no_load().await?;
+        let _guard = self.store.gc_lock.read().await;
+        self.commit_files().await?;
+        let name = self.store.layer_store.finalize_layer(self.name).await?;
 
         let layer = self.store.layer_store.get_layer(name).await?;
-        Ok(StoreLayer::wrap(
+        Ok(StoreLayer::wrap_committed(
             layer.expect("layer that was just created was not found in store"),
             self.store.clone(),
         ))
@@ -183,6 +325,21 @@ impl StoreLayerBuilder {
         Ok(())
     }
 
+    /// Add and remove the triples that were staged in the given staging layer.
+    ///
+    /// The changes are applied as string triples, so the staging
+    /// layer does not have to be based on the parent of this builder.
+    pub fn apply_staged(&self, staged: &StagingLayer) -> Result<(), io::Error> {
+        for triple in staged.staged_string_additions() {
+            self.add_string_triple(triple)?;
+        }
+        for triple in staged.staged_string_removals() {
+            self.remove_string_triple(triple)?;
+        }
+
+        Ok(())
+    }
+
     /// Apply the changes required to change our parent layer into the given layer.
     pub fn apply_diff(&self, other: &StoreLayer) -> Result<(), io::Error> {
         // create a child builder and use it directly
@@ -204,10 +361,10 @@ impl StoreLayerBuilder {
                     if let Some(st) = other.id_triple_to_string(&t) {
                         if let Some(this) = self.parent() {
                             if !this.string_triple_exists(&st) {
-                                self.add_string_triple(st).unwrap()
+                                self.add_string_triple(st).unwrap();
                             }
                         } else {
-                            self.add_string_triple(st).unwrap()
+                            self.add_string_triple(st).unwrap();
                         };
                     }
                 })
@@ -218,6 +375,128 @@ impl StoreLayerBuilder {
     }
 }
 
+/// The changes a patch actually made, as returned by `StoreLayer::apply_patch`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PatchCounts {
+    /// The amount of triples that were added.
+    pub additions: usize,
+    /// The amount of triples that were removed.
+    pub removals: usize,
+}
+
+/// Statistics about a single layer in a layer stack, as returned by `StoreLayer::ancestors`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LayerStats {
+    /// The name of the layer.
+    pub name: [u32; 5],
+    /// The amount of triples this layer adds.
+    pub additions: usize,
+    /// The amount of triples this layer removes.
+    pub removals: usize,
+    /// The total size in bytes of the files of this layer.
+    pub file_size: u64,
+}
+
+/// A subject and predicate that both sides of a merge changed in different ways.
+///
+/// One side added a triple with this subject and predicate, while
+/// the other side removed one. This typically means that both sides
+/// replaced a value. The changes of our side are kept, and the
+/// changes of their side are left out of the merge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeConflict {
+    pub subject: String,
+    pub predicate: String,
+    /// The triples with this subject and predicate that our side added.
+    pub ours_added: Vec<StringTriple>,
+    /// The triples with this subject and predicate that our side removed.
+    pub ours_removed: Vec<StringTriple>,
+    /// The triples with this subject and predicate that their side added.
+    pub theirs_added: Vec<StringTriple>,
+    /// The triples with this subject and predicate that their side removed.
+    pub theirs_removed: Vec<StringTriple>,
+}
+
+/// The outcome of a merge, as returned by `StoreLayer::merge`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MergeReport {
+    /// The amount of triples the merged layer adds.
+    pub additions: usize,
+    /// The amount of triples the merged layer removes.
+    pub removals: usize,
+    /// The changes of their side that could not be merged.
+    pub conflicts: Vec<MergeConflict>,
+}
+
+#[derive(Default)]
+struct MergeSides {
+    ours_added: Vec<StringTriple>,
+    ours_removed: Vec<StringTriple>,
+    theirs_added: Vec<StringTriple>,
+    theirs_removed: Vec<StringTriple>,
+}
+
+impl MergeSides {
+    fn of<'a>(
+        changes: &'a mut BTreeMap<(String, String), MergeSides>,
+        triple: &StringTriple,
+    ) -> &'a mut MergeSides {
+        changes
+            .entry((triple.subject.clone(), triple.predicate.clone()))
+            .or_default()
+    }
+
+    fn conflicts(&self) -> bool {
+        let crossed = (!self.ours_added.is_empty() && !self.theirs_removed.is_empty())
+            || (!self.ours_removed.is_empty() && !self.theirs_added.is_empty());
+
+        // both sides making the exact same change is not a conflict
+        crossed
+            && (self.ours_added != self.theirs_added || self.ours_removed != self.theirs_removed)
+    }
+}
+
+/// How the ids of a layer map onto the ids of its compacted version, as returned by `StoreLayer::compact`.
+///
+/// Both arrays are indexed by the old id. Ids that are no longer
+/// used by any triple are dropped from the dictionaries, and map to 0,
+/// which is never a valid id.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct IdRemapping {
+    /// The new id of each node and value.
+    pub node_value_ids: Vec<u64>,
+    /// The new id of each predicate.
+    pub predicate_ids: Vec<u64>,
+}
+
+impl IdRemapping {
+    /// The new id of the given node or value, or None if it was dropped.
+    pub fn node_value_id(&self, id: u64) -> Option<u64> {
+        match self.node_value_ids.get(id as usize) {
+            None | Some(0) => None,
+            Some(&id) => Some(id),
+        }
+    }
+
+    /// The new id of the given predicate, or None if it was dropped.
+    pub fn predicate_id(&self, id: u64) -> Option<u64> {
+        match self.predicate_ids.get(id as usize) {
+            None | Some(0) => None,
+            Some(&id) => Some(id),
+        }
+    }
+
+    /// The new ids of the given triple, or None if any part of it was dropped.
+    pub fn triple(&self, triple: IdTriple) -> Option<IdTriple> {
+        Some(IdTriple::new(
+            self.node_value_id(triple.subject)?,
+            self.predicate_id(triple.predicate)?,
+            self.node_value_id(triple.object)?,
+        ))
+    }
+}
+
 /// A layer that keeps track of the store it came out of, allowing the creation of a layer builder on top of this layer.
 ///
 /// This type of layer supports querying what was added and what was
@@ -234,11 +513,26 @@ pub struct StoreLayer {
     // TODO this Arc here is not great
     layer: Arc<dyn Layer>,
     store: Store,
+    // set for layers returned by commit, until they are attached to a label
+    _commit_pin: Option<Arc<PinCount>>,
 }
 
 impl StoreLayer {
     fn wrap(layer: Arc<dyn Layer>, store: Store) -> Self {
-        StoreLayer { layer, store }
+        StoreLayer {
+            layer,
+            store,
+            _commit_pin: None,
+        }
+    }
+
+    fn wrap_committed(layer: Arc<dyn Layer>, store: Store) -> Self {
+        let commit_pin = Arc::new(PinCount::new(layer.name(), &store.committed_layers));
+        StoreLayer {
+            layer,
+            store,
+            _commit_pin: Some(commit_pin),
+        }
     }
 
     /// Create a layer builder based on this layer.
@@ -252,6 +546,138 @@ impl StoreLayer {
         Ok(StoreLayerBuilder::wrap(layer, self.store.clone()))
     }
 
+    /// Create an in-memory staging layer on top of this layer.
+    ///
+    /// Changes made to the staging layer can be queried right away,
+    /// without committing anything. Use
+    /// `StoreLayerBuilder::apply_staged` to write them to a layer.
+    pub fn open_staging(&self) -> StagingLayer {
+        StagingLayer::new(Arc::new(self.clone()))
+    }
+
+    /// Create a child layer with the given triples added and removed.
+    ///
+    /// All removals have to exist in this layer, or this fails
+    /// without creating a layer. Additions that already exist are
+    /// skipped, as are duplicates. A triple that is both removed and
+    /// added stays as it is. The new layer is returned together with
+    /// the amount of triples that were actually added and removed.
+    pub async fn apply_patch<
+        A: IntoIterator<Item = StringTriple>,
+        R: IntoIterator<Item = StringTriple>,
+    >(
+        &self,
+        additions: A,
+        removals: R,
+    ) -> io::Result<(StoreLayer, PatchCounts)> {
+        let mut removals: HashSet<_> = removals.into_iter().collect();
+        if let Some(missing) = removals.iter().find(|t| !self.string_triple_exists(t)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("patch removes a triple that does not exist: {:?}", missing),
+            ));
+        }
+
+        let mut new_additions = HashSet::new();
+        for triple in additions {
+            if !self.string_triple_exists(&triple) {
+                new_additions.insert(triple);
+            } else {
+                // removing and adding an existing triple leaves it in place
+                removals.remove(&triple);
+            }
+        }
+
+        let counts = PatchCounts {
+            additions: new_additions.len(),
+            removals: removals.len(),
+        };
+        let builder = self.open_write().await?;
+        for triple in new_additions {
+            builder.add_string_triple(triple)?;
+        }
+        for triple in removals {
+            builder.remove_string_triple(triple)?;
+        }
+        let layer = builder.commit().await?;
+
+        Ok((layer, counts))
+    }
+
+    /// Merge the changes that another layer made since a common ancestor into a new child layer of this layer.
+    ///
+    /// The changes of both sides are computed relative to
+    /// `ancestor`. Changes of their side that this layer already has
+    /// are skipped. When both sides changed the same subject and
+    /// predicate in different ways, the changes of their side for
+    /// that subject and predicate are left out, and reported as a
+    /// conflict instead. Named graph quads are not merged.
+    pub async fn merge(
+        &self,
+        theirs: &StoreLayer,
+        ancestor: &StoreLayer,
+    ) -> io::Result<(StoreLayer, MergeReport)> {
+        let mut changes: BTreeMap<(String, String), MergeSides> = BTreeMap::new();
+        let ours = ancestor.diff(self).await?;
+        for triple in ours.string_additions() {
+            MergeSides::of(&mut changes, &triple)
+                .ours_added
+                .push(triple);
+        }
+        for triple in ours.string_removals() {
+            MergeSides::of(&mut changes, &triple)
+                .ours_removed
+                .push(triple);
+        }
+        let their_changes = ancestor.diff(theirs).await?;
+        for triple in their_changes.string_additions() {
+            MergeSides::of(&mut changes, &triple)
+                .theirs_added
+                .push(triple);
+        }
+        for triple in their_changes.string_removals() {
+            MergeSides::of(&mut changes, &triple)
+                .theirs_removed
+                .push(triple);
+        }
+
+        let mut report = MergeReport::default();
+        let builder = self.open_write().await?;
+        for ((subject, predicate), mut sides) in changes {
+            if sides.conflicts() {
+                sides.ours_added.sort();
+                sides.ours_removed.sort();
+                sides.theirs_added.sort();
+                sides.theirs_removed.sort();
+                report.conflicts.push(MergeConflict {
+                    subject,
+                    predicate,
+                    ours_added: sides.ours_added,
+                    ours_removed: sides.ours_removed,
+                    theirs_added: sides.theirs_added,
+                    theirs_removed: sides.theirs_removed,
+                });
+                continue;
+            }
+
+            for triple in sides.theirs_added {
+                if !self.string_triple_exists(&triple) {
+                    builder.add_string_triple(triple)?;
+                    report.additions += 1;
+                }
+            }
+            for triple in sides.theirs_removed {
+                if self.string_triple_exists(&triple) {
+                    builder.remove_string_triple(triple)?;
+                    report.removals += 1;
+                }
+            }
+        }
+        let merged = builder.commit().await?;
+
+        Ok((merged, report))
+    }
+
     /// Returns the parent of this layer, if any, or None if this layer has no parent.
     pub async fn parent(&self) -> io::Result<Option<StoreLayer>> {
         let parent_name = self.layer.parent_name();
@@ -281,12 +707,137 @@ impl StoreLayer {
         let new_builder = self.store.create_base_layer().await?;
         self.triples().par_bridge().for_each(|t| {
             let st = self.id_triple_to_string(&t).unwrap();
-            new_builder.add_string_triple(st).unwrap()
+            new_builder.add_string_triple(st).unwrap();
         });
+        let quads = self.quads().await?;
+        for graph in quads.graphs() {
+            for t in quads.graph_triples(&graph) {
+                let st = self.id_triple_to_string(&t).unwrap();
+                new_builder.add_string_quad(StringQuad::from_triple(&graph, st))?;
+            }
+        }
 
         new_builder.commit().await
     }
 
+    /// Replace the layers between this layer and the given ancestor with a single child layer of that ancestor.
+    ///
+    /// Deep layer stacks tend to carry changes that cancel each
+    /// other out, such as a triple that is added in one layer and
+    /// removed again in a later one. The new layer only contains the
+    /// net changes, so it has the same content as this layer with the
+    /// minimal amount of additions and removals. Unlike a rollup, the
+    /// result is a new layer with its own name, and the intermediate
+    /// layers are not part of its history.
+    pub async fn squash_upto(&self, upto: &StoreLayer) -> io::Result<StoreLayer> {
+        if !self
+            .store
+            .layer_store
+            .layer_is_ancestor_of(self.name(), upto.name())
+            .await?
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "layer to squash upto is not an ancestor",
+            ));
+        }
+
+        let diff = upto.diff(self).await?;
+        let builder = upto.open_write().await?;
+        for triple in diff.string_additions() {
+            builder.add_string_triple(triple)?;
+        }
+        for triple in diff.string_removals() {
+            builder.remove_string_triple(triple)?;
+        }
+
+        let quads = self.quads().await?;
+        let upto_quads = upto.quads().await?;
+        let mut graphs = quads.graphs();
+        graphs.extend(upto_quads.graphs());
+        graphs.sort();
+        graphs.dedup();
+        for graph in graphs {
+            let resolve = |layer: &StoreLayer, triples: Vec<IdTriple>| -> HashSet<StringTriple> {
+                triples
+                    .iter()
+                    .map(|t| {
+                        layer
+                            .id_triple_to_string(t)
+                            .expect("quad triple should resolve to strings")
+                    })
+                    .collect()
+            };
+            let old = resolve(upto, upto_quads.graph_triples(&graph));
+            let new = resolve(self, quads.graph_triples(&graph));
+            for triple in new.difference(&old) {
+                builder.add_string_quad(StringQuad::from_triple(&graph, triple.clone()))?;
+            }
+            for triple in old.difference(&new) {
+                builder.remove_string_quad(StringQuad::from_triple(&graph, triple.clone()))?;
+            }
+        }
+
+        builder.commit().await
+    }
+
+    /// Squash this layer, and report how its ids map onto the ids of the squashed layer.
+    ///
+    /// Rollups keep every id of the layers they replace, as child
+    /// layers refer to them. Over time, a layer stack with a lot of
+    /// churn accumulates dictionary entries that no triple uses
+    /// anymore. Like `squash`, this creates a new base layer which
+    /// only contains the strings that are still in use. The returned
+    /// remapping can be used to translate ids that were obtained from
+    /// this layer.
+    pub async fn compact(&self) -> io::Result<(StoreLayer, IdRemapping)> {
+        let compacted = self.squash().await?;
+
+        let mut remapping = IdRemapping {
+            node_value_ids: vec![0; self.node_and_value_count() + 1],
+            predicate_ids: vec![0; self.predicate_count() + 1],
+        };
+        let mut remap = |triple: IdTriple| -> io::Result<()> {
+            let missing = || {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "compacted layer is missing a string of the original layer",
+                )
+            };
+            if remapping.node_value_ids[triple.subject as usize] == 0 {
+                let subject = self.id_subject(triple.subject).ok_or_else(missing)?;
+                remapping.node_value_ids[triple.subject as usize] =
+                    compacted.subject_id(&subject).ok_or_else(missing)?;
+            }
+            if remapping.predicate_ids[triple.predicate as usize] == 0 {
+                let predicate = self.id_predicate(triple.predicate).ok_or_else(missing)?;
+                remapping.predicate_ids[triple.predicate as usize] =
+                    compacted.predicate_id(&predicate).ok_or_else(missing)?;
+            }
+            if remapping.node_value_ids[triple.object as usize] == 0 {
+                let object = match self.id_object(triple.object).ok_or_else(missing)? {
+                    ObjectType::Node(node) => compacted.object_node_id(&node),
+                    value => compacted.object_value_id(&value.value_string().unwrap()),
+                };
+                remapping.node_value_ids[triple.object as usize] = object.ok_or_else(missing)?;
+            }
+
+            Ok(())
+        };
+
+        for triple in self.triples() {
+            remap(triple)?;
+        }
+        let quads = self.quads().await?;
+        for graph in quads.graphs() {
+            for triple in quads.graph_triples(&graph) {
+                remap(triple)?;
+            }
+        }
+
+        Ok((compacted, remapping))
+    }
+
     /// Create a new rollup layer which rolls up all triples in this layer, as well as all its ancestors.
     ///
     /// It is a good idea to keep layer stacks small, meaning, to only
@@ -337,6 +888,93 @@ impl StoreLayer {
         Ok(())
     }
 
+    /// Drop the history before this layer.
+    ///
+    /// The parent of this layer and all its ancestors are rolled up
+    /// into a base layer, which becomes the new parent of this layer
+    /// and its siblings. The old ancestors are left in the store. See
+    /// `LayerStore::truncate_history` for details.
+    ///
+    /// Returns this layer as loaded with its new parent.
+    pub async fn truncate_history(&self) -> io::Result<StoreLayer> {
+        self.store.layer_store.truncate_history(self.name()).await?;
+
+        self.store
+            .get_layer_from_id(self.name())
+            .await?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "layer not found"))
+    }
+
+    /// Load the descriptive metadata of this layer, if it has any.
+    pub async fn metadata(&self) -> io::Result<Option<LayerMetadata>> {
+        self.store.layer_store.layer_metadata(self.name()).await
+    }
+
+    /// Load the named graph quads of this layer.
+    ///
+    /// The ids in the returned quads can be converted to strings using this layer.
+    pub async fn quads(&self) -> io::Result<QuadStack> {
+        self.store.layer_store.layer_quads(self.name()).await
+    }
+
+    /// The ids of all string values containing any of the words of the query, ranked by how well they match.
+    ///
+    /// This uses the full-text indexes of this layer and its
+    /// ancestors, which are only built by stores with text indexing
+    /// enabled. Layers without an index don't contribute any
+    /// matches. See `rank_text_matches` for how values are ranked.
+    pub async fn values_matching_text(&self, query: &str) -> io::Result<Vec<(u64, f64)>> {
+        let mut indexes = Vec::new();
+        for name in self.retrieve_layer_stack_names().await? {
+            if let Some(index) = self.store.layer_store.layer_text_index(name).await? {
+                indexes.push(index);
+            }
+        }
+
+        Ok(rank_text_matches(&indexes, query))
+    }
+
+    /// Apply the rules to this layer, and commit everything they infer as a child layer.
+    ///
+    /// See `infer` for how the rules are applied. If nothing new can
+    /// be inferred, the child layer is empty.
+    pub async fn materialize(&self, rules: &[Rule]) -> io::Result<StoreLayer> {
+        let builder = self.open_write().await?;
+        for triple in infer(self, rules)? {
+            builder.add_id_triple(triple)?;
+        }
+
+        builder.commit().await
+    }
+
+    /// Compute the difference between this layer and the given layer.
+    ///
+    /// The layers do not need to be related. The resulting diff
+    /// yields the triples that were added and removed going from this
+    /// layer to the other. These are computed lazily by walking the
+    /// triples of both layers in order.
+    pub async fn diff(&self, other: &StoreLayer) -> io::Result<LayerDiff> {
+        let names = self.retrieve_layer_stack_names().await?;
+        let other_names = other.retrieve_layer_stack_names().await?;
+        // both stacks start at their base layer, so the common ancestor is the last name in their common prefix
+        let common_ancestor = names
+            .iter()
+            .zip(other_names.iter())
+            .take_while(|(n1, n2)| n1 == n2)
+            .last()
+            .map(|(n, _)| *n);
+        let common_ancestor = match common_ancestor {
+            None => None,
+            Some(name) => self.store.layer_store.get_layer(name).await?,
+        };
+
+        Ok(LayerDiff::new(
+            self.layer.clone(),
+            other.layer.clone(),
+            common_ancestor.as_deref().map(|l| l as &dyn Layer),
+        ))
+    }
+
     /// Returns a future that yields true if this triple has been added in this layer, or false if it doesn't.
     ///
     /// Since this operation will involve io when this layer is a
@@ -537,6 +1175,29 @@ impl StoreLayer {
             .triple_layer_removal_count(self.layer.name())
     }
 
+    /// Collect statistics for this layer and all its ancestors.
+    ///
+    /// The result starts with this layer and ends with its base
+    /// layer, so its length is the depth of the layer stack. This can
+    /// be used to decide when a rollup is due.
+    pub async fn ancestors(&self) -> io::Result<Vec<LayerStats>> {
+        let layer_store = &self.store.layer_store;
+        let mut names = self.retrieve_layer_stack_names().await?;
+        names.reverse();
+
+        let mut result = Vec::with_capacity(names.len());
+        for name in names {
+            result.push(LayerStats {
+                name,
+                additions: layer_store.triple_layer_addition_count(name).await?,
+                removals: layer_store.triple_layer_removal_count(name).await?,
+                file_size: layer_store.layer_file_size(name).await?,
+            });
+        }
+
+        Ok(result)
+    }
+
     /// Returns a future that yields a vector of layer stack names describing the history of this layer, starting from the base layer up to and including the name of this layer itself.
     pub fn retrieve_layer_stack_names(
         &self,
@@ -579,6 +1240,38 @@ impl Layer for StoreLayer {
         self.layer.object_value_id(object)
     }
 
+    fn subject_exists(&self, subject: &str) -> bool {
+        self.layer.subject_exists(subject)
+    }
+
+    fn predicate_exists(&self, predicate: &str) -> bool {
+        self.layer.predicate_exists(predicate)
+    }
+
+    fn object_node_exists(&self, object: &str) -> bool {
+        self.layer.object_node_exists(object)
+    }
+
+    fn object_value_exists(&self, object: &str) -> bool {
+        self.layer.object_value_exists(object)
+    }
+
+    fn value_ids_in_range(&self, start: Bound<&str>, end: Bound<&str>) -> Vec<u64> {
+        self.layer.value_ids_in_range(start, end)
+    }
+
+    fn node_ids_matching(&self, regex: &Regex) -> Vec<u64> {
+        self.layer.node_ids_matching(regex)
+    }
+
+    fn predicate_ids_matching(&self, regex: &Regex) -> Vec<u64> {
+        self.layer.predicate_ids_matching(regex)
+    }
+
+    fn value_ids_matching(&self, regex: &Regex) -> Vec<u64> {
+        self.layer.value_ids_matching(regex)
+    }
+
     fn id_subject(&self, id: u64) -> Option<String> {
         self.layer.id_subject(id)
     }
@@ -603,6 +1296,14 @@ impl Layer for StoreLayer {
         self.layer.triples_s(subject)
     }
 
+    fn triples_subject_range(
+        &self,
+        start: u64,
+        end: u64,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triples_subject_range(start, end)
+    }
+
     fn triples_sp(
         &self,
         subject: u64,
@@ -619,6 +1320,31 @@ impl Layer for StoreLayer {
         self.layer.triples_o(object)
     }
 
+    fn ancestor_counts(&self, name: [u32; 5]) -> Option<LayerCounts> {
+        self.layer.ancestor_counts(name)
+    }
+
+    fn common_ancestor_counts(&self, other: &dyn Layer) -> Option<LayerCounts> {
+        self.layer.common_ancestor_counts(other)
+    }
+
+    fn estimate_count(
+        &self,
+        subject: Option<u64>,
+        predicate: Option<u64>,
+        object: Option<u64>,
+    ) -> usize {
+        self.layer.estimate_count(subject, predicate, object)
+    }
+
+    fn predicate_stats(&self, predicate: u64) -> PredicateStats {
+        self.layer.predicate_stats(predicate)
+    }
+
+    fn sample_triples(&self, n: usize, seed: u64) -> Vec<IdTriple> {
+        self.layer.sample_triples(n, seed)
+    }
+
     fn clone_boxed(&self) -> Box<dyn Layer> {
         Box::new(self.clone())
     }
@@ -696,6 +1422,7 @@ impl NamedGraph {
 
     /// Set the database label to the given layer if it is a valid ancestor, returning false otherwise.
     pub async fn set_head(&self, layer: &StoreLayer) -> io::Result<bool> {
+        let _guard = self.store.gc_lock.read().await;
         let layer_name = layer.name();
         let label = self.store.label_store.get_label(&self.label).await?;
         if label.is_none() {
@@ -713,21 +1440,90 @@ impl NamedGraph {
             }
         };
 
-        if set_is_ok {
-            self.store.label_store.set_label(&label, layer_name).await?;
+        if set_is_ok
+            && self
+                .store
+                .label_store
+                .set_label(&label, layer_name)
+                .await?
+                .is_some()
+        {
+            self.store
+                .notify_commit(&self.label, label.layer, Some(layer_name))
+                .await;
         }
 
         Ok(set_is_ok)
     }
 
+    /// Set the database label to the given layer, provided it currently points at `expected`.
+    ///
+    /// If the label points at some other layer, a
+    /// `SetLabelError::Conflict` is returned which contains the name
+    /// of the current head.
+    pub async fn set_head_if(
+        &self,
+        expected: Option<&StoreLayer>,
+        layer: &StoreLayer,
+    ) -> Result<(), SetLabelError> {
+        let _guard = self.store.gc_lock.read().await;
+        let expected = expected.map(|l| l.name());
+        self.store
+            .label_store
+            .set_label_if(&self.label, expected, Some(layer.name()))
+            .await?;
+        self.store
+            .notify_commit(&self.label, expected, Some(layer.name()))
+            .await;
+
+        Ok(())
+    }
+
+    /// Squash the current head into a single base layer, and point this database at it.
+    ///
+    /// The original layer stack is left untouched. It stays in the
+    /// history of this database, so `Store::collect_garbage` keeps it,
+    /// and it can still be read with `head_as_of`, or the database
+    /// pointed back at it with `force_set_head`. The label is only
+    /// updated if it still points at the layer that was squashed,
+    /// returning a `SetLabelError::Conflict` otherwise.
+    ///
+    /// Returns the new head, or None if this database has no head. If
+    /// the head already is a base layer, it is returned as is.
+    pub async fn squash_head(&self) -> Result<Option<StoreLayer>, SetLabelError> {
+        let head = match self.head().await? {
+            None => return Ok(None),
+            Some(head) => head,
+        };
+        if head.parent_name().is_none() {
+            return Ok(Some(head));
+        }
+
+        let squashed = head.squash().await?;
+        self.set_head_if(Some(&head), &squashed).await?;
+
+        Ok(Some(squashed))
+    }
+
     /// Set the database label to the given layer, even if it is not a valid ancestor.
     pub async fn force_set_head(&self, layer: &StoreLayer) -> io::Result<()> {
+        let _guard = self.store.gc_lock.read().await;
         let layer_name = layer.name();
         let label = self.store.label_store.get_label(&self.label).await?;
         match label {
             None => Err(io::Error::new(io::ErrorKind::NotFound, "label not found")),
             Some(label) => {
-                self.store.label_store.set_label(&label, layer_name).await?;
+                if self
+                    .store
+                    .label_store
+                    .set_label(&label, layer_name)
+                    .await?
+                    .is_some()
+                {
+                    self.store
+                        .notify_commit(&self.label, label.layer, Some(layer_name))
+                        .await;
+                }
 
                 Ok(())
             }
@@ -740,6 +1536,7 @@ impl NamedGraph {
         layer: &StoreLayer,
         version: u64,
     ) -> io::Result<bool> {
+        let _guard = self.store.gc_lock.read().await;
         let layer_name = layer.name();
         let label = self.store.label_store.get_label(&self.label).await?;
         match label {
@@ -748,7 +1545,17 @@ impl NamedGraph {
                 if label.version != version {
                     Ok(false)
                 } else {
-                    self.store.label_store.set_label(&label, layer_name).await?;
+                    if self
+                        .store
+                        .label_store
+                        .set_label(&label, layer_name)
+                        .await?
+                        .is_some()
+                    {
+                        self.store
+                            .notify_commit(&self.label, label.layer, Some(layer_name))
+                            .await;
+                    }
 
                     Ok(true)
                 }
@@ -756,25 +1563,231 @@ impl NamedGraph {
         }
     }
 
-    pub async fn delete(&self) -> io::Result<()> {
-        self.store.delete(&self.label).await.map(|_| ())
-    }
-}
-
-impl Store {
-    /// Create a new store from the given label and layer store.
-    pub fn new<Labels: 'static + LabelStore, Layers: 'static + LayerStore>(
-        label_store: Labels,
-        layer_store: Layers,
-    ) -> Store {
-        Store {
-            label_store: Arc::new(label_store),
-            layer_store: Arc::new(layer_store),
-        }
+    /// Point this database back at an earlier layer, or at any other layer in the store.
+    ///
+    /// The label is only moved if it still points at the head it had
+    /// when this was called, returning a `SetLabelError::Conflict`
+    /// otherwise. If `keep_abandoned_as` is given, and the reset
+    /// leaves layers behind that are no longer reachable from the new
+    /// head, a database with that name is pointed at the old head
+    /// together with the reset, creating it if needed. This keeps
+    /// these layers from being garbage collected, so the reset can be
+    /// undone. If the reset fails, that database is left as it was,
+    /// and removed again if it was created.
+    ///
+    /// Returns the old head.
+    pub async fn reset(
+        &self,
+        layer: &StoreLayer,
+        keep_abandoned_as: Option<&str>,
+    ) -> Result<Option<StoreLayer>, SetLabelError> {
+        let head = self.head().await?;
+        self.reset_from(head, layer, keep_abandoned_as).await
     }
 
-    /// Create a new database with the given name.
-    ///
+    async fn reset_from(
+        &self,
+        head: Option<StoreLayer>,
+        layer: &StoreLayer,
+        keep_abandoned_as: Option<&str>,
+    ) -> Result<Option<StoreLayer>, SetLabelError> {
+        let (old_head, keep_label) = match (&head, keep_abandoned_as) {
+            (Some(old_head), Some(keep_label))
+                if !self
+                    .store
+                    .layer_store
+                    .layer_is_ancestor_of(layer.name(), old_head.name())
+                    .await? =>
+            {
+                (old_head, keep_label)
+            }
+            _ => {
+                self.set_head_if(head.as_ref(), layer).await?;
+                return Ok(head);
+            }
+        };
+
+        let (keep, created) = match self.store.open(keep_label).await? {
+            Some(keep) => (keep, false),
+            None => (self.store.create(keep_label).await?, true),
+        };
+        // both labels are moved together, so that a conflict on this
+        // database doesn't leave the other one moved
+        let result: Result<(), SetLabelError> = loop {
+            let keep_head = keep.head().await?;
+            let updates = [
+                (&keep, keep_head.as_ref(), old_head),
+                (self, head.as_ref(), layer),
+            ];
+            match self.store.set_heads_if(&updates).await {
+                Ok(()) => break Ok(()),
+                // the other database is overwritten regardless, so try again
+                Err(SetLabelsError::Conflict { label, .. }) if label == keep.name() => continue,
+                Err(SetLabelsError::Conflict { conflict, .. }) => break Err(conflict.into()),
+                Err(SetLabelsError::Io(e)) => break Err(e.into()),
+            }
+        };
+        if result.is_err() && created {
+            self.store.delete(keep_label).await?;
+        }
+        result?;
+
+        Ok(head)
+    }
+
+    /// Move the head of this database forward to the head of `other`.
+    ///
+    /// This only succeeds if the current head is an ancestor of the
+    /// head of `other`, or if this database has no head yet. Returns
+    /// false without changing anything otherwise. If the head of this
+    /// database changes while fast-forwarding, a
+    /// `SetLabelError::Conflict` is returned.
+    pub async fn fast_forward_to(&self, other: &NamedGraph) -> Result<bool, SetLabelError> {
+        let _guard = self.store.gc_lock.read().await;
+        let head = self.head().await?.map(|l| l.name());
+        let target = match other.head().await? {
+            None => return Ok(head.is_none()),
+            Some(target) => target.name(),
+        };
+        if head == Some(target) {
+            return Ok(true);
+        }
+        if let Some(head) = head {
+            if !self
+                .store
+                .layer_store
+                .layer_is_ancestor_of(target, head)
+                .await?
+            {
+                return Ok(false);
+            }
+        }
+
+        self.store
+            .label_store
+            .set_label_if(&self.label, head, Some(target))
+            .await?;
+        self.store
+            .notify_commit(&self.label, head, Some(target))
+            .await;
+
+        Ok(true)
+    }
+
+    /// Open a write transaction on this database.
+    ///
+    /// See `Transaction` for details.
+    pub async fn transaction(&self) -> io::Result<Transaction> {
+        Transaction::open(self.clone()).await
+    }
+
+    /// Watch this database for head changes.
+    ///
+    /// See `LabelStore::watch_label` for details.
+    pub async fn watch(&self) -> io::Result<LabelWatchStream> {
+        self.store.label_store.watch_label(&self.label).await
+    }
+
+    /// Retrieve all head changes of this database, oldest first.
+    ///
+    /// See `LabelStore::label_history` for details.
+    pub async fn history(&self) -> io::Result<LabelHistoryStream> {
+        self.store.label_store.label_history(&self.label).await
+    }
+
+    /// Retrieve the namespace prefixes of this database.
+    ///
+    /// See `LabelStore::label_prefixes` for details.
+    pub async fn prefixes(&self) -> io::Result<PrefixMap> {
+        self.store.label_store.label_prefixes(&self.label).await
+    }
+
+    /// Replace the namespace prefixes of this database.
+    ///
+    /// To change the prefixes together with the head, use
+    /// `Transaction::with_prefixes` instead.
+    pub async fn set_prefixes(&self, prefixes: PrefixMap) -> io::Result<()> {
+        self.store
+            .label_store
+            .set_label_prefixes(&self.label, prefixes)
+            .await
+    }
+
+    /// Returns the layer this database pointed at at the given time.
+    ///
+    /// This is looked up in the history of this database. Returns
+    /// None if the database had no head at that time, including when
+    /// its history does not go back that far. Fails with a `NotFound`
+    /// error if the layer it pointed at no longer exists. See
+    /// `head_as_of` for details.
+    pub async fn head_at(&self, time: SystemTime) -> io::Result<Option<StoreLayer>> {
+        self.head_as_of(HistoryPoint::Time(time)).await
+    }
+
+    /// Returns the layer this database pointed at at the given point in its history.
+    ///
+    /// See `HistoryPoint` for the kinds of points that can be looked
+    /// up. Returns None if the database had no head at that point,
+    /// including when its history does not go back that far.
+    ///
+    /// `Store::collect_garbage` keeps the earlier heads of a
+    /// database, so they can be read for as long as the database
+    /// exists. If the layer named by the history is gone anyway, for
+    /// example because the layer store was cleaned up by other means,
+    /// this fails with a `NotFound` error.
+    pub async fn head_as_of(&self, point: HistoryPoint) -> io::Result<Option<StoreLayer>> {
+        let mut history = self.history().await?;
+        let mut head = None;
+        while let Some(entry) = history.next().await {
+            let entry = entry?;
+            match point {
+                HistoryPoint::Time(time) if entry.timestamp > time => break,
+                HistoryPoint::Version(version) if entry.version > version => break,
+                _ => head = entry.layer,
+            }
+        }
+
+        let head = match head {
+            None => return Ok(None),
+            Some(head) => head,
+        };
+        match self.store.get_layer_from_id(head).await? {
+            Some(layer) => Ok(Some(layer)),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "layer {} from the history of the database no longer exists",
+                    crate::storage::name_to_string(head)
+                ),
+            )),
+        }
+    }
+
+    pub async fn delete(&self) -> io::Result<()> {
+        self.store.delete(&self.label).await.map(|_| ())
+    }
+}
+
+impl Store {
+    /// Create a new store from the given label and layer store.
+    pub fn new<Labels: 'static + LabelStore, Layers: 'static + LayerStore>(
+        label_store: Labels,
+        layer_store: Layers,
+    ) -> Store {
+        let (commit_events, _) = broadcast::channel(COMMIT_EVENT_CAPACITY);
+        Store {
+            label_store: Arc::new(label_store),
+            layer_store: Arc::new(layer_store),
+            commit_events,
+            layer_sizes: Default::default(),
+            pinned_layers: Default::default(),
+            committed_layers: Default::default(),
+            gc_lock: Default::default(),
+        }
+    }
+
+    /// Create a new database with the given name.
+    ///
     /// If the database already exists, this will return an error.
     pub async fn create(&self, label: &str) -> io::Result<NamedGraph> {
         let label = self.label_store.create_label(label).await?;
@@ -787,12 +1800,223 @@ impl Store {
         Ok(label.map(|label| NamedGraph::new(label.name, self.clone())))
     }
 
+    /// Create a new database pointing at the same head as an existing one.
+    ///
+    /// Both databases share all layers up to that head, and can be
+    /// written to independently afterwards. Fails with a `NotFound`
+    /// error if `from` does not exist, and with an error if
+    /// `new_label` already exists.
+    pub async fn branch(&self, from: &str, new_label: &str) -> io::Result<NamedGraph> {
+        let _guard = self.gc_lock.read().await;
+        let head = match self.label_store.get_label(from).await? {
+            Some(label) => label.layer,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "database not found",
+                ))
+            }
+        };
+        let label = self.label_store.create_label(new_label).await?;
+        if let Some(head) = head {
+            if self.label_store.set_label(&label, head).await?.is_some() {
+                self.notify_commit(&label.name, None, Some(head)).await;
+            }
+        }
+
+        Ok(NamedGraph::new(label.name, self.clone()))
+    }
+
+    /// List all databases whose head is the given layer or one of its descendants, ordered by name.
+    pub async fn branches_of(&self, layer: &StoreLayer) -> io::Result<Vec<NamedGraph>> {
+        let name = layer.name();
+        let mut labels = self.label_store.labels().await?;
+        labels.sort_by(|l1, l2| l1.name.cmp(&l2.name));
+
+        let mut result = Vec::new();
+        for label in labels {
+            if let Some(head) = label.layer {
+                if self.layer_store.layer_is_ancestor_of(head, name).await? {
+                    result.push(NamedGraph::new(label.name, self.clone()));
+                }
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Open a write transaction on the database with the given name.
+    ///
+    /// The transaction captures the current head of the database. On
+    /// commit, the database is only updated if it still points at
+    /// that head. See `Transaction` for details. If the database does
+    /// not exist, this returns a `NotFound` error.
+    pub async fn transaction(&self, label: &str) -> io::Result<Transaction> {
+        match self.open(label).await? {
+            Some(graph) => graph.transaction().await,
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "database not found",
+            )),
+        }
+    }
+
     /// Delete an existing database with the given name. Returns true if this database was deleted
     /// and false otherwise.
+    ///
+    /// Only the label is removed. The layers of the database stay
+    /// in the store until `collect_garbage` is called.
     pub async fn delete(&self, label: &str) -> io::Result<bool> {
         self.label_store.delete_label(label).await
     }
 
+    /// Point several databases at new layers at once.
+    ///
+    /// Each update is given as a database, the head it is expected to
+    /// point at, and the layer it should point at afterwards. Either
+    /// all databases are updated, or none are. If any database does
+    /// not point at its expected head, a `SetLabelsError::Conflict` is
+    /// returned for it. See `LabelStore::set_labels_if` for details.
+    pub async fn set_heads_if(
+        &self,
+        updates: &[(&NamedGraph, Option<&StoreLayer>, &StoreLayer)],
+    ) -> Result<(), SetLabelsError> {
+        let _guard = self.gc_lock.read().await;
+        let updates: Vec<_> = updates
+            .iter()
+            .map(|(graph, expected, layer)| {
+                LabelUpdate::new(graph.name(), expected.map(|l| l.name()), Some(layer.name()))
+            })
+            .collect();
+        self.label_store.set_labels_if(updates.clone()).await?;
+        for update in updates {
+            self.notify_commit(&update.name, update.expected_layer, update.new_layer)
+                .await;
+        }
+
+        Ok(())
+    }
+
+    /// Point the database with the given name at the layer with the given name.
+    ///
+    /// Fails with a `NotFound` error if either the database or the
+    /// layer does not exist. See `NamedGraph::reset` for details.
+    pub async fn reset(
+        &self,
+        label: &str,
+        layer: [u32; 5],
+        keep_abandoned_as: Option<&str>,
+    ) -> Result<Option<StoreLayer>, SetLabelError> {
+        let graph = self
+            .open(label)
+            .await?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "database not found"))?;
+        let layer = self
+            .get_layer_from_id(layer)
+            .await?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "layer not found"))?;
+
+        graph.reset(&layer, keep_abandoned_as).await
+    }
+
+    /// Returns the layer the database with the given name pointed at at the given point in its history.
+    ///
+    /// This allows reading a database as it was at some earlier
+    /// time. Fails with a `NotFound` error if the database does not
+    /// exist, or if the layer it pointed at no longer exists. See
+    /// `NamedGraph::head_as_of` for details.
+    pub async fn open_graph_at<P: Into<HistoryPoint>>(
+        &self,
+        label: &str,
+        point: P,
+    ) -> io::Result<Option<StoreLayer>> {
+        match self.open(label).await? {
+            Some(graph) => graph.head_as_of(point.into()).await,
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "database not found",
+            )),
+        }
+    }
+
+    /// Retrieve all head changes of the database with the given name, oldest first.
+    ///
+    /// See `LabelStore::label_history` for details.
+    pub async fn history(&self, label: &str) -> io::Result<LabelHistoryStream> {
+        self.label_store.label_history(label).await
+    }
+
+    /// List all databases in this store, ordered by name.
+    pub async fn graphs(&self) -> io::Result<Vec<NamedGraph>> {
+        let mut names: Vec<_> = self
+            .label_store
+            .labels()
+            .await?
+            .into_iter()
+            .map(|label| label.name)
+            .collect();
+        names.sort();
+
+        Ok(names
+            .into_iter()
+            .map(|name| NamedGraph::new(name, self.clone()))
+            .collect())
+    }
+
+    /// Rename a database, keeping its head.
+    ///
+    /// Fails with a `NotFound` error if the database does not exist,
+    /// and with an `InvalidInput` error if a database with the new
+    /// name already exists.
+    pub async fn rename(&self, label: &str, new_label: &str) -> io::Result<NamedGraph> {
+        let _guard = self.gc_lock.read().await;
+        let label = self.label_store.rename_label(label, new_label).await?;
+
+        Ok(NamedGraph::new(label.name, self.clone()))
+    }
+
+    /// Remove all layers that are not needed by any database or pinned layer.
+    ///
+    /// See `LayerStore::collect_garbage` for details. Layers that are
+    /// pointed at by a database, or were pointed at by it according
+    /// to its history, are kept, as are layers pinned with
+    /// `StoreLayer::pin`, or returned by `StoreLayerBuilder::commit`
+    /// and still held on to, and the ancestors of all of these. The
+    /// earlier heads of a database are only released once it is
+    /// deleted, which also deletes its history. Garbage collection waits for
+    /// commits and database updates through this store, or one of its
+    /// clones, to finish, and keeps them waiting until it is done.
+    /// Writes through other stores on the same storage are not
+    /// coordinated with, so garbage collection should not run while
+    /// they are in progress. Returns the names of the removed layers.
+    pub async fn collect_garbage(&self) -> io::Result<Vec<[u32; 5]>> {
+        let _guard = self.gc_lock.write().await;
+        let mut keep = Vec::new();
+        for label in self.label_store.labels().await? {
+            keep.extend(label.layer);
+            match self.label_store.label_history(&label.name).await {
+                Ok(mut history) => {
+                    while let Some(entry) = history.next().await {
+                        let entry = entry?;
+                        keep.extend(entry.previous_layer);
+                        keep.extend(entry.layer);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Unsupported => {}
+                Err(e) => return Err(e),
+            }
+        }
+        keep.extend(self.pinned_layers());
+        keep.extend(
+            self.committed_layers
+                .lock()
+                .expect("mutex should always lock")
+                .keys(),
+        );
+
+        self.layer_store.collect_garbage(keep).await
+    }
+
     /// Retrieve a layer with the given name from the layer store this Store was initialized with.
     pub async fn get_layer_from_id(&self, layer: [u32; 5]) -> io::Result<Option<StoreLayer>> {
         let layer = self.layer_store.get_layer(layer).await?;
@@ -806,6 +2030,45 @@ impl Store {
         StoreLayerBuilder::new(self.clone()).await
     }
 
+    /// Reopen the builder for the given layer from its last checkpoint.
+    ///
+    /// This returns None if there's no checkpoint for this layer,
+    /// for example because it has already been committed.
+    pub async fn resume_layer_builder(
+        &self,
+        name: [u32; 5],
+    ) -> io::Result<Option<StoreLayerBuilder>> {
+        let builder = self.layer_store.resume_layer_builder(name).await?;
+
+        Ok(builder.map(|builder| StoreLayerBuilder::wrap(builder, self.clone())))
+    }
+
+    /// Build a base layer from a stream of triples that may be larger than memory.
+    ///
+    /// Triples are sorted externally in scratch files as configured,
+    /// so memory use stays bounded no matter how many triples the
+    /// stream yields. After the layer is built, use `set_head` on a
+    /// `NamedGraph` to attach it.
+    pub async fn bulk_build_base_layer<
+        S: 'static + Stream<Item = io::Result<StringTriple>> + Send,
+    >(
+        &self,
+        triples: S,
+        config: BulkLoadConfig,
+    ) -> io::Result<StoreLayer> {
+        let name = self
+            .layer_store
+            .bulk_build_base_layer(Box::pin(triples), config)
+            .await?;
+        let name = self.layer_store.finalize_layer(name).await?;
+        let layer = self.layer_store.get_layer(name).await?;
+
+        Ok(StoreLayer::wrap(
+            layer.expect("layer that was just created was not found in store"),
+            self.clone(),
+        ))
+    }
+
     /// Export the given layers by creating a pack, a Vec<u8> that can later be used with `import_layers` on a different store.
     pub async fn export_layers(
         &self,
@@ -826,6 +2089,73 @@ impl Store {
     ) -> io::Result<()> {
         self.layer_store.import_layers(pack, layer_ids).await
     }
+
+    /// Copy the current state of every database in this store to the given store.
+    ///
+    /// This is safe to do while other writers keep using this
+    /// store. All labels are read first, after which only the layers
+    /// reachable from those labels are copied. Since layers are
+    /// immutable, this results in a consistent snapshot. The layers
+    /// are pinned until the backup is done, so garbage collection
+    /// does not remove them halfway through. Labels are only written
+    /// to the target once all layers are in place, so an interrupted
+    /// backup never leaves a label pointing at a missing layer.
+    ///
+    /// Layers that already exist in the target are not copied again,
+    /// making repeated backups to the same target incremental. Labels
+    /// in the target that don't exist in this store are left alone.
+    pub async fn backup(&self, target: &Store) -> io::Result<()> {
+        // the heads are pinned, so that garbage collection can't
+        // remove layers while they are being copied
+        let (labels, _pins) = {
+            let _guard = self.gc_lock.read().await;
+            let labels = self.label_store.labels().await?;
+            let mut pins = Vec::with_capacity(labels.len());
+            for layer in labels.iter().filter_map(|label| label.layer) {
+                let layer = self
+                    .get_layer_from_id(layer)
+                    .await?
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "layer not found"))?;
+                pins.push(layer.pin());
+            }
+
+            (labels, pins)
+        };
+
+        // nor from the target before the labels point at them
+        let _target_guard = target.gc_lock.read().await;
+        let existing: HashSet<[u32; 5]> = target.layer_store.layers().await?.into_iter().collect();
+        let mut seen = HashSet::new();
+        let mut missing = Vec::new();
+        for layer in labels.iter().filter_map(|label| label.layer) {
+            for name in self.layer_store.retrieve_layer_stack_names(layer).await? {
+                if !existing.contains(&name) && seen.insert(name) {
+                    missing.push(name);
+                }
+            }
+        }
+
+        if !missing.is_empty() {
+            let pack = self
+                .layer_store
+                .export_layers(Box::new(missing.clone().into_iter()))
+                .await?;
+            target
+                .layer_store
+                .import_layers(&pack, Box::new(missing.into_iter()))
+                .await?;
+        }
+
+        copy_labels(labels, &*target.label_store).await
+    }
+
+    /// Restore all databases from a backup made with `backup`.
+    ///
+    /// Databases in this store that are also in the backup are reset
+    /// to the state they have in the backup.
+    pub async fn restore(&self, backup: &Store) -> io::Result<()> {
+        backup.backup(self).await
+    }
 }
 
 /// Open a store that is entirely in memory.
@@ -839,17 +2169,30 @@ pub fn open_memory_store() -> Store {
 }
 
 /// Open a store that stores its data in the given directory.
+///
+/// Use `DirectoryStoreConfig` to open a store with a different
+/// configuration.
+#[cfg(feature = "fs")]
 pub fn open_directory_store<P: Into<PathBuf>>(path: P) -> Store {
-    let p = path.into();
-    Store::new(
-        DirectoryLabelStore::new(p.clone()),
-        CachedLayerStore::new(DirectoryLayerStore::new(p), LockingHashMapLayerCache::new()),
-    )
+    DirectoryStoreConfig::new(path).open()
+}
+
+/// Open a store that reads its data from the given directory, without ever writing to it.
+///
+/// No write locks are taken, so this is safe to use on a directory
+/// that another process is actively writing to, for example for
+/// backups or reporting. Any operation that would modify the store,
+/// such as creating a database, setting a head or creating a layer
+/// builder, will return a `io::ErrorKind::PermissionDenied` error.
+#[cfg(feature = "fs")]
+pub fn open_read_only_directory_store<P: Into<PathBuf>>(path: P) -> Store {
+    DirectoryStoreConfig::new(path).read_only().open()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::directory::{DirectoryLabelStore, DirectoryLayerStore};
     use tempfile::tempdir;
 
     async fn create_and_manipulate_database(store: Store) {
@@ -882,6 +2225,46 @@ mod tests {
         assert!(layer.string_triple_exists(&StringTriple::new_value("pig", "says", "oink")));
     }
 
+    #[tokio::test]
+    async fn bulk_load_into_database() {
+        let dir = tempdir().unwrap();
+        let scratch = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+        let database = store.create("bulkdb").await.unwrap();
+
+        let triples = (0..50).map(|i| {
+            Ok(StringTriple::new_value(
+                &format!("animal{}", i % 10),
+                "says",
+                &format!("sound{}", i),
+            ))
+        });
+        let config =
+            BulkLoadConfig::new(crate::storage::SpillConfig::new(8).in_directory(scratch.path()));
+        let layer = store
+            .bulk_build_base_layer(futures::stream::iter(triples), config)
+            .await
+            .unwrap();
+        assert!(database.set_head(&layer).await.unwrap());
+
+        let head = database.head().await.unwrap().unwrap();
+        assert_eq!(50, head.triple_addition_count());
+        assert!(head.string_triple_exists(&StringTriple::new_value("animal3", "says", "sound13")));
+
+        let failing = futures::stream::iter(vec![
+            Ok(StringTriple::new_value("cow", "says", "moo")),
+            Err(io::Error::new(io::ErrorKind::InvalidData, "bad input")),
+        ]);
+        let config =
+            BulkLoadConfig::new(crate::storage::SpillConfig::new(8).in_directory(scratch.path()));
+        let err = store
+            .bulk_build_base_layer(failing, config)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
     #[tokio::test]
     async fn create_and_manipulate_memory_database() {
         let store = open_memory_store();
@@ -889,6 +2272,72 @@ mod tests {
         create_and_manipulate_database(store).await;
     }
 
+    #[tokio::test]
+    async fn set_head_if_retries_on_conflict() {
+        let store = open_memory_store();
+        let database = store.create("foodb").await.unwrap();
+
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer1 = builder.commit().await.unwrap();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let layer2 = builder.commit().await.unwrap();
+
+        database.set_head_if(None, &layer1).await.unwrap();
+        let current_head = match database.set_head_if(None, &layer2).await {
+            Err(SetLabelError::Conflict(conflict)) => conflict.current_head,
+            _ => panic!("expected a conflict"),
+        };
+        assert_eq!(Some(layer1.name()), current_head);
+
+        let current_head = store
+            .get_layer_from_id(current_head.unwrap())
+            .await
+            .unwrap();
+        database
+            .set_head_if(current_head.as_ref(), &layer2)
+            .await
+            .unwrap();
+        assert_eq!(
+            layer2.name(),
+            database.head().await.unwrap().unwrap().name()
+        );
+    }
+
+    #[tokio::test]
+    async fn read_only_directory_store_sees_writes_but_refuses_them() {
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+        let read_only = open_read_only_directory_store(dir.path());
+
+        let database = store.create("foodb").await.unwrap();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer = builder.commit().await.unwrap();
+        assert!(database.set_head(&layer).await.unwrap());
+
+        let read_only_database = read_only.open("foodb").await.unwrap().unwrap();
+        let head = read_only_database.head().await.unwrap().unwrap();
+        assert_eq!(layer.name(), head.name());
+        assert!(head.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+
+        let err = read_only.create("bardb").await.err().unwrap();
+        assert_eq!(io::ErrorKind::PermissionDenied, err.kind());
+        let err = read_only.create_base_layer().await.err().unwrap();
+        assert_eq!(io::ErrorKind::PermissionDenied, err.kind());
+        let err = head.open_write().await.err().unwrap();
+        assert_eq!(io::ErrorKind::PermissionDenied, err.kind());
+        let err = read_only_database.set_head(&head).await.unwrap_err();
+        assert_eq!(io::ErrorKind::PermissionDenied, err.kind());
+    }
+
     #[tokio::test]
     async fn create_and_manipulate_directory_database() {
         let dir = tempdir().unwrap();
@@ -985,39 +2434,256 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn apply_a_base_delta() {
+    async fn squash_head_of_graph() {
         let store = open_memory_store();
-        let builder = store.create_base_layer().await.unwrap();
+        let database = store.create("foo").await.unwrap();
+        assert!(database.squash_head().await.unwrap().is_none());
 
+        let builder = store.create_base_layer().await.unwrap();
         builder
             .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
             .unwrap();
-
         let layer = builder.commit().await.unwrap();
+        database.set_head(&layer).await.unwrap();
+        assert_eq!(
+            layer.name(),
+            database.squash_head().await.unwrap().unwrap().name()
+        );
 
-        let builder2 = layer.open_write().await.unwrap();
-
-        builder2
-            .add_string_triple(StringTriple::new_value("dog", "says", "woof"))
+        let builder = layer.open_write().await.unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("cow", "says", "moo"))
             .unwrap();
-
-        let layer2 = builder2.commit().await.unwrap();
-
-        let delta_builder_1 = store.create_base_layer().await.unwrap();
-
-        delta_builder_1
+        builder
             .add_string_triple(StringTriple::new_value("dog", "says", "woof"))
             .unwrap();
-        delta_builder_1
-            .add_string_triple(StringTriple::new_value("cat", "says", "meow"))
+        let layer2 = builder.commit().await.unwrap();
+        database.set_head(&layer2).await.unwrap();
+
+        let squashed = database.squash_head().await.unwrap().unwrap();
+        let head = database.head().await.unwrap().unwrap();
+        assert_eq!(squashed.name(), head.name());
+        assert!(head.parent().await.unwrap().is_none());
+        assert!(head.string_triple_exists(&StringTriple::new_value("dog", "says", "woof")));
+        assert!(!head.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+
+        // the original stack is kept for history, even by garbage collection
+        let names = (layer.name(), layer2.name());
+        std::mem::drop((layer, layer2, head));
+        assert!(store.collect_garbage().await.unwrap().is_empty());
+        let old = database
+            .head_as_of(HistoryPoint::Version(2))
+            .await
+            .unwrap()
             .unwrap();
+        assert_eq!(names.1, old.name());
+        assert_eq!(Some(names.0), old.parent_name());
+        assert!(old
+            .parent()
+            .await
+            .unwrap()
+            .unwrap()
+            .string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        assert!(old.string_triple_exists(&StringTriple::new_value("dog", "says", "woof")));
+        assert!(database
+            .head()
+            .await
+            .unwrap()
+            .unwrap()
+            .string_triple_exists(&StringTriple::new_value("dog", "says", "woof")));
+    }
 
-        let delta_1 = delta_builder_1.commit().await.unwrap();
-
-        let delta_builder_2 = delta_1.open_write().await.unwrap();
-
-        delta_builder_2
-            .add_string_triple(StringTriple::new_value("crow", "says", "caw"))
+    #[tokio::test]
+    async fn diff_arbitrary_layers() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+
+        let builder = base.open_write().await.unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("duck", "likes", "pig"))
+            .unwrap();
+        let left = builder.commit().await.unwrap();
+
+        let builder = base.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("dog", "says", "woof"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("duck", "likes", "pig"))
+            .unwrap();
+        let right = builder.commit().await.unwrap();
+
+        let diff = left.diff(&right).await.unwrap();
+        let mut additions: Vec<_> = diff.string_additions().collect();
+        additions.sort();
+        let mut removals: Vec<_> = diff.string_removals().collect();
+        removals.sort();
+        assert_eq!(
+            vec![
+                StringTriple::new_value("cow", "says", "moo"),
+                StringTriple::new_value("dog", "says", "woof"),
+            ],
+            additions
+        );
+        assert_eq!(
+            vec![StringTriple::new_value("duck", "says", "quack")],
+            removals
+        );
+
+        let diff = base.diff(&left).await.unwrap();
+        assert_eq!(
+            vec![StringTriple::new_value("cow", "says", "moo")],
+            diff.string_removals().collect::<Vec<_>>()
+        );
+        assert_eq!(2, diff.additions().count());
+
+        // unrelated layers are compared too
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("dog", "says", "woof"))
+            .unwrap();
+        let other = builder.commit().await.unwrap();
+        let diff = right.diff(&other).await.unwrap();
+        let mut removals: Vec<_> = diff.string_removals().collect();
+        removals.sort();
+        assert_eq!(
+            vec![
+                StringTriple::new_value("cow", "says", "moo"),
+                StringTriple::new_node("duck", "likes", "pig"),
+            ],
+            removals
+        );
+        assert_eq!(0, diff.additions().count());
+        assert_eq!(0, right.diff(&right).await.unwrap().additions().count());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn store_quads_in_named_graphs() {
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_quad(StringQuad::new_value("farm", "cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_quad(StringQuad::new_value("farm", "pig", "says", "oink"))
+            .unwrap();
+        builder
+            .add_string_quad(StringQuad::new_node("zoo", "lion", "eats", "zebra"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+
+        // quads are not part of the default graph
+        assert!(!base.string_triple_exists(&StringTriple::new_value("pig", "says", "oink")));
+        assert_eq!(1, base.triple_count());
+
+        let builder = base.open_write().await.unwrap();
+        builder
+            .remove_string_quad(StringQuad::new_value("farm", "pig", "says", "oink"))
+            .unwrap();
+        builder
+            .remove_string_quad(StringQuad::new_value("farm", "duck", "says", "quack"))
+            .unwrap();
+        builder
+            .add_string_quad(StringQuad::new_value("zoo", "duck", "says", "quack"))
+            .unwrap();
+        let child = builder.commit().await.unwrap();
+
+        let quads = child.quads().await.unwrap();
+        assert_eq!(vec!["farm", "zoo"], quads.graphs());
+        let farm: Vec<_> = quads
+            .graph_triples("farm")
+            .into_iter()
+            .map(|t| child.id_triple_to_string(&t).unwrap())
+            .collect();
+        assert_eq!(vec![StringTriple::new_value("cow", "says", "moo")], farm);
+        assert!(quads.string_quad_exists(
+            &child,
+            &StringQuad::new_value("zoo", "duck", "says", "quack")
+        ));
+        assert!(base
+            .quads()
+            .await
+            .unwrap()
+            .string_quad_exists(&base, &StringQuad::new_value("farm", "pig", "says", "oink")));
+
+        // quads survive squashing and moving between stores
+        let squashed = child.squash().await.unwrap();
+        let quads = squashed.quads().await.unwrap();
+        assert_eq!(2, quads.graph_triples("zoo").len());
+        let pack = store
+            .export_layers(Box::new(vec![squashed.name()].into_iter()))
+            .await
+            .unwrap();
+        let store2 = open_memory_store();
+        store2
+            .import_layers(&pack, Box::new(vec![squashed.name()].into_iter()))
+            .await
+            .unwrap();
+        let imported = store2
+            .get_layer_from_id(squashed.name())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(imported.quads().await.unwrap().string_quad_exists(
+            &imported,
+            &StringQuad::new_node("zoo", "lion", "eats", "zebra")
+        ));
+    }
+
+    #[tokio::test]
+    async fn apply_a_base_delta() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+
+        let layer = builder.commit().await.unwrap();
+
+        let builder2 = layer.open_write().await.unwrap();
+
+        builder2
+            .add_string_triple(StringTriple::new_value("dog", "says", "woof"))
+            .unwrap();
+
+        let layer2 = builder2.commit().await.unwrap();
+
+        let delta_builder_1 = store.create_base_layer().await.unwrap();
+
+        delta_builder_1
+            .add_string_triple(StringTriple::new_value("dog", "says", "woof"))
+            .unwrap();
+        delta_builder_1
+            .add_string_triple(StringTriple::new_value("cat", "says", "meow"))
+            .unwrap();
+
+        let delta_1 = delta_builder_1.commit().await.unwrap();
+
+        let delta_builder_2 = delta_1.open_write().await.unwrap();
+
+        delta_builder_2
+            .add_string_triple(StringTriple::new_value("crow", "says", "caw"))
             .unwrap();
         delta_builder_2
             .remove_string_triple(StringTriple::new_value("cat", "says", "meow"))
@@ -1220,6 +2886,360 @@ mod tests {
         assert!(store.open("foo").await.unwrap().is_none());
     }
 
+    async fn manage_graphs(store: Store) {
+        store.create("foo").await.unwrap();
+        let bar = store.create("bar").await.unwrap();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+        let builder = base.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let child = builder.commit().await.unwrap();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let unused = builder.commit().await.unwrap();
+        let uncommitted = store.create_base_layer().await.unwrap();
+        bar.set_head(&child).await.unwrap();
+
+        let names: Vec<_> = store
+            .graphs()
+            .await
+            .unwrap()
+            .iter()
+            .map(|g| g.name().to_string())
+            .collect();
+        assert_eq!(vec!["bar", "foo"], names);
+
+        let baz = store.rename("bar", "baz").await.unwrap();
+        assert!(store.open("bar").await.unwrap().is_none());
+        assert_eq!(
+            Some(child.name()),
+            baz.head().await.unwrap().map(|l| l.name())
+        );
+        assert_eq!(
+            io::ErrorKind::InvalidInput,
+            store.rename("foo", "baz").await.err().unwrap().kind()
+        );
+        assert_eq!(
+            io::ErrorKind::NotFound,
+            store.rename("bar", "qux").await.err().unwrap().kind()
+        );
+
+        // committed layers are kept while they are held on to
+        assert!(store.collect_garbage().await.unwrap().is_empty());
+        let names = (base.name(), child.name(), unused.name());
+        std::mem::drop((base, child, unused));
+        let (base, child, unused) = names;
+        assert_eq!(vec![unused], store.collect_garbage().await.unwrap());
+        assert!(store.get_layer_from_id(unused).await.unwrap().is_none());
+        let head = store
+            .open("baz")
+            .await
+            .unwrap()
+            .unwrap()
+            .head()
+            .await
+            .unwrap();
+        assert!(head
+            .unwrap()
+            .string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        uncommitted
+            .add_string_triple(StringTriple::new_value("horse", "says", "neigh"))
+            .unwrap();
+        uncommitted.commit().await.unwrap();
+
+        baz.delete().await.unwrap();
+        let mut removed = store.collect_garbage().await.unwrap();
+        removed.sort();
+        let mut expected = vec![base, child, uncommitted.name()];
+        expected.sort();
+        assert_eq!(expected, removed);
+    }
+
+    #[tokio::test]
+    async fn manage_memory_graphs() {
+        manage_graphs(open_memory_store()).await
+    }
+
+    #[tokio::test]
+    async fn manage_directory_graphs() {
+        let dir = tempdir().unwrap();
+        manage_graphs(open_directory_store(dir.path())).await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn collect_garbage_during_commits() {
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+        let graph = store.create("foo").await.unwrap();
+
+        let writer = {
+            let graph = graph.clone();
+            tokio::spawn(async move {
+                for i in 0..20 {
+                    let transaction = graph.transaction().await.unwrap();
+                    transaction
+                        .builder()
+                        .add_string_triple(StringTriple::new_value(
+                            &format!("cow{}", i),
+                            "says",
+                            "moo",
+                        ))
+                        .unwrap();
+                    transaction.commit().await.unwrap();
+                }
+            })
+        };
+        while !writer.is_finished() {
+            store.collect_garbage().await.unwrap();
+            tokio::task::yield_now().await;
+        }
+        writer.await.unwrap();
+
+        store.collect_garbage().await.unwrap();
+        let head = graph.head().await.unwrap().unwrap();
+        assert_eq!(20, head.triples().count());
+        assert!(head.string_triple_exists(&StringTriple::new_value("cow0", "says", "moo")));
+    }
+
+    #[tokio::test]
+    async fn branch_and_fast_forward() {
+        let store = open_memory_store();
+        let main = store.create("main").await.unwrap();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+        main.set_head(&base).await.unwrap();
+
+        let feature = store.branch("main", "feature").await.unwrap();
+        assert_eq!(
+            Some(base.name()),
+            feature.head().await.unwrap().map(|l| l.name())
+        );
+        let empty = store.create("empty").await.unwrap();
+        let empty_branch = store.branch("empty", "empty_branch").await.unwrap();
+        assert!(empty_branch.head().await.unwrap().is_none());
+        assert_eq!(
+            io::ErrorKind::NotFound,
+            store.branch("missing", "other").await.err().unwrap().kind()
+        );
+        assert!(store.branch("main", "feature").await.is_err());
+
+        let builder = base.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let child = builder.commit().await.unwrap();
+        feature.set_head(&child).await.unwrap();
+
+        let names = |graphs: Vec<NamedGraph>| -> Vec<String> {
+            graphs.iter().map(|g| g.name().to_string()).collect()
+        };
+        assert_eq!(
+            vec!["feature", "main"],
+            names(store.branches_of(&base).await.unwrap())
+        );
+        assert_eq!(
+            vec!["feature"],
+            names(store.branches_of(&child).await.unwrap())
+        );
+
+        assert!(!feature.fast_forward_to(&main).await.unwrap());
+        assert!(main.fast_forward_to(&feature).await.unwrap());
+        assert_eq!(
+            Some(child.name()),
+            main.head().await.unwrap().map(|l| l.name())
+        );
+        assert!(empty.fast_forward_to(&feature).await.unwrap());
+        assert_eq!(
+            Some(child.name()),
+            empty.head().await.unwrap().map(|l| l.name())
+        );
+        assert!(!main.fast_forward_to(&empty_branch).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn graph_head_at_time() {
+        let store = open_memory_store();
+        let graph = store.create("foo").await.unwrap();
+        let before = SystemTime::now();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+        graph.set_head(&base).await.unwrap();
+        let between = SystemTime::now();
+        let builder = base.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let child = builder.commit().await.unwrap();
+        graph.set_head(&child).await.unwrap();
+
+        let history: Vec<_> = store
+            .history("foo")
+            .await
+            .unwrap()
+            .map(|entry| entry.unwrap().layer)
+            .collect()
+            .await;
+        assert_eq!(vec![Some(base.name()), Some(child.name())], history);
+
+        let name_at = |time| {
+            let graph = graph.clone();
+            async move { graph.head_at(time).await.unwrap().map(|l| l.name()) }
+        };
+        assert_eq!(None, name_at(before).await);
+        assert_eq!(Some(base.name()), name_at(between).await);
+        assert_eq!(Some(child.name()), name_at(SystemTime::now()).await);
+
+        let name_as_of = |point| {
+            let store = store.clone();
+            async move {
+                store
+                    .open_graph_at("foo", point)
+                    .await
+                    .unwrap()
+                    .map(|l| l.name())
+            }
+        };
+        assert_eq!(None, name_as_of(HistoryPoint::Version(0)).await);
+        assert_eq!(
+            Some(base.name()),
+            name_as_of(HistoryPoint::Version(1)).await
+        );
+        assert_eq!(
+            Some(child.name()),
+            name_as_of(HistoryPoint::Version(5)).await
+        );
+        assert_eq!(Some(base.name()), name_as_of(between.into()).await);
+        assert_eq!(
+            io::ErrorKind::NotFound,
+            store
+                .open_graph_at("bar", before)
+                .await
+                .err()
+                .unwrap()
+                .kind()
+        );
+
+        // earlier heads survive garbage collection
+        let other = store
+            .create_base_layer()
+            .await
+            .unwrap()
+            .commit()
+            .await
+            .unwrap();
+        graph.force_set_head(&other).await.unwrap();
+        let names = (base.name(), child.name());
+        std::mem::drop((base, child));
+        assert!(store.collect_garbage().await.unwrap().is_empty());
+        assert_eq!(Some(names.0), name_as_of(HistoryPoint::Version(1)).await);
+        assert_eq!(Some(names.0), name_at(between).await);
+        assert_eq!(
+            Some(other.name()),
+            name_as_of(HistoryPoint::Version(3)).await
+        );
+
+        // a head that is gone regardless is an error, rather than no head
+        store
+            .layer_store
+            .collect_garbage(vec![other.name()])
+            .await
+            .unwrap();
+        assert_eq!(
+            io::ErrorKind::NotFound,
+            store
+                .open_graph_at("foo", HistoryPoint::Version(1))
+                .await
+                .err()
+                .unwrap()
+                .kind()
+        );
+        assert_eq!(None, name_as_of(HistoryPoint::Version(0)).await);
+    }
+
+    #[tokio::test]
+    async fn reset_graph_to_ancestor() {
+        let store = open_memory_store();
+        let graph = store.create("foo").await.unwrap();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+        graph.set_head(&base).await.unwrap();
+        let builder = base.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let child = builder.commit().await.unwrap();
+        graph.set_head(&child).await.unwrap();
+
+        let head_name =
+            |graph: NamedGraph| async move { graph.head().await.unwrap().map(|l| l.name()) };
+
+        let old_head = store
+            .reset("foo", base.name(), Some("foo_undo"))
+            .await
+            .unwrap();
+        assert_eq!(Some(child.name()), old_head.map(|l| l.name()));
+        assert_eq!(Some(base.name()), head_name(graph.clone()).await);
+        let undo = store.open("foo_undo").await.unwrap().unwrap();
+        assert_eq!(Some(child.name()), head_name(undo).await);
+
+        // moving forward again abandons nothing
+        store
+            .reset("foo", child.name(), Some("bar_undo"))
+            .await
+            .unwrap();
+        assert_eq!(Some(child.name()), head_name(graph.clone()).await);
+        assert!(store.open("bar_undo").await.unwrap().is_none());
+
+        match store.reset("foo", [1, 2, 3, 4, 5], None).await {
+            Err(SetLabelError::Io(e)) => assert_eq!(io::ErrorKind::NotFound, e.kind()),
+            _ => panic!("expected a missing layer to be reported"),
+        }
+        match store.reset("bar", base.name(), None).await {
+            Err(SetLabelError::Io(e)) => assert_eq!(io::ErrorKind::NotFound, e.kind()),
+            _ => panic!("expected a missing database to be reported"),
+        }
+
+        // a conflicting reset leaves the database for abandoned layers alone
+        let other = store
+            .create_base_layer()
+            .await
+            .unwrap()
+            .commit()
+            .await
+            .unwrap();
+        for keep_label in ["foo_undo", "baz_undo"] {
+            match graph
+                .reset_from(Some(base.clone()), &other, Some(keep_label))
+                .await
+            {
+                Err(SetLabelError::Conflict(conflict)) => {
+                    assert_eq!(Some(child.name()), conflict.current_head)
+                }
+                _ => panic!("expected a conflict"),
+            }
+        }
+        assert_eq!(Some(child.name()), head_name(graph.clone()).await);
+        let undo = store.open("foo_undo").await.unwrap().unwrap();
+        assert_eq!(Some(child.name()), head_name(undo).await);
+        assert!(store.open("baz_undo").await.unwrap().is_none());
+    }
+
     #[tokio::test]
     async fn recreate_graph() {
         let dir = tempdir().unwrap();
@@ -1233,4 +3253,709 @@ mod tests {
         store.create("foo").await.unwrap();
         assert!(graph.head().await.unwrap().is_none());
     }
+
+    #[tokio::test]
+    async fn content_addressed_stores_agree_on_layer_names() {
+        async fn build_stack(store: &Store) -> (StoreLayer, StoreLayer) {
+            let builder = store.create_base_layer().await.unwrap();
+            builder
+                .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+                .unwrap();
+            let base = builder.commit().await.unwrap();
+            let builder = base.open_write().await.unwrap();
+            builder
+                .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+                .unwrap();
+            builder
+                .remove_string_triple(StringTriple::new_value("cow", "says", "moo"))
+                .unwrap();
+            let child = builder.commit().await.unwrap();
+
+            (base, child)
+        }
+
+        let dir = tempdir().unwrap();
+        let store1 = Store::new(
+            MemoryLabelStore::new(),
+            MemoryLayerStore::new().with_content_addressing(),
+        );
+        let store2 = Store::new(
+            DirectoryLabelStore::new(dir.path()),
+            CachedLayerStore::new(
+                DirectoryLayerStore::new(dir.path()).with_content_addressing(),
+                LockingHashMapLayerCache::new(),
+            ),
+        );
+
+        let (base1, child1) = build_stack(&store1).await;
+        let (base2, child2) = build_stack(&store2).await;
+        assert_eq!(base1.name(), base2.name());
+        assert_eq!(child1.name(), child2.name());
+        assert_eq!(Some(base1.name()), child1.parent_name());
+        assert!(child2.string_triple_exists(&StringTriple::new_value("pig", "says", "oink")));
+        assert!(!child2.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+
+        // without content addressing, names are random
+        let store3 = open_memory_store();
+        let (base3, _) = build_stack(&store3).await;
+        assert_ne!(base1.name(), base3.name());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn backup_and_restore_store() {
+        let store = open_memory_store();
+        let graph = store.create("foo").await.unwrap();
+        store.create("empty").await.unwrap();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+        let builder = base.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let child = builder.commit().await.unwrap();
+        graph.set_head(&child).await.unwrap();
+
+        // a layer that no label points at is not part of the backup
+        let unreachable = store
+            .create_base_layer()
+            .await
+            .unwrap()
+            .commit()
+            .await
+            .unwrap();
+
+        let dir = tempdir().unwrap();
+        let backup = open_directory_store(dir.path());
+        store.backup(&backup).await.unwrap();
+
+        let backup_graph = backup.open("foo").await.unwrap().unwrap();
+        let head = backup_graph.head().await.unwrap().unwrap();
+        assert_eq!(child.name(), head.name());
+        assert!(head.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        assert!(head.string_triple_exists(&StringTriple::new_value("pig", "says", "oink")));
+        let empty = backup.open("empty").await.unwrap().unwrap();
+        assert!(empty.head().await.unwrap().is_none());
+        assert!(backup
+            .get_layer_from_id(unreachable.name())
+            .await
+            .unwrap()
+            .is_none());
+
+        // backing up again only copies what changed
+        let builder = child.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let grandchild = builder.commit().await.unwrap();
+        graph.set_head(&grandchild).await.unwrap();
+        store.backup(&backup).await.unwrap();
+        let head = backup_graph.head().await.unwrap().unwrap();
+        assert_eq!(grandchild.name(), head.name());
+
+        let restored = open_memory_store();
+        restored.restore(&backup).await.unwrap();
+        let head = restored
+            .open("foo")
+            .await
+            .unwrap()
+            .unwrap()
+            .head()
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(head.string_triple_exists(&StringTriple::new_value("duck", "says", "quack")));
+        assert!(head.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+    }
+
+    #[tokio::test]
+    async fn apply_patch_counts_effective_changes() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+
+        let (child, counts) = base
+            .apply_patch(
+                vec![
+                    StringTriple::new_value("cow", "says", "moo"),
+                    StringTriple::new_value("duck", "says", "quack"),
+                    StringTriple::new_value("duck", "says", "quack"),
+                ],
+                vec![
+                    StringTriple::new_value("cow", "says", "moo"),
+                    StringTriple::new_value("pig", "says", "oink"),
+                ],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            PatchCounts {
+                additions: 1,
+                removals: 1
+            },
+            counts
+        );
+        assert_eq!(Some(base.name()), child.parent_name());
+        assert!(child.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        assert!(!child.string_triple_exists(&StringTriple::new_value("pig", "says", "oink")));
+        assert!(child.string_triple_exists(&StringTriple::new_value("duck", "says", "quack")));
+
+        let error = child
+            .apply_patch(
+                vec![StringTriple::new_value("horse", "says", "neigh")],
+                vec![StringTriple::new_value("pig", "says", "oink")],
+            )
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(io::ErrorKind::InvalidInput, error.kind());
+    }
+
+    #[tokio::test]
+    async fn ancestors_report_layer_stats() {
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+        let builder = base.open_write().await.unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let child = builder.commit().await.unwrap();
+
+        let ancestors = child.ancestors().await.unwrap();
+        assert_eq!(
+            vec![(child.name(), 0, 1), (base.name(), 2, 0)],
+            ancestors
+                .iter()
+                .map(|stats| (stats.name, stats.additions, stats.removals))
+                .collect::<Vec<_>>()
+        );
+        assert!(ancestors.iter().all(|stats| stats.file_size > 0));
+    }
+
+    #[tokio::test]
+    async fn compact_drops_unused_strings() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("pig", "likes", "cow"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+        let builder = base.open_write().await.unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_node("pig", "likes", "cow"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let child = builder.commit().await.unwrap();
+
+        let (compacted, remapping) = child.compact().await.unwrap();
+        assert!(compacted.parent_name().is_none());
+        assert_eq!(
+            LayerCounts {
+                node_count: 2,
+                predicate_count: 1,
+                value_count: 2,
+                triple_addition_count: 2,
+                triple_removal_count: 0,
+                layer_triple_addition_count: 2,
+                layer_triple_removal_count: 0,
+            },
+            compacted.all_counts()
+        );
+
+        for triple in child.triples() {
+            let remapped = remapping.triple(triple).unwrap();
+            assert!(compacted.id_triple_exists(remapped));
+            assert_eq!(
+                child.id_triple_to_string(&triple),
+                compacted.id_triple_to_string(&remapped)
+            );
+        }
+        assert_eq!(
+            None,
+            remapping.node_value_id(child.subject_id("pig").unwrap())
+        );
+        assert_eq!(
+            None,
+            remapping.predicate_id(child.predicate_id("likes").unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn translate_ids_between_sibling_layers() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+
+        let builder = base.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("horse", "likes", "cow"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let left = builder.commit().await.unwrap();
+
+        let builder = base.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("aardvark", "likes", "horse"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("bison", "likes", "aardvark"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("horse", "says", "neigh"))
+            .unwrap();
+        let right = builder.commit().await.unwrap();
+
+        assert_eq!(Some(base.all_counts()), left.common_ancestor_counts(&right));
+
+        let cow = left.subject_id("cow").unwrap();
+        assert_eq!(Some(cow), left.translate_subject_id(cow, &right));
+        let says = left.predicate_id("says").unwrap();
+        assert_eq!(Some(says), left.translate_predicate_id(says, &right));
+        let moo = left.object_value_id("moo").unwrap();
+        assert_eq!(Some(moo), left.translate_object_id(moo, &right));
+
+        let horse = left.subject_id("horse").unwrap();
+        assert_ne!(right.subject_id("horse"), Some(horse));
+        assert_eq!(
+            right.subject_id("horse"),
+            left.translate_subject_id(horse, &right)
+        );
+        assert_eq!(
+            right.object_node_id("horse"),
+            left.translate_object_id(horse, &right)
+        );
+        let likes = left.predicate_id("likes").unwrap();
+        assert_eq!(
+            right.predicate_id("likes"),
+            left.translate_predicate_id(likes, &right)
+        );
+
+        let duck = left.subject_id("duck").unwrap();
+        assert_eq!(None, left.translate_subject_id(duck, &right));
+        let quack = left.object_value_id("quack").unwrap();
+        assert_eq!(None, left.translate_object_id(quack, &right));
+    }
+
+    #[tokio::test]
+    async fn truncate_history_replaces_ancestors() {
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+        let mut layer = store
+            .create_base_layer()
+            .await
+            .unwrap()
+            .commit()
+            .await
+            .unwrap();
+        let mut names = vec![layer.name()];
+        for animal in ["cow", "pig", "duck"] {
+            let builder = layer.open_write().await.unwrap();
+            builder
+                .add_string_triple(StringTriple::new_node(animal, "lives_on", "farm"))
+                .unwrap();
+            layer = builder.commit().await.unwrap();
+            names.push(layer.name());
+        }
+        let pig = store.get_layer_from_id(names[2]).await.unwrap().unwrap();
+        let builder = pig.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("horse", "lives_on", "farm"))
+            .unwrap();
+        let sibling = builder.commit().await.unwrap();
+        let duck = layer;
+
+        let truncated = duck.truncate_history().await.unwrap();
+        let base = truncated.parent_name().unwrap();
+        assert!(!names.contains(&base));
+        assert_eq!(duck.content_digest(), truncated.content_digest());
+        assert_eq!(duck.subject_id("duck"), truncated.subject_id("duck"),);
+
+        // a fresh store only sees the new ancestry
+        let store = open_directory_store(dir.path());
+        let duck = store.get_layer_from_id(names[3]).await.unwrap().unwrap();
+        assert_eq!(
+            vec![base, names[3]],
+            duck.retrieve_layer_stack_names().await.unwrap()
+        );
+        let sibling = store
+            .get_layer_from_id(sibling.name())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some(base), sibling.parent_name());
+        assert!(sibling.string_triple_exists(&StringTriple::new_node("pig", "lives_on", "farm")));
+        assert!(sibling.string_triple_exists(&StringTriple::new_node("horse", "lives_on", "farm")));
+
+        let base_layer = store.get_layer_from_id(names[0]).await.unwrap().unwrap();
+        assert_eq!(
+            None,
+            base_layer.truncate_history().await.unwrap().parent_name()
+        );
+    }
+
+    #[tokio::test]
+    async fn squash_upto_keeps_only_net_changes() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+
+        let builder = base.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        builder
+            .add_string_quad(StringQuad::new_value("farm", "duck", "says", "quack"))
+            .unwrap();
+        let child1 = builder.commit().await.unwrap();
+
+        let builder = child1.open_write().await.unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("horse", "says", "neigh"))
+            .unwrap();
+        builder
+            .remove_string_quad(StringQuad::new_value("farm", "duck", "says", "quack"))
+            .unwrap();
+        builder
+            .add_string_quad(StringQuad::new_value("farm", "cow", "says", "moo"))
+            .unwrap();
+        let child2 = builder.commit().await.unwrap();
+
+        let squashed = child2.squash_upto(&base).await.unwrap();
+        assert_eq!(Some(base.name()), squashed.parent_name());
+        assert_eq!(1, squashed.triple_layer_addition_count().await.unwrap());
+        assert_eq!(0, squashed.triple_layer_removal_count().await.unwrap());
+        assert_eq!(child2.content_digest(), squashed.content_digest());
+        let quads = squashed.quads().await.unwrap();
+        assert_eq!(vec!["farm".to_string()], quads.graphs());
+        assert_eq!(1, quads.graph_triples("farm").len());
+
+        match base.squash_upto(&child2).await {
+            Err(e) => assert_eq!(io::ErrorKind::InvalidInput, e.kind()),
+            Ok(_) => panic!("squashed upto a descendant"),
+        }
+    }
+
+    #[tokio::test]
+    async fn merge_divergent_layers() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let ancestor = builder.commit().await.unwrap();
+
+        let builder = ancestor.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("horse", "says", "neigh"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "knor"))
+            .unwrap();
+        let ours = builder.commit().await.unwrap();
+
+        let builder = ancestor.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("horse", "says", "neigh"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("sheep", "says", "baa"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "groin"))
+            .unwrap();
+        let theirs = builder.commit().await.unwrap();
+
+        let (merged, report) = ours.merge(&theirs, &ancestor).await.unwrap();
+        assert_eq!(Some(ours.name()), merged.parent_name());
+        assert_eq!(1, report.additions);
+        assert_eq!(1, report.removals);
+        assert_eq!(
+            vec![MergeConflict {
+                subject: "pig".to_string(),
+                predicate: "says".to_string(),
+                ours_added: vec![StringTriple::new_value("pig", "says", "knor")],
+                ours_removed: vec![StringTriple::new_value("pig", "says", "oink")],
+                theirs_added: vec![StringTriple::new_value("pig", "says", "groin")],
+                theirs_removed: vec![StringTriple::new_value("pig", "says", "oink")],
+            }],
+            report.conflicts
+        );
+
+        let mut triples: Vec<_> = merged
+            .triples()
+            .map(|t| merged.id_triple_to_string(&t).unwrap())
+            .collect();
+        triples.sort();
+        let mut expected = vec![
+            StringTriple::new_value("cow", "says", "moo"),
+            StringTriple::new_value("horse", "says", "neigh"),
+            StringTriple::new_value("pig", "says", "knor"),
+            StringTriple::new_value("sheep", "says", "baa"),
+        ];
+        expected.sort();
+        assert_eq!(expected, triples);
+    }
+
+    #[tokio::test]
+    async fn layers_keep_their_metadata() {
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let metadata = LayerMetadata::new()
+            .with_message("initial animals")
+            .with_author("farmer")
+            .with_timestamp(std::time::SystemTime::now())
+            .with_property("source", "farm.csv");
+        builder.set_metadata(metadata.clone()).unwrap();
+        let base = builder.commit().await.unwrap();
+
+        let builder = base.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let child = builder.commit().await.unwrap();
+
+        let store = open_directory_store(dir.path());
+        let base = store.get_layer_from_id(base.name()).await.unwrap().unwrap();
+        assert_eq!(Some(metadata), base.metadata().await.unwrap());
+        let child = store
+            .get_layer_from_id(child.name())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(None, child.metadata().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn find_values_by_text() {
+        let dir = tempdir().unwrap();
+        let store = DirectoryStoreConfig::new(dir.path())
+            .with_text_index()
+            .open();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "Moo, moo!"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "name", "Bessie the cow"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+        let builder = base.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_lang_string(
+                "pig",
+                "says",
+                "groin groin",
+                "fr",
+            ))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value(
+                "pig",
+                "name",
+                "Babe, a pig that says moo",
+            ))
+            .unwrap();
+        let child = builder.commit().await.unwrap();
+
+        let value_id = |value: &str| child.object_value_id(value).unwrap();
+        let ids: Vec<_> = child
+            .values_matching_text("MOO")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(
+            vec![value_id("Moo, moo!"), value_id("Babe, a pig that says moo")],
+            ids
+        );
+        let groin = value_id(
+            &ObjectType::new_lang_string("groin groin", "fr")
+                .value_string()
+                .unwrap(),
+        );
+        assert_eq!(
+            vec![groin],
+            child
+                .values_matching_text("groin")
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>()
+        );
+        assert!(base.values_matching_text("groin").await.unwrap().is_empty());
+
+        // without text indexing, there's nothing to match against
+        let store = open_directory_store(dir.path());
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer = builder.commit().await.unwrap();
+        assert!(layer.values_matching_text("moo").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn remove_triples_matching_pattern() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("cow", "likes", "pig"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("cow", "likes", "duck"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("pig", "likes", "cow"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+
+        let cow = base.subject_id("cow").unwrap();
+        let likes = base.predicate_id("likes").unwrap();
+        let builder = base.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("cow", "likes", "horse"))
+            .unwrap();
+        assert_eq!(
+            2,
+            builder
+                .remove_matching(Some(cow), Some(likes), None)
+                .unwrap()
+        );
+        let child = builder.commit().await.unwrap();
+
+        let mut triples: Vec<_> = child
+            .triples()
+            .map(|t| child.id_triple_to_string(&t).unwrap())
+            .collect();
+        triples.sort();
+        let mut expected = vec![
+            StringTriple::new_value("cow", "says", "moo"),
+            StringTriple::new_node("cow", "likes", "horse"),
+            StringTriple::new_node("pig", "likes", "cow"),
+        ];
+        expected.sort();
+        assert_eq!(expected, triples);
+    }
+
+    #[tokio::test]
+    async fn compare_layer_contents() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+
+        let builder = base.open_write().await.unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let smaller = builder.commit().await.unwrap();
+
+        let builder = base.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("cow", "likes", "duck"))
+            .unwrap();
+        let larger = builder.commit().await.unwrap();
+
+        // same ids through a common ancestor
+        assert!(smaller.is_subset_of(&base));
+        assert!(base.is_subset_of(&larger));
+        assert!(smaller.is_subset_of(&larger));
+        assert!(!larger.is_subset_of(&base));
+        assert!(!base.same_content(&smaller));
+        assert!(base.same_content(&base));
+
+        // different ids in an unrelated layer
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("cow", "likes", "duck"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let unrelated = builder.commit().await.unwrap();
+
+        assert!(unrelated.same_content(&larger));
+        assert!(larger.same_content(&unrelated));
+        assert!(base.is_subset_of(&unrelated));
+        assert!(!unrelated.is_subset_of(&base));
+    }
 }
@@ -0,0 +1,156 @@
+//! Keeping layers alive while they are in use.
+//!
+//! A long-running query may read from a layer that no database needs
+//! anymore, because the database it was opened from was deleted.
+//! Pinning the layer keeps it, and all layers it depends on, from
+//! being removed by `Store::collect_garbage`. Pins only protect layers
+//! from garbage collection by the same `Store`, or one of its clones.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use super::{Store, StoreLayer};
+use crate::layer::Layer;
+
+/// The amount of pins on each pinned layer.
+pub(crate) type PinnedLayers = Arc<Mutex<HashMap<[u32; 5], usize>>>;
+
+/// One pin on a layer, which is removed again when this is dropped.
+pub(crate) struct PinCount {
+    name: [u32; 5],
+    pins: PinnedLayers,
+}
+
+impl PinCount {
+    pub(crate) fn new(name: [u32; 5], pins: &PinnedLayers) -> Self {
+        *pins
+            .lock()
+            .expect("mutex should always lock")
+            .entry(name)
+            .or_insert(0) += 1;
+
+        Self {
+            name,
+            pins: pins.clone(),
+        }
+    }
+}
+
+impl Drop for PinCount {
+    fn drop(&mut self) {
+        let mut pins = self.pins.lock().expect("mutex should always lock");
+        let count = pins
+            .get_mut(&self.name)
+            .expect("pinned layer should have a count");
+        *count -= 1;
+        if *count == 0 {
+            pins.remove(&self.name);
+        }
+    }
+}
+
+/// A handle keeping a layer pinned, as returned by `StoreLayer::pin`.
+///
+/// The layer is unpinned when this handle is dropped. The handle also
+/// holds on to the layer itself, so that it stays in the layer cache.
+pub struct LayerPin {
+    layer: StoreLayer,
+    _count: PinCount,
+}
+
+impl LayerPin {
+    /// The pinned layer.
+    pub fn layer(&self) -> &StoreLayer {
+        &self.layer
+    }
+
+    /// Unpin the layer.
+    ///
+    /// This is the same as dropping the handle.
+    pub fn unpin(self) {}
+}
+
+impl Clone for LayerPin {
+    fn clone(&self) -> Self {
+        self.layer.pin()
+    }
+}
+
+impl StoreLayer {
+    /// Pin this layer, keeping it and its ancestors from being garbage collected until the returned handle is dropped.
+    ///
+    /// A layer may be pinned any amount of times. It stays pinned
+    /// until all handles are dropped.
+    pub fn pin(&self) -> LayerPin {
+        LayerPin {
+            _count: PinCount::new(self.name(), &self.store.pinned_layers),
+            layer: self.clone(),
+        }
+    }
+}
+
+impl Store {
+    /// The names of all layers that are currently pinned through this store.
+    pub fn pinned_layers(&self) -> Vec<[u32; 5]> {
+        let mut names: Vec<_> = self
+            .pinned_layers
+            .lock()
+            .expect("mutex should always lock")
+            .keys()
+            .cloned()
+            .collect();
+        names.sort();
+
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layer::{Layer, StringTriple};
+    use crate::store::open_directory_store;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn pinned_layers_survive_garbage_collection() {
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+        let graph = store.create("foo").await.unwrap();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+        let builder = base.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let child = builder.commit().await.unwrap();
+        graph.set_head(&child).await.unwrap();
+
+        let pin = child.pin();
+        let other_pin = pin.clone();
+        std::mem::drop((base, builder));
+        let new_head = store
+            .create_base_layer()
+            .await
+            .unwrap()
+            .commit()
+            .await
+            .unwrap();
+        graph.force_set_head(&new_head).await.unwrap();
+
+        assert_eq!(vec![child.name()], store.pinned_layers());
+        assert!(store.collect_garbage().await.unwrap().is_empty());
+        assert!(pin
+            .layer()
+            .string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+
+        pin.unpin();
+        assert_eq!(vec![child.name()], store.pinned_layers());
+        std::mem::drop(other_pin);
+        assert!(store.pinned_layers().is_empty());
+        std::mem::drop((child, new_head));
+        graph.delete().await.unwrap();
+        assert_eq!(3, store.collect_garbage().await.unwrap().len());
+    }
+}
@@ -0,0 +1,161 @@
+//! Pattern queries over string triples.
+//!
+//! These resolve the strings in a pattern to ids, run the pattern
+//! against the triple indexes, and resolve the matching triples back
+//! to strings. This is the simplest way to read from a layer, for
+//! consumers that never need to handle ids themselves.
+use std::pin::Pin;
+
+use futures::stream::Stream;
+
+use super::StoreLayer;
+use crate::layer::{Layer, ObjectType, StringTriple, Value};
+use crate::query::IterStream;
+
+/// A stream of string triples, as returned by `StoreLayer::query`.
+pub type StringTripleStream = Pin<Box<dyn Stream<Item = StringTriple> + Send>>;
+
+impl StoreLayer {
+    /// Stream all triples matching the given pattern as string triples, where None matches anything.
+    ///
+    /// The object is a literal value. Use `query_node` to match
+    /// triples with a node as their object. If any part of the
+    /// pattern is unknown to this layer, the stream is empty.
+    pub fn query(
+        &self,
+        subject: Option<&str>,
+        predicate: Option<&str>,
+        object: Option<Value>,
+    ) -> StringTripleStream {
+        Box::pin(IterStream::new(self.query_iter(
+            subject,
+            predicate,
+            object.map(ObjectType::from),
+        )))
+    }
+
+    /// Stream all triples matching the given pattern with a node as their object.
+    ///
+    /// See `query`.
+    pub fn query_node(
+        &self,
+        subject: Option<&str>,
+        predicate: Option<&str>,
+        object: &str,
+    ) -> StringTripleStream {
+        Box::pin(IterStream::new(self.query_iter(
+            subject,
+            predicate,
+            Some(ObjectType::Node(object.to_owned())),
+        )))
+    }
+
+    /// Iterator over the string triples matching the given pattern.
+    pub(crate) fn query_iter(
+        &self,
+        subject: Option<&str>,
+        predicate: Option<&str>,
+        object: Option<ObjectType>,
+    ) -> Box<dyn Iterator<Item = StringTriple> + Send> {
+        let empty =
+            || -> Box<dyn Iterator<Item = StringTriple> + Send> { Box::new(std::iter::empty()) };
+        let subject = match subject.map(|s| self.subject_id(s)) {
+            Some(None) => return empty(),
+            subject => subject.flatten(),
+        };
+        let predicate = match predicate.map(|p| self.predicate_id(p)) {
+            Some(None) => return empty(),
+            predicate => predicate.flatten(),
+        };
+        let object = match object.map(|o| self.object_id(&o)) {
+            Some(None) => return empty(),
+            object => object.flatten(),
+        };
+
+        let layer = self.clone();
+        Box::new(
+            self.triples_matching(subject, predicate, object)
+                .map(move |triple| {
+                    layer
+                        .id_triple_to_string(&triple)
+                        .expect("layer triple should resolve to strings")
+                }),
+        )
+    }
+
+    fn object_id(&self, object: &ObjectType) -> Option<u64> {
+        match object {
+            ObjectType::Node(node) => self.object_node_id(node),
+            value => self.object_value_id(&value.value_string()?),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::open_memory_store;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn query_string_triples() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_literal("cow", "legs", 4))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("cow", "likes", "pig"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+        let builder = base.open_write().await.unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_lang_string("pig", "says", "groin", "fr"))
+            .unwrap();
+        let layer = builder.commit().await.unwrap();
+
+        let mut says: Vec<_> = layer.query(None, Some("says"), None).collect().await;
+        says.sort();
+        assert_eq!(
+            vec![
+                StringTriple::new_value("cow", "says", "moo"),
+                StringTriple::new_lang_string("pig", "says", "groin", "fr"),
+            ],
+            says
+        );
+        assert_eq!(
+            vec![StringTriple::new_literal("cow", "legs", 4)],
+            layer
+                .query(Some("cow"), None, Some(4.into()))
+                .collect::<Vec<_>>()
+                .await
+        );
+        assert_eq!(
+            vec![StringTriple::new_node("cow", "likes", "pig")],
+            layer
+                .query_node(None, None, "pig")
+                .collect::<Vec<_>>()
+                .await
+        );
+        assert_eq!(4, layer.query(None, None, None).count().await);
+        // the object has to be a value, and the cow doesn't say oink
+        assert_eq!(0, layer.query(None, None, Some("pig".into())).count().await);
+        assert_eq!(
+            0,
+            layer
+                .query(Some("cow"), None, Some("oink".into()))
+                .count()
+                .await
+        );
+        assert_eq!(0, layer.query(Some("duck"), None, None).count().await);
+    }
+}
@@ -0,0 +1,134 @@
+//! Consistent views over several databases.
+use std::io;
+
+use super::{LayerPin, Store, StoreLayer};
+
+/// The heads of several databases as they were at a single moment, as returned by `Store::snapshot`.
+///
+/// The heads are pinned for as long as the snapshot is kept, so they
+/// remain readable even if the databases move on.
+pub struct Snapshot {
+    heads: Vec<(String, Option<LayerPin>)>,
+}
+
+impl Snapshot {
+    /// The head of the given database in this snapshot.
+    ///
+    /// Returns None if the database had no head, or is not part of this snapshot.
+    pub fn head(&self, graph: &str) -> Option<&StoreLayer> {
+        self.heads
+            .iter()
+            .find(|(name, _)| name == graph)
+            .and_then(|(_, pin)| pin.as_ref())
+            .map(LayerPin::layer)
+    }
+
+    /// The databases in this snapshot and their heads, in the order they were requested.
+    pub fn heads(&self) -> impl Iterator<Item = (&str, Option<&StoreLayer>)> {
+        self.heads
+            .iter()
+            .map(|(name, pin)| (name.as_str(), pin.as_ref().map(LayerPin::layer)))
+    }
+}
+
+impl Store {
+    /// Read the heads of the given databases as they were at a single moment.
+    ///
+    /// Unlike opening each database in turn, this never observes a
+    /// head change of one database without observing the changes
+    /// that were committed together with it, as with
+    /// `Transaction::commit_all`. Fails with a `NotFound` error if
+    /// any of the databases does not exist.
+    pub async fn snapshot(&self, graphs: &[&str]) -> io::Result<Snapshot> {
+        // garbage collection may not remove a head before it is pinned
+        let _guard = self.gc_lock.read().await;
+        let labels = self.label_store.get_labels(graphs).await?;
+        let mut heads = Vec::with_capacity(labels.len());
+        for (name, label) in graphs.iter().zip(labels) {
+            let label = label.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("database {} not found", name),
+                )
+            })?;
+            let pin = match label.layer {
+                None => None,
+                Some(layer) => {
+                    let layer = self.get_layer_from_id(layer).await?.ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::NotFound, "layer not found")
+                    })?;
+                    Some(layer.pin())
+                }
+            };
+            heads.push((label.name, pin));
+        }
+
+        Ok(Snapshot { heads })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::Layer;
+    use crate::store::{open_directory_store, open_memory_store, Transaction};
+    use tempfile::tempdir;
+
+    async fn read_snapshot(store: Store) {
+        let data = store.create("data").await.unwrap();
+        let schema = store.create("schema").await.unwrap();
+        store.create("empty").await.unwrap();
+        let transactions = vec![
+            data.transaction().await.unwrap(),
+            schema.transaction().await.unwrap(),
+        ];
+        let layers = Transaction::commit_all(transactions).await.unwrap();
+
+        let snapshot = store.snapshot(&["schema", "data", "empty"]).await.unwrap();
+        let other = store
+            .create_base_layer()
+            .await
+            .unwrap()
+            .commit()
+            .await
+            .unwrap();
+        data.force_set_head(&other).await.unwrap();
+
+        assert_eq!(
+            vec![
+                ("schema", Some(layers[1].name())),
+                ("data", Some(layers[0].name())),
+                ("empty", None)
+            ],
+            snapshot
+                .heads()
+                .map(|(name, head)| (name, head.map(|l| l.name())))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Some(layers[0].name()),
+            snapshot.head("data").map(|l| l.name())
+        );
+        assert!(store.pinned_layers().contains(&layers[0].name()));
+        assert_eq!(
+            io::ErrorKind::NotFound,
+            store
+                .snapshot(&["data", "nope"])
+                .await
+                .err()
+                .unwrap()
+                .kind()
+        );
+    }
+
+    #[tokio::test]
+    async fn read_memory_snapshot() {
+        read_snapshot(open_memory_store()).await
+    }
+
+    #[tokio::test]
+    async fn read_directory_snapshot() {
+        let dir = tempdir().unwrap();
+        read_snapshot(open_directory_store(dir.path())).await
+    }
+}
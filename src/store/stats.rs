@@ -0,0 +1,151 @@
+//! Store-wide statistics.
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, RwLock};
+
+use super::Store;
+
+/// Statistics about a single database, as part of `StoreStats`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GraphStats {
+    /// The name of the database.
+    pub name: String,
+    /// The layer the database points at.
+    pub head: Option<[u32; 5]>,
+    /// The amount of layers in the layer stack of the head.
+    pub layers: usize,
+    /// The total size in bytes of the layers in the layer stack of the head.
+    ///
+    /// Layers shared with other databases are counted for each of
+    /// them.
+    pub disk_usage: u64,
+}
+
+/// Statistics about a whole store, as returned by `Store::stats`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StoreStats {
+    /// Statistics for every database, ordered by name.
+    pub graphs: Vec<GraphStats>,
+    /// The amount of layers in the store.
+    pub layers: usize,
+    /// The total size in bytes of all layers in the store.
+    pub disk_usage: u64,
+    /// The amount of layers currently held in memory for reuse, or None if the layer store does not cache layers.
+    pub cached_layers: Option<usize>,
+}
+
+/// Sizes of layers that were already looked up by `Store::stats`.
+///
+/// The files of a committed layer never change, so their sizes only
+/// need to be looked up once. Rollups are not part of the layer size.
+pub(crate) type LayerSizes = Arc<RwLock<HashMap<[u32; 5], u64>>>;
+
+impl Store {
+    /// Collect statistics about this store.
+    ///
+    /// This walks the layer stacks of all databases, and looks up the
+    /// size of every layer in the store. Sizes of layers that are
+    /// part of a database are remembered, so later calls only look up
+    /// the sizes of new layers.
+    pub async fn stats(&self) -> io::Result<StoreStats> {
+        let mut labels = self.label_store.labels().await?;
+        labels.sort_by(|l1, l2| l1.name.cmp(&l2.name));
+
+        let mut graphs = Vec::with_capacity(labels.len());
+        for label in labels {
+            let mut stats = GraphStats {
+                name: label.name,
+                head: label.layer,
+                layers: 0,
+                disk_usage: 0,
+            };
+            if let Some(head) = label.layer {
+                let names = self.layer_store.retrieve_layer_stack_names(head).await?;
+                stats.layers = names.len();
+                for name in names {
+                    // these layers are committed, so their size is final
+                    stats.disk_usage += self.layer_size(name, true).await?;
+                }
+            }
+
+            graphs.push(stats);
+        }
+
+        let layers = self.layer_store.layers().await?;
+        let mut disk_usage = 0;
+        for &name in layers.iter() {
+            disk_usage += self.layer_size(name, false).await?;
+        }
+
+        Ok(StoreStats {
+            graphs,
+            layers: layers.len(),
+            disk_usage,
+            cached_layers: self.layer_store.cached_layer_count(),
+        })
+    }
+
+    async fn layer_size(&self, name: [u32; 5], remember: bool) -> io::Result<u64> {
+        if let Some(&size) = self.layer_sizes.read().unwrap().get(&name) {
+            return Ok(size);
+        }
+
+        let size = self.layer_store.layer_file_size(name).await?;
+        if remember {
+            self.layer_sizes.write().unwrap().insert(name, size);
+        }
+
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layer::{Layer, StringTriple};
+    use crate::store::open_memory_store;
+
+    #[tokio::test]
+    async fn collect_store_stats() {
+        let store = open_memory_store();
+        let foo = store.create("foo").await.unwrap();
+        let bar = store.create("bar").await.unwrap();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+        let builder = base.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let child = builder.commit().await.unwrap();
+        foo.set_head(&base).await.unwrap();
+        bar.set_head(&child).await.unwrap();
+
+        let stats = store.stats().await.unwrap();
+        assert_eq!(
+            vec![
+                ("bar", Some(child.name()), 2),
+                ("foo", Some(base.name()), 1)
+            ],
+            stats
+                .graphs
+                .iter()
+                .map(|g| (g.name.as_str(), g.head, g.layers))
+                .collect::<Vec<_>>()
+        );
+        let base_size = stats.graphs[1].disk_usage;
+        assert!(base_size > 0);
+        assert!(stats.graphs[0].disk_usage > base_size);
+        assert_eq!(2, stats.layers);
+        assert_eq!(stats.graphs[0].disk_usage, stats.disk_usage);
+        assert_eq!(Some(2), stats.cached_layers);
+
+        std::mem::drop((base, child, builder));
+        let stats = store.stats().await.unwrap();
+        assert_eq!(Some(0), stats.cached_layers);
+        assert_eq!(base_size, stats.graphs[1].disk_usage);
+    }
+}
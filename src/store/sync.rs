@@ -5,15 +5,36 @@
 //! without any futures. This is done by wrapping all the async calls
 //! in a sync wrapper that runs on a tokio runtime managed by this
 //! module.
-use futures::Future;
+//!
+//! Every type in this module is `Send` and `Sync`, and cheap to
+//! clone. Clones share the same underlying store, layer or builder,
+//! so they can be handed to other threads freely. Calls block the
+//! calling thread until the operation is done. They must not be made
+//! from within an async context, such as a tokio task, as blocking on
+//! the runtime from there panics. Use the async API there instead.
+use futures::{Future, StreamExt};
 use tokio::runtime::Runtime;
 
 use std::io;
+use std::ops::Bound;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
 
-use crate::layer::{IdTriple, Layer, LayerCounts, ObjectType, StringTriple};
+use crate::layer::{
+    BuildProgressReporter, BulkLoadConfig, IdTriple, Layer, LayerCounts, LayerDiff, LayerMetadata,
+    ObjectType, PredicateStats, QuadStack, Regex, StagingLayer, StringQuad, StringTriple, Value,
+};
+use crate::query::Rule;
+use crate::storage::{
+    Label, LabelHistoryEntry, LabelHistoryStream, LabelStore, LabelWatchStream, LayerStore,
+    PrefixMap, SetLabelError, SetLabelsError,
+};
 use crate::store::{
-    open_directory_store, open_memory_store, NamedGraph, Store, StoreLayer, StoreLayerBuilder,
+    open_directory_store, open_memory_store, open_read_only_directory_store, CommitEvent,
+    CommitEventStream, ConflictPolicy, FederatedStore, HistoryPoint, IdRemapping, LayerPin,
+    LayerStats, MergeReport, MissedCommitEvents, NamedGraph, PatchCounts, Snapshot, Store,
+    StoreLayer, StoreLayerBuilder, StoreStats, Transaction, TransactionError,
 };
 
 lazy_static! {
@@ -53,16 +74,52 @@ impl SyncStoreLayerBuilder {
         self.inner.name()
     }
 
+    /// Returns the parent layer this builder is building on top of, if any.
+    ///
+    /// If there's no parent, this returns None.
+    pub fn parent(&self) -> Option<Arc<dyn Layer>> {
+        self.inner.parent()
+    }
+
     /// Add a string triple.
-    pub fn add_string_triple(&self, triple: StringTriple) -> Result<(), io::Error> {
+    ///
+    /// Returns true if the triple is new, meaning that it neither
+    /// exists in the parent layers nor was added to this builder
+    /// before.
+    pub fn add_string_triple(&self, triple: StringTriple) -> Result<bool, io::Error> {
         self.inner.add_string_triple(triple)
     }
 
     /// Add an id triple.
-    pub fn add_id_triple(&self, triple: IdTriple) -> Result<(), io::Error> {
+    ///
+    /// The ids may be temporary ids from interning strings with
+    /// `intern_node`, `intern_predicate` and `intern_value`. Returns
+    /// true if the triple is new, like `add_string_triple`.
+    pub fn add_id_triple(&self, triple: IdTriple) -> Result<bool, io::Error> {
         self.inner.add_id_triple(triple)
     }
 
+    /// Get an id for the given node, to use in id triples added to or removed from this builder.
+    ///
+    /// See `StoreLayerBuilder::intern_node`.
+    pub fn intern_node(&self, node: &str) -> Result<u64, io::Error> {
+        self.inner.intern_node(node)
+    }
+
+    /// Get an id for the given predicate, to use in id triples added to or removed from this builder.
+    ///
+    /// See `StoreLayerBuilder::intern_node`.
+    pub fn intern_predicate(&self, predicate: &str) -> Result<u64, io::Error> {
+        self.inner.intern_predicate(predicate)
+    }
+
+    /// Get an id for the given value, to use in id triples added to or removed from this builder.
+    ///
+    /// See `StoreLayerBuilder::intern_node`.
+    pub fn intern_value(&self, value: &str) -> Result<u64, io::Error> {
+        self.inner.intern_value(value)
+    }
+
     /// Remove a string triple.
     pub fn remove_string_triple(&self, triple: StringTriple) -> Result<(), io::Error> {
         self.inner.remove_string_triple(triple)
@@ -73,6 +130,44 @@ impl SyncStoreLayerBuilder {
         self.inner.remove_id_triple(triple)
     }
 
+    /// Remove all triples of the parent layers matching the given pattern, where None matches anything.
+    ///
+    /// Triples added to this builder are not affected. Returns the
+    /// amount of removed triples.
+    pub fn remove_matching(
+        &self,
+        subject: Option<u64>,
+        predicate: Option<u64>,
+        object: Option<u64>,
+    ) -> Result<usize, io::Error> {
+        self.inner.remove_matching(subject, predicate, object)
+    }
+
+    /// Add a quad to a named graph.
+    pub fn add_string_quad(&self, quad: StringQuad) -> Result<(), io::Error> {
+        self.inner.add_string_quad(quad)
+    }
+
+    /// Remove a quad from a named graph.
+    pub fn remove_string_quad(&self, quad: StringQuad) -> Result<(), io::Error> {
+        self.inner.remove_string_quad(quad)
+    }
+
+    /// Set the descriptive metadata to store with this layer.
+    pub fn set_metadata(&self, metadata: LayerMetadata) -> Result<(), io::Error> {
+        self.inner.set_metadata(metadata)
+    }
+
+    /// Set a reporter that is called as the commit of this layer progresses.
+    pub fn set_progress(&self, progress: BuildProgressReporter) -> Result<(), io::Error> {
+        self.inner.set_progress(progress)
+    }
+
+    /// Durably store the string triples added and removed so far.
+    pub fn checkpoint(&self) -> Result<(), io::Error> {
+        self.inner.checkpoint()
+    }
+
     /// Returns a boolean result which is true if this builder has been committed, and false otherwise.
     pub fn committed(&self) -> bool {
         self.inner.committed()
@@ -98,6 +193,11 @@ impl SyncStoreLayerBuilder {
         task_sync(self.inner.apply_delta(&delta.inner))
     }
 
+    /// Add and remove the triples that were staged in the given staging layer.
+    pub fn apply_staged(&self, staged: &StagingLayer) -> Result<(), io::Error> {
+        self.inner.apply_staged(staged)
+    }
+
     /// Apply the changes required to change our parent layer into the given layer.
     pub fn apply_diff(&self, other: &SyncStoreLayer) -> Result<(), io::Error> {
         self.inner.apply_diff(&other.inner)
@@ -133,6 +233,95 @@ impl SyncStoreLayer {
         inner.map(SyncStoreLayerBuilder::wrap)
     }
 
+    /// Pin this layer, keeping it and its ancestors from being garbage collected until the returned handle is dropped.
+    ///
+    /// See `StoreLayer::pin` for details.
+    pub fn pin(&self) -> SyncLayerPin {
+        SyncLayerPin {
+            inner: self.inner.pin(),
+        }
+    }
+
+    /// Create an in-memory staging layer on top of this layer.
+    pub fn open_staging(&self) -> StagingLayer {
+        self.inner.open_staging()
+    }
+
+    /// Iterator over all triples matching the given pattern as string triples, where None matches anything.
+    ///
+    /// See `StoreLayer::query`.
+    pub fn query(
+        &self,
+        subject: Option<&str>,
+        predicate: Option<&str>,
+        object: Option<Value>,
+    ) -> Box<dyn Iterator<Item = StringTriple> + Send> {
+        self.inner
+            .query_iter(subject, predicate, object.map(ObjectType::from))
+    }
+
+    /// Iterator over all triples matching the given pattern with a node as their object.
+    ///
+    /// See `StoreLayer::query_node`.
+    pub fn query_node(
+        &self,
+        subject: Option<&str>,
+        predicate: Option<&str>,
+        object: &str,
+    ) -> Box<dyn Iterator<Item = StringTriple> + Send> {
+        self.inner.query_iter(
+            subject,
+            predicate,
+            Some(ObjectType::Node(object.to_owned())),
+        )
+    }
+
+    /// Create a child layer with the given triples added and removed.
+    ///
+    /// See `StoreLayer::apply_patch` for details.
+    pub fn apply_patch<
+        A: IntoIterator<Item = StringTriple>,
+        R: IntoIterator<Item = StringTriple>,
+    >(
+        &self,
+        additions: A,
+        removals: R,
+    ) -> Result<(SyncStoreLayer, PatchCounts), io::Error> {
+        let additions: Vec<_> = additions.into_iter().collect();
+        let removals: Vec<_> = removals.into_iter().collect();
+        let inner = task_sync(self.inner.apply_patch(additions, removals));
+
+        inner.map(|(layer, counts)| (SyncStoreLayer::wrap(layer), counts))
+    }
+
+    /// Load the descriptive metadata of this layer, if it has any.
+    pub fn metadata(&self) -> Result<Option<LayerMetadata>, io::Error> {
+        task_sync(self.inner.metadata())
+    }
+
+    /// The ids of all string values containing any of the words of the query, ranked by how well they match.
+    ///
+    /// See `StoreLayer::values_matching_text` for details.
+    pub fn values_matching_text(&self, query: &str) -> Result<Vec<(u64, f64)>, io::Error> {
+        task_sync(self.inner.values_matching_text(query))
+    }
+
+    /// Apply the rules to this layer, and commit everything they infer as a child layer.
+    ///
+    /// See `StoreLayer::materialize` for details.
+    pub fn materialize(&self, rules: &[Rule]) -> Result<SyncStoreLayer, io::Error> {
+        let inner = task_sync(self.inner.materialize(rules));
+
+        inner.map(SyncStoreLayer::wrap)
+    }
+
+    /// Collect statistics for this layer and all its ancestors.
+    ///
+    /// See `StoreLayer::ancestors` for details.
+    pub fn ancestors(&self) -> Result<Vec<LayerStats>, io::Error> {
+        task_sync(self.inner.ancestors())
+    }
+
     /// Returns the parent of this layer, if any, or None if this layer has no parent.
     pub fn parent(&self) -> Result<Option<SyncStoreLayer>, io::Error> {
         let inner = task_sync(self.inner.parent());
@@ -153,6 +342,38 @@ impl SyncStoreLayer {
         inner.map(SyncStoreLayer::wrap)
     }
 
+    /// Merge the changes that another layer made since a common ancestor into a new child layer of this layer.
+    ///
+    /// See `StoreLayer::merge`.
+    pub fn merge(
+        &self,
+        theirs: &SyncStoreLayer,
+        ancestor: &SyncStoreLayer,
+    ) -> Result<(SyncStoreLayer, MergeReport), io::Error> {
+        let (layer, report) = task_sync(self.inner.clone().merge(&theirs.inner, &ancestor.inner))?;
+
+        Ok((SyncStoreLayer::wrap(layer), report))
+    }
+
+    /// Replace the layers between this layer and the given ancestor with a single child layer of that ancestor.
+    ///
+    /// The new layer only contains the net changes, so it has the
+    /// same content as this layer with the minimal amount of
+    /// additions and removals.
+    pub fn squash_upto(&self, upto: &SyncStoreLayer) -> Result<SyncStoreLayer, io::Error> {
+        task_sync(self.inner.clone().squash_upto(&upto.inner)).map(SyncStoreLayer::wrap)
+    }
+
+    /// Squash this layer, and report how its ids map onto the ids of the squashed layer.
+    ///
+    /// Like `squash`, this creates a new base layer which only
+    /// contains the strings that are still in use.
+    pub fn compact(&self) -> Result<(SyncStoreLayer, IdRemapping), io::Error> {
+        let (layer, remapping) = task_sync(self.inner.clone().compact())?;
+
+        Ok((SyncStoreLayer::wrap(layer), remapping))
+    }
+
     /// Create a new rollup layer which rolls up all triples in this layer, as well as all its ancestors.
     ///
     /// It is a good idea to keep layer stacks small, meaning, to only
@@ -175,6 +396,13 @@ impl SyncStoreLayer {
         task_sync(self.inner.clone().rollup_upto(&upto.inner))
     }
 
+    /// Drop the history before this layer.
+    ///
+    /// See `StoreLayer::truncate_history` for details.
+    pub fn truncate_history(&self) -> Result<SyncStoreLayer, io::Error> {
+        task_sync(self.inner.truncate_history()).map(SyncStoreLayer::wrap)
+    }
+
     /// Like rollup_upto, rolls up upto the given layer. However, if
     /// this layer is a rollup layer, this will roll up upto that
     /// rollup.
@@ -337,6 +565,18 @@ impl SyncStoreLayer {
     pub fn retrieve_layer_stack_names(&self) -> io::Result<Vec<[u32; 5]>> {
         task_sync(self.inner.retrieve_layer_stack_names())
     }
+
+    /// Load the named graph quads of this layer.
+    pub fn quads(&self) -> io::Result<QuadStack> {
+        task_sync(self.inner.quads())
+    }
+
+    /// Compute the difference between this layer and the given layer.
+    ///
+    /// See `StoreLayer::diff` for details.
+    pub fn diff(&self, other: &SyncStoreLayer) -> io::Result<LayerDiff> {
+        task_sync(self.inner.diff(&other.inner))
+    }
 }
 
 impl Layer for SyncStoreLayer {
@@ -372,6 +612,38 @@ impl Layer for SyncStoreLayer {
         self.inner.object_value_id(object)
     }
 
+    fn subject_exists(&self, subject: &str) -> bool {
+        self.inner.subject_exists(subject)
+    }
+
+    fn predicate_exists(&self, predicate: &str) -> bool {
+        self.inner.predicate_exists(predicate)
+    }
+
+    fn object_node_exists(&self, object: &str) -> bool {
+        self.inner.object_node_exists(object)
+    }
+
+    fn object_value_exists(&self, object: &str) -> bool {
+        self.inner.object_value_exists(object)
+    }
+
+    fn value_ids_in_range(&self, start: Bound<&str>, end: Bound<&str>) -> Vec<u64> {
+        self.inner.value_ids_in_range(start, end)
+    }
+
+    fn node_ids_matching(&self, regex: &Regex) -> Vec<u64> {
+        self.inner.node_ids_matching(regex)
+    }
+
+    fn predicate_ids_matching(&self, regex: &Regex) -> Vec<u64> {
+        self.inner.predicate_ids_matching(regex)
+    }
+
+    fn value_ids_matching(&self, regex: &Regex) -> Vec<u64> {
+        self.inner.value_ids_matching(regex)
+    }
+
     fn id_subject(&self, id: u64) -> Option<String> {
         self.inner.id_subject(id)
     }
@@ -396,6 +668,14 @@ impl Layer for SyncStoreLayer {
         self.inner.triples_s(subject)
     }
 
+    fn triples_subject_range(
+        &self,
+        start: u64,
+        end: u64,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triples_subject_range(start, end)
+    }
+
     fn triples_sp(
         &self,
         subject: u64,
@@ -412,6 +692,31 @@ impl Layer for SyncStoreLayer {
         self.inner.triples_o(object)
     }
 
+    fn ancestor_counts(&self, name: [u32; 5]) -> Option<LayerCounts> {
+        self.inner.ancestor_counts(name)
+    }
+
+    fn common_ancestor_counts(&self, other: &dyn Layer) -> Option<LayerCounts> {
+        self.inner.common_ancestor_counts(other)
+    }
+
+    fn estimate_count(
+        &self,
+        subject: Option<u64>,
+        predicate: Option<u64>,
+        object: Option<u64>,
+    ) -> usize {
+        self.inner.estimate_count(subject, predicate, object)
+    }
+
+    fn predicate_stats(&self, predicate: u64) -> PredicateStats {
+        self.inner.predicate_stats(predicate)
+    }
+
+    fn sample_triples(&self, n: usize, seed: u64) -> Vec<IdTriple> {
+        self.inner.sample_triples(n, seed)
+    }
+
     fn clone_boxed(&self) -> Box<dyn Layer> {
         Box::new(self.clone())
     }
@@ -470,6 +775,27 @@ impl SyncNamedGraph {
         task_sync(self.inner.set_head(&layer.inner))
     }
 
+    /// Set the database label to the given layer, provided it currently points at `expected`.
+    pub fn set_head_if(
+        &self,
+        expected: Option<&SyncStoreLayer>,
+        layer: &SyncStoreLayer,
+    ) -> Result<(), SetLabelError> {
+        task_sync(
+            self.inner
+                .set_head_if(expected.map(|l| &l.inner), &layer.inner),
+        )
+    }
+
+    /// Squash the current head into a single base layer, and point this database at it.
+    ///
+    /// See `NamedGraph::squash_head` for details.
+    pub fn squash_head(&self) -> Result<Option<SyncStoreLayer>, SetLabelError> {
+        let inner = task_sync(self.inner.squash_head());
+
+        inner.map(|i| i.map(SyncStoreLayer::wrap))
+    }
+
     /// Set the database label to the given layer, even if it is not a valid ancestor.
     pub fn force_set_head(&self, layer: &SyncStoreLayer) -> Result<(), io::Error> {
         task_sync(self.inner.force_set_head(&layer.inner))
@@ -480,11 +806,220 @@ impl SyncNamedGraph {
         task_sync(self.inner.force_set_head_version(&layer.inner, version))
     }
 
+    /// Point this database back at an earlier layer, or at any other layer in the store.
+    ///
+    /// See `NamedGraph::reset` for details.
+    pub fn reset(
+        &self,
+        layer: &SyncStoreLayer,
+        keep_abandoned_as: Option<&str>,
+    ) -> Result<Option<SyncStoreLayer>, SetLabelError> {
+        let inner = task_sync(self.inner.reset(&layer.inner, keep_abandoned_as));
+
+        inner.map(|i| i.map(SyncStoreLayer::wrap))
+    }
+
+    /// Move the head of this database forward to the head of `other`.
+    ///
+    /// See `NamedGraph::fast_forward_to` for details.
+    pub fn fast_forward_to(&self, other: &SyncNamedGraph) -> Result<bool, SetLabelError> {
+        task_sync(self.inner.fast_forward_to(&other.inner))
+    }
+
+    /// Open a write transaction on this database.
+    ///
+    /// See `Transaction` for details.
+    pub fn transaction(&self) -> io::Result<SyncTransaction> {
+        let inner = task_sync(self.inner.transaction());
+
+        inner.map(SyncTransaction::wrap)
+    }
+
+    /// Watch this database for head changes.
+    ///
+    /// See `LabelStore::watch_label` for details.
+    pub fn watch(&self) -> io::Result<SyncLabelWatch> {
+        let inner = task_sync(self.inner.watch());
+
+        inner.map(|inner| SyncLabelWatch { inner })
+    }
+
+    /// Retrieve all head changes of this database, oldest first.
+    ///
+    /// See `LabelStore::label_history` for details.
+    pub fn history(&self) -> io::Result<Vec<LabelHistoryEntry>> {
+        task_sync(collect_history(self.inner.history()))
+    }
+
+    /// Retrieve the namespace prefixes of this database.
+    ///
+    /// See `LabelStore::label_prefixes` for details.
+    pub fn prefixes(&self) -> io::Result<PrefixMap> {
+        task_sync(self.inner.prefixes())
+    }
+
+    /// Replace the namespace prefixes of this database.
+    pub fn set_prefixes(&self, prefixes: PrefixMap) -> io::Result<()> {
+        task_sync(self.inner.set_prefixes(prefixes))
+    }
+
+    /// Returns the layer this database pointed at at the given time.
+    ///
+    /// See `NamedGraph::head_at` for details.
+    pub fn head_at(&self, time: SystemTime) -> io::Result<Option<SyncStoreLayer>> {
+        let inner = task_sync(self.inner.head_at(time));
+
+        inner.map(|i| i.map(SyncStoreLayer::wrap))
+    }
+
+    /// Returns the layer this database pointed at at the given point in its history.
+    ///
+    /// See `NamedGraph::head_as_of` for details.
+    pub fn head_as_of(&self, point: HistoryPoint) -> io::Result<Option<SyncStoreLayer>> {
+        let inner = task_sync(self.inner.head_as_of(point));
+
+        inner.map(|i| i.map(SyncStoreLayer::wrap))
+    }
+
     pub fn delete(&self) -> io::Result<()> {
         task_sync(self.inner.delete())
     }
 }
 
+async fn collect_history<F: Future<Output = io::Result<LabelHistoryStream>>>(
+    history: F,
+) -> io::Result<Vec<LabelHistoryEntry>> {
+    history
+        .await?
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect()
+}
+
+/// A blocking iterator over the head changes of a named graph, as returned by `SyncNamedGraph::watch`.
+pub struct SyncLabelWatch {
+    inner: LabelWatchStream,
+}
+
+impl Iterator for SyncLabelWatch {
+    type Item = io::Result<Label>;
+
+    /// Wait for the next head change.
+    fn next(&mut self) -> Option<io::Result<Label>> {
+        task_sync(self.inner.next())
+    }
+}
+
+/// A handle keeping a layer pinned, as returned by `SyncStoreLayer::pin`.
+///
+/// The layer is unpinned when this handle is dropped.
+#[derive(Clone)]
+pub struct SyncLayerPin {
+    inner: LayerPin,
+}
+
+impl SyncLayerPin {
+    /// The pinned layer.
+    pub fn layer(&self) -> SyncStoreLayer {
+        SyncStoreLayer::wrap(self.inner.layer().clone())
+    }
+
+    /// Unpin the layer.
+    ///
+    /// This is the same as dropping the handle.
+    pub fn unpin(self) {}
+}
+
+/// A blocking iterator over the commit events of a store, as returned by `SyncStore::subscribe`.
+pub struct SyncCommitEvents {
+    inner: CommitEventStream,
+}
+
+impl Iterator for SyncCommitEvents {
+    type Item = Result<CommitEvent, MissedCommitEvents>;
+
+    /// Wait for the next commit event.
+    fn next(&mut self) -> Option<Result<CommitEvent, MissedCommitEvents>> {
+        task_sync(self.inner.next())
+    }
+}
+
+/// A write transaction on a named graph, as returned by `SyncStore::transaction`.
+///
+/// See `Transaction` for details.
+pub struct SyncTransaction {
+    inner: Transaction,
+}
+
+impl SyncTransaction {
+    fn wrap(inner: Transaction) -> Self {
+        Self { inner }
+    }
+
+    /// Set what happens on commit if the head has moved since this transaction was opened.
+    pub fn with_conflict_policy(self, policy: ConflictPolicy) -> Self {
+        Self::wrap(self.inner.with_conflict_policy(policy))
+    }
+
+    /// Replace the namespace prefixes of the graph when this transaction commits.
+    ///
+    /// See `Transaction::with_prefixes` for details.
+    pub fn with_prefixes(self, prefixes: PrefixMap) -> Self {
+        Self::wrap(self.inner.with_prefixes(prefixes))
+    }
+
+    /// The head of the graph at the time this transaction was opened.
+    pub fn head(&self) -> Option<SyncStoreLayer> {
+        self.inner.head().cloned().map(SyncStoreLayer::wrap)
+    }
+
+    /// The builder collecting the changes of this transaction.
+    pub fn builder(&self) -> SyncStoreLayerBuilder {
+        SyncStoreLayerBuilder::wrap(self.inner.builder().clone())
+    }
+
+    /// Get an id for the given node, to use in id triples added to the builder of this transaction.
+    ///
+    /// See `StoreLayerBuilder::intern_node`.
+    pub fn intern_node(&self, node: &str) -> Result<u64, io::Error> {
+        self.inner.intern_node(node)
+    }
+
+    /// Get an id for the given predicate, to use in id triples added to the builder of this transaction.
+    ///
+    /// See `StoreLayerBuilder::intern_node`.
+    pub fn intern_predicate(&self, predicate: &str) -> Result<u64, io::Error> {
+        self.inner.intern_predicate(predicate)
+    }
+
+    /// Get an id for the given value, to use in id triples added to the builder of this transaction.
+    ///
+    /// See `StoreLayerBuilder::intern_node`.
+    pub fn intern_value(&self, value: &str) -> Result<u64, io::Error> {
+        self.inner.intern_value(value)
+    }
+
+    /// Commit the changes, and point the graph at the new layer.
+    pub fn commit(self) -> Result<SyncStoreLayer, TransactionError> {
+        let inner = task_sync(self.inner.commit());
+
+        inner.map(SyncStoreLayer::wrap)
+    }
+
+    /// Commit several transactions at once.
+    ///
+    /// See `Transaction::commit_all` for details.
+    pub fn commit_all(
+        transactions: Vec<SyncTransaction>,
+    ) -> Result<Vec<SyncStoreLayer>, SetLabelsError> {
+        let transactions = transactions.into_iter().map(|t| t.inner).collect();
+        let inner = task_sync(Transaction::commit_all(transactions));
+
+        inner.map(|layers| layers.into_iter().map(SyncStoreLayer::wrap).collect())
+    }
+}
+
 /// A store, storing a set of layers and database labels pointing to these layers.
 #[derive(Clone)]
 pub struct SyncStore {
@@ -497,6 +1032,14 @@ impl SyncStore {
         Self { inner }
     }
 
+    /// Create a new store from the given label and layer store.
+    pub fn new<Labels: 'static + LabelStore, Layers: 'static + LayerStore>(
+        label_store: Labels,
+        layer_store: Layers,
+    ) -> Self {
+        Self::wrap(Store::new(label_store, layer_store))
+    }
+
     /// Create a new database with the given name.
     ///
     /// If the database already exists, this will return an error.
@@ -513,6 +1056,135 @@ impl SyncStore {
         inner.map(|i| i.map(SyncNamedGraph::wrap))
     }
 
+    /// The names of all layers that are currently pinned through this store.
+    pub fn pinned_layers(&self) -> Vec<[u32; 5]> {
+        self.inner.pinned_layers()
+    }
+
+    /// Read the heads of the given databases as they were at a single moment.
+    ///
+    /// See `Store::snapshot` for details.
+    pub fn snapshot(&self, graphs: &[&str]) -> io::Result<SyncSnapshot> {
+        let inner = task_sync(self.inner.snapshot(graphs));
+
+        inner.map(|inner| SyncSnapshot { inner })
+    }
+
+    /// Collect statistics about this store.
+    ///
+    /// See `Store::stats` for details.
+    pub fn stats(&self) -> io::Result<StoreStats> {
+        task_sync(self.inner.stats())
+    }
+
+    /// Subscribe to the commit events of this store.
+    ///
+    /// See `Store::subscribe` for details.
+    pub fn subscribe(&self) -> SyncCommitEvents {
+        SyncCommitEvents {
+            inner: self.inner.subscribe(),
+        }
+    }
+
+    /// Point several databases at new layers at once.
+    ///
+    /// See `Store::set_heads_if` for details.
+    pub fn set_heads_if(
+        &self,
+        updates: &[(&SyncNamedGraph, Option<&SyncStoreLayer>, &SyncStoreLayer)],
+    ) -> Result<(), SetLabelsError> {
+        let updates: Vec<_> = updates
+            .iter()
+            .map(|(graph, expected, layer)| {
+                (&graph.inner, expected.map(|l| &l.inner), &layer.inner)
+            })
+            .collect();
+        task_sync(self.inner.set_heads_if(&updates))
+    }
+
+    /// Point the database with the given name at the layer with the given name.
+    ///
+    /// See `Store::reset` for details.
+    pub fn reset(
+        &self,
+        label: &str,
+        layer: [u32; 5],
+        keep_abandoned_as: Option<&str>,
+    ) -> Result<Option<SyncStoreLayer>, SetLabelError> {
+        let inner = task_sync(self.inner.reset(label, layer, keep_abandoned_as));
+
+        inner.map(|i| i.map(SyncStoreLayer::wrap))
+    }
+
+    /// Returns the layer the database with the given name pointed at at the given point in its history.
+    ///
+    /// See `Store::open_graph_at` for details.
+    pub fn open_graph_at<P: Into<HistoryPoint>>(
+        &self,
+        label: &str,
+        point: P,
+    ) -> io::Result<Option<SyncStoreLayer>> {
+        let point: HistoryPoint = point.into();
+        let inner = task_sync(self.inner.open_graph_at(label, point));
+
+        inner.map(|i| i.map(SyncStoreLayer::wrap))
+    }
+
+    /// Retrieve all head changes of the database with the given name, oldest first.
+    ///
+    /// See `LabelStore::label_history` for details.
+    pub fn history(&self, label: &str) -> io::Result<Vec<LabelHistoryEntry>> {
+        task_sync(collect_history(self.inner.history(label)))
+    }
+
+    /// List all databases in this store, ordered by name.
+    pub fn graphs(&self) -> io::Result<Vec<SyncNamedGraph>> {
+        let inner = task_sync(self.inner.graphs());
+
+        inner.map(|graphs| graphs.into_iter().map(SyncNamedGraph::wrap).collect())
+    }
+
+    /// Create a new database pointing at the same head as an existing one.
+    ///
+    /// See `Store::branch` for details.
+    pub fn branch(&self, from: &str, new_label: &str) -> io::Result<SyncNamedGraph> {
+        let inner = task_sync(self.inner.branch(from, new_label));
+
+        inner.map(SyncNamedGraph::wrap)
+    }
+
+    /// List all databases whose head is the given layer or one of its descendants, ordered by name.
+    pub fn branches_of(&self, layer: &SyncStoreLayer) -> io::Result<Vec<SyncNamedGraph>> {
+        let inner = task_sync(self.inner.branches_of(&layer.inner));
+
+        inner.map(|graphs| graphs.into_iter().map(SyncNamedGraph::wrap).collect())
+    }
+
+    /// Rename a database, keeping its head.
+    ///
+    /// See `Store::rename` for details.
+    pub fn rename(&self, label: &str, new_label: &str) -> io::Result<SyncNamedGraph> {
+        let inner = task_sync(self.inner.rename(label, new_label));
+
+        inner.map(SyncNamedGraph::wrap)
+    }
+
+    /// Remove all layers that are not needed by any database.
+    ///
+    /// See `Store::collect_garbage` for details.
+    pub fn collect_garbage(&self) -> io::Result<Vec<[u32; 5]>> {
+        task_sync(self.inner.collect_garbage())
+    }
+
+    /// Open a write transaction on the database with the given name.
+    ///
+    /// See `Store::transaction` for details.
+    pub fn transaction(&self, label: &str) -> io::Result<SyncTransaction> {
+        let inner = task_sync(self.inner.transaction(label));
+
+        inner.map(SyncTransaction::wrap)
+    }
+
     /// Delete an existing database with the given name. Returns true if this database was deleted
     /// and false otherwise.
     pub fn delete(&self, label: &str) -> io::Result<bool> {
@@ -538,6 +1210,37 @@ impl SyncStore {
         inner.map(SyncStoreLayerBuilder::wrap)
     }
 
+    /// Reopen the builder for the given layer from its last checkpoint.
+    pub fn resume_layer_builder(
+        &self,
+        name: [u32; 5],
+    ) -> Result<Option<SyncStoreLayerBuilder>, io::Error> {
+        let inner = task_sync(self.inner.resume_layer_builder(name));
+
+        inner.map(|builder| builder.map(SyncStoreLayerBuilder::wrap))
+    }
+
+    /// Build a base layer from an iterator of triples that may be larger than memory.
+    ///
+    /// See `Store::bulk_build_base_layer` for details.
+    pub fn bulk_build_base_layer<
+        I: 'static + IntoIterator<Item = io::Result<StringTriple>> + Send,
+    >(
+        &self,
+        triples: I,
+        config: BulkLoadConfig,
+    ) -> io::Result<SyncStoreLayer>
+    where
+        I::IntoIter: Send,
+    {
+        let inner = task_sync(
+            self.inner
+                .bulk_build_base_layer(futures::stream::iter(triples), config),
+        );
+
+        inner.map(SyncStoreLayer::wrap)
+    }
+
     /// Export the given layers by creating a pack, a Vec<u8> that can later be used with `import_layers` on a different store.
     pub fn export_layers(
         &self,
@@ -558,6 +1261,81 @@ impl SyncStore {
     ) -> io::Result<()> {
         task_sync(self.inner.layer_store.import_layers(pack, layer_ids))
     }
+
+    /// Copy the current state of every database in this store to the given store.
+    ///
+    /// See `Store::backup` for details.
+    pub fn backup(&self, target: &SyncStore) -> io::Result<()> {
+        task_sync(self.inner.backup(&target.inner))
+    }
+
+    /// Restore all databases from a backup made with `backup`.
+    pub fn restore(&self, backup: &SyncStore) -> io::Result<()> {
+        task_sync(self.inner.restore(&backup.inner))
+    }
+}
+
+/// The heads of several databases as they were at a single moment, as returned by `SyncStore::snapshot`.
+///
+/// See `Snapshot` for details.
+pub struct SyncSnapshot {
+    inner: Snapshot,
+}
+
+impl SyncSnapshot {
+    /// The head of the given database in this snapshot.
+    pub fn head(&self, graph: &str) -> Option<SyncStoreLayer> {
+        self.inner.head(graph).cloned().map(SyncStoreLayer::wrap)
+    }
+
+    /// The databases in this snapshot and their heads, in the order they were requested.
+    pub fn heads(&self) -> Vec<(String, Option<SyncStoreLayer>)> {
+        self.inner
+            .heads()
+            .map(|(name, head)| (name.to_owned(), head.cloned().map(SyncStoreLayer::wrap)))
+            .collect()
+    }
+}
+
+/// A set of stores, presented as one.
+///
+/// See `FederatedStore` for details.
+#[derive(Clone, Default)]
+pub struct SyncFederatedStore {
+    inner: FederatedStore,
+}
+
+impl SyncFederatedStore {
+    /// A federated store without any members.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a member store, whose databases are known under the given prefix.
+    pub fn with_member(self, prefix: &str, store: SyncStore) -> Self {
+        Self {
+            inner: self.inner.with_member(prefix, store.inner),
+        }
+    }
+
+    /// Open the database with the given name in the member responsible for it.
+    pub fn open(&self, label: &str) -> io::Result<Option<SyncNamedGraph>> {
+        let inner = task_sync(self.inner.open(label));
+
+        inner.map(|graph| graph.map(SyncNamedGraph::wrap))
+    }
+
+    /// List the names of all databases in all members, ordered by name.
+    pub fn graphs(&self) -> io::Result<Vec<String>> {
+        task_sync(self.inner.graphs())
+    }
+
+    /// Retrieve the layer with the given name from the first member that has it.
+    pub fn get_layer_from_id(&self, layer: [u32; 5]) -> io::Result<Option<SyncStoreLayer>> {
+        let inner = task_sync(self.inner.get_layer_from_id(layer));
+
+        inner.map(|layer| layer.map(SyncStoreLayer::wrap))
+    }
 }
 
 /// Open a store that is entirely in memory.
@@ -572,6 +1350,13 @@ pub fn open_sync_directory_store<P: Into<PathBuf>>(path: P) -> SyncStore {
     SyncStore::wrap(open_directory_store(path))
 }
 
+/// Open a store that reads its data from the given directory, without ever writing to it.
+///
+/// See `open_read_only_directory_store` for details.
+pub fn open_sync_read_only_directory_store<P: Into<PathBuf>>(path: P) -> SyncStore {
+    SyncStore::wrap(open_read_only_directory_store(path))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -724,4 +1509,25 @@ mod tests {
             result_layer.string_triple_exists(&StringTriple::new_value("horse", "says", "neigh"))
         );
     }
+
+    #[test]
+    fn watch_sync_database_head() {
+        let store = open_sync_memory_store();
+        let database = store.create("foodb").unwrap();
+        let mut watch = database.watch().unwrap();
+
+        let builder = store.create_base_layer().unwrap();
+        assert!(builder.parent().is_none());
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer = builder.commit().unwrap();
+        assert!(database.set_head(&layer).unwrap());
+
+        let label = watch.next().unwrap().unwrap();
+        assert_eq!(Some(layer.name()), label.layer);
+
+        let builder = layer.open_write().unwrap();
+        assert_eq!(Some(layer.name()), builder.parent().map(|p| p.name()));
+    }
 }
@@ -0,0 +1,514 @@
+//! Optimistic write transactions on named graphs.
+//!
+//! A transaction remembers the head of a named graph when it is
+//! opened, and collects changes in a layer builder on top of that
+//! head. On commit, the label is only moved to the new layer if it
+//! still points at the remembered head. If another writer moved the
+//! head in the meantime, the commit either fails, or rebases the
+//! changes onto the new head and tries again, depending on the
+//! `ConflictPolicy` of the transaction. Transactions on several
+//! graphs can be committed together with `Transaction::commit_all`.
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::Arc;
+
+use thiserror::Error;
+
+use super::{MergeConflict, NamedGraph, StoreLayer, StoreLayerBuilder};
+use crate::layer::{Layer, StringTriple};
+use crate::storage::{LabelConflict, PrefixMap, SetLabelError, SetLabelsError};
+
+/// What a transaction does on commit when the head of its graph has moved since it was opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    /// Fail the commit with a `TransactionError::Conflict`.
+    #[default]
+    Fail,
+    /// Rebase the changes onto the new head and try again, at most the given amount of times.
+    ///
+    /// Rebasing re-applies the additions and removals of the
+    /// transaction on top of the new head with `StoreLayer::merge`.
+    /// The commit fails with a `TransactionError::MergeConflicts`
+    /// instead if the new head no longer has a triple that the
+    /// transaction removes, or if the merge reports conflicts, which
+    /// happens when one side added triples for a subject and
+    /// predicate that the other side removed triples of.
+    Rebase { retries: usize },
+}
+
+/// An error returned when committing a transaction.
+#[derive(Error, Debug)]
+pub enum TransactionError {
+    /// The head moved, and the changes were not rebased onto it.
+    #[error(transparent)]
+    Conflict(#[from] LabelConflict),
+    /// The head moved, and the changes could not be rebased onto it.
+    #[error("{} conflicts while rebasing the transaction", .0.len())]
+    MergeConflicts(Vec<MergeConflict>),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl From<SetLabelError> for TransactionError {
+    fn from(error: SetLabelError) -> Self {
+        match error {
+            SetLabelError::Conflict(conflict) => TransactionError::Conflict(conflict),
+            SetLabelError::Io(error) => TransactionError::Io(error),
+        }
+    }
+}
+
+/// A write transaction on a named graph, as returned by `Store::transaction`.
+pub struct Transaction {
+    graph: NamedGraph,
+    head: Option<StoreLayer>,
+    builder: StoreLayerBuilder,
+    policy: ConflictPolicy,
+    prefixes: Option<PrefixMap>,
+}
+
+impl Transaction {
+    pub(crate) async fn open(graph: NamedGraph) -> io::Result<Self> {
+        let head = graph.head().await?;
+        let builder = match &head {
+            Some(head) => head.open_write().await?,
+            None => graph.store.create_base_layer().await?,
+        };
+
+        Ok(Self {
+            graph,
+            head,
+            builder,
+            policy: ConflictPolicy::default(),
+            prefixes: None,
+        })
+    }
+
+    /// Set what happens on commit if the head has moved since this transaction was opened.
+    pub fn with_conflict_policy(mut self, policy: ConflictPolicy) -> Self {
+        self.policy = policy;
+
+        self
+    }
+
+    /// Replace the namespace prefixes of the graph when this transaction commits.
+    ///
+    /// The prefixes are only written once the head was moved, so a
+    /// failed commit leaves them alone. A crash in between moving the
+    /// head and writing the prefixes keeps the old prefixes.
+    pub fn with_prefixes(mut self, prefixes: PrefixMap) -> Self {
+        self.prefixes = Some(prefixes);
+
+        self
+    }
+
+    /// The head of the graph at the time this transaction was opened.
+    pub fn head(&self) -> Option<&StoreLayer> {
+        self.head.as_ref()
+    }
+
+    /// The builder collecting the changes of this transaction.
+    pub fn builder(&self) -> &StoreLayerBuilder {
+        &self.builder
+    }
+
+    /// Commit the changes, and point the graph at the new layer.
+    ///
+    /// Returns the layer the graph points at afterwards. This is a
+    /// child of the head this transaction was opened on, unless the
+    /// changes were rebased.
+    pub async fn commit(self) -> Result<StoreLayer, TransactionError> {
+        let mut layer = self.builder.commit().await?;
+        let mut expected = self.head;
+        let mut retries = match self.policy {
+            ConflictPolicy::Fail => 0,
+            ConflictPolicy::Rebase { retries } => retries,
+        };
+
+        loop {
+            let conflict = match self.graph.set_head_if(expected.as_ref(), &layer).await {
+                Ok(()) => {
+                    if let Some(prefixes) = self.prefixes {
+                        self.graph.set_prefixes(prefixes).await?;
+                    }
+
+                    return Ok(layer);
+                }
+                Err(SetLabelError::Conflict(conflict)) => conflict,
+                Err(e) => return Err(e.into()),
+            };
+            if retries == 0 {
+                return Err(conflict.into());
+            }
+            retries -= 1;
+
+            let new_head = match self.graph.head().await? {
+                Some(new_head) => new_head,
+                // the graph was reset, there's nothing to rebase onto
+                None => return Err(conflict.into()),
+            };
+            layer = match &expected {
+                Some(ancestor) => {
+                    let conflicts = removal_conflicts(ancestor, &layer, &new_head).await?;
+                    if !conflicts.is_empty() {
+                        return Err(TransactionError::MergeConflicts(conflicts));
+                    }
+                    let (merged, report) = new_head.merge(&layer, ancestor).await?;
+                    if !report.conflicts.is_empty() {
+                        return Err(TransactionError::MergeConflicts(report.conflicts));
+                    }
+
+                    merged
+                }
+                None => {
+                    // a base layer only adds triples, so these can't conflict
+                    let builder = new_head.open_write().await?;
+                    for triple in layer.triples() {
+                        let triple = layer
+                            .id_triple_to_string(&triple)
+                            .expect("layer triple should resolve to strings");
+                        builder.add_string_triple(triple)?;
+                    }
+
+                    builder.commit().await?
+                }
+            };
+            expected = Some(new_head);
+        }
+    }
+
+    /// Commit several transactions at once.
+    ///
+    /// Either all graphs are pointed at their new layers, or none
+    /// are. This is useful when a single change spans several
+    /// graphs, such as a data graph and its schema graph. The
+    /// conflict policies of the transactions are not used, as
+    /// rebasing could not happen atomically. Instead, if any graph
+    /// moved since its transaction was opened, the commit fails with
+    /// a `SetLabelsError::Conflict` for that graph. All transactions
+    /// must be opened on the same store, and on different graphs.
+    /// Prefixes set with `with_prefixes` are written once all heads
+    /// have moved.
+    ///
+    /// Returns the new layers in the order of the given transactions.
+    pub async fn commit_all(
+        transactions: Vec<Transaction>,
+    ) -> Result<Vec<StoreLayer>, SetLabelsError> {
+        let store = match transactions.first() {
+            None => return Ok(Vec::new()),
+            Some(transaction) => transaction.graph.store.clone(),
+        };
+        if transactions
+            .iter()
+            .any(|t| !Arc::ptr_eq(&t.graph.store.label_store, &store.label_store))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "transactions were opened on different stores",
+            )
+            .into());
+        }
+
+        let mut layers = Vec::with_capacity(transactions.len());
+        for transaction in transactions.iter() {
+            layers.push(transaction.builder.commit().await?);
+        }
+        let updates: Vec<_> = transactions
+            .iter()
+            .zip(layers.iter())
+            .map(|(transaction, layer)| (&transaction.graph, transaction.head.as_ref(), layer))
+            .collect();
+        store.set_heads_if(&updates).await?;
+        for transaction in transactions {
+            if let Some(prefixes) = transaction.prefixes {
+                transaction.graph.set_prefixes(prefixes).await?;
+            }
+        }
+
+        Ok(layers)
+    }
+}
+
+/// The triples that `layer` removes from `ancestor` that are already gone from `head`, as merge conflicts.
+///
+/// The conflicts are reported from the point of view of
+/// `StoreLayer::merge`, so `head` is our side, and `layer` is theirs.
+async fn removal_conflicts(
+    ancestor: &StoreLayer,
+    layer: &StoreLayer,
+    head: &StoreLayer,
+) -> io::Result<Vec<MergeConflict>> {
+    let mut missing: BTreeMap<(String, String), Vec<StringTriple>> = BTreeMap::new();
+    for triple in ancestor.diff(layer).await?.string_removals() {
+        if !head.string_triple_exists(&triple) {
+            missing
+                .entry((triple.subject.clone(), triple.predicate.clone()))
+                .or_default()
+                .push(triple);
+        }
+    }
+
+    Ok(missing
+        .into_iter()
+        .map(|((subject, predicate), mut removed)| {
+            removed.sort();
+            MergeConflict {
+                subject,
+                predicate,
+                ours_added: Vec::new(),
+                ours_removed: removed.clone(),
+                theirs_added: Vec::new(),
+                theirs_removed: removed,
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{open_directory_store, open_memory_store, Store};
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn commit_transaction_on_unchanged_head() {
+        let store = open_memory_store();
+        let graph = store.create("foo").await.unwrap();
+
+        let transaction = store.transaction("foo").await.unwrap();
+        assert!(transaction.head().is_none());
+        transaction
+            .builder()
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer = transaction.commit().await.unwrap();
+
+        assert_eq!(
+            Some(layer.name()),
+            graph.head().await.unwrap().map(|l| l.name())
+        );
+        assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        assert_eq!(
+            io::ErrorKind::NotFound,
+            store.transaction("bar").await.err().unwrap().kind()
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrent_transactions_conflict_or_rebase() {
+        let store = open_memory_store();
+        let graph = store.create("foo").await.unwrap();
+        let first = store.transaction("foo").await.unwrap();
+        let second = store.transaction("foo").await.unwrap();
+        let third = store
+            .transaction("foo")
+            .await
+            .unwrap()
+            .with_conflict_policy(ConflictPolicy::Rebase { retries: 1 });
+
+        first
+            .builder()
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let first = first.commit().await.unwrap();
+
+        second
+            .builder()
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        match second.commit().await {
+            Err(TransactionError::Conflict(conflict)) => {
+                assert_eq!(Some(first.name()), conflict.current_head)
+            }
+            _ => panic!("expected a conflict"),
+        }
+
+        third
+            .builder()
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        let third = third.commit().await.unwrap();
+        assert_eq!(Some(first.name()), third.parent_name());
+        assert_eq!(
+            Some(third.name()),
+            graph.head().await.unwrap().map(|l| l.name())
+        );
+        assert!(third.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        assert!(third.string_triple_exists(&StringTriple::new_value("duck", "says", "quack")));
+    }
+
+    async fn commit_transactions_together(store: Store) {
+        let data = store.create("data").await.unwrap();
+        let schema = store.create("schema").await.unwrap();
+
+        let transactions = vec![
+            data.transaction().await.unwrap(),
+            schema.transaction().await.unwrap(),
+        ];
+        transactions[0]
+            .builder()
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        transactions[1]
+            .builder()
+            .add_string_triple(StringTriple::new_node("says", "range", "string"))
+            .unwrap();
+        let layers = Transaction::commit_all(transactions).await.unwrap();
+        assert_eq!(
+            Some(layers[0].name()),
+            data.head().await.unwrap().map(|l| l.name())
+        );
+        assert_eq!(
+            Some(layers[1].name()),
+            schema.head().await.unwrap().map(|l| l.name())
+        );
+
+        // a conflict on one graph leaves the other alone as well
+        let transactions = vec![
+            data.transaction().await.unwrap(),
+            schema.transaction().await.unwrap(),
+        ];
+        let other = schema.transaction().await.unwrap();
+        other.commit().await.unwrap();
+        match Transaction::commit_all(transactions).await {
+            Err(SetLabelsError::Conflict { label, .. }) => assert_eq!("schema", label),
+            _ => panic!("expected a conflict"),
+        }
+        assert_eq!(
+            Some(layers[0].name()),
+            data.head().await.unwrap().map(|l| l.name())
+        );
+    }
+
+    #[tokio::test]
+    async fn commit_memory_transactions_together() {
+        commit_transactions_together(open_memory_store()).await
+    }
+
+    #[tokio::test]
+    async fn commit_directory_transactions_together() {
+        let dir = tempdir().unwrap();
+        commit_transactions_together(open_directory_store(dir.path())).await
+    }
+
+    #[tokio::test]
+    async fn commit_prefixes_with_transaction() {
+        let store = open_memory_store();
+        let graph = store.create("foo").await.unwrap();
+        let mut prefixes = PrefixMap::new();
+        prefixes.insert("ex".to_string(), "http://example.com/".to_string());
+
+        let first = graph.transaction().await.unwrap();
+        let second = graph
+            .transaction()
+            .await
+            .unwrap()
+            .with_prefixes(prefixes.clone());
+        first.commit().await.unwrap();
+        assert!(second.commit().await.is_err());
+        assert!(graph.prefixes().await.unwrap().is_empty());
+
+        let transaction = graph
+            .transaction()
+            .await
+            .unwrap()
+            .with_prefixes(prefixes.clone());
+        transaction.commit().await.unwrap();
+        assert_eq!(prefixes, graph.prefixes().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn rebase_reports_merge_conflicts() {
+        let store = open_memory_store();
+        let graph = store.create("foo").await.unwrap();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+        graph.set_head(&base).await.unwrap();
+
+        let first = store.transaction("foo").await.unwrap();
+        let second = store
+            .transaction("foo")
+            .await
+            .unwrap()
+            .with_conflict_policy(ConflictPolicy::Rebase { retries: 3 });
+        for (transaction, sound) in [(&first, "moooo"), (&second, "mooo")] {
+            transaction
+                .builder()
+                .remove_string_triple(StringTriple::new_value("cow", "says", "moo"))
+                .unwrap();
+            transaction
+                .builder()
+                .add_string_triple(StringTriple::new_value("cow", "says", sound))
+                .unwrap();
+        }
+        let first = first.commit().await.unwrap();
+
+        match second.commit().await {
+            Err(TransactionError::MergeConflicts(conflicts)) => {
+                assert_eq!(1, conflicts.len());
+                assert_eq!("cow", conflicts[0].subject);
+            }
+            _ => panic!("expected merge conflicts"),
+        }
+        assert_eq!(
+            Some(first.name()),
+            graph.head().await.unwrap().map(|l| l.name())
+        );
+    }
+
+    #[tokio::test]
+    async fn rebase_fails_on_concurrently_removed_triples() {
+        let store = open_memory_store();
+        let graph = store.create("foo").await.unwrap();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+        graph.set_head(&base).await.unwrap();
+
+        let first = store.transaction("foo").await.unwrap();
+        let second = store
+            .transaction("foo")
+            .await
+            .unwrap()
+            .with_conflict_policy(ConflictPolicy::Rebase { retries: 3 });
+        first
+            .builder()
+            .remove_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let first = first.commit().await.unwrap();
+
+        // the removal would be a no-op on the new head, which hides that
+        // the transaction decided on it based on a state that is gone
+        second
+            .builder()
+            .remove_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        second
+            .builder()
+            .remove_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        match second.commit().await {
+            Err(TransactionError::MergeConflicts(conflicts)) => {
+                assert_eq!(1, conflicts.len());
+                assert_eq!("cow", conflicts[0].subject);
+                assert_eq!(
+                    vec![StringTriple::new_value("cow", "says", "moo")],
+                    conflicts[0].theirs_removed
+                );
+            }
+            _ => panic!("expected merge conflicts"),
+        }
+        assert_eq!(
+            Some(first.name()),
+            graph.head().await.unwrap().map(|l| l.name())
+        );
+    }
+}
@@ -3,12 +3,14 @@
 use byteorder::{BigEndian, ByteOrder};
 use bytes::{Buf, Bytes, BytesMut};
 use futures::stream::{Stream, StreamExt};
+use regex::bytes::Regex;
 use std::cmp::{Ord, Ordering};
 use std::convert::TryInto;
 use std::error::Error;
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::io;
+use std::ops::{Bound, Range};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
 use tokio_util::codec::{Decoder, FramedRead};
 
@@ -637,6 +639,45 @@ impl PfcDict {
         None
     }
 
+    /// Returns the index of the first string that is not less than the given string.
+    pub fn lower_bound(&self, s: &str) -> usize {
+        let mut min = 0;
+        let mut max = self.len();
+        while min < max {
+            let mid = (min + max) / 2;
+            if self.get(mid).unwrap().as_bytes() < s.as_bytes() {
+                min = mid + 1;
+            } else {
+                max = mid;
+            }
+        }
+
+        min
+    }
+
+    /// Returns the range of indexes of all strings that lie within the given bounds.
+    pub fn index_range(&self, start: Bound<&str>, end: Bound<&str>) -> Range<usize> {
+        let upper_bound = |s: &str| {
+            let index = self.lower_bound(s);
+            match self.get(index) {
+                Some(found) if found == s => index + 1,
+                _ => index,
+            }
+        };
+        let start = match start {
+            Bound::Included(s) => self.lower_bound(s),
+            Bound::Excluded(s) => upper_bound(s),
+            Bound::Unbounded => 0,
+        };
+        let end = match end {
+            Bound::Included(s) => upper_bound(s),
+            Bound::Excluded(s) => self.lower_bound(s),
+            Bound::Unbounded => self.len(),
+        };
+
+        start..end.max(start)
+    }
+
     pub fn strings(&self) -> impl Iterator<Item = String> {
         let block_iterator = PfcDictBlockIterator::new(self.clone());
 
@@ -648,6 +689,37 @@ impl PfcDict {
 
         block_iterator.flat_map(|block| block.entries())
     }
+
+    /// Iterator over the indexes of all strings accepted by the given filter.
+    ///
+    /// Strings are decoded block by block into a single buffer that
+    /// is handed to the filter as bytes, so no strings are allocated.
+    pub fn filter_indexes<F: FnMut(&[u8]) -> bool>(
+        &self,
+        mut filter: F,
+    ) -> impl Iterator<Item = usize> {
+        let mut string = Vec::new();
+        PfcDictBlockIterator::new(self.clone())
+            .flat_map(|block| block.block_entries())
+            .enumerate()
+            .filter_map(move |(index, (prefix_len, postfix))| {
+                string.truncate(prefix_len);
+                string.extend_from_slice(postfix.as_ref());
+                if filter(&string) {
+                    Some(index)
+                } else {
+                    None
+                }
+            })
+    }
+
+    /// Iterator over the indexes of all strings matching the given regular expression.
+    ///
+    /// See `filter_indexes`.
+    pub fn regex_indexes(&self, regex: &Regex) -> impl Iterator<Item = usize> {
+        let regex = regex.clone();
+        self.filter_indexes(move |string| regex.is_match(string))
+    }
 }
 
 pub struct PfcDictFileBuilder<W: SyncableFile> {
@@ -1191,6 +1263,48 @@ mod tests {
         assert_eq!(18, count);
     }
 
+    #[tokio::test]
+    async fn filter_strings_by_regex() {
+        let contents = vec![
+            "aaaaa",
+            "aaaaaaaaaa",
+            "aaaabbbbbb",
+            "abcdefghijk",
+            "addeeerafa",
+            "arf",
+            "bapofsi",
+            "barf",
+            "berf",
+            "boo boo boo boo",
+            "bzwas baraf",
+            "dradsfadfvbbb",
+        ];
+
+        let blocks = MemoryBackedStore::new();
+        let offsets = MemoryBackedStore::new();
+        let mut builder = PfcDictFileBuilder::new(
+            blocks.open_write().await.unwrap(),
+            offsets.open_write().await.unwrap(),
+        );
+
+        builder.add_all(contents.clone().into_iter()).await.unwrap();
+        builder.finalize().await.unwrap();
+
+        let dict =
+            PfcDict::parse(blocks.map().await.unwrap(), offsets.map().await.unwrap()).unwrap();
+
+        let regex = Regex::new("^b.*rf$").unwrap();
+        let result: Vec<_> = dict.regex_indexes(&regex).collect();
+        assert_eq!(vec![7, 8], result);
+
+        let regex = Regex::new("a{5}|bbb$").unwrap();
+        let result: Vec<_> = dict.regex_indexes(&regex).collect();
+        assert_eq!(vec![0, 1, 2, 11], result);
+
+        let result: Vec<_> = dict.filter_indexes(|s| s.len() == 3).collect();
+        assert_eq!(vec![5], result);
+    }
+
     #[test]
     fn bufeq_empty_entry() {
         let entry = PfcDictEntry::new(Vec::new());